@@ -13,7 +13,7 @@ use std::thread;
 use std::time::Duration;
 use tokio_test::block_on;
 use url::Url;
-use web_archive::parsing::{ImageResource, Resource};
+use web_archive::parsing::{ImageResource, Resource, TextResource};
 use web_archive::{archive, blocking};
 
 mod pages;
@@ -79,7 +79,11 @@ fn test_index(mode: &Mode) -> &'static str {
         a.resource_map
             .get(&Url::parse("http://localhost:8000/style.css").unwrap())
             .unwrap(),
-        &Resource::Css(style().to_string())
+        &Resource::Css(TextResource {
+            data: Bytes::from(style().0),
+            mimetype: "text/css".to_string(),
+            charset: Some("UTF-8".to_string()),
+        })
     );
     "Index page with CSS"
 }
@@ -99,13 +103,21 @@ fn test_blog(mode: &Mode) -> &'static str {
         a.resource_map
             .get(&Url::parse("http://localhost:8000/style.css").unwrap())
             .unwrap(),
-        &Resource::Css(style().to_string())
+        &Resource::Css(TextResource {
+            data: Bytes::from(style().0),
+            mimetype: "text/css".to_string(),
+            charset: Some("UTF-8".to_string()),
+        })
     );
     assert_eq!(
         a.resource_map
             .get(&Url::parse("http://localhost:8000/scripts/1.js").unwrap())
             .unwrap(),
-        &Resource::Javascript(js().to_string())
+        &Resource::Javascript(TextResource {
+            data: Bytes::from(js().0),
+            mimetype: "application/javascript".to_string(),
+            charset: None,
+        })
     );
     assert_eq!(
         a.resource_map
@@ -163,7 +175,11 @@ fn test_500(mode: &Mode) -> &'static str {
         a.resource_map
             .get(&Url::parse("http://localhost:8000/style.css").unwrap())
             .unwrap(),
-        &Resource::Css(style().to_string())
+        &Resource::Css(TextResource {
+            data: Bytes::from(style().0),
+            mimetype: "text/css".to_string(),
+            charset: Some("UTF-8".to_string()),
+        })
     );
 
     "Endpoints returning Internal Server Errors"
@@ -7,7 +7,7 @@
 
 use rocket::get;
 use rocket::http::Status;
-use rocket::response::{status, Responder};
+use rocket::response::{content, status, Responder};
 
 #[get("/")]
 pub fn index() -> &'static str {
@@ -49,10 +49,12 @@ pub fn blog() -> &'static str {
 }
 
 #[get("/style.css")]
-pub fn style() -> &'static str {
-    r#"body {
+pub fn style() -> content::Css<&'static str> {
+    content::Css(
+        r#"body {
 		background-color: blue;
-	}"#
+	}"#,
+    )
 }
 
 #[get("/images/rust-logo-blk.svg")]
@@ -66,11 +68,13 @@ pub fn ferris() -> &'static [u8] {
 }
 
 #[get("/scripts/1.js")]
-pub fn js() -> &'static str {
-    r#"function js_function() {
+pub fn js() -> content::JavaScript<&'static str> {
+    content::JavaScript(
+        r#"function js_function() {
 		console.log("Here is some javascript!");
 	}
-	"#
+	"#,
+    )
 }
 
 #[get("/500.jpg")]
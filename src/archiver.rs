@@ -0,0 +1,255 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for archiving many pages with the same configuration
+
+use crate::client::RedirectLog;
+use crate::download::Cache;
+use crate::error::Error;
+use crate::page_archive::PageArchive;
+use crate::parsing::ResourceUrl;
+use crate::{archive_page, build_client, discover_page, ArchiveOptions};
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::fmt::Display;
+use url::Url;
+
+/// Archives many pages with the same client and options.
+///
+/// Resources are cached between pages, so stylesheets, scripts, and
+/// images shared across a site are only downloaded once. The cache lasts
+/// as long as the `Archiver` unless [`Archiver::clear_cache`] is called,
+/// and holds up to 256 MiB of responses, forgetting the ones downloaded
+/// longest ago to make room for new ones.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{ArchiveOptions, Archiver};
+///
+/// # async fn archive_async() {
+/// let archiver = Archiver::new(ArchiveOptions::default()).unwrap();
+/// let about = archiver.archive("http://example.com/about").await.unwrap();
+/// let pages = archiver
+///     .archive_many(vec!["http://example.com/", "http://example.com/blog"])
+///     .await;
+/// # }
+/// ```
+pub struct Archiver<'a> {
+    client: reqwest::Client,
+    options: ArchiveOptions<'a>,
+    cache: Cache,
+    redirects: RedirectLog,
+    /// Whether each page is archived with a client of its own, so that
+    /// they don't share any Tor circuits
+    #[cfg(feature = "tor")]
+    isolated: bool,
+}
+
+impl<'a> Archiver<'a> {
+    /// Create an archiver with a client configured by the options
+    pub fn new(options: ArchiveOptions<'a>) -> Result<Self, Error> {
+        let redirects = RedirectLog::default();
+        let client = build_client(&options, &redirects)?;
+        Ok(Self {
+            redirects,
+            #[cfg(feature = "tor")]
+            isolated: options.tor.is_some(),
+            ..Self::with_client(client, options)
+        })
+    }
+
+    /// Create an archiver using an existing client. As with
+    /// [`archive_with_client`](crate::archive_with_client), the options
+    /// which configure the client itself are ignored.
+    pub fn with_client(
+        client: reqwest::Client,
+        options: ArchiveOptions<'a>,
+    ) -> Self {
+        Self {
+            client,
+            options,
+            cache: Cache::default(),
+            redirects: RedirectLog::default(),
+            #[cfg(feature = "tor")]
+            isolated: false,
+        }
+    }
+
+    /// The client to archive a page with. Through Tor, each page gets a
+    /// client of its own, with fresh circuits and cookies, so that pages
+    /// archived by the same `Archiver` can't be linked to each other.
+    fn page_client(&self) -> Result<Cow<'_, reqwest::Client>, Error> {
+        #[cfg(feature = "tor")]
+        {
+            if self.isolated {
+                let client = build_client(&self.options, &self.redirects)?;
+                return Ok(Cow::Owned(client));
+            }
+        }
+        Ok(Cow::Borrowed(&self.client))
+    }
+
+    /// Download a page and its resources
+    pub async fn archive<U>(&self, url: U) -> Result<PageArchive, Error>
+    where
+        U: TryInto<Url>,
+        <U as TryInto<Url>>::Error: Display,
+    {
+        let url: Url = url
+            .try_into()
+            .map_err(|e| Error::ParseError(format!("{}", e)))?;
+        let client = self.page_client()?;
+        archive_page(
+            &client,
+            url,
+            &self.options,
+            Some(&self.cache),
+            self.options.http_cache,
+            false,
+            &self.redirects,
+        )
+        .await
+    }
+
+    /// Find the resources that archiving a page would download, fetching
+    /// only the page and its stylesheets, see [`discover`](crate::discover)
+    pub async fn plan<U>(&self, url: U) -> Result<Vec<ResourceUrl>, Error>
+    where
+        U: TryInto<Url>,
+        <U as TryInto<Url>>::Error: Display,
+    {
+        let url: Url = url
+            .try_into()
+            .map_err(|e| Error::ParseError(format!("{}", e)))?;
+        let client = self.page_client()?;
+        discover_page(
+            &client,
+            url,
+            &self.options,
+            Some(&self.cache),
+            self.options.http_cache,
+            &self.redirects,
+        )
+        .await
+    }
+
+    /// Archive the page of an existing `archive` again, asking whether
+    /// each response has changed since, see [`PageArchive::refresh`]
+    pub(crate) async fn refresh(
+        &self,
+        archive: &PageArchive,
+    ) -> Result<PageArchive, Error> {
+        let responses = archive.responses();
+        let client = self.page_client()?;
+        archive_page(
+            &client,
+            archive.url.clone(),
+            &self.options,
+            None,
+            Some(&responses),
+            true,
+            &self.redirects,
+        )
+        .await
+    }
+
+    /// Download several pages one after another, returning the result
+    /// for each in the same order as the URLs
+    pub async fn archive_many<I, U>(
+        &self,
+        urls: I,
+    ) -> Vec<Result<PageArchive, Error>>
+    where
+        I: IntoIterator<Item = U>,
+        U: TryInto<Url>,
+        <U as TryInto<Url>>::Error: Display,
+    {
+        let mut archives = Vec::new();
+        for url in urls {
+            archives.push(self.archive(url).await);
+        }
+        archives
+    }
+
+    /// Forget the resources downloaded so far, so that they're downloaded
+    /// again for later pages
+    pub fn clear_cache(&self) {
+        self.cache.clear();
+    }
+
+    /// The options used for each page
+    pub fn options(&self) -> &ArchiveOptions<'a> {
+        &self.options
+    }
+
+    /// The client used for each page
+    pub(crate) fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_test::block_on;
+
+    #[test]
+    fn test_archive_many() {
+        let archiver = Archiver::new(Default::default()).unwrap();
+        let archives =
+            block_on(archiver.archive_many(vec!["not~a~url", "nor~this"]));
+        assert_eq!(archives.len(), 2);
+        assert!(archives
+            .iter()
+            .all(|a| matches!(a, Err(Error::ParseError(_)))));
+    }
+
+    #[cfg(feature = "tor")]
+    #[test]
+    fn test_page_client() {
+        let tor = || ArchiveOptions::default().tor("127.0.0.1:9050");
+        let archiver = Archiver::new(tor()).unwrap();
+        assert!(matches!(archiver.page_client().unwrap(), Cow::Owned(_)));
+        let archiver = Archiver::with_client(reqwest::Client::new(), tor());
+        assert!(matches!(archiver.page_client().unwrap(), Cow::Borrowed(_)));
+        let archiver = Archiver::new(Default::default()).unwrap();
+        assert!(matches!(archiver.page_client().unwrap(), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_plan() {
+        let archiver = Archiver::new(Default::default()).unwrap();
+        let res = block_on(archiver.plan("not~a~url"));
+        assert!(matches!(res, Err(Error::ParseError(_))));
+    }
+
+    #[test]
+    fn test_archive_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let archiver = Archiver::new(Default::default()).unwrap();
+        assert_send(&archiver.archive("http://example.com"));
+        assert_send(&archiver.archive_many(vec!["http://example.com"]));
+        assert_send(&archiver.plan("http://example.com"));
+        let mut archive = PageArchive {
+            url: Url::parse("http://example.com").unwrap(),
+            content: String::new(),
+            resource_map: Default::default(),
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: Default::default(),
+            meta: Default::default(),
+            stats: Default::default(),
+            screenshot: None,
+        };
+        assert_send(&archive.refresh(&archiver));
+    }
+}
@@ -22,16 +22,35 @@
 //!
 //! ```
 
+use crate::client::{self, RedirectLog, Requests};
+use crate::crawl::{Crawl, CrawlOptions};
+use crate::disk;
+use crate::download::{Cache, Download, Downloader, Fetched, Response};
+use crate::encoding::decode_page;
 use crate::error::Error;
+use crate::event::ArchiveEvent;
+use crate::failure::{FailureReason, ResourceFailure};
+use crate::feed::{Feed, FeedOptions};
+use crate::http_cache::{self, CachedResponse, HttpCache, Policy};
+use crate::memento::{self, Lookup, Memento, MementoOptions, Negotiation};
+use crate::meta::ResourceMeta;
 use crate::page_archive::PageArchive;
-use crate::parsing::{
-    mimetype_from_response, parse_resource_urls, ImageResource, Resource,
-    ResourceMap, ResourceUrl,
-};
+use crate::pagination::{Pagination, PaginationOptions};
+use crate::parsing::{parse_resource_urls, ResourceUrl};
+use crate::resume::{self, Partial};
+use crate::site::SiteArchive;
+use crate::sitemap::{SitemapOptions, Sitemaps};
+use crate::stats::Stopwatch;
 use crate::ArchiveOptions;
-use reqwest::{Proxy, StatusCode};
+use reqwest::header::HeaderMap;
+use std::borrow::Cow;
 use std::convert::TryInto;
 use std::fmt::Display;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use url::Url;
 
 /// The blocking archive function.
@@ -47,54 +66,907 @@ where
     let url: Url = url
         .try_into()
         .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let redirects = RedirectLog::default();
+    let client = build_client(&options, &redirects)?;
+    archive_page(
+        &client,
+        url,
+        &options,
+        None,
+        options.http_cache,
+        false,
+        &redirects,
+    )
+}
 
-    // Initialise client
-    let mut client = reqwest::blocking::Client::builder()
-        .danger_accept_invalid_certs(options.accept_invalid_certificates);
-    if let Some(proxy) = options.proxy {
-        client = client.proxy(Proxy::all(proxy)?);
+/// Archive a page using an existing client, e.g. one shared with the
+/// rest of an application or configured in ways that [`ArchiveOptions`]
+/// doesn't cover.
+///
+/// The options which configure the client itself are ignored: `proxy`,
+/// `accept_invalid_certificates`, `timeout`, `user_agent`, `headers`,
+/// and `max_redirects`. Only the final URL of each redirect is known, so
+/// that's all that [`PageArchive::redirects`] records.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{blocking, ArchiveOptions};
+///
+/// let client = reqwest::blocking::Client::builder()
+///     .user_agent("my-archiver/1.0")
+///     .build()
+///     .unwrap();
+/// let archive = blocking::archive_with_client(
+///     &client,
+///     "http://example.com",
+///     ArchiveOptions::default(),
+/// )
+/// .unwrap();
+/// ```
+pub fn archive_with_client<U>(
+    client: &reqwest::blocking::Client,
+    url: U,
+    options: ArchiveOptions,
+) -> Result<PageArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    archive_page(
+        client,
+        url,
+        &options,
+        None,
+        options.http_cache,
+        false,
+        &RedirectLog::default(),
+    )
+}
+
+/// The blocking equivalent of [`crate::archive_to_file`], archiving a page
+/// and writing it to a file at `path` with its resources embedded.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::blocking;
+///
+/// blocking::archive_to_file(
+///     "http://example.com",
+///     &"example.html",
+///     Default::default(),
+/// )
+/// .unwrap();
+/// ```
+pub fn archive_to_file<U, P>(
+    url: U,
+    path: &P,
+    options: ArchiveOptions,
+) -> Result<PageArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+    P: AsRef<Path>,
+{
+    let archive = archive(url, options)?;
+    let page = archive.embed_resources();
+    disk::write_atomically(path.as_ref(), true, |writer| {
+        writer.write_all(page.as_bytes())
+    })?;
+    Ok(archive)
+}
+
+/// The blocking equivalent of [`crate::discover`], finding the resources
+/// that archiving a page would download while only fetching the page and
+/// its stylesheets.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::blocking;
+///
+/// let resources =
+///     blocking::discover("http://example.com", Default::default()).unwrap();
+/// println!("{} resources", resources.len());
+/// ```
+pub fn discover<U>(
+    url: U,
+    options: ArchiveOptions,
+) -> Result<Vec<ResourceUrl>, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let redirects = RedirectLog::default();
+    let client = build_client(&options, &redirects)?;
+    discover_page(&client, url, &options, None, options.http_cache, &redirects)
+}
+
+/// The blocking equivalent of [`crate::crawl`], archiving a site by
+/// following the links between its pages.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{blocking, CrawlOptions};
+///
+/// let options = CrawlOptions::default().max_pages(20);
+/// let site = blocking::crawl("http://example.com", options).unwrap();
+/// site.write_to_disk(&"example").unwrap();
+/// ```
+pub fn crawl<U>(url: U, options: CrawlOptions) -> Result<SiteArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let mut crawl = Crawl::new(url, &options);
+    let archiver = Archiver::new(options.archive)?;
+    while let Some((url, depth)) = crawl.next() {
+        if let Some(robots_url) = crawl.robots_url(&url) {
+            let response = fetch_robots(archiver.client(), &robots_url);
+            crawl.robots_fetched(&robots_url, response);
+        }
+        let delay = match crawl.permit(&url) {
+            Some(delay) => delay,
+            None => continue,
+        };
+        thread::sleep(delay);
+        let archive = archiver.archive(url.clone());
+        crawl.archived(url, depth, archive)?;
+    }
+    Ok(crawl.finish())
+}
+
+/// The blocking equivalent of [`crate::archive_sitemap`], archiving the
+/// pages listed by a sitemap.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{blocking, SitemapOptions};
+///
+/// let options = SitemapOptions::default().max_pages(100);
+/// let site =
+///     blocking::archive_sitemap("http://example.com/sitemap.xml", options)
+///         .unwrap();
+/// site.write_to_disk(&"example").unwrap();
+/// ```
+pub fn archive_sitemap<U>(
+    url: U,
+    options: SitemapOptions,
+) -> Result<SiteArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let mut sitemaps = Sitemaps::new(url, &options);
+    let archiver = Archiver::new(options.archive)?;
+    while let Some((url, depth)) = sitemaps.next_sitemap() {
+        let response = fetch_sitemap(archiver.client(), &url);
+        sitemaps.fetched(url, depth, response)?;
+    }
+    while let Some(url) = sitemaps.next_page() {
+        let archive = archiver.archive(url.clone());
+        sitemaps.archived(url, archive);
+    }
+    Ok(sitemaps.finish())
+}
+
+/// The blocking equivalent of [`crate::archive_feed`], archiving the
+/// pages of a feed's entries.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{blocking, FeedOptions};
+///
+/// let options = FeedOptions::default().max_entries(10);
+/// let site =
+///     blocking::archive_feed("http://example.com/feed.xml", options)
+///         .unwrap();
+/// for entry in site.entries() {
+///     println!("{}: {:?}", entry.url, entry.title);
+/// }
+/// ```
+pub fn archive_feed<U>(
+    url: U,
+    options: FeedOptions,
+) -> Result<SiteArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let mut feed = Feed::new(&options);
+    let archiver = Archiver::new(options.archive)?;
+    let response = archiver.client().get(url.clone()).send()?;
+    let status = response.status().as_u16();
+    let data = response.bytes()?;
+    feed.read(&url, status, &data)?;
+    while let Some(url) = feed.next_page() {
+        let archive = archiver.archive(url.clone());
+        feed.archived(url, archive);
+    }
+    Ok(feed.finish())
+}
+
+/// The blocking equivalent of [`crate::archive_paginated`], archiving a
+/// page along with the pages before and after it.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{blocking, PaginationOptions};
+///
+/// let site = blocking::archive_paginated(
+///     "http://example.com/article?page=2",
+///     PaginationOptions::default(),
+/// )
+/// .unwrap();
+/// site.write_to_disk(&"article").unwrap();
+/// ```
+pub fn archive_paginated<U>(
+    url: U,
+    options: PaginationOptions,
+) -> Result<SiteArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let mut pagination = Pagination::new(url, &options);
+    let archiver = Archiver::new(options.archive)?;
+    while let Some(url) = pagination.next_page() {
+        let archive = archiver.archive(url.clone());
+        pagination.archived(url, archive)?;
+    }
+    Ok(pagination.finish())
+}
+
+/// The blocking equivalent of [`crate::archive_memento`], archiving a
+/// page as it was at some time in the past.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use web_archive::{blocking, MementoOptions};
+///
+/// // 1st January 2010
+/// let datetime = UNIX_EPOCH + Duration::from_secs(1_262_304_000);
+/// let memento = blocking::archive_memento(
+///     "http://example.com",
+///     datetime,
+///     MementoOptions::default(),
+/// )
+/// .unwrap();
+/// memento.archive.write_to_disk(&"example").unwrap();
+/// ```
+pub fn archive_memento<U>(
+    url: U,
+    datetime: SystemTime,
+    options: MementoOptions,
+) -> Result<Memento, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let mut negotiation = Negotiation::new(url, datetime, &options)?;
+    let archiver = Archiver::new(options.archive)?;
+    while let Some(lookup) = negotiation.next_lookup() {
+        match lookup {
+            Lookup::TimeGate(gate) => {
+                let response = archiver
+                    .client()
+                    .get(gate)
+                    .header(
+                        memento::ACCEPT_DATETIME,
+                        negotiation.accept_datetime(),
+                    )
+                    .send();
+                if let Ok(response) = response {
+                    let url = response.url().clone();
+                    let status = response.status().as_u16();
+                    negotiation.negotiated(url, status, response.headers());
+                }
+            }
+            Lookup::TimeMap(timemap) => {
+                let response = archiver.client().get(timemap).send();
+                if let Ok(response) = response {
+                    let url = response.url().clone();
+                    let status = response.status().as_u16();
+                    if let Ok(text) = response.text() {
+                        negotiation.listed(&url, status, &text);
+                    }
+                }
+            }
+        }
     }
-    let client = client.build()?;
+    let (original, memento, datetime) = negotiation.finish()?;
+    Ok(Memento {
+        original,
+        datetime,
+        archive: archiver.archive(memento)?,
+    })
+}
 
-    // Fetch the page contents
-    let content = client.get(url.clone()).send()?.text()?;
+/// Fetch a sitemap, returning its status and contents
+fn fetch_sitemap(
+    client: &reqwest::blocking::Client,
+    url: &Url,
+) -> Result<(u16, Vec<u8>), Error> {
+    let response = client.get(url.clone()).send()?;
+    let status = response.status().as_u16();
+    Ok((status, response.bytes()?.to_vec()))
+}
+
+/// Fetch a `robots.txt`, returning its status and text, or `None` if it
+/// couldn't be fetched
+fn fetch_robots(
+    client: &reqwest::blocking::Client,
+    url: &Url,
+) -> Option<(u16, String)> {
+    let response = client.get(url.clone()).send().ok()?;
+    let status = response.status().as_u16();
+    Some((status, response.text().ok()?))
+}
+
+/// Archive a page, sharing downloads with other pages through the
+/// `cache` if there is one, and keeping responses in the `http_cache`,
+/// which is usually `options.http_cache`, or else holds the responses of
+/// an archive being refreshed if `replaying`. The `redirects` are those
+/// followed by the client.
+pub(crate) fn archive_page(
+    client: &reqwest::blocking::Client,
+    url: Url,
+    options: &ArchiveOptions,
+    cache: Option<&Cache>,
+    http_cache: Option<&dyn HttpCache>,
+    replaying: bool,
+    redirects: &RedirectLog,
+) -> Result<PageArchive, Error> {
+    let mut requests = Requests::new(url, options, redirects, http_cache);
+    requests.replaying = replaying;
+    let started = Instant::now();
+    let (url, content, page) = load_page(client, options, &requests)?;
 
     // Determine the resources that the page needs
-    let resource_urls = parse_resource_urls(&url, &content);
-    let mut resource_map = ResourceMap::new();
-
-    // Download them
-    for resource_url in resource_urls {
-        use ResourceUrl::*;
-
-        let response = client.get(resource_url.url().clone()).send()?;
-        if response.status() != StatusCode::OK {
-            // Skip any errors
-            println!("Code: {}", response.status());
-            continue;
+    let resource_urls = parse_resource_urls(&url, &content, options);
+
+    let mut downloader =
+        Downloader::new(url.clone(), resource_urls, options, cache);
+    downloader.redirected(requests.page.clone(), page.redirects);
+    downloader.fetched(url, page.meta);
+    downloader.timed(page.timing);
+    download_resources(client, options, &requests, &mut downloader);
+
+    Ok(downloader.into_archive(content, started.elapsed()))
+}
+
+/// Download the resources that the `downloader` has queued, on several
+/// threads at once. Storing them may turn up more resources, such as
+/// modules imported by the scripts, so keep going until there are none
+/// left.
+fn download_resources(
+    client: &reqwest::blocking::Client,
+    options: &ArchiveOptions,
+    requests: &Requests,
+    downloader: &mut Downloader,
+) {
+    let page_url = &downloader.page_url().clone();
+    loop {
+        let batch = downloader.take_queue();
+        if batch.is_empty() {
+            break;
         }
-        match resource_url {
-            Image(u) => {
-                let data = response.bytes()?;
-                let mimetype = mimetype_from_response(&data, &u);
-                resource_map.insert(
-                    u,
-                    Resource::Image(ImageResource { data, mimetype }),
-                );
+        let threads = options.concurrency.clamp(1, batch.len());
+        let batch = Mutex::new(batch.into_iter());
+        let downloads = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let next = batch.lock().unwrap().next();
+                    let (resource_url, depth) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    let download = fetch(
+                        client,
+                        options,
+                        requests,
+                        page_url,
+                        resource_url,
+                        depth,
+                    );
+                    downloads.lock().unwrap().push(download);
+                });
             }
-            Css(u) => {
-                resource_map.insert(u, Resource::Css(response.text()?));
+        });
+        for fetched in downloads.into_inner().unwrap() {
+            downloader.record(fetched);
+        }
+    }
+}
+
+/// Find the resources that archiving a page would download, fetching
+/// only the page and its stylesheets, see [`discover`]
+pub(crate) fn discover_page(
+    client: &reqwest::blocking::Client,
+    url: Url,
+    options: &ArchiveOptions,
+    cache: Option<&Cache>,
+    http_cache: Option<&dyn HttpCache>,
+    redirects: &RedirectLog,
+) -> Result<Vec<ResourceUrl>, Error> {
+    let requests = Requests::new(url, options, redirects, http_cache);
+    let (url, content, _) = load_page(client, options, &requests)?;
+    let resource_urls = parse_resource_urls(&url, &content, options);
+    let mut downloader =
+        Downloader::new(url, resource_urls, options, cache).stylesheets_only();
+    download_resources(client, options, &requests, &mut downloader);
+    Ok(downloader.into_queue())
+}
+
+/// Fetch the page and decode its content, returning the URL that it came
+/// from after following any redirects, the content, and the response
+fn load_page(
+    client: &reqwest::blocking::Client,
+    options: &ArchiveOptions,
+    requests: &Requests,
+) -> Result<(Url, String, Response), Error> {
+    let url = &requests.page;
+    if !options.allows_scheme(url) {
+        return Err(Error::DisallowedScheme(url.to_string()));
+    }
+    if options.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+    // Credentials are sent to the host that was asked for, even if the
+    // page is redirected elsewhere
+    let headers = requests.headers(options, None)?;
+    let cached = requests.http_cache.and_then(|cache| cache.get(url));
+    let page = match cached {
+        Some(cached) if cached.is_fresh() => cached.into_response(),
+        cached => fetch_page(client, options, requests, headers, cached)?,
+    };
+    // Relative URLs in the page are resolved against the URL that it was
+    // redirected to
+    let url = page.redirects.last().unwrap_or(url).clone();
+    options.emit(ArchiveEvent::PageFetched {
+        url: url.clone(),
+        bytes: page.data.len(),
+    });
+    let content = decode_page(page.meta.content_type.as_deref(), &page.data);
+    Ok((url, content, page))
+}
+
+/// Archives many pages with the same client and options, caching
+/// resources between them. The blocking equivalent of
+/// [`crate::Archiver`].
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{blocking::Archiver, ArchiveOptions};
+///
+/// let archiver = Archiver::new(ArchiveOptions::default()).unwrap();
+/// let pages =
+///     archiver.archive_many(vec!["http://example.com/", "http://example.com/blog"]);
+/// ```
+pub struct Archiver<'a> {
+    client: reqwest::blocking::Client,
+    options: ArchiveOptions<'a>,
+    cache: Cache,
+    redirects: RedirectLog,
+    /// Whether each page is archived with a client of its own, so that
+    /// they don't share any Tor circuits
+    #[cfg(feature = "tor")]
+    isolated: bool,
+}
+
+impl<'a> Archiver<'a> {
+    /// Create an archiver with a client configured by the options
+    pub fn new(options: ArchiveOptions<'a>) -> Result<Self, Error> {
+        let redirects = RedirectLog::default();
+        let client = build_client(&options, &redirects)?;
+        Ok(Self {
+            redirects,
+            #[cfg(feature = "tor")]
+            isolated: options.tor.is_some(),
+            ..Self::with_client(client, options)
+        })
+    }
+
+    /// Create an archiver using an existing client. As with
+    /// [`archive_with_client`], the options which configure the client
+    /// itself are ignored.
+    pub fn with_client(
+        client: reqwest::blocking::Client,
+        options: ArchiveOptions<'a>,
+    ) -> Self {
+        Self {
+            client,
+            options,
+            cache: Cache::default(),
+            redirects: RedirectLog::default(),
+            #[cfg(feature = "tor")]
+            isolated: false,
+        }
+    }
+
+    /// The client to archive a page with. Through Tor, each page gets a
+    /// client of its own, with fresh circuits and cookies, so that pages
+    /// archived by the same `Archiver` can't be linked to each other.
+    fn page_client(&self) -> Result<Cow<'_, reqwest::blocking::Client>, Error> {
+        #[cfg(feature = "tor")]
+        {
+            if self.isolated {
+                let client = build_client(&self.options, &self.redirects)?;
+                return Ok(Cow::Owned(client));
             }
-            Javascript(u) => {
-                resource_map.insert(u, Resource::Javascript(response.text()?));
+        }
+        Ok(Cow::Borrowed(&self.client))
+    }
+
+    /// Download a page and its resources
+    pub fn archive<U>(&self, url: U) -> Result<PageArchive, Error>
+    where
+        U: TryInto<Url>,
+        <U as TryInto<Url>>::Error: Display,
+    {
+        let url: Url = url
+            .try_into()
+            .map_err(|e| Error::ParseError(format!("{}", e)))?;
+        let client = self.page_client()?;
+        archive_page(
+            &client,
+            url,
+            &self.options,
+            Some(&self.cache),
+            self.options.http_cache,
+            false,
+            &self.redirects,
+        )
+    }
+
+    /// Find the resources that archiving a page would download, fetching
+    /// only the page and its stylesheets, see [`discover`]
+    pub fn plan<U>(&self, url: U) -> Result<Vec<ResourceUrl>, Error>
+    where
+        U: TryInto<Url>,
+        <U as TryInto<Url>>::Error: Display,
+    {
+        let url: Url = url
+            .try_into()
+            .map_err(|e| Error::ParseError(format!("{}", e)))?;
+        let client = self.page_client()?;
+        discover_page(
+            &client,
+            url,
+            &self.options,
+            Some(&self.cache),
+            self.options.http_cache,
+            &self.redirects,
+        )
+    }
+
+    /// Archive the page of an existing `archive` again, asking whether
+    /// each response has changed since, see [`PageArchive::refresh`]
+    pub(crate) fn refresh(
+        &self,
+        archive: &PageArchive,
+    ) -> Result<PageArchive, Error> {
+        let responses = archive.responses();
+        let client = self.page_client()?;
+        archive_page(
+            &client,
+            archive.url.clone(),
+            &self.options,
+            None,
+            Some(&responses),
+            true,
+            &self.redirects,
+        )
+    }
+
+    /// Download several pages one after another, returning the result
+    /// for each in the same order as the URLs
+    pub fn archive_many<I, U>(&self, urls: I) -> Vec<Result<PageArchive, Error>>
+    where
+        I: IntoIterator<Item = U>,
+        U: TryInto<Url>,
+        <U as TryInto<Url>>::Error: Display,
+    {
+        urls.into_iter().map(|url| self.archive(url)).collect()
+    }
+
+    /// Forget the resources downloaded so far, so that they're downloaded
+    /// again for later pages
+    pub fn clear_cache(&self) {
+        self.cache.clear();
+    }
+
+    /// The options used for each page
+    pub fn options(&self) -> &ArchiveOptions<'a> {
+        &self.options
+    }
+
+    /// The client used for each page
+    pub(crate) fn client(&self) -> &reqwest::blocking::Client {
+        &self.client
+    }
+}
+
+/// Build a client configured by the options, which records the redirects
+/// it follows in `redirects`
+pub(crate) fn build_client(
+    options: &ArchiveOptions,
+    redirects: &RedirectLog,
+) -> Result<reqwest::blocking::Client, Error> {
+    let mut client = reqwest::blocking::Client::builder()
+        .danger_accept_invalid_certs(options.accept_invalid_certificates)
+        .default_headers(client::default_headers(options)?)
+        .redirect(client::redirect_policy(options, redirects.clone()));
+    for certificate in client::root_certificates(options)? {
+        client = client.add_root_certificate(certificate);
+    }
+    if let Some(identity) = client::identity(options)? {
+        client = client.identity(identity);
+    }
+    if let Some(version) = options.min_tls_version {
+        client = client.min_tls_version(version);
+    }
+    for (host, address) in client::resolve(options)? {
+        client = client.resolve(host, address);
+    }
+    if let Some(jar) = client::cookie_jar(options)? {
+        client = client.cookie_provider(jar);
+    }
+    if let Some(proxy) = client::proxy(options)? {
+        client = client.proxy(proxy);
+    }
+    if let Some(timeout) = options.timeout {
+        client = client.timeout(timeout);
+    }
+    if let Some(user_agent) = options.user_agent {
+        client = client.user_agent(user_agent);
+    }
+    Ok(client.build()?)
+}
+
+/// Request the page, unless the `cached` response is still current
+fn fetch_page(
+    client: &reqwest::blocking::Client,
+    options: &ArchiveOptions,
+    requests: &Requests,
+    headers: HeaderMap,
+    cached: Option<CachedResponse>,
+) -> Result<Response, Error> {
+    let url = &requests.page;
+    let hosts = &requests.hosts;
+    thread::sleep(options.rate_limit_delay(url).max(hosts.delay(url)));
+    let mut request = client.get(url.clone()).headers(headers);
+    if let Some(cached) = &cached {
+        request = request.headers(cached.meta.validators.conditional_headers());
+    }
+    if let Some(timeout) = options.request_timeout(requests.deadline) {
+        request = request.timeout(timeout);
+    }
+    let log = requests.redirects.start(url, None);
+    let mut stopwatch = Stopwatch::start(url);
+    let response = request.send()?;
+    stopwatch.first_byte();
+    let redirects = log.finish(response.url());
+    let (status, headers) = (response.status(), response.headers());
+    let cache = requests.http_cache;
+    if let Some(page) =
+        http_cache::revalidate(cache, url, cached, status, headers)
+    {
+        let timing = Some(stopwatch.stop(0));
+        return Ok(Response { timing, ..page });
+    }
+    let policy = Policy::new(status, headers);
+    let meta = ResourceMeta::new(status, response.url(), headers);
+    let data = response.bytes()?;
+    let page = Response {
+        redirects,
+        meta,
+        timing: Some(stopwatch.stop(data.len() as u64)),
+        transformed: false,
+        data,
+    };
+    Policy::store(policy, cache, url, &page);
+    Ok(page)
+}
+
+/// Fetch a single resource of the page at `page_url`, retrying it up to
+/// `options.retries` times if it fails for a reason that might be
+/// temporary. Resources which can't be downloaded are reported rather
+/// than failing the archive.
+fn fetch(
+    client: &reqwest::blocking::Client,
+    options: &ArchiveOptions,
+    requests: &Requests,
+    page_url: &Url,
+    resource_url: ResourceUrl,
+    depth: usize,
+) -> Fetched {
+    if options.is_cancelled() {
+        return Fetched::Cancelled;
+    }
+    options.emit(ArchiveEvent::ResourceStarted {
+        url: resource_url.url().clone(),
+    });
+    let mut retries = 0;
+    // Whatever was downloaded before a failure, to carry on from
+    let mut partial = None;
+    loop {
+        let fetched = fetch_once(
+            client,
+            options,
+            requests,
+            page_url,
+            &resource_url,
+            &mut partial,
+        );
+        match fetched {
+            Ok(response) => {
+                let download = Download::new(resource_url, depth, response);
+                return Fetched::Downloaded(Box::new(download));
+            }
+            Err(_) if options.is_cancelled() => return Fetched::Cancelled,
+            Err(reason)
+                if reason.is_transient() && retries < options.retries =>
+            {
+                retries += 1
+            }
+            Err(reason) => {
+                return Fetched::Failed(ResourceFailure {
+                    url: resource_url.url().clone(),
+                    reason,
+                    retries,
+                })
             }
         }
     }
+}
 
-    Ok(PageArchive {
-        url,
-        content,
-        resource_map,
+/// Make a single request for a resource, returning the response, or else
+/// the reason that it couldn't be downloaded. Responses in the HTTP cache
+/// are used instead while they're fresh, and afterwards only downloaded
+/// again if they've changed.
+fn fetch_once(
+    client: &reqwest::blocking::Client,
+    options: &ArchiveOptions,
+    requests: &Requests,
+    page_url: &Url,
+    resource_url: &ResourceUrl,
+    partial: &mut Option<Partial>,
+) -> Result<Response, FailureReason> {
+    let url = resource_url.url();
+    let cached = requests.http_cache.and_then(|cache| cache.get(url));
+    if let Some(cached) = cached.as_ref().filter(|cached| cached.is_fresh()) {
+        let transformed = requests.replaying;
+        return Ok(Response {
+            transformed,
+            ..cached.clone().into_response()
+        });
+    }
+    let hosts = &requests.hosts;
+    let _slot = hosts.acquire_blocking(url);
+    thread::sleep(options.rate_limit_delay(url).max(hosts.delay(url)));
+    // The headers were checked when the page was fetched
+    let mut headers = requests
+        .headers(options, Some(resource_url))
+        .unwrap_or_default();
+    // Carrying on from a partial download takes the place of checking
+    // whether the cached response has changed
+    match (&partial, &cached) {
+        (Some(partial), _) => headers.extend(partial.request_headers()),
+        (None, Some(cached)) => {
+            headers.extend(cached.meta.validators.conditional_headers())
+        }
+        (None, None) => {}
+    }
+    let mut request = client.get(url.clone()).headers(headers);
+    if let Some(timeout) = options.request_timeout(requests.deadline) {
+        if timeout == Duration::ZERO {
+            return Err(FailureReason::TimedOut);
+        }
+        request = request.timeout(timeout);
+    }
+    let log = requests.redirects.start(url, Some(page_url));
+    let mut stopwatch = Stopwatch::start(url);
+    let response = request.send()?;
+    stopwatch.first_byte();
+    let redirects = log.finish(response.url());
+    // Clients which weren't built with the log follow redirects anywhere
+    if !options.allows_origin(page_url, response.url()) {
+        return Err(FailureReason::DisallowedHost);
+    }
+    let (status, headers) = (response.status(), response.headers());
+    let cache = requests.http_cache;
+    if let Some(response) =
+        http_cache::revalidate(cache, url, cached, status, headers)
+    {
+        let timing = Some(stopwatch.stop(0));
+        let transformed = requests.replaying;
+        return Ok(Response {
+            timing,
+            transformed,
+            ..response
+        });
+    }
+    let policy = Policy::new(status, headers);
+    let meta = ResourceMeta::new(status, response.url(), headers);
+    if !response.status().is_success() {
+        return Err(FailureReason::Status(response.status().as_u16()));
+    }
+    let (mut data, validator) = resume::carry_on(
+        partial.take(),
+        response.status(),
+        response.headers(),
+    )?;
+    let resumed = data.len();
+    if resumed > 0 {
+        options.emit(ArchiveEvent::ResourceResumed {
+            url: url.clone(),
+            offset: resumed,
+        });
+    }
+    let expected = (data.len() as u64)
+        .saturating_add(response.content_length().unwrap_or(0));
+    if options.exceeds_resource_size(expected) {
+        return Err(FailureReason::TooLarge);
+    }
+    let limit = read_limit(options.max_resource_size, data.len());
+    let mut rest = Vec::new();
+    let read = response.take(limit).read_to_end(&mut rest);
+    // What was read before an error is kept, to carry on from
+    data.extend_from_slice(&rest);
+    if let Err(err) = read {
+        *partial = Partial::new(validator, data);
+        return Err(err.into());
+    }
+    if options.exceeds_resource_size(data.len() as u64) {
+        return Err(FailureReason::TooLarge);
+    }
+    let fetched = Response {
+        redirects,
+        meta,
+        timing: Some(stopwatch.stop((data.len() - resumed) as u64)),
+        transformed: false,
+        data: data.freeze(),
+    };
+    Policy::store(policy, cache, url, &fetched);
+    Ok(fetched)
+}
+
+/// How many more bytes to read of a resource which has `read` already:
+/// one past the `max_resource_size`, to tell whether it was exceeded
+fn read_limit(max_resource_size: Option<u64>, read: usize) -> u64 {
+    max_resource_size.map_or(u64::MAX, |max| {
+        max.saturating_add(1).saturating_sub(read as u64)
     })
 }
 
@@ -114,5 +986,52 @@ mod tests {
         } else {
             panic!("Expected parse error");
         }
+
+        let client = reqwest::blocking::Client::new();
+        let res = archive_with_client(&client, u, Default::default());
+        assert!(matches!(res, Err(Error::ParseError(_))));
+
+        let archiver = Archiver::new(Default::default()).unwrap();
+        let res = archiver.archive_many(vec![u]);
+        assert!(matches!(res[..], [Err(Error::ParseError(_))]));
+    }
+
+    #[test]
+    fn archive_to_file_blocking() {
+        let path = std::env::temp_dir().join(format!(
+            "web-archive-to-file-{}-blocking.html",
+            std::process::id()
+        ));
+        let res =
+            archive_to_file("this~is~not~a~url", &path, Default::default());
+        assert!(matches!(res, Err(Error::ParseError(_))));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn cancelled_before_page() {
+        let token = crate::CancellationToken::new();
+        token.cancel();
+        let options = ArchiveOptions::default().cancellation(token);
+        let res = archive("http://example.com", options);
+        assert!(matches!(res, Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn discover_blocking() {
+        let res = discover("this~is~not~a~url", Default::default());
+        assert!(matches!(res, Err(Error::ParseError(_))));
+        let options = ArchiveOptions::default().https_only();
+        let archiver = Archiver::new(options).unwrap();
+        let res = archiver.plan("http://example.com/");
+        assert!(matches!(res, Err(Error::DisallowedScheme(_))));
+    }
+
+    #[test]
+    fn read_limit_blocking() {
+        assert_eq!(read_limit(None, 10), u64::MAX);
+        assert_eq!(read_limit(Some(100), 10), 91);
+        assert_eq!(read_limit(Some(100), 200), 0);
+        assert_eq!(read_limit(Some(u64::MAX), 0), u64::MAX);
     }
 }
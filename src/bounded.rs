@@ -0,0 +1,114 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for maps of responses which are limited to a number of bytes, so
+//! caches shared between many pages can't grow without bound
+
+use std::collections::{HashMap, VecDeque};
+use url::Url;
+
+/// Map from URLs to values which holds at most `max_size` bytes of them,
+/// forgetting the ones inserted longest ago to make room for new ones
+#[derive(Debug)]
+pub(crate) struct BoundedMap<V> {
+    entries: HashMap<Url, (V, usize)>,
+    /// URLs in the order they were inserted, oldest first
+    order: VecDeque<Url>,
+    size: usize,
+    max_size: usize,
+}
+
+impl<V> BoundedMap<V> {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    pub fn get(&self, url: &Url) -> Option<&V> {
+        self.entries.get(url).map(|(value, _)| value)
+    }
+
+    /// Insert the `value`, which takes up `size` bytes. Values bigger than
+    /// the whole map aren't kept.
+    pub fn insert(&mut self, url: Url, value: V, size: usize) {
+        self.remove(&url);
+        if size > self.max_size {
+            return;
+        }
+        while self.size + size > self.max_size {
+            match self.order.pop_front() {
+                Some(oldest) => self.remove(&oldest),
+                None => break,
+            }
+        }
+        self.size += size;
+        self.order.push_back(url.clone());
+        self.entries.insert(url, (value, size));
+    }
+
+    fn remove(&mut self, url: &Url) {
+        if let Some((_, size)) = self.entries.remove(url) {
+            self.size -= size;
+            self.order.retain(|u| u != url);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.size = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bounded_map() {
+        let url = |path| {
+            Url::parse("http://example.com/")
+                .unwrap()
+                .join(path)
+                .unwrap()
+        };
+        let mut map = BoundedMap::new(10);
+        map.insert(url("a"), 'a', 4);
+        map.insert(url("b"), 'b', 4);
+        assert_eq!(map.len(), 2);
+
+        // The oldest is forgotten to make room
+        map.insert(url("c"), 'c', 4);
+        assert_eq!(map.get(&url("a")), None);
+        assert_eq!(map.get(&url("b")), Some(&'b'));
+        assert_eq!(map.get(&url("c")), Some(&'c'));
+
+        // Replacing a value frees the room the old one took
+        map.insert(url("c"), 'C', 6);
+        assert_eq!(map.get(&url("b")), Some(&'b'));
+        assert_eq!(map.get(&url("c")), Some(&'C'));
+
+        // Too big to keep at all
+        map.insert(url("d"), 'd', 11);
+        assert_eq!(map.get(&url("d")), None);
+
+        assert_eq!(map.len(), 2);
+
+        map.clear();
+        assert_eq!(map.len(), 0);
+        map.insert(url("e"), 'e', 10);
+        assert_eq!(map.get(&url("e")), Some(&'e'));
+    }
+}
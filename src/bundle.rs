@@ -0,0 +1,155 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for packaging the files written by
+//! [`PageArchive::write_to_disk`] into a single ZIP or tar.gz file, see
+//! [`PageArchive::write_zip`] and [`PageArchive::write_tar_gz`]
+
+use crate::disk;
+use crate::page_archive::PageArchive;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Seek, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Write the archive's files into a ZIP file
+pub(crate) fn write_zip<W: Write + Seek>(
+    archive: &PageArchive,
+    writer: W,
+) -> io::Result<()> {
+    let options =
+        FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut zip = ZipWriter::new(writer);
+    for (path, data) in disk::files(archive) {
+        zip.start_file(path.join("/"), options)?;
+        zip.write_all(&data)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Write the archive's files into a gzipped tar file
+pub(crate) fn write_tar_gz<W: Write>(
+    archive: &PageArchive,
+    writer: W,
+) -> io::Result<()> {
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let mut tar =
+        tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+    for (path, data) in disk::files(archive) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        tar.append_data(&mut header, path.join("/"), &data[..])?;
+    }
+    tar.into_inner()?.finish()?.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::{ImageResource, Resource, TextResource};
+    use bytes::Bytes;
+    use flate2::read::GzDecoder;
+    use std::collections::HashMap;
+    use std::io::{Cursor, Read};
+    use url::Url;
+
+    fn archive() -> PageArchive {
+        let url = Url::parse("http://example.com/").unwrap();
+        let mut resource_map = HashMap::new();
+        resource_map.insert(
+            url.join("style.css").unwrap(),
+            Resource::Css(TextResource {
+                data: Bytes::from_static(b"a { color: red }"),
+                mimetype: "text/css".to_string(),
+                charset: None,
+            }),
+        );
+        resource_map.insert(
+            url.join("image.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"png"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        PageArchive {
+            url,
+            content: r#"<link rel="stylesheet" href="style.css">"#.to_string(),
+            resource_map,
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            screenshot: None,
+        }
+    }
+
+    const FILES: &[&str] = &[
+        "index.html",
+        "resources/example.com/image.png",
+        "resources/example.com/style.css",
+        "manifest.json",
+        "SHA256SUMS",
+    ];
+
+    #[test]
+    fn test_write_zip() {
+        let mut file = Cursor::new(Vec::new());
+        write_zip(&archive(), &mut file).unwrap();
+        let mut zip = zip::ZipArchive::new(file).unwrap();
+        let names: Vec<_> = zip.file_names().collect();
+        assert_eq!(names.len(), FILES.len());
+        for name in FILES {
+            assert!(names.contains(name), "{} missing", name);
+        }
+
+        let mut style = String::new();
+        zip.by_name("resources/example.com/style.css")
+            .unwrap()
+            .read_to_string(&mut style)
+            .unwrap();
+        assert_eq!(style, "a { color: red }");
+        let mut index = String::new();
+        zip.by_name("index.html")
+            .unwrap()
+            .read_to_string(&mut index)
+            .unwrap();
+        assert!(index.contains(r#"href="resources/example.com/style.css""#));
+    }
+
+    #[test]
+    fn test_write_tar_gz() {
+        let mut file = Vec::new();
+        write_tar_gz(&archive(), &mut file).unwrap();
+        let mut tar = tar::Archive::new(GzDecoder::new(&file[..]));
+        let files: Vec<_> = tar
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (path, data)
+            })
+            .collect();
+        let names: Vec<_> = files.iter().map(|(path, _)| path).collect();
+        assert_eq!(names, FILES);
+        assert_eq!(files[1].1, b"png");
+    }
+}
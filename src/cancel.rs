@@ -0,0 +1,134 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for cancelling archives which are in progress
+
+use futures::future::{self, Either};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Cancels an archive from another task or thread, see
+/// [`ArchiveOptions::cancellation`](crate::ArchiveOptions::cancellation).
+///
+/// Clones share the same state, so cancelling any of them cancels every
+/// archive using the token.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{archive, ArchiveOptions, CancellationToken};
+///
+/// # async fn archive_async() {
+/// let token = CancellationToken::new();
+/// let options = ArchiveOptions {
+///     cancellation: Some(token.clone()),
+///     ..Default::default()
+/// };
+///
+/// // e.g. when the user presses Ctrl-C
+/// std::thread::spawn(move || token.cancel());
+///
+/// let archive = archive("http://example.com", options).await.unwrap();
+/// if archive.cancelled {
+///     println!("Only some of the resources were archived");
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    /// Wakes the futures waiting for the token to be cancelled. Each
+    /// stops waiting when it's dropped, so a long-lived token doesn't
+    /// collect the futures of every archive which has used it.
+    notify: Notify,
+}
+
+impl CancellationToken {
+    /// Create a token which hasn't been cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every archive using the token. Async downloads in progress
+    /// are abandoned straight away, while blocking downloads in progress
+    /// are allowed to finish but no more are started.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    /// Whether the token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait for the token to be cancelled
+    async fn cancelled(&self) {
+        // Waiting starts here, so that cancelling after the check below
+        // still wakes it
+        let notified = self.0.notify.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await
+    }
+}
+
+/// Run a future to completion, or return `None` if the token is cancelled
+/// first
+pub(crate) async fn until_cancelled<F: Future>(
+    token: Option<&CancellationToken>,
+    f: F,
+) -> Option<F::Output> {
+    let token = match token {
+        Some(token) => token,
+        None => return Some(f.await),
+    };
+    match future::select(Box::pin(f), Box::pin(token.cancelled())).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_test::block_on;
+
+    #[test]
+    fn test_until_cancelled() {
+        let token = CancellationToken::new();
+        assert_eq!(
+            block_on(until_cancelled(Some(&token), async { 1 })),
+            Some(1)
+        );
+        assert_eq!(block_on(until_cancelled(None, async { 2 })), Some(2));
+
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        let never = future::pending::<()>();
+        assert_eq!(block_on(until_cancelled(Some(&token), never)), None);
+    }
+
+    #[test]
+    fn test_cancel_wakes() {
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            canceller.cancel();
+        });
+        let never = future::pending::<()>();
+        assert_eq!(block_on(until_cancelled(Some(&token), never)), None);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,976 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for archiving a site by following the links between its pages,
+//! see [`crawl`]
+
+use crate::archiver::Archiver;
+use crate::cancel::CancellationToken;
+use crate::error::Error;
+use crate::failure::FailureReason;
+use crate::mimetype;
+use crate::normalize::UrlNormalizer;
+use crate::page_archive::PageArchive;
+use crate::robots::Robots;
+use crate::site::SiteArchive;
+use crate::ArchiveOptions;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::convert::TryInto;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Configuration options for [`crawl`], controlling which links are
+/// followed and how far
+pub struct CrawlOptions<'a> {
+    /// The options used to archive each page
+    ///
+    /// Default: [`ArchiveOptions::default`]
+    pub archive: ArchiveOptions<'a>,
+    /// How many links away from the start page to go. The start page is
+    /// at a depth of `0`, the pages it links to at `1`, and so on, so a
+    /// value of `0` archives only the start page.
+    ///
+    /// Default: `3`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::CrawlOptions;
+    /// let options = CrawlOptions {
+    ///     max_depth: 1,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_depth: usize,
+    /// The most pages to request, counting the start page and any which
+    /// couldn't be archived, or `None` for no limit
+    ///
+    /// Default: `Some(100)`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::CrawlOptions;
+    /// let options = CrawlOptions {
+    ///     max_pages: Some(20),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_pages: Option<usize>,
+    /// Which hosts to follow links to, relative to the host of the start
+    /// page after following any redirects
+    ///
+    /// Default: [`CrawlScope::Host`]
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{CrawlOptions, CrawlScope};
+    /// let options = CrawlOptions {
+    ///     scope: CrawlScope::Domain,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub scope: CrawlScope,
+    /// Only follow links to pages whose path starts with this, e.g.
+    /// `/blog/`. The start page is archived wherever it is.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::CrawlOptions;
+    /// let options = CrawlOptions {
+    ///     path_prefix: Some("/docs/"),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub path_prefix: Option<&'a str>,
+    /// Archive pages which the sites' `robots.txt` files disallow, and
+    /// don't wait for their `Crawl-delay`s. Only do this for sites you
+    /// have permission to crawl.
+    ///
+    /// Otherwise each host's `robots.txt` is fetched before its first
+    /// page, and the rules which apply are those of the groups naming
+    /// the product in the archive options'
+    /// [`user_agent`](ArchiveOptions::user_agent), e.g. `my-archiver` for
+    /// `my-archiver/1.0`, or `web-archive` if there isn't one. Pages it
+    /// disallows are recorded in [`SiteArchive::disallowed`]. A host
+    /// without a `robots.txt` allows every page, while one whose
+    /// `robots.txt` can't be fetched because of a server or network
+    /// error allows none.
+    ///
+    /// Default: `false`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::CrawlOptions;
+    /// let options = CrawlOptions {
+    ///     ignore_robots: true,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub ignore_robots: bool,
+    /// Which order to archive the pages found in, which decides the pages
+    /// archived when the crawl is cut short by
+    /// [`max_pages`](#structfield.max_pages)
+    ///
+    /// Default: [`CrawlOrder::BreadthFirst`]
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{CrawlOptions, CrawlOrder};
+    /// let docs_first =
+    ///     |url: &url::Url| url.path().starts_with("/docs/") as i64;
+    /// let options = CrawlOptions {
+    ///     order: CrawlOrder::Priority(&docs_first),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub order: CrawlOrder<'a>,
+    /// How to tell whether two URLs are for the same page, so that it's
+    /// only archived once. A page whose `<link rel="canonical">` is a
+    /// page archived already is left out too.
+    ///
+    /// Default: [`UrlNormalizer::for_pages`]
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{CrawlOptions, UrlNormalizer};
+    /// let options = CrawlOptions {
+    ///     normalizer: UrlNormalizer::for_pages().strip_param("sessionid"),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub normalizer: UrlNormalizer,
+}
+
+impl<'a> CrawlOptions<'a> {
+    /// Archive each page with the options, see
+    /// [`CrawlOptions::archive`](#structfield.archive)
+    pub fn archive(mut self, options: ArchiveOptions<'a>) -> Self {
+        self.archive = options;
+        self
+    }
+
+    /// Set how many links away from the start page to go, see
+    /// [`CrawlOptions::max_depth`](#structfield.max_depth)
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Set the most pages to request, see
+    /// [`CrawlOptions::max_pages`](#structfield.max_pages)
+    pub fn max_pages(mut self, n: usize) -> Self {
+        self.max_pages = Some(n);
+        self
+    }
+
+    /// Set which hosts to follow links to, see
+    /// [`CrawlOptions::scope`](#structfield.scope)
+    pub fn scope(mut self, scope: CrawlScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Only follow links to paths starting with `prefix`, see
+    /// [`CrawlOptions::path_prefix`](#structfield.path_prefix)
+    pub fn path_prefix(mut self, prefix: &'a str) -> Self {
+        self.path_prefix = Some(prefix);
+        self
+    }
+
+    /// Ignore the sites' `robots.txt` files, see
+    /// [`CrawlOptions::ignore_robots`](#structfield.ignore_robots)
+    pub fn ignore_robots(mut self, ignore: bool) -> Self {
+        self.ignore_robots = ignore;
+        self
+    }
+
+    /// Set which order to archive the pages in, see
+    /// [`CrawlOptions::order`](#structfield.order)
+    pub fn order(mut self, order: CrawlOrder<'a>) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Set how to tell whether URLs are for the same page, see
+    /// [`CrawlOptions::normalizer`](#structfield.normalizer)
+    pub fn normalizer(mut self, normalizer: UrlNormalizer) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+}
+
+impl<'a> Default for CrawlOptions<'a> {
+    fn default() -> Self {
+        Self {
+            archive: ArchiveOptions::default(),
+            max_depth: 3,
+            max_pages: Some(100),
+            scope: CrawlScope::default(),
+            path_prefix: None,
+            ignore_robots: false,
+            order: CrawlOrder::default(),
+            normalizer: UrlNormalizer::for_pages(),
+        }
+    }
+}
+
+/// Which hosts a crawl follows links to, see [`CrawlOptions::scope`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CrawlScope {
+    /// Only the host of the start page, e.g. `www.example.com`
+    #[default]
+    Host,
+    /// The host of the start page and its subdomains, e.g.
+    /// `blog.example.com` and `docs.blog.example.com` when starting from
+    /// `blog.example.com`
+    Subdomains,
+    /// The domain of the start page and all its subdomains, which is its
+    /// host without any leading `www.`, e.g. `example.com` and
+    /// `blog.example.com` when starting from `www.example.com`
+    Domain,
+}
+
+/// Which order a crawl archives the pages it finds in, see
+/// [`CrawlOptions::order`]
+#[derive(Clone, Copy, Default)]
+#[non_exhaustive]
+pub enum CrawlOrder<'a> {
+    /// The pages nearest to the start page first, for the broadest
+    /// coverage of the site
+    #[default]
+    BreadthFirst,
+    /// Each link followed as far as it goes before the next, in the order
+    /// the links appear, for the whole of one section before another
+    DepthFirst,
+    /// The pages whose URLs the function gives the highest priority
+    /// first, with pages of the same priority nearest to the start page
+    /// first
+    Priority(&'a (dyn Fn(&Url) -> i64 + Sync)),
+}
+
+/// Archive a site, starting from the page at `url` and following the
+/// links between its pages, as far as the options allow.
+///
+/// Pages are archived one after another, nearest to the start page first
+/// unless the options give another [`order`](CrawlOptions::order),
+/// with an [`Archiver`] so that resources shared between them are only
+/// downloaded once. Only links to `http` and `https` URLs in the options'
+/// [`scope`](CrawlOptions::scope) are followed, and not those to files
+/// which aren't pages going by their extension, such as images and PDFs.
+/// Pages which turn out not to be HTML, or which are redirected out of
+/// scope, are left out, as are pages which have been archived already
+/// under another URL, see [`normalizer`](CrawlOptions::normalizer). Each host's `robots.txt` is honoured unless the
+/// options [ignore](CrawlOptions::ignore_robots) it, though the resources
+/// of the pages are archived regardless.
+///
+/// An error is only returned if the start page can't be archived. Other
+/// pages which can't be, including those with an unsuccessful status
+/// code, are recorded in [`SiteArchive::failures`]. If the options'
+/// [`cancellation`](ArchiveOptions::cancellation) token is cancelled, the
+/// pages archived so far are returned.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{crawl, CrawlOptions};
+///
+/// # async fn crawl_async() {
+/// let options = CrawlOptions::default().max_depth(2).path_prefix("/blog/");
+/// let site = crawl("http://example.com/blog/", options).await.unwrap();
+/// site.write_to_disk(&"example").unwrap();
+/// # }
+/// ```
+pub async fn crawl<U>(
+    url: U,
+    options: CrawlOptions<'_>,
+) -> Result<SiteArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let mut crawl = Crawl::new(url, &options);
+    let archiver = Archiver::new(options.archive)?;
+    while let Some((url, depth)) = crawl.next() {
+        if let Some(robots_url) = crawl.robots_url(&url) {
+            let response = fetch_robots(archiver.client(), &robots_url).await;
+            crawl.robots_fetched(&robots_url, response);
+        }
+        let delay = match crawl.permit(&url) {
+            Some(delay) => delay,
+            None => continue,
+        };
+        tokio::time::sleep(delay).await;
+        let archive = archiver.archive(url.clone()).await;
+        crawl.archived(url, depth, archive)?;
+    }
+    Ok(crawl.finish())
+}
+
+/// Fetch a `robots.txt`, returning its status and text, or `None` if it
+/// couldn't be fetched
+async fn fetch_robots(
+    client: &reqwest::Client,
+    url: &Url,
+) -> Option<(u16, String)> {
+    let response = client.get(url.clone()).send().await.ok()?;
+    let status = response.status().as_u16();
+    Some((status, response.text().await.ok()?))
+}
+
+/// The state of a crawl, shared between the async and blocking APIs,
+/// which hand out the pages to archive and report back how each went
+pub(crate) struct Crawl<'a> {
+    max_depth: usize,
+    max_pages: Option<usize>,
+    scope: CrawlScope,
+    path_prefix: Option<&'a str>,
+    cancellation: Option<CancellationToken>,
+    /// The product named by the `User-Agent`, which `robots.txt` rules
+    /// are looked up by, or `None` if they're ignored
+    agent: Option<String>,
+    /// The `robots.txt` rules of each origin fetched so far
+    robots: HashMap<String, Robots>,
+    /// When the last page was requested from each origin, for keeping to
+    /// their `Crawl-delay`s
+    last_requested: HashMap<String, Instant>,
+    /// The host that the scope is relative to, taken from the start page
+    /// once it's been archived
+    host: Option<String>,
+    order: CrawlOrder<'a>,
+    /// Pages waiting to be archived
+    queue: BinaryHeap<Queued>,
+    /// How many pages have been queued, for ordering those with the same
+    /// priority
+    queued: i64,
+    normalizer: UrlNormalizer,
+    /// Pages which have been queued, normalized
+    seen: HashSet<Url>,
+    /// Pages which have been archived and their canonical URLs,
+    /// normalized
+    archived: HashSet<Url>,
+    /// How many pages have been requested, which doesn't include those
+    /// disallowed by `robots.txt`
+    requested: usize,
+    cancelled: bool,
+    site: SiteArchive,
+}
+
+impl<'a> Crawl<'a> {
+    pub fn new(mut url: Url, options: &CrawlOptions<'a>) -> Self {
+        url.set_fragment(None);
+        Self {
+            max_depth: options.max_depth,
+            max_pages: options.max_pages,
+            scope: options.scope,
+            path_prefix: options.path_prefix,
+            cancellation: options.archive.cancellation.clone(),
+            agent: Some(agent(&options.archive))
+                .filter(|_| !options.ignore_robots),
+            robots: HashMap::new(),
+            last_requested: HashMap::new(),
+            host: None,
+            order: options.order,
+            queue: vec![Queued {
+                key: (0, 0),
+                url: url.clone(),
+                depth: 0,
+            }]
+            .into(),
+            queued: 1,
+            seen: vec![options.normalizer.normalize(&url)]
+                .into_iter()
+                .collect(),
+            archived: HashSet::new(),
+            normalizer: options.normalizer.clone(),
+            requested: 0,
+            cancelled: false,
+            site: SiteArchive::new(),
+        }
+    }
+
+    /// The next page to archive and its depth, or `None` once the crawl
+    /// is over
+    pub fn next(&mut self) -> Option<(Url, usize)> {
+        let cancelled = self
+            .cancellation
+            .as_ref()
+            .map_or(false, CancellationToken::is_cancelled);
+        let full = self.max_pages.map_or(false, |max| self.requested >= max);
+        if self.cancelled || cancelled || full {
+            return None;
+        }
+        self.queue.pop().map(|queued| (queued.url, queued.depth))
+    }
+
+    /// The URL of the `robots.txt` that applies to `url`, if it hasn't
+    /// been fetched yet and isn't being ignored
+    pub fn robots_url(&self, url: &Url) -> Option<Url> {
+        self.agent.as_ref()?;
+        if self.robots.contains_key(&origin(url)) {
+            return None;
+        }
+        url.join("/robots.txt").ok()
+    }
+
+    /// Record the status and text of the `robots.txt` at `url`, or `None`
+    /// if it couldn't be fetched
+    pub fn robots_fetched(
+        &mut self,
+        url: &Url,
+        response: Option<(u16, String)>,
+    ) {
+        let agent = self.agent.as_deref().unwrap_or("");
+        let robots = match response {
+            Some((200..=299, text)) => Robots::parse(&text, agent),
+            Some((400..=499, _)) => Robots::allow_all(),
+            _ => Robots::disallow_all(),
+        };
+        self.robots.insert(origin(url), robots);
+    }
+
+    /// How long to wait before requesting `url` to keep to its host's
+    /// `Crawl-delay`, or `None` if its `robots.txt` disallows it, in
+    /// which case it's recorded as such
+    pub fn permit(&mut self, url: &Url) -> Option<Duration> {
+        if self.agent.is_none() {
+            self.requested += 1;
+            return Some(Duration::ZERO);
+        }
+        let origin = origin(url);
+        let robots = self.robots.get(&origin);
+        if !robots.map_or(true, |robots| robots.allows(url)) {
+            self.site.disallowed.push(url.clone());
+            return None;
+        }
+        self.requested += 1;
+        let crawl_delay = robots.and_then(Robots::crawl_delay);
+        let now = Instant::now();
+        let at = match (crawl_delay, self.last_requested.get(&origin)) {
+            (Some(crawl_delay), Some(last)) => (*last + crawl_delay).max(now),
+            _ => now,
+        };
+        self.last_requested.insert(origin, at);
+        Some(at - now)
+    }
+
+    /// Record how archiving the page at `url` went, queueing the pages
+    /// it links to. Only an error archiving the start page is returned.
+    pub fn archived(
+        &mut self,
+        url: Url,
+        depth: usize,
+        archive: Result<PageArchive, Error>,
+    ) -> Result<(), Error> {
+        let start = self.host.is_none();
+        let archive = match archive {
+            Ok(archive) => archive,
+            Err(e) if start => return Err(e),
+            Err(Error::Cancelled) => {
+                self.cancelled = true;
+                return Ok(());
+            }
+            Err(e) => {
+                self.site.failed(url, FailureReason::from_error(e));
+                return Ok(());
+            }
+        };
+        if start {
+            let host = archive.url.host_str().unwrap_or("");
+            self.host = Some(host.to_ascii_lowercase());
+        }
+        self.cancelled |= archive.cancelled;
+        for url in archive.redirects.keys().chain(Some(&archive.url)) {
+            self.seen.insert(self.normalizer.normalize(url));
+        }
+
+        let meta = archive.meta.get(&archive.url);
+        let status = meta.map_or(200, |meta| meta.status);
+        if status >= 400 {
+            self.site.failed(url, FailureReason::Status(status));
+            return Ok(());
+        }
+        let is_html = meta
+            .and_then(|meta| meta.content_type.as_deref())
+            .map_or(true, |content_type| {
+                content_type.to_ascii_lowercase().contains("html")
+            });
+        if !start && (!is_html || !self.in_scope(&archive.url)) {
+            return Ok(());
+        }
+
+        // Leave out a page which has been archived already under another
+        // URL, going by their canonical URLs, such as a listing sorted
+        // another way
+        let page = archive.parse();
+        let own = self.normalizer.normalize(&archive.url);
+        let canonical = page
+            .canonical_url()
+            .filter(|url| self.in_scope_host(url))
+            .map(|url| self.normalizer.normalize(&url));
+        let duplicate = Some(&own)
+            .into_iter()
+            .chain(&canonical)
+            .any(|url| self.archived.contains(url));
+        if !start && duplicate {
+            return Ok(());
+        }
+        if let Some(canonical) = canonical {
+            self.seen.insert(canonical.clone());
+            self.archived.insert(canonical);
+        }
+        self.archived.insert(own);
+
+        if depth < self.max_depth {
+            let mut links = page.links();
+            if let CrawlOrder::DepthFirst = self.order {
+                // The last page queued is archived first
+                links.reverse();
+            }
+            for link in links {
+                let mut url = link.url;
+                url.set_fragment(None);
+                if self.in_scope(&url)
+                    && self.seen.insert(self.normalizer.normalize(&url))
+                {
+                    self.push(url, depth + 1);
+                }
+            }
+        }
+        self.site.add_page(archive);
+        Ok(())
+    }
+
+    /// The pages archived so far
+    pub fn finish(self) -> SiteArchive {
+        self.site
+    }
+
+    /// Queue the page at `url` to be archived
+    fn push(&mut self, url: Url, depth: usize) {
+        let n = self.queued;
+        self.queued += 1;
+        let key = match self.order {
+            CrawlOrder::BreadthFirst => (0, -n),
+            CrawlOrder::DepthFirst => (0, n),
+            CrawlOrder::Priority(priority) => (priority(&url), -n),
+        };
+        self.queue.push(Queued { key, url, depth });
+    }
+
+    /// Whether a link to `url` should be followed
+    fn in_scope(&self, url: &Url) -> bool {
+        if !matches!(url.scheme(), "http" | "https") {
+            return false;
+        }
+        let is_page = mimetype::from_extension(url).map_or(true, |mimetype| {
+            matches!(mimetype, "text/html" | "application/xhtml+xml")
+        });
+        let in_path = self
+            .path_prefix
+            .map_or(true, |prefix| url.path().starts_with(prefix));
+        is_page && in_path && self.in_scope_host(url)
+    }
+
+    fn in_scope_host(&self, url: &Url) -> bool {
+        let start = self.host.as_deref().unwrap_or("");
+        let host = url.host_str().unwrap_or("").to_ascii_lowercase();
+        let is_under = |domain: &str| {
+            host == domain
+                || host
+                    .strip_suffix(domain)
+                    .map_or(false, |sub| sub.ends_with('.'))
+        };
+        match self.scope {
+            CrawlScope::Host => host == start,
+            CrawlScope::Subdomains => is_under(start),
+            CrawlScope::Domain => {
+                is_under(start.strip_prefix("www.").unwrap_or(start))
+            }
+        }
+    }
+}
+
+/// The product named by the options' `User-Agent`, e.g. `my-archiver`
+/// for `my-archiver/1.0`, or `web-archive` if there isn't one
+fn agent(options: &ArchiveOptions) -> String {
+    let product = options
+        .user_agent
+        .and_then(|user_agent| user_agent.split(['/', ' ']).next())
+        .map(str::trim)
+        .filter(|product| !product.is_empty());
+    product.unwrap_or("web-archive").to_ascii_lowercase()
+}
+
+/// A page waiting to be archived, which the queue hands out highest
+/// `key` first
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Queued {
+    /// The page's priority, and then the order it was queued in, as
+    /// its crawl order has it
+    key: (i64, i64),
+    url: Url,
+    depth: usize,
+}
+
+/// The origin of a URL, which `robots.txt` files apply to
+fn origin(url: &Url) -> String {
+    url.origin().ascii_serialization()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::meta::ResourceMeta;
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    fn page(url: &str, content_type: &str, links: &[&str]) -> PageArchive {
+        let url = Url::parse(url).unwrap();
+        let content = links
+            .iter()
+            .map(|link| format!("<a href=\"{}\">Link</a>", link))
+            .collect();
+        let meta = ResourceMeta {
+            status: 200,
+            final_url: url.clone(),
+            fetched_at: SystemTime::now(),
+            content_type: Some(content_type.to_string()),
+            cache_control: None,
+            validators: Default::default(),
+            snapshot: None,
+            sha256: None,
+        };
+        PageArchive {
+            url: url.clone(),
+            content,
+            resource_map: HashMap::new(),
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: vec![(url, meta)].into_iter().collect(),
+            stats: Default::default(),
+            screenshot: None,
+        }
+    }
+
+    /// Crawl a site made up of `pages`, returning the URLs requested
+    fn crawl_pages(
+        options: CrawlOptions,
+        pages: &[(&str, &[&str])],
+    ) -> (Vec<String>, SiteArchive) {
+        crawl_with_robots(options, None, pages)
+    }
+
+    /// Crawl a site made up of `pages` with a `robots.txt` if there is
+    /// one, returning the URLs requested
+    fn crawl_with_robots(
+        options: CrawlOptions,
+        robots: Option<&str>,
+        pages: &[(&str, &[&str])],
+    ) -> (Vec<String>, SiteArchive) {
+        let start = Url::parse(pages[0].0).unwrap();
+        let mut crawl = Crawl::new(start, &options);
+        let mut requested = Vec::new();
+        while let Some((url, depth)) = crawl.next() {
+            if let Some(robots_url) = crawl.robots_url(&url) {
+                let response = match robots {
+                    Some(robots) => (200, robots.to_string()),
+                    None => (404, String::new()),
+                };
+                crawl.robots_fetched(&robots_url, Some(response));
+            }
+            if crawl.permit(&url).is_none() {
+                continue;
+            }
+            requested.push(url.to_string());
+            let archive = pages
+                .iter()
+                .find(|(page, _)| *page == url.as_str())
+                .map(|(page, links)| page_for(page, links))
+                .ok_or_else(|| Error::ReqwestError("not found".to_string()));
+            crawl.archived(url, depth, archive).unwrap();
+        }
+        (requested, crawl.finish())
+    }
+
+    fn page_for(url: &str, links: &[&str]) -> PageArchive {
+        page(url, "text/html; charset=utf-8", links)
+    }
+
+    #[test]
+    fn test_crawl_depth_and_limit() {
+        let pages: &[(&str, &[&str])] = &[
+            ("http://example.com/", &["/a", "/b#top", "/logo.png"]),
+            (
+                "http://example.com/a",
+                &["/", "/c", "mailto:me@example.com"],
+            ),
+            ("http://example.com/b", &["/a", "/missing"]),
+            ("http://example.com/c", &["/d"]),
+        ];
+        let (requested, site) = crawl_pages(Default::default(), pages);
+        assert_eq!(
+            requested,
+            vec![
+                "http://example.com/",
+                "http://example.com/a",
+                "http://example.com/b",
+                "http://example.com/c",
+                "http://example.com/missing",
+                "http://example.com/d",
+            ]
+        );
+        assert_eq!(site.len(), 4);
+        let failures: Vec<_> =
+            site.failures().iter().map(|f| f.url.as_str()).collect();
+        assert_eq!(
+            failures,
+            vec!["http://example.com/missing", "http://example.com/d"]
+        );
+
+        let options = CrawlOptions::default().max_depth(1);
+        let (requested, _) = crawl_pages(options, pages);
+        assert_eq!(requested.len(), 3);
+        let options = CrawlOptions::default().max_pages(2);
+        let (requested, site) = crawl_pages(options, pages);
+        assert_eq!(requested.len(), 2);
+        assert_eq!(site.len(), 2);
+    }
+
+    #[test]
+    fn test_crawl_order() {
+        let pages: &[(&str, &[&str])] = &[
+            ("http://example.com/", &["/a", "/b", "/docs/"]),
+            ("http://example.com/a", &["/a/1", "/a/2"]),
+            ("http://example.com/a/1", &["/a/1/x"]),
+            ("http://example.com/b", &["/docs/b"]),
+            ("http://example.com/docs/", &["/docs/c"]),
+        ];
+        let paths = |options: CrawlOptions| {
+            let (requested, _) = crawl_pages(options, pages);
+            requested
+                .iter()
+                .map(|url| Url::parse(url).unwrap().path().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let options = CrawlOptions::default().max_pages(6);
+        assert_eq!(
+            paths(options),
+            vec!["/", "/a", "/b", "/docs/", "/a/1", "/a/2"]
+        );
+        let options = CrawlOptions::default()
+            .order(CrawlOrder::DepthFirst)
+            .max_pages(6);
+        assert_eq!(
+            paths(options),
+            vec!["/", "/a", "/a/1", "/a/1/x", "/a/2", "/b"]
+        );
+        let docs_first = |url: &Url| url.path().starts_with("/docs/") as i64;
+        let options = CrawlOptions::default()
+            .order(CrawlOrder::Priority(&docs_first))
+            .max_pages(6);
+        assert_eq!(
+            paths(options),
+            vec!["/", "/docs/", "/docs/c", "/a", "/b", "/docs/b"]
+        );
+    }
+
+    #[test]
+    fn test_crawl_scope() {
+        let pages: &[(&str, &[&str])] = &[
+            (
+                "http://www.example.com/docs/",
+                &[
+                    "intro",
+                    "/blog/",
+                    "https://www.example.com/docs/https",
+                    "http://blog.example.com/docs/",
+                    "http://docs.www.example.com/docs/",
+                    "http://example.org/docs/",
+                    "http://notexample.com/docs/",
+                ],
+            ),
+            ("http://www.example.com/docs/intro", &[]),
+        ];
+        let crawled = |options: CrawlOptions| {
+            let (mut requested, _) = crawl_pages(options, pages);
+            requested.remove(0);
+            requested
+        };
+        assert_eq!(
+            crawled(Default::default()),
+            vec![
+                "http://www.example.com/docs/intro",
+                "http://www.example.com/blog/",
+                "https://www.example.com/docs/https",
+            ]
+        );
+        assert_eq!(
+            crawled(CrawlOptions::default().path_prefix("/docs/")),
+            vec![
+                "http://www.example.com/docs/intro",
+                "https://www.example.com/docs/https",
+            ]
+        );
+        let options = CrawlOptions::default()
+            .scope(CrawlScope::Subdomains)
+            .path_prefix("/docs/");
+        assert_eq!(
+            crawled(options),
+            vec![
+                "http://www.example.com/docs/intro",
+                "https://www.example.com/docs/https",
+                "http://docs.www.example.com/docs/",
+            ]
+        );
+        let options = CrawlOptions::default()
+            .scope(CrawlScope::Domain)
+            .path_prefix("/docs/");
+        assert_eq!(
+            crawled(options),
+            vec![
+                "http://www.example.com/docs/intro",
+                "https://www.example.com/docs/https",
+                "http://blog.example.com/docs/",
+                "http://docs.www.example.com/docs/",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_crawl_robots() {
+        let pages: &[(&str, &[&str])] = &[
+            ("http://example.com/", &["/a", "/private/b", "/c"]),
+            ("http://example.com/a", &[]),
+            ("http://example.com/private/b", &[]),
+            ("http://example.com/c", &[]),
+        ];
+        let robots = "User-agent: *\nDisallow: /private/\n\n\
+            User-agent: my-archiver\nDisallow: /c\n";
+        let crawled = |options| crawl_with_robots(options, Some(robots), pages);
+
+        let (requested, site) = crawled(CrawlOptions::default().max_pages(3));
+        assert_eq!(
+            requested,
+            vec![
+                "http://example.com/",
+                "http://example.com/a",
+                "http://example.com/c",
+            ]
+        );
+        let disallowed: Vec<_> =
+            site.disallowed().iter().map(Url::as_str).collect();
+        assert_eq!(disallowed, vec!["http://example.com/private/b"]);
+
+        let archive = ArchiveOptions::default().user_agent("My-Archiver/1.0");
+        let (requested, _) = crawled(CrawlOptions::default().archive(archive));
+        assert_eq!(requested.len(), 3);
+        assert_eq!(requested[2], "http://example.com/private/b");
+
+        let (requested, site) =
+            crawled(CrawlOptions::default().ignore_robots(true));
+        assert_eq!(requested.len(), 4);
+        assert!(site.disallowed().is_empty());
+
+        // A robots.txt which can't be fetched disallows everything
+        let options = CrawlOptions::default();
+        let start = Url::parse("http://example.com/").unwrap();
+        let mut crawl = Crawl::new(start.clone(), &options);
+        let robots_url = crawl.robots_url(&start).unwrap();
+        assert_eq!(robots_url.as_str(), "http://example.com/robots.txt");
+        crawl.robots_fetched(&robots_url, Some((503, String::new())));
+        assert!(crawl.robots_url(&start).is_none());
+        assert_eq!(crawl.permit(&start), None);
+    }
+
+    #[test]
+    fn test_crawl_delay() {
+        let options = CrawlOptions::default();
+        let start = Url::parse("http://example.com/").unwrap();
+        let mut crawl = Crawl::new(start.clone(), &options);
+        let robots = "User-agent: *\nCrawl-delay: 10\n".to_string();
+        crawl.robots_fetched(&start, Some((200, robots)));
+        assert_eq!(crawl.permit(&start), Some(Duration::ZERO));
+        let delay = crawl.permit(&start.join("/a").unwrap()).unwrap();
+        assert!(delay > Duration::from_secs(9));
+        let other = Url::parse("http://example.org/").unwrap();
+        assert_eq!(crawl.permit(&other), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_crawl_skips_other_pages() {
+        let options = CrawlOptions::default();
+        let start = Url::parse("http://example.com/").unwrap();
+        let mut crawl = Crawl::new(start.clone(), &options);
+        let (url, depth) = crawl.next().unwrap();
+        let archive = page_for(url.as_str(), &["/feed", "/moved"]);
+        crawl.archived(url, depth, Ok(archive)).unwrap();
+
+        // A page which isn't HTML is left out
+        let (url, depth) = crawl.next().unwrap();
+        let archive = page(url.as_str(), "application/rss+xml", &["/a"]);
+        crawl.archived(url, depth, Ok(archive)).unwrap();
+        // As is one redirected elsewhere
+        let (url, depth) = crawl.next().unwrap();
+        let mut archive = page_for("http://example.org/", &["/b"]);
+        archive.redirects.insert(url.clone(), vec![]);
+        crawl.archived(url, depth, Ok(archive)).unwrap();
+        assert!(crawl.next().is_none());
+        assert_eq!(crawl.finish().len(), 1);
+
+        // As are pages which are the same as one archived already
+        let mut crawl = Crawl::new(start, &options);
+        let (url, depth) = crawl.next().unwrap();
+        let links = [
+            "/a/",
+            "/a?utm_source=feed",
+            "/b?y=2&x=1",
+            "/b?x=1&y=2#top",
+            "/list?sort=new",
+            "/list",
+        ];
+        let archive = page_for(url.as_str(), &links);
+        crawl.archived(url, depth, Ok(archive)).unwrap();
+        let mut requested = Vec::new();
+        while let Some((url, depth)) = crawl.next() {
+            requested.push(url.to_string());
+            let mut archive = page_for(url.as_str(), &[]);
+            if url.query() == Some("sort=new") {
+                archive.content =
+                    "<link rel=\"canonical\" href=\"/list\">".into();
+            }
+            crawl.archived(url, depth, Ok(archive)).unwrap();
+        }
+        assert_eq!(
+            requested,
+            vec![
+                "http://example.com/a/",
+                "http://example.com/b?y=2&x=1",
+                "http://example.com/list?sort=new",
+                "http://example.com/list",
+            ]
+        );
+        assert_eq!(crawl.finish().len(), 4);
+
+        // The start page failing fails the crawl
+        let mut crawl =
+            Crawl::new(Url::parse("http://a.b/").unwrap(), &options);
+        let (url, depth) = crawl.next().unwrap();
+        let res = crawl.archived(url, depth, Err(Error::Cancelled));
+        assert!(matches!(res, Err(Error::Cancelled)));
+    }
+}
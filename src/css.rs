@@ -0,0 +1,418 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for locating and rewriting the `url()` references in CSS
+
+use std::ops::Range;
+
+const IMAGE_SET: &str = "image-set(";
+const IMPORT: &str = "@import";
+const FONT_FACE: &str = "@font-face";
+
+/// A `url()` reference found within some CSS
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct CssUrl {
+    /// Byte range of the whole `url(...)` token within the CSS
+    pub range: Range<usize>,
+    /// The unquoted and unescaped URL
+    pub url: String,
+    /// What the URL is used for, going by where it appears
+    pub kind: CssUrlKind,
+}
+
+/// What a URL within some CSS refers to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CssUrlKind {
+    /// Anything other than the below, which in practice means images
+    Image,
+    /// A font from the `src` of an `@font-face` rule
+    Font,
+    /// A stylesheet pulled in by an `@import` rule
+    Import,
+}
+
+/// Find every `url()` in the CSS which points at an external resource,
+/// along with the plain strings which `image-set()` and `@import` accept
+/// in place of a `url()`.
+///
+/// References to fragments within the document (e.g. `url(#gradient)`)
+/// and existing `data:` URIs are skipped, as is anything inside
+/// comments or other string literals.
+pub(crate) fn find_urls(css: &str) -> Vec<CssUrl> {
+    let bytes = css.as_bytes();
+    let mut urls = Vec::new();
+    let mut i = 0;
+    // Bracket nesting depth, and the depths at which the arguments of any
+    // `image-set()`s currently being read sit
+    let mut depth = 0_usize;
+    let mut image_sets = Vec::new();
+    // Whether an `@import` is waiting for its URL, and whether an
+    // `@font-face` is waiting for its block
+    let mut import = false;
+    let mut font_face = false;
+    // Block nesting depth, and the depth of the `@font-face` block being
+    // read if there is one
+    let mut blocks = 0_usize;
+    let mut font_face_block = None;
+
+    while i < bytes.len() {
+        let kind = if import {
+            CssUrlKind::Import
+        } else if font_face_block.is_some() {
+            CssUrlKind::Font
+        } else {
+            CssUrlKind::Image
+        };
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // Skip over the comment
+                i = match css[i + 2..].find("*/") {
+                    Some(end) => i + 2 + end + 2,
+                    None => bytes.len(),
+                };
+            }
+            quote @ b'"' | quote @ b'\'' => {
+                let (end, url) = read_string(css, i + 1, quote);
+                let wanted =
+                    (import && depth == 0) || image_sets.last() == Some(&depth);
+                if wanted && is_external(&url) {
+                    urls.push(CssUrl {
+                        range: i..end,
+                        url,
+                        kind,
+                    });
+                }
+                import = false;
+                i = end;
+            }
+            b'@' if starts_with_at_keyword(&css[i..], IMPORT) => {
+                import = true;
+                i += IMPORT.len();
+            }
+            b'@' if starts_with_at_keyword(&css[i..], FONT_FACE) => {
+                font_face = true;
+                i += FONT_FACE.len();
+            }
+            b';' => {
+                import = false;
+                i += 1;
+            }
+            b'{' => {
+                import = false;
+                blocks += 1;
+                if font_face {
+                    font_face = false;
+                    font_face_block = Some(blocks);
+                }
+                i += 1;
+            }
+            b'}' => {
+                if font_face_block == Some(blocks) {
+                    font_face_block = None;
+                }
+                blocks = blocks.saturating_sub(1);
+                i += 1;
+            }
+            b'i' | b'I'
+                if starts_with_ignore_case(&css[i..], IMAGE_SET)
+                    && (i == 0
+                        || !is_ident_byte(bytes[i - 1])
+                        || ends_with_ignore_case(&css[..i], "-webkit-")) =>
+            {
+                depth += 1;
+                image_sets.push(depth);
+                i += IMAGE_SET.len();
+            }
+            b'(' => {
+                depth += 1;
+                i += 1;
+            }
+            b')' => {
+                if image_sets.last() == Some(&depth) {
+                    image_sets.pop();
+                }
+                depth = depth.saturating_sub(1);
+                i += 1;
+            }
+            b'u' | b'U'
+                if starts_with_ignore_case(&css[i..], "url(")
+                    && (i == 0 || !is_ident_byte(bytes[i - 1])) =>
+            {
+                match read_url(css, i + 4) {
+                    Some((end, url)) => {
+                        if is_external(&url) {
+                            urls.push(CssUrl {
+                                range: i..end,
+                                url,
+                                kind,
+                            });
+                        }
+                        import = false;
+                        i = end;
+                    }
+                    None => i += 4,
+                }
+            }
+            _ => i += 1,
+        }
+    }
+
+    urls
+}
+
+/// Replace each external `url()` in the CSS with the result of calling
+/// `replace` on its URL. If `replace` returns `None` then the original
+/// reference is left untouched. `image-set()` candidates and `@import`s
+/// given as plain strings are replaced with a `url()`, which is equally
+/// valid there.
+pub(crate) fn rewrite_urls<F>(css: &str, mut replace: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut output = String::with_capacity(css.len());
+    let mut last = 0;
+
+    for css_url in find_urls(css) {
+        if let Some(new_url) = replace(&css_url.url) {
+            output.push_str(&css[last..css_url.range.start]);
+            output.push_str("url(\"");
+            output.push_str(&new_url);
+            output.push_str("\")");
+            last = css_url.range.end;
+        }
+    }
+    output.push_str(&css[last..]);
+
+    output
+}
+
+/// Whether the URL refers to something which has to be downloaded
+fn is_external(url: &str) -> bool {
+    !url.is_empty()
+        && !url.starts_with('#')
+        && !starts_with_ignore_case(url, "data:")
+}
+
+fn ends_with_ignore_case(haystack: &str, needle: &str) -> bool {
+    let bytes = haystack.as_bytes();
+    bytes
+        .len()
+        .checked_sub(needle.len())
+        .map(|start| bytes[start..].eq_ignore_ascii_case(needle.as_bytes()))
+        .unwrap_or(false)
+}
+
+fn starts_with_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack
+        .as_bytes()
+        .get(..needle.len())
+        .map(|start| start.eq_ignore_ascii_case(needle.as_bytes()))
+        .unwrap_or(false)
+}
+
+/// Whether the CSS starts with the at-keyword, e.g. `@import`, and not
+/// just a longer one that begins the same way
+fn starts_with_at_keyword(css: &str, keyword: &str) -> bool {
+    starts_with_ignore_case(css, keyword)
+        && !css
+            .as_bytes()
+            .get(keyword.len())
+            .map(|&b| is_ident_byte(b))
+            .unwrap_or(false)
+}
+
+fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b >= 0x80
+}
+
+/// Read the contents of a `url()` token starting just after the opening
+/// bracket. Returns the index just after the closing bracket along with
+/// the URL.
+fn read_url(css: &str, start: usize) -> Option<(usize, String)> {
+    let bytes = css.as_bytes();
+    let mut i = skip_whitespace(bytes, start);
+
+    let url = match bytes.get(i) {
+        Some(&quote) if quote == b'"' || quote == b'\'' => {
+            let (end, url) = read_string(css, i + 1, quote);
+            i = skip_whitespace(bytes, end);
+            url
+        }
+        _ => {
+            let mut url = String::new();
+            let mut end = css.len();
+            let mut chars = css[i..].char_indices();
+            while let Some((offset, c)) = chars.next() {
+                match c {
+                    ')' => {
+                        end = i + offset;
+                        break;
+                    }
+                    '\\' => {
+                        if let Some((_, c)) = chars.next() {
+                            url.push(c);
+                        }
+                    }
+                    c => url.push(c),
+                }
+            }
+            i = end;
+            url.trim_end().to_string()
+        }
+    };
+
+    if bytes.get(i) == Some(&b')') {
+        Some((i + 1, url))
+    } else {
+        None
+    }
+}
+
+/// Read a string literal starting just after the opening quote. Returns
+/// the index just after the closing quote along with the contents.
+fn read_string(css: &str, start: usize, quote: u8) -> (usize, String) {
+    let mut contents = String::new();
+    let mut chars = css[start..].char_indices();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, c)) = chars.next() {
+                    if c != '\n' {
+                        contents.push(c);
+                    }
+                }
+            }
+            c if c as u32 == quote as u32 => {
+                return (start + offset + 1, contents);
+            }
+            c => contents.push(c),
+        }
+    }
+    (css.len(), contents)
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while bytes.get(i).map(u8::is_ascii_whitespace).unwrap_or(false) {
+        i += 1;
+    }
+    i
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn urls(css: &str) -> Vec<String> {
+        find_urls(css).into_iter().map(|u| u.url).collect()
+    }
+
+    #[test]
+    fn test_url_forms() {
+        let css = r#"
+            .a { background: url(hero.jpg) no-repeat; }
+            .b { background-image: url( "quoted.png" ); }
+            .c { background-image: URL('single.gif'); }
+            .d { background-image: url(esc\)aped.png); }
+        "#;
+
+        assert_eq!(
+            urls(css),
+            vec!["hero.jpg", "quoted.png", "single.gif", "esc)aped.png"]
+        );
+    }
+
+    #[test]
+    fn test_skipped_urls() {
+        let css = r#"
+            /* .a { background: url(commented.png); } */
+            .b { content: "url(in-a-string.png)"; }
+            .c { fill: url(#gradient); }
+            .d { background: url("data:image/png;base64,AAAA"); }
+            .e { background: myurl(not-a-url.png); }
+            .f { background: url(unterminated.png
+        "#;
+
+        assert!(urls(css).is_empty());
+    }
+
+    #[test]
+    fn test_image_set() {
+        let css = r#"
+            .a { background: image-set("a.png" 1x, url(a@2x.png) 2x); }
+            .b { background: -webkit-image-set('b.png' 1x, "b@2x.png" 2x); }
+            .c { background: IMAGE-SET("c.avif" type("image/avif")); }
+            .d { content: "not-an-image.png"; }
+            .e { background: image-set("data:image/png;base64,AAAA" 1x); }
+        "#;
+
+        assert_eq!(
+            urls(css),
+            vec!["a.png", "a@2x.png", "b.png", "b@2x.png", "c.avif"]
+        );
+
+        let rewritten = rewrite_urls(r#"image-set("a.png" 1x)"#, |u| {
+            Some(format!("data:{}", u))
+        });
+        assert_eq!(rewritten, r#"image-set(url("data:a.png") 1x)"#);
+    }
+
+    #[test]
+    fn test_kinds() {
+        let css = r#"
+            @import url(base.css) screen;
+            @import "theme.css";
+            @IMPORT 'print.css' print;
+            @font-face {
+                font-family: "Sans";
+                src: url(sans.woff2) format("woff2"), url(sans.ttf);
+            }
+            .a { background: url(hero.jpg); font-family: "Sans"; }
+            @media print { .b { background: url(print.png); } }
+            @importance "not-an-import.css";
+        "#;
+
+        let kinds: Vec<_> = find_urls(css)
+            .into_iter()
+            .map(|u| (u.url, u.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("base.css".to_string(), CssUrlKind::Import),
+                ("theme.css".to_string(), CssUrlKind::Import),
+                ("print.css".to_string(), CssUrlKind::Import),
+                ("sans.woff2".to_string(), CssUrlKind::Font),
+                ("sans.ttf".to_string(), CssUrlKind::Font),
+                ("hero.jpg".to_string(), CssUrlKind::Image),
+                ("print.png".to_string(), CssUrlKind::Image),
+            ]
+        );
+
+        let rewritten = rewrite_urls(r#"@import "theme.css";"#, |u| {
+            Some(format!("data:{}", u))
+        });
+        assert_eq!(rewritten, r#"@import url("data:theme.css");"#);
+    }
+
+    #[test]
+    fn test_rewrite_urls() {
+        let css =
+            "a { background: url(one.png) } b { background: url('two.png') }";
+        let rewritten = rewrite_urls(css, |u| {
+            if u == "one.png" {
+                Some("data:image/png;base64,AAAA".to_string())
+            } else {
+                None
+            }
+        });
+
+        assert_eq!(
+            rewritten,
+            r#"a { background: url("data:image/png;base64,AAAA") } b { background: url('two.png') }"#
+        );
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for reading the dates found in sitemaps and feeds
+
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The start of each month's name, in lower case
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct",
+    "nov", "dec",
+];
+
+/// Parse a W3C date and time, as used by sitemaps and Atom feeds, e.g.
+/// `2021-02-03`, `2021-02-03T04:05Z` or `2021-02-03T04:05:06.7+01:00`.
+/// Times without a time zone are taken to be UTC.
+pub(crate) fn parse_w3c(date: &str) -> Option<SystemTime> {
+    let date = date.trim();
+    let (day, time) = match date.find(['T', 't', ' ']) {
+        Some(t) => (&date[..t], Some(&date[t + 1..])),
+        None => (date, None),
+    };
+    let mut parts = day.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month = parts.next().map_or(Some(1), |month| month.parse().ok())?;
+    let day = parts.next().map_or(Some(1), |day| day.parse().ok())?;
+    let mut seconds = days_from_civil(year, month, day)? * 86_400;
+
+    if let Some(time) = time {
+        let zone = time.find(['Z', 'z', '+', '-']).unwrap_or(time.len());
+        let mut parts = time[..zone].splitn(3, ':');
+        let hours: i64 = parts.next()?.parse().ok()?;
+        let minutes: i64 = parts.next()?.parse().ok()?;
+        let whole_seconds = parts
+            .next()
+            .map_or(Some(0), |s| s.split('.').next()?.parse().ok())?;
+        if !in_range(hours, minutes, whole_seconds) {
+            return None;
+        }
+        seconds += hours * 3600 + minutes * 60 + whole_seconds;
+        seconds -= offset(&time[zone..])?;
+    }
+    let seconds = u64::try_from(seconds).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Parse an RFC 822 date and time, as used by RSS feeds, e.g.
+/// `Wed, 03 Feb 2021 04:05:06 GMT` or `3 Feb 21 04:05 -0500`. The day of
+/// the week is optional and not checked, and two digit years are taken
+/// to be in 1950 to 2049.
+pub(crate) fn parse_rfc822(date: &str) -> Option<SystemTime> {
+    let date = date.trim();
+    // Skip the day of the week
+    let date = match date.find(',') {
+        Some(comma) => &date[comma + 1..],
+        None => date,
+    };
+    let mut parts = date.split_whitespace();
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = parts.next()?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|m| month.starts_with(m))? as u32 + 1;
+    let year: i64 = match parts.next()? {
+        year if year.len() == 2 => {
+            let year: i64 = year.parse().ok()?;
+            if year < 50 {
+                2000 + year
+            } else {
+                1900 + year
+            }
+        }
+        year => year.parse().ok()?,
+    };
+    let mut time = parts.next()?.splitn(3, ':');
+    let hours: i64 = time.next()?.parse().ok()?;
+    let minutes: i64 = time.next()?.parse().ok()?;
+    let seconds: i64 = time.next().map_or(Some(0), |s| s.parse().ok())?;
+    if !in_range(hours, minutes, seconds) {
+        return None;
+    }
+    let zone = match parts.next().unwrap_or("GMT").to_ascii_uppercase() {
+        zone if zone.starts_with(['+', '-']) => offset(&zone)?,
+        zone => {
+            let hours = match zone.as_str() {
+                "GMT" | "UT" | "UTC" | "Z" => 0,
+                "EDT" => -4,
+                "EST" | "CDT" => -5,
+                "CST" | "MDT" => -6,
+                "MST" | "PDT" => -7,
+                "PST" => -8,
+                _ => return None,
+            };
+            hours * 3600
+        }
+    };
+    let seconds = days_from_civil(year, month, day)? * 86_400
+        + hours * 3600
+        + minutes * 60
+        + seconds
+        - zone;
+    let seconds = u64::try_from(seconds).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// The number of seconds that a time zone such as `+01:00`, `-0500` or
+/// `Z` is ahead of UTC
+pub(crate) fn offset(zone: &str) -> Option<i64> {
+    let zone = zone.trim();
+    let sign = match zone.chars().next() {
+        None | Some('Z' | 'z') => return Some(0),
+        Some('+') => 1,
+        Some('-') => -1,
+        Some(_) => return None,
+    };
+    let digits: String = zone[1..].chars().filter(|c| *c != ':').collect();
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Whether a time of day is in range, allowing `24:00:00` and leap
+/// seconds
+fn in_range(hours: i64, minutes: i64, seconds: i64) -> bool {
+    (0..=24).contains(&hours)
+        && (0..=59).contains(&minutes)
+        && (0..=60).contains(&seconds)
+}
+
+/// The number of days from 1970-01-01 to a date, using Howard Hinnant's
+/// `days_from_civil`. Only four digit years are accepted, so that the
+/// arithmetic can't overflow however the date was written.
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> Option<i64> {
+    if !(1..=9999).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+    {
+        return None;
+    }
+    let (month, day) = (i64::from(month), i64::from(day));
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era =
+        year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_w3c() {
+        let at = |seconds| Some(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(parse_w3c("1970-01-01"), at(0));
+        assert_eq!(parse_w3c("2021-02-03"), at(1_612_310_400));
+        assert_eq!(parse_w3c("2021-02"), at(1_612_137_600));
+        assert_eq!(parse_w3c("2021-02-03T04:05:06Z"), at(1_612_325_106));
+        assert_eq!(parse_w3c(" 2021-02-03T04:05Z "), at(1_612_325_100));
+        assert_eq!(
+            parse_w3c("2021-02-03T05:05:06.789+01:00"),
+            at(1_612_325_106)
+        );
+        assert_eq!(parse_w3c("2021-02-02T23:05:06-0500"), at(1_612_325_106));
+        assert_eq!(parse_w3c("2021-13-01"), None);
+        assert_eq!(parse_w3c("yesterday"), None);
+        assert_eq!(parse_w3c("1960-01-01"), None);
+        assert_eq!(parse_w3c("99999999999999-01-01"), None);
+        assert_eq!(parse_w3c("-9223372036854775808-01-01"), None);
+        assert_eq!(parse_w3c("9999-12-31T23:59:59Z"), at(253_402_300_799));
+    }
+
+    #[test]
+    fn test_parse_rfc822() {
+        let at = |seconds| Some(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(
+            parse_rfc822("Wed, 03 Feb 2021 04:05:06 GMT"),
+            at(1_612_325_106)
+        );
+        assert_eq!(parse_rfc822("3 Feb 21 04:05:06 +0000"), at(1_612_325_106));
+        assert_eq!(
+            parse_rfc822("Tue, 2 February 2021 23:05:06 EST"),
+            at(1_612_325_106)
+        );
+        assert_eq!(
+            parse_rfc822("Wed, 03 Feb 2021 05:05:06 +01:00"),
+            at(1_612_325_106)
+        );
+        assert_eq!(parse_rfc822("03 Feb 2021 04:05"), at(1_612_325_100));
+        assert_eq!(parse_rfc822("Wed, 03 Feb 2021 04:05:06 XYZ"), None);
+        assert_eq!(parse_rfc822("2021-02-03"), None);
+        assert_eq!(
+            parse_rfc822("Wed, 03 Feb 99999999999999 04:05:06 GMT"),
+            None
+        );
+        assert_eq!(
+            parse_rfc822("Wed, 03 Feb 2021 -9999999999999999:05:06 GMT"),
+            None
+        );
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for the SHA-256 digests of archived responses and files, see
+//! [`ResourceMeta::sha256`](crate::ResourceMeta::sha256)
+
+use crate::page_archive::PageArchive;
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+
+/// SHA-256 digest of the `data` in lowercase hex
+pub(crate) fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Record the digest of the page and each of the resources in their
+/// [`meta`](PageArchive::meta), replacing any recorded before. The page's
+/// is of its content as UTF-8, and responses without anything in the
+/// archive are left alone.
+pub(crate) fn record(archive: &mut PageArchive) {
+    for (url, meta) in &mut archive.meta {
+        let data = if url == &archive.url {
+            archive.content.as_bytes()
+        } else {
+            match archive.resource_map.get(url) {
+                Some(resource) => resource.data(),
+                None => continue,
+            }
+        };
+        meta.sha256 = Some(sha256(data));
+    }
+}
+
+/// List the digests of the `files` in the format of `sha256sum`, so that
+/// they can be checked with `sha256sum -c`
+pub(crate) fn checksums(files: &[(Vec<String>, Bytes)]) -> String {
+    files
+        .iter()
+        .map(|(path, data)| format!("{}  {}\n", sha256(data), path.join("/")))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::meta::ResourceMeta;
+    use crate::parsing::{ImageResource, Resource};
+    use reqwest::header::HeaderMap;
+    use reqwest::StatusCode;
+    use url::Url;
+
+    // `echo -n "" | sha256sum` and `echo -n "png" | sha256sum`
+    const EMPTY: &str =
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const PNG: &str =
+        "8f8cbb7dcf46e0bc7d53265749a6c17d116093a6ba95e442764060c76fd4a86c";
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(sha256(b""), EMPTY);
+        assert_eq!(sha256(b"png"), PNG);
+    }
+
+    #[test]
+    fn test_record() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let logo = url.join("logo.png").unwrap();
+        let gone = url.join("gone.png").unwrap();
+        let meta = |u| ResourceMeta::new(StatusCode::OK, u, &HeaderMap::new());
+        let mut archive = PageArchive {
+            url: url.clone(),
+            content: String::new(),
+            resource_map: Default::default(),
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: Default::default(),
+            meta: Default::default(),
+            stats: Default::default(),
+            screenshot: None,
+        };
+        archive.resource_map.insert(
+            logo.clone(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"png"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        for u in [&url, &logo, &gone] {
+            archive.meta.insert(u.clone(), meta(u));
+        }
+        record(&mut archive);
+        assert_eq!(archive.meta[&url].sha256.as_deref(), Some(EMPTY));
+        assert_eq!(archive.meta[&logo].sha256.as_deref(), Some(PNG));
+        assert_eq!(archive.meta[&gone].sha256, None);
+    }
+
+    #[test]
+    fn test_checksums() {
+        let files = vec![
+            (vec!["index.html".to_string()], Bytes::new()),
+            (
+                vec!["resources".to_string(), "logo.png".to_string()],
+                Bytes::from_static(b"png"),
+            ),
+        ];
+        assert_eq!(
+            checksums(&files),
+            format!("{}  index.html\n{}  resources/logo.png\n", EMPTY, PNG)
+        );
+    }
+}
@@ -0,0 +1,1324 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for writing archives out to a directory and reading them back
+//! in, see [`PageArchive::write_to_disk`]
+
+use crate::css;
+use crate::digest;
+use crate::dom;
+use crate::javascript::{self, resolve_specifier};
+use crate::manifest;
+use crate::mimetype;
+use crate::page_archive::{
+    remove_subresource_attributes, set_utf8_charset, PageArchive,
+    ORIGINAL_URL_ATTRIBUTES,
+};
+use crate::parsing::{
+    base_url, is_module_script, is_srcset_attribute, matches_tag, parse_srcset,
+    resolve_url, Resource, ResourceMap, TextResource,
+};
+use crate::site::SiteArchive;
+use bytes::Bytes;
+use html5ever::{interface::QualName, local_name, namespace_url, ns};
+use kuchiki::iter::NodeIterator;
+use kuchiki::traits::TendrilSink;
+use kuchiki::{parse_html, Attribute, ExpandedName, NodeRef};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use url::Url;
+
+/// File that the page itself is written to
+pub(crate) const INDEX: &str = "index.html";
+/// File describing the archive, for reading it back in
+const MANIFEST: &str = "manifest.json";
+/// File listing the digests of the others, for checking with
+/// `sha256sum -c`
+pub(crate) const CHECKSUMS: &str = "SHA256SUMS";
+/// File that the screenshot is written to, if there is one
+pub(crate) const SCREENSHOT: &str = "screenshot.png";
+/// Directory that the resources are written under
+const RESOURCES: &str = "resources";
+/// Longest file name to write, in bytes. Most file systems allow 255, but
+/// leave room for the hashes added to clashing names.
+const MAX_NAME_LEN: usize = 100;
+/// File names which Windows reserves for devices, with or without an
+/// extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6",
+    "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6",
+    "LPT7", "LPT8", "LPT9",
+];
+
+/// Write the archive out to `dir`. The page is written last, so that
+/// there's no `index.html` until everything it needs is there, and with
+/// `sync` each file and the directories holding them are synced to disk
+/// before it.
+pub(crate) fn write(
+    archive: &PageArchive,
+    dir: &Path,
+    sync: bool,
+) -> io::Result<()> {
+    write_files(dir, files(archive), sync)
+}
+
+/// Write the site out to `dir`, in the same way as [`write`]
+pub(crate) fn write_site(
+    site: &SiteArchive,
+    dir: &Path,
+    sync: bool,
+) -> io::Result<()> {
+    write_files(dir, site_files(site), sync)
+}
+
+/// Write `files` out to `dir`, with the first, the index, last
+fn write_files(
+    dir: &Path,
+    mut files: Vec<(Vec<String>, Bytes)>,
+    sync: bool,
+) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let (index, html) = files.remove(0);
+    let mut dirs = HashSet::new();
+    for (path, data) in files {
+        let file = file_path(dir, &path)?;
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+            dirs.insert(parent.to_path_buf());
+        }
+        write_atomically(&file, sync, |writer| writer.write_all(&data))?;
+    }
+    if sync {
+        for dir in dirs {
+            sync_dir(&dir)?;
+        }
+    }
+    write_atomically(&file_path(dir, &index)?, sync, |writer| {
+        writer.write_all(&html)
+    })
+}
+
+/// The files making up the archive and their contents, with the paths to
+/// them as lists of names: the page first, then the resources in order of
+/// their paths, then the screenshot if there is one, then the manifest,
+/// and last the checksums of them all
+pub(crate) fn files(archive: &PageArchive) -> Vec<(Vec<String>, Bytes)> {
+    let layout = Layout::new(archive);
+    let index = vec![INDEX.to_string()];
+    let html = layout.rewriter(&index).html(&archive.url, &archive.content);
+
+    let mut files = vec![(index, html.into())];
+    files.extend(resource_files(archive, &layout));
+    if let Some(screenshot) = &archive.screenshot {
+        files.push((vec![SCREENSHOT.to_string()], screenshot.clone()));
+    }
+    let manifest = manifest::to_string(archive, &layout.paths, &files);
+    files.push((vec![MANIFEST.to_string()], manifest.into()));
+    with_checksums(files)
+}
+
+/// The pages of a site as though they were frames of a single page,
+/// alongside its resources, with the URLs which were redirected to them
+pub(crate) fn combined(site: &SiteArchive) -> PageArchive {
+    let mut lazy_attributes = Vec::new();
+    let mut url_attributes = Vec::new();
+    let mut resource_map = site.resources().clone();
+    let mut redirects = HashMap::new();
+    let pages: Vec<_> = site.pages().collect();
+    for page in &pages {
+        for attribute in &page.lazy_attributes {
+            if !lazy_attributes.contains(attribute) {
+                lazy_attributes.push(attribute.clone());
+            }
+        }
+        for attribute in &page.url_attributes {
+            if !url_attributes.contains(attribute) {
+                url_attributes.push(attribute.clone());
+            }
+        }
+        let page_resource = Resource::Document(TextResource {
+            data: page.content.clone().into(),
+            mimetype: "text/html".to_string(),
+            charset: Some("utf-8".to_string()),
+        });
+        resource_map.insert(page.url.clone(), page_resource);
+        for (requested, chain) in &page.redirects {
+            if chain.last() == Some(&page.url) {
+                redirects.insert(requested.clone(), chain.clone());
+            }
+        }
+    }
+    PageArchive {
+        url: match pages.first() {
+            Some(page) => page.url.clone(),
+            None => Url::parse("about:blank").unwrap(),
+        },
+        content: String::new(),
+        resource_map,
+        lazy_attributes,
+        url_attributes,
+        timed_out: Vec::new(),
+        failures: Vec::new(),
+        cancelled: false,
+        redirects,
+        meta: HashMap::new(),
+        stats: Default::default(),
+        screenshot: None,
+    }
+}
+
+/// The files making up a site: an index listing its pages first, then the
+/// pages and the resources in order of their paths
+fn site_files(site: &SiteArchive) -> Vec<(Vec<String>, Bytes)> {
+    // Lay the pages out alongside the resources, so that links between
+    // them can be pointed at their files
+    let combined = combined(site);
+    let pages: Vec<_> = site.pages().collect();
+    let mut layout = Layout::new(&combined);
+    // Point links to URLs which were redirected to a page at its file too
+    for (requested, chain) in &combined.redirects {
+        let page = chain.last().map(without_fragment);
+        let path = match page.and_then(|page| layout.paths.get(&page)) {
+            Some(path) => path.clone(),
+            None => continue,
+        };
+        let through = &chain[..chain.len() - 1];
+        for url in Some(requested).into_iter().chain(through) {
+            let url = without_fragment(url);
+            layout.paths.entry(url).or_insert_with(|| path.clone());
+        }
+    }
+
+    let index = vec![INDEX.to_string()];
+    let document = parse_html().one(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+        <title>Archived pages</title></head><body><ul></ul></body></html>",
+    );
+    let list = document.select_first("ul").unwrap();
+    for page in &pages {
+        let title = crate::metadata::title(&page.document())
+            .unwrap_or_else(|| page.url.to_string());
+        let href = layout.link(&index, &page.url).unwrap_or_default();
+        let anchor = NodeRef::new_element(
+            QualName::new(None, ns!(html), local_name!("a")),
+            Some((
+                ExpandedName::new(ns!(), local_name!("href")),
+                Attribute {
+                    prefix: None,
+                    value: href,
+                },
+            )),
+        );
+        anchor.append(NodeRef::new_text(title));
+        let item = NodeRef::new_element(
+            QualName::new(None, ns!(html), local_name!("li")),
+            None,
+        );
+        item.append(anchor);
+        list.as_node().append(item);
+    }
+
+    let mut files = vec![(index, dom::serialize(&document).into())];
+    files.extend(resource_files(&combined, &layout));
+    with_checksums(files)
+}
+
+/// The `files` followed by the checksums of them all
+fn with_checksums(
+    mut files: Vec<(Vec<String>, Bytes)>,
+) -> Vec<(Vec<String>, Bytes)> {
+    let checksums = digest::checksums(&files);
+    files.push((vec![CHECKSUMS.to_string()], checksums.into()));
+    files
+}
+
+/// The resources of an archive laid out by `layout`, in order of their
+/// paths, with the URLs in text resources pointed at the files
+fn resource_files(
+    archive: &PageArchive,
+    layout: &Layout,
+) -> Vec<(Vec<String>, Bytes)> {
+    let mut resources = Vec::new();
+    let mut written = HashSet::new();
+    for (url, resource) in &archive.resource_map {
+        let key = without_fragment(url);
+        let path = &layout.paths[&key];
+        if !written.insert(key) {
+            continue;
+        }
+        let rewriter = layout.rewriter(path);
+        let text = match resource {
+            Resource::Css(css) => rewriter.css(url, &css.text()),
+            Resource::Javascript(js) => rewriter.js(url, &js.text()),
+            Resource::Document(frame) => rewriter.html(url, &frame.text()),
+            _ => {
+                resources.push((path.clone(), resource.data().clone()));
+                continue;
+            }
+        };
+        resources.push((path.clone(), text.into()));
+    }
+    resources.sort();
+    resources
+}
+
+/// Write the file at `path` by having `write` fill a temporary file
+/// alongside, and renaming that into place, so that the file is never left
+/// half written. With `sync` the file is synced to disk before it's
+/// renamed, and the directory holding it afterwards.
+pub(crate) fn write_atomically<F>(
+    path: &Path,
+    sync: bool,
+    write: F,
+) -> io::Result<()>
+where
+    F: FnOnce(&mut io::BufWriter<fs::File>) -> io::Result<()>,
+{
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not a file: {}", path.display()),
+        )
+    })?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp = path.with_file_name(temp_name);
+
+    let written = fs::File::create(&temp).and_then(|file| {
+        let mut writer = io::BufWriter::new(file);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        if sync {
+            file.sync_all()?;
+        }
+        fs::rename(&temp, path)
+    });
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+        return written;
+    }
+    match path.parent() {
+        Some(parent) if sync => sync_dir(parent),
+        _ => Ok(()),
+    }
+}
+
+/// Sync a directory to disk, so that files created or renamed in it
+/// survive a crash. Windows can't open directories to do this, and syncs
+/// them along with the files in them.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Read an archive written by [`write`] back in from `dir`, pointing the
+/// relative paths back at the original URLs. The digests are worked out
+/// again, since text resources don't come back exactly as they were
+/// downloaded.
+pub(crate) fn read(dir: &Path) -> io::Result<PageArchive> {
+    let manifest =
+        fs::read_to_string(file_path(dir, &[MANIFEST.to_string()])?)?;
+    let manifest = manifest::parse(&manifest)?;
+    let urls: HashMap<_, _> = manifest
+        .resources
+        .iter()
+        .map(|entry| (entry.path.clone(), without_fragment(&entry.url)))
+        .collect();
+    let rewriter = Rewriter {
+        links: FromDisk { urls: &urls },
+        lazy_attributes: &manifest.lazy_attributes,
+        url_attributes: &manifest.url_attributes,
+    };
+
+    let index = [INDEX.to_string()];
+    let content = fs::read_to_string(file_path(dir, &index)?)?;
+    let content = rewriter.html(&file_url(&index), &content);
+
+    let mut resource_map = ResourceMap::new();
+    for entry in &manifest.resources {
+        let data = fs::read(file_path(dir, &entry.path)?)?;
+        let base = file_url(&entry.path);
+        let text = || String::from_utf8_lossy(&data).into_owned();
+        let data = match entry.kind.as_str() {
+            "css" => rewriter.css(&base, &text()).into(),
+            "javascript" => rewriter.js(&base, &text()).into(),
+            "document" => rewriter.html(&base, &text()).into(),
+            _ => Bytes::from(data),
+        };
+        let resource = entry.resource(data).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown kind of resource: {}", entry.kind),
+            )
+        })?;
+        resource_map.insert(entry.url.clone(), resource);
+    }
+    let screenshot = match &manifest.screenshot {
+        Some(path) => Some(fs::read(file_path(dir, path)?)?.into()),
+        None => None,
+    };
+
+    let mut archive = PageArchive {
+        url: manifest.url,
+        content,
+        resource_map,
+        lazy_attributes: manifest.lazy_attributes,
+        url_attributes: manifest.url_attributes,
+        timed_out: manifest.timed_out,
+        failures: Vec::new(),
+        cancelled: manifest.cancelled,
+        redirects: manifest.redirects,
+        meta: manifest.meta,
+        stats: Default::default(),
+        screenshot,
+    };
+    digest::record(&mut archive);
+    Ok(archive)
+}
+
+/// The file at `path` within `dir`, as long as none of the names in the
+/// path could lead out of it
+fn file_path(dir: &Path, path: &[String]) -> io::Result<PathBuf> {
+    let mut file = dir.to_path_buf();
+    for name in path {
+        // The names are sanitised already when writing, but the manifest
+        // could have been tampered with since
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => file.push(name),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsafe file name: {:?}", name),
+                ))
+            }
+        }
+    }
+    Ok(file)
+}
+
+/// A `file:` URL for the file at `path`, which relative paths in it are
+/// resolved against when reading it back in
+fn file_url(path: &[String]) -> Url {
+    let path: Vec<_> = path.iter().map(|name| percent_encode(name)).collect();
+    Url::parse(&format!("file:///{}", path.join("/"))).unwrap()
+}
+
+/// Where each archived resource is written within the output directory
+pub(crate) struct Layout<'a> {
+    archive: &'a PageArchive,
+    /// The path of each resource's file, keyed by its URL without any
+    /// fragment
+    paths: HashMap<Url, Vec<String>>,
+}
+
+impl<'a> Layout<'a> {
+    /// Give every resource in the archive a path of its own. They're
+    /// handed out in order of their URLs, so that the same archive always
+    /// comes out the same.
+    pub(crate) fn new(archive: &'a PageArchive) -> Self {
+        let mut resources: Vec<_> = archive.resource_map.iter().collect();
+        resources.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        let mut claimed = Claimed::default();
+        let mut paths = HashMap::new();
+        for (url, resource) in resources {
+            let url = without_fragment(url);
+            if paths.contains_key(&url) {
+                continue;
+            }
+            let mimetype = resource.mimetype();
+            let path = candidate_paths(&url, mimetype)
+                .find(|path| claimed.claim(path))
+                .expect("the hashed names go on forever");
+            paths.insert(url, path);
+        }
+        Self { archive, paths }
+    }
+
+    /// The relative URL of the file holding `url` from the file at
+    /// `from`, or `None` if it wasn't archived
+    pub(crate) fn link(&self, from: &[String], url: &Url) -> Option<String> {
+        let path = self.paths.get(&without_fragment(url))?;
+        let mut link = relative_path(from, path);
+        if let Some(fragment) = url.fragment() {
+            link.push('#');
+            link.push_str(fragment);
+        }
+        Some(link)
+    }
+
+    /// Rewrites the URLs in a file to be written to `from`
+    fn rewriter<'b>(&'b self, from: &'b [String]) -> Rewriter<'b, ToDisk<'b>> {
+        Rewriter::new(ToDisk { layout: self, from }, self.archive)
+    }
+}
+
+/// Which way the URLs in the files are being rewritten
+pub(crate) trait Links {
+    /// What to replace a URL with, resolving it against `base`, and
+    /// whether it now points at a file in the directory. `None` leaves it
+    /// as it is.
+    fn url(&self, base: &Url, u: &str) -> Option<(String, bool)>;
+
+    /// What to replace the specifier of a module import with, resolving
+    /// it against the module's `base`
+    fn import(&self, base: &Url, specifier: &str) -> Option<String>;
+}
+
+/// Points URLs at the files that the archived resources are written to
+struct ToDisk<'a> {
+    layout: &'a Layout<'a>,
+    from: &'a [String],
+}
+
+impl Links for ToDisk<'_> {
+    /// The relative URL of the archived resource, or otherwise the
+    /// absolute URL so that it can still be loaded from the network
+    fn url(&self, base: &Url, u: &str) -> Option<(String, bool)> {
+        let url = resolve_url(base, u)?;
+        match self.layout.link(self.from, &url) {
+            Some(link) => Some((link, true)),
+            None => Some((url.to_string(), false)),
+        }
+    }
+
+    /// Relative specifiers have to start with `./` or `../`
+    fn import(&self, base: &Url, specifier: &str) -> Option<String> {
+        let url = resolve_specifier(base, specifier)?;
+        match self.layout.link(self.from, &url) {
+            Some(link) if link.starts_with("../") => Some(link),
+            Some(link) => Some(format!("./{}", link)),
+            None => Some(url.to_string()),
+        }
+    }
+}
+
+/// Points relative paths to files in the directory back at the URLs that
+/// they were downloaded from
+struct FromDisk<'a> {
+    /// The URL of each file, without any fragment, keyed by its path
+    urls: &'a HashMap<Vec<String>, Url>,
+}
+
+impl Links for FromDisk<'_> {
+    fn url(&self, base: &Url, u: &str) -> Option<(String, bool)> {
+        let u = u.trim();
+        if u.is_empty() || u.starts_with('#') {
+            return None;
+        }
+        let url = base.join(u).ok()?;
+        if url.scheme() != "file" {
+            return None;
+        }
+        let path: Vec<_> = url.path_segments()?.map(percent_decode).collect();
+        let mut original = self.urls.get(&path)?.clone();
+        original.set_fragment(url.fragment());
+        Some((original.to_string(), true))
+    }
+
+    fn import(&self, base: &Url, specifier: &str) -> Option<String> {
+        self.url(base, specifier).map(|(url, _)| url)
+    }
+}
+
+/// Rewrites the URLs in pages, stylesheets and modules using `links`
+pub(crate) struct Rewriter<'a, L> {
+    links: L,
+    lazy_attributes: &'a [String],
+    url_attributes: &'a [(String, String)],
+}
+
+impl<'a, L: Links> Rewriter<'a, L> {
+    /// Rewrite the URLs in the attributes that `archive` has them in
+    pub(crate) fn new(links: L, archive: &'a PageArchive) -> Self {
+        Self {
+            links,
+            lazy_attributes: &archive.lazy_attributes,
+            url_attributes: &archive.url_attributes,
+        }
+    }
+
+    /// What to replace a URL with, setting `local` if it now points at a
+    /// file in the directory
+    fn url(&self, base: &Url, u: &str, local: &mut bool) -> Option<String> {
+        let (url, is_local) = self.links.url(base, u)?;
+        *local |= is_local;
+        Some(url)
+    }
+
+    /// Like [`Rewriter::url`], for the value of an attribute which may be
+    /// a `srcset`
+    fn attribute(
+        &self,
+        base: &Url,
+        name: &str,
+        value: &str,
+        local: &mut bool,
+    ) -> Option<String> {
+        if !is_srcset_attribute(name) {
+            return self.url(base, value, local);
+        }
+        let srcset: Vec<_> = parse_srcset(value)
+            .iter()
+            .map(|c| {
+                let url = self
+                    .url(base, c.url, local)
+                    .unwrap_or_else(|| c.url.to_string());
+                format!("{} {}", url, c.descriptor).trim_end().to_string()
+            })
+            .collect();
+        Some(srcset.join(", "))
+    }
+
+    /// Rewrite the URLs in a page or frame whose URL is `url`
+    pub(crate) fn html(&self, url: &Url, content: &str) -> String {
+        let document = parse_html().one(content);
+        let base = base_url(url, &document);
+        dom::rewrite_nested(&document, |document| {
+            self.document(document, &base)
+        });
+
+        // Everything has been resolved against the `<base>` already, and
+        // leaving it in would point the relative paths somewhere else
+        let base_elements: Vec<_> = document.select("base").unwrap().collect();
+        for element in base_elements {
+            element.as_node().detach();
+        }
+        set_utf8_charset(&document);
+        dom::serialize(&document)
+    }
+
+    /// Rewrite the URLs in a single document, not including any nested
+    /// `<template>` or `<noscript>` documents
+    fn document(&self, document: &NodeRef, base: &Url) {
+        // Replacing the text of `<style>` and `<script>` elements would
+        // throw off walking the tree as it goes, so gather them up first
+        let elements: Vec<_> = document.descendants().elements().collect();
+        for element in elements {
+            let mut attr = element.attributes.borrow_mut();
+            let mut local = false;
+
+            let names = ORIGINAL_URL_ATTRIBUTES
+                .iter()
+                .copied()
+                .chain(self.lazy_attributes.iter().map(String::as_str))
+                .chain(
+                    self.url_attributes
+                        .iter()
+                        .filter(|(tag, _)| {
+                            matches_tag(tag, &element.name.local)
+                        })
+                        .map(|(_, name)| name.as_str()),
+                );
+            for name in names {
+                let rewritten = attr.get(name).and_then(|value| {
+                    self.attribute(base, name, value, &mut local)
+                });
+                if let Some(value) = rewritten {
+                    attr.insert(name, value);
+                }
+            }
+            // SVG `<image>` and `<use>` may use the legacy `xlink:href`,
+            // which isn't covered by `href` above
+            let xlink = ExpandedName::new(ns!(xlink), local_name!("href"));
+            if let Some(href) = attr.map.get_mut(&xlink) {
+                let value = &mut href.value;
+                if let Some(u) = self.url(base, value, &mut local) {
+                    *value = u;
+                }
+            }
+            if let Some(style) = attr.get_mut("style") {
+                *style =
+                    css::rewrite_urls(style, |u| self.url(base, u, &mut local));
+            }
+            if local {
+                remove_subresource_attributes(&mut attr);
+            }
+
+            let node = element.as_node();
+            let text = match &*element.name.local {
+                "style" => {
+                    let css = node.text_contents();
+                    Some(css::rewrite_urls(&css, |u| {
+                        self.url(base, u, &mut local)
+                    }))
+                }
+                "script"
+                    if !attr.contains("src")
+                        && is_module_script(attr.get("type")) =>
+                {
+                    Some(self.js(base, &node.text_contents()))
+                }
+                _ => None,
+            };
+            if let Some(text) = text {
+                for child in node.children() {
+                    child.detach();
+                }
+                node.append(NodeRef::new_text(text));
+            }
+        }
+    }
+
+    /// Rewrite the `url()`s in a stylesheet whose URL is `url`. It's
+    /// written out as UTF-8, so any `@charset` is updated to match.
+    pub(crate) fn css(&self, url: &Url, css: &str) -> String {
+        let mut local = false;
+        let css = css::rewrite_urls(css, |u| self.url(url, u, &mut local));
+        match css.strip_prefix("@charset \"") {
+            Some(rest) => match rest.find("\";") {
+                Some(end) => format!("@charset \"utf-8\";{}", &rest[end + 2..]),
+                None => css,
+            },
+            None => css,
+        }
+    }
+
+    /// Rewrite the imports of a module whose URL is `url`
+    pub(crate) fn js(&self, url: &Url, js: &str) -> String {
+        javascript::rewrite_imports(js, |specifier| {
+            self.links.import(url, specifier)
+        })
+    }
+}
+
+/// The paths claimed for files so far, compared ignoring case since the
+/// files may be written to a case-insensitive file system
+#[derive(Default)]
+struct Claimed {
+    files: HashSet<String>,
+    dirs: HashSet<String>,
+}
+
+impl Claimed {
+    /// Claim `path` for a file, unless it's taken already or would need a
+    /// directory where there's a file or vice versa
+    fn claim(&mut self, path: &[String]) -> bool {
+        let mut prefixes: Vec<String> = path
+            .iter()
+            .scan(String::new(), |prefix, name| {
+                prefix.push('/');
+                prefix.push_str(&name.to_lowercase());
+                Some(prefix.clone())
+            })
+            .collect();
+        let file = match prefixes.pop() {
+            Some(file) => file,
+            None => return false,
+        };
+        if self.files.contains(&file)
+            || self.dirs.contains(&file)
+            || prefixes.iter().any(|dir| self.files.contains(dir))
+        {
+            return false;
+        }
+        self.files.insert(file);
+        self.dirs.extend(prefixes);
+        true
+    }
+}
+
+/// The paths to try for a resource, best first: the mirrored path, then
+/// the same with a hash of the URL added to the file name, then an
+/// endless supply of hashed names directly under the resources directory
+fn candidate_paths(
+    url: &Url,
+    mimetype: &str,
+) -> impl Iterator<Item = Vec<String>> {
+    let mirrored = mirrored_path(url, mimetype);
+    let hash = short_hash(url.as_str());
+    let mut hashed = mirrored.clone();
+    if let Some(name) = hashed.last_mut() {
+        *name = sanitize(&with_suffix(name, &hash));
+    }
+    let extension = mimetype::extension(mimetype)
+        .map(|e| format!(".{}", e))
+        .unwrap_or_default();
+    let numbered = (1..).map(move |n| {
+        vec![
+            RESOURCES.to_string(),
+            format!("{}-{}{}", hash, n, extension),
+        ]
+    });
+    vec![mirrored, hashed].into_iter().chain(numbered)
+}
+
+/// The path mirroring the host and path of the URL, e.g.
+/// `resources/example.com/css/style.css`
+fn mirrored_path(url: &Url, mimetype: &str) -> Vec<String> {
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}_{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => String::new(),
+    };
+    let segments: Vec<String> = url
+        .path_segments()
+        .map(|segments| segments.map(percent_decode).collect())
+        .unwrap_or_default();
+    let (name, dirs) = match segments.split_last() {
+        Some((name, dirs)) => (name.as_str(), dirs),
+        None => ("", &[][..]),
+    };
+
+    let mut path = vec![RESOURCES.to_string(), sanitize(&host)];
+    path.extend(dirs.iter().map(|dir| sanitize(dir)));
+    let mut name = if name.is_empty() { "index" } else { name }.to_string();
+    if let Some(query) = url.query() {
+        name = with_suffix(&name, &short_hash(query));
+    }
+    path.push(sanitize(&with_extension(name, mimetype)));
+    path
+}
+
+/// Add `-suffix` to a file name, before its extension
+fn with_suffix(name: &str, suffix: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}-{}.{}", stem, suffix, extension)
+        }
+        _ => format!("{}-{}", name, suffix),
+    }
+}
+
+/// Add the extension of the mimetype to a file name which doesn't have it
+/// already, so that browsers know what the file is when it's opened from
+/// disk
+fn with_extension(name: String, mimetype: &str) -> String {
+    let expected = match mimetype::extension(mimetype) {
+        Some(expected) => expected,
+        None => return name,
+    };
+    let current = name
+        .rsplit_once('.')
+        .and_then(|(_, extension)| mimetype::for_extension(extension))
+        .and_then(mimetype::extension);
+    if current == Some(expected) {
+        name
+    } else {
+        format!("{}.{}", name, expected)
+    }
+}
+
+/// Make a file name safe to write on any platform: no separators,
+/// characters which Windows forbids, or control characters, no trailing
+/// dots or spaces, which Windows drops, and no names which are hidden,
+/// refer to a directory (`.` and `..`), or are reserved for devices
+fn sanitize(name: &str) -> String {
+    let mut name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || r#"<>:"/\|?*"#.contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    while name.ends_with(['.', ' ']) {
+        name.pop();
+    }
+    let stem = name.split('.').next().unwrap_or("");
+    if name.is_empty()
+        || name.starts_with('.')
+        || RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem))
+    {
+        name.insert(0, '_');
+    }
+    truncate(name)
+}
+
+/// Shorten a file name to [`MAX_NAME_LEN`], keeping its extension
+fn truncate(name: String) -> String {
+    if name.len() <= MAX_NAME_LEN {
+        return name;
+    }
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension)) if extension.len() <= 10 => extension,
+        _ => "",
+    };
+    let mut end = MAX_NAME_LEN - extension.len() - 1;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    if extension.is_empty() {
+        name[..end].to_string()
+    } else {
+        format!("{}.{}", &name[..end], extension)
+    }
+}
+
+/// Decode the `%XX` escapes in a URL path segment
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escape a file name for use in a relative URL
+fn percent_encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,=@".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// The relative URL of the file at `to` from the file at `from`
+fn relative_path(from: &[String], to: &[String]) -> String {
+    let from_dir = &from[..from.len().saturating_sub(1)];
+    let common = from_dir.iter().zip(to).take_while(|(a, b)| a == b).count();
+    let mut parts = vec!["..".to_string(); from_dir.len() - common];
+    parts.extend(to[common..].iter().map(|name| percent_encode(name)));
+    parts.join("/")
+}
+
+pub(crate) fn without_fragment(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url
+}
+
+/// A short hash for telling apart names which would otherwise be the same:
+/// the start of the SHA-256 digest, which unlike `DefaultHasher` is the
+/// same whichever version of Rust built the crate
+fn short_hash(s: &str) -> String {
+    let mut hash = digest::sha256(s.as_bytes());
+    hash.truncate(8);
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::{ImageResource, TextResource};
+
+    fn path(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn mirrored(u: &str, mimetype: &str) -> Vec<String> {
+        mirrored_path(&Url::parse(u).unwrap(), mimetype)
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("style.css"), "style.css");
+        assert_eq!(sanitize(".."), "_");
+        assert_eq!(sanitize("."), "_");
+        assert_eq!(sanitize(".htaccess"), "_.htaccess");
+        assert_eq!(sanitize("a/../b"), "a_.._b");
+        assert_eq!(sanitize("..\\..\\evil"), "_.._.._evil");
+        assert_eq!(sanitize("C:evil"), "C_evil");
+        assert_eq!(sanitize("a<b>c|d?e*f\"g\n"), "a_b_c_d_e_f_g_");
+        assert_eq!(sanitize("name. . "), "name");
+        assert_eq!(sanitize("con.css"), "_con.css");
+        assert_eq!(sanitize("LPT1"), "_LPT1");
+        assert_eq!(sanitize("console.css"), "console.css");
+
+        let long = format!("{}.png", "a".repeat(300));
+        let name = sanitize(&long);
+        assert_eq!(name.len(), MAX_NAME_LEN);
+        assert!(name.ends_with("a.png"));
+        let name = sanitize(&"é".repeat(100));
+        assert!(name.len() <= MAX_NAME_LEN);
+    }
+
+    #[test]
+    fn test_mirrored_path() {
+        assert_eq!(
+            mirrored("http://example.com/css/style.css", "text/css"),
+            path(&[RESOURCES, "example.com", "css", "style.css"])
+        );
+        assert_eq!(
+            mirrored("http://example.com:8080/", "text/html"),
+            path(&[RESOURCES, "example.com_8080", "index.html"])
+        );
+        assert_eq!(
+            mirrored("http://example.com/logo", "image/png"),
+            path(&[RESOURCES, "example.com", "logo.png"])
+        );
+        assert_eq!(
+            mirrored("http://example.com/photo.jpeg", "image/jpeg"),
+            path(&[RESOURCES, "example.com", "photo.jpeg"])
+        );
+        assert_eq!(
+            mirrored("http://example.com/frame.php", "text/html"),
+            path(&[RESOURCES, "example.com", "frame.php.html"])
+        );
+        assert_eq!(
+            mirrored("http://example.com/my%20file.bin", "x/unknown"),
+            path(&[RESOURCES, "example.com", "my file.bin"])
+        );
+
+        // Hostile URLs stay within the directory. Encoded dot segments
+        // are removed when the URL is parsed, and encoded slashes stay in
+        // the name.
+        let path = mirrored("http://example.com/%2e%2e/%2E%2E/etc/passwd", "");
+        assert_eq!(
+            path,
+            self::path(&[RESOURCES, "example.com", "etc", "passwd"])
+        );
+        let path = mirrored("http://example.com/a%2F..%2F..%2Fb", "");
+        assert_eq!(path, self::path(&[RESOURCES, "example.com", "a_.._.._b"]));
+
+        // Query strings are hashed into the name
+        let v1 = mirrored("http://example.com/style.css?v=1", "text/css");
+        let v2 = mirrored("http://example.com/style.css?v=2", "text/css");
+        assert_ne!(v1, v2);
+        // The same names whichever version of Rust built the crate
+        assert_eq!(v1[2], "style-a798de8e.css");
+    }
+
+    #[test]
+    fn test_claimed() {
+        let mut claimed = Claimed::default();
+        assert!(claimed.claim(&path(&["r", "a", "b.png"])));
+        assert!(!claimed.claim(&path(&["r", "a", "b.png"])));
+        // Case-insensitive file systems would mix these up
+        assert!(!claimed.claim(&path(&["r", "A", "B.PNG"])));
+        // `a` is a directory already, and `b.png` a file
+        assert!(!claimed.claim(&path(&["r", "a"])));
+        assert!(!claimed.claim(&path(&["r", "a", "b.png", "c"])));
+        assert!(claimed.claim(&path(&["r", "a", "c.png"])));
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let index = path(&[INDEX]);
+        let style = path(&[RESOURCES, "example.com", "css", "style.css"]);
+        let image = path(&[RESOURCES, "example.com", "img", "a b#1.png"]);
+        assert_eq!(
+            relative_path(&index, &style),
+            "resources/example.com/css/style.css"
+        );
+        assert_eq!(relative_path(&style, &image), "../img/a%20b%231.png");
+        let font = path(&[RESOURCES, "fonts.example.com", "font.woff2"]);
+        assert_eq!(
+            relative_path(&style, &font),
+            "../../fonts.example.com/font.woff2"
+        );
+    }
+
+    fn archive(resources: Vec<(&str, Resource)>) -> PageArchive {
+        let url = Url::parse("http://example.com/blog/").unwrap();
+        let resource_map: ResourceMap = resources
+            .into_iter()
+            .map(|(u, resource)| (url.join(u).unwrap(), resource))
+            .collect();
+        PageArchive {
+            url,
+            content: String::new(),
+            resource_map,
+            lazy_attributes: vec!["data-src".to_string()],
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            screenshot: None,
+        }
+    }
+
+    fn text(mimetype: &str, text: &str) -> TextResource {
+        TextResource {
+            data: Bytes::from(text.to_string()),
+            mimetype: mimetype.to_string(),
+            charset: None,
+        }
+    }
+
+    fn image(data: &'static [u8]) -> ImageResource {
+        ImageResource {
+            data: Bytes::from_static(data),
+            mimetype: "image/png".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let archive = archive(vec![
+            ("/a", Resource::Image(image(b"a"))),
+            ("/a/b.png", Resource::Image(image(b"b"))),
+            ("/A.png", Resource::Image(image(b"A"))),
+            ("/a.png", Resource::Image(image(b"a"))),
+            ("/a.png#copy", Resource::Image(image(b"a"))),
+        ]);
+        let layout = Layout::new(&archive);
+        assert_eq!(layout.paths.len(), 4);
+        let paths: HashSet<_> = layout.paths.values().collect();
+        assert_eq!(paths.len(), 4);
+        let url = |u: &str| archive.url.join(u).unwrap();
+        assert_eq!(
+            layout.paths[&url("/A.png")],
+            path(&[RESOURCES, "example.com", "A.png"])
+        );
+        // Clashes are given hashed names
+        assert_ne!(layout.paths[&url("/a.png")][2], "a.png");
+        assert!(layout.paths[&url("/a.png")][2].ends_with(".png"));
+        let index = path(&[INDEX]);
+        assert_eq!(
+            layout.link(&index, &url("/a.png#copy")).unwrap(),
+            format!("{}#copy", layout.link(&index, &url("/a.png")).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_rewrite_html() {
+        let archive = archive(vec![
+            ("/css/style.css", Resource::Css(text("text/css", ""))),
+            ("logo.png", Resource::Image(image(b"png"))),
+            ("lazy.png", Resource::Image(image(b"lazy"))),
+        ]);
+        let layout = Layout::new(&archive);
+        let content = r##"<html><head>
+            <base href="/blog/">
+            <link rel="stylesheet" href="/css/style.css" integrity="sha384-x">
+            <style>p { background: url(logo.png) }</style>
+            <script type="module">import "./missing.js";</script>
+            </head><body>
+            <img src="logo.png" srcset="logo.png 1x, big.png 2x">
+            <img data-src="lazy.png">
+            <a href="/about">About</a><a href="#top">Top</a>
+            <div style="background: url('logo.png')"></div>
+            </body></html>"##;
+        let index = path(&[INDEX]);
+        let html = layout.rewriter(&index).html(&archive.url, content);
+
+        let logo = "resources/example.com/blog/logo.png";
+        assert!(html.contains(
+            r#"<link href="resources/example.com/css/style.css" rel="stylesheet">"#
+        ));
+        assert!(html.contains(&format!(r#"background: url("{}")"#, logo)));
+        assert!(html.contains(&format!(
+            r#"style="background: url(&quot;{}&quot;)""#,
+            logo
+        )));
+        assert!(html.contains(&format!(
+            r#"srcset="{} 1x, http://example.com/blog/big.png 2x""#,
+            logo
+        )));
+        assert!(html.contains(&format!(r#"<img src="{}""#, logo)));
+        assert!(
+            html.contains(r#"data-src="resources/example.com/blog/lazy.png""#)
+        );
+        assert!(html.contains(r#"href="http://example.com/about""#));
+        assert!(html.contains(r##"href="#top""##));
+        assert!(html.contains(r#"import "http://example.com/blog/missing.js""#));
+        assert!(!html.contains("<base"));
+    }
+
+    #[test]
+    fn test_rewrite_resources() {
+        let archive = archive(vec![
+            ("/css/style.css", Resource::Css(text("text/css", ""))),
+            ("/img/logo.png", Resource::Image(image(b"png"))),
+            (
+                "/js/main.js",
+                Resource::Javascript(text("text/javascript", "")),
+            ),
+            (
+                "/js/lib.js",
+                Resource::Javascript(text("text/javascript", "")),
+            ),
+        ]);
+        let layout = Layout::new(&archive);
+        let url = |u: &str| archive.url.join(u).unwrap();
+
+        let style = &layout.paths[&url("/css/style.css")];
+        let css = layout.rewriter(style).css(
+            &url("/css/style.css"),
+            "@charset \"Shift_JIS\";\na { background: url(../img/logo.png) }",
+        );
+        assert_eq!(
+            css,
+            "@charset \"utf-8\";\na { background: url(\"../img/logo.png\") }"
+        );
+
+        let main = &layout.paths[&url("/js/main.js")];
+        let js = layout.rewriter(main).js(
+            &url("/js/main.js"),
+            "import './lib.js'; import '/css/style.css';",
+        );
+        assert_eq!(js, "import \"./lib.js\"; import \"../css/style.css\";");
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = std::env::temp_dir()
+            .join(format!("web-archive-disk-{}", std::process::id()));
+        let mut archive = archive(vec![
+            (
+                "/style.css",
+                Resource::Css(text("text/css", "a { color: red }")),
+            ),
+            ("/a%2F..%2F..%2Fescape.png", Resource::Image(image(b"png"))),
+        ]);
+        archive.content =
+            r#"<link rel="stylesheet" href="/style.css">"#.to_string();
+        write(&archive, &dir, true).unwrap();
+
+        let index = fs::read_to_string(dir.join(INDEX)).unwrap();
+        assert!(index.contains(r#"href="resources/example.com/style.css""#));
+        let style = dir.join(RESOURCES).join("example.com").join("style.css");
+        assert_eq!(fs::read_to_string(style).unwrap(), "a { color: red }");
+        let escaped = dir
+            .join(RESOURCES)
+            .join("example.com")
+            .join("a_.._.._escape.png");
+        assert_eq!(fs::read(escaped).unwrap(), b"png");
+        let checksums = fs::read_to_string(dir.join(CHECKSUMS)).unwrap();
+        let listed: Vec<_> = checksums
+            .lines()
+            .map(|line| line.split_once("  ").unwrap())
+            .collect();
+        assert_eq!(
+            listed[0],
+            (digest::sha256(index.as_bytes()).as_str(), INDEX)
+        );
+        assert!(listed.contains(&(
+            digest::sha256(b"png").as_str(),
+            "resources/example.com/a_.._.._escape.png"
+        )));
+        assert_eq!(listed.last().unwrap().1, MANIFEST);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(file_path(&dir, &path(&["..", "evil"])).is_err());
+        assert!(file_path(&dir, &path(&["/etc"])).is_err());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir()
+            .join(format!("web-archive-disk-{}-atomic", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("page.html");
+        let write = |data: &'static [u8], sync| {
+            write_atomically(&file, sync, |writer| writer.write_all(data))
+        };
+        write(b"first", false).unwrap();
+        write(b"second", true).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"second");
+        // A failed write leaves the file as it was
+        let failed = write_atomically(&file, false, |writer| {
+            writer.write_all(b"half")?;
+            Err(io::Error::other("crashed"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read(&file).unwrap(), b"second");
+        // Only the file itself is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let missing = dir.join("missing").join("page.html");
+        assert!(write_atomically(&missing, false, |_| Ok(())).is_err());
+        assert!(write_atomically(Path::new("/"), false, |_| Ok(())).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read() {
+        let dir = std::env::temp_dir()
+            .join(format!("web-archive-disk-{}-read", std::process::id()));
+        let mut archive = archive(vec![
+            (
+                "/css/style.css",
+                Resource::Css(text(
+                    "text/css",
+                    "a { background: url(/img/logo.png) }",
+                )),
+            ),
+            ("/img/logo.png", Resource::Image(image(b"png"))),
+            ("/img/logo.png#copy", Resource::Image(image(b"png"))),
+        ]);
+        archive.content = r#"<html><head>
+            <link rel="stylesheet" href="/css/style.css">
+            </head><body>
+            <img src="/img/logo.png#copy" data-src="/img/logo.png">
+            <a href="/about">About</a>
+            </body></html>"#
+            .to_string();
+        archive
+            .timed_out
+            .push(archive.url.join("/slow.png").unwrap());
+        archive.screenshot = Some(Bytes::from_static(b"\x89PNG"));
+        write(&archive, &dir, false).unwrap();
+        assert_eq!(fs::read(dir.join(SCREENSHOT)).unwrap(), b"\x89PNG");
+        let read_back = read(&dir).unwrap();
+
+        assert_eq!(read_back.url, archive.url);
+        assert_eq!(read_back.lazy_attributes, archive.lazy_attributes);
+        assert_eq!(read_back.timed_out, archive.timed_out);
+        assert_eq!(read_back.screenshot, archive.screenshot);
+        let urls: HashSet<_> = read_back.resource_map.keys().collect();
+        assert_eq!(urls, archive.resource_map.keys().collect());
+        let logo = archive.url.join("/img/logo.png").unwrap();
+        assert_eq!(read_back.resource_map[&logo], archive.resource_map[&logo]);
+        let style = archive.url.join("/css/style.css").unwrap();
+        match &read_back.resource_map[&style] {
+            Resource::Css(css) => assert_eq!(
+                css.text(),
+                r#"a { background: url("http://example.com/img/logo.png") }"#
+            ),
+            resource => panic!("expected CSS, got {:?}", resource),
+        }
+
+        let content = &read_back.content;
+        assert!(content.contains(r#"href="http://example.com/css/style.css""#));
+        assert!(
+            content.contains(r#"src="http://example.com/img/logo.png#copy""#)
+        );
+        assert!(
+            content.contains(r#"data-src="http://example.com/img/logo.png""#)
+        );
+        assert!(content.contains(r#"href="http://example.com/about""#));
+        let embedded = read_back.embed_resources();
+        assert!(embedded.contains("data:image/png;base64,"));
+
+        // A manifest pointing outside the directory is refused
+        let manifest = dir.join(MANIFEST);
+        let tampered = fs::read_to_string(&manifest)
+            .unwrap()
+            .replace("resources/example.com/img/logo.png", "../../etc/passwd");
+        fs::write(&manifest, tampered).unwrap();
+        assert_eq!(read(&dir).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
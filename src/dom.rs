@@ -0,0 +1,277 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for walking and serialising DOMs. Neither recurses, so pages
+//! with pathologically deep nesting can't overflow the stack.
+
+use html5ever::serialize::{HtmlSerializer, SerializeOpts, Serializer};
+use html5ever::QualName;
+use kuchiki::traits::TendrilSink;
+use kuchiki::{parse_html, ElementData, NodeData, NodeDataRef, NodeRef};
+use std::io::{self, Write};
+
+/// Visit every element in a document in document order. The contents of
+/// `<template>` elements are walked as if they were the element's
+/// children.
+///
+/// Nodes nested more than `max_depth` deep are skipped along with their
+/// descendants. `visit` may return another document, such as the parsed
+/// contents of a `<noscript>`, which is walked in the same way.
+pub(crate) fn walk_elements<F>(
+    document: &NodeRef,
+    max_depth: usize,
+    mut visit: F,
+) where
+    F: FnMut(&NodeDataRef<ElementData>) -> Option<NodeRef>,
+{
+    let mut stack = vec![(document.clone(), 0)];
+    while let Some((node, depth)) = stack.pop() {
+        if depth > max_depth {
+            continue;
+        }
+        if let Some(element) = node.clone().into_element_ref() {
+            if let Some(contents) = &element.template_contents {
+                stack.push((contents.clone(), depth));
+            }
+            if let Some(document) = visit(&element) {
+                stack.push((document, depth));
+            }
+        }
+        stack.extend(node.children().rev().map(|child| (child, depth + 1)));
+    }
+}
+
+/// Call `rewrite` on a parsed document and on each `<template>` and
+/// `<noscript>` document nested within it, then write the nested
+/// documents back into their parents
+pub(crate) fn rewrite_nested<F>(document: &NodeRef, mut rewrite: F)
+where
+    F: FnMut(&NodeRef),
+{
+    // `<template>` contents (including declarative shadow roots) live
+    // in a separate document fragment, and `<noscript>` contents are
+    // parsed as text, so have to be parsed as their own document to
+    // reach the fallback resources. Rather than recursing into them,
+    // the nested documents are gathered up front, parents first.
+    let mut documents = vec![(document.clone(), None)];
+    let mut i = 0;
+    while let Some((document, _)) = documents.get(i) {
+        let document = document.clone();
+        for element in document.select("template").unwrap() {
+            if let Some(contents) = &element.template_contents {
+                documents.push((contents.clone(), None));
+            }
+        }
+        for element in document.select("noscript").unwrap() {
+            let node = element.as_node();
+            let fragment = parse_html().one(node.text_contents());
+            documents.push((fragment, Some(node.clone())));
+        }
+        i += 1;
+    }
+
+    for (document, _) in &documents {
+        rewrite(document);
+    }
+
+    // Children come after their parents, so working backwards means
+    // nested documents are finished before they're written back
+    for (document, noscript) in documents.iter().rev() {
+        // The serialiser doesn't output `<template>` contents, so move
+        // them into the element itself. Browsers parse them back into
+        // the template's contents when loading the page.
+        for element in document.select("template").unwrap() {
+            if let Some(contents) = &element.template_contents {
+                for child in contents.children() {
+                    element.as_node().append(child);
+                }
+            }
+        }
+
+        if let Some(node) = noscript {
+            let html = fragment_html(document);
+            for child in node.children() {
+                child.detach();
+            }
+            node.append(NodeRef::new_text(html));
+        }
+    }
+}
+
+/// Serialise a node and its descendants as HTML.
+///
+/// Unlike `html5ever`'s serialiser, doctypes are written out in full.
+/// Dropping their public and system identifiers could change the page's
+/// rendering mode.
+pub(crate) fn serialize(node: &NodeRef) -> String {
+    let mut output = Vec::new();
+    // Writing to a `Vec` can't fail
+    let _ = serialize_to(&mut output, node);
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Serialise a parsed fragment without the `<html>`, `<head>`, and
+/// `<body>` elements which the parser wraps around it. Comments are kept
+/// wherever the parser put them.
+fn fragment_html(fragment: &NodeRef) -> String {
+    fn is_wrapper(node: &NodeRef) -> bool {
+        node.as_element()
+            .map(|e| ["html", "head", "body"].contains(&e.name.local.as_ref()))
+            .unwrap_or(false)
+    }
+
+    let mut html = String::new();
+    let mut stack: Vec<NodeRef> = fragment.children().rev().collect();
+    while let Some(node) = stack.pop() {
+        if is_wrapper(&node) {
+            stack.extend(node.children().rev());
+        } else {
+            html.push_str(&serialize(&node));
+        }
+    }
+    html
+}
+
+enum Step {
+    Open(NodeRef),
+    Close(QualName),
+}
+
+fn serialize_to<W: Write>(writer: W, node: &NodeRef) -> io::Result<()> {
+    let mut serializer = HtmlSerializer::new(writer, SerializeOpts::default());
+    let mut stack = vec![Step::Open(node.clone())];
+
+    while let Some(step) = stack.pop() {
+        let node = match step {
+            Step::Open(node) => node,
+            Step::Close(name) => {
+                serializer.end_elem(name)?;
+                continue;
+            }
+        };
+        match node.data() {
+            NodeData::Element(element) => {
+                let attrs = element.attributes.borrow();
+                let attrs = attrs
+                    .map
+                    .iter()
+                    .map(|(name, attr)| {
+                        (
+                            QualName::new(
+                                attr.prefix.clone(),
+                                name.ns.clone(),
+                                name.local.clone(),
+                            ),
+                            attr.value.as_str(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                serializer.start_elem(
+                    element.name.clone(),
+                    attrs.iter().map(|(name, value)| (name, *value)),
+                )?;
+                stack.push(Step::Close(element.name.clone()));
+            }
+            NodeData::Text(text) => serializer.write_text(&text.borrow())?,
+            NodeData::Comment(text) => {
+                serializer.write_comment(&text.borrow())?
+            }
+            NodeData::ProcessingInstruction(contents) => {
+                let contents = contents.borrow();
+                serializer
+                    .write_processing_instruction(&contents.0, &contents.1)?
+            }
+            NodeData::Doctype(doctype) => {
+                let writer = &mut serializer.writer;
+                write!(writer, "<!DOCTYPE {}", doctype.name)?;
+                if !doctype.public_id.is_empty() {
+                    write!(writer, " PUBLIC \"{}\"", doctype.public_id)?;
+                } else if !doctype.system_id.is_empty() {
+                    write!(writer, " SYSTEM")?;
+                }
+                if !doctype.system_id.is_empty() {
+                    write!(writer, " \"{}\"", doctype.system_id)?;
+                }
+                write!(writer, ">")?;
+            }
+            NodeData::Document(_) | NodeData::DocumentFragment => {}
+        }
+        stack.extend(node.children().rev().map(Step::Open));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kuchiki::parse_html;
+    use kuchiki::traits::TendrilSink;
+
+    #[test]
+    fn test_deep_nesting() {
+        let depth = 100_000;
+        let document = parse_html().one(format!(
+            "<body>{}<img src=\"deep.png\">{}",
+            "<span>".repeat(depth),
+            "</span>".repeat(depth)
+        ));
+
+        let mut images = Vec::new();
+        walk_elements(&document, usize::MAX, |element| {
+            if &*element.name.local == "img" {
+                images.push(element.clone());
+            }
+            None
+        });
+        assert_eq!(images.len(), 1);
+
+        let mut images = 0;
+        walk_elements(&document, 512, |element| {
+            if &*element.name.local == "img" {
+                images += 1;
+            }
+            None
+        });
+        assert_eq!(images, 0);
+
+        assert!(serialize(&document).contains("<img src=\"deep.png\">"));
+    }
+
+    #[test]
+    fn test_walk_order() {
+        let document = parse_html().one(
+            "<p id=a><template><b id=b></b></template><i id=c></i></p>\
+             <noscript><img id=d></noscript>",
+        );
+
+        let mut ids = Vec::new();
+        walk_elements(&document, usize::MAX, |element| {
+            if let Some(id) = element.attributes.borrow().get("id") {
+                ids.push(id.to_string());
+            }
+            if &*element.name.local == "noscript" {
+                Some(parse_html().one(element.text_contents()))
+            } else {
+                None
+            }
+        });
+        assert_eq!(ids, vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let html = "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\" \
+                    \"http://www.w3.org/TR/html4/strict.dtd\"><!-- c -->\
+                    <html><head><script>a < b</script></head>\
+                    <body><p class=\"x\">1 &lt; 2</p>\
+                    <svg><use xlink:href=\"#i\"></use></svg></body></html>";
+        let document = parse_html().one(html);
+
+        assert_eq!(serialize(&document), html);
+    }
+}
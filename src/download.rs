@@ -0,0 +1,1359 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for the bookkeeping shared by the async and blocking archive
+//! functions. They only differ in how the resources are fetched.
+
+use crate::bounded::BoundedMap;
+use crate::digest;
+use crate::encoding;
+use crate::event::ArchiveEvent;
+use crate::failure::{FailureReason, ResourceFailure};
+use crate::javascript::module_imports;
+use crate::meta::ResourceMeta;
+use crate::mimetype::{self, OCTET_STREAM};
+use crate::normalize::UrlNormalizer;
+use crate::parsing::{
+    css_resource_urls, parse_resource_urls, ImageResource, Resource,
+    ResourceMap, ResourceUrl, TextResource,
+};
+use crate::source_map;
+use crate::stats::{ArchiveStats, FetchTiming};
+use crate::{ArchiveOptions, PageArchive};
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use url::Url;
+
+/// A resource which has been fetched, but not yet stored
+pub(crate) struct Download {
+    pub resource_url: ResourceUrl,
+    /// URL that the resource was downloaded from after following any
+    /// redirects
+    pub final_url: Url,
+    /// URLs that the resource was redirected through, ending with the
+    /// `final_url`, or empty if it wasn't redirected
+    pub redirects: Vec<Url>,
+    /// How deep the resource is nested, see [`Downloader::take_queue`]
+    pub depth: usize,
+    /// Where and when the response came from, see [`PageArchive::meta`]
+    pub meta: ResourceMeta,
+    pub data: Bytes,
+    /// How long the request took, or `None` if the response was reused
+    /// from a cache
+    pub timing: Option<FetchTiming>,
+    /// Whether the data has been through `options.transform` already, as
+    /// it has when it's replayed from an archive being refreshed
+    pub transformed: bool,
+}
+
+impl Download {
+    pub fn new(
+        resource_url: ResourceUrl,
+        depth: usize,
+        response: Response,
+    ) -> Self {
+        Self {
+            final_url: response
+                .redirects
+                .last()
+                .unwrap_or_else(|| resource_url.url())
+                .clone(),
+            resource_url,
+            redirects: response.redirects,
+            depth,
+            meta: response.meta,
+            data: response.data,
+            timing: response.timing,
+            transformed: response.transformed,
+        }
+    }
+}
+
+/// What became of the request for a resource
+pub(crate) enum Fetched {
+    Downloaded(Box<Download>),
+    /// The resource couldn't be downloaded
+    Failed(ResourceFailure),
+    /// The archive was cancelled before the request finished
+    Cancelled,
+}
+
+/// Responses shared between archives, so that resources used by many
+/// pages are only downloaded once. Holds at most [`CACHE_SIZE`] bytes of
+/// them, forgetting the ones downloaded longest ago first.
+pub(crate) struct Cache(Mutex<BoundedMap<Response>>);
+
+/// How many bytes of responses a [`Cache`] holds
+pub(crate) const CACHE_SIZE: usize = 256 * 1024 * 1024;
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self(Mutex::new(BoundedMap::new(CACHE_SIZE)))
+    }
+}
+
+/// A response to a request for the page or a resource
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Response {
+    /// URLs that the request was redirected through, ending with the one
+    /// the response came from, or empty if it wasn't redirected
+    pub redirects: Vec<Url>,
+    /// Where and when the response came from
+    pub meta: ResourceMeta,
+    pub data: Bytes,
+    /// How long the request took, or `None` if the response was reused
+    /// from a cache
+    pub timing: Option<FetchTiming>,
+    /// Whether the data has been through `options.transform` already, as
+    /// it has when it's replayed from an archive being refreshed
+    pub transformed: bool,
+}
+
+impl Cache {
+    fn get(&self, url: &Url) -> Option<Response> {
+        self.0.lock().unwrap().get(url).cloned()
+    }
+
+    fn insert(&self, url: Url, response: Response) {
+        let size = response.data.len();
+        self.0.lock().unwrap().insert(url, response, size);
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+/// Keeps track of which resources still need downloading, and stores the
+/// ones which have been downloaded
+pub(crate) struct Downloader<'o, 'a> {
+    /// URL of the page, after following any redirects
+    url: Url,
+    options: &'o ArchiveOptions<'a>,
+    queue: VecDeque<(ResourceUrl, usize)>,
+    /// Every URL queued so far, keyed by its [`normalize`]d form so that
+    /// each resource is only downloaded once
+    seen: HashMap<Url, Vec<Url>>,
+    resource_map: ResourceMap,
+    timed_out: Vec<Url>,
+    failures: Vec<ResourceFailure>,
+    cancelled: bool,
+    /// Where the page and resources were redirected, see
+    /// [`PageArchive::redirects`]
+    redirects: HashMap<Url, Vec<Url>>,
+    /// Where and when the page and resources came from, see
+    /// [`PageArchive::meta`]
+    meta: HashMap<Url, ResourceMeta>,
+    /// How long each request took, see [`ArchiveStats::fetches`]
+    fetches: Vec<FetchTiming>,
+    /// How many resources have been queued since this was last reported
+    discovered: usize,
+    /// How many resources have been taken from the queue, and how many
+    /// bytes they've come to, for `options.max_resources` and
+    /// `options.max_total_bytes`
+    started: usize,
+    total_bytes: u64,
+    cache: Option<&'o Cache>,
+    /// Whether only stylesheets are downloaded, see
+    /// [`Downloader::stylesheets_only`], and the ones taken from the
+    /// queue so far if so
+    stylesheets_only: bool,
+    stylesheets: Vec<ResourceUrl>,
+}
+
+impl<'o, 'a> Downloader<'o, 'a> {
+    /// Start off with the resources referenced by the page. Resources
+    /// found in the `cache` aren't downloaded again, and new downloads
+    /// are added to it.
+    pub fn new(
+        url: Url,
+        resource_urls: Vec<ResourceUrl>,
+        options: &'o ArchiveOptions<'a>,
+        cache: Option<&'o Cache>,
+    ) -> Self {
+        let mut downloader = Self {
+            url,
+            options,
+            queue: VecDeque::new(),
+            seen: HashMap::new(),
+            resource_map: ResourceMap::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            fetches: Vec::new(),
+            discovered: 0,
+            started: 0,
+            total_bytes: 0,
+            cache,
+            stylesheets_only: false,
+            stylesheets: Vec::new(),
+        };
+        for resource_url in resource_urls {
+            downloader.enqueue(resource_url, 0);
+        }
+        downloader
+    }
+
+    /// Only take stylesheets from the queue, leaving the rest of the
+    /// resources waiting, so that the resources which the stylesheets
+    /// refer to can be found without downloading anything else. See
+    /// [`crate::discover`].
+    pub fn stylesheets_only(mut self) -> Self {
+        self.stylesheets_only = true;
+        self
+    }
+
+    /// URL of the page that the resources are for, after any redirects
+    pub fn page_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Take every resource waiting to be downloaded, along with its
+    /// depth. For scripts this is how many imports deep the module is,
+    /// and for frames how many frames deep the document is.
+    ///
+    /// Storing the downloads may queue up more resources, so this should
+    /// be called until it comes back empty. Nothing more is returned once
+    /// a download has been cancelled, and resources beyond the archive's
+    /// budget are recorded as failures instead.
+    pub fn take_queue(&mut self) -> Vec<(ResourceUrl, usize)> {
+        if self.cancelled {
+            self.queue.clear();
+        }
+        let mut batch = Vec::new();
+        let mut waiting = VecDeque::new();
+        while let Some((resource_url, depth)) = self.queue.pop_front() {
+            if self.stylesheets_only {
+                if !matches!(resource_url, ResourceUrl::Css(_)) {
+                    waiting.push_back((resource_url, depth));
+                    continue;
+                }
+                self.stylesheets.push(resource_url.clone());
+            }
+            if self.over_budget() {
+                self.record(Fetched::Failed(ResourceFailure {
+                    url: resource_url.url().clone(),
+                    reason: FailureReason::OverBudget,
+                    retries: 0,
+                }));
+                continue;
+            }
+            self.started += 1;
+            match self.cache.and_then(|cache| cache.get(resource_url.url())) {
+                Some(response) => {
+                    let download = Download::new(resource_url, depth, response);
+                    self.record(Fetched::Downloaded(Box::new(download)))
+                }
+                None => batch.push((resource_url, depth)),
+            }
+        }
+        self.queue = waiting;
+        if self.discovered > 0 {
+            self.options.emit(ArchiveEvent::ResourcesDiscovered {
+                count: self.discovered,
+            });
+            self.discovered = 0;
+        }
+        batch
+    }
+
+    /// The resources waiting to be downloaded, in the order they were
+    /// found, without downloading them. If only stylesheets were
+    /// downloaded then they come first.
+    pub fn into_queue(self) -> Vec<ResourceUrl> {
+        self.stylesheets
+            .into_iter()
+            .chain(self.queue.into_iter().map(|(resource_url, _)| resource_url))
+            .collect()
+    }
+
+    /// Record what became of a request
+    pub fn record(&mut self, fetched: Fetched) {
+        match fetched {
+            Fetched::Downloaded(download)
+                if matches!(
+                    self.options.max_total_bytes,
+                    Some(max) if self.total_bytes + download.data.len() as u64 > max
+                ) =>
+            {
+                self.record(Fetched::Failed(ResourceFailure {
+                    url: download.resource_url.url().clone(),
+                    reason: FailureReason::OverBudget,
+                    retries: 0,
+                }));
+            }
+            Fetched::Downloaded(mut download) => {
+                self.total_bytes += download.data.len() as u64;
+                self.fetches.extend(download.timing.take());
+                self.options.emit(ArchiveEvent::ResourceFinished {
+                    url: download.resource_url.url().clone(),
+                    bytes: download.data.len(),
+                });
+                self.store(*download);
+            }
+            Fetched::Failed(failure) => {
+                self.options.emit(ArchiveEvent::ResourceFailed {
+                    url: failure.url.clone(),
+                    reason: failure.reason.to_string(),
+                });
+                if failure.reason == FailureReason::TimedOut {
+                    self.timed_out.push(failure.url.clone());
+                }
+                self.failures.push(failure);
+            }
+            Fetched::Cancelled => self.cancelled = true,
+        }
+    }
+
+    /// Store a downloaded resource, queueing up any further resources
+    /// that it needs: the images, fonts, and imports of stylesheets,
+    /// modules imported by scripts up to `options.module_depth` imports
+    /// deep, the contents of frames, and source maps if they were asked
+    /// for.
+    ///
+    /// Resources which were redirected are stored under both the
+    /// requested and final URLs, and every URL which normalizes to the
+    /// same resource is stored too. They're passed through
+    /// `options.transform` first, if it's set.
+    pub fn store(&mut self, download: Download) {
+        use ResourceUrl::*;
+
+        let Download {
+            resource_url,
+            final_url,
+            redirects,
+            depth,
+            meta,
+            data,
+            timing: _,
+            transformed,
+        } = download;
+        // The cache only has responses as they were downloaded
+        if let Some(cache) = self.cache.filter(|_| !transformed) {
+            cache.insert(
+                resource_url.url().clone(),
+                Response {
+                    redirects: redirects.clone(),
+                    meta: meta.clone(),
+                    data: data.clone(),
+                    timing: None,
+                    transformed: false,
+                },
+            );
+        }
+        self.redirected(resource_url.url().clone(), redirects);
+        let transform = self
+            .options
+            .transform
+            .filter(|_| !transformed)
+            .map(|transform| (transform, resource_url.clone()));
+        let content_type = meta.content_type.as_deref();
+        let binary = |u: &Url, data: Bytes| ImageResource {
+            mimetype: mimetype::detect(content_type, u, &data, OCTET_STREAM),
+            data,
+        };
+
+        let (u, resource) = match resource_url {
+            Image(u) => {
+                let image = binary(&u, data);
+                (u, Resource::Image(image))
+            }
+            Css(u) => {
+                let mimetype =
+                    mimetype::detect(content_type, &u, &data, "text/css");
+                let charset = encoding::css_charset(content_type, &data);
+                let css = TextResource {
+                    data,
+                    mimetype,
+                    charset,
+                };
+                self.queue_source_map(&u, &css.text());
+                // The images, fonts, and imported stylesheets it needs,
+                // which are relative to the stylesheet
+                for resource_url in css_resource_urls(&u, &css.text()) {
+                    self.enqueue(resource_url, 0);
+                }
+                (u, Resource::Css(css))
+            }
+            Javascript(u) => {
+                let mimetype = mimetype::detect(
+                    content_type,
+                    &u,
+                    &data,
+                    "text/javascript",
+                );
+                let script = TextResource {
+                    data,
+                    mimetype,
+                    charset: encoding::script_charset(content_type),
+                };
+                self.queue_source_map(&u, &script.text());
+                if depth < self.options.module_depth {
+                    for import in module_imports(&u, &script.text()) {
+                        self.enqueue(Javascript(import), depth + 1);
+                    }
+                }
+                (u, Resource::Javascript(script))
+            }
+            Object(u) => {
+                let object = binary(&u, data);
+                (u, Resource::Object(object))
+            }
+            Font(u) => {
+                let font = binary(&u, data);
+                (u, Resource::Font(font))
+            }
+            Media(u) => {
+                let media = binary(&u, data);
+                (u, Resource::Media(media))
+            }
+            Document(u) => {
+                let mimetype =
+                    mimetype::detect(content_type, &u, &data, "text/html");
+                let charset = encoding::document_charset(content_type, &data);
+                let frame = TextResource {
+                    data,
+                    mimetype,
+                    charset,
+                };
+                // The frame's own resources are needed too, while frames
+                // nested within it count towards `options.frame_depth`
+                for resource_url in
+                    parse_resource_urls(&u, &frame.text(), self.options)
+                {
+                    match resource_url {
+                        Document(_)
+                            if depth + 1 >= self.options.frame_depth => {}
+                        Document(_) => self.enqueue(resource_url, depth + 1),
+                        _ => self.enqueue(resource_url, 0),
+                    }
+                }
+                (u, Resource::Document(frame))
+            }
+            Attachment(u) => {
+                let attachment = binary(&u, data);
+                (u, Resource::Attachment(attachment))
+            }
+            Other(u) => {
+                let ImageResource { data, mimetype } = binary(&u, data);
+                (u, Resource::Other { data, mimetype })
+            }
+        };
+        let resource = match transform {
+            Some((transform, resource_url)) => {
+                match transform(&resource_url, resource) {
+                    Some(resource) => resource,
+                    None => return,
+                }
+            }
+            None => resource,
+        };
+        if final_url != u {
+            self.seen
+                .entry(normalize(&final_url, self.options))
+                .or_default()
+                .push(final_url.clone());
+            self.meta.insert(final_url.clone(), meta.clone());
+            self.resource_map.insert(final_url, resource.clone());
+        }
+        let aliases = self.seen.get(&normalize(&u, self.options));
+        for alias in aliases.into_iter().flatten() {
+            if alias != &u {
+                self.meta.insert(alias.clone(), meta.clone());
+                self.resource_map.insert(alias.clone(), resource.clone());
+            }
+        }
+        self.meta.insert(u.clone(), meta);
+        self.resource_map.insert(u, resource);
+    }
+
+    /// Put together the archive from everything that was downloaded,
+    /// which took `wall_time` altogether, recording the digests of the
+    /// page and resources
+    pub fn into_archive(
+        self,
+        content: String,
+        wall_time: Duration,
+    ) -> PageArchive {
+        let mut archive = PageArchive {
+            url: self.url,
+            content,
+            resource_map: self.resource_map,
+            lazy_attributes: self
+                .options
+                .lazy_attributes
+                .iter()
+                .map(|a| a.to_string())
+                .collect(),
+            url_attributes: self
+                .options
+                .url_attributes
+                .iter()
+                .map(|a| (a.tag.to_string(), a.attribute.to_string()))
+                .collect(),
+            timed_out: self.timed_out,
+            failures: self.failures,
+            cancelled: self.cancelled,
+            redirects: self.redirects,
+            meta: self.meta,
+            stats: ArchiveStats {
+                wall_time,
+                fetches: self.fetches,
+            },
+            screenshot: None,
+        };
+        digest::record(&mut archive);
+        archive
+    }
+
+    /// Record that `url` was redirected through the `redirects`, if it
+    /// was redirected at all
+    pub fn redirected(&mut self, url: Url, redirects: Vec<Url>) {
+        if !redirects.is_empty() {
+            self.redirects.insert(url, redirects);
+        }
+    }
+
+    /// Record where and when the response to a request for `url` came
+    /// from
+    pub fn fetched(&mut self, url: Url, meta: ResourceMeta) {
+        self.meta.insert(url, meta);
+    }
+
+    /// Record how long the request for the page took, if it was made
+    pub fn timed(&mut self, timing: Option<FetchTiming>) {
+        self.fetches.extend(timing);
+    }
+
+    /// Queue up a resource, unless it has been seen already or resources
+    /// of its kind aren't wanted. A URL which normalizes to one seen
+    /// already isn't downloaded again, but the resource is stored under
+    /// it as well.
+    fn enqueue(&mut self, resource_url: ResourceUrl, depth: usize) {
+        if self.options.skips(&self.url, &resource_url) {
+            return;
+        }
+        let url = resource_url.url();
+        let resource_map = &mut self.resource_map;
+        let meta = &mut self.meta;
+        match self.seen.get_mut(&normalize(url, self.options)) {
+            Some(aliases) if aliases.contains(url) => {}
+            Some(aliases) => {
+                // It may have been downloaded already
+                let resource =
+                    aliases.iter().find_map(|a| resource_map.get(a)).cloned();
+                if let Some(resource) = resource {
+                    resource_map.insert(url.clone(), resource);
+                }
+                let found = aliases.iter().find_map(|a| meta.get(a)).cloned();
+                if let Some(found) = found {
+                    meta.insert(url.clone(), found);
+                }
+                aliases.push(url.clone());
+            }
+            None => {
+                self.seen
+                    .insert(normalize(url, self.options), vec![url.clone()]);
+                if !self.options.allows_scheme(url) {
+                    let url = url.clone();
+                    self.record(Fetched::Failed(ResourceFailure {
+                        url,
+                        reason: FailureReason::DisallowedScheme,
+                        retries: 0,
+                    }));
+                    return;
+                }
+                self.queue.push_back((resource_url, depth));
+                self.discovered += 1;
+            }
+        }
+    }
+
+    /// Whether the archive's resource count or size budget is used up
+    fn over_budget(&self) -> bool {
+        matches!(self.options.max_resources, Some(max) if self.started >= max)
+            || matches!(
+                self.options.max_total_bytes,
+                Some(max) if self.total_bytes >= max
+            )
+    }
+
+    /// Queue up the source map of a script or stylesheet, if asked to
+    fn queue_source_map(&mut self, u: &Url, text: &str) {
+        if !self.options.source_maps {
+            return;
+        }
+        if let Some(map) = source_map::map_url(u, text) {
+            self.enqueue(ResourceUrl::Other(map), 0);
+        }
+    }
+}
+
+/// The form of a URL used to tell whether two resources are the same:
+/// as `options.url_normalizer` has it, or otherwise without the fragment
+fn normalize(url: &Url, options: &ArchiveOptions) -> Url {
+    match options.url_normalizer {
+        Some(normalizer) => normalizer.normalize(url),
+        None => UrlNormalizer::new().normalize(url),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FilterList, Regex};
+    use std::time::SystemTime;
+
+    fn failed(resource_url: &ResourceUrl, reason: FailureReason) -> Fetched {
+        Fetched::Failed(ResourceFailure {
+            url: resource_url.url().clone(),
+            reason,
+            retries: 0,
+        })
+    }
+
+    fn download(
+        resource_url: ResourceUrl,
+        depth: usize,
+        data: &str,
+    ) -> Download {
+        Download {
+            final_url: resource_url.url().clone(),
+            redirects: Vec::new(),
+            meta: ResourceMeta {
+                status: 200,
+                final_url: resource_url.url().clone(),
+                fetched_at: SystemTime::UNIX_EPOCH,
+                content_type: None,
+                cache_control: None,
+                validators: Default::default(),
+                snapshot: None,
+                sha256: None,
+            },
+            resource_url,
+            depth,
+            data: Bytes::from(data.to_string()),
+            timing: None,
+            transformed: false,
+        }
+    }
+
+    #[test]
+    fn test_store() {
+        let options = ArchiveOptions {
+            module_depth: 1,
+            ..Default::default()
+        };
+        let base = Url::parse("http://example.com/").unwrap();
+        let main = base.join("main.js").unwrap();
+        let lib = base.join("lib.js").unwrap();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![
+                ResourceUrl::Javascript(main.clone()),
+                ResourceUrl::Javascript(main.clone()),
+            ],
+            &options,
+            None,
+        );
+
+        assert_eq!(downloader.take_queue().len(), 1);
+        assert!(downloader.take_queue().is_empty());
+
+        // Imports are queued one level deeper, up to `module_depth`
+        downloader.store(download(
+            ResourceUrl::Javascript(main.clone()),
+            0,
+            "import './lib.js'; import './main.js';",
+        ));
+        let queue = downloader.take_queue();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].0.url(), &lib);
+        assert_eq!(queue[0].1, 1);
+
+        downloader.store(download(
+            ResourceUrl::Javascript(lib.clone()),
+            1,
+            "import './deeper.js';",
+        ));
+        assert!(downloader.take_queue().is_empty());
+
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert!(matches!(
+            archive.resource_map.get(&main),
+            Some(Resource::Javascript(_))
+        ));
+        assert!(archive.resource_map.contains_key(&lib));
+    }
+
+    #[test]
+    fn test_stylesheet_resources() {
+        let options = ArchiveOptions::default();
+        let base = Url::parse("http://example.com/").unwrap();
+        let style = base.join("css/style.css").unwrap();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![ResourceUrl::Css(style.clone())],
+            &options,
+            None,
+        );
+        assert_eq!(downloader.take_queue().len(), 1);
+
+        // URLs in the stylesheet are relative to it rather than the page,
+        // and an import of itself isn't downloaded again
+        downloader.store(download(
+            ResourceUrl::Css(style),
+            0,
+            r#"@import "theme.css"; @import "style.css";
+            @font-face { src: url(../fonts/sans.woff2); }
+            body { background: image-set("bg.png" 1x); }"#,
+        ));
+        let mut queue: Vec<_> = downloader
+            .take_queue()
+            .into_iter()
+            .map(|(u, _)| u)
+            .collect();
+        queue.sort();
+        assert_eq!(
+            queue,
+            vec![
+                ResourceUrl::Image(base.join("css/bg.png").unwrap()),
+                ResourceUrl::Css(base.join("css/theme.css").unwrap()),
+                ResourceUrl::Font(base.join("fonts/sans.woff2").unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resource_types() {
+        let options = ArchiveOptions::default().scripts(false).fonts(false);
+        let base = Url::parse("http://example.com/").unwrap();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![
+                ResourceUrl::Javascript(base.join("app.js").unwrap()),
+                ResourceUrl::Css(base.join("style.css").unwrap()),
+                ResourceUrl::Font(base.join("font.woff2").unwrap()),
+                ResourceUrl::Image(base.join("image.png").unwrap()),
+            ],
+            &options,
+            None,
+        );
+
+        let queue = downloader.take_queue();
+        assert_eq!(queue.len(), 2);
+        assert!(matches!(queue[0].0, ResourceUrl::Css(_)));
+        assert!(matches!(queue[1].0, ResourceUrl::Image(_)));
+    }
+
+    #[test]
+    fn test_filters() {
+        let not_ads = |resource_url: &ResourceUrl| {
+            !resource_url.url().path().starts_with("/ads/")
+        };
+        let options = ArchiveOptions::default()
+            .include(Regex::new(r"^http://example\.com/").unwrap())
+            .exclude(Regex::new(r"analytics\.js$").unwrap())
+            .filter(&not_ads);
+        let base = Url::parse("http://example.com/").unwrap();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![
+                ResourceUrl::Javascript(base.join("analytics.js").unwrap()),
+                ResourceUrl::Image(base.join("/ads/banner.png").unwrap()),
+                ResourceUrl::Image(
+                    Url::parse("http://cdn.example.com/logo.png").unwrap(),
+                ),
+                ResourceUrl::Image(base.join("logo.png").unwrap()),
+            ],
+            &options,
+            None,
+        );
+
+        let queue = downloader.take_queue();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].0.url().path(), "/logo.png");
+    }
+
+    #[test]
+    fn test_same_origin() {
+        let options = ArchiveOptions::default()
+            .same_origin(true)
+            .allowed_host("static.example.com");
+        let base = Url::parse("http://example.com/").unwrap();
+        let urls = [
+            "http://example.com/logo.png",
+            "https://example.com/secure.png",
+            "http://static.example.com/app.js",
+            "http://tracker.example.net/pixel.gif",
+        ];
+        let mut downloader = Downloader::new(
+            base.clone(),
+            urls.iter()
+                .map(|u| ResourceUrl::Image(Url::parse(u).unwrap()))
+                .collect(),
+            &options,
+            None,
+        );
+
+        let queue = downloader.take_queue();
+        let queue: Vec<&str> = queue
+            .iter()
+            .map(|(resource_url, _)| resource_url.url().as_str())
+            .collect();
+        assert_eq!(queue, vec![urls[0], urls[2]]);
+    }
+
+    #[test]
+    fn test_schemes() {
+        let options = ArchiveOptions::default().https_only();
+        let base = Url::parse("https://example.com/").unwrap();
+        let urls = [
+            "https://example.com/logo.png",
+            "http://example.com/insecure.png",
+            "http://example.com/insecure.png#again",
+        ];
+        let mut downloader = Downloader::new(
+            base.clone(),
+            urls.iter()
+                .map(|u| ResourceUrl::Image(Url::parse(u).unwrap()))
+                .collect(),
+            &options,
+            None,
+        );
+
+        let queue = downloader.take_queue();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].0.url().as_str(), urls[0]);
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert_eq!(archive.failures.len(), 1);
+        assert_eq!(archive.failures[0].url.as_str(), urls[1]);
+        assert_eq!(archive.failures[0].reason, FailureReason::DisallowedScheme);
+    }
+
+    #[test]
+    fn test_filter_list() {
+        let list = FilterList::parse("||ads.example.net^$script");
+        let options = ArchiveOptions::default().filter_list(&list);
+        let base = Url::parse("http://example.com/").unwrap();
+        let ad = Url::parse("http://ads.example.net/ad.js").unwrap();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![ResourceUrl::Javascript(ad.clone()), ResourceUrl::Image(ad)],
+            &options,
+            None,
+        );
+
+        let queue = downloader.take_queue();
+        assert_eq!(queue.len(), 1);
+        assert!(matches!(queue[0].0, ResourceUrl::Image(_)));
+    }
+
+    #[test]
+    fn test_inline_module_depth() {
+        let options = ArchiveOptions {
+            module_depth: 1,
+            ..Default::default()
+        };
+        let base = Url::parse("http://example.com/").unwrap();
+        let html = r#"<script type="module">import './a.js';</script>"#;
+        let resource_urls = parse_resource_urls(&base, html, &options);
+        let mut downloader =
+            Downloader::new(base.clone(), resource_urls, &options, None);
+
+        // The inline module's imports are at depth zero, and one more
+        // level of imports is followed from them
+        let queue = downloader.take_queue();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].0.url().path(), "/a.js");
+        assert_eq!(queue[0].1, 0);
+        let (a, depth) = queue.into_iter().next().unwrap();
+        downloader.store(download(a, depth, "import './b.js';"));
+        let queue = downloader.take_queue();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].0.url().path(), "/b.js");
+        assert_eq!(queue[0].1, 1);
+        let (b, depth) = queue.into_iter().next().unwrap();
+        downloader.store(download(b, depth, "import './c.js';"));
+        assert!(downloader.take_queue().is_empty());
+    }
+
+    #[test]
+    fn test_transform() {
+        let transform =
+            |resource_url: &ResourceUrl, resource| match resource_url {
+                ResourceUrl::Image(_) => None,
+                _ => Some(resource),
+            };
+        let options = ArchiveOptions {
+            module_depth: 1,
+            ..Default::default()
+        }
+        .transform(&transform);
+        let base = Url::parse("http://example.com/").unwrap();
+        let main = base.join("main.js").unwrap();
+        let logo = base.join("logo.png").unwrap();
+        let icon = base.join("icon.png").unwrap();
+        let mut downloader = Downloader::new(base, Vec::new(), &options, None);
+        downloader.store(download(
+            ResourceUrl::Javascript(main.clone()),
+            0,
+            "import './lib.js';",
+        ));
+        downloader.store(download(ResourceUrl::Image(logo.clone()), 0, "PNG"));
+        // Replayed from an archive, so it's been through the transform
+        downloader.store(Download {
+            transformed: true,
+            ..download(ResourceUrl::Image(icon.clone()), 0, "PNG")
+        });
+
+        // Imports are still found
+        assert_eq!(downloader.take_queue().len(), 1);
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert!(archive.resource_map.contains_key(&main));
+        assert!(!archive.resource_map.contains_key(&logo));
+        assert!(archive.resource_map.contains_key(&icon));
+    }
+
+    #[test]
+    fn test_cache() {
+        let options = ArchiveOptions::default();
+        let cache = Cache::default();
+        let base = Url::parse("http://example.com/").unwrap();
+        let main = base.join("main.js").unwrap();
+        let lib = base.join("lib.js").unwrap();
+
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![ResourceUrl::Javascript(main.clone())],
+            &options,
+            Some(&cache),
+        );
+        downloader.take_queue();
+        downloader.store(download(
+            ResourceUrl::Javascript(main.clone()),
+            0,
+            "import './lib.js';",
+        ));
+        downloader.take_queue();
+        downloader.store(download(ResourceUrl::Javascript(lib.clone()), 1, ""));
+
+        // Both scripts come from the cache the second time round
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![ResourceUrl::Javascript(main.clone())],
+            &options,
+            Some(&cache),
+        );
+        assert!(downloader.take_queue().is_empty());
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert!(archive.resource_map.contains_key(&main));
+        assert!(archive.resource_map.contains_key(&lib));
+
+        cache.clear();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![ResourceUrl::Javascript(main)],
+            &options,
+            Some(&cache),
+        );
+        assert_eq!(downloader.take_queue().len(), 1);
+    }
+
+    #[test]
+    fn test_timed_out() {
+        let options = ArchiveOptions::default();
+        let base = Url::parse("http://example.com/").unwrap();
+        let slow = base.join("slow.png").unwrap();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![ResourceUrl::Image(slow.clone())],
+            &options,
+            None,
+        );
+        downloader.take_queue();
+        let resource_url = ResourceUrl::Image(slow.clone());
+        downloader.record(failed(&resource_url, FailureReason::TimedOut));
+        downloader.record(failed(&resource_url, FailureReason::TooLarge));
+
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert!(archive.resource_map.is_empty());
+        assert_eq!(archive.timed_out, vec![slow.clone()]);
+        assert_eq!(
+            archive.failures,
+            vec![
+                ResourceFailure {
+                    url: slow.clone(),
+                    reason: FailureReason::TimedOut,
+                    retries: 0,
+                },
+                ResourceFailure {
+                    url: slow,
+                    reason: FailureReason::TooLarge,
+                    retries: 0,
+                },
+            ]
+        );
+        assert!(!archive.is_complete());
+        assert!(!archive.cancelled);
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let options = ArchiveOptions::default();
+        let base = Url::parse("http://example.com/").unwrap();
+        let main = base.join("main.js").unwrap();
+        let image = base.join("image.png").unwrap();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![
+                ResourceUrl::Javascript(main.clone()),
+                ResourceUrl::Image(image.clone()),
+            ],
+            &options,
+            None,
+        );
+        downloader.take_queue();
+        downloader.store(download(
+            ResourceUrl::Javascript(main.clone()),
+            0,
+            "import './lib.js';",
+        ));
+        downloader.record(Fetched::Cancelled);
+
+        // The import isn't downloaded after cancelling
+        assert!(downloader.take_queue().is_empty());
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert!(archive.resource_map.contains_key(&main));
+        assert!(archive.cancelled);
+    }
+
+    #[test]
+    fn test_events() {
+        let events = Mutex::new(Vec::new());
+        let on_event = |event: &ArchiveEvent| {
+            events.lock().unwrap().push(event.clone());
+        };
+        let options = ArchiveOptions::default().on_event(&on_event);
+        let base = Url::parse("http://example.com/").unwrap();
+        let main = base.join("main.js").unwrap();
+        let image = base.join("image.png").unwrap();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![
+                ResourceUrl::Javascript(main.clone()),
+                ResourceUrl::Image(image.clone()),
+            ],
+            &options,
+            None,
+        );
+        downloader.take_queue();
+        downloader.record(Fetched::Downloaded(Box::new(download(
+            ResourceUrl::Javascript(main.clone()),
+            0,
+            "import './lib.js';",
+        ))));
+        downloader.record(failed(
+            &ResourceUrl::Image(image.clone()),
+            FailureReason::Status(404),
+        ));
+        downloader.take_queue();
+
+        assert_eq!(
+            events.into_inner().unwrap(),
+            vec![
+                ArchiveEvent::ResourcesDiscovered { count: 2 },
+                ArchiveEvent::ResourceFinished {
+                    url: main,
+                    bytes: 18
+                },
+                ArchiveEvent::ResourceFailed {
+                    url: image,
+                    reason: "HTTP 404 Not Found".to_string()
+                },
+                ArchiveEvent::ResourcesDiscovered { count: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redirects() {
+        let options = ArchiveOptions::default();
+        let base = Url::parse("http://example.com/").unwrap();
+        let logo = base.join("logo.png").unwrap();
+        let moved = Url::parse("http://cdn.example.com/logo.png").unwrap();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![ResourceUrl::Image(logo.clone())],
+            &options,
+            None,
+        );
+        downloader.take_queue();
+        downloader.store(Download {
+            final_url: moved.clone(),
+            redirects: vec![moved.clone()],
+            ..download(ResourceUrl::Image(logo.clone()), 0, "PNG")
+        });
+
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert_eq!(archive.resource_map.len(), 2);
+        assert_eq!(archive.resource_map[&logo], archive.resource_map[&moved]);
+        assert_eq!(archive.redirects.len(), 1);
+        assert_eq!(archive.redirects[&logo], vec![moved]);
+    }
+
+    #[test]
+    fn test_meta() {
+        let base = Url::parse("http://example.com/").unwrap();
+        let logo = base.join("logo.png").unwrap();
+        let copy = base.join("logo.png#copy").unwrap();
+        let moved = Url::parse("http://cdn.example.com/logo.png").unwrap();
+        let options = ArchiveOptions::default();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![ResourceUrl::Image(logo.clone())],
+            &options,
+            None,
+        );
+        downloader.take_queue();
+        let download = download(ResourceUrl::Image(logo.clone()), 0, "PNG");
+        let meta = ResourceMeta {
+            final_url: moved.clone(),
+            ..download.meta.clone()
+        };
+        downloader.store(Download {
+            final_url: moved.clone(),
+            redirects: vec![moved.clone()],
+            meta: meta.clone(),
+            ..download
+        });
+        // A URL for the same resource turns up afterwards
+        downloader.enqueue(ResourceUrl::Image(copy.clone()), 0);
+        downloader.fetched(base.clone(), meta.clone());
+
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert_eq!(archive.meta.len(), 4);
+        for url in [&base, &logo, &copy, &moved] {
+            let data: &[u8] = if url == &base { b"" } else { b"PNG" };
+            let sha256 = Some(digest::sha256(data));
+            assert_eq!(
+                archive.meta[url],
+                ResourceMeta {
+                    sha256,
+                    ..meta.clone()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let options = ArchiveOptions::default();
+        let cache = Cache::default();
+        let base = Url::parse("http://example.com/").unwrap();
+        let style = base.join("style.css").unwrap();
+        let timing = |url: &Url| FetchTiming {
+            url: url.clone(),
+            time_to_first_byte: Duration::from_millis(10),
+            total: Duration::from_millis(20),
+            bytes: 3,
+        };
+
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![ResourceUrl::Css(style.clone())],
+            &options,
+            Some(&cache),
+        );
+        downloader.timed(Some(timing(&base)));
+        downloader.take_queue();
+        downloader.record(Fetched::Downloaded(Box::new(Download {
+            timing: Some(timing(&style)),
+            ..download(ResourceUrl::Css(style.clone()), 0, "a{}")
+        })));
+        let wall_time = Duration::from_millis(50);
+        let archive = downloader.into_archive(String::new(), wall_time);
+        assert_eq!(archive.stats.wall_time, wall_time);
+        assert_eq!(archive.stats.fetches, vec![timing(&base), timing(&style)]);
+
+        // Nothing is requested when the stylesheet comes from the cache
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![ResourceUrl::Css(style)],
+            &options,
+            Some(&cache),
+        );
+        downloader.timed(None);
+        assert!(downloader.take_queue().is_empty());
+        let archive = downloader.into_archive(String::new(), wall_time);
+        assert!(archive.stats.fetches.is_empty());
+    }
+
+    #[test]
+    fn test_into_queue() {
+        let options = ArchiveOptions::default().scripts(false);
+        let base = Url::parse("http://example.com/").unwrap();
+        let style = base.join("style.css").unwrap();
+        let downloader = Downloader::new(
+            base.clone(),
+            vec![
+                ResourceUrl::Css(style.clone()),
+                ResourceUrl::Javascript(base.join("main.js").unwrap()),
+                ResourceUrl::Image(base.join("logo.png").unwrap()),
+                ResourceUrl::Css(base.join("style.css#print").unwrap()),
+            ],
+            &options,
+            None,
+        );
+        assert_eq!(
+            downloader.into_queue(),
+            vec![
+                ResourceUrl::Css(style),
+                ResourceUrl::Image(base.join("logo.png").unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stylesheets_only() {
+        let options = ArchiveOptions::default();
+        let base = Url::parse("http://example.com/").unwrap();
+        let style = ResourceUrl::Css(base.join("style.css").unwrap());
+        let image = ResourceUrl::Image(base.join("image.png").unwrap());
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![image.clone(), style.clone()],
+            &options,
+            None,
+        )
+        .stylesheets_only();
+
+        let queue = downloader.take_queue();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].0, style);
+        downloader.store(download(style.clone(), 0, "a { src: url(a.png) }"));
+        assert!(downloader.take_queue().is_empty());
+
+        let a = ResourceUrl::Image(base.join("a.png").unwrap());
+        assert_eq!(downloader.into_queue(), vec![style, image, a]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let url = Url::parse("http://example.com/a.png?w=1&h=2#x").unwrap();
+        let options = ArchiveOptions::default();
+        assert_eq!(
+            normalize(&url, &options).as_str(),
+            "http://example.com/a.png?w=1&h=2"
+        );
+        let options = options.sort_query();
+        assert_eq!(
+            normalize(&url, &options).as_str(),
+            "http://example.com/a.png?h=2&w=1"
+        );
+        let normalizer = UrlNormalizer::new().strip_param("w");
+        let options = options.url_normalizer(&normalizer);
+        assert_eq!(
+            normalize(&url, &options).as_str(),
+            "http://example.com/a.png?h=2"
+        );
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let options = ArchiveOptions::default().sort_query();
+        let base = Url::parse("http://example.com/").unwrap();
+        let logo = base.join("logo.svg?a=1&b=2").unwrap();
+        let icon = base.join("logo.svg?b=2&a=1#icon").unwrap();
+        let badge = base.join("logo.svg?a=1&b=2#badge").unwrap();
+        let mut downloader = Downloader::new(
+            base.clone(),
+            vec![
+                ResourceUrl::Image(logo.clone()),
+                ResourceUrl::Image(icon.clone()),
+                ResourceUrl::Image(logo.clone()),
+            ],
+            &options,
+            None,
+        );
+
+        let queue = downloader.take_queue();
+        assert_eq!(queue.len(), 1);
+        downloader.store(download(
+            ResourceUrl::Image(logo.clone()),
+            0,
+            "<svg>",
+        ));
+
+        // Referenced again once it's been downloaded, while a different
+        // query string is a different resource
+        downloader.enqueue(ResourceUrl::Image(badge.clone()), 0);
+        assert!(downloader.take_queue().is_empty());
+        downloader
+            .enqueue(ResourceUrl::Image(base.join("logo.svg").unwrap()), 0);
+        assert_eq!(downloader.take_queue().len(), 1);
+
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert_eq!(archive.resource_map[&logo], archive.resource_map[&icon]);
+        assert_eq!(archive.resource_map[&logo], archive.resource_map[&badge]);
+    }
+
+    #[test]
+    fn test_budget() {
+        let options = ArchiveOptions::default().max_resources(2);
+        let base = Url::parse("http://example.com/").unwrap();
+        let urls: Vec<Url> = ["a.png", "b.png", "c.png"]
+            .iter()
+            .map(|u| base.join(u).unwrap())
+            .collect();
+        let resource_urls =
+            || urls.iter().cloned().map(ResourceUrl::Image).collect();
+        let mut downloader =
+            Downloader::new(base.clone(), resource_urls(), &options, None);
+        assert_eq!(downloader.take_queue().len(), 2);
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert_eq!(archive.failures.len(), 1);
+        assert_eq!(archive.failures[0].url, urls[2]);
+        assert_eq!(archive.failures[0].reason, FailureReason::OverBudget);
+
+        // Downloads which would go over the byte budget are dropped, and
+        // nothing more is downloaded once it's used up
+        let options = ArchiveOptions::default().max_total_bytes(3);
+        let mut downloader = Downloader::new(
+            base.clone(),
+            resource_urls().into_iter().take(2).collect(),
+            &options,
+            None,
+        );
+        assert_eq!(downloader.take_queue().len(), 2);
+        downloader.record(Fetched::Downloaded(Box::new(download(
+            ResourceUrl::Image(urls[0].clone()),
+            0,
+            "PNG",
+        ))));
+        downloader.record(Fetched::Downloaded(Box::new(download(
+            ResourceUrl::Image(urls[1].clone()),
+            0,
+            "PNG",
+        ))));
+        downloader.store(download(
+            ResourceUrl::Document(base.join("frame.html").unwrap()),
+            0,
+            "<img src=c.png>",
+        ));
+        assert!(downloader.take_queue().is_empty());
+        let archive = downloader.into_archive(String::new(), Duration::ZERO);
+        assert!(archive.resource_map.contains_key(&urls[0]));
+        let skipped: Vec<&Url> =
+            archive.failures.iter().map(|f| &f.url).collect();
+        assert_eq!(skipped, vec![&urls[1], &urls[2]]);
+    }
+}
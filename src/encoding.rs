@@ -0,0 +1,225 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for detecting the character encoding of downloaded pages and
+//! text resources
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// How far into the page to look for a `<meta charset>` declaration. The
+/// HTML spec requires it to be within the first 1024 bytes.
+const PRESCAN_LENGTH: usize = 1024;
+
+/// Decode a page into UTF-8. A byte order mark takes priority, followed
+/// by the `charset` parameter of the `Content-Type` header and then any
+/// `<meta charset>` declaration near the start of the page. Pages with
+/// no declared encoding are assumed to be UTF-8.
+pub(crate) fn decode_page(content_type: Option<&str>, data: &[u8]) -> String {
+    let encoding = content_type
+        .and_then(charset_from_content_type)
+        .or_else(|| prescan(data))
+        .unwrap_or(UTF_8);
+
+    // `decode` sniffs for a BOM, which overrides the given encoding
+    let (text, _, _) = encoding.decode(data);
+    text.into_owned()
+}
+
+/// Determine the charset of a stylesheet from the `Content-Type` header,
+/// or failing that its `@charset` rule. A byte order mark overrides
+/// both, which is handled when the stylesheet is decoded.
+pub(crate) fn css_charset(
+    content_type: Option<&str>,
+    data: &[u8],
+) -> Option<String> {
+    content_type
+        .and_then(charset_from_content_type)
+        .or_else(|| charset_rule(data))
+        .map(|encoding| encoding.name().to_string())
+}
+
+/// Determine the charset of a script from the `Content-Type` header
+pub(crate) fn script_charset(content_type: Option<&str>) -> Option<String> {
+    content_type
+        .and_then(charset_from_content_type)
+        .map(|encoding| encoding.name().to_string())
+}
+
+/// Determine the charset of an HTML document from the `Content-Type`
+/// header, or failing that its `<meta charset>` declaration
+pub(crate) fn document_charset(
+    content_type: Option<&str>,
+    data: &[u8],
+) -> Option<String> {
+    content_type
+        .and_then(charset_from_content_type)
+        .or_else(|| prescan(data))
+        .map(|encoding| encoding.name().to_string())
+}
+
+/// Extract the encoding from the `charset` parameter of a `Content-Type`
+/// header or `<meta http-equiv>` content
+fn charset_from_content_type(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Encoding::for_label(value.trim().trim_matches('"').as_bytes())
+        } else {
+            None
+        }
+    })
+}
+
+/// Look for a `<meta charset>` or `<meta http-equiv="Content-Type">`
+/// declaration near the start of the page
+fn prescan(data: &[u8]) -> Option<&'static Encoding> {
+    let data = &data[..data.len().min(PRESCAN_LENGTH)];
+    let mut i = 0;
+
+    while i < data.len() {
+        let rest = &data[i..];
+        if rest.starts_with(b"<!--") {
+            // Skip over the comment
+            i += find(rest, b"-->").map(|end| end + 3).unwrap_or(rest.len());
+        } else if rest.len() > 5
+            && rest[..5].eq_ignore_ascii_case(b"<meta")
+            && (rest[5].is_ascii_whitespace() || rest[5] == b'/')
+        {
+            let end = find(rest, b">").unwrap_or(rest.len());
+            if let Some(encoding) = charset_from_meta(&rest[..end]) {
+                return Some(ascii_compatible(encoding));
+            }
+            i += end;
+        } else {
+            i += 1;
+        }
+    }
+
+    None
+}
+
+/// Find the charset within the attributes of a `<meta>` tag. This covers
+/// both `charset="..."` and `content="text/html; charset=..."`.
+fn charset_from_meta(tag: &[u8]) -> Option<&'static Encoding> {
+    let tag = String::from_utf8_lossy(tag).to_ascii_lowercase();
+    let start = tag.find("charset")? + "charset".len();
+    let value = tag[start..].trim_start().strip_prefix('=')?.trim_start();
+    let value = value.trim_start_matches(['"', '\'']);
+    let end = value
+        .find(|c: char| {
+            c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace()
+        })
+        .unwrap_or(value.len());
+    Encoding::for_label(&value.as_bytes()[..end])
+}
+
+/// Read a stylesheet's `@charset "...";` rule, which has to be the very
+/// first thing in the file
+fn charset_rule(data: &[u8]) -> Option<&'static Encoding> {
+    let rest = data.strip_prefix(b"@charset \"")?;
+    let end = rest.iter().position(|&b| b == b'"')?;
+    if rest.get(end + 1) != Some(&b';') {
+        return None;
+    }
+    Encoding::for_label(&rest[..end]).map(ascii_compatible)
+}
+
+/// A file can't really be UTF-16 if its encoding declaration was readable
+/// as ASCII, so treat it as UTF-8 instead
+fn ascii_compatible(encoding: &'static Encoding) -> &'static Encoding {
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        UTF_8
+    } else {
+        encoding
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1251};
+
+    #[test]
+    fn test_content_type_charset() {
+        let (data, _, _) = SHIFT_JIS.encode("<p>こんにちは</p>");
+
+        assert_eq!(
+            decode_page(Some("text/html; charset=Shift_JIS"), &data),
+            "<p>こんにちは</p>"
+        );
+        assert_eq!(
+            charset_from_content_type("text/html;charset=\"windows-1251\""),
+            Some(WINDOWS_1251)
+        );
+        assert_eq!(charset_from_content_type("text/html"), None);
+    }
+
+    #[test]
+    fn test_meta_charset() {
+        let page = "<html><head><meta charset=\"windows-1251\"></head>\
+                    <body>Привет</body></html>";
+        let (data, _, _) = WINDOWS_1251.encode(page);
+        assert_eq!(decode_page(Some("text/html"), &data), page);
+
+        let page = "<!-- <meta charset=\"utf-16\"> -->\n\
+                    <meta http-equiv=\"Content-Type\" \
+                    content=\"text/html; charset=shift_jis\" />\
+                    <p>こんにちは</p>";
+        let (data, _, _) = SHIFT_JIS.encode(page);
+        assert_eq!(decode_page(None, &data), page);
+    }
+
+    #[test]
+    fn test_encoding_priority() {
+        // The header overrides the meta tag
+        let page = "<meta charset=\"shift_jis\"><p>Привет</p>";
+        let (data, _, _) = WINDOWS_1251.encode(page);
+        assert_eq!(
+            decode_page(Some("text/html; charset=windows-1251"), &data),
+            page
+        );
+
+        // And a BOM overrides both
+        let mut data = b"\xEF\xBB\xBF".to_vec();
+        data.extend_from_slice("<p>héllo</p>".as_bytes());
+        assert_eq!(
+            decode_page(Some("text/html; charset=windows-1251"), &data),
+            "<p>héllo</p>"
+        );
+
+        // UTF-8 is assumed otherwise
+        assert_eq!(
+            decode_page(None, "<p>héllo</p>".as_bytes()),
+            "<p>héllo</p>"
+        );
+    }
+
+    #[test]
+    fn test_css_charset() {
+        let css = b"@charset \"windows-1251\";\np { content: \"\xCF\" }";
+        assert_eq!(css_charset(None, css).as_deref(), Some("windows-1251"));
+        assert_eq!(
+            css_charset(Some("text/css; charset=shift_jis"), css).as_deref(),
+            Some("Shift_JIS")
+        );
+
+        // The rule has to be exactly at the start
+        assert_eq!(css_charset(None, b" @charset \"windows-1251\";"), None);
+        assert_eq!(css_charset(None, b"@charset 'windows-1251';"), None);
+        assert_eq!(
+            css_charset(None, b"@charset \"utf-16\";").as_deref(),
+            Some("UTF-8")
+        );
+        assert_eq!(css_charset(Some("text/css"), b"p {}"), None);
+    }
+}
@@ -7,7 +7,9 @@
 
 //! Module for the error parsing functionality
 
+use crate::client::DisallowedScheme;
 use std::string::FromUtf8Error;
+use url::Url;
 
 /// Error type used by `web_archive` to wrap the errors returned by
 /// operations in this crate or errors from other sources (e.g. URL
@@ -18,10 +20,40 @@ pub enum Error {
     ParseError(String),
     /// Error fetching a resource
     ReqwestError(String),
+    /// The archive was cancelled before the page had been downloaded,
+    /// see [`ArchiveOptions::cancellation`](crate::ArchiveOptions::cancellation)
+    Cancelled,
+    /// The page's URL, or one that it redirected to, has a scheme which
+    /// isn't in [`ArchiveOptions::schemes`](crate::ArchiveOptions::schemes)
+    DisallowedScheme(String),
+    /// The page being read from a WARC file isn't in it, see `read_warc`
+    NotInWarc(String),
+    /// The page being read from a HAR file isn't in it, see
+    /// [`read_har`](crate::read_har)
+    NotInHar(String),
+    /// The page being archived from the responses that a `RecordingProxy`
+    /// recorded wasn't recorded by it
+    NotRecorded(String),
+    /// The server couldn't listen on the address given, or failed while
+    /// serving, see `serve` and `RecordingProxy`
+    Serve(String),
+    /// The browser couldn't be started, or failed while loading the page,
+    /// see `archive_rendered`
+    Browser(String),
+    /// None of the web archives asked by `archive_memento` had a memento
+    /// of the page
+    NoMemento(String),
+    /// The Internet Archive's Save Page Now refused or failed to capture
+    /// the page, see `PageArchive::save_page_now`
+    SavePageNow(String),
 }
 
 impl From<reqwest::Error> for Error {
     fn from(e: reqwest::Error) -> Self {
+        if DisallowedScheme::caused(&e) {
+            let url = e.url().map_or_else(String::new, Url::to_string);
+            return Self::DisallowedScheme(url);
+        }
         Self::ReqwestError(e.to_string())
     }
 }
@@ -37,3 +69,10 @@ impl From<FromUtf8Error> for Error {
         Self::ParseError(e.to_string())
     }
 }
+
+#[cfg(feature = "streaming")]
+impl From<lol_html::errors::RewritingError> for Error {
+    fn from(e: lol_html::errors::RewritingError) -> Self {
+        Self::ParseError(e.to_string())
+    }
+}
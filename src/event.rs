@@ -0,0 +1,64 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for the progress events reported while archiving
+
+use url::Url;
+
+/// Something that happened while archiving a page, passed to
+/// [`ArchiveOptions::on_event`](crate::ArchiveOptions::on_event) so that
+/// progress can be displayed.
+///
+/// Resources are downloaded in parallel, so the events for different
+/// resources may be interleaved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ArchiveEvent {
+    /// The page itself has been downloaded
+    PageFetched {
+        /// URL of the page
+        url: Url,
+        /// Size of the page in bytes
+        bytes: usize,
+    },
+    /// More resources have been found which need downloading. This is
+    /// reported once for those referenced by the page, then again for
+    /// any referenced by the resources themselves, e.g. imported modules.
+    ResourcesDiscovered {
+        /// How many new resources were found
+        count: usize,
+    },
+    /// A resource has started downloading
+    ResourceStarted {
+        /// URL of the resource
+        url: Url,
+    },
+    /// The download of a resource was interrupted, and is being carried on
+    /// from where it stopped rather than started again
+    ResourceResumed {
+        /// URL of the resource
+        url: Url,
+        /// How many bytes had already been downloaded
+        offset: usize,
+    },
+    /// A resource has been downloaded, or found in the
+    /// [`Archiver`](crate::Archiver)'s cache
+    ResourceFinished {
+        /// URL of the resource
+        url: Url,
+        /// Size of the resource in bytes
+        bytes: usize,
+    },
+    /// A resource couldn't be downloaded and has been left out of the
+    /// archive
+    ResourceFailed {
+        /// URL of the resource
+        url: Url,
+        /// Why it couldn't be downloaded, e.g. `HTTP 404 Not Found`
+        reason: String,
+    },
+}
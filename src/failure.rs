@@ -0,0 +1,210 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for recording the resources which couldn't be downloaded
+
+use crate::client::{DisallowedHost, DisallowedScheme};
+use crate::error::Error;
+use reqwest::StatusCode;
+use std::fmt;
+use url::Url;
+
+/// A resource which couldn't be downloaded, and has been left pointing at
+/// its original URL
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResourceFailure {
+    /// URL of the resource
+    pub url: Url,
+    /// Why it couldn't be downloaded
+    pub reason: FailureReason,
+    /// How many times the download was retried before giving up, see
+    /// [`ArchiveOptions::retries`](crate::ArchiveOptions::retries)
+    pub retries: usize,
+}
+
+/// Why a resource couldn't be downloaded
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum FailureReason {
+    /// The server responded with an unsuccessful status code
+    Status(u16),
+    /// The resource was larger than
+    /// [`ArchiveOptions::max_resource_size`](crate::ArchiveOptions::max_resource_size)
+    TooLarge,
+    /// The archive's
+    /// [`max_total_bytes`](crate::ArchiveOptions::max_total_bytes) or
+    /// [`max_resources`](crate::ArchiveOptions::max_resources) budget was
+    /// used up, so it wasn't downloaded
+    OverBudget,
+    /// The URL, or one that it redirected to, has a scheme which isn't in
+    /// [`ArchiveOptions::schemes`](crate::ArchiveOptions::schemes)
+    DisallowedScheme,
+    /// The resource was redirected to a host which
+    /// [`ArchiveOptions::same_origin`](crate::ArchiveOptions::same_origin)
+    /// and
+    /// [`ArchiveOptions::allowed_hosts`](crate::ArchiveOptions::allowed_hosts)
+    /// don't allow
+    DisallowedHost,
+    /// The request timed out, or the archive's
+    /// [`deadline`](crate::ArchiveOptions::deadline) passed first
+    TimedOut,
+    /// The request failed, e.g. because the server couldn't be reached
+    Network(String),
+    /// The resource isn't in the WARC file that the page is being read
+    /// from, see `read_warc`
+    NotInWarc,
+    /// The resource isn't in the HAR file that the page is being read
+    /// from, see [`read_har`](crate::read_har)
+    NotInHar,
+    /// The resource wasn't requested through the `RecordingProxy` that
+    /// the page is being archived from
+    NotRecorded,
+    /// The response couldn't be read as what it was expected to be, such
+    /// as a sitemap which isn't XML
+    Unreadable(String),
+}
+
+impl FailureReason {
+    /// Whether the download might succeed if it's retried
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            Self::Status(status) => *status >= 500,
+            Self::TooLarge
+            | Self::OverBudget
+            | Self::DisallowedScheme
+            | Self::DisallowedHost
+            | Self::NotInWarc
+            | Self::NotInHar
+            | Self::NotRecorded
+            | Self::Unreadable(_) => false,
+            Self::TimedOut | Self::Network(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Status(status) => {
+                write!(f, "HTTP {}", status)?;
+                let reason = StatusCode::from_u16(*status)
+                    .ok()
+                    .and_then(|status| status.canonical_reason());
+                match reason {
+                    Some(reason) => write!(f, " {}", reason),
+                    None => Ok(()),
+                }
+            }
+            Self::TooLarge => {
+                write!(f, "larger than the maximum resource size")
+            }
+            Self::OverBudget => write!(f, "over the archive's budget"),
+            Self::DisallowedScheme => write!(f, "scheme not allowed"),
+            Self::DisallowedHost => {
+                write!(f, "redirected to a host not allowed")
+            }
+            Self::TimedOut => write!(f, "timed out"),
+            Self::Network(error) => write!(f, "{}", error),
+            Self::NotInWarc => write!(f, "not in the WARC file"),
+            Self::NotInHar => write!(f, "not in the HAR file"),
+            Self::NotRecorded => write!(f, "not recorded by the proxy"),
+            Self::Unreadable(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl FailureReason {
+    /// Why a page couldn't be archived, from the error archiving it
+    pub(crate) fn from_error(e: Error) -> Self {
+        match e {
+            Error::ReqwestError(e) => Self::Network(e),
+            Error::ParseError(e) => Self::Unreadable(e),
+            Error::DisallowedScheme(_) => Self::DisallowedScheme,
+            // Cancelled archives stop rather than recording a failure
+            Error::Cancelled => Self::TimedOut,
+            Error::NotInWarc(_) => Self::NotInWarc,
+            Error::NotInHar(_) => Self::NotInHar,
+            Error::NotRecorded(_) => Self::NotRecorded,
+            Error::Serve(e) | Error::Browser(e) | Error::SavePageNow(e) => {
+                Self::Network(e)
+            }
+            Error::NoMemento(_) => Self::Status(404),
+        }
+    }
+}
+
+impl From<reqwest::Error> for FailureReason {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::TimedOut
+        } else if DisallowedScheme::caused(&e) {
+            Self::DisallowedScheme
+        } else if DisallowedHost::caused(&e) {
+            Self::DisallowedHost
+        } else {
+            Self::Network(e.to_string())
+        }
+    }
+}
+
+/// Reading a blocking response's body fails with an I/O error wrapping the
+/// underlying request error
+impl From<std::io::Error> for FailureReason {
+    fn from(e: std::io::Error) -> Self {
+        let inner = e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<reqwest::Error>());
+        match inner {
+            Some(inner) if inner.is_timeout() => Self::TimedOut,
+            _ if e.kind() == std::io::ErrorKind::TimedOut => Self::TimedOut,
+            _ => Self::Network(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            FailureReason::Status(404).to_string(),
+            "HTTP 404 Not Found"
+        );
+        assert_eq!(FailureReason::Status(999).to_string(), "HTTP 999");
+        assert_eq!(FailureReason::TimedOut.to_string(), "timed out");
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(FailureReason::Status(503).is_transient());
+        assert!(!FailureReason::Status(404).is_transient());
+        assert!(!FailureReason::TooLarge.is_transient());
+        assert!(!FailureReason::OverBudget.is_transient());
+        assert!(FailureReason::Network("refused".into()).is_transient());
+    }
+
+    #[test]
+    fn test_from_io_error() {
+        use std::io::{Error, ErrorKind};
+
+        assert_eq!(
+            FailureReason::from(Error::new(ErrorKind::TimedOut, "slow")),
+            FailureReason::TimedOut
+        );
+        assert_eq!(
+            FailureReason::from(Error::new(
+                ErrorKind::ConnectionReset,
+                "reset"
+            )),
+            FailureReason::Network("reset".into())
+        );
+    }
+}
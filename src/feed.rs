@@ -0,0 +1,509 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for archiving the pages of an RSS or Atom feed's entries, see
+//! [`archive_feed`]
+
+use crate::archiver::Archiver;
+use crate::cancel::CancellationToken;
+use crate::date;
+use crate::error::Error;
+use crate::failure::FailureReason;
+use crate::page_archive::PageArchive;
+use crate::site::SiteArchive;
+use crate::xml::{self, Element};
+use crate::ArchiveOptions;
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryInto;
+use std::fmt::Display;
+use std::time::SystemTime;
+use url::Url;
+
+/// Configuration options for [`archive_feed`], controlling which entries'
+/// pages are archived
+#[derive(Default)]
+pub struct FeedOptions<'a> {
+    /// The options used to archive each page
+    ///
+    /// Default: [`ArchiveOptions::default`]
+    pub archive: ArchiveOptions<'a>,
+    /// Only archive entries published at or after this time. Entries
+    /// without a date are archived regardless.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    /// use web_archive::FeedOptions;
+    /// let week = Duration::from_secs(7 * 24 * 60 * 60);
+    /// let options = FeedOptions {
+    ///     published_since: Some(SystemTime::now() - week),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub published_since: Option<SystemTime>,
+    /// The most entries to archive, in the order the feed lists them,
+    /// which is usually the newest first, or `None` for no limit
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::FeedOptions;
+    /// let options = FeedOptions {
+    ///     max_entries: Some(10),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_entries: Option<usize>,
+}
+
+impl<'a> FeedOptions<'a> {
+    /// Archive each page with the options, see
+    /// [`FeedOptions::archive`](#structfield.archive)
+    pub fn archive(mut self, options: ArchiveOptions<'a>) -> Self {
+        self.archive = options;
+        self
+    }
+
+    /// Only archive entries published since `time`, see
+    /// [`FeedOptions::published_since`](#structfield.published_since)
+    pub fn published_since(mut self, time: SystemTime) -> Self {
+        self.published_since = Some(time);
+        self
+    }
+
+    /// Set the most entries to archive, see
+    /// [`FeedOptions::max_entries`](#structfield.max_entries)
+    pub fn max_entries(mut self, n: usize) -> Self {
+        self.max_entries = Some(n);
+        self
+    }
+}
+
+/// An entry of a feed whose page was archived by [`archive_feed`], kept
+/// in [`SiteArchive::entries`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeedEntry {
+    /// The entry's unique identifier, from its Atom `<id>` or RSS
+    /// `<guid>`, or else its URL
+    pub id: String,
+    /// The URL of the entry's page, which
+    /// [`SiteArchive::page`](crate::SiteArchive::page) takes
+    pub url: Url,
+    /// The entry's title
+    pub title: Option<String>,
+    /// The name of the entry's author
+    pub author: Option<String>,
+    /// When the entry was published, or else last updated
+    pub published: Option<SystemTime>,
+}
+
+/// Archive the page of every entry in the RSS or Atom feed at `url`
+/// into a [`SiteArchive`], recording the entries in
+/// [`SiteArchive::entries`] so that each one's page can be looked up.
+///
+/// RSS 2.0, RSS 1.0, and Atom feeds are read. Pages are archived one
+/// after another in the order the feed lists them, with an [`Archiver`]
+/// so that resources shared between them are only downloaded once. A page
+/// linked to by several entries is only archived once, and entries
+/// without a link are skipped.
+///
+/// An error is returned if the feed can't be fetched or read. Pages which
+/// can't be archived, including those with an unsuccessful status code,
+/// are recorded in [`SiteArchive::failures`] instead of their entries. If
+/// the options' [`cancellation`](ArchiveOptions::cancellation) token is
+/// cancelled, the pages archived so far are returned.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{archive_feed, FeedOptions};
+///
+/// # async fn archive_feed_async() {
+/// let options = FeedOptions::default().max_entries(10);
+/// let site = archive_feed("http://example.com/feed.xml", options)
+///     .await
+///     .unwrap();
+/// for entry in site.entries() {
+///     let page = site.page(&entry.url).unwrap();
+///     println!("{:?}: {} bytes", entry.title, page.content.len());
+/// }
+/// # }
+/// ```
+pub async fn archive_feed<U>(
+    url: U,
+    options: FeedOptions<'_>,
+) -> Result<SiteArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let mut feed = Feed::new(&options);
+    let archiver = Archiver::new(options.archive)?;
+    let response = archiver.client().get(url.clone()).send().await?;
+    let status = response.status().as_u16();
+    let data = response.bytes().await?;
+    feed.read(&url, status, &data)?;
+    while let Some(url) = feed.next_page() {
+        let archive = archiver.archive(url.clone()).await;
+        feed.archived(url, archive);
+    }
+    Ok(feed.finish())
+}
+
+/// The state of archiving a feed, shared between the async and blocking
+/// APIs, which hand out the pages to archive once the feed has been read
+pub(crate) struct Feed {
+    published_since: Option<SystemTime>,
+    max_entries: Option<usize>,
+    cancellation: Option<CancellationToken>,
+    /// The entries to archive, in the order they were listed
+    entries: Vec<FeedEntry>,
+    /// The pages of the entries waiting to be archived
+    pages: VecDeque<Url>,
+    cancelled: bool,
+    site: SiteArchive,
+}
+
+impl Feed {
+    pub fn new(options: &FeedOptions) -> Self {
+        Self {
+            published_since: options.published_since,
+            max_entries: options.max_entries,
+            cancellation: options.archive.cancellation.clone(),
+            entries: Vec::new(),
+            pages: VecDeque::new(),
+            cancelled: false,
+            site: SiteArchive::new(),
+        }
+    }
+
+    /// Read the feed fetched from `url`, queueing the pages of its
+    /// entries
+    pub fn read(
+        &mut self,
+        url: &Url,
+        status: u16,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        if status >= 400 {
+            let reason = FailureReason::Status(status);
+            return Err(Error::ReqwestError(format!("{}: {}", url, reason)));
+        }
+        let text = String::from_utf8_lossy(data);
+        let entries = parse_feed(url, &text).ok_or_else(|| {
+            Error::ParseError(format!("{}: not an RSS or Atom feed", url))
+        })?;
+
+        let mut pages = HashSet::new();
+        for entry in entries {
+            let full = self
+                .max_entries
+                .map_or(false, |max| self.entries.len() >= max);
+            if full {
+                break;
+            }
+            let is_recent = match (self.published_since, entry.published) {
+                (Some(since), Some(published)) => published >= since,
+                _ => true,
+            };
+            if !is_recent {
+                continue;
+            }
+            let mut page = entry.url.clone();
+            page.set_fragment(None);
+            if pages.insert(page.clone()) {
+                self.pages.push_back(page);
+            }
+            self.entries.push(entry);
+        }
+        Ok(())
+    }
+
+    /// The next page to archive, or `None` once they've all been archived
+    pub fn next_page(&mut self) -> Option<Url> {
+        let cancelled = self
+            .cancellation
+            .as_ref()
+            .map_or(false, CancellationToken::is_cancelled);
+        if self.cancelled || cancelled {
+            return None;
+        }
+        self.pages.pop_front()
+    }
+
+    /// Record how archiving the page at `url` went
+    pub fn archived(&mut self, url: Url, archive: Result<PageArchive, Error>) {
+        match archive {
+            Ok(archive) => {
+                self.cancelled |= archive.cancelled;
+                self.site.add_archived(url, archive);
+            }
+            Err(Error::Cancelled) => self.cancelled = true,
+            Err(e) => self.site.failed(url, FailureReason::from_error(e)),
+        }
+    }
+
+    /// The pages archived, along with the entries they belong to
+    pub fn finish(mut self) -> SiteArchive {
+        let site = &self.site;
+        self.entries.retain(|entry| site.contains(&entry.url));
+        self.site.entries = self.entries;
+        self.site
+    }
+}
+
+/// The entries of an RSS or Atom feed, or `None` if it isn't one
+fn parse_feed(feed_url: &Url, text: &str) -> Option<Vec<FeedEntry>> {
+    let root = xml::parse(text)?;
+    let (items, is_atom) = match root.local_name() {
+        "rss" => (root.element("channel")?.elements_named("item"), false),
+        // RSS 1.0, whose items are alongside its channel
+        "RDF" => (root.elements_named("item"), false),
+        "feed" => (root.elements_named("entry"), true),
+        _ => return None,
+    };
+    let entries = items
+        .filter_map(|item| {
+            if is_atom {
+                atom_entry(feed_url, item)
+            } else {
+                rss_item(feed_url, item)
+            }
+        })
+        .collect();
+    Some(entries)
+}
+
+fn rss_item(feed_url: &Url, item: &Element) -> Option<FeedEntry> {
+    let guid = item.element("guid");
+    let guid_text = item.child_text("guid");
+    let is_permalink = guid
+        .and_then(|guid| guid.attribute("isPermaLink"))
+        .map_or(true, |permalink| permalink.trim() != "false");
+    let link = link(item)
+        .or_else(|| item.attribute("rdf:about").map(str::to_string))
+        .or_else(|| guid_text.clone().filter(|_| is_permalink))?;
+    let url = feed_url.join(&link).ok()?;
+    let published = item
+        .child_text("pubDate")
+        .and_then(|date| date::parse_rfc822(&date))
+        .or_else(|| {
+            let date = item.child_text("date")?;
+            date::parse_w3c(&date)
+        });
+    Some(FeedEntry {
+        id: guid_text.unwrap_or_else(|| url.to_string()),
+        title: title(item),
+        author: item
+            .child_text("creator")
+            .or_else(|| item.child_text("author")),
+        published,
+        url,
+    })
+}
+
+fn atom_entry(feed_url: &Url, entry: &Element) -> Option<FeedEntry> {
+    let url = feed_url.join(&link(entry)?).ok()?;
+    let published = entry
+        .child_text("published")
+        .or_else(|| entry.child_text("updated"))
+        .and_then(|date| date::parse_w3c(&date));
+    Some(FeedEntry {
+        id: entry.child_text("id").unwrap_or_else(|| url.to_string()),
+        title: title(entry),
+        author: entry
+            .element("author")
+            .and_then(|author| author.child_text("name")),
+        published,
+        url,
+    })
+}
+
+/// The link of an entry to its page: the `href` of an Atom `<link>` to
+/// an alternate version, or the text of an RSS `<link>`
+fn link(entry: &Element) -> Option<String> {
+    entry.elements_named("link").find_map(|link| {
+        let link = match link.attribute("href") {
+            Some(href) => {
+                let rel = link.attribute("rel").unwrap_or("alternate");
+                Some(href.trim().to_string()).filter(|_| rel == "alternate")
+            }
+            None => Some(link.text().trim().to_string()),
+        };
+        link.filter(|link| !link.is_empty())
+    })
+}
+
+/// The title of an entry, with its whitespace collapsed
+fn title(entry: &Element) -> Option<String> {
+    let title = entry.child_text("title")?;
+    Some(title.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::meta::ResourceMeta;
+    use std::collections::HashMap;
+
+    const RSS: &str = "<?xml version=\"1.0\"?>\
+        <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" \
+        xmlns:dc=\"http://purl.org/dc/elements/1.1/\"><channel>\
+        <title>A Blog</title><link>http://example.com/</link>\
+        <atom:link href=\"http://example.com/feed\" rel=\"self\"/>\
+        <item><title>Newest\n  post</title>\
+        <link>http://example.com/posts/3</link>\
+        <guid isPermaLink=\"false\">post-3</guid>\
+        <pubDate>Wed, 03 Feb 2021 04:05:06 GMT</pubDate>\
+        <dc:creator>Jane</dc:creator></item>\
+        <item><title>By permalink</title>\
+        <guid>http://example.com/posts/2</guid>\
+        <dc:date>2021-01-01T00:00:00Z</dc:date></item>\
+        <item><title>Not linked</title><guid isPermaLink=\"false\">x</guid>\
+        </item>\
+        <item><title>Same page</title><link>/posts/3#comments</link></item>\
+        <item><title>Oldest</title><link>/posts/1</link>\
+        <pubDate>Tue, 01 Jan 2019 00:00:00 GMT</pubDate></item>\
+        </channel></rss>";
+
+    const ATOM: &str = "<feed xmlns=\"http://www.w3.org/2005/Atom\">\
+        <title>A Blog</title>\
+        <entry><id>tag:example.com,2021:1</id><title type=\"html\">\
+        Fish &amp;amp; chips</title>\
+        <link rel=\"edit\" href=\"/edit/1\"/>\
+        <link href=\"/posts/1\"/>\
+        <author><name>Jane</name></author>\
+        <updated>2021-02-03T04:05:06Z</updated></entry>\
+        <entry><id>tag:example.com,2021:2</id>\
+        <link rel=\"alternate\" href=\"http://example.com/posts/2\"/>\
+        <published>2021-01-01T00:00:00+01:00</published></entry>\
+        </feed>";
+
+    fn page(url: &Url, status: u16) -> PageArchive {
+        let meta = ResourceMeta {
+            status,
+            final_url: url.clone(),
+            fetched_at: SystemTime::now(),
+            content_type: Some("text/html".to_string()),
+            cache_control: None,
+            validators: Default::default(),
+            snapshot: None,
+            sha256: None,
+        };
+        PageArchive {
+            url: url.clone(),
+            content: String::new(),
+            resource_map: HashMap::new(),
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: vec![(url.clone(), meta)].into_iter().collect(),
+            stats: Default::default(),
+            screenshot: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_feed() {
+        let url = Url::parse("http://example.com/feed").unwrap();
+        let entries = parse_feed(&url, RSS).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| (e.id.as_str(), e.url.as_str(), e.title.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("post-3", "http://example.com/posts/3", Some("Newest post")),
+                (
+                    "http://example.com/posts/2",
+                    "http://example.com/posts/2",
+                    Some("By permalink")
+                ),
+                (
+                    "http://example.com/posts/3#comments",
+                    "http://example.com/posts/3#comments",
+                    Some("Same page")
+                ),
+                (
+                    "http://example.com/posts/1",
+                    "http://example.com/posts/1",
+                    Some("Oldest")
+                ),
+            ]
+        );
+        assert_eq!(entries[0].author.as_deref(), Some("Jane"));
+        assert_eq!(
+            entries[0].published,
+            date::parse_w3c("2021-02-03T04:05:06Z")
+        );
+        assert_eq!(entries[1].published, date::parse_w3c("2021-01-01"));
+
+        let entries = parse_feed(&url, ATOM).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, "tag:example.com,2021:1");
+        assert_eq!(entries[0].url.as_str(), "http://example.com/posts/1");
+        assert_eq!(entries[0].title.as_deref(), Some("Fish &amp; chips"));
+        assert_eq!(entries[0].author.as_deref(), Some("Jane"));
+        assert_eq!(
+            entries[1].published,
+            date::parse_w3c("2020-12-31T23:00:00Z")
+        );
+
+        assert_eq!(parse_feed(&url, "<urlset/>"), None);
+    }
+
+    #[test]
+    fn test_archive_feed() {
+        let url = Url::parse("http://example.com/feed").unwrap();
+        let since = date::parse_w3c("2020-01-01").unwrap();
+        let options = FeedOptions::default().published_since(since);
+        let mut feed = Feed::new(&options);
+        feed.read(&url, 200, RSS.as_bytes()).unwrap();
+        let mut requested = Vec::new();
+        while let Some(url) = feed.next_page() {
+            requested.push(url.to_string());
+            let status = if url.path() == "/posts/2" { 404 } else { 200 };
+            feed.archived(url.clone(), Ok(page(&url, status)));
+        }
+        assert_eq!(
+            requested,
+            vec!["http://example.com/posts/3", "http://example.com/posts/2"]
+        );
+        let site = feed.finish();
+        assert_eq!(site.len(), 1);
+        let ids: Vec<_> =
+            site.entries().iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["post-3", "http://example.com/posts/3#comments"]);
+        assert_eq!(site.failures().len(), 1);
+        let page = site.entry_page("post-3").unwrap();
+        assert_eq!(page.url.as_str(), "http://example.com/posts/3");
+        assert!(site.entry_page("http://example.com/posts/2").is_none());
+
+        let options = FeedOptions::default().max_entries(1);
+        let mut feed = Feed::new(&options);
+        feed.read(&url, 200, ATOM.as_bytes()).unwrap();
+        assert!(feed.next_page().is_some());
+        assert!(feed.next_page().is_none());
+
+        let mut feed = Feed::new(&options);
+        let res = feed.read(&url, 200, b"<html></html>");
+        assert!(matches!(res, Err(Error::ParseError(_))));
+        let res = feed.read(&url, 404, b"");
+        assert!(matches!(res, Err(Error::ReqwestError(_))));
+    }
+}
@@ -0,0 +1,667 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for blocking ads and trackers with Adblock Plus style filter
+//! lists, such as EasyList and EasyPrivacy
+
+use crate::parsing::{resolve_url, ResourceUrl};
+use std::collections::HashMap;
+use url::{Position, Url};
+
+/// An element which loads a resource, the attribute holding its URL, and
+/// the kind of resource it is
+type ResourceElement = (&'static str, &'static str, fn(Url) -> ResourceUrl);
+
+const RESOURCE_ELEMENTS: &[ResourceElement] = &[
+    ("script", "src", ResourceUrl::Javascript),
+    ("img", "src", ResourceUrl::Image),
+    ("link", "href", ResourceUrl::Css),
+    ("iframe", "src", ResourceUrl::Document),
+    ("frame", "src", ResourceUrl::Document),
+    ("object", "data", ResourceUrl::Object),
+    ("embed", "src", ResourceUrl::Object),
+    ("video", "src", ResourceUrl::Media),
+    ("audio", "src", ResourceUrl::Media),
+];
+
+/// Every kind of resource, as a mask of the bits from [`kind_bit`]
+const ALL_KINDS: u16 = 0xff;
+
+/// Tokens found in so many URLs that rules are only indexed by them if
+/// they have no others
+const COMMON_TOKENS: &[&str] = &["http", "https", "www", "com", "js"];
+
+/// A set of Adblock Plus style filter rules, such as EasyList and
+/// EasyPrivacy.
+///
+/// Network rules, e.g. `||ads.example.com^$script,third-party`, stop
+/// matching resources from being downloaded when set as
+/// [`ArchiveOptions::filter_list`](crate::ArchiveOptions::filter_list).
+/// Element hiding rules, e.g. `##.ad-banner`, remove the elements they
+/// match when set as
+/// [`EmbedOptions::filter_list`](crate::EmbedOptions::filter_list), along
+/// with any elements loading blocked resources.
+///
+/// Only part of the syntax is supported. Network rules with options other
+/// than the resource types, `third-party`, `domain`, and `match-case` are
+/// ignored, as are regular expression rules, element hiding exceptions,
+/// and extended element hiding rules. Third parties are told apart by the
+/// last two labels of their host names, since the public suffix list
+/// isn't available.
+///
+/// Network rules are indexed by a word that any URL they match contains,
+/// so checking a URL only tries the few rules sharing one of its words,
+/// and lists with tens of thousands of rules stay quick.
+///
+/// ## Example
+/// ```no_run
+/// use web_archive::{blocking, ArchiveOptions, EmbedOptions, FilterList};
+///
+/// let mut list =
+///     FilterList::parse(&std::fs::read_to_string("easylist.txt").unwrap());
+/// list.add_rules(&std::fs::read_to_string("easyprivacy.txt").unwrap());
+///
+/// let options = ArchiveOptions::default().filter_list(&list);
+/// let archive = blocking::archive("http://example.com", options).unwrap();
+/// let page = archive.embed_resources_with(&EmbedOptions {
+///     filter_list: Some(&list),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FilterList {
+    blocking: RuleIndex,
+    exceptions: RuleIndex,
+    hiding: Vec<HidingRule>,
+}
+
+impl FilterList {
+    /// Parse a filter list, one rule per line. Comments and rules which
+    /// aren't supported are skipped.
+    pub fn parse(rules: &str) -> Self {
+        let mut list = Self::default();
+        list.add_rules(rules);
+        list
+    }
+
+    /// Add the rules from another filter list
+    pub fn add_rules(&mut self, rules: &str) {
+        for line in rules.lines() {
+            self.add_rule(line.trim());
+        }
+    }
+
+    /// Whether a resource is blocked when archiving the page at `page`
+    ///
+    /// ## Example
+    /// ```
+    /// use url::Url;
+    /// use web_archive::{FilterList, ResourceUrl};
+    ///
+    /// let list = FilterList::parse("||ads.example.com^$third-party");
+    /// let page = Url::parse("http://example.org/").unwrap();
+    /// let ad = Url::parse("http://ads.example.com/banner.png").unwrap();
+    /// assert!(list.blocks(&ResourceUrl::Image(ad), &page));
+    /// ```
+    pub fn blocks(&self, resource_url: &ResourceUrl, page: &Url) -> bool {
+        let request = Request::new(resource_url, page);
+        self.blocking.matches(&request) && !self.exceptions.matches(&request)
+    }
+
+    /// The element hiding selectors which apply to the page at `page`
+    pub(crate) fn hidden_selectors<'s>(
+        &'s self,
+        page: &'s Url,
+    ) -> impl Iterator<Item = &'s str> + 's {
+        self.hiding
+            .iter()
+            .filter(move |rule| matches_domains(&rule.domains, page))
+            .map(|rule| rule.selector.as_str())
+    }
+
+    /// Whether an element loads a resource which the list blocks, looking
+    /// up its attributes with `attribute`
+    pub(crate) fn blocks_element(
+        &self,
+        tag: &str,
+        attribute: impl Fn(&str) -> Option<String>,
+        base: &Url,
+    ) -> bool {
+        RESOURCE_ELEMENTS
+            .iter()
+            .filter(|(name, _, _)| tag.eq_ignore_ascii_case(name))
+            .filter_map(|(_, attr, kind)| {
+                let url = resolve_url(base, &attribute(attr)?)?;
+                Some(kind(url))
+            })
+            .any(|resource_url| self.blocks(&resource_url, base))
+    }
+
+    fn add_rule(&mut self, line: &str) {
+        if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+            // Comments and the `[Adblock Plus 2.0]` header
+            return;
+        }
+        if ["#@#", "#?#", "#$#", "#%#"]
+            .iter()
+            .any(|m| line.contains(m))
+        {
+            // Element hiding exceptions, extended hiding, and snippets
+            return;
+        }
+        if let Some(i) = line.find("##") {
+            let selector = line[i + 2..].trim();
+            if !selector.is_empty() && !selector.starts_with('+') {
+                self.hiding.push(HidingRule {
+                    domains: parse_domains(&line[..i], ','),
+                    selector: selector.to_string(),
+                });
+            }
+            return;
+        }
+        let (index, rule) = match line.strip_prefix("@@") {
+            Some(rule) => (&mut self.exceptions, rule),
+            None => (&mut self.blocking, line),
+        };
+        if let Some(rule) = NetworkRule::parse(rule) {
+            index.insert(rule);
+        }
+    }
+}
+
+/// Network rules, keyed by a token that every URL they match contains
+#[derive(Clone, Debug, Default)]
+struct RuleIndex {
+    by_token: HashMap<String, Vec<NetworkRule>>,
+    /// Rules without a token, which are tried against every URL
+    untokened: Vec<NetworkRule>,
+}
+
+impl RuleIndex {
+    fn insert(&mut self, rule: NetworkRule) {
+        let tokens = rule.tokens();
+        let common = |token: &&str| COMMON_TOKENS.contains(token);
+        // The longest is likely to be the rarest
+        let token = tokens
+            .iter()
+            .filter(|token| !common(token))
+            .max_by_key(|token| token.len())
+            .or_else(|| tokens.iter().max_by_key(|token| token.len()));
+        match token {
+            Some(token) => {
+                let token = token.to_ascii_lowercase();
+                self.by_token.entry(token).or_default().push(rule);
+            }
+            None => self.untokened.push(rule),
+        }
+    }
+
+    fn matches(&self, request: &Request) -> bool {
+        self.untokened.iter().any(|rule| rule.matches(request))
+            || tokens(&request.lowercase).any(|token| {
+                self.by_token.get(token).map_or(false, |rules| {
+                    rules.iter().any(|r| r.matches(request))
+                })
+            })
+    }
+}
+
+/// A rule matching the URLs of resources
+#[derive(Clone, Debug)]
+struct NetworkRule {
+    /// Lowercase unless `match_case` is set. `*` matches anything, and
+    /// `^` matches a separator character or the end of the URL.
+    pattern: String,
+    anchor: Anchor,
+    /// Whether the pattern has to match the end of the URL
+    end_anchor: bool,
+    match_case: bool,
+    /// Mask of the kinds of resource that the rule applies to
+    kinds: u16,
+    third_party: Option<bool>,
+    /// Domains of the pages that the rule applies to, or doesn't if
+    /// they're `false`
+    domains: Vec<(String, bool)>,
+}
+
+/// Where in the URL a pattern has to start matching
+#[derive(Clone, Copy, Debug)]
+enum Anchor {
+    /// Anywhere, e.g. `/ads/`
+    None,
+    /// At the start of the URL, e.g. `|https://ads.`
+    Start,
+    /// At the start of the host name or one of its labels, e.g.
+    /// `||ads.example.com^`
+    Domain,
+}
+
+impl NetworkRule {
+    fn parse(rule: &str) -> Option<Self> {
+        let (pattern, options) = match rule.rfind('$') {
+            Some(i) => (&rule[..i], Some(&rule[i + 1..])),
+            None => (rule, None),
+        };
+        if pattern.len() > 1
+            && pattern.starts_with('/')
+            && pattern.ends_with('/')
+        {
+            // Regular expression rules aren't supported
+            return None;
+        }
+
+        let mut parsed = Self {
+            pattern: String::new(),
+            anchor: Anchor::None,
+            end_anchor: false,
+            match_case: false,
+            kinds: ALL_KINDS,
+            third_party: None,
+            domains: Vec::new(),
+        };
+        let (mut included, mut excluded) = (0, 0);
+        let mut unarchived = false;
+        for option in options.into_iter().flat_map(|o| o.split(',')) {
+            let (negated, name) = match option.strip_prefix('~') {
+                Some(name) => (true, name),
+                None => (false, option),
+            };
+            match name {
+                "third-party" | "3p" => parsed.third_party = Some(!negated),
+                "first-party" | "1p" => parsed.third_party = Some(negated),
+                "match-case" => parsed.match_case = true,
+                "important" => {}
+                _ if name.starts_with("domain=") => {
+                    parsed.domains = parse_domains(&name[7..], '|');
+                }
+                _ => {
+                    let kind = option_kinds(name)?;
+                    if negated {
+                        excluded |= kind;
+                    } else {
+                        included |= kind;
+                        unarchived |= kind == 0;
+                    }
+                }
+            }
+        }
+        if included != 0 {
+            parsed.kinds = included;
+        } else if unarchived {
+            // Only applies to requests which are never made while
+            // archiving, e.g. `$xmlhttprequest`
+            return None;
+        }
+        parsed.kinds &= !excluded;
+
+        let pattern = if let Some(pattern) = pattern.strip_prefix("||") {
+            parsed.anchor = Anchor::Domain;
+            pattern
+        } else if let Some(pattern) = pattern.strip_prefix('|') {
+            parsed.anchor = Anchor::Start;
+            pattern
+        } else {
+            pattern
+        };
+        let pattern = match pattern.strip_suffix('|') {
+            Some(pattern) => {
+                parsed.end_anchor = true;
+                pattern
+            }
+            None => pattern,
+        };
+        parsed.pattern = if parsed.match_case {
+            pattern.to_string()
+        } else {
+            pattern.to_ascii_lowercase()
+        };
+        Some(parsed)
+    }
+
+    /// The tokens of the pattern which are whole tokens of any URL that it
+    /// matches, i.e. those with nothing on either side that could match
+    /// more token characters
+    fn tokens(&self) -> Vec<&str> {
+        let pattern = self.pattern.as_bytes();
+        let mut found = Vec::new();
+        let mut start = 0;
+        while start < pattern.len() {
+            if !is_token(pattern[start]) {
+                start += 1;
+                continue;
+            }
+            let end = pattern[start..]
+                .iter()
+                .position(|&c| !is_token(c))
+                .map_or(pattern.len(), |i| start + i);
+            let bounded_before = match start.checked_sub(1) {
+                Some(i) => pattern[i] != b'*',
+                None => !matches!(self.anchor, Anchor::None),
+            };
+            let bounded_after = match pattern.get(end) {
+                Some(&c) => c != b'*',
+                None => self.end_anchor,
+            };
+            if bounded_before && bounded_after {
+                found.push(&self.pattern[start..end]);
+            }
+            start = end;
+        }
+        found
+    }
+
+    fn matches(&self, request: &Request) -> bool {
+        if self.kinds & request.kind == 0 {
+            return false;
+        }
+        if let Some(third_party) = self.third_party {
+            if request.is_third_party() != third_party {
+                return false;
+            }
+        }
+        if !matches_domains(&self.domains, request.page) {
+            return false;
+        }
+
+        let text = if self.match_case {
+            request.url.as_str().as_bytes()
+        } else {
+            request.lowercase.as_bytes()
+        };
+        let pattern = self.pattern.as_bytes();
+        let matches_at = |i: usize| glob(pattern, &text[i..], self.end_anchor);
+        match self.anchor {
+            Anchor::None => (0..=text.len()).any(matches_at),
+            Anchor::Start => matches_at(0),
+            Anchor::Domain => label_starts(request.url).any(matches_at),
+        }
+    }
+}
+
+/// A rule hiding the elements matched by a CSS selector
+#[derive(Clone, Debug)]
+struct HidingRule {
+    domains: Vec<(String, bool)>,
+    selector: String,
+}
+
+/// A resource being checked against the rules
+struct Request<'u> {
+    url: &'u Url,
+    /// The URL in lowercase, for rules which don't match case
+    lowercase: String,
+    kind: u16,
+    page: &'u Url,
+}
+
+impl<'u> Request<'u> {
+    fn new(resource_url: &'u ResourceUrl, page: &'u Url) -> Self {
+        let url = resource_url.url();
+        Self {
+            url,
+            lowercase: url.as_str().to_ascii_lowercase(),
+            kind: kind_bit(resource_url),
+            page,
+        }
+    }
+
+    fn is_third_party(&self) -> bool {
+        let site = |url: &Url| {
+            let host = url.host_str().unwrap_or("").to_ascii_lowercase();
+            match host.rmatch_indices('.').nth(1) {
+                Some((i, _)) => host[i + 1..].to_string(),
+                None => host,
+            }
+        };
+        site(self.url) != site(self.page)
+    }
+}
+
+/// The bit representing a kind of resource in a rule's mask
+fn kind_bit(resource_url: &ResourceUrl) -> u16 {
+    use ResourceUrl::*;
+
+    match resource_url {
+        Javascript(_) => 1,
+        Css(_) => 1 << 1,
+        Image(_) => 1 << 2,
+        Object(_) => 1 << 3,
+        Font(_) => 1 << 4,
+        Media(_) => 1 << 5,
+        Document(_) => 1 << 6,
+        Attachment(_) | Other(_) => 1 << 7,
+    }
+}
+
+/// The kinds of resource named by a rule option, which are empty for
+/// requests that are never made while archiving. Options which aren't
+/// supported give `None`.
+fn option_kinds(name: &str) -> Option<u16> {
+    let kinds = match name {
+        "script" => 1,
+        "stylesheet" | "css" => 1 << 1,
+        "image" => 1 << 2,
+        "object" => 1 << 3,
+        "font" => 1 << 4,
+        "media" => 1 << 5,
+        "subdocument" | "frame" => 1 << 6,
+        "other" => 1 << 7,
+        "all" => ALL_KINDS,
+        "xmlhttprequest" | "xhr" | "ping" | "websocket" | "webrtc"
+        | "popup" | "document" | "beacon" => 0,
+        _ => return None,
+    };
+    Some(kinds)
+}
+
+/// Parse a list of domains, where those starting with `~` are excluded
+fn parse_domains(domains: &str, separator: char) -> Vec<(String, bool)> {
+    domains
+        .split(separator)
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| match d.strip_prefix('~') {
+            Some(d) => (d.to_ascii_lowercase(), false),
+            None => (d.to_ascii_lowercase(), true),
+        })
+        .collect()
+}
+
+/// Whether the page is on one of the included domains, or any domain if
+/// none are included, and isn't on an excluded one
+fn matches_domains(domains: &[(String, bool)], page: &Url) -> bool {
+    let host = page.host_str().unwrap_or("").to_ascii_lowercase();
+    let on = |domain: &str| match host.strip_suffix(domain) {
+        Some(rest) => rest.is_empty() || rest.ends_with('.'),
+        None => false,
+    };
+    if domains.iter().any(|(d, included)| !included && on(d)) {
+        return false;
+    }
+    let mut included = domains.iter().filter(|(_, included)| *included);
+    domains.iter().all(|(_, included)| !included)
+        || included.any(|(d, _)| on(d))
+}
+
+/// Offsets into the URL of the start of the host name and each of its
+/// labels, where `||` rules may start matching
+fn label_starts(url: &Url) -> impl Iterator<Item = usize> + '_ {
+    let start = url[..Position::BeforeHost].len();
+    let host = url.host_str().unwrap_or("");
+    std::iter::once(start)
+        .chain(host.match_indices('.').map(move |(i, _)| start + i + 1))
+}
+
+/// Whether `pattern` matches the start of `text`, or all of it if
+/// `to_end` is set
+fn glob(pattern: &[u8], text: &[u8], to_end: bool) -> bool {
+    match pattern.split_first() {
+        None => !to_end || text.is_empty(),
+        Some((b'*', rest)) => {
+            (0..=text.len()).any(|i| glob(rest, &text[i..], to_end))
+        }
+        Some((b'^', rest)) => match text.split_first() {
+            None => glob(rest, text, to_end),
+            Some((c, text)) => is_separator(*c) && glob(rest, text, to_end),
+        },
+        Some((p, rest)) => match text.split_first() {
+            Some((c, text)) => c == p && glob(rest, text, to_end),
+            None => false,
+        },
+    }
+}
+
+/// The tokens of a URL, i.e. its runs of [token](is_token) characters
+fn tokens(url: &str) -> impl Iterator<Item = &str> {
+    url.split(|c: char| !c.is_ascii() || !is_token(c as u8))
+        .filter(|token| !token.is_empty())
+}
+
+/// Whether a character is part of a token, for indexing rules. Anything
+/// matched by `^` isn't.
+fn is_token(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'%'
+}
+
+/// Whether a character is matched by `^`
+fn is_separator(c: u8) -> bool {
+    !(c.is_ascii_alphanumeric() || b"_-.%".contains(&c))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn url(u: &str) -> Url {
+        Url::parse(u).unwrap()
+    }
+
+    #[test]
+    fn test_network_rules() {
+        let list = FilterList::parse(
+            "[Adblock Plus 2.0]
+! A comment
+||ads.example.com^
+/banner/*.gif|
+|http://tracker.
+||cdn.example.net/ads/$script,third-party
+@@||ads.example.com/allowed/
+||fonts.example.net^$~font
+/sponsor$domain=news.example.org|~sports.news.example.org
+/*.js$xmlhttprequest",
+        );
+        let page = url("http://news.example.org/");
+        let blocks = |resource_url| list.blocks(&resource_url, &page);
+
+        assert!(blocks(ResourceUrl::Image(url("http://ads.example.com/a"))));
+        assert!(blocks(ResourceUrl::Image(url(
+            "https://x.ads.example.com/"
+        ))));
+        assert!(!blocks(ResourceUrl::Image(url(
+            "http://myads.example.com/"
+        ))));
+        assert!(!blocks(ResourceUrl::Image(url(
+            "http://ads.example.com/allowed/a.png"
+        ))));
+        assert!(blocks(ResourceUrl::Image(url("http://x.org/banner/a.gif"))));
+        assert!(!blocks(ResourceUrl::Image(url(
+            "http://x.org/banner/a.gif?v=1"
+        ))));
+        assert!(blocks(ResourceUrl::Image(url("http://tracker.x.org/"))));
+        assert!(!blocks(ResourceUrl::Image(url("https://tracker.x.org/"))));
+
+        // Resource types and third parties
+        let ad = url("http://cdn.example.net/ads/app.js");
+        assert!(blocks(ResourceUrl::Javascript(ad.clone())));
+        assert!(!blocks(ResourceUrl::Css(ad.clone())));
+        let first_party = url("http://static.example.net/");
+        assert!(!list.blocks(&ResourceUrl::Javascript(ad), &first_party));
+        let font = url("http://fonts.example.net/a.woff2");
+        assert!(!blocks(ResourceUrl::Font(font.clone())));
+        assert!(blocks(ResourceUrl::Css(font)));
+
+        // Page domains
+        let sponsor = ResourceUrl::Image(url("http://x.org/sponsor.png"));
+        assert!(list.blocks(&sponsor, &page));
+        let sports = url("http://sports.news.example.org/");
+        assert!(!list.blocks(&sponsor, &sports));
+
+        // Requests which aren't made while archiving
+        assert!(!blocks(ResourceUrl::Javascript(url("http://x.org/a.js"))));
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tokens = |rule| {
+            let rule = NetworkRule::parse(rule).unwrap();
+            rule.tokens()
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tokens("||ads.example.com^"), vec!["ads", "example", "com"]);
+        // `ads` could be the end of a longer token, `banner` the start of
+        // one, and `gif` too without the `|`
+        assert!(tokens("ads/banner*.gif").is_empty());
+        assert_eq!(tokens("/ads/banner*.gif|"), vec!["ads", "gif"]);
+        assert_eq!(tokens("|http://tracker."), vec!["http", "tracker"]);
+
+        let text = "http://ads.example.com/a%20b.png?x=1";
+        assert_eq!(
+            super::tokens(text).collect::<Vec<_>>(),
+            vec!["http", "ads", "example", "com", "a%20b", "png", "x", "1"]
+        );
+
+        let list = FilterList::parse("||ads.example.com^\n||x.org^\nads");
+        assert_eq!(list.blocking.by_token["example"].len(), 1);
+        assert_eq!(list.blocking.by_token["org"].len(), 1);
+        assert_eq!(list.blocking.untokened.len(), 1);
+        let page = url("http://example.org/");
+        let loads = ResourceUrl::Image(url("http://uploads.example.net/"));
+        assert!(list.blocks(&loads, &page));
+    }
+
+    #[test]
+    fn test_hiding_rules() {
+        let list = FilterList::parse(
+            "##.ad-banner
+example.com,~blog.example.com##.sponsored
+example.com#@#.ad-banner
+example.com#?#div:-abp-has(.ad)",
+        );
+        let selectors = |page| {
+            let page = url(page);
+            list.hidden_selectors(&page)
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            selectors("http://www.example.com/"),
+            vec![".ad-banner", ".sponsored"]
+        );
+        assert_eq!(selectors("http://blog.example.com/"), vec![".ad-banner"]);
+        assert_eq!(selectors("http://example.org/"), vec![".ad-banner"]);
+    }
+
+    #[test]
+    fn test_blocks_element() {
+        let list = FilterList::parse("||ads.example.com^");
+        let base = url("http://example.com/");
+        let attribute = |u: &'static str| move |_: &str| Some(u.to_string());
+        assert!(list.blocks_element(
+            "script",
+            attribute("//ads.example.com/ad.js"),
+            &base
+        ));
+        assert!(!list.blocks_element("script", attribute("/app.js"), &base));
+        assert!(!list.blocks_element(
+            "a",
+            attribute("http://ads.example.com/"),
+            &base
+        ));
+    }
+}
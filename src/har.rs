@@ -0,0 +1,317 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for replaying pages from HAR files, see [`read_har`]
+
+use crate::failure::FailureReason;
+use crate::meta::ResourceMeta;
+use crate::page_archive::PageArchive;
+use crate::replay::{parse_date, replay, Record, Records};
+use crate::{ArchiveOptions, Error};
+use bytes::Bytes;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, LOCATION,
+};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::io::Read;
+use url::Url;
+
+/// Read a page and its resources from a HAR file, such as one saved from
+/// the network panel of a browser's developer tools, as though it were
+/// being archived from the network. This makes it possible to archive
+/// pages behind a login, or which build themselves with JavaScript, by
+/// browsing to them and recording the session.
+///
+/// The page is the response in the file for the `target` URL, or if
+/// that's `None` the first HTML response in it. Its resources are found
+/// in the same way as when archiving, following the `options`, and looked
+/// up in the file instead of being downloaded. Nothing is requested from
+/// the network: resources which aren't in the file are reported in the
+/// archive's [`failures`](PageArchive::failures) as
+/// [`FailureReason::NotInHar`], and left pointing at their original URLs.
+///
+/// Only `GET` requests are used, with redirects followed to their
+/// targets, and if a URL was requested more than once the first response
+/// is used. Responses are only in the file if it was saved with their
+/// content, e.g. with "Save all as HAR with content" in Chrome. The
+/// [`meta`](PageArchive::meta) of each resource has the time that it was
+/// requested.
+///
+/// Fails with [`Error::ParseError`] if the file isn't a HAR file, and with
+/// [`Error::NotInHar`] if the page isn't in it.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::fs::File;
+/// use web_archive::read_har;
+///
+/// let file = File::open("example.har").unwrap();
+/// let archive =
+///     read_har(file, Some("http://example.com/"), Default::default())
+///         .unwrap();
+/// println!("{}", archive.embed_resources());
+/// ```
+pub fn read_har<R: Read>(
+    reader: R,
+    target: Option<&str>,
+    options: ArchiveOptions<'_>,
+) -> Result<PageArchive, Error> {
+    let target = target
+        .map(Url::parse)
+        .transpose()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let har: Value = serde_json::from_reader(reader)
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let records = read_records(&har)?;
+    let target = match target.or_else(|| records.first_page.clone()) {
+        Some(target) => target,
+        None => {
+            let error = "no HTML response in the HAR file".to_string();
+            return Err(Error::NotInHar(error));
+        }
+    };
+    replay(&records, target, &options, Error::NotInHar)
+}
+
+/// Read the responses to every `GET` request in a HAR file
+fn read_records(har: &Value) -> Result<Records, Error> {
+    let entries = har["log"]["entries"].as_array().ok_or_else(|| {
+        Error::ParseError("expected a HAR file with log entries".to_string())
+    })?;
+    let mut records = Records::new(FailureReason::NotInHar);
+    for entry in entries {
+        let request = &entry["request"];
+        let method = request["method"].as_str().unwrap_or("GET");
+        if !method.eq_ignore_ascii_case("GET") {
+            continue;
+        }
+        let url = match request["url"].as_str().map(Url::parse) {
+            Some(Ok(url)) => url,
+            _ => continue,
+        };
+        if let Some(mut record) = parse_response(&url, &entry["response"]) {
+            if let Some(started) =
+                entry["startedDateTime"].as_str().and_then(parse_date)
+            {
+                record.meta.fetched_at = started;
+            }
+            records.insert(url, record);
+        }
+    }
+    Ok(records)
+}
+
+/// Parse the `response` of an entry. `None` if the request failed, or if
+/// the content of a successful response wasn't saved.
+fn parse_response(url: &Url, response: &Value) -> Option<Record> {
+    let status = response["status"].as_u64()?;
+    let status = StatusCode::from_u16(status as u16).ok()?;
+
+    let mut headers = HeaderMap::new();
+    for header in response["headers"].as_array().into_iter().flatten() {
+        let name = header["name"].as_str().map(str::as_bytes);
+        let name = name.and_then(|name| HeaderName::from_bytes(name).ok());
+        let value = header["value"].as_str();
+        let value = value.and_then(|value| HeaderValue::from_str(value).ok());
+        if let (Some(name), Some(value)) = (name, value) {
+            headers.append(name, value);
+        }
+    }
+
+    let content = &response["content"];
+    let text = content["text"].as_str();
+    if text.is_none() && status.is_success() {
+        return None;
+    }
+    let base64 = content["encoding"].as_str() == Some("base64");
+    let data = match text {
+        Some(text) if base64 => base64::decode(text).ok()?,
+        Some(text) => text.as_bytes().to_vec(),
+        None => Vec::new(),
+    };
+
+    // Browsers save text that they've decoded already, so it's UTF-8
+    // whatever the response said
+    let mimetype = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| content["mimeType"].as_str())
+        .and_then(|t| t.split(';').next())
+        .map(str::trim)
+        .filter(|mimetype| !mimetype.is_empty());
+    let content_type = match mimetype {
+        Some(mimetype) if text.is_some() && !base64 => {
+            HeaderValue::from_str(&format!("{}; charset=utf-8", mimetype)).ok()
+        }
+        Some(_) if headers.contains_key(CONTENT_TYPE) => None,
+        Some(mimetype) => HeaderValue::from_str(mimetype).ok(),
+        None => None,
+    };
+    if let Some(content_type) = content_type {
+        headers.insert(CONTENT_TYPE, content_type);
+    }
+
+    let location = response["redirectURL"]
+        .as_str()
+        .filter(|location| !location.is_empty())
+        .or_else(|| headers.get(LOCATION).and_then(|value| value.to_str().ok()))
+        .map(str::to_string);
+    Some(Record {
+        status,
+        location,
+        meta: ResourceMeta::new(status, url, &headers),
+        data: Bytes::from(data),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::Resource;
+    use serde_json::json;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(method: &str, url: &str, response: Value) -> Value {
+        json!({
+            "startedDateTime": "2021-02-03T05:05:06.000+01:00",
+            "request": { "method": method, "url": url, "headers": [] },
+            "response": response,
+        })
+    }
+
+    fn har() -> Vec<u8> {
+        let entries = vec![
+            entry(
+                "GET",
+                "http://example.com/",
+                json!({
+                    "status": 301,
+                    "headers": [],
+                    "content": { "size": 0, "mimeType": "" },
+                    "redirectURL": "/index.html",
+                }),
+            ),
+            entry(
+                "GET",
+                "http://example.com/index.html",
+                json!({
+                    "status": 200,
+                    "headers": [{
+                        "name": "Content-Type",
+                        "value": "text/html; charset=Shift_JIS",
+                    }],
+                    "content": {
+                        "mimeType": "text/html",
+                        "text": "<html><head>\
+                            <link rel=\"stylesheet\" href=\"style.css\">\
+                            </head><body>\u{65e5}\u{672c}\
+                            <img src=\"image.png\">\
+                            <img src=\"missing.png\">\
+                            <script src=\"app.js\"></script>\
+                            </body></html>",
+                    },
+                }),
+            ),
+            entry(
+                "GET",
+                "http://example.com/style.css",
+                json!({
+                    "status": 200,
+                    "headers": [],
+                    "content": { "mimeType": "text/css", "text": "a {}" },
+                }),
+            ),
+            entry(
+                "GET",
+                "http://example.com/image.png",
+                json!({
+                    "status": 200,
+                    "headers": [{ "name": "Content-Type", "value": "image/png" }],
+                    "content": {
+                        "mimeType": "image/png",
+                        "text": base64::encode(b"png"),
+                        "encoding": "base64",
+                    },
+                }),
+            ),
+            // Saved without its content
+            entry(
+                "GET",
+                "http://example.com/app.js",
+                json!({
+                    "status": 200,
+                    "headers": [],
+                    "content": { "mimeType": "application/javascript" },
+                }),
+            ),
+            entry(
+                "POST",
+                "http://example.com/missing.png",
+                json!({
+                    "status": 200,
+                    "headers": [],
+                    "content": { "mimeType": "image/png", "text": "" },
+                }),
+            ),
+        ];
+        serde_json::to_vec(&json!({ "log": { "entries": entries } })).unwrap()
+    }
+
+    #[test]
+    fn test_read_har() {
+        let archive = read_har(&har()[..], None, Default::default()).unwrap();
+        assert_eq!(archive.url.as_str(), "http://example.com/index.html");
+        assert!(archive.content.contains("\u{65e5}\u{672c}"));
+
+        let url = |u| Url::parse(u).unwrap();
+        assert!(matches!(
+            archive
+                .resource_map
+                .get(&url("http://example.com/style.css")),
+            Some(Resource::Css(_))
+        ));
+        let image = &archive.resource_map[&url("http://example.com/image.png")];
+        assert_eq!(image.data(), "png".as_bytes());
+        assert_eq!(image.mimetype(), "image/png");
+        assert_eq!(
+            archive.meta[&archive.url].fetched_at,
+            UNIX_EPOCH + Duration::from_secs(1_612_325_106)
+        );
+
+        let mut failed: Vec<_> = archive
+            .failures
+            .iter()
+            .map(|failure| (failure.url.as_str(), &failure.reason))
+            .collect();
+        failed.sort_by_key(|(url, _)| *url);
+        assert_eq!(
+            failed,
+            vec![
+                ("http://example.com/app.js", &FailureReason::NotInHar),
+                ("http://example.com/missing.png", &FailureReason::NotInHar),
+            ]
+        );
+
+        let archive = read_har(
+            &har()[..],
+            Some("http://example.com/"),
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(archive.url.as_str(), "http://example.com/index.html");
+        let missing = read_har(
+            &har()[..],
+            Some("http://example.com/other"),
+            Default::default(),
+        );
+        assert!(matches!(missing, Err(Error::NotInHar(_))));
+        let invalid = read_har(&b"{}"[..], None, Default::default());
+        assert!(matches!(invalid, Err(Error::ParseError(_))));
+    }
+}
@@ -0,0 +1,1245 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for archiving pages as a browser renders them, see
+//! [`archive_rendered`]
+
+use crate::client::{self, RedirectLog, Requests};
+use crate::digest;
+use crate::dom;
+use crate::download::Downloader;
+use crate::error::Error;
+use crate::event::ArchiveEvent;
+use crate::failure::FailureReason;
+use crate::meta::ResourceMeta;
+use crate::mimetype::{self, OCTET_STREAM};
+use crate::page_archive::PageArchive;
+use crate::parsing::{parse_resource_urls, Resource};
+use crate::replay::{Record, Records};
+use crate::{build_client, download_resources, ArchiveOptions};
+use bytes::Bytes;
+use chromiumoxide::cdp::browser_protocol::network::{
+    CookieParam, EnableParams, EventLoadingFailed, EventLoadingFinished,
+    EventRequestWillBeSent, EventResponseReceived, GetResponseBodyParams,
+    Headers, RequestId, ResourceType, SetCookiesParams,
+    SetExtraHttpHeadersParams,
+};
+use chromiumoxide::cdp::browser_protocol::page::CaptureScreenshotFormat;
+use chromiumoxide::page::ScreenshotParams;
+use chromiumoxide::{Browser, BrowserConfig, Page};
+use futures::stream::{self, BoxStream, StreamExt};
+use futures::FutureExt;
+use html5ever::{interface::QualName, local_name, namespace_url, ns};
+use kuchiki::traits::TendrilSink;
+use kuchiki::NodeRef;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_LANGUAGE, LOCATION,
+};
+use reqwest::StatusCode;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// Waits for the page to finish loading, including its fonts, so that
+/// nothing is missing when it's printed or a screenshot is taken
+pub(crate) const LOADED: &str = "new Promise((resolve) => {
+    if (document.readyState === 'complete') resolve();
+    else window.addEventListener('load', resolve);
+}).then(() => document.fonts.ready).then(() => true)";
+
+/// How often to look for [`RenderOptions::selector`] in the page
+const SELECTOR_POLL: Duration = Duration::from_millis(100);
+
+/// The script added by [`RenderOptions::api_shim`], with `RESPONSES`
+/// standing in for the kept responses. Those are an object keyed by URL,
+/// with the status, content type and base64 encoded body of each.
+const API_SHIM: &str = r#"(function () {
+  var responses = RESPONSES;
+  function find(method, url) {
+    if (String(method || "GET").toUpperCase() !== "GET") return null;
+    try {
+      url = new URL(url, document.baseURI);
+    } catch (e) {
+      return null;
+    }
+    url.hash = "";
+    return responses[url.href] || null;
+  }
+  function dataUrl(response) {
+    return "data:" + response.type + ";base64," + response.body;
+  }
+  var fetch = window.fetch;
+  if (fetch) {
+    window.fetch = function (input, init) {
+      var request = typeof input === "object" && input.url ? input : null;
+      var method = (init && init.method) || (request && request.method);
+      var response = find(method, request ? request.url : input);
+      if (!response) return fetch.apply(this, arguments);
+      return fetch(dataUrl(response)).then(function (data) {
+        var empty = [101, 204, 205, 304].indexOf(response.status) >= 0;
+        return new Response(empty ? null : data.body, {
+          status: response.status,
+          headers: { "Content-Type": response.type },
+        });
+      });
+    };
+  }
+  var open = XMLHttpRequest.prototype.open;
+  XMLHttpRequest.prototype.open = function (method, url) {
+    var response = find(method, url);
+    var args = Array.prototype.slice.call(arguments);
+    if (response) args[1] = dataUrl(response);
+    return open.apply(this, args);
+  };
+})();"#;
+
+/// Configuration options for [`archive_rendered`], controlling the
+/// browser and how long to let the page render for
+pub struct RenderOptions<'a> {
+    /// The options used to archive the page. Its `user_agent`, `headers`,
+    /// `accept_language`, `cookies`, `tor`, `proxy`, `resolve` and
+    /// `accept_invalid_certificates` are passed on to the browser, and
+    /// the rest apply to the resources as they would for
+    /// [`archive`](crate::archive). The browser can't be given `auth`,
+    /// `host_headers`, `root_certificates` or a `client_identity`, so
+    /// setting any of them fails with [`Error::Browser`]. Through Tor, the
+    /// browser's requests share circuits with any others that don't ask
+    /// for circuits of their own.
+    ///
+    /// Default: [`ArchiveOptions::default`]
+    pub archive: ArchiveOptions<'a>,
+    /// A CSS selector to wait for an element matching, e.g. the content
+    /// that a single-page app renders, instead of waiting for the network
+    /// to be idle
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::RenderOptions;
+    /// let options = RenderOptions {
+    ///     selector: Some("#app article"),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub selector: Option<&'a str>,
+    /// How long the page has to go without a request in flight for the
+    /// network to count as idle, once it has loaded
+    ///
+    /// Default: 500 milliseconds
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use web_archive::RenderOptions;
+    /// let options = RenderOptions {
+    ///     idle: Duration::from_secs(2),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub idle: Duration,
+    /// The longest to wait for the page to render, after which it's
+    /// archived as it is. Requests which are still in flight then, such
+    /// as long polls, aren't recorded.
+    ///
+    /// Default: 30 seconds
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use web_archive::RenderOptions;
+    /// let options = RenderOptions {
+    ///     timeout: Duration::from_secs(10),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub timeout: Duration,
+    /// Path of the Chrome or Chromium executable to run, or `None` to look
+    /// for one in the usual places
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use std::path::Path;
+    /// use web_archive::RenderOptions;
+    /// let options = RenderOptions {
+    ///     browser: Some(Path::new("/usr/bin/chromium")),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub browser: Option<&'a Path>,
+    /// Whether to keep the responses to the `GET` requests which the page
+    /// made with `fetch` or `XMLHttpRequest`, such as calls to an API, in
+    /// the archive's resource map, so that they can be served alongside
+    /// the page. Responses which weren't successful aren't kept.
+    ///
+    /// Default: `true`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::RenderOptions;
+    /// let options = RenderOptions {
+    ///     capture_api: false,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub capture_api: bool,
+    /// Whether to add a script to the start of the page which answers the
+    /// page's `fetch` and `XMLHttpRequest` calls with the responses kept
+    /// by [`capture_api`](#structfield.capture_api), so that a page which
+    /// loads its content from an API can still show it when it's opened
+    /// offline. Calls for anything else go to the network as usual. Has
+    /// no effect unless `capture_api` is set.
+    ///
+    /// Default: `false`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::RenderOptions;
+    /// let options = RenderOptions {
+    ///     api_shim: true,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub api_shim: bool,
+}
+
+impl<'a> RenderOptions<'a> {
+    /// Archive the page with the options, see
+    /// [`RenderOptions::archive`](#structfield.archive)
+    pub fn archive(mut self, options: ArchiveOptions<'a>) -> Self {
+        self.archive = options;
+        self
+    }
+
+    /// Wait for an element matching `selector`, see
+    /// [`RenderOptions::selector`](#structfield.selector)
+    pub fn selector(mut self, selector: &'a str) -> Self {
+        self.selector = Some(selector);
+        self
+    }
+
+    /// Set how long the network has to be idle for, see
+    /// [`RenderOptions::idle`](#structfield.idle)
+    pub fn idle(mut self, idle: Duration) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    /// Set the longest to wait for the page to render, see
+    /// [`RenderOptions::timeout`](#structfield.timeout)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run the browser at `path`, see
+    /// [`RenderOptions::browser`](#structfield.browser)
+    pub fn browser(mut self, path: &'a Path) -> Self {
+        self.browser = Some(path);
+        self
+    }
+
+    /// Keep the page's API responses, see
+    /// [`RenderOptions::capture_api`](#structfield.capture_api)
+    pub fn capture_api(mut self, capture: bool) -> Self {
+        self.capture_api = capture;
+        self
+    }
+
+    /// Answer the page's API calls from the archive, see
+    /// [`RenderOptions::api_shim`](#structfield.api_shim)
+    pub fn api_shim(mut self, shim: bool) -> Self {
+        self.api_shim = shim;
+        self
+    }
+}
+
+impl<'a> Default for RenderOptions<'a> {
+    fn default() -> Self {
+        Self {
+            archive: ArchiveOptions::default(),
+            selector: None,
+            idle: Duration::from_millis(500),
+            timeout: Duration::from_secs(30),
+            browser: None,
+            capture_api: true,
+            api_shim: false,
+        }
+    }
+}
+
+/// How much of the page to take a screenshot of, see
+/// [`ArchiveOptions::screenshot`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Screenshot {
+    /// Only what fits in the browser's window
+    Viewport,
+    /// The whole page, however far down it goes
+    FullPage,
+}
+
+/// Archive the page at `url` as a headless browser renders it, for pages
+/// which build their content with JavaScript and would otherwise be
+/// archived as an empty shell.
+///
+/// The page is loaded in Chrome or Chromium, which has to be installed,
+/// and given time to render: until the network has been idle for
+/// [`idle`](RenderOptions::idle), or an element matching the
+/// [`selector`](RenderOptions::selector) appears, or the
+/// [`timeout`](RenderOptions::timeout) passes. The rendered DOM is then
+/// archived in place of the HTML that the server sent. Its resources are
+/// taken from the responses that the browser received while loading the
+/// page, and those it didn't request, such as images that are only
+/// loaded once they're scrolled to, are downloaded as usual. The
+/// responses to the page's own API calls are kept too, see
+/// [`capture_api`](RenderOptions::capture_api), and can be answered from
+/// the archive when it's opened offline, see
+/// [`api_shim`](RenderOptions::api_shim).
+///
+/// Fails with [`Error::Browser`] if the browser can't be started or the
+/// page can't be loaded in it, or the options can't be given to it, see
+/// [`RenderOptions::archive`].
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{archive_rendered, RenderOptions};
+///
+/// # async fn archive_rendered_async() {
+/// let options = RenderOptions::default().selector("#app article");
+/// let archive = archive_rendered("http://example.com/app", options)
+///     .await
+///     .unwrap();
+/// println!("{}", archive.embed_resources());
+/// # }
+/// ```
+pub async fn archive_rendered<U>(
+    url: U,
+    options: RenderOptions<'_>,
+) -> Result<PageArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    if !options.archive.allows_scheme(&url) {
+        return Err(Error::DisallowedScheme(url.to_string()));
+    }
+    let started = Instant::now();
+    let (browser, handling) = launch(&options.archive, options.browser).await?;
+    let rendered = render(&browser, &url, &options).await;
+    close(browser, handling).await;
+    let rendered = rendered?;
+
+    let redirects = RedirectLog::default();
+    let client = build_client(&options.archive, &redirects)?;
+    let requests = Requests::new(
+        url,
+        &options.archive,
+        &redirects,
+        options.archive.http_cache,
+    );
+    let records = &rendered.records;
+    let mut archive = archive_recorded(
+        &client,
+        &options.archive,
+        &requests,
+        rendered.content,
+        records,
+        started,
+    )
+    .await?;
+    if options.capture_api {
+        keep_api_responses(
+            &mut archive,
+            records,
+            &rendered.api,
+            options.api_shim,
+        );
+        // The responses are new, and the shim changes the page
+        digest::record(&mut archive);
+    }
+    archive.screenshot = rendered.screenshot;
+    Ok(archive)
+}
+
+/// Take the screenshot of the `archive`'s page that the `options` ask
+/// for, if any, by loading it in a browser with its resources embedded, so
+/// that it shows what was archived rather than the page as it is now
+pub(crate) async fn with_screenshot(
+    mut archive: PageArchive,
+    options: &ArchiveOptions<'_>,
+) -> Result<PageArchive, Error> {
+    let area = match options.screenshot {
+        Some(area) => area,
+        None => return Ok(archive),
+    };
+    let (browser, handling) = launch(options, None).await?;
+    let taken = async {
+        let page = open(&browser, options).await?;
+        page.set_content(archive.embed_resources())
+            .await
+            .map_err(browser_error)?;
+        page.evaluate_expression(LOADED)
+            .await
+            .map_err(browser_error)?;
+        screenshot(&page, area).await
+    }
+    .await;
+    close(browser, handling).await;
+    archive.screenshot = Some(taken?);
+    Ok(archive)
+}
+
+/// Start the browser at `path`, or wherever one can be found, along with
+/// the task which handles its events. Fails with [`Error::Browser`] if the
+/// `options` ask for something the browser can't be given, see
+/// [`browser_args`].
+pub(crate) async fn launch(
+    options: &ArchiveOptions<'_>,
+    path: Option<&Path>,
+) -> Result<(Browser, JoinHandle<()>), Error> {
+    let mut config = BrowserConfig::builder();
+    if let Some(path) = path {
+        config = config.chrome_executable(path);
+    }
+    if !options.accept_invalid_certificates {
+        config = config.respect_https_errors();
+    }
+    for arg in browser_args(options)? {
+        config = config.arg(arg);
+    }
+    let config = config.build().map_err(Error::Browser)?;
+    let (browser, mut handler) =
+        Browser::launch(config).await.map_err(browser_error)?;
+    let handling = tokio::spawn(async move {
+        while let Some(Ok(())) = handler.next().await {}
+    });
+    Ok((browser, handling))
+}
+
+/// The command line arguments which give the browser the `options`' Tor
+/// daemon or proxy, and the addresses to connect to hosts at. Credentials,
+/// certificates, and headers for particular hosts can't be given to it,
+/// so rather than loading pages without them this fails with
+/// [`Error::Browser`].
+fn browser_args(options: &ArchiveOptions<'_>) -> Result<Vec<String>, Error> {
+    let unsupported = [
+        ("auth", options.auth.is_some()),
+        ("host_headers", !options.host_headers.is_empty()),
+        ("root_certificates", !options.root_certificates.is_empty()),
+        ("client_identity", options.client_identity.is_some()),
+    ];
+    if let Some((name, _)) = unsupported.iter().find(|(_, set)| *set) {
+        let error = format!("{} can't be given to the browser", name);
+        return Err(Error::Browser(error));
+    }
+
+    #[cfg(feature = "tor")]
+    {
+        if let Some(address) = options.tor {
+            // Chromium resolves host names through SOCKS5 proxies. Make
+            // sure nothing else is looked up, and keep WebRTC off the
+            // network, so that neither gives away where the browser is.
+            let host = address.rsplit_once(':').map_or(address, |(h, _)| h);
+            return Ok(vec![
+                format!("--proxy-server=socks5://{}", address),
+                format!(
+                    "--host-resolver-rules=MAP * ~NOTFOUND , EXCLUDE {}",
+                    host
+                ),
+                "--force-webrtc-ip-handling-policy=disable_non_proxied_udp"
+                    .to_string(),
+            ]);
+        }
+    }
+    if let Some(proxy) = options.proxy {
+        // Chromium always resolves host names through SOCKS5 proxies, and
+        // doesn't know the `socks5h` scheme that asks reqwest to
+        let proxy = proxy.replacen("socks5h://", "socks5://", 1);
+        // Addresses to resolve hosts to don't apply through a proxy
+        return Ok(vec![format!("--proxy-server={}", proxy)]);
+    }
+    let rules = client::resolve(options)?
+        .into_iter()
+        .map(|(host, address)| match address.ip() {
+            IpAddr::V4(ip) => format!("MAP {} {}", host, ip),
+            IpAddr::V6(ip) => format!("MAP {} [{}]", host, ip),
+        })
+        .collect::<Vec<_>>();
+    if rules.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(vec![format!("--host-resolver-rules={}", rules.join(", "))])
+}
+
+/// Close a browser started by [`launch`]. Everything has been read from it
+/// by now, so there's nothing to be done if it doesn't close cleanly.
+pub(crate) async fn close(mut browser: Browser, handling: JoinHandle<()>) {
+    let _ = browser.close().await;
+    let _ = browser.wait().await;
+    handling.abort();
+}
+
+/// Open a blank page in the browser, sending the options' user agent,
+/// headers, and cookies
+pub(crate) async fn open(
+    browser: &Browser,
+    options: &ArchiveOptions<'_>,
+) -> Result<Page, Error> {
+    let headers = extra_headers(options)?;
+    let cookies = cookies(options)?;
+    let page = browser
+        .new_page("about:blank")
+        .await
+        .map_err(browser_error)?;
+    page.execute(EnableParams::default())
+        .await
+        .map_err(browser_error)?;
+    if let Some(user_agent) = options.user_agent {
+        page.set_user_agent(user_agent)
+            .await
+            .map_err(browser_error)?;
+    }
+    if !headers.is_empty() {
+        let headers = Headers::new(Value::Object(headers));
+        page.execute(SetExtraHttpHeadersParams::new(headers))
+            .await
+            .map_err(browser_error)?;
+    }
+    if !cookies.is_empty() {
+        page.execute(SetCookiesParams::new(cookies))
+            .await
+            .map_err(browser_error)?;
+    }
+    Ok(page)
+}
+
+/// Headers for the browser to send with every request, from
+/// `options.headers` and `options.accept_language`
+fn extra_headers(
+    options: &ArchiveOptions<'_>,
+) -> Result<Map<String, Value>, Error> {
+    let mut headers = client::default_headers(options)?;
+    if let Some(language) = options.accept_language {
+        let value = HeaderValue::from_str(language).map_err(|e| {
+            Error::ParseError(format!("Invalid Accept-Language: {}", e))
+        })?;
+        headers.entry(ACCEPT_LANGUAGE).or_insert(value);
+    }
+    let mut extra = Map::new();
+    for name in headers.keys() {
+        let values = headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>();
+        extra.insert(name.to_string(), Value::from(values.join(", ")));
+    }
+    Ok(extra)
+}
+
+/// The browser's cookies to start off with, from `options.cookies`
+fn cookies(options: &ArchiveOptions<'_>) -> Result<Vec<CookieParam>, Error> {
+    options
+        .cookies
+        .iter()
+        .filter_map(|&(url, cookie)| {
+            if let Err(e) = Url::parse(url) {
+                let error = format!("Invalid cookie URL {}: {}", url, e);
+                return Some(Err(Error::ParseError(error)));
+            }
+            let mut parts = cookie.split(';').map(str::trim);
+            let (name, value) = parts.next()?.split_once('=')?;
+            let mut param = CookieParam::new(name.trim(), value.trim());
+            param.url = Some(url.to_string());
+            for part in parts {
+                let (attribute, value) =
+                    part.split_once('=').unwrap_or((part, ""));
+                match attribute.trim().to_ascii_lowercase().as_str() {
+                    "domain" => param.domain = Some(value.trim().to_string()),
+                    "path" => param.path = Some(value.trim().to_string()),
+                    "secure" => param.secure = Some(true),
+                    "httponly" => param.http_only = Some(true),
+                    _ => {}
+                }
+            }
+            Some(Ok(param))
+        })
+        .collect()
+}
+
+/// A PNG screenshot of the `area` of the page
+async fn screenshot(page: &Page, area: Screenshot) -> Result<Bytes, Error> {
+    let params = ScreenshotParams::builder()
+        .format(CaptureScreenshotFormat::Png)
+        .full_page(area == Screenshot::FullPage)
+        .build();
+    let png = page.screenshot(params).await.map_err(browser_error)?;
+    Ok(png.into())
+}
+
+/// A page as the browser rendered it, see [`render`]
+struct Rendered {
+    content: String,
+    /// The responses that the browser received while loading the page
+    records: Records,
+    /// The URLs of the page's API calls, see [`Capture::api`]
+    api: Vec<Url>,
+    /// The screenshot that [`ArchiveOptions::screenshot`] asks for
+    screenshot: Option<Bytes>,
+}
+
+/// Load the page at `url` in the browser, returning it once it has
+/// rendered
+async fn render(
+    browser: &Browser,
+    url: &Url,
+    options: &RenderOptions<'_>,
+) -> Result<Rendered, Error> {
+    let deadline = Instant::now() + options.timeout;
+    let page = open(browser, &options.archive).await?;
+    let mut events = listen(&page).await?;
+    page.goto(url.as_str()).await.map_err(browser_error)?;
+
+    let mut capture = Capture::default();
+    settle(&page, &mut events, &mut capture, options, deadline).await;
+    let content = page.content().await.map_err(browser_error)?;
+    let screenshot = match options.archive.screenshot {
+        Some(area) => Some(screenshot(&page, area).await?),
+        None => None,
+    };
+
+    let mut records = Records::new(FailureReason::NotRecorded);
+    for (url, record) in capture.redirects {
+        records.insert(url, record);
+    }
+    for (id, url, mut record) in capture.finished {
+        // Some responses have no body to get, such as those to preflight
+        // requests, which are of no use to the archive anyway
+        let body = GetResponseBodyParams::new(RequestId::new(id));
+        let body = match page.execute(body).await {
+            Ok(body) => body.result,
+            Err(_) => continue,
+        };
+        record.data = if body.base64_encoded {
+            match base64::decode(&body.body) {
+                Ok(data) => data.into(),
+                Err(_) => continue,
+            }
+        } else {
+            body.body.into()
+        };
+        records.insert(url, record);
+    }
+    Ok(Rendered {
+        content,
+        records,
+        api: capture.api,
+        screenshot,
+    })
+}
+
+/// A network event in the page
+enum Event {
+    Sent(Arc<EventRequestWillBeSent>),
+    Received(Arc<EventResponseReceived>),
+    Finished(Arc<EventLoadingFinished>),
+    Failed(Arc<EventLoadingFailed>),
+}
+
+/// The page's network events, as they happen
+async fn listen(page: &Page) -> Result<BoxStream<'static, Event>, Error> {
+    let sent = page.event_listener::<EventRequestWillBeSent>();
+    let sent = sent.await.map_err(browser_error)?.map(Event::Sent);
+    let received = page.event_listener::<EventResponseReceived>();
+    let received = received.await.map_err(browser_error)?;
+    let finished = page.event_listener::<EventLoadingFinished>();
+    let finished = finished.await.map_err(browser_error)?;
+    let failed = page.event_listener::<EventLoadingFailed>();
+    let failed = failed.await.map_err(browser_error)?;
+    Ok(stream::select_all(vec![
+        sent.boxed(),
+        received.map(Event::Received).boxed(),
+        finished.map(Event::Finished).boxed(),
+        failed.map(Event::Failed).boxed(),
+    ])
+    .boxed())
+}
+
+/// Record the page's network `events` until it has rendered, going by
+/// the `options`, or the `deadline` passes
+async fn settle(
+    page: &Page,
+    events: &mut BoxStream<'static, Event>,
+    capture: &mut Capture,
+    options: &RenderOptions<'_>,
+    deadline: Instant,
+) {
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        let left = deadline - now;
+        if let Some(selector) = options.selector {
+            while let Some(Some(event)) = events.next().now_or_never() {
+                capture.apply(event);
+            }
+            if page.find_element(selector).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(SELECTOR_POLL.min(left)).await;
+            continue;
+        }
+        let wait = if capture.is_idle() {
+            options.idle
+        } else {
+            left
+        };
+        match tokio::time::timeout(wait.min(left), events.next()).await {
+            Ok(Some(event)) => capture.apply(event),
+            Ok(None) => break,
+            Err(_) if capture.is_idle() => break,
+            Err(_) => {}
+        }
+    }
+    // Take in whatever else has happened, without waiting for more
+    while let Some(Some(event)) = events.next().now_or_never() {
+        capture.apply(event);
+    }
+}
+
+/// The responses that the browser received while loading the page, from
+/// its network events, without their bodies
+#[derive(Default)]
+struct Capture {
+    /// The requests in flight, with their responses once they've been
+    /// received, keyed by their IDs
+    pending: HashMap<String, (Url, Option<Record>)>,
+    /// The requests which have finished, with their IDs, in order
+    finished: Vec<(String, Url, Record)>,
+    /// The redirects which were followed, with the URLs they redirected
+    redirects: Vec<(Url, Record)>,
+    /// The URLs which the page requested with `fetch` or `XMLHttpRequest`
+    /// using `GET`, in order, not including the hops of redirects
+    api: Vec<Url>,
+}
+
+impl Capture {
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Sent(e) => {
+                let redirect = e.redirect_response.as_ref();
+                let redirect = redirect.map(|r| (r.status, r.headers.inner()));
+                let api = e.request.method == "GET"
+                    && matches!(
+                        e.r#type,
+                        Some(ResourceType::Xhr) | Some(ResourceType::Fetch)
+                    );
+                self.sent(e.request_id.inner(), &e.request.url, api, redirect)
+            }
+            Event::Received(e) => self.received(
+                e.request_id.inner(),
+                e.response.status,
+                e.response.headers.inner(),
+            ),
+            Event::Finished(e) => self.finished(e.request_id.inner()),
+            Event::Failed(e) => self.failed(e.request_id.inner()),
+        }
+    }
+
+    /// The request `id` was sent for `url`, following a `redirect` with
+    /// the status and headers given if it's a hop of one. `api` is whether
+    /// the page made it with `fetch` or `XMLHttpRequest` using `GET`.
+    fn sent(
+        &mut self,
+        id: &str,
+        url: &str,
+        api: bool,
+        redirect: Option<(i64, &Value)>,
+    ) {
+        let from = self.pending.remove(id).map(|(from, _)| from);
+        if let (Some(from), Some((status, headers))) = (from, redirect) {
+            if let Some(record) = record(&from, status, headers) {
+                self.redirects.push((from, record));
+            }
+        }
+        let url = match Url::parse(url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+            _ => return,
+        };
+        if api && redirect.is_none() {
+            self.api.push(url.clone());
+        }
+        self.pending.insert(id.to_string(), (url, None));
+    }
+
+    /// The response to the request `id` was received, without its body
+    fn received(&mut self, id: &str, status: i64, headers: &Value) {
+        if let Some((url, response)) = self.pending.get_mut(id) {
+            *response = record(url, status, headers);
+        }
+    }
+
+    /// The request `id` finished, and its body can be got
+    fn finished(&mut self, id: &str) {
+        if let Some((url, Some(record))) = self.pending.remove(id) {
+            self.finished.push((id.to_string(), url, record));
+        }
+    }
+
+    /// The request `id` failed
+    fn failed(&mut self, id: &str) {
+        self.pending.remove(id);
+    }
+
+    /// Whether there are no requests in flight
+    fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// A response from `url` without its body, from the `status` and
+/// `headers` that the browser reported
+fn record(url: &Url, status: i64, headers: &Value) -> Option<Record> {
+    let status = u16::try_from(status).ok()?;
+    let status = StatusCode::from_u16(status).ok()?;
+    let headers = header_map(headers);
+    let location = headers
+        .get(LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    Some(Record {
+        status,
+        location,
+        meta: ResourceMeta::new(status, url, &headers),
+        data: Bytes::new(),
+    })
+}
+
+/// Headers as the browser reports them, as an object of names and values,
+/// with the values of a header sent more than once on separate lines
+fn header_map(headers: &Value) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    let headers = headers.as_object().into_iter().flatten();
+    for (name, values) in headers {
+        let name = match HeaderName::from_bytes(name.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        for value in values.as_str().unwrap_or("").lines() {
+            if let Ok(value) = HeaderValue::from_str(value) {
+                map.append(name.clone(), value);
+            }
+        }
+    }
+    map
+}
+
+/// Archive the page which the browser rendered to `content`, taking its
+/// resources from the responses `recorded` while it did, and downloading
+/// those it didn't request
+async fn archive_recorded(
+    client: &reqwest::Client,
+    options: &ArchiveOptions<'_>,
+    requests: &Requests<'_>,
+    content: String,
+    recorded: &Records,
+    started: Instant,
+) -> Result<PageArchive, Error> {
+    let page = recorded.response(&requests.page).map_err(|reason| {
+        Error::Browser(format!("{}: {}", requests.page, reason))
+    })?;
+    let url = page.redirects.last().unwrap_or(&requests.page).clone();
+    options.emit(ArchiveEvent::PageFetched {
+        url: url.clone(),
+        bytes: content.len(),
+    });
+    let resource_urls = parse_resource_urls(&url, &content, options);
+    let mut downloader =
+        Downloader::new(url.clone(), resource_urls, options, None);
+    downloader.redirected(requests.page.clone(), page.redirects);
+    downloader.fetched(url, page.meta);
+    download_resources(
+        client,
+        options,
+        requests,
+        &mut downloader,
+        Some(recorded),
+    )
+    .await;
+    Ok(downloader.into_archive(content, started.elapsed()))
+}
+
+/// Add the successful responses to the page's API calls to `urls` to the
+/// `archive`'s resource map, as [`Resource::Other`], and add the script
+/// which answers the calls with them to its content if `shim` is set, see
+/// [`RenderOptions::api_shim`]
+fn keep_api_responses(
+    archive: &mut PageArchive,
+    records: &Records,
+    urls: &[Url],
+    shim: bool,
+) {
+    let mut responses = serde_json::Map::new();
+    for url in urls {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        let response = match records.response(&url) {
+            Ok(response) if !responses.contains_key(url.as_str()) => response,
+            _ => continue,
+        };
+        let key = response.redirects.last().unwrap_or(&url).clone();
+        let mimetype = mimetype::detect(
+            response.meta.content_type.as_deref(),
+            &key,
+            &response.data,
+            OCTET_STREAM,
+        );
+        responses.insert(
+            url.to_string(),
+            serde_json::json!({
+                "status": response.meta.status,
+                "type": mimetype,
+                "body": base64::encode(&response.data),
+            }),
+        );
+        if !archive.resource_map.contains_key(&key) {
+            let data = response.data;
+            archive
+                .resource_map
+                .insert(key.clone(), Resource::Other { data, mimetype });
+            archive.meta.insert(key, response.meta);
+        }
+        if !response.redirects.is_empty() {
+            archive.redirects.insert(url, response.redirects);
+        }
+    }
+    if shim && !responses.is_empty() {
+        // `<` only appears within strings in the JSON, where it's escaped
+        // so that nothing in the responses can end the script early
+        let responses = Value::Object(responses).to_string();
+        let responses = responses.replace('<', "\\u003c");
+        let script = API_SHIM.replace("RESPONSES", &responses);
+        archive.content = with_script(&archive.content, &script);
+    }
+}
+
+/// The page `content` with an inline `script` added to the start of its
+/// `<head>`, so that it runs before the page's own scripts
+fn with_script(content: &str, script: &str) -> String {
+    let document = kuchiki::parse_html().one(content);
+    let element = NodeRef::new_element(
+        QualName::new(None, ns!(html), local_name!("script")),
+        None,
+    );
+    element.append(NodeRef::new_text(script));
+    // The parser always adds a `<head>` if the page doesn't have one
+    if let Ok(head) = document.select_first("head") {
+        head.as_node().prepend(element);
+    }
+    dom::serialize(&document)
+}
+
+pub(crate) fn browser_error(e: chromiumoxide::error::CdpError) -> Error {
+    Error::Browser(e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::Resource;
+    use crate::Auth;
+    use serde_json::json;
+    use tokio_test::block_on;
+
+    #[test]
+    fn test_capture() {
+        let mut capture = Capture::default();
+        let html = json!({ "Content-Type": "text/html" });
+        capture.sent("1", "http://example.com/", false, None);
+        let redirect = json!({ "Location": "/app/" });
+        let redirect = Some((301, &redirect));
+        capture.sent("1", "http://example.com/app/", false, redirect);
+        capture.received("1", 200, &html);
+        capture.sent("2", "http://example.com/app.js", false, None);
+        capture.sent("3", "data:text/plain,hi", false, None);
+        capture.sent("4", "http://example.com/missing.png", false, None);
+        assert!(!capture.is_idle());
+        capture.finished("1");
+        capture.received("2", 200, &json!({}));
+        capture.failed("4");
+        assert!(!capture.is_idle());
+        capture.finished("2");
+        assert!(capture.is_idle());
+
+        let urls: Vec<_> = capture
+            .finished
+            .iter()
+            .map(|(_, url, _)| url.as_str())
+            .collect();
+        assert_eq!(
+            urls,
+            vec!["http://example.com/app/", "http://example.com/app.js"]
+        );
+        let page = &capture.finished[0].2;
+        assert_eq!(page.meta.content_type.as_deref(), Some("text/html"));
+        let (from, redirect) = &capture.redirects[0];
+        assert_eq!(from.as_str(), "http://example.com/");
+        assert_eq!(redirect.status, StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(redirect.location.as_deref(), Some("/app/"));
+        assert!(capture.api.is_empty());
+
+        let redirect = json!({ "Location": "/api/v2/posts" });
+        capture.sent("5", "http://example.com/api/posts", true, None);
+        let redirect = Some((302, &redirect));
+        capture.sent("5", "http://example.com/api/v2/posts", true, redirect);
+        capture.sent("6", "data:application/json,[]", true, None);
+        let api: Vec<_> = capture.api.iter().map(Url::as_str).collect();
+        assert_eq!(api, vec!["http://example.com/api/posts"]);
+    }
+
+    #[test]
+    fn test_header_map() {
+        let headers = header_map(&json!({
+            "content-type": "text/css",
+            "Set-Cookie": "a=1\nb=2",
+            "bad header": "x",
+        }));
+        assert_eq!(headers.get("content-type").unwrap(), "text/css");
+        assert_eq!(headers.get_all("set-cookie").iter().count(), 2);
+        assert_eq!(headers.len(), 3);
+    }
+
+    #[test]
+    fn test_archive_recorded() {
+        let mut records = Records::new(FailureReason::NotRecorded);
+        let mut add =
+            |url: &str, status, headers: Value, data: &'static str| {
+                let url = Url::parse(url).unwrap();
+                let mut record = record(&url, status, &headers).unwrap();
+                record.data = data.into();
+                records.insert(url, record);
+            };
+        add(
+            "http://example.com/",
+            302,
+            json!({ "Location": "/app/" }),
+            "",
+        );
+        add(
+            "http://example.com/app/",
+            200,
+            json!({ "Content-Type": "text/html" }),
+            "<div id=\"app\"></div>",
+        );
+        add(
+            "http://example.com/style.css",
+            200,
+            json!({ "Content-Type": "text/css" }),
+            "body { color: red }",
+        );
+        let content = "<div id=\"app\">\
+            <link rel=\"stylesheet\" href=\"/style.css\">Rendered</div>"
+            .to_string();
+
+        let options = ArchiveOptions::default();
+        let client = reqwest::Client::new();
+        let redirects = RedirectLog::default();
+        let url = Url::parse("http://example.com/").unwrap();
+        let requests = Requests::new(url, &options, &redirects, None);
+        let archive = block_on(archive_recorded(
+            &client,
+            &options,
+            &requests,
+            content,
+            &records,
+            Instant::now(),
+        ))
+        .unwrap();
+        assert_eq!(archive.url.as_str(), "http://example.com/app/");
+        assert!(archive.content.contains("Rendered"));
+        let style = Url::parse("http://example.com/style.css").unwrap();
+        assert!(matches!(archive.resource_map[&style], Resource::Css(_)));
+        assert!(archive.failures.is_empty());
+    }
+
+    #[test]
+    fn test_keep_api_responses() {
+        let mut records = Records::new(FailureReason::NotRecorded);
+        let mut add =
+            |url: &str, status, headers: Value, data: &'static str| {
+                let url = Url::parse(url).unwrap();
+                let mut record = record(&url, status, &headers).unwrap();
+                record.data = data.into();
+                records.insert(url, record);
+            };
+        add(
+            "http://example.com/api/posts",
+            301,
+            json!({ "Location": "/api/v2/posts" }),
+            "",
+        );
+        add(
+            "http://example.com/api/v2/posts",
+            200,
+            json!({ "Content-Type": "application/json" }),
+            "[\"</script>\"]",
+        );
+        add("http://example.com/api/user", 401, json!({}), "");
+        let urls: Vec<_> = [
+            "http://example.com/api/posts",
+            "http://example.com/api/user",
+            "http://example.com/api/missing",
+        ]
+        .iter()
+        .map(|url| Url::parse(url).unwrap())
+        .collect();
+
+        let mut archive = PageArchive {
+            url: Url::parse("http://example.com/").unwrap(),
+            content: "<p>Posts</p>".to_string(),
+            resource_map: HashMap::new(),
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            screenshot: None,
+        };
+        keep_api_responses(&mut archive, &records, &urls, true);
+
+        let posts = Url::parse("http://example.com/api/v2/posts").unwrap();
+        assert_eq!(archive.resource_map.len(), 1);
+        match &archive.resource_map[&posts] {
+            Resource::Other { data, mimetype } => {
+                assert_eq!(data.as_ref(), b"[\"</script>\"]");
+                assert_eq!(mimetype, "application/json");
+            }
+            _ => panic!("API response kept as the wrong resource"),
+        }
+        assert_eq!(archive.meta[&posts].status, 200);
+        assert_eq!(archive.redirects[&urls[0]], vec![posts]);
+
+        let head = archive.content.find("<head><script>").unwrap();
+        assert!(head < archive.content.find("<p>Posts</p>").unwrap());
+        assert!(archive.content.contains(&format!(
+            "\"http://example.com/api/posts\":{{\"body\":\"{}\"",
+            base64::encode("[\"</script>\"]")
+        )));
+        assert_eq!(archive.content.matches("</script>").count(), 1);
+        assert!(!archive.content.contains("api/user"));
+    }
+
+    #[test]
+    fn test_with_script() {
+        let content = "<!DOCTYPE html><html><head><title>App</title>\
+            <script src=\"app.js\"></script></head></html>";
+        let content = with_script(content, "var shim = 1 < 2;");
+        assert!(content.starts_with(
+            "<!DOCTYPE html><html><head>\
+            <script>var shim = 1 < 2;</script><title>App</title>"
+        ));
+    }
+
+    #[test]
+    fn test_browser_args() {
+        let args = |options| browser_args(&options).unwrap();
+        assert!(args(ArchiveOptions::default()).is_empty());
+        let resolve = || {
+            ArchiveOptions::default()
+                .resolve("example.com", "10.1.2.3:443")
+                .resolve("ipv6.example.com", "::1")
+        };
+        assert_eq!(
+            args(resolve()),
+            vec![
+                "--host-resolver-rules=MAP example.com 10.1.2.3, \
+                 MAP ipv6.example.com [::1]"
+            ]
+        );
+        let proxy = resolve().proxy("socks5h://localhost:1080");
+        assert_eq!(args(proxy), vec!["--proxy-server=socks5://localhost:1080"]);
+        #[cfg(feature = "tor")]
+        {
+            let tor = ArchiveOptions::default().tor("127.0.0.1:9050");
+            assert_eq!(
+                args(tor),
+                vec![
+                    "--proxy-server=socks5://127.0.0.1:9050",
+                    "--host-resolver-rules=MAP * ~NOTFOUND , EXCLUDE 127.0.0.1",
+                    "--force-webrtc-ip-handling-policy=disable_non_proxied_udp",
+                ]
+            );
+        }
+
+        let auth = ArchiveOptions {
+            auth: Some(Auth::Bearer("token")),
+            ..Default::default()
+        };
+        assert!(matches!(browser_args(&auth), Err(Error::Browser(_))));
+        let ca = ArchiveOptions::default().root_certificate(b"pem");
+        assert!(matches!(browser_args(&ca), Err(Error::Browser(_))));
+        let bad = ArchiveOptions::default().resolve("example.com", "staging");
+        assert!(browser_args(&bad).is_err());
+    }
+
+    #[test]
+    fn test_extra_headers() {
+        let options = ArchiveOptions::default()
+            .header("X-Archive", "1")
+            .header("X-Archive", "2")
+            .accept_language("en-GB");
+        let headers = extra_headers(&options).unwrap();
+        assert_eq!(headers["x-archive"], "1, 2");
+        assert_eq!(headers["accept-language"], "en-GB");
+        let overridden = options.header("Accept-Language", "fr");
+        assert_eq!(
+            extra_headers(&overridden).unwrap()["accept-language"],
+            "fr"
+        );
+    }
+
+    #[test]
+    fn test_cookies() {
+        let options = ArchiveOptions::default()
+            .cookie(
+                "https://example.com",
+                "session=abc; Domain=example.com; Path=/admin; Secure; HttpOnly",
+            )
+            .cookie("https://example.com", "not a cookie");
+        let cookies = cookies(&options).unwrap();
+        assert_eq!(cookies.len(), 1);
+        let cookie = &cookies[0];
+        assert_eq!(
+            (cookie.name.as_str(), cookie.value.as_str()),
+            ("session", "abc")
+        );
+        assert_eq!(cookie.url.as_deref(), Some("https://example.com"));
+        assert_eq!(cookie.domain.as_deref(), Some("example.com"));
+        assert_eq!(cookie.path.as_deref(), Some("/admin"));
+        assert_eq!((cookie.secure, cookie.http_only), (Some(true), Some(true)));
+
+        let bad = ArchiveOptions::default().cookie("example", "a=b");
+        assert!(matches!(super::cookies(&bad), Err(Error::ParseError(_))));
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for limiting the requests made to each host while archiving a
+//! page
+
+use crate::{ArchiveOptions, RateLimit};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use url::Url;
+
+/// How many requests may be made to each host at once, from
+/// `options.host_concurrency`, and how long to leave between them, from
+/// `options.host_delay`
+pub(crate) struct HostLimits {
+    concurrency: Option<usize>,
+    slots: Mutex<HashMap<String, Arc<Semaphore>>>,
+    delay: Option<RateLimit>,
+}
+
+impl HostLimits {
+    pub fn new(options: &ArchiveOptions) -> Self {
+        Self {
+            concurrency: options.host_concurrency.map(|n| n.max(1)),
+            slots: Mutex::new(HashMap::new()),
+            delay: options.host_delay.map(|delay| RateLimit::new(1, delay)),
+        }
+    }
+
+    /// Wait for a place among the requests to `url`'s host, which is
+    /// given up when the permit is dropped. There's no permit if there's
+    /// no limit.
+    pub async fn acquire(&self, url: &Url) -> Option<OwnedSemaphorePermit> {
+        let max = self.concurrency?;
+        let semaphore = self
+            .slots
+            .lock()
+            .unwrap()
+            .entry(host(url).to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(max)))
+            .clone();
+        // The semaphore is never closed
+        semaphore.acquire_owned().await.ok()
+    }
+
+    /// Wait for a place among the requests to `url`'s host, blocking the
+    /// thread
+    #[cfg(feature = "blocking")]
+    pub fn acquire_blocking(&self, url: &Url) -> Option<OwnedSemaphorePermit> {
+        futures::executor::block_on(self.acquire(url))
+    }
+
+    /// How long to wait before requesting `url`, so that requests to its
+    /// host are at least `options.host_delay` apart. This counts as a
+    /// request, so it should follow.
+    pub fn delay(&self, url: &Url) -> Duration {
+        self.delay
+            .as_ref()
+            .map_or(Duration::ZERO, |delay| delay.reserve(host(url)))
+    }
+}
+
+fn host(url: &Url) -> &str {
+    url.host_str().unwrap_or("")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_test::block_on;
+
+    #[test]
+    fn test_concurrency() {
+        let options = ArchiveOptions::default().host_concurrency(2);
+        let hosts = HostLimits::new(&options);
+        let a = Url::parse("http://a.example.com/1.png").unwrap();
+        let b = Url::parse("http://b.example.com/1.png").unwrap();
+
+        let first = block_on(hosts.acquire(&a));
+        let _second = block_on(hosts.acquire(&a));
+        let _other = block_on(hosts.acquire(&b));
+        let semaphore = hosts.slots.lock().unwrap()["a.example.com"].clone();
+        assert_eq!(semaphore.available_permits(), 0);
+        drop(first);
+        assert_eq!(semaphore.available_permits(), 1);
+
+        let hosts = HostLimits::new(&ArchiveOptions::default());
+        assert!(block_on(hosts.acquire(&a)).is_none());
+    }
+
+    #[test]
+    fn test_delay() {
+        let options =
+            ArchiveOptions::default().host_delay(Duration::from_secs(60));
+        let hosts = HostLimits::new(&options);
+        let a = Url::parse("http://a.example.com/1.png").unwrap();
+        let b = Url::parse("http://b.example.com/1.png").unwrap();
+        assert_eq!(hosts.delay(&a), Duration::ZERO);
+        assert_eq!(hosts.delay(&b), Duration::ZERO);
+        assert!(hosts.delay(&a) > Duration::from_secs(59));
+
+        let hosts = HostLimits::new(&ArchiveOptions::default());
+        assert_eq!(hosts.delay(&a), Duration::ZERO);
+        assert_eq!(hosts.delay(&a), Duration::ZERO);
+    }
+}
@@ -0,0 +1,563 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for keeping responses between archives, so that only what has
+//! changed is downloaded again
+
+use crate::bounded::BoundedMap;
+use crate::download::{Response, CACHE_SIZE};
+use crate::meta::ResourceMeta;
+use bytes::Bytes;
+use reqwest::header::{
+    HeaderMap, HeaderValue, AGE, CACHE_CONTROL, DATE, ETAG, EXPIRES,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, VARY,
+};
+use reqwest::StatusCode;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// Where responses are kept between archives, see
+/// [`ArchiveOptions::http_cache`](crate::ArchiveOptions::http_cache).
+///
+/// [`MemoryCache`] keeps them for as long as it lives. Implement this to
+/// keep them elsewhere, e.g. on disk or in a database.
+///
+/// ## Example
+///
+/// ```
+/// use std::collections::HashMap;
+/// use std::sync::Mutex;
+/// use url::Url;
+/// use web_archive::{CachedResponse, HttpCache};
+///
+/// /// Only keeps responses from one host
+/// #[derive(Default)]
+/// struct HostCache(Mutex<HashMap<Url, CachedResponse>>);
+///
+/// impl HttpCache for HostCache {
+///     fn get(&self, url: &Url) -> Option<CachedResponse> {
+///         self.0.lock().unwrap().get(url).cloned()
+///     }
+///
+///     fn put(&self, url: &Url, response: CachedResponse) {
+///         if url.host_str() == Some("example.com") {
+///             self.0.lock().unwrap().insert(url.clone(), response);
+///         }
+///     }
+/// }
+/// ```
+pub trait HttpCache: Send + Sync {
+    /// The response stored for a request to `url`, if there is one
+    fn get(&self, url: &Url) -> Option<CachedResponse>;
+
+    /// Store the response to a request for `url`, replacing any stored
+    /// already
+    fn put(&self, url: &Url, response: CachedResponse);
+}
+
+/// A response kept by an [`HttpCache`], along with what's needed to tell
+/// whether it's still current
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CachedResponse {
+    /// URLs that the request was redirected through, ending with the one
+    /// the response came from, or empty if it wasn't redirected
+    pub redirects: Vec<Url>,
+    /// Where and when the response came from, including its `Content-Type`
+    /// and what's needed to ask the server whether it has changed
+    pub meta: ResourceMeta,
+    /// The body of the response
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_data"))]
+    pub data: Bytes,
+    /// When the response stops being fresh, from its `Cache-Control` or
+    /// `Expires` headers. Until then it's used without asking the server,
+    /// and afterwards the server is asked whether it has changed. `None`
+    /// if it should be checked every time.
+    pub fresh_until: Option<SystemTime>,
+}
+
+impl CachedResponse {
+    /// Whether the response can still be used without asking the server
+    pub fn is_fresh(&self) -> bool {
+        self.is_fresh_at(SystemTime::now())
+    }
+
+    fn is_fresh_at(&self, now: SystemTime) -> bool {
+        matches!(self.fresh_until, Some(until) if until > now)
+    }
+
+    pub(crate) fn into_response(self) -> Response {
+        Response {
+            redirects: self.redirects,
+            meta: self.meta,
+            data: self.data,
+            timing: None,
+            transformed: false,
+        }
+    }
+}
+
+/// The headers of a response which let a server be asked whether it has
+/// changed since, see [`PageArchive::refresh`](crate::PageArchive::refresh)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Validators {
+    /// Value of the `ETag` header, sent back in `If-None-Match`
+    pub etag: Option<String>,
+    /// Value of the `Last-Modified` header, sent back in
+    /// `If-Modified-Since`
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub(crate) fn new(headers: &HeaderMap) -> Self {
+        Self {
+            etag: header(headers, ETAG).map(str::to_string),
+            last_modified: header(headers, LAST_MODIFIED).map(str::to_string),
+        }
+    }
+
+    /// Whether there's nothing to ask the server with
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Headers asking the server to only send the response again if it
+    /// has changed
+    pub(crate) fn conditional_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let etag = self.etag.as_deref().map(HeaderValue::from_str);
+        if let Some(Ok(etag)) = etag {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        let modified = self.last_modified.as_deref().map(HeaderValue::from_str);
+        if let Some(Ok(modified)) = modified {
+            headers.insert(IF_MODIFIED_SINCE, modified);
+        }
+        headers
+    }
+
+    /// These validators, updated with any sent again in `headers`
+    pub(crate) fn updated(self, headers: &HeaderMap) -> Self {
+        let Self {
+            etag,
+            last_modified,
+        } = Self::new(headers);
+        Self {
+            etag: etag.or(self.etag),
+            last_modified: last_modified.or(self.last_modified),
+        }
+    }
+}
+
+/// How long a response may be cached for, from its headers, until its
+/// body has been read
+pub(crate) struct Policy {
+    fresh_until: Option<SystemTime>,
+}
+
+impl Policy {
+    /// How a response with the `status` and `headers` may be cached, or
+    /// `None` if it mustn't be, or it would never be of any use
+    pub fn new(status: StatusCode, headers: &HeaderMap) -> Option<Self> {
+        Self::at(status, headers, SystemTime::now())
+    }
+
+    fn at(
+        status: StatusCode,
+        headers: &HeaderMap,
+        now: SystemTime,
+    ) -> Option<Self> {
+        let directives = cache_control(headers);
+        if status != StatusCode::OK
+            || varies(headers)
+            || directives.iter().any(|d| d == "no-store")
+        {
+            return None;
+        }
+        let fresh_until = fresh_until(&directives, headers, now);
+        // It could never be used or checked
+        if fresh_until.is_none() && Validators::new(headers).is_empty() {
+            return None;
+        }
+        Some(Self { fresh_until })
+    }
+
+    /// Store the `response` in the `cache`, if there is one and the
+    /// response may be cached
+    pub fn store(
+        policy: Option<Self>,
+        cache: Option<&dyn HttpCache>,
+        url: &Url,
+        response: &Response,
+    ) {
+        if let (Some(policy), Some(cache)) = (policy, cache) {
+            let response = response.clone();
+            cache.put(
+                url,
+                CachedResponse {
+                    redirects: response.redirects,
+                    meta: response.meta,
+                    data: response.data,
+                    fresh_until: policy.fresh_until,
+                },
+            );
+        }
+    }
+}
+
+/// Whether a response with the `headers` may differ depending on the
+/// headers of the request. Only the URL is looked up in an [`HttpCache`],
+/// so these responses aren't cached, except for those which only vary by
+/// `Accept-Encoding`, which the client always sends the same.
+fn varies(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(VARY)
+        .iter()
+        .map(|value| value.to_str().unwrap_or("*"))
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|name| {
+            !name.is_empty() && !name.eq_ignore_ascii_case("accept-encoding")
+        })
+}
+
+/// If a response with the `status` and `headers` says that the `cached`
+/// response hasn't changed, it's made fresh again in the `cache` and
+/// returned
+pub(crate) fn revalidate(
+    cache: Option<&dyn HttpCache>,
+    url: &Url,
+    cached: Option<CachedResponse>,
+    status: StatusCode,
+    headers: &HeaderMap,
+) -> Option<Response> {
+    let (cache, cached) = (cache?, cached?);
+    if status != StatusCode::NOT_MODIFIED {
+        return None;
+    }
+    let cached = revalidated(cached, headers, SystemTime::now());
+    cache.put(url, cached.clone());
+    Some(cached.into_response())
+}
+
+fn revalidated(
+    cached: CachedResponse,
+    headers: &HeaderMap,
+    now: SystemTime,
+) -> CachedResponse {
+    let directives = cache_control(headers);
+    CachedResponse {
+        meta: cached.meta.revalidated(headers),
+        fresh_until: fresh_until(&directives, headers, now),
+        ..cached
+    }
+}
+
+/// When a response stops being fresh, from its `max-age` or else its
+/// `Expires` header
+fn fresh_until(
+    directives: &[String],
+    headers: &HeaderMap,
+    now: SystemTime,
+) -> Option<SystemTime> {
+    if directives.iter().any(|d| d == "no-cache") {
+        return None;
+    }
+    let max_age = directives
+        .iter()
+        .find_map(|d| d.strip_prefix("max-age="))
+        .and_then(|age| age.trim_matches('"').parse::<u64>().ok());
+    if let Some(max_age) = max_age {
+        // How long it has already spent in other caches
+        let age = header(headers, AGE)
+            .and_then(|age| age.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        return Some(now + Duration::from_secs(max_age.saturating_sub(age)));
+    }
+    let expires = http_date(headers, EXPIRES)?;
+    // Compared with the server's clock rather than ours
+    let date = http_date(headers, DATE).unwrap_or(now);
+    let lifetime = expires.duration_since(date).unwrap_or(Duration::ZERO);
+    Some(now + lifetime)
+}
+
+/// The directives of the `Cache-Control` headers, in lowercase
+fn cache_control(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect()
+}
+
+fn header(
+    headers: &HeaderMap,
+    name: reqwest::header::HeaderName,
+) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn http_date(
+    headers: &HeaderMap,
+    name: reqwest::header::HeaderName,
+) -> Option<SystemTime> {
+    header(headers, name).and_then(|date| httpdate::parse_http_date(date).ok())
+}
+
+/// An [`HttpCache`] which keeps responses in memory for as long as it
+/// lives. Share one between archives to only download what has changed
+/// since. It holds up to 256 MiB of responses by default, forgetting the
+/// ones stored longest ago to make room for new ones.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{archive, ArchiveOptions, MemoryCache};
+///
+/// # async fn archive_async() {
+/// let cache = MemoryCache::new();
+/// let options = || ArchiveOptions::default().http_cache(&cache);
+/// let first = archive("http://example.com", options()).await.unwrap();
+/// // Only what has changed is downloaded again
+/// let second = archive("http://example.com", options()).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MemoryCache(Mutex<BoundedMap<CachedResponse>>);
+
+impl MemoryCache {
+    /// Create an empty cache holding up to 256 MiB of responses
+    pub fn new() -> Self {
+        Self::with_max_size(CACHE_SIZE)
+    }
+
+    /// Create an empty cache holding up to `bytes` of responses
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use web_archive::MemoryCache;
+    ///
+    /// let cache = MemoryCache::with_max_size(64 * 1024 * 1024);
+    /// ```
+    pub fn with_max_size(bytes: usize) -> Self {
+        Self(Mutex::new(BoundedMap::new(bytes)))
+    }
+
+    /// How many responses are stored
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Whether no responses are stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget every response
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpCache for MemoryCache {
+    fn get(&self, url: &Url) -> Option<CachedResponse> {
+        self.0.lock().unwrap().get(url).cloned()
+    }
+
+    fn put(&self, url: &Url, response: CachedResponse) {
+        let size = response.data.len();
+        self.0.lock().unwrap().insert(url.clone(), response, size);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (name.parse().unwrap(), HeaderValue::from_static(value))
+            })
+            .collect()
+    }
+
+    fn response() -> Response {
+        let url = Url::parse("http://example.com/style.css").unwrap();
+        let headers =
+            headers(&[("content-type", "text/css"), ("etag", "\"abc\"")]);
+        Response {
+            redirects: Vec::new(),
+            meta: ResourceMeta {
+                fetched_at: SystemTime::UNIX_EPOCH,
+                ..ResourceMeta::new(StatusCode::OK, &url, &headers)
+            },
+            data: Bytes::from("a {}"),
+            timing: None,
+            transformed: false,
+        }
+    }
+
+    #[test]
+    fn test_policy() {
+        let now = SystemTime::now();
+        let ok = StatusCode::OK;
+        let policy = |pairs| Policy::at(ok, &headers(pairs), now);
+
+        let fresh = policy(&[("cache-control", "public, max-age=60")]).unwrap();
+        assert_eq!(fresh.fresh_until, Some(now + Duration::from_secs(60)));
+        let aged = policy(&[("cache-control", "max-age=60"), ("age", "50")]);
+        assert_eq!(
+            aged.unwrap().fresh_until,
+            Some(now + Duration::from_secs(10))
+        );
+        let expires = policy(&[
+            ("date", "Wed, 21 Oct 2015 07:28:00 GMT"),
+            ("expires", "Wed, 21 Oct 2015 08:28:00 GMT"),
+        ]);
+        assert_eq!(
+            expires.unwrap().fresh_until,
+            Some(now + Duration::from_secs(3600))
+        );
+        let no_cache = policy(&[
+            ("cache-control", "no-cache, max-age=60"),
+            ("etag", "\"abc\""),
+        ]);
+        assert_eq!(no_cache.unwrap().fresh_until, None);
+
+        assert!(policy(&[("cache-control", "No-Store, max-age=60")]).is_none());
+        let vary = |vary| {
+            let headers =
+                headers(&[("cache-control", "max-age=60"), ("vary", vary)]);
+            Policy::at(ok, &headers, now)
+        };
+        assert!(vary("*").is_none());
+        // Only the URL is looked up, so the request headers can't be matched
+        assert!(vary("Accept, Accept-Encoding").is_none());
+        assert!(vary("User-Agent").is_none());
+        assert!(vary("Accept-Encoding").is_some());
+        // It could never be used or checked
+        assert!(policy(&[]).is_none());
+        let missing = StatusCode::NOT_FOUND;
+        let etag = headers(&[("etag", "\"abc\"")]);
+        assert!(Policy::at(missing, &etag, now).is_none());
+    }
+
+    #[test]
+    fn test_validators() {
+        let validators = Validators::new(&headers(&[
+            ("etag", "\"abc\""),
+            ("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT"),
+        ]));
+        let conditional = validators.conditional_headers();
+        assert_eq!(conditional[IF_NONE_MATCH], "\"abc\"");
+        assert_eq!(
+            conditional[IF_MODIFIED_SINCE],
+            "Wed, 21 Oct 2015 07:28:00 GMT"
+        );
+
+        let updated = validators.updated(&headers(&[("etag", "\"def\"")]));
+        assert_eq!(updated.etag.as_deref(), Some("\"def\""));
+        assert!(updated.last_modified.is_some());
+
+        assert!(Validators::new(&HeaderMap::new()).is_empty());
+        assert!(Validators::default().conditional_headers().is_empty());
+    }
+
+    #[test]
+    fn test_store() {
+        let cache = MemoryCache::new();
+        let url = Url::parse("http://example.com/style.css").unwrap();
+        let headers = headers(&[("etag", "\"abc\"")]);
+        let policy = Policy::new(StatusCode::OK, &headers);
+        Policy::store(policy, Some(&cache), &url, &response());
+        let cached = cache.get(&url).unwrap();
+        assert_eq!(cached.clone().into_response(), response());
+        assert!(!cached.is_fresh());
+
+        let other = Response {
+            data: Bytes::new(),
+            ..response()
+        };
+        Policy::store(None, Some(&cache), &url, &other);
+        assert_eq!(cache.get(&url), Some(cached));
+        cache.clear();
+        assert!(cache.is_empty());
+
+        // The response stored longest ago makes room for the next
+        let small = MemoryCache::with_max_size(6);
+        let script = url.join("script.js").unwrap();
+        Policy::store(
+            Policy::new(StatusCode::OK, &headers),
+            Some(&small),
+            &url,
+            &response(),
+        );
+        Policy::store(
+            Policy::new(StatusCode::OK, &headers),
+            Some(&small),
+            &script,
+            &response(),
+        );
+        assert_eq!(small.len(), 1);
+        assert!(small.get(&url).is_none());
+        assert!(small.get(&script).is_some());
+    }
+
+    #[test]
+    fn test_revalidate() {
+        let cache = MemoryCache::new();
+        let url = Url::parse("http://example.com/style.css").unwrap();
+        let Response {
+            redirects,
+            meta,
+            data,
+            ..
+        } = response();
+        let cached = CachedResponse {
+            redirects,
+            meta,
+            data,
+            fresh_until: None,
+        };
+        let not_modified = StatusCode::NOT_MODIFIED;
+        let fresh = headers(&[("cache-control", "max-age=60")]);
+
+        let revalidated = revalidate(
+            Some(&cache),
+            &url,
+            Some(cached.clone()),
+            not_modified,
+            &fresh,
+        );
+        let revalidated = revalidated.unwrap();
+        assert_eq!(revalidated.data, cached.data);
+        assert!(revalidated.meta.fetched_at > cached.meta.fetched_at);
+        let stored = cache.get(&url).unwrap();
+        assert!(stored.is_fresh());
+        assert_eq!(stored.meta.validators, cached.meta.validators);
+
+        let changed = StatusCode::OK;
+        let cache = Some(&cache as &dyn HttpCache);
+        assert!(
+            revalidate(cache, &url, Some(cached), changed, &fresh).is_none()
+        );
+        assert!(revalidate(cache, &url, None, not_modified, &fresh).is_none());
+    }
+}
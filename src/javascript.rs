@@ -0,0 +1,325 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for locating and rewriting the static imports in ES modules
+
+use std::ops::Range;
+use url::Url;
+
+/// A static `import` or `export ... from` specifier found within a module
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct JsImport {
+    /// Byte range of the specifier's string literal, including quotes
+    pub range: Range<usize>,
+    /// The module specifier, e.g. `./chunk.js`
+    pub specifier: String,
+}
+
+/// Find the specifiers of every static `import` and `export ... from`
+/// statement in a module. Dynamic `import()` calls are skipped, as is
+/// anything inside comments, strings, or template literals.
+pub(crate) fn find_imports(js: &str) -> Vec<JsImport> {
+    let bytes = js.as_bytes();
+    let mut imports = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = js[i..].find('\n').map(|end| i + end).unwrap_or(js.len());
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = js[i + 2..]
+                    .find("*/")
+                    .map(|end| i + 2 + end + 2)
+                    .unwrap_or(js.len());
+            }
+            quote @ b'"' | quote @ b'\'' | quote @ b'`' => {
+                i = skip_string(bytes, i + 1, quote);
+            }
+            b'i' | b'e' if is_keyword_start(bytes, i) => {
+                let (word, end) = read_word(js, i);
+                let found = match word {
+                    "import" => read_import(js, end),
+                    "export" => read_export(js, end),
+                    _ => None,
+                };
+                match found {
+                    Some(import) => {
+                        i = import.range.end;
+                        imports.push(import);
+                    }
+                    None => i = end,
+                }
+            }
+            b if is_ident_byte(b) => {
+                // Skip the rest of the identifier so that keywords aren't
+                // matched from its middle
+                i = read_word(js, i).1;
+            }
+            _ => i += 1,
+        }
+    }
+
+    imports
+}
+
+/// Resolve an import specifier against the URL of the module containing
+/// it. Bare specifiers (e.g. `lodash`) need an import map to resolve, so
+/// they are skipped.
+pub(crate) fn resolve_specifier(base: &Url, specifier: &str) -> Option<Url> {
+    if specifier.starts_with("./")
+        || specifier.starts_with("../")
+        || specifier.starts_with('/')
+    {
+        return base.join(specifier).ok();
+    }
+    match Url::parse(specifier) {
+        Ok(u) if u.scheme() == "http" || u.scheme() == "https" => Some(u),
+        _ => None,
+    }
+}
+
+/// The URLs of every module statically imported by a module, where
+/// `base` is the URL of the importing module
+pub(crate) fn module_imports(base: &Url, js: &str) -> Vec<Url> {
+    find_imports(js)
+        .iter()
+        .filter_map(|import| resolve_specifier(base, &import.specifier))
+        .collect()
+}
+
+/// Replace each import specifier in the module with the result of calling
+/// `replace` on it. If `replace` returns `None` then the original
+/// specifier is left untouched.
+pub(crate) fn rewrite_imports<F>(js: &str, mut replace: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut output = String::with_capacity(js.len());
+    let mut last = 0;
+
+    for import in find_imports(js) {
+        if let Some(new_specifier) = replace(&import.specifier) {
+            output.push_str(&js[last..import.range.start]);
+            output.push('"');
+            output.push_str(&new_specifier);
+            output.push('"');
+            last = import.range.end;
+        }
+    }
+    output.push_str(&js[last..]);
+
+    output
+}
+
+/// `import "x"`, `import x from "x"`, `import { a, b as c } from "x"`, and
+/// `import * as x from "x"`
+fn read_import(js: &str, start: usize) -> Option<JsImport> {
+    let bytes = js.as_bytes();
+    let i = skip_whitespace(bytes, start);
+    match bytes.get(i)? {
+        b'"' | b'\'' => read_specifier(js, i),
+        // Dynamic import or `import.meta`
+        b'(' | b'.' => None,
+        _ => read_from_clause(js, i),
+    }
+}
+
+/// `export * from "x"`, `export * as x from "x"`, and
+/// `export { a, b as c } from "x"`
+fn read_export(js: &str, start: usize) -> Option<JsImport> {
+    let bytes = js.as_bytes();
+    let i = skip_whitespace(bytes, start);
+    match bytes.get(i)? {
+        b'*' | b'{' => read_from_clause(js, i),
+        _ => None,
+    }
+}
+
+/// Scan the bindings of an import/export up to the `from` keyword and
+/// read the specifier which follows it
+fn read_from_clause(js: &str, start: usize) -> Option<JsImport> {
+    let bytes = js.as_bytes();
+    let mut i = start;
+    let mut in_braces = false;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' if !in_braces => in_braces = true,
+            b'}' if in_braces => in_braces = false,
+            b';' | b'(' | b')' | b'=' | b'"' | b'\'' | b'`' if !in_braces => {
+                return None;
+            }
+            b if is_ident_byte(b) => {
+                let (word, end) = read_word(js, i);
+                if word == "from" && !in_braces {
+                    let quote = skip_whitespace(bytes, end);
+                    return match bytes.get(quote)? {
+                        b'"' | b'\'' => read_specifier(js, quote),
+                        _ => None,
+                    };
+                }
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    None
+}
+
+/// Read a string literal starting at its opening quote
+fn read_specifier(js: &str, start: usize) -> Option<JsImport> {
+    let bytes = js.as_bytes();
+    let quote = bytes[start];
+    let end = skip_string(bytes, start + 1, quote);
+    if end - 1 == start || bytes[end - 1] != quote {
+        // Unterminated
+        return None;
+    }
+    Some(JsImport {
+        range: start..end,
+        specifier: js[start + 1..end - 1].to_string(),
+    })
+}
+
+/// Skip past a string literal, starting just after the opening quote.
+/// Returns the index just after the closing quote.
+fn skip_string(bytes: &[u8], mut i: usize, quote: u8) -> usize {
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b if b == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+fn read_word(js: &str, start: usize) -> (&str, usize) {
+    let end = js.as_bytes()[start..]
+        .iter()
+        .position(|&b| !is_ident_byte(b))
+        .map(|len| start + len)
+        .unwrap_or(js.len());
+    (&js[start..end], end)
+}
+
+/// Whether a keyword can start here, i.e. it isn't part of a longer
+/// identifier or a property access like `foo.import`
+fn is_keyword_start(bytes: &[u8], i: usize) -> bool {
+    i == 0 || !(is_ident_byte(bytes[i - 1]) || bytes[i - 1] == b'.')
+}
+
+fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$' || b >= 0x80
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while bytes.get(i).map(u8::is_ascii_whitespace).unwrap_or(false) {
+        i += 1;
+    }
+    i
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn specifiers(js: &str) -> Vec<String> {
+        find_imports(js).into_iter().map(|i| i.specifier).collect()
+    }
+
+    #[test]
+    fn test_import_forms() {
+        let js = r#"
+            import "./side-effect.js";
+            import def from './default.js';
+            import { a, b as c } from "./named.js";
+            import * as ns from "./namespace.js";
+            import def2, { d } from "./mixed.js";
+            export * from "./reexport.js";
+            export { e } from "./reexport-named.js";
+            import{f}from"./minified.js";
+        "#;
+
+        assert_eq!(
+            specifiers(js),
+            vec![
+                "./side-effect.js",
+                "./default.js",
+                "./named.js",
+                "./namespace.js",
+                "./mixed.js",
+                "./reexport.js",
+                "./reexport-named.js",
+                "./minified.js",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skipped_imports() {
+        let js = r#"
+            // import "./commented.js";
+            /* import "./block-commented.js"; */
+            const s = "import './in-a-string.js'";
+            const t = `import "./in-a-template.js"`;
+            import("./dynamic.js");
+            console.log(import.meta.url);
+            foo.import("./method.js");
+            export const from = "./not-an-import.js";
+            export { g };
+            const reimport = "x";
+        "#;
+
+        assert!(specifiers(js).is_empty());
+    }
+
+    #[test]
+    fn test_resolve_specifier() {
+        let base = Url::parse("http://example.com/js/app.js").unwrap();
+
+        assert_eq!(
+            resolve_specifier(&base, "./chunk.js").unwrap().as_str(),
+            "http://example.com/js/chunk.js"
+        );
+        assert_eq!(
+            resolve_specifier(&base, "../lib.js").unwrap().as_str(),
+            "http://example.com/lib.js"
+        );
+        assert_eq!(
+            resolve_specifier(&base, "https://cdn.example.com/x.js")
+                .unwrap()
+                .as_str(),
+            "https://cdn.example.com/x.js"
+        );
+        assert!(resolve_specifier(&base, "lodash").is_none());
+        assert!(resolve_specifier(&base, "data:text/javascript,").is_none());
+    }
+
+    #[test]
+    fn test_rewrite_imports() {
+        let js = "import a from './a.js';\nimport b from './b.js';";
+        let rewritten = rewrite_imports(js, |s| {
+            if s == "./a.js" {
+                Some("data:text/javascript;base64,AAAA".to_string())
+            } else {
+                None
+            }
+        });
+
+        assert_eq!(
+            rewritten,
+            "import a from \"data:text/javascript;base64,AAAA\";\n\
+             import b from './b.js';"
+        );
+    }
+}
@@ -73,22 +73,144 @@
 //!
 //! ```
 
+pub use archiver::Archiver;
+use cancel::until_cancelled;
+pub use cancel::CancellationToken;
+pub use client::{AcceptHeaders, Auth};
+use client::{RedirectLog, Requests};
+pub use crawl::{crawl, CrawlOptions, CrawlOrder, CrawlScope};
+use download::{Cache, Download, Downloader, Fetched, Response};
+use encoding::decode_page;
 pub use error::Error;
-pub use page_archive::PageArchive;
-use parsing::{mimetype_from_response, parse_resource_urls};
-pub use parsing::{ImageResource, Resource, ResourceMap, ResourceUrl};
-use reqwest::{Proxy, StatusCode};
+pub use event::ArchiveEvent;
+pub use failure::{FailureReason, ResourceFailure};
+pub use feed::{archive_feed, FeedEntry, FeedOptions};
+pub use filter_list::FilterList;
+use futures::stream::{self, StreamExt};
+pub use har::read_har;
+#[cfg(feature = "headless")]
+pub use headless::{archive_rendered, RenderOptions, Screenshot};
+use http_cache::Policy;
+pub use http_cache::{CachedResponse, HttpCache, MemoryCache, Validators};
+pub use links::Link;
+pub use markdown::{MarkdownImages, MarkdownOptions};
+pub use memento::{archive_memento, Memento, MementoOptions};
+pub use meta::ResourceMeta;
+pub use metadata::ParsedPage;
+pub use normalize::UrlNormalizer;
+use normalize::SORT_QUERY;
+pub use page_archive::{EmbedOptions, PageArchive, Refresh, WriteOptions};
+pub use pagination::{archive_paginated, PaginationOptions};
+use parsing::parse_resource_urls;
+pub use parsing::{
+    ImageResource, Resource, ResourceMap, ResourceUrl, TextResource,
+};
+#[cfg(feature = "headless")]
+pub use pdf::{Margins, PaperSize, PdfOptions};
+pub use provenance::Provenance;
+#[cfg(feature = "proxy")]
+pub use proxy::RecordingProxy;
+pub use rate_limit::RateLimit;
+pub use reader::Article;
+pub use regex::Regex;
+use replay::Records;
+use reqwest::header::HeaderMap;
+pub use reqwest::tls::Version as TlsVersion;
+use resume::Partial;
+pub use save_page_now::SavePageNowOptions;
+#[cfg(feature = "serve")]
+pub use serve::serve;
+pub use site::SiteArchive;
+pub use sitemap::{archive_sitemap, SitemapOptions};
+use stats::Stopwatch;
+pub use stats::{ArchiveStats, FetchTiming};
 use std::convert::TryInto;
 use std::fmt::Display;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+pub use structured_data::StructuredData;
 use url::Url;
+pub use verify::Verification;
+#[cfg(feature = "warc")]
+pub use warc::read_warc;
 
+mod archiver;
+mod bounded;
+#[cfg(feature = "bundle")]
+mod bundle;
+mod cancel;
+mod client;
+mod crawl;
+mod css;
+mod date;
+mod digest;
+mod disk;
+mod dom;
+mod download;
+mod encoding;
 pub mod error;
+mod event;
+mod failure;
+mod feed;
+mod filter_list;
+mod har;
+#[cfg(feature = "headless")]
+mod headless;
+mod host_limits;
+mod http_cache;
+mod javascript;
+mod links;
+mod manifest;
+mod markdown;
+mod memento;
+mod meta;
+mod metadata;
+mod mimetype;
+mod native;
+mod normalize;
 pub mod page_archive;
+mod pagination;
 pub mod parsing;
+#[cfg(feature = "headless")]
+mod pdf;
+mod provenance;
+#[cfg(feature = "proxy")]
+mod proxy;
+mod rate_limit;
+mod reader;
+mod replay;
+mod resume;
+mod robots;
+mod save_page_now;
+#[cfg(feature = "serde")]
+mod serde_data;
+#[cfg(feature = "serve")]
+mod serve;
+mod site;
+mod sitemap;
+mod source_map;
+mod stats;
+#[cfg(feature = "streaming")]
+mod streaming;
+mod structured_data;
+mod text;
+pub mod user_agent;
+mod verify;
+#[cfg(feature = "wacz")]
+mod wacz;
+#[cfg(feature = "warc")]
+mod warc;
+mod xml;
 
 #[cfg(feature = "blocking")]
 pub mod blocking;
 
+/// The address of a Tor daemon's SOCKS port in its default configuration,
+/// for use with [`ArchiveOptions::tor`]
+#[cfg(feature = "tor")]
+pub const TOR_SOCKS_ADDRESS: &str = "127.0.0.1:9050";
+
 /// The async archive function.
 ///
 /// Takes in a URL and attempts to download the page and its resources.
@@ -105,56 +227,539 @@ where
     let url: Url = url
         .try_into()
         .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let redirects = RedirectLog::default();
+    let client = build_client(&options, &redirects)?;
+    archive_page(
+        &client,
+        url,
+        &options,
+        None,
+        options.http_cache,
+        false,
+        &redirects,
+    )
+    .await
+}
 
-    // Initialise client
-    let mut client = reqwest::Client::builder()
-        .danger_accept_invalid_certs(options.accept_invalid_certificates);
-    if let Some(proxy) = options.proxy {
-        client = client.proxy(Proxy::all(proxy)?);
-    }
-    let client = client.build()?;
+/// Archive a page using an existing client, e.g. one shared with the
+/// rest of an application or configured in ways that [`ArchiveOptions`]
+/// doesn't cover.
+///
+/// The options which configure the client itself are ignored: `proxy`,
+/// `accept_invalid_certificates`, `timeout`, `user_agent`, `headers`,
+/// and `max_redirects`. Only the final URL of each redirect is known, so
+/// that's all that [`PageArchive::redirects`] records.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{archive_with_client, ArchiveOptions};
+///
+/// # async fn archive_async() {
+/// let client = reqwest::Client::builder()
+///     .user_agent("my-archiver/1.0")
+///     .build()
+///     .unwrap();
+/// let archive = archive_with_client(
+///     &client,
+///     "http://example.com",
+///     ArchiveOptions::default(),
+/// )
+/// .await
+/// .unwrap();
+/// # }
+/// ```
+pub async fn archive_with_client<U>(
+    client: &reqwest::Client,
+    url: U,
+    options: ArchiveOptions<'_>,
+) -> Result<PageArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    archive_page(
+        client,
+        url,
+        &options,
+        None,
+        options.http_cache,
+        false,
+        &RedirectLog::default(),
+    )
+    .await
+}
+
+/// Find the resources that archiving a page would download, without
+/// downloading any of them.
+///
+/// Only the page itself and its stylesheets are fetched. Each resource is
+/// tagged with the type it's expected to be from how the page or
+/// stylesheet refers to it, and the options' filters apply as they would
+/// for [`archive`], so the list can be checked or narrowed down before
+/// archiving. The stylesheets come first, followed by the images, fonts,
+/// and imported stylesheets referred to by them and by the page's inline
+/// `<style>` tags and `style` attributes. Resources referred to by other
+/// kinds of resource, such as modules imported by scripts, aren't known
+/// until those resources are downloaded.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{discover, ResourceUrl};
+///
+/// # async fn discover_async() {
+/// let resources = discover("http://example.com", Default::default())
+///     .await
+///     .unwrap();
+/// for resource in &resources {
+///     if let ResourceUrl::Image(url) = resource {
+///         println!("image: {}", url);
+///     }
+/// }
+/// # }
+/// ```
+pub async fn discover<U>(
+    url: U,
+    options: ArchiveOptions<'_>,
+) -> Result<Vec<ResourceUrl>, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let redirects = RedirectLog::default();
+    let client = build_client(&options, &redirects)?;
+    discover_page(&client, url, &options, None, options.http_cache, &redirects)
+        .await
+}
+
+/// Archive a page and write it to a file at `path` with its resources
+/// embedded, as a single HTML file which can be opened without the
+/// network.
+///
+/// The file is written to a temporary file alongside and then renamed
+/// into place, so it's never left half written. The archive is returned
+/// too, e.g. to check its [`failures`](PageArchive::failures).
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::archive_to_file;
+///
+/// # async fn archive_to_file_async() {
+/// archive_to_file("http://example.com", &"example.html", Default::default())
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+pub async fn archive_to_file<U, P>(
+    url: U,
+    path: &P,
+    options: ArchiveOptions<'_>,
+) -> Result<PageArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+    P: AsRef<Path>,
+{
+    let archive = archive(url, options).await?;
+    let page = archive.embed_resources();
+    disk::write_atomically(path.as_ref(), true, |writer| {
+        writer.write_all(page.as_bytes())
+    })?;
+    Ok(archive)
+}
 
-    // Fetch the page contents
-    let content = client.get(url.clone()).send().await?.text().await?;
+/// Archive a page, sharing downloads with other pages through the
+/// `cache` if there is one, and keeping responses in the `http_cache`,
+/// which is usually `options.http_cache`, or else holds the responses of
+/// an archive being refreshed if `replaying`. The `redirects` are those
+/// followed by the client.
+pub(crate) async fn archive_page(
+    client: &reqwest::Client,
+    url: Url,
+    options: &ArchiveOptions<'_>,
+    cache: Option<&Cache>,
+    http_cache: Option<&dyn HttpCache>,
+    replaying: bool,
+    redirects: &RedirectLog,
+) -> Result<PageArchive, Error> {
+    let mut requests = Requests::new(url, options, redirects, http_cache);
+    requests.replaying = replaying;
+    let started = Instant::now();
+    let (url, content, page) = load_page(client, options, &requests).await?;
 
     // Determine the resources that the page needs
-    let resource_urls = parse_resource_urls(&url, &content);
+    let resource_urls = parse_resource_urls(&url, &content, options);
 
-    // Download them
-    let mut resource_map = ResourceMap::new();
-    for resource_url in resource_urls {
-        use ResourceUrl::*;
+    let mut downloader =
+        Downloader::new(url.clone(), resource_urls, options, cache);
+    downloader.redirected(requests.page.clone(), page.redirects);
+    downloader.fetched(url, page.meta);
+    downloader.timed(page.timing);
+    download_resources(client, options, &requests, &mut downloader, None).await;
+
+    let archive = downloader.into_archive(content, started.elapsed());
+    #[cfg(feature = "headless")]
+    let archive = headless::with_screenshot(archive, options).await?;
+    Ok(archive)
+}
 
-        let response = client.get(resource_url.url().clone()).send().await?;
-        if response.status() != StatusCode::OK {
-            // Skip any errors
-            continue;
+/// Download the resources that the `downloader` has queued, several at a
+/// time, taking those which were `recorded` from the recording instead.
+/// Storing them may turn up more resources, such as modules imported by
+/// the scripts, so keep going until there are none left.
+pub(crate) async fn download_resources(
+    client: &reqwest::Client,
+    options: &ArchiveOptions<'_>,
+    requests: &Requests<'_>,
+    downloader: &mut Downloader<'_, '_>,
+    recorded: Option<&Records>,
+) {
+    let token = options.cancellation.as_ref();
+    let page_url = &downloader.page_url().clone();
+    loop {
+        let batch = downloader.take_queue();
+        if batch.is_empty() {
+            break;
         }
-        match resource_url {
-            Image(u) => {
-                // Get mimetype of image
-                let data = response.bytes().await?;
-                let mimetype = mimetype_from_response(&data, &u);
-                resource_map.insert(
-                    u,
-                    Resource::Image(ImageResource { data, mimetype }),
+        let downloads: Vec<_> = stream::iter(batch)
+            .map(|(resource_url, depth)| async move {
+                let records = recorded
+                    .filter(|records| records.contains(resource_url.url()));
+                if let Some(records) = records {
+                    return records.fetched(resource_url, depth);
+                }
+                let fetch = fetch(
+                    client,
+                    options,
+                    requests,
+                    page_url,
+                    resource_url,
+                    depth,
                 );
+                until_cancelled(token, fetch)
+                    .await
+                    .unwrap_or(Fetched::Cancelled)
+            })
+            .buffer_unordered(options.concurrency.max(1))
+            .collect()
+            .await;
+        for fetched in downloads {
+            downloader.record(fetched);
+        }
+    }
+}
+
+/// Find the resources that archiving a page would download, fetching
+/// only the page and its stylesheets, see [`discover`]
+pub(crate) async fn discover_page(
+    client: &reqwest::Client,
+    url: Url,
+    options: &ArchiveOptions<'_>,
+    cache: Option<&Cache>,
+    http_cache: Option<&dyn HttpCache>,
+    redirects: &RedirectLog,
+) -> Result<Vec<ResourceUrl>, Error> {
+    let requests = Requests::new(url, options, redirects, http_cache);
+    let (url, content, _) = load_page(client, options, &requests).await?;
+    let resource_urls = parse_resource_urls(&url, &content, options);
+    let mut downloader =
+        Downloader::new(url, resource_urls, options, cache).stylesheets_only();
+    download_resources(client, options, &requests, &mut downloader, None).await;
+    Ok(downloader.into_queue())
+}
+
+/// Fetch the page and decode its content, returning the URL that it came
+/// from after following any redirects, the content, and the response
+async fn load_page(
+    client: &reqwest::Client,
+    options: &ArchiveOptions<'_>,
+    requests: &Requests<'_>,
+) -> Result<(Url, String, Response), Error> {
+    let url = &requests.page;
+    if !options.allows_scheme(url) {
+        return Err(Error::DisallowedScheme(url.to_string()));
+    }
+    if options.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+    // Credentials are sent to the host that was asked for, even if the
+    // page is redirected elsewhere
+    let headers = requests.headers(options, None)?;
+    let token = options.cancellation.as_ref();
+    let cached = requests.http_cache.and_then(|cache| cache.get(url));
+    let page = match cached {
+        Some(cached) if cached.is_fresh() => cached.into_response(),
+        cached => {
+            let fetch = fetch_page(client, options, requests, headers, cached);
+            until_cancelled(token, fetch)
+                .await
+                .ok_or(Error::Cancelled)??
+        }
+    };
+    // Relative URLs in the page are resolved against the URL that it was
+    // redirected to
+    let url = page.redirects.last().unwrap_or(url).clone();
+    options.emit(ArchiveEvent::PageFetched {
+        url: url.clone(),
+        bytes: page.data.len(),
+    });
+    let content = decode_page(page.meta.content_type.as_deref(), &page.data);
+    Ok((url, content, page))
+}
+
+/// Build a client configured by the options, which records the redirects
+/// it follows in `redirects`
+pub(crate) fn build_client(
+    options: &ArchiveOptions,
+    redirects: &RedirectLog,
+) -> Result<reqwest::Client, Error> {
+    let mut client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(options.accept_invalid_certificates)
+        .default_headers(client::default_headers(options)?)
+        .redirect(client::redirect_policy(options, redirects.clone()));
+    for certificate in client::root_certificates(options)? {
+        client = client.add_root_certificate(certificate);
+    }
+    if let Some(identity) = client::identity(options)? {
+        client = client.identity(identity);
+    }
+    if let Some(version) = options.min_tls_version {
+        client = client.min_tls_version(version);
+    }
+    for (host, address) in client::resolve(options)? {
+        client = client.resolve(host, address);
+    }
+    if let Some(jar) = client::cookie_jar(options)? {
+        client = client.cookie_provider(jar);
+    }
+    if let Some(proxy) = client::proxy(options)? {
+        client = client.proxy(proxy);
+    }
+    if let Some(timeout) = options.timeout {
+        client = client.timeout(timeout);
+    }
+    if let Some(user_agent) = options.user_agent {
+        client = client.user_agent(user_agent);
+    }
+    Ok(client.build()?)
+}
+
+/// Request the page, unless the `cached` response is still current
+async fn fetch_page(
+    client: &reqwest::Client,
+    options: &ArchiveOptions<'_>,
+    requests: &Requests<'_>,
+    headers: HeaderMap,
+    cached: Option<CachedResponse>,
+) -> Result<Response, Error> {
+    let url = &requests.page;
+    let hosts = &requests.hosts;
+    let delay = options.rate_limit_delay(url).max(hosts.delay(url));
+    rate_limit::wait(delay).await;
+    let mut request = client.get(url.clone()).headers(headers);
+    if let Some(cached) = &cached {
+        request = request.headers(cached.meta.validators.conditional_headers());
+    }
+    if let Some(timeout) = options.request_timeout(requests.deadline) {
+        request = request.timeout(timeout);
+    }
+    let log = requests.redirects.start(url, None);
+    let mut stopwatch = Stopwatch::start(url);
+    let response = request.send().await?;
+    stopwatch.first_byte();
+    let redirects = log.finish(response.url());
+    let (status, headers) = (response.status(), response.headers());
+    let cache = requests.http_cache;
+    if let Some(page) =
+        http_cache::revalidate(cache, url, cached, status, headers)
+    {
+        let timing = Some(stopwatch.stop(0));
+        return Ok(Response { timing, ..page });
+    }
+    let policy = Policy::new(status, headers);
+    let meta = ResourceMeta::new(status, response.url(), headers);
+    let data = response.bytes().await?;
+    let page = Response {
+        redirects,
+        meta,
+        timing: Some(stopwatch.stop(data.len() as u64)),
+        transformed: false,
+        data,
+    };
+    Policy::store(policy, cache, url, &page);
+    Ok(page)
+}
+
+/// Fetch a single resource of the page at `page_url`, retrying it up to
+/// `options.retries` times if it fails for a reason that might be
+/// temporary. Resources which can't be downloaded are reported rather
+/// than failing the archive.
+async fn fetch(
+    client: &reqwest::Client,
+    options: &ArchiveOptions<'_>,
+    requests: &Requests<'_>,
+    page_url: &Url,
+    resource_url: ResourceUrl,
+    depth: usize,
+) -> Fetched {
+    if options.is_cancelled() {
+        return Fetched::Cancelled;
+    }
+    options.emit(ArchiveEvent::ResourceStarted {
+        url: resource_url.url().clone(),
+    });
+    let mut retries = 0;
+    // Whatever was downloaded before a failure, to carry on from
+    let mut partial = None;
+    loop {
+        let fetched = fetch_once(
+            client,
+            options,
+            requests,
+            page_url,
+            &resource_url,
+            &mut partial,
+        );
+        match fetched.await {
+            Ok(response) => {
+                let download = Download::new(resource_url, depth, response);
+                return Fetched::Downloaded(Box::new(download));
             }
-            Css(u) => {
-                resource_map.insert(u, Resource::Css(response.text().await?));
+            Err(reason)
+                if reason.is_transient() && retries < options.retries =>
+            {
+                retries += 1
             }
-            Javascript(u) => {
-                resource_map
-                    .insert(u, Resource::Javascript(response.text().await?));
+            Err(reason) => {
+                return Fetched::Failed(ResourceFailure {
+                    url: resource_url.url().clone(),
+                    reason,
+                    retries,
+                })
             }
         }
     }
+}
 
-    Ok(PageArchive {
-        url,
-        content,
-        resource_map,
-    })
+/// Make a single request for a resource, returning the response, or else
+/// the reason that it couldn't be downloaded. Responses in the HTTP cache
+/// are used instead while they're fresh, and afterwards only downloaded
+/// again if they've changed.
+async fn fetch_once(
+    client: &reqwest::Client,
+    options: &ArchiveOptions<'_>,
+    requests: &Requests<'_>,
+    page_url: &Url,
+    resource_url: &ResourceUrl,
+    partial: &mut Option<Partial>,
+) -> Result<Response, FailureReason> {
+    let url = resource_url.url();
+    let cached = requests.http_cache.and_then(|cache| cache.get(url));
+    if let Some(cached) = cached.as_ref().filter(|cached| cached.is_fresh()) {
+        let transformed = requests.replaying;
+        return Ok(Response {
+            transformed,
+            ..cached.clone().into_response()
+        });
+    }
+    let hosts = &requests.hosts;
+    let _slot = hosts.acquire(url).await;
+    let delay = options.rate_limit_delay(url).max(hosts.delay(url));
+    rate_limit::wait(delay).await;
+    // The headers were checked when the page was fetched
+    let mut headers = requests
+        .headers(options, Some(resource_url))
+        .unwrap_or_default();
+    // Carrying on from a partial download takes the place of checking
+    // whether the cached response has changed
+    match (&partial, &cached) {
+        (Some(partial), _) => headers.extend(partial.request_headers()),
+        (None, Some(cached)) => {
+            headers.extend(cached.meta.validators.conditional_headers())
+        }
+        (None, None) => {}
+    }
+    let mut request = client.get(url.clone()).headers(headers);
+    if let Some(timeout) = options.request_timeout(requests.deadline) {
+        if timeout == Duration::ZERO {
+            return Err(FailureReason::TimedOut);
+        }
+        request = request.timeout(timeout);
+    }
+    let log = requests.redirects.start(url, Some(page_url));
+    let mut stopwatch = Stopwatch::start(url);
+    let mut response = request.send().await?;
+    stopwatch.first_byte();
+    let redirects = log.finish(response.url());
+    // Clients which weren't built with the log follow redirects anywhere
+    if !options.allows_origin(page_url, response.url()) {
+        return Err(FailureReason::DisallowedHost);
+    }
+    let (status, headers) = (response.status(), response.headers());
+    let cache = requests.http_cache;
+    if let Some(response) =
+        http_cache::revalidate(cache, url, cached, status, headers)
+    {
+        let timing = Some(stopwatch.stop(0));
+        let transformed = requests.replaying;
+        return Ok(Response {
+            timing,
+            transformed,
+            ..response
+        });
+    }
+    let policy = Policy::new(status, headers);
+    let meta = ResourceMeta::new(status, response.url(), headers);
+    if !response.status().is_success() {
+        return Err(FailureReason::Status(response.status().as_u16()));
+    }
+    let (mut data, validator) = resume::carry_on(
+        partial.take(),
+        response.status(),
+        response.headers(),
+    )?;
+    let resumed = data.len();
+    if resumed > 0 {
+        options.emit(ArchiveEvent::ResourceResumed {
+            url: url.clone(),
+            offset: resumed,
+        });
+    }
+    let expected = (data.len() as u64)
+        .saturating_add(response.content_length().unwrap_or(0));
+    if options.exceeds_resource_size(expected) {
+        return Err(FailureReason::TooLarge);
+    }
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => data.extend_from_slice(&chunk),
+            Ok(None) => break,
+            Err(err) => {
+                *partial = Partial::new(validator, data);
+                return Err(err.into());
+            }
+        }
+        if options.exceeds_resource_size(data.len() as u64) {
+            return Err(FailureReason::TooLarge);
+        }
+    }
+    let fetched = Response {
+        redirects,
+        meta,
+        timing: Some(stopwatch.stop((data.len() - resumed) as u64)),
+        transformed: false,
+        data: data.freeze(),
+    };
+    Policy::store(policy, cache, url, &fetched);
+    Ok(fetched)
 }
 
 /// Configuration options to control aspects of the archiving behaviour.
@@ -164,8 +769,13 @@ pub struct ArchiveOptions<'a> {
     /// against an IP address will more than likely result in a hostname
     /// mismatch.
     ///
-    /// Corresponds to [`reqwest::ClientBuilder::danger_accept_invalid_certs`]
-    /// and [`reqwest::ClientBuilder::danger_accept_invalid_hostnames`].
+    /// **Dangerous:** this turns off certificate checking entirely, for
+    /// every host, so anyone in the way can serve the archive whatever
+    /// they like. To archive a host with an internal certificate
+    /// authority, prefer adding it to the
+    /// [`root_certificates`](#structfield.root_certificates).
+    ///
+    /// Corresponds to [`reqwest::ClientBuilder::danger_accept_invalid_certs`].
     ///
     /// Default: `false`
     ///
@@ -178,6 +788,52 @@ pub struct ArchiveOptions<'a> {
     /// };
     /// ```
     pub accept_invalid_certificates: bool,
+    /// PEM encoded certificates to trust as well as the built-in root
+    /// certificates, such as those of an internal certificate authority.
+    /// Each entry may hold several certificates. Archiving fails with
+    /// [`Error::ParseError`] if any of them can't be read.
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::ArchiveOptions;
+    /// let ca = std::fs::read("internal-ca.pem").unwrap();
+    /// let options = ArchiveOptions {
+    ///     root_certificates: vec![&ca],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub root_certificates: Vec<&'a [u8]>,
+    /// A PEM encoded certificate chain and private key to present to
+    /// servers which ask for a client certificate. Archiving fails with
+    /// [`Error::ParseError`] if it can't be read.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::ArchiveOptions;
+    /// let identity = std::fs::read("client.pem").unwrap();
+    /// let options = ArchiveOptions {
+    ///     client_identity: Some(&identity),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub client_identity: Option<&'a [u8]>,
+    /// The oldest version of TLS to accept when connecting to a server.
+    ///
+    /// Default: `None`, i.e. whatever `reqwest` accepts, currently TLS 1.2
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, TlsVersion};
+    /// let options = ArchiveOptions {
+    ///     min_tls_version: Some(TlsVersion::TLS_1_3),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub min_tls_version: Option<TlsVersion>,
     /// Connect via specified proxy. Accepts HTTP/HTTPS proxies, and if
     /// the `socks` feature is enabled then SOCKS proxies may also be
     /// specified.
@@ -194,33 +850,1611 @@ pub struct ArchiveOptions<'a> {
     /// };
     /// ```
     pub proxy: Option<&'a str>,
-}
-
-impl<'a> Default for ArchiveOptions<'a> {
-    fn default() -> Self {
-        Self {
-            accept_invalid_certificates: false,
-            proxy: None,
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tokio_test::block_on;
-
-    #[test]
-    fn parse_invalid_url_async() {
-        let u = "this~is~not~a~url";
-
-        let res = block_on(archive(u, Default::default()));
-        assert!(res.is_err());
-
-        if let Err(Error::ParseError(_err)) = res {
-            // Okay, it's a parse error
-        } else {
-            panic!("Expected parse error");
-        }
+    /// Send every request through Tor, via the SOCKS port of a Tor
+    /// daemon at this address. Each page gets circuits of its own, so a
+    /// page archived by [`archive`] or an [`Archiver`] isn't linked to any
+    /// other, unless the `Archiver` was given its client with
+    /// [`Archiver::with_client`]. Host names are resolved
+    /// by Tor, so onion services can be archived too. This replaces the
+    /// [`proxy`](#structfield.proxy).
+    ///
+    /// Default: `None`
+    /// Related feature: `features = ["tor"]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, TOR_SOCKS_ADDRESS};
+    /// let options = ArchiveOptions {
+    ///     tor: Some(TOR_SOCKS_ADDRESS),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    #[cfg(feature = "tor")]
+    pub tor: Option<&'a str>,
+    /// Hosts to connect to at particular IP addresses instead of looking
+    /// them up, as host and address pairs. The address may include a
+    /// port, but it's ignored: requests go to the port in the URL. This
+    /// doesn't apply to requests sent through a
+    /// [`proxy`](#structfield.proxy). Archiving fails with
+    /// [`Error::ParseError`] if an address isn't valid.
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// // Archive the staging server as if it were the live site
+    /// let options = ArchiveOptions {
+    ///     resolve: vec![("www.example.com", "10.1.2.3")],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub resolve: Vec<(&'a str, &'a str)>,
+    /// Timeout for each request, covering everything from connecting to
+    /// reading the body. Resources which time out are left out of the
+    /// archive and listed in [`PageArchive::timed_out`].
+    ///
+    /// Default: `None`, i.e. `reqwest`'s default of no timeout for the
+    /// async API and 30 seconds for the blocking API
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     timeout: Some(Duration::from_secs(10)),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub timeout: Option<Duration>,
+    /// How many times to retry a resource which fails for a reason that
+    /// might be temporary: a server error (5xx) or a timeout. Resources
+    /// which still fail are listed in [`PageArchive::failures`].
+    ///
+    /// If a download is cut off part of the way through and the server
+    /// supports `Range` requests, the retry carries on from where it
+    /// stopped, as long as the resource hasn't changed, and reports
+    /// [`ArchiveEvent::ResourceResumed`].
+    ///
+    /// Default: `0`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     retries: 2,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub retries: usize,
+    /// How long to spend archiving a page in total. Once it passes, any
+    /// requests still in progress are abandoned and the archive is
+    /// returned with the resources downloaded so far. The rest are listed
+    /// in [`PageArchive::timed_out`].
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     deadline: Some(Duration::from_secs(60)),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub deadline: Option<Duration>,
+    /// Token for cancelling the archive while it's in progress. Once
+    /// cancelled, the archive is returned with the resources downloaded
+    /// so far and [`PageArchive::cancelled`] set, or fails with
+    /// [`Error::Cancelled`] if the page itself hadn't been downloaded
+    /// yet.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, CancellationToken};
+    /// let token = CancellationToken::new();
+    /// let options = ArchiveOptions {
+    ///     cancellation: Some(token.clone()),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub cancellation: Option<CancellationToken>,
+    /// Called with each [`ArchiveEvent`] as the archive progresses, e.g.
+    /// to display a progress bar. It may be called from several threads
+    /// at once by the blocking API.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveEvent, ArchiveOptions};
+    /// let options = ArchiveOptions {
+    ///     on_event: Some(&|event: &ArchiveEvent| println!("{:?}", event)),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub on_event: Option<&'a (dyn Fn(&ArchiveEvent) + Sync)>,
+    /// `User-Agent` header to send with every request.
+    ///
+    /// Default: `None`, which doesn't send one
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     user_agent: Some("Mozilla/5.0 (X11; Linux x86_64; rv:85.0)"),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub user_agent: Option<&'a str>,
+    /// `User-Agent` headers to take turns sending, one for each request,
+    /// instead of the [`user_agent`](#structfield.user_agent). The
+    /// [`user_agent`] module has those of common
+    /// browsers. Archiving fails with [`Error::ParseError`] if any of them
+    /// aren't valid headers.
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{user_agent, ArchiveOptions};
+    /// let options = ArchiveOptions {
+    ///     user_agents: vec![user_agent::CHROME_WINDOWS, user_agent::SAFARI_MAC],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub user_agents: Vec<&'a str>,
+    /// `Accept` headers to send for each kind of resource, such as
+    /// [`AcceptHeaders::BROWSER`] so that the archive holds the same
+    /// variants of the resources that a browser would be sent. Archiving
+    /// fails with [`Error::ParseError`] if any of them aren't valid
+    /// headers.
+    ///
+    /// Default: none of them
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{AcceptHeaders, ArchiveOptions};
+    /// let options = ArchiveOptions {
+    ///     accept: AcceptHeaders::BROWSER,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub accept: AcceptHeaders<'a>,
+    /// `Accept-Language` header to send with every request, for sites
+    /// which serve different languages. Archiving fails with
+    /// [`Error::ParseError`] if it isn't a valid header.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     accept_language: Some("en-GB,en;q=0.9"),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub accept_language: Option<&'a str>,
+    /// Extra headers to send with every request, as name and value
+    /// pairs. Archiving fails with [`Error::ParseError`] if any of them
+    /// aren't valid headers.
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     headers: vec![("Accept-Language", "en-GB")],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub headers: Vec<(&'a str, &'a str)>,
+    /// Extra headers to send with requests to particular hosts, as host,
+    /// name, and value triples. These replace any of the
+    /// [`headers`](#structfield.headers) with the same name. Archiving
+    /// fails with [`Error::ParseError`] if any of them aren't valid
+    /// headers.
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     host_headers: vec![("api.example.com", "X-Api-Key", "secret")],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub host_headers: Vec<(&'a str, &'a str, &'a str)>,
+    /// Credentials to send in the `Authorization` header. They're only
+    /// sent to the origin of the page being archived, i.e. the same
+    /// scheme, host, and port, so that they don't leak to third parties
+    /// serving its resources or over plain HTTP, and a
+    /// [host header](#structfield.host_headers) named `Authorization`
+    /// takes precedence over them.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, Auth};
+    /// let options = ArchiveOptions {
+    ///     auth: Some(Auth::Basic {
+    ///         username: "user",
+    ///         password: "hunter2",
+    ///     }),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub auth: Option<Auth<'a>>,
+    /// Keep the cookies set by responses and send them with later
+    /// requests, like a browser. The cookies last as long as the client,
+    /// so an [`Archiver`] shares them between pages.
+    ///
+    /// Default: `false`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     cookie_store: true,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub cookie_store: bool,
+    /// Cookies to start off with, e.g. the session cookie of a logged in
+    /// user, as pairs of the URL they were set by and a `Set-Cookie`
+    /// header value. They're sent to the matching domains and paths, as
+    /// with [`cookie_store`](#structfield.cookie_store), which setting
+    /// any cookies turns on. Archiving fails with [`Error::ParseError`]
+    /// if any of the URLs are invalid.
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     cookies: vec![(
+    ///         "https://example.com",
+    ///         "session=abc123; Domain=example.com",
+    ///     )],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub cookies: Vec<(&'a str, &'a str)>,
+    /// How many redirects to follow for each request. Setting this to
+    /// `0` doesn't follow redirects at all.
+    ///
+    /// Default: `10`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     max_redirects: 0,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_redirects: usize,
+    /// Largest resource to archive, in bytes. Anything bigger is skipped
+    /// and left pointing at its original URL. The limit is checked as the
+    /// response is read, so oversized downloads are abandoned as soon as
+    /// they pass it rather than being buffered first. The page itself
+    /// isn't limited.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     max_resource_size: Some(10 * 1024 * 1024),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_resource_size: Option<u64>,
+    /// Most bytes to download across all of the page's resources. Once
+    /// it's used up no more resources are downloaded, and the rest are
+    /// left pointing at their original URLs and recorded in
+    /// [`PageArchive::failures`]. The page itself isn't counted.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     max_total_bytes: Some(100 * 1024 * 1024),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_total_bytes: Option<u64>,
+    /// Most resources to download for the page. As with
+    /// [`max_total_bytes`](#structfield.max_total_bytes), the rest are
+    /// left pointing at their original URLs and recorded in
+    /// [`PageArchive::failures`].
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     max_resources: Some(500),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_resources: Option<usize>,
+    /// Archive images, including SVG images and `image-set()`
+    /// candidates.
+    ///
+    /// Default: `true`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     images: false,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub images: bool,
+    /// Archive scripts, along with the modules that they import.
+    ///
+    /// Default: `true`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     scripts: false,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub scripts: bool,
+    /// Archive stylesheets.
+    ///
+    /// Default: `true`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     stylesheets: false,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub stylesheets: bool,
+    /// Archive fonts, i.e. those from the `@font-face` rules of
+    /// stylesheets and those preloaded by `<link rel="preload">`.
+    ///
+    /// Default: `true`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     fonts: false,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub fonts: bool,
+    /// Archive audio, video, and text tracks, from `<video>`, `<audio>`,
+    /// `<source>`, and `<track>` elements and `<link rel="preload">`.
+    /// Video posters are images, so they're controlled by
+    /// [`ArchiveOptions::images`](#structfield.images) instead.
+    ///
+    /// Default: `true`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     media: false,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub media: bool,
+    /// Archive the resources of `<object>` and `<embed>` elements.
+    ///
+    /// Default: `true`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     objects: false,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub objects: bool,
+    /// Only archive resources whose URL matches at least one of these
+    /// patterns. Every resource is archived if it's empty.
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, Regex};
+    /// let options = ArchiveOptions {
+    ///     include: vec![Regex::new(r"^https://example\.com/").unwrap()],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub include: Vec<Regex>,
+    /// Skip resources whose URL matches any of these patterns, e.g.
+    /// analytics scripts. They're left pointing at their original URLs.
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, Regex};
+    /// let options = ArchiveOptions {
+    ///     exclude: vec![Regex::new(r"google-analytics\.com").unwrap()],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub exclude: Vec<Regex>,
+    /// Called for each resource before it's downloaded, skipping it if
+    /// this returns `false`. Resources are only passed to it if they
+    /// make it past [`include`](#structfield.include) and
+    /// [`exclude`](#structfield.exclude).
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, ResourceUrl};
+    /// let options = ArchiveOptions {
+    ///     filter: Some(&|resource_url: &ResourceUrl| {
+    ///         !resource_url.url().path().starts_with("/ads/")
+    ///     }),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub filter: Option<&'a (dyn Fn(&ResourceUrl) -> bool + Sync)>,
+    /// Only archive resources from the same origin as the page, along
+    /// with any [`allowed_hosts`](#structfield.allowed_hosts), so that
+    /// third-party trackers and CDNs are never contacted. Other resources
+    /// are left pointing at their original URLs, and those redirected
+    /// anywhere else are listed in [`PageArchive::failures`] with
+    /// [`FailureReason::DisallowedHost`].
+    ///
+    /// Default: `false`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     same_origin: true,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub same_origin: bool,
+    /// Hosts to archive resources from. If this isn't empty, resources
+    /// from any other host are skipped, unless
+    /// [`same_origin`](#structfield.same_origin) is set and they come from
+    /// the page's origin.
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     same_origin: true,
+    ///     allowed_hosts: vec!["static.example.com"],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub allowed_hosts: Vec<&'a str>,
+    /// The URL schemes which may be fetched, including by following
+    /// redirects. Archiving a page with any other scheme fails with
+    /// [`Error::DisallowedScheme`], and resources with them are listed in
+    /// [`PageArchive::failures`] with
+    /// [`FailureReason::DisallowedScheme`]. Only `http` and `https` are
+    /// ever fetched, so this is for restricting the archive to `https`.
+    ///
+    /// Default: `["http", "https"]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     schemes: vec!["https"],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub schemes: Vec<&'a str>,
+    /// Skip the resources blocked by an ad and tracker filter list, such
+    /// as EasyList. They're left pointing at their original URLs, and can
+    /// be removed from the page along with the elements that the list
+    /// hides by setting
+    /// [`EmbedOptions::filter_list`](crate::EmbedOptions::filter_list).
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, FilterList};
+    /// let list = FilterList::parse("||ads.example.com^");
+    /// let options = ArchiveOptions {
+    ///     filter_list: Some(&list),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub filter_list: Option<&'a FilterList>,
+    /// Called with each resource after it's downloaded and before it's
+    /// stored in the archive, e.g. to minify, recompress, or redact it.
+    /// The resource is replaced with whatever this returns, or left out
+    /// of the archive if it returns `None`. Resources which are left out
+    /// are left pointing at their original URLs when embedding.
+    ///
+    /// Any resources that a resource refers to, such as the modules
+    /// imported by a script, are found before it's transformed.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, Resource, ResourceUrl};
+    ///
+    /// // Leave out images over 1MB
+    /// let transform = |_: &ResourceUrl, resource: Resource| match resource {
+    ///     Resource::Image(image) if image.data.len() > 1024 * 1024 => None,
+    ///     resource => Some(resource),
+    /// };
+    /// let options = ArchiveOptions {
+    ///     transform: Some(&transform),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub transform:
+        Option<&'a (dyn Fn(&ResourceUrl, Resource) -> Option<Resource> + Sync)>,
+    /// Attributes of `<img>` tags which hold the real URL of a
+    /// lazy-loaded image, while `src` only holds a placeholder. These
+    /// images are archived and their URLs are promoted to `src` (or
+    /// `srcset` for attributes ending in `srcset`) when embedding.
+    ///
+    /// Default: `["data-src", "data-srcset"]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     lazy_attributes: &["data-src", "data-srcset", "data-original"],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub lazy_attributes: &'a [&'a str],
+    /// How many levels of static `import`/`export ... from` statements
+    /// to follow when downloading ES modules. Modules referenced directly
+    /// by the page, including those imported by its inline module
+    /// scripts, are at depth zero, so setting this to `0` only downloads
+    /// those. Bare specifiers (e.g. `import "lodash"`) are
+    /// never followed because they need an import map to resolve.
+    ///
+    /// Default: `5`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     module_depth: 0,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub module_depth: usize,
+    /// How many levels of `<frame>` documents to archive. Frames within
+    /// the page are the first level, and each frame's own resources are
+    /// archived along with it. Setting this to `0` skips frames entirely.
+    ///
+    /// Default: `3`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     frame_depth: 1,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub frame_depth: usize,
+    /// Extensions of files linked by `<a href>` (and `<area href>`) to
+    /// download into the archive, e.g. `["pdf", "docx", "zip"]`. The
+    /// links are pointed at `data:` URIs of the archived files, with a
+    /// `download` attribute so that browsers save them rather than
+    /// navigating to them.
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     attachment_extensions: &["pdf", "docx", "zip"],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub attachment_extensions: &'a [&'a str],
+    /// Download the source maps linked from scripts and stylesheets by
+    /// `sourceMappingURL` comments, which is handy when debugging an
+    /// archived app. The comments are pointed at `data:` URIs of the
+    /// archived maps when embedding, and are removed if the map wasn't
+    /// archived so that browsers don't try to fetch it.
+    ///
+    /// Default: `false`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     source_maps: true,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub source_maps: bool,
+    /// Maximum depth of nested elements to search for resources.
+    /// Anything nested more deeply than this is left as it is. This
+    /// protects against pathological pages, and matches the limit that
+    /// Chromium's HTML parser places on the depth of the DOM.
+    ///
+    /// Default: `512`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     max_depth: 64,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_depth: usize,
+    /// Additional attributes which hold resource URLs, e.g. `data-bg` for
+    /// a lazy-loaded background image. These are usually added with
+    /// [`ArchiveOptions::extra_url_attributes`]. The archived resources
+    /// are embedded into the attributes as `data:` URIs.
+    ///
+    /// Default: `[]`
+    pub url_attributes: Vec<UrlAttribute<'a>>,
+    /// How many resources to download at once. The blocking API uses
+    /// this many threads. A value of `0` is treated as `1`, downloading
+    /// the resources one after another.
+    ///
+    /// Default: `8`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     concurrency: 16,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub concurrency: usize,
+    /// Limit how many requests are made, across every host. Requests wait
+    /// for the limit before being sent, and share it with any other
+    /// archives using a clone of the same [`RateLimit`].
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, RateLimit};
+    /// let options = ArchiveOptions {
+    ///     rate_limit: Some(RateLimit::per_second(10)),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub rate_limit: Option<RateLimit>,
+    /// Limit how many requests are made to each host, as well as any
+    /// overall [`rate_limit`](#structfield.rate_limit). Each host has an
+    /// allowance of its own.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, RateLimit};
+    /// let options = ArchiveOptions {
+    ///     host_rate_limit: Some(RateLimit::per_second(2)),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub host_rate_limit: Option<RateLimit>,
+    /// How many requests to make to each host at once, as well as the
+    /// overall [`concurrency`](#structfield.concurrency). A value of `0`
+    /// is treated as `1`.
+    ///
+    /// Default: `None`, i.e. no limit for each host
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     host_concurrency: Some(2),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub host_concurrency: Option<usize>,
+    /// The least time to leave between starting requests to the same
+    /// host, counting from the request for the page.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions {
+    ///     host_concurrency: Some(1),
+    ///     host_delay: Some(Duration::from_millis(500)),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub host_delay: Option<Duration>,
+    /// How to tell whether resource URLs are for the same resource,
+    /// downloading it once however many of them there are. URLs which
+    /// differ only in their fragment are always treated as the same
+    /// resource. See [`ArchiveOptions::sort_query`] for also ignoring the
+    /// order of their query parameters.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, UrlNormalizer};
+    /// let normalizer = UrlNormalizer::new().sort_query(true).strip_param("v");
+    /// let options = ArchiveOptions {
+    ///     url_normalizer: Some(&normalizer),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub url_normalizer: Option<&'a UrlNormalizer>,
+    /// Where to keep responses between archives, so that archiving the
+    /// same site again only downloads what has changed since. Responses
+    /// are kept as their `Cache-Control`, `Expires`, `ETag`, and
+    /// `Last-Modified` headers allow: fresh ones are used without asking
+    /// the server, and stale ones are only downloaded again if the server
+    /// says that they've changed. See [`HttpCache`] to keep them
+    /// somewhere other than memory.
+    ///
+    /// Unlike an [`Archiver`]'s cache this applies to the page too, and
+    /// lasts beyond the archive.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, MemoryCache};
+    /// let cache = MemoryCache::new();
+    /// let options = ArchiveOptions {
+    ///     http_cache: Some(&cache),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub http_cache: Option<&'a dyn HttpCache>,
+    /// Take a PNG screenshot of the page in a headless browser, as a
+    /// visual reference for when the archived page doesn't look quite
+    /// the same. It's kept in [`PageArchive::screenshot`], and written
+    /// out alongside the page by [`PageArchive::write_to_disk`] and the
+    /// other exports. [`archive_rendered`] takes it of the page it
+    /// renders, and otherwise the archived page, with its resources
+    /// embedded, is opened in Chrome or Chromium for it, which makes
+    /// archiving a good deal slower. Archiving fails with [`Error::Browser`] if the
+    /// screenshot can't be taken.
+    ///
+    /// Default: `None`
+    /// Related feature: `features = ["headless"]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, Screenshot};
+    /// let options = ArchiveOptions {
+    ///     screenshot: Some(Screenshot::FullPage),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    #[cfg(feature = "headless")]
+    pub screenshot: Option<Screenshot>,
+}
+
+impl<'a> ArchiveOptions<'a> {
+    /// Accept invalid certificates, see
+    /// [`ArchiveOptions::accept_invalid_certificates`](#structfield.accept_invalid_certificates)
+    pub fn accept_invalid_certificates(mut self, accept: bool) -> Self {
+        self.accept_invalid_certificates = accept;
+        self
+    }
+
+    /// Turn off certificate checking, see
+    /// [`ArchiveOptions::accept_invalid_certificates`](#structfield.accept_invalid_certificates)
+    pub fn danger_accept_invalid_certificates(self) -> Self {
+        self.accept_invalid_certificates(true)
+    }
+
+    /// Trust the PEM encoded certificates as well as the built-in roots,
+    /// see [`ArchiveOptions::root_certificates`](#structfield.root_certificates)
+    pub fn root_certificate(mut self, pem: &'a [u8]) -> Self {
+        self.root_certificates.push(pem);
+        self
+    }
+
+    /// Present a client certificate, see
+    /// [`ArchiveOptions::client_identity`](#structfield.client_identity)
+    pub fn client_identity(mut self, pem: &'a [u8]) -> Self {
+        self.client_identity = Some(pem);
+        self
+    }
+
+    /// Set the oldest version of TLS to accept, see
+    /// [`ArchiveOptions::min_tls_version`](#structfield.min_tls_version)
+    pub fn min_tls_version(mut self, version: TlsVersion) -> Self {
+        self.min_tls_version = Some(version);
+        self
+    }
+
+    /// Connect via a proxy, see
+    /// [`ArchiveOptions::proxy`](#structfield.proxy)
+    pub fn proxy(mut self, proxy: &'a str) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Send every request through Tor, see
+    /// [`ArchiveOptions::tor`](#structfield.tor)
+    #[cfg(feature = "tor")]
+    pub fn tor(mut self, address: &'a str) -> Self {
+        self.tor = Some(address);
+        self
+    }
+
+    /// Connect to `host` at `address` instead of looking it up, see
+    /// [`ArchiveOptions::resolve`](#structfield.resolve)
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions::default()
+    ///     .resolve("example.com", "10.1.2.3:443")
+    ///     .resolve("www.example.com", "10.1.2.3:443");
+    /// ```
+    pub fn resolve(mut self, host: &'a str, address: &'a str) -> Self {
+        self.resolve.push((host, address));
+        self
+    }
+
+    /// Time out each request after `timeout`, see
+    /// [`ArchiveOptions::timeout`](#structfield.timeout)
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions::default()
+    ///     .timeout(Duration::from_secs(10))
+    ///     .user_agent("Mozilla/5.0 (X11; Linux x86_64; rv:85.0)")
+    ///     .header("Accept-Language", "en-GB")
+    ///     .max_redirects(3)
+    ///     .max_resource_size(10 * 1024 * 1024)
+    ///     .scripts(false);
+    /// ```
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retry failed resources up to `n` times, see
+    /// [`ArchiveOptions::retries`](#structfield.retries)
+    pub fn retries(mut self, n: usize) -> Self {
+        self.retries = n;
+        self
+    }
+
+    /// Stop archiving once `deadline` has passed, see
+    /// [`ArchiveOptions::deadline`](#structfield.deadline)
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Cancel the archive with `token`, see
+    /// [`ArchiveOptions::cancellation`](#structfield.cancellation)
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Report progress to `on_event`, see
+    /// [`ArchiveOptions::on_event`](#structfield.on_event)
+    pub fn on_event(
+        mut self,
+        on_event: &'a (dyn Fn(&ArchiveEvent) + Sync),
+    ) -> Self {
+        self.on_event = Some(on_event);
+        self
+    }
+
+    /// Send a `User-Agent` header, see
+    /// [`ArchiveOptions::user_agent`](#structfield.user_agent)
+    pub fn user_agent(mut self, user_agent: &'a str) -> Self {
+        self.user_agent = Some(user_agent);
+        self
+    }
+
+    /// Take turns sending each of the `User-Agent` headers, see
+    /// [`ArchiveOptions::user_agents`](#structfield.user_agents)
+    pub fn user_agents(mut self, user_agents: &[&'a str]) -> Self {
+        self.user_agents = user_agents.to_vec();
+        self
+    }
+
+    /// Send `Accept` headers for each kind of resource, see
+    /// [`ArchiveOptions::accept`](#structfield.accept)
+    pub fn accept(mut self, accept: AcceptHeaders<'a>) -> Self {
+        self.accept = accept;
+        self
+    }
+
+    /// Send an `Accept-Language` header, see
+    /// [`ArchiveOptions::accept_language`](#structfield.accept_language)
+    pub fn accept_language(mut self, language: &'a str) -> Self {
+        self.accept_language = Some(language);
+        self
+    }
+
+    /// Send an extra header with every request, see
+    /// [`ArchiveOptions::headers`](#structfield.headers)
+    pub fn header(mut self, name: &'a str, value: &'a str) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// Send an extra header with requests to `host`, see
+    /// [`ArchiveOptions::host_headers`](#structfield.host_headers)
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions::default()
+    ///     .header("Accept-Language", "en-GB")
+    ///     .host_header("intranet.example.com", "Accept-Language", "fr");
+    /// ```
+    pub fn host_header(
+        mut self,
+        host: &'a str,
+        name: &'a str,
+        value: &'a str,
+    ) -> Self {
+        self.host_headers.push((host, name, value));
+        self
+    }
+
+    /// Use HTTP Basic authentication, see
+    /// [`ArchiveOptions::auth`](#structfield.auth)
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options =
+    ///     ArchiveOptions::default().basic_auth("user", "hunter2");
+    /// ```
+    pub fn basic_auth(mut self, username: &'a str, password: &'a str) -> Self {
+        self.auth = Some(Auth::Basic { username, password });
+        self
+    }
+
+    /// Authenticate with a bearer token, see
+    /// [`ArchiveOptions::auth`](#structfield.auth)
+    pub fn bearer_token(mut self, token: &'a str) -> Self {
+        self.auth = Some(Auth::Bearer(token));
+        self
+    }
+
+    /// Keep cookies between requests, see
+    /// [`ArchiveOptions::cookie_store`](#structfield.cookie_store)
+    pub fn cookie_store(mut self, enable: bool) -> Self {
+        self.cookie_store = enable;
+        self
+    }
+
+    /// Start off with a cookie set by `url`, see
+    /// [`ArchiveOptions::cookies`](#structfield.cookies)
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions::default()
+    ///     .cookie("https://example.com", "session=abc123; Path=/")
+    ///     .cookie("https://cdn.example.com", "token=xyz");
+    /// ```
+    pub fn cookie(mut self, url: &'a str, cookie: &'a str) -> Self {
+        self.cookies.push((url, cookie));
+        self
+    }
+
+    /// Follow at most `n` redirects, see
+    /// [`ArchiveOptions::max_redirects`](#structfield.max_redirects)
+    pub fn max_redirects(mut self, n: usize) -> Self {
+        self.max_redirects = n;
+        self
+    }
+
+    /// Don't follow redirects at all, see
+    /// [`ArchiveOptions::max_redirects`](#structfield.max_redirects)
+    pub fn no_redirects(self) -> Self {
+        self.max_redirects(0)
+    }
+
+    /// Skip resources over `bytes` in size, see
+    /// [`ArchiveOptions::max_resource_size`](#structfield.max_resource_size)
+    pub fn max_resource_size(mut self, bytes: u64) -> Self {
+        self.max_resource_size = Some(bytes);
+        self
+    }
+
+    /// Stop downloading resources after `bytes` in total, see
+    /// [`ArchiveOptions::max_total_bytes`](#structfield.max_total_bytes)
+    pub fn max_total_bytes(mut self, bytes: u64) -> Self {
+        self.max_total_bytes = Some(bytes);
+        self
+    }
+
+    /// Stop downloading resources after `n` of them, see
+    /// [`ArchiveOptions::max_resources`](#structfield.max_resources)
+    pub fn max_resources(mut self, n: usize) -> Self {
+        self.max_resources = Some(n);
+        self
+    }
+
+    /// Whether to archive images, see
+    /// [`ArchiveOptions::images`](#structfield.images)
+    pub fn images(mut self, archive: bool) -> Self {
+        self.images = archive;
+        self
+    }
+
+    /// Whether to archive scripts, see
+    /// [`ArchiveOptions::scripts`](#structfield.scripts)
+    pub fn scripts(mut self, archive: bool) -> Self {
+        self.scripts = archive;
+        self
+    }
+
+    /// Whether to archive stylesheets, see
+    /// [`ArchiveOptions::stylesheets`](#structfield.stylesheets)
+    pub fn stylesheets(mut self, archive: bool) -> Self {
+        self.stylesheets = archive;
+        self
+    }
+
+    /// Whether to archive fonts, see
+    /// [`ArchiveOptions::fonts`](#structfield.fonts)
+    pub fn fonts(mut self, archive: bool) -> Self {
+        self.fonts = archive;
+        self
+    }
+
+    /// Whether to archive audio and video, see
+    /// [`ArchiveOptions::media`](#structfield.media)
+    pub fn media(mut self, archive: bool) -> Self {
+        self.media = archive;
+        self
+    }
+
+    /// Whether to archive objects and embeds, see
+    /// [`ArchiveOptions::objects`](#structfield.objects)
+    pub fn objects(mut self, archive: bool) -> Self {
+        self.objects = archive;
+        self
+    }
+
+    /// Only archive resources whose URL matches `pattern`, see
+    /// [`ArchiveOptions::include`](#structfield.include)
+    pub fn include(mut self, pattern: Regex) -> Self {
+        self.include.push(pattern);
+        self
+    }
+
+    /// Skip resources whose URL matches `pattern`, see
+    /// [`ArchiveOptions::exclude`](#structfield.exclude)
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, Regex};
+    /// let options = ArchiveOptions::default()
+    ///     .exclude(Regex::new(r"google-analytics\.com").unwrap())
+    ///     .exclude(Regex::new(r"^https://cdn\.example\.com/ads/").unwrap());
+    /// ```
+    pub fn exclude(mut self, pattern: Regex) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
+
+    /// Skip resources for which `filter` returns `false`, see
+    /// [`ArchiveOptions::filter`](#structfield.filter)
+    pub fn filter(
+        mut self,
+        filter: &'a (dyn Fn(&ResourceUrl) -> bool + Sync),
+    ) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Only archive resources from the page's origin, see
+    /// [`ArchiveOptions::same_origin`](#structfield.same_origin)
+    pub fn same_origin(mut self, same_origin: bool) -> Self {
+        self.same_origin = same_origin;
+        self
+    }
+
+    /// Archive resources from `host`, see
+    /// [`ArchiveOptions::allowed_hosts`](#structfield.allowed_hosts)
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions::default()
+    ///     .same_origin(true)
+    ///     .allowed_host("static.example.com");
+    /// ```
+    pub fn allowed_host(mut self, host: &'a str) -> Self {
+        self.allowed_hosts.push(host);
+        self
+    }
+
+    /// Only fetch URLs with these schemes, see
+    /// [`ArchiveOptions::schemes`](#structfield.schemes)
+    pub fn schemes(mut self, schemes: &[&'a str]) -> Self {
+        self.schemes = schemes.to_vec();
+        self
+    }
+
+    /// Only fetch `https` URLs, see
+    /// [`ArchiveOptions::schemes`](#structfield.schemes)
+    pub fn https_only(self) -> Self {
+        self.schemes(&["https"])
+    }
+
+    /// Skip resources blocked by `list`, see
+    /// [`ArchiveOptions::filter_list`](#structfield.filter_list)
+    pub fn filter_list(mut self, list: &'a FilterList) -> Self {
+        self.filter_list = Some(list);
+        self
+    }
+
+    /// Transform each resource before it's stored, see
+    /// [`ArchiveOptions::transform`](#structfield.transform)
+    pub fn transform(
+        mut self,
+        transform: &'a (dyn Fn(&ResourceUrl, Resource) -> Option<Resource>
+                 + Sync),
+    ) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    /// Download up to `n` resources at once, see
+    /// [`ArchiveOptions::concurrency`](#structfield.concurrency)
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::ArchiveOptions;
+    /// let options = ArchiveOptions::default().concurrency(16);
+    /// ```
+    pub fn concurrency(mut self, n: usize) -> Self {
+        self.concurrency = n;
+        self
+    }
+
+    /// Limit how many requests are made, see
+    /// [`ArchiveOptions::rate_limit`](#structfield.rate_limit)
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Limit how many requests are made to each host, see
+    /// [`ArchiveOptions::host_rate_limit`](#structfield.host_rate_limit)
+    pub fn host_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.host_rate_limit = Some(limit);
+        self
+    }
+
+    /// Make up to `n` requests to each host at once, see
+    /// [`ArchiveOptions::host_concurrency`](#structfield.host_concurrency)
+    pub fn host_concurrency(mut self, n: usize) -> Self {
+        self.host_concurrency = Some(n);
+        self
+    }
+
+    /// Leave at least `delay` between requests to the same host, see
+    /// [`ArchiveOptions::host_delay`](#structfield.host_delay)
+    pub fn host_delay(mut self, delay: Duration) -> Self {
+        self.host_delay = Some(delay);
+        self
+    }
+
+    /// Treat resource URLs which differ only in the order of their query
+    /// parameters as the same resource, e.g. `logo.png?w=64&h=64` and
+    /// `logo.png?h=64&w=64`, downloading it once. This sets
+    /// [`ArchiveOptions::url_normalizer`](#structfield.url_normalizer) to
+    /// a normalizer which only sorts the query, in place of any set
+    /// already.
+    pub fn sort_query(mut self) -> Self {
+        self.url_normalizer = Some(&SORT_QUERY);
+        self
+    }
+
+    /// Tell whether resource URLs are the same with `normalizer`, see
+    /// [`ArchiveOptions::url_normalizer`](#structfield.url_normalizer)
+    pub fn url_normalizer(mut self, normalizer: &'a UrlNormalizer) -> Self {
+        self.url_normalizer = Some(normalizer);
+        self
+    }
+
+    /// Keep responses between archives, see
+    /// [`ArchiveOptions::http_cache`](#structfield.http_cache)
+    pub fn http_cache(mut self, cache: &'a dyn HttpCache) -> Self {
+        self.http_cache = Some(cache);
+        self
+    }
+
+    /// Take a screenshot of the page, of all of it if `full_page` is set
+    /// or of what fits in the browser's window otherwise, see
+    /// [`ArchiveOptions::screenshot`](#structfield.screenshot)
+    #[cfg(feature = "headless")]
+    pub fn screenshot(mut self, full_page: bool) -> Self {
+        self.screenshot = Some(if full_page {
+            Screenshot::FullPage
+        } else {
+            Screenshot::Viewport
+        });
+        self
+    }
+
+    /// Look for resource URLs in the `attribute` of `tag` elements, and
+    /// archive them as the given kind of resource. A `tag` of `*`
+    /// matches every element.
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{ArchiveOptions, ResourceUrl};
+    /// let options = ArchiveOptions::default()
+    ///     .extra_url_attributes("div", "data-bg", ResourceUrl::Image)
+    ///     .extra_url_attributes("video", "data-poster", ResourceUrl::Image);
+    /// ```
+    pub fn extra_url_attributes(
+        mut self,
+        tag: &'a str,
+        attribute: &'a str,
+        kind: fn(Url) -> ResourceUrl,
+    ) -> Self {
+        self.url_attributes.push(UrlAttribute {
+            tag,
+            attribute,
+            kind,
+        });
+        self
+    }
+
+    /// Whether resources at `url` may be archived for the page at `page`,
+    /// going by `same_origin` and `allowed_hosts`
+    pub(crate) fn allows_origin(&self, page: &Url, url: &Url) -> bool {
+        client::allows_origin(self.same_origin, &self.allowed_hosts, page, url)
+    }
+
+    /// Whether `url` may be fetched, going by `schemes`
+    pub(crate) fn allows_scheme(&self, url: &Url) -> bool {
+        self.schemes
+            .iter()
+            .any(|scheme| scheme.eq_ignore_ascii_case(url.scheme()))
+    }
+
+    /// Whether a resource should be archived, going by its kind and the
+    /// filters
+    pub(crate) fn wants(&self, resource_url: &ResourceUrl) -> bool {
+        use ResourceUrl::*;
+
+        let url = resource_url.url().as_str();
+        if !self.include.is_empty()
+            && !self.include.iter().any(|r| r.is_match(url))
+        {
+            return false;
+        }
+        if self.exclude.iter().any(|r| r.is_match(url)) {
+            return false;
+        }
+        if let Some(filter) = self.filter {
+            if !filter(resource_url) {
+                return false;
+            }
+        }
+        match resource_url {
+            Image(_) => self.images,
+            Javascript(_) => self.scripts,
+            Css(_) => self.stylesheets,
+            Font(_) => self.fonts,
+            Media(_) => self.media,
+            Object(_) => self.objects,
+            Document(_) | Attachment(_) | Other(_) => true,
+        }
+    }
+
+    /// Whether a resource of the page at `page` is left out of its
+    /// archive, going by its kind, the filters, the origins allowed, and
+    /// `filter_list`
+    pub(crate) fn skips(&self, page: &Url, resource_url: &ResourceUrl) -> bool {
+        !self.wants(resource_url)
+            || !self.allows_origin(page, resource_url.url())
+            || self
+                .filter_list
+                .map_or(false, |list| list.blocks(resource_url, page))
+    }
+
+    /// Report an event to `on_event`
+    pub(crate) fn emit(&self, event: ArchiveEvent) {
+        if let Some(on_event) = self.on_event {
+            on_event(&event);
+        }
+    }
+
+    /// Whether the archive has been cancelled
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .map(CancellationToken::is_cancelled)
+            .unwrap_or(false)
+    }
+
+    /// How long to wait before requesting `url`, going by `rate_limit`
+    /// and `host_rate_limit`. This uses up some of their allowance, so
+    /// the request should follow.
+    pub(crate) fn rate_limit_delay(&self, url: &Url) -> Duration {
+        let overall = self.rate_limit.as_ref().map(|limit| limit.reserve(""));
+        let host = self
+            .host_rate_limit
+            .as_ref()
+            .map(|limit| limit.reserve(url.host_str().unwrap_or("")));
+        overall.into_iter().chain(host).max().unwrap_or_default()
+    }
+
+    /// Timeout for a request made before `deadline`, if there is one.
+    /// This is `Some(Duration::ZERO)` once the deadline has passed.
+    pub(crate) fn request_timeout(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Option<Duration> {
+        let remaining =
+            deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        match (self.timeout, remaining) {
+            (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+            (timeout, remaining) => timeout.or(remaining),
+        }
+    }
+
+    /// Whether a resource of `len` bytes is too large to archive
+    pub(crate) fn exceeds_resource_size(&self, len: u64) -> bool {
+        self.max_resource_size.map(|max| len > max).unwrap_or(false)
+    }
+}
+
+/// An attribute holding a resource URL, see
+/// [`ArchiveOptions::extra_url_attributes`]
+#[derive(Clone, Copy, Debug)]
+pub struct UrlAttribute<'a> {
+    /// Name of the element, or `*` for any element
+    pub tag: &'a str,
+    /// Name of the attribute
+    pub attribute: &'a str,
+    /// Kind of resource that the URL points at, e.g. [`ResourceUrl::Image`]
+    pub kind: fn(Url) -> ResourceUrl,
+}
+
+impl<'a> Default for ArchiveOptions<'a> {
+    fn default() -> Self {
+        Self {
+            accept_invalid_certificates: false,
+            root_certificates: Vec::new(),
+            client_identity: None,
+            min_tls_version: None,
+            proxy: None,
+            #[cfg(feature = "tor")]
+            tor: None,
+            resolve: Vec::new(),
+            timeout: None,
+            retries: 0,
+            deadline: None,
+            cancellation: None,
+            on_event: None,
+            user_agent: None,
+            user_agents: Vec::new(),
+            accept: AcceptHeaders::default(),
+            accept_language: None,
+            headers: Vec::new(),
+            host_headers: Vec::new(),
+            auth: None,
+            cookie_store: false,
+            cookies: Vec::new(),
+            max_redirects: 10,
+            max_resource_size: None,
+            max_total_bytes: None,
+            max_resources: None,
+            images: true,
+            scripts: true,
+            stylesheets: true,
+            fonts: true,
+            media: true,
+            objects: true,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            filter: None,
+            same_origin: false,
+            allowed_hosts: Vec::new(),
+            schemes: vec!["http", "https"],
+            filter_list: None,
+            transform: None,
+            lazy_attributes: &["data-src", "data-srcset"],
+            module_depth: 5,
+            frame_depth: 3,
+            attachment_extensions: &[],
+            source_maps: false,
+            max_depth: 512,
+            url_attributes: Vec::new(),
+            concurrency: 8,
+            rate_limit: None,
+            host_rate_limit: None,
+            host_concurrency: None,
+            host_delay: None,
+            url_normalizer: None,
+            http_cache: None,
+            #[cfg(feature = "headless")]
+            screenshot: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio_test::block_on;
+
+    #[test]
+    fn parse_invalid_url_async() {
+        let u = "this~is~not~a~url";
+
+        let res = block_on(archive(u, Default::default()));
+        assert!(res.is_err());
+
+        if let Err(Error::ParseError(_err)) = res {
+            // Okay, it's a parse error
+        } else {
+            panic!("Expected parse error");
+        }
+    }
+
+    #[test]
+    fn disallowed_scheme_async() {
+        let options = ArchiveOptions::default().https_only();
+        let res = block_on(archive("http://example.com/", options));
+        assert!(matches!(res, Err(Error::DisallowedScheme(_))));
+    }
+
+    #[test]
+    fn cancelled_before_page_async() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let options = ArchiveOptions::default().cancellation(token);
+        let res = block_on(archive("http://example.com", options));
+        assert!(matches!(res, Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn archive_to_file_async() {
+        let path = std::env::temp_dir()
+            .join(format!("web-archive-to-file-{}.html", std::process::id()));
+        let res = block_on(archive_to_file(
+            "this~is~not~a~url",
+            &path,
+            Default::default(),
+        ));
+        assert!(matches!(res, Err(Error::ParseError(_))));
+        let options = ArchiveOptions::default().https_only();
+        let res =
+            block_on(archive_to_file("http://example.com/", &path, options));
+        assert!(matches!(res, Err(Error::DisallowedScheme(_))));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn discover_async() {
+        let res = block_on(discover("this~is~not~a~url", Default::default()));
+        assert!(matches!(res, Err(Error::ParseError(_))));
+        let options = ArchiveOptions::default().https_only();
+        let res = block_on(discover("http://example.com/", options));
+        assert!(matches!(res, Err(Error::DisallowedScheme(_))));
+    }
+
+    #[test]
+    fn archive_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        // Archives are often fetched from spawned tasks
+        let future = archive("http://example.com", Default::default());
+        assert_send(&future);
+        let client = reqwest::Client::new();
+        let future = archive_with_client(
+            &client,
+            "http://example.com",
+            Default::default(),
+        );
+        assert_send(&future);
+        assert_send(&PageArchive {
+            url: Url::parse("http://example.com").unwrap(),
+            content: String::new(),
+            resource_map: ResourceMap::new(),
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        });
+    }
+
+    #[test]
+    fn request_timeout() {
+        let options = ArchiveOptions::default();
+        assert_eq!(options.request_timeout(None), None);
+
+        let options = options.timeout(Duration::from_secs(10));
+        assert_eq!(
+            options.request_timeout(None),
+            Some(Duration::from_secs(10))
+        );
+        let soon = Instant::now() + Duration::from_secs(5);
+        assert!(
+            options.request_timeout(Some(soon)).unwrap()
+                <= Duration::from_secs(5)
+        );
+        let later = Instant::now() + Duration::from_secs(60);
+        assert_eq!(
+            options.request_timeout(Some(later)),
+            Some(Duration::from_secs(10))
+        );
+        let past = Instant::now() - Duration::from_secs(1);
+        assert_eq!(options.request_timeout(Some(past)), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn rate_limit_delay() {
+        let a = Url::parse("http://a.example.com/").unwrap();
+        let b = Url::parse("http://b.example.com/").unwrap();
+        let options = ArchiveOptions::default();
+        assert_eq!(options.rate_limit_delay(&a), Duration::ZERO);
+
+        let minute = Duration::from_secs(60);
+        let options = options.host_rate_limit(RateLimit::new(1, minute));
+        assert_eq!(options.rate_limit_delay(&a), Duration::ZERO);
+        assert_eq!(options.rate_limit_delay(&b), Duration::ZERO);
+        assert!(options.rate_limit_delay(&a) > Duration::from_secs(59));
+
+        let options = options.rate_limit(RateLimit::new(1, minute));
+        let c = Url::parse("http://c.example.com/").unwrap();
+        let d = Url::parse("http://d.example.com/").unwrap();
+        assert_eq!(options.rate_limit_delay(&c), Duration::ZERO);
+        assert!(options.rate_limit_delay(&d) > Duration::from_secs(59));
     }
 }
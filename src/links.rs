@@ -0,0 +1,112 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for finding the links out of a page, see [`PageArchive::links`]
+
+use crate::metadata::collapse;
+use crate::parsing::base_url;
+use kuchiki::iter::NodeIterator;
+use kuchiki::NodeRef;
+use url::Url;
+
+/// A link from a page, found by
+/// [`PageArchive::links`](crate::PageArchive::links)
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Link {
+    /// Where the link points, resolved against the page's URL
+    pub url: Url,
+    /// The keywords of the link's `rel` attribute, in lower case, such as
+    /// `nofollow` or `noopener`
+    pub rel: Vec<String>,
+    /// The text of the link, with its whitespace collapsed. Links holding
+    /// only an image have the image's `alt` text instead.
+    pub text: String,
+}
+
+/// The links from `<a href>` and `<area href>` elements in the page, in
+/// the order they appear
+pub(crate) fn links(page_url: &Url, document: &NodeRef) -> Vec<Link> {
+    let base = base_url(page_url, document);
+    let mut links = Vec::new();
+    for element in document.select("a[href], area[href]").unwrap() {
+        let attributes = element.attributes.borrow();
+        let href = attributes.get("href").unwrap_or("").trim();
+        let url = match base.join(href) {
+            Ok(url) if url.scheme() != "javascript" => url,
+            _ => continue,
+        };
+        let rel = attributes
+            .get("rel")
+            .unwrap_or("")
+            .split_ascii_whitespace()
+            .map(|keyword| keyword.to_ascii_lowercase())
+            .collect();
+        let mut text = collapse(&element.text_contents());
+        if text.is_empty() {
+            let alt = if &*element.name.local == "area" {
+                attributes.get("alt").map(collapse)
+            } else {
+                element
+                    .as_node()
+                    .descendants()
+                    .elements()
+                    .filter(|e| &*e.name.local == "img")
+                    .find_map(|img| {
+                        img.attributes.borrow().get("alt").map(collapse)
+                    })
+            };
+            text = alt.unwrap_or_default();
+        }
+        links.push(Link { url, rel, text });
+    }
+    links
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kuchiki::parse_html;
+    use kuchiki::traits::TendrilSink;
+
+    #[test]
+    fn test_links() {
+        let document = parse_html().one(
+            "<a href=\"/about\">About\n  us</a>\
+            <a href=\" https://example.org/ \" rel=\"NoFollow noopener\">\
+            Elsewhere</a>\
+            <a href=\"#top\"><img src=\"up.png\" alt=\"Back to top\"></a>\
+            <a href=\"javascript:void(0)\">Nowhere</a>\
+            <a name=\"anchor\">Not a link</a>\
+            <a href=\"mailto:me@example.com\"></a>\
+            <map><area href=\"map/north\" alt=\"North\"></map>",
+        );
+        let url = Url::parse("http://example.com/page").unwrap();
+        let links: Vec<_> = links(&url, &document)
+            .into_iter()
+            .map(|link| (link.url.to_string(), link.rel, link.text))
+            .collect();
+        let link = |url: &str, rel: &[&str], text: &str| {
+            let rel = rel.iter().map(|r| r.to_string()).collect();
+            (url.to_string(), rel, text.to_string())
+        };
+        assert_eq!(
+            links,
+            vec![
+                link("http://example.com/about", &[], "About us"),
+                link(
+                    "https://example.org/",
+                    &["nofollow", "noopener"],
+                    "Elsewhere"
+                ),
+                link("http://example.com/page#top", &[], "Back to top"),
+                link("mailto:me@example.com", &[], ""),
+                link("http://example.com/map/north", &[], "North"),
+            ]
+        );
+    }
+}
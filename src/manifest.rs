@@ -0,0 +1,449 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for the manifest describing an archive written to disk, which
+//! lets it be read back in again
+
+use crate::digest;
+use crate::disk::{INDEX, SCREENSHOT};
+use crate::http_cache::Validators;
+use crate::meta::ResourceMeta;
+use crate::page_archive::PageArchive;
+use crate::parsing::{ImageResource, Resource, TextResource};
+use bytes::Bytes;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+use url::Url;
+
+/// Version of the manifest format, bumped if it changes incompatibly
+const VERSION: u64 = 1;
+
+/// Everything about an archive that isn't in the files themselves
+#[derive(Debug, PartialEq)]
+pub(crate) struct Manifest {
+    pub url: Url,
+    pub lazy_attributes: Vec<String>,
+    pub url_attributes: Vec<(String, String)>,
+    pub resources: Vec<Entry>,
+    pub timed_out: Vec<Url>,
+    pub cancelled: bool,
+    pub redirects: HashMap<Url, Vec<Url>>,
+    pub meta: HashMap<Url, ResourceMeta>,
+    /// Path of the screenshot, if there is one
+    pub screenshot: Option<Vec<String>>,
+}
+
+/// Where a resource was written, and what it is
+#[derive(Debug, PartialEq)]
+pub(crate) struct Entry {
+    pub url: Url,
+    pub path: Vec<String>,
+    pub kind: String,
+    pub mimetype: String,
+    pub charset: Option<String>,
+}
+
+impl Entry {
+    /// The resource, given the `data` read back from its file
+    pub fn resource(&self, data: Bytes) -> Option<Resource> {
+        let text = || TextResource {
+            data: data.clone(),
+            mimetype: self.mimetype.clone(),
+            charset: self.charset.clone(),
+        };
+        let image = || ImageResource {
+            data: data.clone(),
+            mimetype: self.mimetype.clone(),
+        };
+        Some(match self.kind.as_str() {
+            "javascript" => Resource::Javascript(text()),
+            "css" => Resource::Css(text()),
+            "document" => Resource::Document(text()),
+            "image" => Resource::Image(image()),
+            "object" => Resource::Object(image()),
+            "font" => Resource::Font(image()),
+            "media" => Resource::Media(image()),
+            "attachment" => Resource::Attachment(image()),
+            "other" => Resource::Other {
+                data: data.clone(),
+                mimetype: self.mimetype.clone(),
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Name of the kind of resource in the manifest
+fn kind(resource: &Resource) -> &'static str {
+    match resource {
+        Resource::Javascript(_) => "javascript",
+        Resource::Css(_) => "css",
+        Resource::Document(_) => "document",
+        Resource::Image(_) => "image",
+        Resource::Object(_) => "object",
+        Resource::Font(_) => "font",
+        Resource::Media(_) => "media",
+        Resource::Attachment(_) => "attachment",
+        Resource::Other { .. } => "other",
+    }
+}
+
+/// Describe the archive, whose resources without their fragments were
+/// written to `paths`, with the page and resources written to `files`
+pub(crate) fn to_string(
+    archive: &PageArchive,
+    paths: &HashMap<Url, Vec<String>>,
+    files: &[(Vec<String>, Bytes)],
+) -> String {
+    let files: HashMap<_, _> =
+        files.iter().map(|(path, data)| (&path[..], data)).collect();
+    // The size and hash of the file at `path`, and when the resource at
+    // `url` was fetched
+    let file = |path: &[String], url: &Url| {
+        let data = files.get(path);
+        let hash = data.map(|data| digest::sha256(data));
+        let fetched_at = archive
+            .meta
+            .get(url)
+            .map(|meta| httpdate::fmt_http_date(meta.fetched_at));
+        (data.map(|data| data.len()), hash, fetched_at)
+    };
+
+    let mut resources: Vec<_> = archive.resource_map.iter().collect();
+    resources.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    let resources: Vec<_> = resources
+        .into_iter()
+        .map(|(url, resource)| {
+            let mut key = url.clone();
+            key.set_fragment(None);
+            // Text resources are written out as UTF-8
+            let charset = match resource {
+                Resource::Javascript(r)
+                | Resource::Css(r)
+                | Resource::Document(r) => r.charset.as_ref().map(|_| "utf-8"),
+                _ => None,
+            };
+            let path = &paths[&key];
+            let (size, sha256, fetched_at) = file(path, &key);
+            json!({
+                "url": url.as_str(),
+                "path": path.join("/"),
+                "kind": kind(resource),
+                "mimetype": resource.mimetype(),
+                "charset": charset,
+                "size": size,
+                "sha256": sha256,
+                "fetched_at": fetched_at,
+            })
+        })
+        .collect();
+
+    let redirects: Map<_, _> = archive
+        .redirects
+        .iter()
+        .map(|(url, chain)| (url.to_string(), json!(strings(chain))))
+        .collect();
+    let meta: Map<_, _> = archive
+        .meta
+        .iter()
+        .map(|(url, meta)| {
+            let value = json!({
+                "status": meta.status,
+                "final_url": meta.final_url.as_str(),
+                "fetched_at": httpdate::fmt_http_date(meta.fetched_at),
+                "content_type": meta.content_type,
+                "cache_control": meta.cache_control,
+                "etag": meta.validators.etag,
+                "last_modified": meta.validators.last_modified,
+                "snapshot": meta.snapshot.as_ref().map(Url::as_str),
+                "sha256": meta.sha256,
+            });
+            (url.to_string(), value)
+        })
+        .collect();
+
+    let index = [INDEX.to_string()];
+    let (size, sha256, fetched_at) = file(&index, &archive.url);
+    let page = json!({
+        "path": INDEX,
+        "size": size,
+        "sha256": sha256,
+        "fetched_at": fetched_at,
+    });
+    let screenshot = archive.screenshot.as_ref().map(|_| {
+        let (size, sha256, _) = file(&[SCREENSHOT.to_string()], &archive.url);
+        json!({
+            "path": SCREENSHOT,
+            "size": size,
+            "sha256": sha256,
+        })
+    });
+
+    let manifest = json!({
+        "version": VERSION,
+        "url": archive.url.as_str(),
+        "page": page,
+        "lazy_attributes": archive.lazy_attributes,
+        "url_attributes": archive.url_attributes,
+        "resources": resources,
+        "timed_out": strings(&archive.timed_out),
+        "cancelled": archive.cancelled,
+        "redirects": redirects,
+        "meta": meta,
+        "screenshot": screenshot,
+    });
+    serde_json::to_string_pretty(&manifest).unwrap()
+}
+
+fn strings(urls: &[Url]) -> Vec<&str> {
+    urls.iter().map(Url::as_str).collect()
+}
+
+/// Read back a manifest written by [`to_string`]
+pub(crate) fn parse(text: &str) -> io::Result<Manifest> {
+    let manifest: Value = serde_json::from_str(text).map_err(invalid)?;
+    let version = manifest["version"].as_u64();
+    if version != Some(VERSION) {
+        return Err(invalid(format!(
+            "unsupported manifest version: {}",
+            manifest["version"]
+        )));
+    }
+
+    let resources = array(&manifest["resources"])?
+        .iter()
+        .map(|entry| {
+            Ok(Entry {
+                url: url(&entry["url"])?,
+                path: string(&entry["path"])?
+                    .split('/')
+                    .map(str::to_string)
+                    .collect(),
+                kind: string(&entry["kind"])?.to_string(),
+                mimetype: string(&entry["mimetype"])?.to_string(),
+                charset: optional(&entry["charset"])?,
+            })
+        })
+        .collect::<io::Result<_>>()?;
+    let url_attributes = array(&manifest["url_attributes"])?
+        .iter()
+        .map(|pair| {
+            Ok((string(&pair[0])?.to_string(), string(&pair[1])?.to_string()))
+        })
+        .collect::<io::Result<_>>()?;
+    let redirects = object(&manifest["redirects"])?
+        .iter()
+        .map(|(from, chain)| Ok((parse_url(from)?, urls(chain)?)))
+        .collect::<io::Result<_>>()?;
+    let meta = object(&manifest["meta"])?
+        .iter()
+        .map(|(u, meta)| {
+            let fetched_at =
+                httpdate::parse_http_date(string(&meta["fetched_at"])?)
+                    .map_err(invalid)?;
+            let meta = ResourceMeta {
+                status: meta["status"]
+                    .as_u64()
+                    .and_then(|status| status.try_into().ok())
+                    .ok_or_else(|| invalid("invalid status"))?,
+                final_url: url(&meta["final_url"])?,
+                fetched_at,
+                content_type: optional(&meta["content_type"])?,
+                cache_control: optional(&meta["cache_control"])?,
+                validators: Validators {
+                    etag: optional(&meta["etag"])?,
+                    last_modified: optional(&meta["last_modified"])?,
+                },
+                snapshot: optional(&meta["snapshot"])?
+                    .map(|snapshot| parse_url(&snapshot))
+                    .transpose()?,
+                sha256: optional(&meta["sha256"])?,
+            };
+            Ok((parse_url(u)?, meta))
+        })
+        .collect::<io::Result<_>>()?;
+
+    Ok(Manifest {
+        url: url(&manifest["url"])?,
+        lazy_attributes: array(&manifest["lazy_attributes"])?
+            .iter()
+            .map(|name| string(name).map(str::to_string))
+            .collect::<io::Result<_>>()?,
+        url_attributes,
+        resources,
+        timed_out: urls(&manifest["timed_out"])?,
+        cancelled: manifest["cancelled"].as_bool().unwrap_or(false),
+        redirects,
+        meta,
+        screenshot: match &manifest["screenshot"]["path"] {
+            Value::Null => None,
+            path => {
+                Some(string(path)?.split('/').map(str::to_string).collect())
+            }
+        },
+    })
+}
+
+fn invalid<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn array(value: &Value) -> io::Result<&Vec<Value>> {
+    value.as_array().ok_or_else(|| invalid("expected an array"))
+}
+
+fn object(value: &Value) -> io::Result<&Map<String, Value>> {
+    value
+        .as_object()
+        .ok_or_else(|| invalid("expected an object"))
+}
+
+fn string(value: &Value) -> io::Result<&str> {
+    value.as_str().ok_or_else(|| invalid("expected a string"))
+}
+
+fn optional(value: &Value) -> io::Result<Option<String>> {
+    match value {
+        Value::Null => Ok(None),
+        value => string(value).map(|s| Some(s.to_string())),
+    }
+}
+
+fn parse_url(u: &str) -> io::Result<Url> {
+    Url::parse(u).map_err(invalid)
+}
+
+fn url(value: &Value) -> io::Result<Url> {
+    parse_url(string(value)?)
+}
+
+fn urls(value: &Value) -> io::Result<Vec<Url>> {
+    array(value)?.iter().map(url).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stats::ArchiveStats;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_manifest() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let style = url.join("style.css").unwrap();
+        let mut resource_map = HashMap::new();
+        resource_map.insert(
+            style.clone(),
+            Resource::Css(TextResource {
+                data: Bytes::from_static(b"a {}"),
+                mimetype: "text/css".to_string(),
+                charset: Some("Shift_JIS".to_string()),
+            }),
+        );
+        let mut meta = HashMap::new();
+        meta.insert(
+            style.clone(),
+            ResourceMeta {
+                status: 200,
+                final_url: style.clone(),
+                fetched_at: UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+                content_type: Some("text/css".to_string()),
+                cache_control: None,
+                validators: Validators {
+                    etag: Some("\"abc\"".to_string()),
+                    last_modified: None,
+                },
+                snapshot: Some(
+                    Url::parse("https://web.archive.org/web/2020/style.css")
+                        .unwrap(),
+                ),
+                sha256: Some(crate::digest::sha256(b"a {}")),
+            },
+        );
+        let mut redirects = HashMap::new();
+        redirects.insert(url.join("old").unwrap(), vec![url.clone()]);
+        let archive = PageArchive {
+            url: url.clone(),
+            content: String::new(),
+            resource_map,
+            lazy_attributes: vec!["data-src".to_string()],
+            url_attributes: vec![("img".to_string(), "data-hi".to_string())],
+            timed_out: vec![url.join("slow.png").unwrap()],
+            failures: Vec::new(),
+            cancelled: true,
+            redirects: redirects.clone(),
+            meta: meta.clone(),
+            stats: ArchiveStats::default(),
+            screenshot: Some(Bytes::from_static(b"\x89PNG")),
+        };
+        let mut paths = HashMap::new();
+        let path: Vec<_> = ["resources", "example.com", "style.css"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        paths.insert(style.clone(), path.clone());
+
+        let files = vec![
+            (
+                vec![INDEX.to_string()],
+                Bytes::from_static(b"<html></html>"),
+            ),
+            (path.clone(), Bytes::from_static(b"a {}")),
+            (vec![SCREENSHOT.to_string()], Bytes::from_static(b"\x89PNG")),
+        ];
+
+        let text = to_string(&archive, &paths, &files);
+        let value: Value = serde_json::from_str(&text).unwrap();
+        let entry = &value["resources"][0];
+        assert_eq!(entry["size"], 4);
+        assert_eq!(
+            entry["sha256"],
+            "9a4487ccbedf68e53bfd4feff149a0fb4e7c47af7ebcdd80db1984dc5cf5abdf"
+        );
+        assert_eq!(entry["fetched_at"], "Sun, 13 Sep 2020 12:26:40 GMT");
+        assert_eq!(value["page"]["path"], INDEX);
+        assert_eq!(value["page"]["size"], 13);
+        assert_eq!(value["page"]["fetched_at"], Value::Null);
+        assert_eq!(value["screenshot"]["size"], 4);
+
+        let manifest = parse(&text).unwrap();
+        assert_eq!(
+            manifest,
+            Manifest {
+                url,
+                lazy_attributes: archive.lazy_attributes.clone(),
+                url_attributes: archive.url_attributes.clone(),
+                resources: vec![Entry {
+                    url: style,
+                    path,
+                    kind: "css".to_string(),
+                    mimetype: "text/css".to_string(),
+                    charset: Some("utf-8".to_string()),
+                }],
+                timed_out: archive.timed_out.clone(),
+                cancelled: true,
+                redirects,
+                meta,
+                screenshot: Some(vec![SCREENSHOT.to_string()]),
+            }
+        );
+        let resource = manifest.resources[0].resource(Bytes::new());
+        assert!(matches!(resource, Some(Resource::Css(_))));
+
+        assert!(parse("{}").is_err());
+        assert!(parse("not json").is_err());
+        assert_eq!(
+            parse(r#"{"version": 2}"#).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
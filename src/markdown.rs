@@ -0,0 +1,681 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for converting archived pages to Markdown, see
+//! [`PageArchive::to_markdown`]
+
+use crate::disk::{Layout, INDEX};
+use crate::page_archive::PageArchive;
+use crate::parsing::{base_url, is_srcset_attribute, parse_srcset};
+use kuchiki::iter::NodeIterator;
+use kuchiki::traits::TendrilSink;
+use kuchiki::{parse_html, NodeData, NodeRef};
+use url::Url;
+
+/// Options controlling how a page is converted to Markdown by
+/// [`PageArchive::to_markdown_with`]
+#[derive(Clone, Debug, Default)]
+pub struct MarkdownOptions {
+    /// Where the images in the Markdown point
+    ///
+    /// Default: [`MarkdownImages::Original`]
+    pub images: MarkdownImages,
+}
+
+/// Where the images in a page converted to Markdown point, see
+/// [`MarkdownOptions::images`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MarkdownImages {
+    /// The original absolute URLs of the images, so that they're loaded
+    /// from the network
+    #[default]
+    Original,
+    /// `data:` URIs of the archived images, so that the Markdown stands
+    /// alone. Images which weren't archived point at their original URLs.
+    DataUri,
+    /// The files that [`PageArchive::write_to_disk`] writes the archived
+    /// images to, relative to its `index.html`, so that the Markdown can
+    /// be saved alongside it. Images which weren't archived point at
+    /// their original URLs.
+    Files,
+}
+
+/// Convert the archive's page to Markdown
+pub(crate) fn convert(
+    archive: &PageArchive,
+    options: &MarkdownOptions,
+) -> String {
+    let document = parse_html().one(archive.content.as_str());
+    let converter = Converter {
+        archive,
+        base: base_url(&archive.url, &document),
+        images: options.images,
+        layout: match options.images {
+            MarkdownImages::Files => Some(Layout::new(archive)),
+            _ => None,
+        },
+    };
+    let body = document
+        .select_first("body")
+        .map(|body| body.as_node().clone())
+        .unwrap_or(document);
+    let mut markdown = converter.blocks(&body);
+    if !markdown.is_empty() {
+        markdown.push('\n');
+    }
+    markdown
+}
+
+/// Elements which are left out of the Markdown along with their contents
+const SKIPPED: &[&str] = &[
+    "script", "style", "noscript", "template", "head", "title", "svg",
+    "canvas", "iframe", "object", "embed", "button", "input", "select",
+    "textarea",
+];
+
+/// Elements which are laid out as blocks of their own, but are otherwise
+/// left out
+const CONTAINERS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "body",
+    "center",
+    "details",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "header",
+    "html",
+    "legend",
+    "li",
+    "main",
+    "nav",
+    "p",
+    "section",
+    "summary",
+];
+
+/// Markdown being built up from a sequence of blocks
+#[derive(Default)]
+struct Blocks {
+    blocks: Vec<String>,
+    /// The inline content of the current paragraph, with hard line
+    /// breaks as `\n`
+    inline: String,
+}
+
+impl Blocks {
+    /// Finish the current paragraph
+    fn flush(&mut self) {
+        let lines: Vec<_> = self
+            .inline
+            .split('\n')
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(escape_line_start)
+            .collect();
+        if !lines.is_empty() {
+            self.blocks.push(lines.join("\\\n"));
+        }
+        self.inline.clear();
+    }
+
+    /// Add a block after the current paragraph
+    fn push(&mut self, block: String) {
+        self.flush();
+        if !block.trim().is_empty() {
+            self.blocks.push(block);
+        }
+    }
+
+    /// Add text to the current paragraph, collapsing whitespace as a
+    /// browser would
+    fn text(&mut self, text: &str) {
+        for (i, word) in text.split(char::is_whitespace).enumerate() {
+            let at_break =
+                self.inline.is_empty() || self.inline.ends_with([' ', '\n']);
+            if i > 0 && !at_break {
+                self.inline.push(' ');
+            }
+            self.inline.push_str(&escape(word));
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        self.blocks.join("\n\n")
+    }
+}
+
+struct Converter<'a> {
+    archive: &'a PageArchive,
+    /// What relative URLs in the page are resolved against
+    base: Url,
+    images: MarkdownImages,
+    /// Where the resources are written to disk, for
+    /// [`MarkdownImages::Files`]
+    layout: Option<Layout<'a>>,
+}
+
+impl Converter<'_> {
+    /// The Markdown for the contents of `node`
+    fn blocks(&self, node: &NodeRef) -> String {
+        let mut blocks = Blocks::default();
+        self.children(node, &mut blocks);
+        blocks.finish()
+    }
+
+    /// The Markdown for the contents of `node` on a single line
+    fn line(&self, node: &NodeRef) -> String {
+        let markdown = self.blocks(node).replace("\\\n", " ");
+        let words: Vec<_> = markdown.split_whitespace().collect();
+        words.join(" ")
+    }
+
+    fn node(&self, node: &NodeRef, blocks: &mut Blocks) {
+        let element = match node.data() {
+            NodeData::Text(text) => {
+                blocks.text(&text.borrow());
+                return;
+            }
+            NodeData::Element(element) => element,
+            NodeData::Document(_) | NodeData::DocumentFragment => {
+                self.children(node, blocks);
+                return;
+            }
+            _ => return,
+        };
+        let name = &*element.name.local;
+        match name {
+            _ if SKIPPED.contains(&name) => {}
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse().unwrap_or(1);
+                let heading = self.line(node);
+                if !heading.is_empty() {
+                    blocks.push(format!("{} {}", "#".repeat(level), heading));
+                }
+            }
+            "ul" | "ol" => blocks.push(self.list(node, name == "ol")),
+            "blockquote" => {
+                let quote = self.blocks(node);
+                let lines: Vec<_> = quote
+                    .lines()
+                    .map(|line| format!("> {}", line).trim_end().to_string())
+                    .collect();
+                blocks.push(lines.join("\n"));
+            }
+            "pre" => blocks.push(code_block(node)),
+            "hr" => blocks.push("---".to_string()),
+            "table" => blocks.push(self.table(node)),
+            "br" => blocks.inline.push('\n'),
+            "img" => {
+                if let Some(image) = self.image(node) {
+                    blocks.inline.push_str(&image);
+                }
+            }
+            "code" | "kbd" | "samp" | "tt" => {
+                blocks.inline.push_str(&code_span(&node.text_contents()));
+            }
+            "strong" | "b" => self.wrap(node, blocks, "**", "**"),
+            "em" | "i" => self.wrap(node, blocks, "*", "*"),
+            "del" | "s" | "strike" => self.wrap(node, blocks, "~~", "~~"),
+            "a" => {
+                let href = element
+                    .attributes
+                    .borrow()
+                    .get("href")
+                    .and_then(|href| self.link_destination(href));
+                match href {
+                    Some(href) => {
+                        self.wrap(node, blocks, "[", &format!("]({})", href))
+                    }
+                    None => self.children(node, blocks),
+                }
+            }
+            _ if CONTAINERS.contains(&name) => {
+                blocks.flush();
+                self.children(node, blocks);
+                blocks.flush();
+            }
+            _ => self.children(node, blocks),
+        }
+    }
+
+    /// Add the contents of an inline element to the paragraph, between
+    /// `open` and `close`. Whitespace at either end is moved outside of
+    /// them, since Markdown doesn't allow it inside, and they're left
+    /// out if the element is empty.
+    fn wrap(
+        &self,
+        node: &NodeRef,
+        blocks: &mut Blocks,
+        open: &str,
+        close: &str,
+    ) {
+        let start = blocks.inline.len();
+        let count = blocks.blocks.len();
+        self.children(node, blocks);
+        if blocks.blocks.len() != count {
+            // The element held blocks, which can't be wrapped
+            return;
+        }
+        let contents = blocks.inline.split_off(start);
+        let trimmed = contents.trim_start();
+        let leading = &contents[..contents.len() - trimmed.len()];
+        let trimmed = trimmed.trim_end();
+        let trailing = &contents[leading.len() + trimmed.len()..];
+        blocks.inline.push_str(leading);
+        if !trimmed.is_empty() {
+            blocks.inline.push_str(open);
+            blocks.inline.push_str(trimmed);
+            blocks.inline.push_str(close);
+        }
+        blocks.inline.push_str(trailing);
+    }
+
+    fn children(&self, node: &NodeRef, blocks: &mut Blocks) {
+        for child in node.children() {
+            self.node(&child, blocks);
+        }
+    }
+
+    /// Where a link from the page should point in the Markdown, or `None`
+    /// for links which run scripts
+    fn link_destination(&self, href: &str) -> Option<String> {
+        let href = href.trim();
+        if href.starts_with('#') {
+            return Some(destination(href));
+        }
+        let url = self.base.join(href).ok()?;
+        if url.scheme() == "javascript" {
+            return None;
+        }
+        let link = match &self.layout {
+            Some(layout) => layout.link(&[INDEX.to_string()], &url),
+            None => None,
+        };
+        Some(destination(&link.unwrap_or_else(|| url.to_string())))
+    }
+
+    /// The Markdown for an image, using its lazy-loading attribute in
+    /// place of its `src` if it has one, or `None` if it has neither
+    fn image(&self, node: &NodeRef) -> Option<String> {
+        let element = node.as_element()?;
+        let attributes = element.attributes.borrow();
+        let src = self
+            .archive
+            .lazy_attributes
+            .iter()
+            .filter_map(|name| {
+                let value = attributes.get(name.as_str())?;
+                if is_srcset_attribute(name) {
+                    parse_srcset(value).first().map(|c| c.url.to_string())
+                } else {
+                    Some(value.to_string())
+                }
+            })
+            .chain(attributes.get("src").map(str::to_string))
+            .find(|src| !src.trim().is_empty())?;
+        let url = self.base.join(src.trim()).ok()?;
+        let archived = match self.images {
+            MarkdownImages::Original => None,
+            MarkdownImages::DataUri => self
+                .archive
+                .resource_map
+                .get(&url)
+                .map(|resource| resource.to_data_uri()),
+            MarkdownImages::Files => self
+                .layout
+                .as_ref()
+                .and_then(|layout| layout.link(&[INDEX.to_string()], &url)),
+        };
+        let alt = attributes.get("alt").unwrap_or("");
+        let alt: Vec<_> = alt.split_whitespace().map(escape).collect();
+        Some(format!(
+            "![{}]({})",
+            alt.join(" "),
+            destination(&archived.unwrap_or_else(|| url.to_string()))
+        ))
+    }
+
+    /// The Markdown for a list, with the contents of each item indented
+    /// under its marker
+    fn list(&self, node: &NodeRef, ordered: bool) -> String {
+        let start = node
+            .as_element()
+            .and_then(|e| e.attributes.borrow().get("start")?.parse().ok())
+            .unwrap_or(1);
+        let items = node.children().filter(|child| {
+            child.as_element().map(|e| &*e.name.local == "li") == Some(true)
+        });
+        let mut list = Vec::new();
+        for (i, item) in items.enumerate() {
+            let marker = if ordered {
+                format!("{}. ", start + i as u64)
+            } else {
+                "- ".to_string()
+            };
+            let indent = " ".repeat(marker.len());
+            let contents = self.blocks(&item);
+            let mut lines = contents.lines();
+            list.push(format!("{}{}", marker, lines.next().unwrap_or("")));
+            for line in lines {
+                if line.is_empty() {
+                    list.push(String::new());
+                } else {
+                    list.push(format!("{}{}", indent, line));
+                }
+            }
+        }
+        list.join("\n")
+    }
+
+    /// The Markdown for a table, as a GitHub Flavored Markdown table with
+    /// its first row as the header
+    fn table(&self, node: &NodeRef) -> String {
+        let mut rows = Vec::new();
+        for child in node.children() {
+            let name = match child.as_element() {
+                Some(element) => element.name.local.to_string(),
+                None => continue,
+            };
+            match name.as_str() {
+                "tr" => rows.push(self.row(&child)),
+                "thead" | "tbody" | "tfoot" => {
+                    for row in child.children() {
+                        if row.as_element().map(|e| &*e.name.local == "tr")
+                            == Some(true)
+                        {
+                            rows.push(self.row(&row));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return String::new();
+        }
+        let mut lines = Vec::new();
+        for (i, mut row) in rows.into_iter().enumerate() {
+            row.resize(columns, String::new());
+            lines.push(format!("| {} |", row.join(" | ")));
+            if i == 0 {
+                lines.push(format!("|{}", " --- |".repeat(columns)));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// The Markdown for each cell of a table row
+    fn row(&self, node: &NodeRef) -> Vec<String> {
+        node.children()
+            .filter(|cell| {
+                cell.as_element()
+                    .map(|e| matches!(&*e.name.local, "td" | "th"))
+                    == Some(true)
+            })
+            .map(|cell| self.line(&cell).replace('|', "\\|"))
+            .collect()
+    }
+}
+
+/// A fenced code block for a `<pre>`, labelled with the language from a
+/// `language-*` or `lang-*` class of it or the `<code>` inside it
+fn code_block(node: &NodeRef) -> String {
+    let mut code = node.text_contents();
+    if code.ends_with('\n') {
+        code.pop();
+    }
+    let classes = node
+        .inclusive_descendants()
+        .elements()
+        .take_while(|e| matches!(&*e.name.local, "pre" | "code"))
+        .filter_map(|e| e.attributes.borrow().get("class").map(str::to_string))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let language = classes
+        .split_whitespace()
+        .find_map(|class| {
+            class
+                .strip_prefix("language-")
+                .or_else(|| class.strip_prefix("lang-"))
+        })
+        .unwrap_or("");
+    let fence = "`".repeat((longest_run(&code, '`') + 1).max(3));
+    format!("{}{}\n{}\n{}", fence, language, code, fence)
+}
+
+/// An inline code span, with enough backticks around it to hold any in
+/// the code
+fn code_span(code: &str) -> String {
+    let code: Vec<_> = code.split_whitespace().collect();
+    let code = code.join(" ");
+    if code.is_empty() {
+        return code;
+    }
+    let fence = "`".repeat(longest_run(&code, '`') + 1);
+    if code.starts_with('`') || code.ends_with('`') {
+        format!("{} {} {}", fence, code, fence)
+    } else {
+        format!("{}{}{}", fence, code, fence)
+    }
+}
+
+/// The length of the longest run of `c` in `text`
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|other| other != c)
+        .map(str::len)
+        .max()
+        .unwrap_or(0)
+}
+
+/// A link or image destination, in angle brackets if it has characters
+/// which would otherwise end it early
+fn destination(url: &str) -> String {
+    if url.contains(|c: char| c.is_whitespace() || "()<>".contains(c)) {
+        format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        url.to_string()
+    }
+}
+
+/// Escape the characters in text which Markdown would treat as markup
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escape whatever at the start of a line of text would make it a
+/// heading, quote, list item, or rule
+fn escape_line_start(line: &str) -> String {
+    let digits = line.len()
+        - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = &line[digits..];
+    let marker = if digits > 0 {
+        (rest.starts_with(". ") || rest.starts_with(") ") || rest.len() == 1)
+            && (rest.starts_with('.') || rest.starts_with(')'))
+    } else {
+        line.starts_with('#')
+            || line.starts_with('>')
+            || line.starts_with("- ")
+            || line.starts_with("+ ")
+            || line == "-"
+            || line == "+"
+            || line.starts_with("---")
+            || line.starts_with("===")
+    };
+    if !marker {
+        line.to_string()
+    } else if digits > 0 {
+        format!("{}\\{}", &line[..digits], rest)
+    } else {
+        format!("\\{}", line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::{ImageResource, Resource};
+    use bytes::Bytes;
+    use std::collections::HashMap;
+
+    fn archive(content: &str) -> PageArchive {
+        let url = Url::parse("http://example.com/blog/post.html").unwrap();
+        let mut resource_map = HashMap::new();
+        resource_map.insert(
+            url.join("/images/cat.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"png"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        PageArchive {
+            url,
+            content: content.to_string(),
+            resource_map,
+            lazy_attributes: vec!["data-src".to_string()],
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            screenshot: None,
+        }
+    }
+
+    fn markdown(content: &str) -> String {
+        archive(content).to_markdown()
+    }
+
+    #[test]
+    fn test_blocks() {
+        let content = "<html><head><title>Post</title>\
+            <style>p { color: red }</style></head><body>\
+            <h1>A  <em>great</em>\n post</h1>\
+            <p>First   paragraph,<br>\n  broken.</p>\
+            <div>Second <b>bold </b>and <code>x `y`</code></div>\
+            <script>alert(1)</script>\
+            <ul><li>One</li><li>Two<ol start=\"3\"><li>Three</li></ol></li></ul>\
+            <blockquote><p>Quoted</p><p>twice</p></blockquote>\
+            <pre><code class=\"language-rust\">fn main() {\n    ```\n}\n</code></pre>\
+            <hr>\
+            <table><thead><tr><th>Name</th><th>Value</th></tr></thead>\
+            <tbody><tr><td>a|b</td></tr></tbody></table>\
+            </body></html>";
+        assert_eq!(
+            markdown(content),
+            "# A *great* post\n\
+            \n\
+            First paragraph,\\\n\
+            broken.\n\
+            \n\
+            Second **bold** and `` x `y` ``\n\
+            \n\
+            - One\n\
+            - Two\n\
+            \n  \
+              3. Three\n\
+            \n\
+            > Quoted\n\
+            >\n\
+            > twice\n\
+            \n\
+            ````rust\n\
+            fn main() {\n    ```\n}\n\
+            ````\n\
+            \n\
+            ---\n\
+            \n\
+            | Name | Value |\n\
+            | --- | --- |\n\
+            | a\\|b |  |\n"
+        );
+        assert_eq!(markdown(""), "");
+    }
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(
+            markdown("<p>*not* [a link] snake_case &lt;tag&gt; 1 \\ 2</p>"),
+            "\\*not\\* \\[a link\\] snake\\_case \\<tag> 1 \\\\ 2\n"
+        );
+        assert_eq!(
+            markdown(
+                "<p># Not a heading</p><p>- not a list</p>\
+                <p>1. not a list</p><p>&gt; not a quote</p>"
+            ),
+            "\\# Not a heading\n\n\\- not a list\n\n1\\. not a list\n\n\
+            \\> not a quote\n"
+        );
+    }
+
+    #[test]
+    fn test_links() {
+        let content = "<p><a href=\"../about\">About <b>us</b></a>, \
+            <a href=\"#top\">top</a>, \
+            <a href=\"javascript:void(0)\">nowhere</a>, \
+            <a href=\"/a page (1)\">spaces</a>, \
+            <a href=\"/\"> </a>\
+            <a href=\"/images/cat.png\">cat</a></p>";
+        assert_eq!(
+            markdown(content),
+            "[About **us**](http://example.com/about), [top](#top), \
+            nowhere, [spaces](<http://example.com/a%20page%20(1)>), \
+            [cat](http://example.com/images/cat.png)\n"
+        );
+        let with_base = "<head><base href=\"http://example.org/\"></head>\
+            <a href=\"page\">page</a>";
+        assert_eq!(markdown(with_base), "[page](http://example.org/page)\n");
+    }
+
+    #[test]
+    fn test_images() {
+        let archive = archive(
+            "<p><img src=\"/images/cat.png\" alt=\"A *cat*\">\
+            <img src=\"placeholder.gif\" data-src=\"/images/cat.png\">\
+            <img src=\"/images/dog.png\" alt=\"dog\"></p>",
+        );
+        let cat = "http://example.com/images/cat.png";
+        let dog = "![dog](http://example.com/images/dog.png)";
+        assert_eq!(
+            archive.to_markdown(),
+            format!("![A \\*cat\\*]({})![]({}){}\n", cat, cat, dog)
+        );
+
+        let with =
+            |images| archive.to_markdown_with(&MarkdownOptions { images });
+        let data_uri = "data:image/png;base64,cG5n";
+        assert_eq!(
+            with(MarkdownImages::DataUri),
+            format!("![A \\*cat\\*]({})![]({}){}\n", data_uri, data_uri, dog)
+        );
+        let file = "resources/example.com/images/cat.png";
+        assert_eq!(
+            with(MarkdownImages::Files),
+            format!("![A \\*cat\\*]({})![]({}){}\n", file, file, dog)
+        );
+    }
+}
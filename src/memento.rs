@@ -0,0 +1,532 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for archiving pages as they were at a given time, see
+//! [`archive_memento`]
+
+use crate::archiver::Archiver;
+use crate::error::Error;
+use crate::page_archive::PageArchive;
+use crate::ArchiveOptions;
+use reqwest::header::HeaderMap;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fmt::Display;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// Header asking a TimeGate for the memento closest to a time
+pub(crate) const ACCEPT_DATETIME: &str = "accept-datetime";
+
+/// Header giving the time that a memento was captured
+const MEMENTO_DATETIME: &str = "memento-datetime";
+
+/// Configuration options for [`archive_memento`], controlling which web
+/// archives are asked for the page
+pub struct MementoOptions<'a> {
+    /// The options used to archive the memento
+    ///
+    /// Default: [`ArchiveOptions::default`]
+    pub archive: ArchiveOptions<'a>,
+    /// Prefixes of the TimeGates to ask for the memento closest to the
+    /// time, in order, which the page's URL is appended to. These can be
+    /// single archives or aggregators which ask many of them.
+    ///
+    /// Default: `["https://web.archive.org/web/"]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::MementoOptions;
+    /// let options = MementoOptions {
+    ///     timegates: vec![
+    ///         "http://timetravel.mementoweb.org/timegate/".to_string(),
+    ///     ],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub timegates: Vec<String>,
+    /// Prefixes of the TimeMaps listing every memento of a page, which
+    /// the page's URL is appended to. These are only fetched if none of
+    /// the [`timegates`](#structfield.timegates) had a memento, and must
+    /// be in the `application/link-format` format.
+    ///
+    /// Default: `["https://web.archive.org/web/timemap/link/"]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::MementoOptions;
+    /// let options = MementoOptions {
+    ///     timemaps: vec![
+    ///         "http://timetravel.mementoweb.org/timemap/link/".to_string(),
+    ///     ],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub timemaps: Vec<String>,
+}
+
+impl<'a> MementoOptions<'a> {
+    /// Archive the memento with the options, see
+    /// [`MementoOptions::archive`](#structfield.archive)
+    pub fn archive(mut self, options: ArchiveOptions<'a>) -> Self {
+        self.archive = options;
+        self
+    }
+
+    /// Ask the TimeGate at `prefix` after those already added, see
+    /// [`MementoOptions::timegates`](#structfield.timegates)
+    pub fn timegate(mut self, prefix: &str) -> Self {
+        self.timegates.push(prefix.to_string());
+        self
+    }
+
+    /// Fetch the TimeMap at `prefix` after those already added, see
+    /// [`MementoOptions::timemaps`](#structfield.timemaps)
+    pub fn timemap(mut self, prefix: &str) -> Self {
+        self.timemaps.push(prefix.to_string());
+        self
+    }
+}
+
+impl<'a> Default for MementoOptions<'a> {
+    fn default() -> Self {
+        Self {
+            archive: ArchiveOptions::default(),
+            timegates: vec!["https://web.archive.org/web/".to_string()],
+            timemaps: vec![
+                "https://web.archive.org/web/timemap/link/".to_string()
+            ],
+        }
+    }
+}
+
+/// A page as it was at some time in the past, archived by
+/// [`archive_memento`]
+pub struct Memento {
+    /// URL of the page that was asked for
+    pub original: Url,
+    /// When the memento was captured by the web archive holding it, which
+    /// is as close as it has to the time asked for
+    pub datetime: SystemTime,
+    /// The memento archived from the web archive holding it, whose
+    /// [`url`](PageArchive::url) is where it's held
+    pub archive: PageArchive,
+}
+
+/// Archive the page at `url` as it was at `datetime`, from whichever web
+/// archive has the closest memento of it, using the
+/// [Memento](https://datatracker.ietf.org/doc/html/rfc7089) protocol.
+///
+/// Each of the options' [`timegates`](MementoOptions::timegates) is
+/// asked in turn for the memento closest to `datetime` with an
+/// `Accept-Datetime` header. If none of them have one, every
+/// [`timemap`](MementoOptions::timemaps) is fetched and the closest of
+/// the mementos they list is used. The memento is then archived along
+/// with its resources, which web archives serve from the same time.
+///
+/// [`Error::NoMemento`] is returned if no memento of the page could be
+/// found, and any other error archiving the memento is returned as it
+/// would be by [`archive`](crate::archive).
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use web_archive::{archive_memento, MementoOptions};
+///
+/// # async fn archive_memento_async() {
+/// // 1st January 2010
+/// let datetime = UNIX_EPOCH + Duration::from_secs(1_262_304_000);
+/// let memento = archive_memento(
+///     "http://example.com",
+///     datetime,
+///     MementoOptions::default(),
+/// )
+/// .await
+/// .unwrap();
+/// println!("Captured at {:?}", memento.datetime);
+/// memento.archive.write_to_disk(&"example").unwrap();
+/// # }
+/// ```
+pub async fn archive_memento<U>(
+    url: U,
+    datetime: SystemTime,
+    options: MementoOptions<'_>,
+) -> Result<Memento, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let mut negotiation = Negotiation::new(url, datetime, &options)?;
+    let archiver = Archiver::new(options.archive)?;
+    while let Some(lookup) = negotiation.next_lookup() {
+        match lookup {
+            Lookup::TimeGate(gate) => {
+                let response = archiver
+                    .client()
+                    .get(gate)
+                    .header(ACCEPT_DATETIME, negotiation.accept_datetime())
+                    .send()
+                    .await;
+                if let Ok(response) = response {
+                    let url = response.url().clone();
+                    let status = response.status().as_u16();
+                    negotiation.negotiated(url, status, response.headers());
+                }
+            }
+            Lookup::TimeMap(timemap) => {
+                let response = archiver.client().get(timemap).send().await;
+                if let Ok(response) = response {
+                    let url = response.url().clone();
+                    let status = response.status().as_u16();
+                    if let Ok(text) = response.text().await {
+                        negotiation.listed(&url, status, &text);
+                    }
+                }
+            }
+        }
+    }
+    let (original, memento, datetime) = negotiation.finish()?;
+    Ok(Memento {
+        original,
+        datetime,
+        archive: archiver.archive(memento).await?,
+    })
+}
+
+/// A request to make while looking for a memento
+pub(crate) enum Lookup {
+    /// Ask a TimeGate for the closest memento, with an `Accept-Datetime`
+    /// header
+    TimeGate(Url),
+    /// Fetch a TimeMap listing every memento
+    TimeMap(Url),
+}
+
+/// The state of looking for the memento closest to a time, shared
+/// between the async and blocking APIs, which hand out the TimeGates and
+/// TimeMaps to request until one is found
+pub(crate) struct Negotiation {
+    url: Url,
+    datetime: SystemTime,
+    timegates: VecDeque<Url>,
+    timemaps: VecDeque<Url>,
+    /// Mementos listed by the TimeMaps fetched so far, with when they
+    /// were captured
+    listed: Vec<(Url, SystemTime)>,
+    /// The memento which a TimeGate negotiated
+    found: Option<(Url, SystemTime)>,
+}
+
+impl Negotiation {
+    pub fn new(
+        url: Url,
+        datetime: SystemTime,
+        options: &MementoOptions,
+    ) -> Result<Self, Error> {
+        let prefixed = |prefixes: &[String]| {
+            prefixes
+                .iter()
+                .map(|prefix| prefixed_url(prefix, &url))
+                .collect::<Result<_, _>>()
+        };
+        Ok(Self {
+            timegates: prefixed(&options.timegates)?,
+            timemaps: prefixed(&options.timemaps)?,
+            url,
+            datetime,
+            listed: Vec::new(),
+            found: None,
+        })
+    }
+
+    /// The `Accept-Datetime` header to send to the TimeGates
+    pub fn accept_datetime(&self) -> String {
+        httpdate::fmt_http_date(self.datetime)
+    }
+
+    /// The next TimeGate or TimeMap to request, or `None` once a
+    /// TimeGate has negotiated a memento or there are none left
+    pub fn next_lookup(&mut self) -> Option<Lookup> {
+        if self.found.is_some() {
+            return None;
+        }
+        if let Some(gate) = self.timegates.pop_front() {
+            return Some(Lookup::TimeGate(gate));
+        }
+        self.timemaps.pop_front().map(Lookup::TimeMap)
+    }
+
+    /// Read the response from a TimeGate, which redirects to the memento
+    /// at `url` if it has one
+    pub fn negotiated(&mut self, url: Url, status: u16, headers: &HeaderMap) {
+        if !(200..300).contains(&status) {
+            return;
+        }
+        let datetime = headers
+            .get(MEMENTO_DATETIME)
+            .and_then(|datetime| datetime.to_str().ok())
+            .and_then(|datetime| httpdate::parse_http_date(datetime).ok());
+        if let Some(datetime) = datetime {
+            self.found = Some((url, datetime));
+        }
+    }
+
+    /// Read a TimeMap fetched from `url`, keeping the mementos it lists
+    pub fn listed(&mut self, url: &Url, status: u16, timemap: &str) {
+        if (200..300).contains(&status) {
+            self.listed.extend(parse_timemap(timemap, url));
+        }
+    }
+
+    /// The page's URL, along with the memento closest to the time and
+    /// when it was captured
+    pub fn finish(self) -> Result<(Url, Url, SystemTime), Error> {
+        let Self {
+            url,
+            datetime,
+            listed,
+            found,
+            ..
+        } = self;
+        let (memento, captured) = found
+            .or_else(|| {
+                listed
+                    .into_iter()
+                    .min_by_key(|(_, captured)| distance(*captured, datetime))
+            })
+            .ok_or_else(|| Error::NoMemento(url.to_string()))?;
+        Ok((url, memento, captured))
+    }
+}
+
+/// The URL of a TimeGate or TimeMap for the page at `url`, which are
+/// given by appending it to their prefix
+fn prefixed_url(prefix: &str, url: &Url) -> Result<Url, Error> {
+    Url::parse(&format!("{}{}", prefix, url))
+        .map_err(|e| Error::ParseError(format!("{}: {}", prefix, e)))
+}
+
+/// How far apart two times are, whichever comes first
+fn distance(a: SystemTime, b: SystemTime) -> Duration {
+    a.duration_since(b).unwrap_or_else(|e| e.duration())
+}
+
+/// Read the mementos, with when they were captured, from a TimeMap in
+/// the `application/link-format` format, fetched from `base`
+pub(crate) fn parse_timemap(
+    timemap: &str,
+    base: &Url,
+) -> Vec<(Url, SystemTime)> {
+    let mut mementos = Vec::new();
+    let mut rest = timemap;
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let target = &rest[start + 1..end];
+        let (mut params, next) = split_unquoted(&rest[end + 1..], ',');
+        rest = next;
+
+        let mut is_memento = false;
+        let mut datetime = None;
+        while !params.trim().is_empty() {
+            let (param, next) = split_unquoted(params, ';');
+            params = next;
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), unquote(value)),
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case("rel") {
+                is_memento = value
+                    .split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("memento"));
+            } else if name.eq_ignore_ascii_case("datetime") {
+                datetime = httpdate::parse_http_date(value).ok();
+            }
+        }
+        if let (true, Some(datetime), Ok(url)) =
+            (is_memento, datetime, base.join(target.trim()))
+        {
+            mementos.push((url, datetime));
+        }
+    }
+    mementos
+}
+
+/// Split `s` at the first `delimiter` which isn't within double quotes,
+/// returning the parts before and after it
+fn split_unquoted(s: &str, delimiter: char) -> (&str, &str) {
+    let mut quoted = false;
+    for (i, c) in s.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == delimiter && !quoted {
+            return (&s[..i], &s[i + 1..]);
+        }
+    }
+    (s, "")
+}
+
+/// A link parameter's value, without the quotes around it
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use std::time::UNIX_EPOCH;
+
+    const TIMEMAP: &str = r#"<http://example.com/>;rel="original",
+<http://archive.example.org/timemap/link/http://example.com/>
+  ; rel="self";type="application/link-format"
+  ; from="Tue, 20 Jun 2000 18:02:59 GMT"
+  ; until="Wed, 09 Apr 2008 20:30:51 GMT",
+<http://archive.example.org/timegate/http://example.com/>
+  ; rel="timegate",
+<http://archive.example.org/web/20000620180259/http://example.com/>
+  ; rel="first memento";datetime="Tue, 20 Jun 2000 18:02:59 GMT"
+  ; license="http://creativecommons.org/publicdomain/zero/1.0/",
+</web/20050101000000/http://example.com/>
+  ; rel="memento";datetime="Sat, 01 Jan 2005 00:00:00 GMT",
+<http://archive.example.org/web/20080409203051/http://example.com/>
+  ; rel="last memento";datetime="Wed, 09 Apr 2008 20:30:51 GMT"
+"#;
+
+    fn time(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn options() -> MementoOptions<'static> {
+        MementoOptions {
+            timegates: vec!["http://gate.example.org/".to_string()],
+            timemaps: vec!["http://map.example.org/link/".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_timemap() {
+        let base =
+            Url::parse("http://archive.example.org/timemap/link/").unwrap();
+        let mementos = parse_timemap(TIMEMAP, &base);
+        let urls: Vec<_> =
+            mementos.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "http://archive.example.org/web/20000620180259/http://example.com/",
+                "http://archive.example.org/web/20050101000000/http://example.com/",
+                "http://archive.example.org/web/20080409203051/http://example.com/",
+            ]
+        );
+        assert_eq!(mementos[0].1, time(961_524_179));
+        assert_eq!(mementos[1].1, time(1_104_537_600));
+    }
+
+    #[test]
+    fn test_split_unquoted() {
+        assert_eq!(
+            split_unquoted(r#"a="b, c", d"#, ','),
+            (r#"a="b, c""#, " d")
+        );
+        assert_eq!(split_unquoted("a;b", ','), ("a;b", ""));
+    }
+
+    #[test]
+    fn test_timegate() {
+        let url = Url::parse("http://example.com/page").unwrap();
+        let mut negotiation =
+            Negotiation::new(url, time(1_104_537_600), &options()).unwrap();
+        assert_eq!(
+            negotiation.accept_datetime(),
+            "Sat, 01 Jan 2005 00:00:00 GMT"
+        );
+        let gate = match negotiation.next_lookup() {
+            Some(Lookup::TimeGate(gate)) => gate,
+            _ => panic!("expected a TimeGate"),
+        };
+        assert_eq!(
+            gate.as_str(),
+            "http://gate.example.org/http://example.com/page"
+        );
+
+        let memento =
+            Url::parse("http://gate.example.org/2004/http://example.com/page")
+                .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            MEMENTO_DATETIME,
+            HeaderValue::from_static("Fri, 31 Dec 2004 12:00:00 GMT"),
+        );
+        negotiation.negotiated(memento.clone(), 200, &headers);
+        assert!(negotiation.next_lookup().is_none());
+        let (original, found, datetime) = negotiation.finish().unwrap();
+        assert_eq!(original.as_str(), "http://example.com/page");
+        assert_eq!(found, memento);
+        assert_eq!(datetime, time(1_104_494_400));
+    }
+
+    #[test]
+    fn test_timemap() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let mut negotiation =
+            Negotiation::new(url, time(1_200_000_000), &options()).unwrap();
+        assert!(matches!(
+            negotiation.next_lookup(),
+            Some(Lookup::TimeGate(_))
+        ));
+        // The TimeGate has no memento
+        negotiation.negotiated(
+            Url::parse("http://gate.example.org/http://example.com/").unwrap(),
+            404,
+            &HeaderMap::new(),
+        );
+        let timemap = match negotiation.next_lookup() {
+            Some(Lookup::TimeMap(timemap)) => timemap,
+            _ => panic!("expected a TimeMap"),
+        };
+        assert_eq!(
+            timemap.as_str(),
+            "http://map.example.org/link/http://example.com/"
+        );
+        negotiation.listed(&timemap, 200, TIMEMAP);
+        assert!(negotiation.next_lookup().is_none());
+        let (_, found, datetime) = negotiation.finish().unwrap();
+        assert_eq!(
+            found.as_str(),
+            "http://archive.example.org/web/20080409203051/http://example.com/"
+        );
+        assert_eq!(datetime, time(1_207_773_051));
+    }
+
+    #[test]
+    fn test_no_memento() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let mut negotiation =
+            Negotiation::new(url, time(0), &options()).unwrap();
+        while let Some(lookup) = negotiation.next_lookup() {
+            if let Lookup::TimeMap(timemap) = lookup {
+                negotiation.listed(&timemap, 404, TIMEMAP);
+            }
+        }
+        assert!(matches!(
+            negotiation.finish(),
+            Err(Error::NoMemento(url)) if url == "http://example.com/"
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for describing where and when each response came from
+
+use crate::http_cache::Validators;
+use reqwest::header::{HeaderMap, CACHE_CONTROL, CONTENT_TYPE};
+use reqwest::StatusCode;
+use std::time::SystemTime;
+use url::Url;
+
+/// Where and when the page or a resource was downloaded from, kept in
+/// [`PageArchive::meta`](crate::PageArchive::meta)
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResourceMeta {
+    /// HTTP status of the response, e.g. `200`
+    pub status: u16,
+    /// URL that the response came from, after following any redirects
+    pub final_url: Url,
+    /// When the response was received. Responses taken from an
+    /// [`HttpCache`](crate::HttpCache) keep the time they were first
+    /// received, or last found to be unchanged.
+    pub fetched_at: SystemTime,
+    /// Value of the `Content-Type` header
+    pub content_type: Option<String>,
+    /// Value of the `Cache-Control` header
+    pub cache_control: Option<String>,
+    /// Values of the `ETag` and `Last-Modified` headers, for asking
+    /// whether the response has changed since
+    pub validators: Validators,
+    /// Where the Internet Archive's Wayback Machine keeps a snapshot of
+    /// the response, once it's been submitted with
+    /// [`PageArchive::save_page_now`](crate::PageArchive::save_page_now)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub snapshot: Option<Url>,
+    /// SHA-256 digest of the response's data as it was archived, in
+    /// lowercase hex, for noticing if it's been changed or corrupted
+    /// since, see [`PageArchive::verify`](crate::PageArchive::verify). The
+    /// page's is of its [`content`](crate::PageArchive::content) as UTF-8.
+    ///
+    /// This is `None` for responses which aren't kept in the archive, and
+    /// in archives written by earlier versions of this crate.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sha256: Option<String>,
+}
+
+impl ResourceMeta {
+    /// Describe a response received just now
+    pub(crate) fn new(
+        status: StatusCode,
+        final_url: &Url,
+        headers: &HeaderMap,
+    ) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            status: status.as_u16(),
+            final_url: final_url.clone(),
+            fetched_at: SystemTime::now(),
+            content_type: header(CONTENT_TYPE),
+            cache_control: header(CACHE_CONTROL),
+            validators: Validators::new(headers),
+            snapshot: None,
+            sha256: None,
+        }
+    }
+
+    /// The same response, found just now to be unchanged by a response
+    /// with the `headers`
+    pub(crate) fn revalidated(self, headers: &HeaderMap) -> Self {
+        let cache_control = headers
+            .get(CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Self {
+            fetched_at: SystemTime::now(),
+            cache_control: cache_control.or(self.cache_control),
+            validators: self.validators.updated(headers),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_meta() {
+        let url = Url::parse("http://example.com/style.css").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/css"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        headers.insert("etag", HeaderValue::from_static("\"abc\""));
+        let meta = ResourceMeta::new(StatusCode::OK, &url, &headers);
+        assert_eq!(meta.status, 200);
+        assert_eq!(meta.final_url, url);
+        assert_eq!(meta.content_type.as_deref(), Some("text/css"));
+        assert_eq!(meta.cache_control.as_deref(), Some("no-cache"));
+        assert_eq!(meta.validators.etag.as_deref(), Some("\"abc\""));
+
+        let mut not_modified = HeaderMap::new();
+        not_modified.insert("etag", HeaderValue::from_static("\"def\""));
+        let revalidated = meta.clone().revalidated(&not_modified);
+        assert!(revalidated.fetched_at >= meta.fetched_at);
+        assert_eq!(revalidated.content_type, meta.content_type);
+        assert_eq!(revalidated.cache_control, meta.cache_control);
+        assert_eq!(revalidated.validators.etag.as_deref(), Some("\"def\""));
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for reading the metadata of a page, such as its title and
+//! Open Graph properties, see [`PageArchive::title`] and friends
+
+use crate::links::Link;
+use crate::parsing::{base_url, has_rel};
+use crate::structured_data::StructuredData;
+use kuchiki::NodeRef;
+use std::collections::HashMap;
+use url::Url;
+
+/// A page parsed once, for reading several of its metadata, links, and
+/// structured data without parsing it again for each, see
+/// [`PageArchive::parse`](crate::PageArchive::parse)
+///
+/// ## Example
+/// ```no_run
+/// use web_archive::blocking;
+///
+/// let archive =
+///     blocking::archive("http://example.com", Default::default())
+///         .unwrap();
+/// let page = archive.parse();
+/// println!("{:?}: {:?}", page.title(), page.description());
+/// ```
+pub struct ParsedPage {
+    url: Url,
+    document: NodeRef,
+}
+
+impl ParsedPage {
+    pub(crate) fn new(url: Url, document: NodeRef) -> Self {
+        Self { url, document }
+    }
+
+    /// The page's DOM, see [`PageArchive::document`](crate::PageArchive::document)
+    pub fn document(&self) -> &NodeRef {
+        &self.document
+    }
+
+    /// See [`PageArchive::title`](crate::PageArchive::title)
+    pub fn title(&self) -> Option<String> {
+        title(&self.document)
+    }
+
+    /// See [`PageArchive::description`](crate::PageArchive::description)
+    pub fn description(&self) -> Option<String> {
+        description(&self.document)
+    }
+
+    /// See [`PageArchive::canonical_url`](crate::PageArchive::canonical_url)
+    pub fn canonical_url(&self) -> Option<Url> {
+        canonical_url(&self.url, &self.document)
+    }
+
+    /// See [`PageArchive::next_page_url`](crate::PageArchive::next_page_url)
+    pub fn next_page_url(&self) -> Option<Url> {
+        rel_url(&self.url, &self.document, &["next"])
+    }
+
+    /// See [`PageArchive::prev_page_url`](crate::PageArchive::prev_page_url)
+    pub fn prev_page_url(&self) -> Option<Url> {
+        rel_url(&self.url, &self.document, &["prev", "previous"])
+    }
+
+    /// See [`PageArchive::language`](crate::PageArchive::language)
+    pub fn language(&self) -> Option<String> {
+        language(&self.document)
+    }
+
+    /// See [`PageArchive::open_graph`](crate::PageArchive::open_graph)
+    pub fn open_graph(&self) -> HashMap<String, String> {
+        open_graph(&self.document)
+    }
+
+    /// See [`PageArchive::links`](crate::PageArchive::links)
+    pub fn links(&self) -> Vec<Link> {
+        crate::links::links(&self.url, &self.document)
+    }
+
+    /// See [`PageArchive::structured_data`](crate::PageArchive::structured_data)
+    pub fn structured_data(&self) -> StructuredData {
+        crate::structured_data::extract(&self.url, &self.document)
+    }
+}
+
+/// Prefixes of the Open Graph properties, including the namespaces of
+/// its object types
+const OPEN_GRAPH_PREFIXES: &[&str] =
+    &["og:", "article:", "book:", "music:", "profile:", "video:"];
+
+/// Collapse runs of whitespace into single spaces, as a browser would
+pub(crate) fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The `content` of the first element matching `selector` which has a
+/// non-blank one
+pub(crate) fn meta_content(
+    document: &NodeRef,
+    selector: &str,
+) -> Option<String> {
+    document
+        .select(selector)
+        .unwrap()
+        .filter_map(|meta| {
+            let content = collapse(meta.attributes.borrow().get("content")?);
+            Some(content).filter(|content| !content.is_empty())
+        })
+        .next()
+}
+
+/// The text of the page's `<title>`
+pub(crate) fn title(document: &NodeRef) -> Option<String> {
+    let title = document.select_first("title").ok()?;
+    Some(collapse(&title.text_contents())).filter(|title| !title.is_empty())
+}
+
+/// The page's `description` metadata, or its `og:description`
+pub(crate) fn description(document: &NodeRef) -> Option<String> {
+    meta_content(document, "meta[name=\"description\" i]")
+        .or_else(|| meta_content(document, "meta[property=\"og:description\"]"))
+}
+
+/// The URL of the page's `<link rel="canonical">`, resolved against its
+/// base URL
+pub(crate) fn canonical_url(page_url: &Url, document: &NodeRef) -> Option<Url> {
+    let base = base_url(page_url, document);
+    document
+        .select("link[rel][href]")
+        .unwrap()
+        .filter_map(|link| {
+            let attributes = link.attributes.borrow();
+            if !has_rel(attributes.get("rel"), "canonical") {
+                return None;
+            }
+            base.join(attributes.get("href")?.trim()).ok()
+        })
+        .next()
+}
+
+/// The URL of the first `<link>` or `<a>` in the page whose `rel` has
+/// one of the `keywords`, resolved against its base URL
+pub(crate) fn rel_url(
+    page_url: &Url,
+    document: &NodeRef,
+    keywords: &[&str],
+) -> Option<Url> {
+    let base = base_url(page_url, document);
+    document
+        .select("link[rel][href], a[rel][href]")
+        .unwrap()
+        .filter_map(|link| {
+            let attributes = link.attributes.borrow();
+            let rel = attributes.get("rel");
+            if !keywords.iter().any(|keyword| has_rel(rel, keyword)) {
+                return None;
+            }
+            base.join(attributes.get("href")?.trim()).ok()
+        })
+        .next()
+}
+
+/// The language of the page, from the `lang` of its `<html>` element or
+/// its `Content-Language` metadata
+pub(crate) fn language(document: &NodeRef) -> Option<String> {
+    let lang = document.select_first("html").ok().and_then(|html| {
+        let lang = html.attributes.borrow().get("lang")?.trim().to_string();
+        Some(lang).filter(|lang| !lang.is_empty())
+    });
+    lang.or_else(|| {
+        let selector = "meta[http-equiv=\"content-language\" i]";
+        let languages = meta_content(document, selector)?;
+        // This may list several languages, of which the first is taken
+        let language = languages.split(',').next()?.trim().to_string();
+        Some(language).filter(|language| !language.is_empty())
+    })
+}
+
+/// The page's Open Graph properties, keyed by their names
+pub(crate) fn open_graph(document: &NodeRef) -> HashMap<String, String> {
+    let mut properties = HashMap::new();
+    for meta in document.select("meta[property][content]").unwrap() {
+        let attributes = meta.attributes.borrow();
+        let property = attributes.get("property").unwrap_or("").trim();
+        let is_open_graph = OPEN_GRAPH_PREFIXES
+            .iter()
+            .any(|prefix| property.starts_with(prefix));
+        if is_open_graph && !properties.contains_key(property) {
+            let content = attributes.get("content").unwrap_or("").trim();
+            properties.insert(property.to_string(), content.to_string());
+        }
+    }
+    properties
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kuchiki::parse_html;
+    use kuchiki::traits::TendrilSink;
+
+    #[test]
+    fn test_metadata() {
+        let document = parse_html().one(
+            "<html lang=\"en-GB\"><head>\
+            <base href=\"http://example.com/blog/\">\
+            <title>\n  A   Post\n</title>\
+            <meta name=\"Description\" content=\"\">\
+            <meta name=\"description\" content=\"About  things\">\
+            <link rel=\"stylesheet\" href=\"style.css\">\
+            <link rel=\"alternate canonical\" href=\"post\">\
+            <meta property=\"og:title\" content=\"A Post\">\
+            <meta property=\"og:image\" content=\"http://example.com/a.png\">\
+            <meta property=\"og:image\" content=\"http://example.com/b.png\">\
+            <meta property=\"article:author\" content=\"Jane\">\
+            <meta property=\"fb:app_id\" content=\"1234\">\
+            <link rel=\"next\" href=\"post?page=3\">\
+            </head><body><a rel=\"Previous\" href=\"post?page=1\">Back</a>\
+            </body></html>",
+        );
+        let url = Url::parse("http://example.com/blog/post?ref=feed").unwrap();
+        assert_eq!(title(&document).as_deref(), Some("A Post"));
+        assert_eq!(description(&document).as_deref(), Some("About things"));
+        assert_eq!(
+            canonical_url(&url, &document).as_ref().map(Url::as_str),
+            Some("http://example.com/blog/post")
+        );
+        assert_eq!(language(&document).as_deref(), Some("en-GB"));
+        let rel = |keywords| rel_url(&url, &document, keywords);
+        assert_eq!(
+            rel(&["next"]).as_ref().map(Url::as_str),
+            Some("http://example.com/blog/post?page=3")
+        );
+        assert_eq!(
+            rel(&["prev", "previous"]).as_ref().map(Url::as_str),
+            Some("http://example.com/blog/post?page=1")
+        );
+        assert_eq!(rel(&["license"]), None);
+
+        let properties = open_graph(&document);
+        assert_eq!(properties.len(), 3);
+        assert_eq!(properties["og:title"], "A Post");
+        assert_eq!(properties["og:image"], "http://example.com/a.png");
+        assert_eq!(properties["article:author"], "Jane");
+
+        let document = parse_html().one(
+            "<meta http-equiv=\"Content-Language\" content=\"de, en\">\
+            <meta property=\"og:description\" content=\"Fallback\">",
+        );
+        assert_eq!(title(&document), None);
+        assert_eq!(description(&document).as_deref(), Some("Fallback"));
+        assert_eq!(canonical_url(&url, &document), None);
+        assert_eq!(language(&document).as_deref(), Some("de"));
+    }
+}
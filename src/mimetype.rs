@@ -0,0 +1,277 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for determining the mimetypes of downloaded resources
+
+use url::Url;
+
+/// Mimetype used for binary resources when nothing more specific is
+/// known
+pub(crate) const OCTET_STREAM: &str = "application/octet-stream";
+
+// https://github.com/Y2Z/monolith/blob/fa71f6a42c94df4c48d01819922afe1248eabad5/src/utils.rs#L13
+// A `.` in the pattern matches any byte.
+const MAGIC: [(&[u8], &str); 23] = [
+    // Image
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\xFF\xD8\xFF", "image/jpeg"),
+    (b"\x89PNG\x0D\x0A\x1A\x0A", "image/png"),
+    (b"<svg ", "image/svg+xml"),
+    (b"RIFF....WEBPVP8", "image/webp"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    // Audio
+    (b"ID3", "audio/mpeg"),
+    (b"\xFF\x0E", "audio/mpeg"),
+    (b"\xFF\x0F", "audio/mpeg"),
+    (b"OggS", "audio/ogg"),
+    (b"RIFF....WAVEfmt ", "audio/wav"),
+    (b"fLaC", "audio/x-flac"),
+    // Video
+    (b"RIFF....AVI LIST", "video/avi"),
+    (b"....ftyp", "video/mp4"),
+    (b"\x00\x00\x01\x0B", "video/mpeg"),
+    (b"....moov", "video/quicktime"),
+    (b"\x1A\x45\xDF\xA3", "video/webm"),
+    // Documents
+    (b"%PDF-", "application/pdf"),
+    // Fonts
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+    (b"OTTO", "font/otf"),
+    (b"\x00\x01\x00\x00", "font/ttf"),
+];
+
+/// Determine the mimetype of a resource. The `Content-Type` header is
+/// preferred, followed by the file extension of the URL, then sniffing
+/// the magic bytes at the start of the data. If none of these give an
+/// answer then `fallback` is used.
+///
+/// Headers claiming the resource is a generic `application/octet-stream`
+/// are ignored since they don't say anything useful.
+pub(crate) fn detect(
+    content_type: Option<&str>,
+    url: &Url,
+    data: &[u8],
+    fallback: &str,
+) -> String {
+    content_type
+        .and_then(from_content_type)
+        .or_else(|| from_extension(url).map(str::to_string))
+        .or_else(|| sniff(data).map(str::to_string))
+        .unwrap_or_else(|| fallback.to_string())
+}
+
+/// Extract the mimetype from a `Content-Type` header, dropping any
+/// parameters such as `charset`
+pub(crate) fn from_content_type(content_type: &str) -> Option<String> {
+    let mimetype = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    if !mimetype.contains('/')
+        || mimetype == OCTET_STREAM
+        || mimetype == "binary/octet-stream"
+    {
+        return None;
+    }
+    Some(mimetype)
+}
+
+/// Guess the mimetype from the file extension of the URL's path
+pub(crate) fn from_extension(url: &Url) -> Option<&'static str> {
+    let path = url.path();
+    let file_name = &path[path.rfind('/').map(|i| i + 1).unwrap_or(0)..];
+    for_extension(&file_name[file_name.rfind('.')? + 1..])
+}
+
+/// The mimetype of files with the extension, e.g. `image/png` for `png`
+pub(crate) fn for_extension(extension: &str) -> Option<&'static str> {
+    Some(match extension.to_ascii_lowercase().as_str() {
+        // Text
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" | "map" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain",
+        "wasm" => "application/wasm",
+        // Image
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "gif" => "image/gif",
+        "ico" => "image/x-icon",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "svg" => "image/svg+xml",
+        "tif" | "tiff" => "image/tiff",
+        "webp" => "image/webp",
+        // Audio
+        "flac" => "audio/x-flac",
+        "mp3" => "audio/mpeg",
+        "oga" | "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        // Video
+        "avi" => "video/avi",
+        "mov" => "video/quicktime",
+        "mp4" => "video/mp4",
+        "mpeg" | "mpg" => "video/mpeg",
+        "webm" => "video/webm",
+        // Documents
+        "pdf" => "application/pdf",
+        // Fonts
+        "eot" => "application/vnd.ms-fontobject",
+        "otf" => "font/otf",
+        "ttf" => "font/ttf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => return None,
+    })
+}
+
+/// The usual file extension for a mimetype, e.g. `png` for `image/png`
+pub(crate) fn extension(mimetype: &str) -> Option<&'static str> {
+    Some(match mimetype {
+        // Text
+        "text/html" => "html",
+        "text/css" => "css",
+        "text/javascript" | "application/javascript" => "js",
+        "application/json" => "json",
+        "application/xml" | "text/xml" => "xml",
+        "text/plain" => "txt",
+        "application/wasm" => "wasm",
+        // Image
+        "image/avif" => "avif",
+        "image/bmp" => "bmp",
+        "image/gif" => "gif",
+        "image/x-icon" | "image/vnd.microsoft.icon" => "ico",
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/svg+xml" => "svg",
+        "image/tiff" => "tiff",
+        "image/webp" => "webp",
+        // Audio
+        "audio/x-flac" | "audio/flac" => "flac",
+        "audio/mpeg" => "mp3",
+        "audio/ogg" => "ogg",
+        "audio/wav" => "wav",
+        // Video
+        "video/avi" => "avi",
+        "video/quicktime" => "mov",
+        "video/mp4" => "mp4",
+        "video/mpeg" => "mpeg",
+        "video/webm" => "webm",
+        // Documents
+        "application/pdf" => "pdf",
+        // Fonts
+        "application/vnd.ms-fontobject" => "eot",
+        "font/otf" => "otf",
+        "font/ttf" => "ttf",
+        "font/woff" => "woff",
+        "font/woff2" => "woff2",
+        _ => return None,
+    })
+}
+
+// https://github.com/Y2Z/monolith/blob/fa71f6a42c94df4c48d01819922afe1248eabad5/src/utils.rs#L44
+/// Guess the mimetype from the magic bytes at the start of the data
+pub(crate) fn sniff(data: &[u8]) -> Option<&'static str> {
+    MAGIC
+        .iter()
+        .find(|(magic, _)| {
+            data.len() >= magic.len()
+                && magic
+                    .iter()
+                    .zip(data.iter())
+                    .all(|(m, d)| *m == b'.' || m == d)
+        })
+        .map(|(_, mimetype)| *mimetype)
+}
+
+#[cfg(test)]
+#[allow(clippy::needless_borrow)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mimetype_detection() {
+        let data: &[u8] = include_bytes!(
+            "../dynamic_tests/resources/rustacean-flat-happy.png"
+        );
+        let url = Url::parse("http://example.com/ferris").unwrap();
+        let mimetype = detect(None, &url, &data, OCTET_STREAM);
+        assert_eq!(mimetype, "image/png");
+
+        let data: &[u8] =
+            include_bytes!("../dynamic_tests/resources/rust-logo-blk.svg");
+        let url = Url::parse("http://example.com/rust.svg").unwrap();
+        let mimetype = detect(None, &url, &data, OCTET_STREAM);
+        assert_eq!(mimetype, "image/svg+xml");
+
+        let data: &[u8] = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n";
+        let url = Url::parse("http://example.com/download").unwrap();
+        let mimetype = detect(None, &url, data, OCTET_STREAM);
+        assert_eq!(mimetype, "application/pdf");
+
+        let data: &[u8] = b"wOF2\x00\x01\x00\x00";
+        let url = Url::parse("http://example.com/font").unwrap();
+        let mimetype = detect(None, &url, data, OCTET_STREAM);
+        assert_eq!(mimetype, "font/woff2");
+
+        let data: &[u8] = b"RIFF\x00\x01\x02\x03WEBPVP8 ";
+        let url = Url::parse("http://example.com/image").unwrap();
+        let mimetype = detect(None, &url, data, OCTET_STREAM);
+        assert_eq!(mimetype, "image/webp");
+
+        let url = Url::parse("http://example.com/unknown").unwrap();
+        let mimetype = detect(None, &url, b"????", OCTET_STREAM);
+        assert_eq!(mimetype, OCTET_STREAM);
+    }
+
+    #[test]
+    fn test_detection_order() {
+        let url = Url::parse("http://example.com/image.png?v=2").unwrap();
+        let data = b"GIF89a";
+
+        // Header wins
+        let mimetype =
+            detect(Some("image/webp; q=1"), &url, data, OCTET_STREAM);
+        assert_eq!(mimetype, "image/webp");
+
+        // Then extension
+        let mimetype = detect(None, &url, data, OCTET_STREAM);
+        assert_eq!(mimetype, "image/png");
+        let mimetype =
+            detect(Some("application/octet-stream"), &url, data, OCTET_STREAM);
+        assert_eq!(mimetype, "image/png");
+
+        // Then magic bytes
+        let url = Url::parse("http://example.com/image").unwrap();
+        let mimetype = detect(None, &url, data, OCTET_STREAM);
+        assert_eq!(mimetype, "image/gif");
+    }
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(for_extension("JPEG"), Some("image/jpeg"));
+        assert_eq!(extension("image/jpeg"), Some("jpg"));
+        assert_eq!(extension("application/javascript"), Some("js"));
+        assert_eq!(extension(OCTET_STREAM), None);
+    }
+
+    #[test]
+    fn test_from_content_type() {
+        assert_eq!(
+            from_content_type("text/CSS; charset=UTF-8").as_deref(),
+            Some("text/css")
+        );
+        assert_eq!(from_content_type("nonsense"), None);
+        assert_eq!(from_content_type(""), None);
+    }
+}
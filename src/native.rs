@@ -0,0 +1,788 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for the crate's own archive file format, see
+//! [`PageArchive::save`] and [`PageArchive::load`]
+//!
+//! A file starts with the [`MAGIC`] bytes and the format [`VERSION`] as a
+//! little-endian `u32`, followed by a zlib stream of sections. Each
+//! section is a four byte tag, its length as a little-endian `u64`, and
+//! then its contents. Numbers within the sections are LEB128 varints, and
+//! strings and byte strings are a varint length followed by the bytes.
+//!
+//! Newer versions of the crate only ever add sections, which older ones
+//! skip, and add new kinds of resource and failure, which older ones read
+//! as [`Resource::Other`] and [`FailureReason::Network`]. The version is
+//! only bumped if a file couldn't be read correctly by older versions,
+//! which then refuse to read it.
+
+use crate::failure::{FailureReason, ResourceFailure};
+use crate::http_cache::Validators;
+use crate::meta::ResourceMeta;
+use crate::page_archive::PageArchive;
+use crate::parsing::{ImageResource, Resource, ResourceMap, TextResource};
+use crate::stats::{ArchiveStats, FetchTiming};
+use bytes::Bytes;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// First bytes of every file
+const MAGIC: &[u8; 8] = b"WEBARCHV";
+/// Version of the format, bumped if it changes incompatibly
+const VERSION: u32 = 1;
+
+/// The page's URL and content
+const PAGE: &[u8; 4] = b"PAGE";
+/// Lazy loading and URL attributes
+const ATTRIBUTES: &[u8; 4] = b"ATTR";
+const RESOURCES: &[u8; 4] = b"RSRC";
+/// Failures, timeouts, and whether the archive was cancelled
+const FAILURES: &[u8; 4] = b"FAIL";
+const REDIRECTS: &[u8; 4] = b"RDIR";
+const META: &[u8; 4] = b"META";
+const STATS: &[u8; 4] = b"STAT";
+/// The screenshot, if there is one
+const SCREENSHOT: &[u8; 4] = b"SHOT";
+/// Wayback Machine snapshots of the page and resources, if there are any
+const SNAPSHOTS: &[u8; 4] = b"SNAP";
+/// Digests of the page and resources, if they've been worked out
+const DIGESTS: &[u8; 4] = b"HASH";
+
+/// Write the archive to `writer`
+pub(crate) fn save<W: Write>(
+    archive: &PageArchive,
+    mut writer: W,
+) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    let mut zlib = ZlibEncoder::new(writer, Compression::default());
+
+    let mut page = Encoder::default();
+    page.url(&archive.url);
+    page.str(&archive.content);
+    section(&mut zlib, PAGE, page)?;
+
+    let mut attributes = Encoder::default();
+    attributes.len(archive.lazy_attributes.len());
+    for name in &archive.lazy_attributes {
+        attributes.str(name);
+    }
+    attributes.len(archive.url_attributes.len());
+    for (tag, name) in &archive.url_attributes {
+        attributes.str(tag);
+        attributes.str(name);
+    }
+    section(&mut zlib, ATTRIBUTES, attributes)?;
+
+    // Sorted so that the same archive is always saved the same way
+    let mut resources: Vec<_> = archive.resource_map.iter().collect();
+    resources.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    let mut encoder = Encoder::default();
+    encoder.len(resources.len());
+    for (url, resource) in resources {
+        encoder.url(url);
+        encoder.resource(resource);
+    }
+    section(&mut zlib, RESOURCES, encoder)?;
+
+    let mut failures = Encoder::default();
+    failures.len(archive.failures.len());
+    for failure in &archive.failures {
+        failures.url(&failure.url);
+        failures.reason(&failure.reason);
+        failures.uint(failure.retries as u64);
+    }
+    failures.urls(&archive.timed_out);
+    failures.uint(archive.cancelled as u64);
+    section(&mut zlib, FAILURES, failures)?;
+
+    let mut redirects: Vec<_> = archive.redirects.iter().collect();
+    redirects.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    let mut encoder = Encoder::default();
+    encoder.len(redirects.len());
+    for (url, chain) in redirects {
+        encoder.url(url);
+        encoder.urls(chain);
+    }
+    section(&mut zlib, REDIRECTS, encoder)?;
+
+    let mut meta: Vec<_> = archive.meta.iter().collect();
+    meta.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    let mut encoder = Encoder::default();
+    encoder.len(meta.len());
+    for (url, meta) in meta {
+        encoder.url(url);
+        encoder.meta(meta);
+    }
+    section(&mut zlib, META, encoder)?;
+
+    let mut stats = Encoder::default();
+    stats.duration(archive.stats.wall_time);
+    stats.len(archive.stats.fetches.len());
+    for fetch in &archive.stats.fetches {
+        stats.url(&fetch.url);
+        stats.duration(fetch.time_to_first_byte);
+        stats.duration(fetch.total);
+        stats.uint(fetch.bytes);
+    }
+    section(&mut zlib, STATS, stats)?;
+
+    if let Some(screenshot) = &archive.screenshot {
+        let mut encoder = Encoder::default();
+        encoder.bytes(screenshot);
+        section(&mut zlib, SCREENSHOT, encoder)?;
+    }
+
+    let mut snapshots: Vec<_> = archive
+        .meta
+        .iter()
+        .filter_map(|(url, meta)| Some((url, meta.snapshot.as_ref()?)))
+        .collect();
+    if !snapshots.is_empty() {
+        snapshots.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        let mut encoder = Encoder::default();
+        encoder.len(snapshots.len());
+        for (url, snapshot) in snapshots {
+            encoder.url(url);
+            encoder.url(snapshot);
+        }
+        section(&mut zlib, SNAPSHOTS, encoder)?;
+    }
+
+    let mut digests: Vec<_> = archive
+        .meta
+        .iter()
+        .filter_map(|(url, meta)| Some((url, meta.sha256.as_ref()?)))
+        .collect();
+    if !digests.is_empty() {
+        digests.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        let mut encoder = Encoder::default();
+        encoder.len(digests.len());
+        for (url, digest) in digests {
+            encoder.url(url);
+            encoder.str(digest);
+        }
+        section(&mut zlib, DIGESTS, encoder)?;
+    }
+
+    zlib.finish()?.flush()
+}
+
+fn section<W: Write>(
+    writer: &mut W,
+    tag: &[u8; 4],
+    encoder: Encoder,
+) -> io::Result<()> {
+    writer.write_all(tag)?;
+    writer.write_all(&(encoder.0.len() as u64).to_le_bytes())?;
+    writer.write_all(&encoder.0)
+}
+
+/// Read an archive written by [`save`] from `reader`
+pub(crate) fn load<R: Read>(mut reader: R) -> io::Result<PageArchive> {
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..8] != MAGIC {
+        return Err(invalid("not a web-archive file"));
+    }
+    let version = u32::from_le_bytes(header[8..].try_into().unwrap());
+    if version > VERSION {
+        return Err(invalid(format!(
+            "saved by a newer version of web-archive, format version {}",
+            version
+        )));
+    }
+    let mut data = Vec::new();
+    ZlibDecoder::new(reader).read_to_end(&mut data)?;
+
+    let mut sections = HashMap::new();
+    let mut rest = &data[..];
+    while !rest.is_empty() {
+        if rest.len() < 12 {
+            return Err(invalid("truncated section"));
+        }
+        let (tag, length) = rest[..12].split_at(4);
+        let length = u64::from_le_bytes(length.try_into().unwrap());
+        let length: usize = length
+            .try_into()
+            .ok()
+            .filter(|&length| length <= rest.len() - 12)
+            .ok_or_else(|| invalid("truncated section"))?;
+        sections.insert(tag, Decoder(&rest[12..12 + length]));
+        rest = &rest[12 + length..];
+    }
+    let mut section = |tag: &[u8; 4]| {
+        sections.remove(&tag[..]).ok_or_else(|| {
+            invalid(format!("missing {} section", String::from_utf8_lossy(tag)))
+        })
+    };
+
+    let mut page = section(PAGE)?;
+    let url = page.url()?;
+    let content = page.string()?;
+
+    let mut attributes = section(ATTRIBUTES)?;
+    let lazy_attributes = (0..attributes.len()?)
+        .map(|_| attributes.string())
+        .collect::<io::Result<_>>()?;
+    let mut url_attributes = Vec::new();
+    for _ in 0..attributes.len()? {
+        url_attributes.push((attributes.string()?, attributes.string()?));
+    }
+
+    let mut decoder = section(RESOURCES)?;
+    let mut resource_map = ResourceMap::new();
+    for _ in 0..decoder.len()? {
+        resource_map.insert(decoder.url()?, decoder.resource()?);
+    }
+
+    let mut decoder = section(FAILURES)?;
+    let mut failures = Vec::new();
+    for _ in 0..decoder.len()? {
+        failures.push(ResourceFailure {
+            url: decoder.url()?,
+            reason: decoder.reason()?,
+            retries: decoder.len()?,
+        });
+    }
+    let timed_out = decoder.urls()?;
+    let cancelled = decoder.uint()? != 0;
+
+    let mut decoder = section(REDIRECTS)?;
+    let mut redirects = HashMap::new();
+    for _ in 0..decoder.len()? {
+        redirects.insert(decoder.url()?, decoder.urls()?);
+    }
+
+    let mut decoder = section(META)?;
+    let mut meta = HashMap::new();
+    for _ in 0..decoder.len()? {
+        meta.insert(decoder.url()?, decoder.meta()?);
+    }
+
+    let mut decoder = section(STATS)?;
+    let wall_time = decoder.duration()?;
+    let mut fetches = Vec::new();
+    for _ in 0..decoder.len()? {
+        fetches.push(FetchTiming {
+            url: decoder.url()?,
+            time_to_first_byte: decoder.duration()?,
+            total: decoder.duration()?,
+            bytes: decoder.uint()?,
+        });
+    }
+
+    // Only there if the archive has a screenshot
+    let screenshot = match sections.remove(&SCREENSHOT[..]) {
+        Some(mut decoder) => Some(Bytes::copy_from_slice(decoder.bytes()?)),
+        None => None,
+    };
+
+    // Only there if the page or its resources have been submitted to the
+    // Wayback Machine
+    if let Some(mut decoder) = sections.remove(&SNAPSHOTS[..]) {
+        for _ in 0..decoder.len()? {
+            let url = decoder.url()?;
+            let snapshot = decoder.url()?;
+            if let Some(meta) = meta.get_mut(&url) {
+                meta.snapshot = Some(snapshot);
+            }
+        }
+    }
+
+    // Only there if the archive was written by a version of this crate
+    // which records digests
+    if let Some(mut decoder) = sections.remove(&DIGESTS[..]) {
+        for _ in 0..decoder.len()? {
+            let url = decoder.url()?;
+            let digest = decoder.string()?;
+            if let Some(meta) = meta.get_mut(&url) {
+                meta.sha256 = Some(digest);
+            }
+        }
+    }
+
+    Ok(PageArchive {
+        url,
+        content,
+        resource_map,
+        lazy_attributes,
+        url_attributes,
+        timed_out,
+        failures,
+        cancelled,
+        redirects,
+        meta,
+        stats: ArchiveStats { wall_time, fetches },
+        screenshot,
+    })
+}
+
+fn invalid<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Kinds of resource, which mustn't be renumbered
+fn kind(resource: &Resource) -> u64 {
+    match resource {
+        Resource::Javascript(_) => 0,
+        Resource::Css(_) => 1,
+        Resource::Document(_) => 2,
+        Resource::Image(_) => 3,
+        Resource::Object(_) => 4,
+        Resource::Font(_) => 5,
+        Resource::Media(_) => 6,
+        Resource::Attachment(_) => 7,
+        Resource::Other { .. } => 8,
+    }
+}
+
+/// Builds up the contents of a section
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn uint(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.0.push(byte);
+                return;
+            }
+            self.0.push(byte | 0x80);
+        }
+    }
+
+    fn len(&mut self, len: usize) {
+        self.uint(len as u64);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    /// An optional string, with `0` for `None` and `1` before `Some`
+    fn option(&mut self, s: Option<&str>) {
+        match s {
+            Some(s) => {
+                self.uint(1);
+                self.str(s);
+            }
+            None => self.uint(0),
+        }
+    }
+
+    fn url(&mut self, url: &Url) {
+        self.str(url.as_str());
+    }
+
+    fn urls(&mut self, urls: &[Url]) {
+        self.len(urls.len());
+        for url in urls {
+            self.url(url);
+        }
+    }
+
+    fn duration(&mut self, duration: Duration) {
+        self.uint(duration.as_secs());
+        self.uint(duration.subsec_nanos().into());
+    }
+
+    fn resource(&mut self, resource: &Resource) {
+        self.uint(kind(resource));
+        self.str(resource.mimetype());
+        let charset = match resource {
+            Resource::Javascript(r)
+            | Resource::Css(r)
+            | Resource::Document(r) => r.charset.as_deref(),
+            _ => None,
+        };
+        self.option(charset);
+        self.bytes(resource.data());
+    }
+
+    /// A failure reason, with its description so that versions which
+    /// don't know the kind of failure can still say what it was
+    fn reason(&mut self, reason: &FailureReason) {
+        let (kind, status) = match reason {
+            FailureReason::Status(status) => (0, *status),
+            FailureReason::TooLarge => (1, 0),
+            FailureReason::OverBudget => (2, 0),
+            FailureReason::DisallowedScheme => (3, 0),
+            FailureReason::TimedOut => (4, 0),
+            FailureReason::Network(_) => (5, 0),
+            FailureReason::NotInWarc => (6, 0),
+            FailureReason::NotInHar => (7, 0),
+            FailureReason::Unreadable(_) => (8, 0),
+            FailureReason::NotRecorded => (9, 0),
+            FailureReason::DisallowedHost => (10, 0),
+        };
+        self.uint(kind);
+        self.uint(status.into());
+        self.str(&reason.to_string());
+    }
+
+    fn meta(&mut self, meta: &ResourceMeta) {
+        self.uint(meta.status.into());
+        self.url(&meta.final_url);
+        let fetched_at = meta
+            .fetched_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.duration(fetched_at);
+        self.option(meta.content_type.as_deref());
+        self.option(meta.cache_control.as_deref());
+        self.option(meta.validators.etag.as_deref());
+        self.option(meta.validators.last_modified.as_deref());
+    }
+}
+
+/// Reads back the contents of a section written by an [`Encoder`]
+struct Decoder<'a>(&'a [u8]);
+
+impl Decoder<'_> {
+    fn uint(&mut self) -> io::Result<u64> {
+        let mut n = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) =
+                self.0.split_first().ok_or_else(|| invalid("truncated"))?;
+            self.0 = rest;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(invalid("number too large"))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        self.uint()?
+            .try_into()
+            .map_err(|_| invalid("length too large"))
+    }
+
+    fn bytes(&mut self) -> io::Result<&[u8]> {
+        let len = self.len()?;
+        if len > self.0.len() {
+            return Err(invalid("truncated"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let bytes = self.bytes()?.to_vec();
+        String::from_utf8(bytes).map_err(invalid)
+    }
+
+    fn option(&mut self) -> io::Result<Option<String>> {
+        match self.uint()? {
+            0 => Ok(None),
+            _ => self.string().map(Some),
+        }
+    }
+
+    fn url(&mut self) -> io::Result<Url> {
+        Url::parse(&self.string()?).map_err(invalid)
+    }
+
+    fn urls(&mut self) -> io::Result<Vec<Url>> {
+        (0..self.len()?).map(|_| self.url()).collect()
+    }
+
+    fn duration(&mut self) -> io::Result<Duration> {
+        let secs = self.uint()?;
+        let nanos = self.uint()?;
+        let nanos = nanos
+            .try_into()
+            .ok()
+            .filter(|&nanos| nanos < 1_000_000_000)
+            .ok_or_else(|| invalid("invalid duration"))?;
+        Ok(Duration::new(secs, nanos))
+    }
+
+    fn time(&mut self) -> io::Result<SystemTime> {
+        UNIX_EPOCH
+            .checked_add(self.duration()?)
+            .ok_or_else(|| invalid("invalid time"))
+    }
+
+    fn resource(&mut self) -> io::Result<Resource> {
+        let kind = self.uint()?;
+        let mimetype = self.string()?;
+        let charset = self.option()?;
+        let data = Bytes::copy_from_slice(self.bytes()?);
+        let text = || TextResource {
+            data: data.clone(),
+            mimetype: mimetype.clone(),
+            charset: charset.clone(),
+        };
+        let image = || ImageResource {
+            data: data.clone(),
+            mimetype: mimetype.clone(),
+        };
+        Ok(match kind {
+            0 => Resource::Javascript(text()),
+            1 => Resource::Css(text()),
+            2 => Resource::Document(text()),
+            3 => Resource::Image(image()),
+            4 => Resource::Object(image()),
+            5 => Resource::Font(image()),
+            6 => Resource::Media(image()),
+            7 => Resource::Attachment(image()),
+            _ => Resource::Other {
+                data: data.clone(),
+                mimetype: mimetype.clone(),
+            },
+        })
+    }
+
+    fn reason(&mut self) -> io::Result<FailureReason> {
+        let kind = self.uint()?;
+        let status = self.uint()?;
+        let description = self.string()?;
+        Ok(match kind {
+            0 => FailureReason::Status(
+                status.try_into().map_err(|_| invalid("invalid status"))?,
+            ),
+            1 => FailureReason::TooLarge,
+            2 => FailureReason::OverBudget,
+            3 => FailureReason::DisallowedScheme,
+            4 => FailureReason::TimedOut,
+            6 => FailureReason::NotInWarc,
+            7 => FailureReason::NotInHar,
+            8 => FailureReason::Unreadable(description),
+            9 => FailureReason::NotRecorded,
+            10 => FailureReason::DisallowedHost,
+            _ => FailureReason::Network(description),
+        })
+    }
+
+    fn meta(&mut self) -> io::Result<ResourceMeta> {
+        let status = self.uint()?;
+        Ok(ResourceMeta {
+            status: status.try_into().map_err(|_| invalid("invalid status"))?,
+            final_url: self.url()?,
+            fetched_at: self.time()?,
+            content_type: self.option()?,
+            cache_control: self.option()?,
+            validators: Validators {
+                etag: self.option()?,
+                last_modified: self.option()?,
+            },
+            snapshot: None,
+            sha256: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn archive() -> PageArchive {
+        let url = Url::parse("http://example.com/").unwrap();
+        let style = url.join("style.css").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            style.clone(),
+            Resource::Css(TextResource {
+                data: Bytes::from_static(b"a {}"),
+                mimetype: "text/css".to_string(),
+                charset: Some("Shift_JIS".to_string()),
+            }),
+        );
+        resource_map.insert(
+            url.join("data.bin").unwrap(),
+            Resource::Other {
+                data: Bytes::from(vec![0; 1000]),
+                mimetype: "application/octet-stream".to_string(),
+            },
+        );
+        let mut redirects = HashMap::new();
+        redirects.insert(url.join("old").unwrap(), vec![url.clone()]);
+        let mut meta = HashMap::new();
+        meta.insert(
+            style.clone(),
+            ResourceMeta {
+                status: 200,
+                final_url: style.clone(),
+                fetched_at: UNIX_EPOCH + Duration::new(1_600_000_000, 5),
+                content_type: Some("text/css".to_string()),
+                cache_control: None,
+                validators: Validators {
+                    etag: Some("\"abc\"".to_string()),
+                    last_modified: None,
+                },
+                snapshot: Some(
+                    Url::parse("https://web.archive.org/web/2020/style.css")
+                        .unwrap(),
+                ),
+                sha256: Some(crate::digest::sha256(b"a {}")),
+            },
+        );
+        PageArchive {
+            url: url.clone(),
+            content: "<p>\u{65e5}\u{672c}</p>".to_string(),
+            resource_map,
+            lazy_attributes: vec!["data-src".to_string()],
+            url_attributes: vec![("img".to_string(), "data-hi".to_string())],
+            timed_out: vec![url.join("slow.png").unwrap()],
+            failures: vec![
+                ResourceFailure {
+                    url: url.join("gone.png").unwrap(),
+                    reason: FailureReason::Status(404),
+                    retries: 2,
+                },
+                ResourceFailure {
+                    url: url.join("down.png").unwrap(),
+                    reason: FailureReason::Network("refused".to_string()),
+                    retries: 0,
+                },
+            ],
+            cancelled: true,
+            redirects,
+            meta,
+            stats: ArchiveStats {
+                wall_time: Duration::from_millis(1500),
+                fetches: vec![FetchTiming {
+                    url: style,
+                    time_to_first_byte: Duration::from_millis(10),
+                    total: Duration::from_millis(20),
+                    bytes: 4,
+                }],
+            },
+            screenshot: Some(Bytes::from_static(b"\x89PNG")),
+        }
+    }
+
+    #[test]
+    fn test_save_load() {
+        let archive = archive();
+        let mut file = Vec::new();
+        save(&archive, &mut file).unwrap();
+        assert!(file.starts_with(MAGIC));
+        // The zeros compress away
+        assert!(file.len() < 1000);
+
+        let loaded = load(&file[..]).unwrap();
+        assert_eq!(loaded.url, archive.url);
+        assert_eq!(loaded.content, archive.content);
+        assert_eq!(loaded.resource_map, archive.resource_map);
+        assert_eq!(loaded.lazy_attributes, archive.lazy_attributes);
+        assert_eq!(loaded.url_attributes, archive.url_attributes);
+        assert_eq!(loaded.timed_out, archive.timed_out);
+        assert_eq!(loaded.failures, archive.failures);
+        assert_eq!(loaded.cancelled, archive.cancelled);
+        assert_eq!(loaded.redirects, archive.redirects);
+        assert_eq!(loaded.meta, archive.meta);
+        assert_eq!(loaded.stats, archive.stats);
+        assert_eq!(loaded.screenshot, archive.screenshot);
+
+        let mut again = Vec::new();
+        save(&loaded, &mut again).unwrap();
+        assert_eq!(again, file);
+    }
+
+    #[test]
+    fn test_invalid_time() {
+        let mut e = Encoder::default();
+        e.duration(Duration::new(u64::MAX, 999_999_999));
+        let err = Decoder(&e.0).time().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_compatibility() {
+        // Sections that this version doesn't know about are skipped, as
+        // are kinds of resource and failure
+        let mut data = Vec::new();
+        for (tag, encoder) in [
+            (PAGE, {
+                let mut e = Encoder::default();
+                e.str("http://example.com/");
+                e.str("page");
+                e
+            }),
+            (b"NEW!", Encoder(b"whatever".to_vec())),
+            (ATTRIBUTES, Encoder(vec![0, 0])),
+            (RESOURCES, {
+                let mut e = Encoder::default();
+                e.len(1);
+                e.str("http://example.com/new");
+                e.uint(100);
+                e.str("application/x-new");
+                e.option(None);
+                e.bytes(b"new");
+                e
+            }),
+            (FAILURES, {
+                let mut e = Encoder::default();
+                e.len(1);
+                e.str("http://example.com/failed");
+                e.uint(100);
+                e.uint(0);
+                e.str("something new");
+                e.uint(0);
+                e.len(0);
+                e.uint(0);
+                e
+            }),
+            (REDIRECTS, Encoder(vec![0])),
+            (META, Encoder(vec![0])),
+            (STATS, Encoder(vec![0, 0, 0])),
+        ] {
+            section(&mut data, tag, encoder).unwrap();
+        }
+        let mut file = MAGIC.to_vec();
+        file.extend_from_slice(&VERSION.to_le_bytes());
+        let mut zlib = ZlibEncoder::new(file, Compression::default());
+        zlib.write_all(&data).unwrap();
+        let file = zlib.finish().unwrap();
+
+        let loaded = load(&file[..]).unwrap();
+        assert_eq!(loaded.content, "page");
+        let new = Url::parse("http://example.com/new").unwrap();
+        assert_eq!(
+            loaded.resource_map[&new],
+            Resource::Other {
+                data: Bytes::from_static(b"new"),
+                mimetype: "application/x-new".to_string(),
+            }
+        );
+        assert_eq!(
+            loaded.failures[0].reason,
+            FailureReason::Network("something new".to_string())
+        );
+        assert_eq!(loaded.screenshot, None);
+
+        // Newer versions of the format, other files, and damaged files
+        // are refused
+        let mut newer = file.clone();
+        newer[8] = 2;
+        let error = load(&newer[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(load(&b"<html></html>"[..]).is_err());
+        assert!(load(&file[..file.len() - 10]).is_err());
+    }
+}
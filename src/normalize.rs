@@ -0,0 +1,204 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for telling whether two URLs are for the same thing, see
+//! [`UrlNormalizer`]
+
+use url::Url;
+
+/// Query parameters which only track where a visitor came from, removed
+/// by [`UrlNormalizer::strip_tracking_params`]
+const TRACKING_PARAMS: &[&str] = &[
+    "utm_*", "fbclid", "gclid", "dclid", "msclkid", "yclid", "mc_cid",
+    "mc_eid", "_ga", "_gl", "_hsenc", "_hsmi", "igshid", "ref_src",
+];
+
+/// Only sorts the query, for
+/// [`ArchiveOptions::sort_query`](crate::ArchiveOptions::sort_query)
+pub(crate) static SORT_QUERY: UrlNormalizer = UrlNormalizer {
+    sort_query: true,
+    strip_params: Vec::new(),
+    ignore_trailing_slash: false,
+};
+
+/// Puts URLs into a canonical form, so that URLs which differ only in ways
+/// that don't change what they point at compare equal, e.g.
+/// `http://Example.com:80/about/?utm_source=feed#team` and
+/// `http://example.com/about`.
+///
+/// The fragment is always removed. The host is always compared in lower
+/// case and default ports are always left out, as parsing the URL does
+/// that already. The rest is up to the options below.
+///
+/// [`crawl`](crate::crawl) uses one to avoid archiving the same page
+/// more than once, see
+/// [`CrawlOptions::normalizer`](crate::CrawlOptions::normalizer), and an
+/// archive can use one to avoid downloading the same resource more than
+/// once, see
+/// [`ArchiveOptions::url_normalizer`](crate::ArchiveOptions::url_normalizer).
+///
+/// ## Example
+/// ```
+/// use url::Url;
+/// use web_archive::UrlNormalizer;
+///
+/// let normalizer = UrlNormalizer::for_pages();
+/// let url = "http://Example.com:80/about/?utm_source=feed&b=2&a=1";
+/// let url = Url::parse(url).unwrap();
+/// assert_eq!(
+///     normalizer.normalize(&url).as_str(),
+///     "http://example.com/about?a=1&b=2"
+/// );
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UrlNormalizer {
+    /// Sort the query parameters, so that their order doesn't matter,
+    /// e.g. `?w=64&h=64` and `?h=64&w=64`
+    ///
+    /// Default: `false`
+    pub sort_query: bool,
+    /// The names of query parameters to remove. A name ending in `*`
+    /// removes every parameter starting with the rest of it, e.g. `utm_*`.
+    ///
+    /// Default: empty
+    pub strip_params: Vec<String>,
+    /// Remove a trailing slash from the path, other than the root's, so
+    /// that e.g. `/about/` and `/about` are the same
+    ///
+    /// Default: `false`
+    pub ignore_trailing_slash: bool,
+}
+
+impl UrlNormalizer {
+    /// A normalizer which only removes the fragment
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A normalizer suited to pages, as [`crawl`](crate::crawl) uses by
+    /// default, which sorts the query, strips the
+    /// [tracking parameters](UrlNormalizer::strip_tracking_params), and
+    /// ignores trailing slashes
+    pub fn for_pages() -> Self {
+        Self::new()
+            .sort_query(true)
+            .strip_tracking_params()
+            .ignore_trailing_slash(true)
+    }
+
+    /// Set whether to sort the query parameters, see
+    /// [`UrlNormalizer::sort_query`](#structfield.sort_query)
+    pub fn sort_query(mut self, sort: bool) -> Self {
+        self.sort_query = sort;
+        self
+    }
+
+    /// Remove the query parameter `name`, see
+    /// [`UrlNormalizer::strip_params`](#structfield.strip_params)
+    pub fn strip_param(mut self, name: &str) -> Self {
+        self.strip_params.push(name.to_string());
+        self
+    }
+
+    /// Remove the query parameters which are commonly added to track
+    /// where visitors came from, such as `utm_source`, `fbclid` and
+    /// `gclid`
+    pub fn strip_tracking_params(mut self) -> Self {
+        let params = TRACKING_PARAMS.iter().map(|param| param.to_string());
+        self.strip_params.extend(params);
+        self
+    }
+
+    /// Set whether to ignore trailing slashes, see
+    /// [`UrlNormalizer::ignore_trailing_slash`](#structfield.ignore_trailing_slash)
+    pub fn ignore_trailing_slash(mut self, ignore: bool) -> Self {
+        self.ignore_trailing_slash = ignore;
+        self
+    }
+
+    /// The canonical form of `url`
+    pub fn normalize(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        let rewrite = self.sort_query || !self.strip_params.is_empty();
+        if rewrite && url.query().is_some() {
+            let mut pairs: Vec<(String, String)> = url
+                .query_pairs()
+                .into_owned()
+                .filter(|(name, _)| !self.strips(name))
+                .collect();
+            if self.sort_query {
+                pairs.sort();
+            }
+            if pairs.is_empty() {
+                url.set_query(None);
+            } else {
+                url.query_pairs_mut().clear().extend_pairs(pairs);
+            }
+        }
+        if self.ignore_trailing_slash
+            && url.path().len() > 1
+            && url.path().ends_with('/')
+        {
+            let path = url.path().trim_end_matches('/').to_string();
+            url.set_path(&path);
+        }
+        url
+    }
+
+    /// Whether the query parameter `name` is removed
+    fn strips(&self, name: &str) -> bool {
+        self.strip_params
+            .iter()
+            .any(|param| match param.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == param,
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let normalize = |normalizer: &UrlNormalizer, url: &str| {
+            let url = Url::parse(url).unwrap();
+            normalizer.normalize(&url).to_string()
+        };
+
+        let normalizer = UrlNormalizer::new();
+        assert_eq!(
+            normalize(&normalizer, "HTTPS://Example.COM:443/a/?b=2&a=1#top"),
+            "https://example.com/a/?b=2&a=1"
+        );
+
+        let normalizer = UrlNormalizer::for_pages();
+        assert_eq!(
+            normalize(
+                &normalizer,
+                "http://example.com/a/?utm_source=x&b=2&fbclid=y&a=1"
+            ),
+            "http://example.com/a?a=1&b=2"
+        );
+        assert_eq!(
+            normalize(&normalizer, "http://example.com/?utm_medium=email"),
+            "http://example.com/"
+        );
+        assert_eq!(
+            normalize(&normalizer, "http://example.com/a//"),
+            "http://example.com/a"
+        );
+
+        let normalizer = UrlNormalizer::new().strip_param("session");
+        assert_eq!(
+            normalize(&normalizer, "http://example.com/?session=1&sessions=2"),
+            "http://example.com/?sessions=2"
+        );
+    }
+}
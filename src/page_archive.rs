@@ -7,83 +7,1216 @@
 
 //! Module for the core archiving functionality
 
-use crate::parsing::{Resource, ResourceMap};
+use crate::css;
+use crate::dom;
+use crate::error::Error;
+use crate::failure::ResourceFailure;
+use crate::filter_list::FilterList;
+use crate::http_cache::{CachedResponse, HttpCache, MemoryCache};
+use crate::javascript;
+use crate::links::Link;
+use crate::markdown::MarkdownOptions;
+use crate::meta::ResourceMeta;
+use crate::metadata::ParsedPage;
+use crate::parsing::{
+    base_url, file_name, has_rel, is_image_input, is_module_script,
+    is_srcset_attribute, matches_tag, parse_srcset, preload_kind, resolve_url,
+    svg_href_name, Resource, ResourceMap,
+};
+use crate::provenance::Provenance;
+use crate::reader::Article;
+use crate::save_page_now::SavePageNowOptions;
+use crate::source_map;
+use crate::stats::ArchiveStats;
+use crate::structured_data::StructuredData;
+use crate::verify::Verification;
+use crate::{ArchiveOptions, Archiver};
+use bytes::Bytes;
 use html5ever::{interface::QualName, local_name, namespace_url, ns};
+use kuchiki::iter::NodeIterator;
 use kuchiki::traits::TendrilSink;
-use kuchiki::{parse_html, NodeData, NodeRef};
+use kuchiki::{
+    parse_html, Attribute, Attributes, ExpandedName, NodeData, NodeRef,
+    Selectors,
+};
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::path::Path;
 use url::Url;
 
 /// Intermediate struct storing the downloaded resources
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PageArchive {
-    /// Base URL of the page being archived
+    /// Base URL of the page being archived, after following any redirects
     pub url: Url,
     /// The content/body of the page
     pub content: String,
     /// A mapping of resource URLs to the downloaded resources
     pub resource_map: ResourceMap,
+    /// Attributes holding the real URLs of lazy-loaded images, see
+    /// [`ArchiveOptions::lazy_attributes`](crate::ArchiveOptions::lazy_attributes)
+    pub lazy_attributes: Vec<String>,
+    /// Element and attribute names of additional attributes holding
+    /// resource URLs, see
+    /// [`ArchiveOptions::url_attributes`](crate::ArchiveOptions::url_attributes)
+    pub url_attributes: Vec<(String, String)>,
+    /// Resources which weren't downloaded because their request timed
+    /// out, or because the archive's
+    /// [`deadline`](crate::ArchiveOptions::deadline) passed first. They
+    /// are left pointing at their original URLs.
+    pub timed_out: Vec<Url>,
+    /// Resources which couldn't be downloaded, including those which
+    /// timed out. They are left pointing at their original URLs.
+    pub failures: Vec<ResourceFailure>,
+    /// Whether the archive was cancelled before all of its resources had
+    /// been downloaded, see
+    /// [`ArchiveOptions::cancellation`](crate::ArchiveOptions::cancellation)
+    pub cancelled: bool,
+    /// Where the page and its resources were redirected, keyed by the URL
+    /// that was requested. Each holds the URLs it was redirected through
+    /// in order, ending with the one it was downloaded from. URLs which
+    /// weren't redirected are left out.
+    pub redirects: HashMap<Url, Vec<Url>>,
+    /// Where and when the page and each resource were downloaded from,
+    /// keyed by the same URLs as the page and the
+    /// [`resource_map`](#structfield.resource_map). Their validators are
+    /// what [`PageArchive::refresh`] asks the server with.
+    pub meta: HashMap<Url, ResourceMeta>,
+    /// How long the requests took and how much they downloaded
+    pub stats: ArchiveStats,
+    /// A PNG screenshot of the page as a browser rendered it, as a visual
+    /// reference for when the archived page doesn't look quite the same,
+    /// see [`ArchiveOptions::screenshot`](crate::ArchiveOptions::screenshot)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::serde_data::option")
+    )]
+    pub screenshot: Option<Bytes>,
 }
 
+/// What changed when a [`PageArchive`] was refreshed, see
+/// [`PageArchive::refresh`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Refresh {
+    /// Whether the page itself changed, or was redirected somewhere else
+    pub page_changed: bool,
+    /// Resources which changed or are new, in order of their URLs
+    pub updated: Vec<Url>,
+    /// Resources which the page no longer needs, and were removed from
+    /// the archive, in order of their URLs
+    pub removed: Vec<Url>,
+}
+
+/// Options controlling how an archive is written out to disk by
+/// [`PageArchive::write_to_disk_with`]
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+    /// Sync each file to disk before moving it into place, and the
+    /// directories holding them afterwards, so that the archive survives
+    /// a crash or power cut once it's been written. This makes writing
+    /// much slower, especially for pages with many resources.
+    ///
+    /// The single files written by [`PageArchive::save`] and the other
+    /// exports are always synced.
+    ///
+    /// Default: `false`
+    pub sync: bool,
+}
+
+/// Options controlling how resources are embedded into the page by
+/// [`PageArchive::embed_resources_with`]
+#[derive(Debug, Default)]
+pub struct EmbedOptions<'a> {
+    /// Remove `<link>` resource hints which would only leak requests to
+    /// the original site from the archived page: `preconnect`,
+    /// `dns-prefetch`, `prefetch`, and `prerender`.
+    ///
+    /// Default: `false`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::EmbedOptions;
+    /// let options = EmbedOptions {
+    ///     strip_resource_hints: true,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub strip_resource_hints: bool,
+    /// Remove the elements hidden by the element hiding rules of an ad
+    /// and tracker filter list, along with any elements loading resources
+    /// which the list blocks. Use the same list as
+    /// [`ArchiveOptions::filter_list`](crate::ArchiveOptions::filter_list)
+    /// to clean up after the resources it skipped.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{EmbedOptions, FilterList};
+    /// let list = FilterList::parse("##.ad-banner");
+    /// let options = EmbedOptions {
+    ///     filter_list: Some(&list),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub filter_list: Option<&'a FilterList>,
+    /// Leave scripts out of the page. Unless
+    /// [`keep_unarchived_scripts`](#structfield.keep_unarchived_scripts)
+    /// is set, this disables them.
+    ///
+    /// Default: `false`
+    pub skip_scripts: bool,
+    /// Leave images pointing at their original URLs rather than embedding
+    /// them, including those referenced from CSS.
+    ///
+    /// Default: `false`
+    pub skip_images: bool,
+    /// Largest `data:` URI to embed, in bytes of base-64 encoded data.
+    /// Larger resources are treated as though they weren't archived.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::EmbedOptions;
+    /// let options = EmbedOptions {
+    ///     max_data_uri_size: Some(1024 * 1024),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_data_uri_size: Option<usize>,
+    /// Point anything which wasn't embedded at its original absolute URL,
+    /// so that it can still be loaded from the network. Otherwise
+    /// relative URLs are left as they are, and break once the page is
+    /// saved somewhere else.
+    ///
+    /// Default: `false`
+    pub keep_original_urls: bool,
+    /// Leave the `src` of scripts which weren't archived in place, rather
+    /// than removing it so that the archived page doesn't load scripts
+    /// from the network.
+    ///
+    /// Default: `false`
+    pub keep_unarchived_scripts: bool,
+    /// Record where and when the page was archived from within the
+    /// embedded page, with metadata and optionally a banner, so that
+    /// anyone viewing the file knows it's an archive. Frames within the
+    /// page aren't marked.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{EmbedOptions, Provenance};
+    /// let options = EmbedOptions {
+    ///     provenance: Some(Provenance::Banner(
+    ///         ".web-archive-banner { background: black; color: white }",
+    ///     )),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub provenance: Option<Provenance<'a>>,
+}
+
+impl EmbedOptions<'_> {
+    /// Whether any resources have to be left out of the page
+    fn skips_resources(&self) -> bool {
+        self.skip_scripts
+            || self.skip_images
+            || self.max_data_uri_size.is_some()
+    }
+
+    /// Whether an archived resource should be embedded
+    fn embeds(&self, resource: &Resource) -> bool {
+        match resource {
+            Resource::Javascript(_) if self.skip_scripts => false,
+            Resource::Image(_) if self.skip_images => false,
+            _ => match self.max_data_uri_size {
+                Some(max) => (resource.data().len() + 2) / 3 * 4 <= max,
+                None => true,
+            },
+        }
+    }
+
+    /// The original absolute URL to use in place of a URL from the page
+    /// which wasn't embedded, if
+    /// [`keep_original_urls`](#structfield.keep_original_urls) is set
+    pub(crate) fn original_url(&self, base: &Url, u: &str) -> Option<String> {
+        if self.keep_original_urls {
+            resolve_url(base, u).map(String::from)
+        } else {
+            None
+        }
+    }
+
+    /// Like [`EmbedOptions::original_url`], for the value of an attribute
+    /// which may be a `srcset`
+    pub(crate) fn original_attribute(
+        &self,
+        base: &Url,
+        name: &str,
+        value: &str,
+    ) -> Option<String> {
+        if !is_srcset_attribute(name) {
+            return self.original_url(base, value);
+        }
+        if !self.keep_original_urls {
+            return None;
+        }
+        let srcset: Vec<_> = parse_srcset(value)
+            .iter()
+            .map(|c| {
+                let url = self
+                    .original_url(base, c.url)
+                    .unwrap_or_else(|| c.url.to_string());
+                format!("{} {}", url, c.descriptor).trim_end().to_string()
+            })
+            .collect();
+        Some(srcset.join(", "))
+    }
+
+    /// Whether to remove the `src` of a script, depending on whether it
+    /// was embedded
+    pub(crate) fn strips_script_src(&self, embedded: bool) -> bool {
+        embedded || !self.keep_unarchived_scripts
+    }
+}
+
+/// Attributes holding URLs which are pointed back at the network by
+/// [`EmbedOptions::keep_original_urls`]
+pub(crate) const ORIGINAL_URL_ATTRIBUTES: &[&str] =
+    &["src", "href", "data", "poster", "srcset"];
+
+/// Attributes of `<video>`, `<audio>`, `<source>`, and `<track>` which
+/// refer to resources, see [`PageArchive::media_attribute`]
+pub(crate) const MEDIA_ATTRIBUTES: &[&str] = &["src", "poster", "srcset"];
+
 impl PageArchive {
-    /// Searches `img`, `link`, and `script` tags in the page body and
-    /// substitutes in the downloaded content.
+    /// Whether every resource that the page needs was archived, i.e.
+    /// none of them failed and the archive wasn't cancelled.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// if !archive.is_complete() {
+    ///     for failure in &archive.failures {
+    ///         println!("{}: {}", failure.url, failure.reason);
+    ///     }
+    /// }
+    /// ```
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty() && !self.cancelled
+    }
+
+    /// Parse the page into a DOM, e.g. to query its title or metadata.
+    ///
+    /// The DOM built while discovering the resources isn't kept, because
+    /// its nodes are reference counted and holding on to it would stop
+    /// both `PageArchive` and the future returned by
+    /// [`archive`](crate::archive) from being `Send`. Each call parses a
+    /// fresh copy, which may be modified freely.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// let document = archive.document();
+    /// if let Ok(title) = document.select_first("title") {
+    ///     println!("{}", title.text_contents());
+    /// }
+    /// ```
+    pub fn document(&self) -> NodeRef {
+        parse_html().one(self.content.as_str())
+    }
+
+    /// Parse the page once for reading several of its metadata, links,
+    /// and structured data. The accessors on `PageArchive` itself, such
+    /// as [`PageArchive::title`], parse the page each time they're called.
+    /// Like [`PageArchive::document`], the parsed page isn't `Send`.
+    pub fn parse(&self) -> ParsedPage {
+        ParsedPage::new(self.url.clone(), self.document())
+    }
+
+    /// The text of the page's `<title>`, with its whitespace collapsed,
+    /// or `None` if it doesn't have one.
+    ///
+    /// This and the other metadata accessors parse the page each time
+    /// they're called, so use [`PageArchive::parse`] to read more than
+    /// one or two of them.
+    pub fn title(&self) -> Option<String> {
+        self.parse().title()
+    }
+
+    /// The page's `<meta name="description">`, or its `og:description`
+    /// if it doesn't have one
+    pub fn description(&self) -> Option<String> {
+        self.parse().description()
+    }
+
+    /// The canonical URL of the page, from its `<link rel="canonical">`,
+    /// resolved against the page's URL
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com/?ref=1", Default::default())
+    ///         .unwrap();
+    /// let url = archive.canonical_url().unwrap_or(archive.url.clone());
+    /// println!("Archived {}", url);
+    /// ```
+    pub fn canonical_url(&self) -> Option<Url> {
+        self.parse().canonical_url()
+    }
+
+    /// The URL of the page after this one, for articles and listings
+    /// split across several pages, from its `<link rel="next">` or
+    /// `<a rel="next">`, resolved against the page's URL. See
+    /// [`archive_paginated`](crate::archive_paginated) to archive every
+    /// page.
+    pub fn next_page_url(&self) -> Option<Url> {
+        self.parse().next_page_url()
+    }
+
+    /// The URL of the page before this one, from its `rel="prev"` or
+    /// `rel="previous"` link, like [`PageArchive::next_page_url`]
+    pub fn prev_page_url(&self) -> Option<Url> {
+        self.parse().prev_page_url()
+    }
+
+    /// The language of the page, as a language tag such as `en-GB`, from
+    /// the `lang` attribute of its `<html>` element, or its
+    /// `<meta http-equiv="Content-Language">` if it doesn't have one
+    pub fn language(&self) -> Option<String> {
+        self.parse().language()
+    }
+
+    /// The page's Open Graph properties, from its
+    /// `<meta property="og:...">` elements, keyed by their full names
+    /// such as `og:title` or `article:published_time`. Where a property
+    /// is given more than once, as `og:image` can be, the first is kept.
     ///
-    /// * Images are base-64 encoded and inserted as `data:` URIs
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// if let Some(image) = archive.open_graph().get("og:image") {
+    ///     println!("Preview image: {}", image);
+    /// }
+    /// ```
+    pub fn open_graph(&self) -> HashMap<String, String> {
+        self.parse().open_graph()
+    }
+
+    /// The links out of the page, from its `<a href>` and `<area href>`
+    /// elements in the order they appear, with their URLs resolved
+    /// against the page's. Links which run scripts, with `javascript:`
+    /// URLs, are left out, but links within the page are kept.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// for link in archive.links() {
+    ///     if !link.rel.iter().any(|rel| rel == "nofollow") {
+    ///         println!("{} ({})", link.url, link.text);
+    ///     }
+    /// }
+    /// ```
+    pub fn links(&self) -> Vec<Link> {
+        self.parse().links()
+    }
+
+    /// The structured data embedded in the page: its JSON-LD scripts, and
+    /// its microdata and RDFa items, for indexing the products, articles,
+    /// events, and so on that it describes. See [`StructuredData`].
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// for value in archive.structured_data().json_ld {
+    ///     if value["@type"] == "Product" {
+    ///         println!("{}", value["name"]);
+    ///     }
+    /// }
+    /// ```
+    pub fn structured_data(&self) -> StructuredData {
+        self.parse().structured_data()
+    }
+
+    /// Check that the archive holds everything its page needs: that every
+    /// resource referenced by the page, its frames, and its scripts'
+    /// imports is either in the [`resource_map`](#structfield.resource_map)
+    /// or recorded as one of the [`failures`](#structfield.failures), and
+    /// that the page and resources haven't changed since their
+    /// [`sha256`](ResourceMeta::sha256) digests were recorded, and that
+    /// resources loaded with an `integrity` attribute match its hash.
+    /// Resources are found and skipped the same way as when the page was
+    /// archived with the `options`, so those which they leave out, such
+    /// as images if [`images`](crate::ArchiveOptions::images) is `false`,
+    /// aren't counted as missing.
+    ///
+    /// This is useful after loading an archive which may have been
+    /// damaged, or after editing its resources.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking::Archiver;
+    /// use web_archive::ArchiveOptions;
+    ///
+    /// let options = ArchiveOptions::default().images(false);
+    /// let archiver = Archiver::new(options).unwrap();
+    /// let archive = archiver.archive("http://example.com").unwrap();
+    /// let verification = archive.verify(archiver.options());
+    /// if !verification.is_ok() {
+    ///     for url in &verification.missing {
+    ///         println!("{} is missing", url);
+    ///     }
+    /// }
+    /// ```
+    pub fn verify(&self, options: &ArchiveOptions) -> Verification {
+        crate::verify::verify(self, options)
+    }
+
+    /// Download the page and its resources again using the `archiver`'s
+    /// client and options, only downloading what has changed since. The
+    /// server is asked about each response which has
+    /// [`validators`](ResourceMeta::validators) using `If-None-Match` and
+    /// `If-Modified-Since` headers, and the rest are downloaded in full.
+    ///
+    /// Changed and new resources replace those in the archive, and those
+    /// which the page no longer needs are removed. Resources which can't
+    /// be downloaded again are kept as they were, but are still listed in
+    /// the [`failures`](#structfield.failures). If the page itself can't
+    /// be downloaded, the archive is left alone.
+    ///
+    /// The `archiver`'s own caches aren't used, so that every response is
+    /// checked.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::{ArchiveOptions, Archiver};
+    ///
+    /// # async fn archive_async() {
+    /// let archiver = Archiver::new(ArchiveOptions::default()).unwrap();
+    /// let mut archive = archiver.archive("http://example.com").await.unwrap();
+    /// // Later on...
+    /// let refresh = archive.refresh(&archiver).await.unwrap();
+    /// for url in &refresh.updated {
+    ///     println!("{} changed", url);
+    /// }
+    /// # }
+    /// ```
+    pub async fn refresh(
+        &mut self,
+        archiver: &Archiver<'_>,
+    ) -> Result<Refresh, Error> {
+        let refreshed = archiver.refresh(self).await?;
+        Ok(self.take_refreshed(refreshed))
+    }
+
+    /// Download the page and its resources again, blocking the thread,
+    /// see [`PageArchive::refresh`]
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking::Archiver;
+    ///
+    /// let archiver = Archiver::new(Default::default()).unwrap();
+    /// let mut archive = archiver.archive("http://example.com").unwrap();
+    /// // Later on...
+    /// let refresh = archive.refresh_blocking(&archiver).unwrap();
+    /// ```
+    #[cfg(feature = "blocking")]
+    pub fn refresh_blocking(
+        &mut self,
+        archiver: &crate::blocking::Archiver<'_>,
+    ) -> Result<Refresh, Error> {
+        let refreshed = archiver.refresh(self)?;
+        Ok(self.take_refreshed(refreshed))
+    }
+
+    /// Submit the page to the Internet Archive's
+    /// [Save Page Now](https://web.archive.org/save), waiting for the
+    /// Wayback Machine to capture it, and return where its snapshot is
+    /// kept. The snapshot is also recorded in the page's
+    /// [`meta`](ResourceMeta::snapshot), so that it's kept when the archive
+    /// is saved.
+    ///
+    /// If the options say so, each of the resources is submitted too, and
+    /// their snapshots recorded in the same way. A resource which can't be
+    /// captured is left without one, while [`Error::SavePageNow`] is
+    /// returned if the page can't be.
+    ///
+    /// The `archiver`'s client is used for the requests, with its proxy
+    /// and other settings, but not its caches.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::{ArchiveOptions, Archiver, SavePageNowOptions};
+    ///
+    /// # async fn archive_async() {
+    /// let archiver = Archiver::new(ArchiveOptions::default()).unwrap();
+    /// let mut archive = archiver.archive("http://example.com").await.unwrap();
+    /// let options = SavePageNowOptions::default().keys("access", "secret");
+    /// let snapshot = archive.save_page_now(&archiver, &options).await.unwrap();
+    /// println!("Also saved to {}", snapshot);
+    /// archive.save(&"example.webarchive").unwrap();
+    /// # }
+    /// ```
+    pub async fn save_page_now(
+        &mut self,
+        archiver: &Archiver<'_>,
+        options: &SavePageNowOptions<'_>,
+    ) -> Result<Url, Error> {
+        crate::save_page_now::save(self, archiver.client(), options).await
+    }
+
+    /// Submit the page to the Internet Archive's Save Page Now, blocking
+    /// the thread, see [`PageArchive::save_page_now`]
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking::Archiver;
+    /// use web_archive::SavePageNowOptions;
+    ///
+    /// let archiver = Archiver::new(Default::default()).unwrap();
+    /// let mut archive = archiver.archive("http://example.com").unwrap();
+    /// let options = SavePageNowOptions::default().resources(true);
+    /// let snapshot =
+    ///     archive.save_page_now_blocking(&archiver, &options).unwrap();
+    /// ```
+    #[cfg(feature = "blocking")]
+    pub fn save_page_now_blocking(
+        &mut self,
+        archiver: &crate::blocking::Archiver<'_>,
+        options: &SavePageNowOptions<'_>,
+    ) -> Result<Url, Error> {
+        crate::save_page_now::save_blocking(self, archiver.client(), options)
+    }
+
+    /// The archived responses which have validators, for asking whether
+    /// each has changed since. They're never fresh, so the server is
+    /// always asked.
+    pub(crate) fn responses(&self) -> MemoryCache {
+        let responses = MemoryCache::with_max_size(usize::MAX);
+        for (url, meta) in &self.meta {
+            if meta.validators.is_empty() {
+                continue;
+            }
+            let (meta, data) = if url == &self.url {
+                // The page has already been decoded
+                let meta = ResourceMeta {
+                    content_type: Some("text/html;charset=utf-8".to_string()),
+                    ..meta.clone()
+                };
+                (meta, Bytes::from(self.content.clone()))
+            } else {
+                match self.resource_map.get(url) {
+                    Some(resource) => (meta.clone(), resource.data().clone()),
+                    None => continue,
+                }
+            };
+            let response = CachedResponse {
+                redirects: self.redirects.get(url).cloned().unwrap_or_default(),
+                meta,
+                data,
+                fresh_until: None,
+            };
+            responses.put(url, response);
+        }
+        responses
+    }
+
+    /// Replace the archive with a `refreshed` copy, keeping any resources
+    /// which couldn't be downloaded again, and report what changed
+    fn take_refreshed(&mut self, mut refreshed: PageArchive) -> Refresh {
+        for failure in &refreshed.failures {
+            let url = &failure.url;
+            let resource = match self.resource_map.get(url) {
+                Some(resource) if !refreshed.resource_map.contains_key(url) => {
+                    resource
+                }
+                _ => continue,
+            };
+            // It's stored under the URL it was redirected to as well
+            let mut urls = vec![url.clone()];
+            urls.extend(
+                self.redirects.get(url).and_then(|r| r.last()).cloned(),
+            );
+            for url in urls {
+                if let Some(meta) = self.meta.get(&url) {
+                    refreshed.meta.insert(url.clone(), meta.clone());
+                }
+                refreshed.resource_map.insert(url, resource.clone());
+            }
+            if let Some(redirects) = self.redirects.get(url) {
+                refreshed.redirects.insert(url.clone(), redirects.clone());
+            }
+        }
+        let mut updated: Vec<Url> = refreshed
+            .resource_map
+            .iter()
+            .filter(|(url, resource)| {
+                self.resource_map.get(url) != Some(resource)
+            })
+            .map(|(url, _)| url.clone())
+            .collect();
+        let mut removed: Vec<Url> = self
+            .resource_map
+            .keys()
+            .filter(|url| !refreshed.resource_map.contains_key(url))
+            .cloned()
+            .collect();
+        updated.sort();
+        removed.sort();
+        let page_changed =
+            refreshed.url != self.url || refreshed.content != self.content;
+        *self = refreshed;
+        Refresh {
+            page_changed,
+            updated,
+            removed,
+        }
+    }
+
+    /// Embeds the resources into the page using the default
+    /// [`EmbedOptions`]. See [`PageArchive::embed_resources_with`] for
+    /// details.
+    pub fn embed_resources(&self) -> String {
+        self.embed_resources_with(&Default::default())
+    }
+
+    /// Searches `img`, `input`, `link`, and `script` tags in the page body
+    /// and substitutes in the downloaded content.
+    ///
+    /// * Images (including `<input type="image">` buttons) are base-64
+    ///   encoded and inserted as `data:` URIs
+    /// * Lazy-loaded images have their archived URLs promoted to `src`
+    ///   or `srcset` and the lazy-loading attributes are removed
+    /// * Images referenced by `url()`s within inline `style` attributes
+    ///   are replaced with `data:` URIs
+    /// * Images, fonts, and imported stylesheets referenced from inline
+    ///   `<style>` tags are replaced with `data:` URIs
+    /// * Images referenced from inline SVG `<image>` elements are also
+    ///   inserted as `data:` URIs, while elements referenced by `<use>`
+    ///   are copied into a hidden `<svg>` within the page
+    /// * `<object>` and `<embed>` payloads are likewise inserted as
+    ///   `data:` URIs
+    /// * Audio and video, along with their sources, text tracks, and
+    ///   posters, are inserted as `data:` URIs, as are the images offered
+    ///   by `<picture>` sources
+    /// * Frames are inserted as `data:` URIs of the archived frame
+    ///   documents, which have their own resources embedded in turn
     /// * Stylesheets are inserted as inline `<style>` tags, replacing
-    ///   the `<link>` tags they originated from
+    ///   the `<link>` tags they originated from. The images, fonts, and
+    ///   imported stylesheets they reference are inserted as `data:`
+    ///   URIs, and anything else is pointed at its absolute URL, since
+    ///   it would otherwise be resolved against the page
     /// * Scripts are inserted into their originating `<script>` tags
     ///   and the original `src` attribute is deleted.
-    pub fn embed_resources(&self) -> String {
-        // Parse DOM again, and substitute in the downloaded resources
+    /// * The static imports of ES modules are replaced with `data:` URIs
+    ///   of the archived modules
+    /// * `sourceMappingURL` comments in the embedded scripts and
+    ///   stylesheets are pointed at `data:` URIs of their archived source
+    ///   maps, or removed if the maps weren't archived
+    /// * Any `integrity` or `crossorigin` attributes are removed from
+    ///   elements whose resources have been embedded
+    /// * Preload hints are removed, except for preloaded stylesheets
+    ///   which are inserted as `data:` URIs
+    ///
+    /// Resources are also embedded into the contents of `<template>` tags
+    /// and the fallback contents of `<noscript>` tags. Resource hints are removed if
+    /// [`EmbedOptions::strip_resource_hints`] is set, and ads and trackers
+    /// if [`EmbedOptions::filter_list`] is. Where the page was archived
+    /// from is recorded in it if [`EmbedOptions::provenance`] is set.
+    /// See [`EmbedOptions`] for leaving scripts, images, or large
+    /// resources out of the page.
+    ///
+    /// Relative URLs are resolved against the page's `<base>` element if
+    /// it has one, and the `<base>` is then removed from the output.
+    ///
+    /// The output is always UTF-8, so any `<meta charset>` or
+    /// `<meta http-equiv="Content-Type">` declarations are updated to
+    /// match.
+    pub fn embed_resources_with(&self, options: &EmbedOptions) -> String {
+        let skipped;
+        let archive = if options.skips_resources() {
+            skipped = self.without_skipped(options);
+            &skipped
+        } else {
+            self
+        };
+        archive.embed_page(
+            &self.url,
+            &self.content,
+            options,
+            &mut vec![self.url.clone()],
+        )
+    }
+
+    /// A copy of the archive without the resources which the options say
+    /// not to embed, so that they're treated as though they weren't
+    /// archived
+    fn without_skipped(&self, options: &EmbedOptions) -> PageArchive {
+        PageArchive {
+            url: self.url.clone(),
+            content: self.content.clone(),
+            resource_map: self
+                .resource_map
+                .iter()
+                .filter(|(_, resource)| options.embeds(resource))
+                .map(|(url, resource)| (url.clone(), resource.clone()))
+                .collect(),
+            lazy_attributes: self.lazy_attributes.clone(),
+            timed_out: self.timed_out.clone(),
+            failures: self.failures.clone(),
+            cancelled: self.cancelled,
+            redirects: self.redirects.clone(),
+            meta: self.meta.clone(),
+            stats: self.stats.clone(),
+            url_attributes: self.url_attributes.clone(),
+            screenshot: self.screenshot.clone(),
+        }
+    }
 
-        let document = parse_html().one(self.content.as_str());
+    /// Embed the resources into the page, or a frame within it, whose URL
+    /// is `url`. `frames` holds the URLs of the pages currently being
+    /// embedded, so that a frame which contains itself doesn't go on
+    /// forever.
+    fn embed_page(
+        &self,
+        url: &Url,
+        content: &str,
+        options: &EmbedOptions,
+        frames: &mut Vec<Url>,
+    ) -> String {
+        // Parse DOM again, and substitute in the downloaded resources. The
+        // DOM from finding the resources can't be reused, since it isn't
+        // `Send` and so can't be kept in the archive.
 
-        // Replace images
-        for element in document.select("img").unwrap() {
+        let document = parse_html().one(content);
+        let base = base_url(url, &document);
+        self.embed_into(&document, &base, options, frames);
+        set_utf8_charset(&document);
+        // Only the page itself records where it came from, not its frames
+        if let (Some(provenance), [_]) = (options.provenance, &frames[..]) {
+            crate::provenance::insert(&document, self, provenance);
+        }
+
+        dom::serialize(&document)
+    }
+
+    /// Resolve the URL of a `<frame>` against `base` and return the
+    /// archived frame document, with its own resources embedded, as a
+    /// `data:` URI
+    pub(crate) fn frame_data_uri(
+        &self,
+        base: &Url,
+        u: &str,
+        options: &EmbedOptions,
+        frames: &mut Vec<Url>,
+    ) -> Option<String> {
+        let url = base.join(u).ok()?;
+        let frame = match self.resource_map.get(&url) {
+            Some(Resource::Document(frame)) => frame,
+            _ => return None,
+        };
+        if frames.contains(&url) {
+            return None;
+        }
+
+        frames.push(url.clone());
+        let html = self.embed_page(&url, &frame.text(), options, frames);
+        frames.pop();
+        Some(format!(
+            "data:text/html;charset=utf-8;base64,{}",
+            base64::encode(html)
+        ))
+    }
+
+    /// Embeds the resources into the page like
+    /// [`PageArchive::embed_resources_with`], but streams the page through
+    /// `lol_html` instead of parsing it into a DOM. Only the markup which
+    /// has to change is rewritten, so the rest of the page comes out
+    /// exactly as it went in, and large pages don't need a DOM held in
+    /// memory.
+    ///
+    /// Because the page is only read once, from start to finish, there
+    /// are a few differences in the output:
+    ///
+    /// * A `<base>` element only applies to the URLs which follow it
+    /// * Stylesheets replace their `<link>` tags in place rather than
+    ///   being moved to the end of the parent element
+    /// * Elements referenced by SVG `<use>` are copied into a hidden
+    ///   `<svg>` at the end of the body rather than the start
+    ///
+    /// Requires the `streaming` feature.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// let page = archive
+    ///     .embed_resources_streaming(&Default::default())
+    ///     .unwrap();
+    /// println!("{}", page);
+    /// ```
+    #[cfg(feature = "streaming")]
+    pub fn embed_resources_streaming(
+        &self,
+        options: &EmbedOptions,
+    ) -> Result<String, Error> {
+        if options.skips_resources() {
+            let archive = self.without_skipped(options);
+            return Ok(crate::streaming::embed(&archive, options)?);
+        }
+        Ok(crate::streaming::embed(self, options)?)
+    }
+
+    /// Substitute the downloaded resources into a parsed document,
+    /// resolving URLs against `base`
+    fn embed_into(
+        &self,
+        document: &NodeRef,
+        base: &Url,
+        options: &EmbedOptions,
+        frames: &mut Vec<Url>,
+    ) {
+        dom::rewrite_nested(document, |document| {
+            self.embed_document(document, base, options, frames)
+        });
+    }
+
+    /// Substitute the downloaded resources into a single document, not
+    /// including any nested `<template>` or `<noscript>` documents
+    fn embed_document(
+        &self,
+        document: &NodeRef,
+        base: &Url,
+        options: &EmbedOptions,
+        frames: &mut Vec<Url>,
+    ) {
+        if let Some(list) = options.filter_list {
+            remove_filtered(document, base, list);
+        }
+
+        // Replace images, including `<input type="image">` buttons
+        for element in document.select("img, input").unwrap() {
             let node = element.as_node();
             if let NodeData::Element(data) = node.data() {
                 // node is an 'element'
                 let mut attr = data.attributes.borrow_mut();
+                if &*data.name.local == "input"
+                    && !is_image_input(attr.get("type"))
+                {
+                    // Only image buttons have an image source
+                    continue;
+                }
+                let mut embedded = false;
                 if let Some(u) = attr.get_mut("src") {
                     // has a src attribute
-                    if let Ok(url) = self.url.join(u) {
+                    if let Ok(url) = base.join(u) {
                         // The url parses correctly
                         if let Some(Resource::Image(image_data)) =
                             self.resource_map.get(&url)
                         {
                             // We have a stored copy of this resource
                             *u = image_data.to_data_uri();
+                            embedded = true;
+                        }
+                    }
+                }
+                if embedded {
+                    remove_subresource_attributes(&mut attr);
+                }
+            }
+        }
+
+        // Promote lazy-loaded images. The lazy-loading attributes are
+        // removed, otherwise the loader script would swap the network URLs
+        // back in.
+        for element in document.select("img").unwrap() {
+            let node = element.as_node();
+            if let NodeData::Element(data) = node.data() {
+                // node is an 'element'
+                let mut attr = data.attributes.borrow_mut();
+                for name in &self.lazy_attributes {
+                    let promoted = attr.get(name.as_str()).and_then(|value| {
+                        self.promote_lazy_attribute(base, name, value)
+                    });
+                    if let Some((target, value)) = promoted {
+                        attr.insert(target, value);
+                        attr.remove(name.as_str());
+                        remove_subresource_attributes(&mut attr);
+                    }
+                }
+            }
+        }
+
+        // Replace images referenced from inline `style` attributes
+        for element in document.select("[style]").unwrap() {
+            let node = element.as_node();
+            if let NodeData::Element(data) = node.data() {
+                // node is an 'element'
+                let mut attr = data.attributes.borrow_mut();
+                if let Some(style) = attr.get_mut("style") {
+                    *style = self.embed_css_urls(
+                        base,
+                        style,
+                        &mut Vec::new(),
+                        |u| options.original_url(base, u),
+                    );
+                }
+            }
+        }
+
+        // Replace URLs in the extra attributes from the archive options
+        if !self.url_attributes.is_empty() {
+            for element in document.descendants().elements() {
+                let mut attr = element.attributes.borrow_mut();
+                for (tag, name) in &self.url_attributes {
+                    if !matches_tag(tag, &element.name.local) {
+                        continue;
+                    }
+                    if let Some(u) = attr.get_mut(name.as_str()) {
+                        if let Some(data_uri) = self.data_uri(base, u) {
+                            *u = data_uri;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Replace embedded objects
+        for (tag, attr_name) in [("object", "data"), ("embed", "src")].iter() {
+            for element in document.select(tag).unwrap() {
+                let node = element.as_node();
+                if let NodeData::Element(data) = node.data() {
+                    // node is an 'element'
+                    let mut attr = data.attributes.borrow_mut();
+                    if let Some(u) = attr.get_mut(*attr_name) {
+                        // has a data/src attribute
+                        if let Ok(url) = base.join(u) {
+                            // The url parses correctly
+                            if let Some(Resource::Object(object_data)) =
+                                self.resource_map.get(&url)
+                            {
+                                // We have a stored copy of this resource
+                                *u = object_data.to_data_uri();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Replace audio, video, and the sources and text tracks within
+        // them, along with the images offered by `<picture>` sources
+        for element in document.select("video, audio, source, track").unwrap() {
+            let mut attr = element.attributes.borrow_mut();
+            let mut embedded = false;
+            for name in MEDIA_ATTRIBUTES {
+                let data_uri = attr
+                    .get(*name)
+                    .and_then(|value| self.media_attribute(base, name, value));
+                if let Some(data_uri) = data_uri {
+                    attr.insert(*name, data_uri);
+                    embedded = true;
+                }
+            }
+            if embedded {
+                remove_subresource_attributes(&mut attr);
+            }
+        }
+
+        // Replace links to attachments. The `download` attribute stops
+        // browsers from trying to navigate to the `data:` URI.
+        for element in document.select("a, area").unwrap() {
+            let mut attr = element.attributes.borrow_mut();
+            let attachment = attr
+                .get("href")
+                .and_then(|u| base.join(u).ok())
+                .and_then(|url| match self.resource_map.get(&url) {
+                    Some(Resource::Attachment(attachment)) => {
+                        Some((attachment.to_data_uri(), url))
+                    }
+                    _ => None,
+                });
+            if let Some((data_uri, url)) = attachment {
+                attr.insert("href", data_uri);
+                if !attr.contains("download") {
+                    attr.insert("download", file_name(&url).to_string());
+                }
+            }
+        }
+
+        // Replace frames with their archived documents
+        for element in document.select("frame").unwrap() {
+            let mut attr = element.attributes.borrow_mut();
+            if let Some(u) = attr.get_mut("src") {
+                if let Some(data_uri) =
+                    self.frame_data_uri(base, u, options, frames)
+                {
+                    *u = data_uri;
+                }
+            }
+        }
+
+        // Replace SVG subresources. `<image>` can take a `data:` URI like
+        // `<img>`, but `<use>` can't, so the element that it references is
+        // copied out of the downloaded file and into the page instead.
+        let mut sprites: HashMap<Url, NodeRef> = HashMap::new();
+        let mut inlined_ids = HashSet::new();
+        let mut inlined_nodes = Vec::new();
+        for element in document.select("image, use").unwrap() {
+            let node = element.as_node();
+            if let NodeData::Element(data) = node.data() {
+                if data.name.ns != ns!(svg) {
+                    continue;
+                }
+                let mut attr = data.attributes.borrow_mut();
+                let href_name = match svg_href_name(&attr) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let href = &mut attr.map.get_mut(&href_name).unwrap().value;
+                if href.starts_with('#') {
+                    // Already refers to something within the page
+                    continue;
+                }
+                let mut url = match base.join(href) {
+                    Ok(url) => url,
+                    Err(_) => continue,
+                };
+                let fragment = url.fragment().map(str::to_string);
+                url.set_fragment(None);
+                let image_data = match self.resource_map.get(&url) {
+                    Some(Resource::Image(image_data)) => image_data,
+                    _ => continue,
+                };
+
+                if &*data.name.local == "image" {
+                    *href = image_data.to_data_uri();
+                    remove_subresource_attributes(&mut attr);
+                } else if let Some(id) = fragment {
+                    if !inlined_ids.contains(&id) {
+                        let sprite = sprites.entry(url).or_insert_with(|| {
+                            parse_html().one(
+                                String::from_utf8_lossy(&image_data.data)
+                                    .as_ref(),
+                            )
+                        });
+                        let target =
+                            sprite.descendants().elements().find(|e| {
+                                e.attributes.borrow().get("id")
+                                    == Some(id.as_str())
+                            });
+                        match target {
+                            Some(target) => {
+                                inlined_nodes.push(target.as_node().clone());
+                                inlined_ids.insert(id.clone());
+                            }
+                            // Nothing to point at, so leave it alone
+                            None => continue,
                         }
                     }
+                    *href = format!("#{}", id);
+                }
+            }
+        }
+        if !inlined_nodes.is_empty() {
+            // Keep the copied elements in a hidden `<svg>` at the start
+            // of the body so that they don't affect the page layout
+            let container = NodeRef::new_element(
+                QualName::new(None, ns!(svg), local_name!("svg")),
+                Some((
+                    ExpandedName::new(ns!(), local_name!("style")),
+                    Attribute {
+                        prefix: None,
+                        value: "display: none".to_string(),
+                    },
+                )),
+            );
+            for node in inlined_nodes {
+                container.append(node);
+            }
+            match document.select_first("body") {
+                Ok(body) => body.as_node().prepend(container),
+                Err(()) => document.append(container),
+            }
+        }
+
+        // Replace images, fonts, and imported stylesheets referenced from
+        // inline `<style>` tags. This has to happen before the external
+        // stylesheets are inlined because their URLs are relative to the
+        // stylesheet rather than the page.
+        for element in document.select("style").unwrap() {
+            let node = element.as_node();
+            let css = node.text_contents();
+            let rewritten =
+                self.embed_css_urls(base, &css, &mut Vec::new(), |u| {
+                    options.original_url(base, u)
+                });
+            if rewritten != css {
+                for child in node.children() {
+                    child.detach();
                 }
+                node.append(NodeRef::new_text(rewritten));
             }
         }
 
-        // Replace CSS
-        for element in document.select("link").unwrap() {
+        // Replace CSS. The `<link>`s are collected first, since detaching
+        // them part way through the selection would cut it short.
+        let links: Vec<_> = document.select("link").unwrap().collect();
+        for element in links {
             let node = element.as_node();
 
             // Create a place to store the css data reference so that
             // the horribly nested borrows can be dropped before we
             // replace the `<link>` element with a `<style>`.
-            let mut css_data: Option<&String> = None;
+            let mut css_data: Option<String> = None;
 
             if let NodeData::Element(data) = node.data() {
                 // node is an 'element'
                 let attr = data.attributes.borrow();
-                if Some("stylesheet") == attr.get("rel") {
+                if has_rel(attr.get("rel"), "stylesheet") {
                     // rel="stylesheet"
                     if let Some(u) = attr.get("href") {
                         // href="style.css"
-                        if let Ok(u) = self.url.join(u) {
+                        if let Ok(u) = base.join(u) {
                             // href parses properly
                             if let Some(Resource::Css(css)) =
                                 self.resource_map.get(&u)
                             {
                                 // we have a stored copy of the CSS
-                                css_data = Some(css);
+                                css_data = Some(self.embed_stylesheet(
+                                    &u,
+                                    &css.text(),
+                                    &mut Vec::new(),
+                                ));
                             }
                         }
                     }
@@ -114,51 +1247,755 @@ impl PageArchive {
             }
         }
 
-        // Replace scripts
+        // Preload hints are pointless once their targets are embedded, and
+        // would otherwise make requests to the network. The exception is
+        // an archived stylesheet, which is rewritten to a `data:` URI in
+        // case something (e.g. a loadCSS `onload` handler) turns the hint
+        // into a real stylesheet. Hints which already point at a `data:`
+        // URI are left alone, as are stylesheets which are also preloaded
+        // but weren't archived.
+        let preloads: Vec<_> = document
+            .select("link")
+            .unwrap()
+            .filter(|element| {
+                let attr = element.attributes.borrow();
+                !has_rel(attr.get("rel"), "stylesheet")
+                    && preload_kind(attr.get("rel"), attr.get("as")).is_some()
+                    && attr
+                        .get("href")
+                        .and_then(|u| resolve_url(base, u))
+                        .is_some()
+            })
+            .collect();
+        for element in preloads {
+            let mut attr = element.attributes.borrow_mut();
+            let css = attr
+                .get("href")
+                .and_then(|u| base.join(u).ok())
+                .and_then(|u| match self.resource_map.get(&u) {
+                    Some(Resource::Css(css)) => Some(self.embed_stylesheet(
+                        &u,
+                        &css.text(),
+                        &mut Vec::new(),
+                    )),
+                    _ => None,
+                });
+            match css {
+                Some(css) => {
+                    attr.insert(
+                        "href",
+                        format!(
+                            "data:text/css;charset=utf-8;base64,{}",
+                            base64::encode(css.as_bytes())
+                        ),
+                    );
+                    remove_subresource_attributes(&mut attr);
+                }
+                None => element.as_node().detach(),
+            }
+        }
+
+        if options.strip_resource_hints {
+            let hints: Vec<_> = document
+                .select("link")
+                .unwrap()
+                .filter(|element| {
+                    let attr = element.attributes.borrow();
+                    ["preconnect", "dns-prefetch", "prefetch", "prerender"]
+                        .iter()
+                        .any(|hint| has_rel(attr.get("rel"), hint))
+                })
+                .collect();
+            for element in hints {
+                element.as_node().detach();
+            }
+        }
+
+        // Replace scripts. The imports of ES modules are rewritten to
+        // `data:` URIs of the imported modules.
+        let mut modules = HashMap::new();
         for element in document.select("script").unwrap() {
             let node = element.as_node();
             if let NodeData::Element(data) = node.data() {
                 // node is an 'element'
                 let mut attr = data.attributes.borrow_mut();
+                let is_module = is_module_script(attr.get("type"));
+                let mut embedded = false;
                 if let Some(u) = attr.get_mut("src") {
                     // has a src attribute
-                    if let Ok(url) = self.url.join(u) {
+                    if let Ok(url) = base.join(u) {
                         // The url parses correctly
                         if let Some(Resource::Javascript(script_text)) =
                             self.resource_map.get(&url)
                         {
                             // We have a stored copy of this resource
+                            let script_text = self
+                                .embed_source_map(&url, &script_text.text());
+                            let script_text = if is_module {
+                                self.embed_module_imports(
+                                    &url,
+                                    &script_text,
+                                    &mut Vec::new(),
+                                    &mut modules,
+                                )
+                            } else {
+                                script_text
+                            };
                             node.append(NodeRef::new_text(script_text));
+                            embedded = true;
                         }
                     }
+                } else if is_module {
+                    // Inline module, whose imports are relative to the page
+                    let script_text = node.text_contents();
+                    let rewritten = self.embed_module_imports(
+                        base,
+                        &script_text,
+                        &mut Vec::new(),
+                        &mut modules,
+                    );
+                    if rewritten != script_text {
+                        for child in node.children() {
+                            child.detach();
+                        }
+                        node.append(NodeRef::new_text(rewritten));
+                    }
+                }
+                // Remove the original 'src' attribute - by default it
+                // doesn't matter whether we managed to archive it or not
+                // because external resources won't be reachable from the
+                // archived page. `data:` URIs are self-contained, so they
+                // stay.
+                if options.strips_script_src(embedded)
+                    && attr
+                        .get("src")
+                        .and_then(|u| resolve_url(base, u))
+                        .is_some()
+                {
+                    let _ = attr.remove("src");
+                }
+                if embedded {
+                    remove_subresource_attributes(&mut attr);
+                }
+            }
+        }
+
+        // Anything left pointing at a relative URL wasn't embedded
+        if options.keep_original_urls {
+            for element in document.descendants().elements() {
+                let mut attr = element.attributes.borrow_mut();
+                for name in ORIGINAL_URL_ATTRIBUTES {
+                    let original = attr.get(*name).and_then(|value| {
+                        options.original_attribute(base, name, value)
+                    });
+                    if let Some(original) = original {
+                        attr.insert(*name, original);
+                    }
                 }
-                // Remove the original 'src' attribute - doesn't matter
-                // whether we managed to archive it or not because
-                // external resources won't be reachable from the archived
-                // page
-                let _ = attr.remove("src");
             }
         }
 
-        document.to_string()
+        // Everything has been resolved against the `<base>` already, and
+        // leaving it in would point anything left over at the network
+        let base_elements: Vec<_> = document.select("base").unwrap().collect();
+        for element in base_elements {
+            element.as_node().detach();
+        }
     }
 
-    /// NOT YET IMPLEMENTED
+    /// Rewrite the imports of a module to `data:` URIs of the archived
+    /// modules, whose own imports are rewritten in turn. A `data:` URI
+    /// can't contain itself, so imports which would form a cycle, along
+    /// with any that weren't archived, are rewritten to their absolute
+    /// URLs instead; relative specifiers don't work from within a
+    /// `data:` URI anyway.
     ///
-    /// Write the downloaded resources to disk in the directory specified
-    pub fn write_to_disk<P: AsRef<Path>>(
+    /// `stack` holds the modules currently being rewritten, and `cache`
+    /// the `data:` URIs of modules which have already been rewritten.
+    pub(crate) fn embed_module_imports(
         &self,
-        _output_dir: &P,
-    ) -> Result<(), io::Error> {
-        todo!()
+        base: &Url,
+        script: &str,
+        stack: &mut Vec<Url>,
+        cache: &mut HashMap<Url, String>,
+    ) -> String {
+        javascript::rewrite_imports(script, |specifier| {
+            let url = javascript::resolve_specifier(base, specifier)?;
+            if let Some(data_uri) = cache.get(&url) {
+                return Some(data_uri.clone());
+            }
+            match self.resource_map.get(&url) {
+                Some(Resource::Javascript(module)) if !stack.contains(&url) => {
+                    stack.push(url.clone());
+                    let module = self.embed_module_imports(
+                        &url,
+                        &self.embed_source_map(&url, &module.text()),
+                        stack,
+                        cache,
+                    );
+                    stack.pop();
+                    let data_uri = format!(
+                        "data:text/javascript;base64,{}",
+                        base64::encode(module)
+                    );
+                    cache.insert(url, data_uri.clone());
+                    Some(data_uri)
+                }
+                _ => Some(url.to_string()),
+            }
+        })
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::*;
-    use bytes::Bytes;
+    /// Rewrite the `url()`s and `@import`s in some CSS to `data:` URIs of
+    /// the archived images, fonts, and stylesheets, resolving them against
+    /// `base`. Imported stylesheets have their own resources embedded in
+    /// turn, except for any which would import themselves, since a
+    /// `data:` URI can't contain itself. References which weren't
+    /// archived are replaced with whatever `fallback` gives for them.
+    ///
+    /// `stack` holds the stylesheets currently being embedded.
+    pub(crate) fn embed_css_urls<F>(
+        &self,
+        base: &Url,
+        css: &str,
+        stack: &mut Vec<Url>,
+        mut fallback: F,
+    ) -> String
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        css::rewrite_urls(css, |u| {
+            let url = match base.join(u) {
+                Ok(url) => url,
+                Err(_) => return fallback(u),
+            };
+            match self.resource_map.get(&url) {
+                Some(Resource::Image(data)) | Some(Resource::Font(data)) => {
+                    Some(data.to_data_uri())
+                }
+                Some(Resource::Css(imported)) if !stack.contains(&url) => {
+                    let imported =
+                        self.embed_stylesheet(&url, &imported.text(), stack);
+                    Some(format!(
+                        "data:text/css;charset=utf-8;base64,{}",
+                        base64::encode(imported)
+                    ))
+                }
+                _ => fallback(u),
+            }
+        })
+    }
+
+    /// Embed the resources of a stylesheet downloaded from `url`, along
+    /// with its source map. Anything which wasn't archived is pointed at
+    /// its absolute URL, since relative URLs would otherwise be resolved
+    /// against the page once the stylesheet is inlined into it.
+    pub(crate) fn embed_stylesheet(
+        &self,
+        url: &Url,
+        css: &str,
+        stack: &mut Vec<Url>,
+    ) -> String {
+        stack.push(url.clone());
+        let css = self.embed_source_map(url, css);
+        let css = self.embed_css_urls(url, &css, stack, |u| {
+            url.join(u).ok().map(String::from)
+        });
+        stack.pop();
+        css
+    }
+
+    /// Point the `sourceMappingURL` comment of a script or stylesheet
+    /// downloaded from `url` at a `data:` URI of its archived source map.
+    /// If the map wasn't archived then the comment is removed, since the
+    /// browser would only fail to fetch it.
+    pub(crate) fn embed_source_map(&self, url: &Url, text: &str) -> String {
+        source_map::rewrite(text, |u| match resolve_url(url, u) {
+            Some(map) => self.resource_map.get(&map).map(Resource::to_data_uri),
+            // Already a `data:` URI
+            None => Some(u.to_string()),
+        })
+    }
+
+    /// Work out which attribute a lazy-loading attribute should be
+    /// promoted to (`src` or `srcset`) along with its embedded value.
+    /// Returns `None` unless every image it refers to has been archived.
+    pub(crate) fn promote_lazy_attribute(
+        &self,
+        base: &Url,
+        name: &str,
+        value: &str,
+    ) -> Option<(&'static str, String)> {
+        if !is_srcset_attribute(name) {
+            return Some(("src", self.image_data_uri(base, value)?));
+        }
+
+        let candidates = parse_srcset(value);
+        let srcset = candidates
+            .iter()
+            .map(|c| {
+                let data_uri = self.image_data_uri(base, c.url)?;
+                Some(
+                    format!("{} {}", data_uri, c.descriptor)
+                        .trim_end()
+                        .to_string(),
+                )
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(("srcset", srcset.join(", ")))
+    }
+
+    /// The embedded value of an attribute of a `<video>`, `<audio>`,
+    /// `<source>`, or `<track>`: the archived media for `src`, the poster
+    /// image for `poster`, or the images of a `<picture>` source's
+    /// `srcset`. Returns `None` unless everything it refers to has been
+    /// archived.
+    pub(crate) fn media_attribute(
+        &self,
+        base: &Url,
+        name: &str,
+        value: &str,
+    ) -> Option<String> {
+        match name {
+            "src" => match self.resource_map.get(&base.join(value).ok()?) {
+                Some(Resource::Media(media)) => Some(media.to_data_uri()),
+                _ => None,
+            },
+            "poster" => self.image_data_uri(base, value),
+            "srcset" => self
+                .promote_lazy_attribute(base, name, value)
+                .map(|(_, srcset)| srcset),
+            _ => None,
+        }
+    }
+
+    /// Resolve a URL from the page against `base` and return the archived
+    /// resource as a `data:` URI, whatever kind of resource it is
+    pub(crate) fn data_uri(&self, base: &Url, u: &str) -> Option<String> {
+        self.resource_map
+            .get(&base.join(u).ok()?)
+            .map(Resource::to_data_uri)
+    }
+
+    /// Resolve a URL from the page against `base` and return the archived
+    /// image as a `data:` URI, if there is one. Fonts are included since
+    /// stylesheets reference them in the same way.
+    pub(crate) fn image_data_uri(&self, base: &Url, u: &str) -> Option<String> {
+        match self.resource_map.get(&base.join(u).ok()?) {
+            Some(Resource::Image(image_data))
+            | Some(Resource::Font(image_data)) => {
+                Some(image_data.to_data_uri())
+            }
+            _ => None,
+        }
+    }
+
+    /// Write the page and its resources out to the directory specified,
+    /// creating it if need be, so that the page can be opened from disk.
+    ///
+    /// The page is written to `index.html`, and the resources under
+    /// `resources/`, mirroring the hosts and paths they were downloaded
+    /// from, e.g. `resources/example.com/css/style.css`. URLs in the page,
+    /// stylesheets, module scripts, and frames which point at archived
+    /// resources are rewritten to relative paths, and the rest to their
+    /// absolute URLs so that they can still be loaded from the network.
+    ///
+    /// File names are made safe for Windows, macOS, and Linux alike, and
+    /// can't escape the directory however hostile the URLs. URLs with a
+    /// query string get a hash of it added to their file name, files are
+    /// given the extension of their mimetype if they don't have it
+    /// already, and resources whose paths would clash are given hashed
+    /// names instead.
+    ///
+    /// Each file is written to a temporary file and renamed into place,
+    /// and the page is written last, so that if writing is interrupted
+    /// there's no `index.html` until everything it needs is there. See
+    /// [`WriteOptions::sync`] for making sure that the files are on disk.
+    ///
+    /// A `manifest.json` describing the archive is written alongside, so
+    /// that it can be read back in with [`PageArchive::read_from_disk`].
+    /// It lists the page and every resource with the path it was written
+    /// to, and the resources' original URLs and mimetypes, along with the
+    /// size and SHA-256 hash of each file and when it was fetched, so
+    /// that other tools can index the archive without parsing the HTML.
+    /// The [`screenshot`](#structfield.screenshot), if there is one, is
+    /// written to `screenshot.png`. Last of all, `SHA256SUMS` lists the
+    /// SHA-256 digest of every other file in the format of `sha256sum`, so
+    /// that changes to the files can be found with `sha256sum -c
+    /// SHA256SUMS`.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// archive.write_to_disk(&"example").unwrap();
+    /// ```
+    pub fn write_to_disk<P: AsRef<Path>>(
+        &self,
+        output_dir: &P,
+    ) -> Result<(), io::Error> {
+        self.write_to_disk_with(output_dir, &WriteOptions::default())
+    }
+
+    /// Write the page and its resources out to the directory specified,
+    /// like [`PageArchive::write_to_disk`], with [`WriteOptions`].
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::{blocking, WriteOptions};
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// let options = WriteOptions { sync: true };
+    /// archive.write_to_disk_with(&"example", &options).unwrap();
+    /// ```
+    pub fn write_to_disk_with<P: AsRef<Path>>(
+        &self,
+        output_dir: &P,
+        options: &WriteOptions,
+    ) -> Result<(), io::Error> {
+        crate::disk::write(self, output_dir.as_ref(), options.sync)
+    }
+
+    /// Package the files written by [`PageArchive::write_to_disk`], the
+    /// page, its resources, the manifest, and the checksums, into a single
+    /// ZIP file, for storing or sending on. The files sit at the top level
+    /// of the ZIP file, so that extracting it gives the same directory
+    /// that `write_to_disk` would, which [`PageArchive::read_from_disk`]
+    /// can read back in.
+    ///
+    /// Related feature: `features = ["bundle"]`
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// archive.write_zip(&"example.zip").unwrap();
+    /// ```
+    #[cfg(feature = "bundle")]
+    pub fn write_zip<P: AsRef<Path>>(&self, path: &P) -> Result<(), io::Error> {
+        crate::disk::write_atomically(path.as_ref(), true, |writer| {
+            crate::bundle::write_zip(self, writer)
+        })
+    }
+
+    /// Package the files written by [`PageArchive::write_to_disk`] into a
+    /// single gzipped tar file, like [`PageArchive::write_zip`].
+    ///
+    /// Related feature: `features = ["bundle"]`
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// archive.write_tar_gz(&"example.tar.gz").unwrap();
+    /// ```
+    #[cfg(feature = "bundle")]
+    pub fn write_tar_gz<P: AsRef<Path>>(
+        &self,
+        path: &P,
+    ) -> Result<(), io::Error> {
+        crate::disk::write_atomically(path.as_ref(), true, |writer| {
+            crate::bundle::write_tar_gz(self, writer)
+        })
+    }
+
+    /// Package the page and its resources as a WACZ file, which can be
+    /// shared and then replayed in the browser with
+    /// [ReplayWeb.page](https://replayweb.page) and other players.
+    ///
+    /// The package holds a WARC file with a response record for the page
+    /// and each resource, with their redirects, a CDXJ index of the
+    /// records, the list of pages, and a `datapackage.json` listing the
+    /// files with their SHA-256 hashes. The page is stored as it is in
+    /// [`content`](#structfield.content), encoded as UTF-8. The
+    /// [`screenshot`](#structfield.screenshot), if there is one, is
+    /// stored in a resource record for `urn:screenshot:` followed by the
+    /// page's URL.
+    ///
+    /// Related feature: `features = ["wacz"]`
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// archive.write_wacz(&"example.wacz").unwrap();
+    /// ```
+    #[cfg(feature = "wacz")]
+    pub fn write_wacz<P: AsRef<Path>>(
+        &self,
+        path: &P,
+    ) -> Result<(), io::Error> {
+        crate::disk::write_atomically(path.as_ref(), true, |writer| {
+            crate::wacz::write(self, writer)
+        })
+    }
+
+    /// Render the page with its resources embedded to a PDF, as a record
+    /// of it with a fixed layout, e.g. for compliance purposes. It's
+    /// rendered in Chrome or Chromium, which has to be installed, and laid
+    /// out on paper as the [`PdfOptions`](crate::PdfOptions) say. The
+    /// page's print styles apply.
+    ///
+    /// Fails with [`Error::Browser`] if the browser can't be started or
+    /// the page can't be rendered in it.
+    ///
+    /// Related feature: `features = ["headless"]`
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::{PaperSize, PdfOptions};
+    ///
+    /// # async fn to_pdf_async() {
+    /// let archive = web_archive::archive("http://example.com", Default::default())
+    ///     .await
+    ///     .unwrap();
+    /// let options = PdfOptions::default().paper(PaperSize::A4);
+    /// let pdf = archive.to_pdf(&options).await.unwrap();
+    /// std::fs::write("example.pdf", pdf).unwrap();
+    /// # }
+    /// ```
+    #[cfg(feature = "headless")]
+    pub async fn to_pdf(
+        &self,
+        options: &crate::PdfOptions<'_>,
+    ) -> Result<Vec<u8>, Error> {
+        crate::pdf::to_pdf(self, options).await
+    }
+
+    /// Read an archive back in from a directory written by
+    /// [`PageArchive::write_to_disk`], e.g. to embed its resources after
+    /// editing the files.
+    ///
+    /// `write_to_disk` also writes a `manifest.json` describing the
+    /// archive, which gives the original URL of each file, and the
+    /// relative paths in the page, stylesheets, module scripts, and frames
+    /// are pointed back at those URLs. The redirects, response metadata,
+    /// and the resources which timed out are read back from the manifest
+    /// too, but the [`failures`](#structfield.failures) and
+    /// [`stats`](#structfield.stats) are left empty. Pages and text
+    /// resources are read back as UTF-8, since that's how they were
+    /// written, so their [`sha256`](ResourceMeta::sha256) digests are
+    /// worked out again from what's read.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the manifest can't be
+    /// understood.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::PageArchive;
+    ///
+    /// let archive = PageArchive::read_from_disk(&"example").unwrap();
+    /// std::fs::write("example.html", archive.embed_resources()).unwrap();
+    /// ```
+    pub fn read_from_disk<P: AsRef<Path>>(
+        input_dir: &P,
+    ) -> Result<PageArchive, io::Error> {
+        crate::disk::read(input_dir.as_ref())
+    }
+
+    /// Save the archive to a file in the crate's own compact format, which
+    /// keeps everything in it, including the resources' original data
+    /// and charsets, the [`failures`](#structfield.failures), the
+    /// [`stats`](#structfield.stats), and the
+    /// [`screenshot`](#structfield.screenshot), so that it can be loaded
+    /// back exactly as it was with [`PageArchive::load`].
+    ///
+    /// The file is versioned, and files saved by this version of the crate
+    /// can be loaded by every later version. Files saved by later versions
+    /// can be loaded by this one too, leaving out anything it doesn't know
+    /// about, unless they had to change the format incompatibly.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// archive.save(&"example.webarchive").unwrap();
+    /// ```
+    pub fn save<P: AsRef<Path>>(&self, path: &P) -> Result<(), io::Error> {
+        crate::disk::write_atomically(path.as_ref(), true, |writer| {
+            crate::native::save(self, writer)
+        })
+    }
+
+    /// Load an archive saved with [`PageArchive::save`].
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the file wasn't saved
+    /// by `save`, is damaged, or was saved by a later version of the
+    /// crate which had to change the format incompatibly.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::PageArchive;
+    ///
+    /// let archive = PageArchive::load(&"example.webarchive").unwrap();
+    /// println!("{}", archive.embed_resources());
+    /// ```
+    pub fn load<P: AsRef<Path>>(path: &P) -> Result<PageArchive, io::Error> {
+        let file = std::fs::File::open(path)?;
+        crate::native::load(io::BufReader::new(file))
+    }
+
+    /// Convert the page to Markdown with the default [`MarkdownOptions`],
+    /// for note-taking apps and knowledge bases. See
+    /// [`PageArchive::to_markdown_with`] for details.
+    pub fn to_markdown(&self) -> String {
+        self.to_markdown_with(&Default::default())
+    }
+
+    /// Convert the page to Markdown, for note-taking apps and knowledge
+    /// bases, with [`MarkdownOptions`] choosing where its images point.
+    ///
+    /// Headings, paragraphs, lists, quotes, code, rules, and tables are
+    /// kept, along with emphasis and line breaks, and the rest of the
+    /// markup is dropped. Links point at their absolute URLs, apart from
+    /// links within the page, and scripts, styles, and form controls are
+    /// left out. Tables come out as GitHub Flavored Markdown tables, and
+    /// lazy-loaded images use their real URLs.
+    ///
+    /// With [`MarkdownImages::Files`](crate::MarkdownImages::Files), images and links to archived
+    /// resources point at the files written by
+    /// [`PageArchive::write_to_disk`], so that the Markdown can be saved
+    /// next to its `index.html`.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::{blocking, MarkdownImages, MarkdownOptions};
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// let options = MarkdownOptions {
+    ///     images: MarkdownImages::DataUri,
+    /// };
+    /// std::fs::write("example.md", archive.to_markdown_with(&options))
+    ///     .unwrap();
+    /// ```
+    pub fn to_markdown_with(&self, options: &MarkdownOptions) -> String {
+        crate::markdown::convert(self, options)
+    }
+
+    /// Extract the main article from the page, in the manner of
+    /// Readability, for read-later apps and the like. See [`Article`].
+    ///
+    /// The article is the part of the page holding the most paragraphs of
+    /// text, once scripts, forms, navigation, and anything whose classes
+    /// mark it as comments, sidebars, or ads have been removed. It's put
+    /// in a plain page of its own under its title and byline, with its
+    /// styles removed, and the archive of that page only keeps the
+    /// resources which the article uses, so that embedding them gives a
+    /// much smaller and cleaner page than the original.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// let article = archive.reader_mode();
+    /// println!("{:?}", article.title);
+    /// println!("{}", article.archive.embed_resources());
+    /// ```
+    pub fn reader_mode(&self) -> Article {
+        crate::reader::extract(self)
+    }
+
+    /// The visible text of the page, for indexing and language
+    /// processing.
+    ///
+    /// Scripts, styles, and hidden elements are left out, and whitespace
+    /// is collapsed as a browser would, apart from within `<pre>`
+    /// elements. Paragraphs and other blocks are separated by blank
+    /// lines, and list items, table rows, and line breaks start new
+    /// lines, with tabs between the cells of a table.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use web_archive::blocking;
+    ///
+    /// let archive =
+    ///     blocking::archive("http://example.com", Default::default())
+    ///         .unwrap();
+    /// println!("{}", archive.text());
+    /// ```
+    pub fn text(&self) -> String {
+        crate::text::text(self)
+    }
+}
+
+/// Point the page's charset declarations at UTF-8, since the page was
+/// decoded from whatever its original encoding was
+pub(crate) fn set_utf8_charset(document: &NodeRef) {
+    for meta in document.select("meta").unwrap() {
+        let mut attr = meta.attributes.borrow_mut();
+        if attr.contains("charset") {
+            attr.insert("charset", "utf-8".to_string());
+        }
+        let is_content_type = attr
+            .get("http-equiv")
+            .map(|h| h.trim().eq_ignore_ascii_case("content-type"))
+            .unwrap_or(false);
+        if is_content_type {
+            attr.insert("content", "text/html; charset=utf-8".to_string());
+        }
+    }
+}
+
+/// Remove the elements hidden by a filter list, and those loading
+/// resources which it blocks. Selectors which can't be parsed are skipped.
+fn remove_filtered(document: &NodeRef, base: &Url, list: &FilterList) {
+    let selectors: Vec<Selectors> = list
+        .hidden_selectors(base)
+        .filter_map(|selector| Selectors::compile(selector).ok())
+        .collect();
+    let filtered: Vec<_> = document
+        .descendants()
+        .elements()
+        .filter(|element| {
+            let attr = element.attributes.borrow();
+            selectors.iter().any(|s| s.matches(element))
+                || list.blocks_element(
+                    &element.name.local,
+                    |name| attr.get(name).map(str::to_string),
+                    base,
+                )
+        })
+        .collect();
+    for element in filtered {
+        element.as_node().detach();
+    }
+}
+
+/// Remove the attributes which only apply to fetching a subresource from
+/// the network. Once the resource has been embedded, a leftover
+/// `integrity` hash can cause browsers to refuse the inline content.
+pub(crate) fn remove_subresource_attributes(attr: &mut Attributes) {
+    attr.remove("integrity");
+    attr.remove("crossorigin");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use bytes::Bytes;
 
     #[test]
     fn test_single_css() {
@@ -175,17 +2012,29 @@ mod test {
         let mut resource_map = ResourceMap::new();
         resource_map.insert(
             url.join("style.css").unwrap(),
-            Resource::Css(
-                r#"
+            Resource::Css(TextResource {
+                data: Bytes::from(
+                    r#"
 					body { background-color: blue; }
-				"#
-                .to_string(),
-            ),
+				"#,
+                ),
+                mimetype: "text/css".to_string(),
+                charset: None,
+            }),
         );
         let archive = PageArchive {
             url,
             content,
             resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
         };
 
         let output = archive.embed_resources();
@@ -236,6 +2085,15 @@ mod test {
             url,
             content,
             resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
         };
 
         let output = archive.embed_resources();
@@ -249,52 +2107,1732 @@ mod test {
     }
 
     #[test]
-    fn test_single_js() {
+    fn test_base_element() {
         let content = r#"
 		<html>
 			<head>
-				<script src="script.js"></script>
+				<base href="https://cdn.example.com/app/" />
 			</head>
-			<body></body>
+			<body>
+				<img src="logo.png" />
+			</body>
 		</html>
 		"#
         .to_string();
         let url = Url::parse("http://example.com").unwrap();
         let mut resource_map = ResourceMap::new();
         resource_map.insert(
-            url.join("script.js").unwrap(),
-            Resource::Javascript(
-                r#"
-					function do_stuff() {
-						console.log("Hello!");
-					}
-				"#
-                .to_string(),
-            ),
+            Url::parse("https://cdn.example.com/app/logo.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
         );
         let archive = PageArchive {
             url,
             content,
             resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
         };
 
         let output = archive.embed_resources();
-        assert_eq!(
-            output.replace("\t", "").replace("\n", ""),
-            r#"
-		<html><head>
-				<script>
-					function do_stuff() {
-						console.log("Hello!");
-					}
-				</script>
+        assert!(output
+            .contains(r#"<img src="data:image/png;base64,iVBORw0KGgo=">"#));
+        assert!(!output.contains("<base"));
+    }
+
+    #[test]
+    fn test_lazy_loaded_images() {
+        let content = r#"
+		<html>
+			<head></head>
+			<body>
+				<img class="lazyload" src="placeholder.gif" data-src="real.png" />
+				<img data-srcset="real.png 1x, real.png 2x" />
+				<img data-src="missing.png" />
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("real.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: vec![
+                "data-src".to_string(),
+                "data-srcset".to_string(),
+            ],
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(concat!(
+            r#"<img class="lazyload" "#,
+            r#"src="data:image/png;base64,iVBORw0KGgo=">"#
+        )));
+        assert!(output.contains(concat!(
+            r#"<img srcset="data:image/png;base64,iVBORw0KGgo= 1x, "#,
+            r#"data:image/png;base64,iVBORw0KGgo= 2x">"#
+        )));
+        assert!(output.contains(r#"<img data-src="missing.png">"#));
+    }
+
+    #[test]
+    fn test_image_input() {
+        let content = r#"
+		<html>
+			<head></head>
+			<body>
+				<form>
+					<input type="image" src="submit.png" />
+					<input type="text" src="submit.png" />
+				</form>
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("submit.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(
+            r#"<input src="data:image/png;base64,iVBORw0KGgo=" type="image">"#
+        ));
+        assert!(output.contains(r#"<input src="submit.png" type="text">"#));
+    }
+
+    #[test]
+    fn test_style_attribute() {
+        let content = r#"
+		<html>
+			<head></head>
+			<body>
+				<div style="background: url('hero.png') no-repeat, url(other.png)">
+				</div>
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("hero.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(concat!(
+            r#"<div style="background: "#,
+            r#"url(&quot;data:image/png;base64,iVBORw0KGgo=&quot;) "#,
+            r#"no-repeat, url(other.png)">"#
+        )));
+    }
+
+    #[test]
+    fn test_style_tag() {
+        let content = r#"
+		<html>
+			<head>
+				<style>
+					body { background: url(bg.png); }
+				</style>
+				<link rel="stylesheet" href="css/style.css" />
 			</head>
 			<body></body>
 		</html>
 		"#
-            .to_string()
-            .replace("\t", "")
-            .replace("\n", "")
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("bg.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
         );
+        resource_map.insert(
+            url.join("css/style.css").unwrap(),
+            Resource::Css(TextResource {
+                data: Bytes::from("p { background: url(bg.png); }"),
+                mimetype: "text/css".to_string(),
+                charset: None,
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(concat!(
+            r#"body { background: "#,
+            r#"url("data:image/png;base64,iVBORw0KGgo="); }"#
+        )));
+        // The external stylesheet's URLs are relative to css/style.css,
+        // so must not be resolved against the page URL
+        assert!(output.contains(
+            r#"p { background: url("http://example.com/css/bg.png"); }"#
+        ));
+    }
+
+    #[test]
+    fn test_stylesheet_resources() {
+        let content = r#"
+		<html>
+			<head><link rel="stylesheet" href="css/style.css" /></head>
+			<body></body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("css/style.css").unwrap(),
+            Resource::Css(TextResource {
+                data: Bytes::from(concat!(
+                    r#"@import "print.css"; "#,
+                    "p { background: url(../bg.png); } ",
+                    "@font-face { src: url(missing.woff2); }"
+                )),
+                mimetype: "text/css".to_string(),
+                charset: None,
+            }),
+        );
+        resource_map.insert(
+            url.join("css/print.css").unwrap(),
+            Resource::Css(TextResource {
+                data: Bytes::from("a { background: url(bg.png); }"),
+                mimetype: "text/css".to_string(),
+                charset: None,
+            }),
+        );
+        resource_map.insert(
+            url.join("bg.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        // The imported stylesheet's own URLs are relative to it in turn
+        let print = base64::encode(
+            r#"a { background: url("http://example.com/css/bg.png"); }"#,
+        );
+        assert!(output.contains(&format!(
+            r#"@import url("data:text/css;charset=utf-8;base64,{}");"#,
+            print
+        )));
+        assert!(output.contains(
+            r#"p { background: url("data:image/png;base64,iVBORw0KGgo="); }"#
+        ));
+        assert!(output.contains(
+            r#"@font-face { src: url("http://example.com/css/missing.woff2"); }"#
+        ));
+    }
+
+    #[test]
+    fn test_style_imports_and_fonts() {
+        let content = r#"
+		<html>
+			<head>
+				<style>
+					@import "css/theme.css";
+					@font-face { src: url(fonts/sans.woff2); }
+				</style>
+			</head>
+			<body></body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("css/theme.css").unwrap(),
+            Resource::Css(TextResource {
+                data: Bytes::from("@import 'theme.css'; p { color: red; }"),
+                mimetype: "text/css".to_string(),
+                charset: None,
+            }),
+        );
+        resource_map.insert(
+            url.join("fonts/sans.woff2").unwrap(),
+            Resource::Font(ImageResource {
+                data: Bytes::from_static(b"wOF2"),
+                mimetype: "font/woff2".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        // The imported stylesheet imports itself, which is left pointing
+        // at the network rather than going round forever
+        let theme = base64::encode(
+            r#"@import url("http://example.com/css/theme.css"); p { color: red; }"#,
+        );
+        assert!(output.contains(&format!(
+            r#"@import url("data:text/css;charset=utf-8;base64,{}");"#,
+            theme
+        )));
+        assert!(output.contains(
+            r#"@font-face { src: url("data:font/woff2;base64,d09GMg=="); }"#
+        ));
+    }
+
+    #[test]
+    fn test_svg_subresources() {
+        let content = r##"
+		<html>
+			<head></head>
+			<body>
+				<svg>
+					<image href="photo.png" />
+					<use href="sprite.svg#home" />
+					<use href="sprite.svg#home" />
+					<use href="sprite.svg#missing" />
+				</svg>
+			</body>
+		</html>
+		"##
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("photo.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        resource_map.insert(
+            url.join("sprite.svg").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(
+                    br#"<svg xmlns="http://www.w3.org/2000/svg">
+                        <symbol id="home"><path d="M0 0h1v1z"></path></symbol>
+                        <symbol id="other"></symbol>
+                    </svg>"#,
+                ),
+                mimetype: "image/svg+xml".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output
+            .contains(r#"<image href="data:image/png;base64,iVBORw0KGgo=">"#));
+        assert!(output.contains(concat!(
+            r#"<body><svg style="display: none">"#,
+            r#"<symbol id="home"><path d="M0 0h1v1z"></path></symbol>"#,
+            "</svg>"
+        )));
+        assert_eq!(output.matches(r##"<use href="#home">"##).count(), 2);
+        assert!(output.contains(r#"<use href="sprite.svg#missing">"#));
+        assert!(!output.contains("other"));
+    }
+
+    #[test]
+    fn test_media() {
+        let content = r#"
+		<html>
+			<head></head>
+			<body>
+				<video src="clip.mp4" poster="poster.png" crossorigin>
+					<track src="captions.vtt" kind="captions">
+				</video>
+				<audio><source src="missing.ogg"></audio>
+				<picture><source srcset="poster.png 2x"></picture>
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("clip.mp4").unwrap(),
+            Resource::Media(ImageResource {
+                data: Bytes::from_static(b"mp4"),
+                mimetype: "video/mp4".to_string(),
+            }),
+        );
+        resource_map.insert(
+            url.join("captions.vtt").unwrap(),
+            Resource::Media(ImageResource {
+                data: Bytes::from_static(b"WEBVTT"),
+                mimetype: "text/vtt".to_string(),
+            }),
+        );
+        resource_map.insert(
+            url.join("poster.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(concat!(
+            r#"<video poster="data:image/png;base64,iVBORw0KGgo=" "#,
+            r#"src="data:video/mp4;base64,bXA0">"#
+        )));
+        assert!(output.contains(
+            r#"<track kind="captions" src="data:text/vtt;base64,V0VCVlRU">"#
+        ));
+        assert!(output.contains(r#"<source src="missing.ogg">"#));
+        assert!(output.contains(
+            r#"<source srcset="data:image/png;base64,iVBORw0KGgo= 2x">"#
+        ));
+    }
+
+    #[test]
+    fn test_object_and_embed() {
+        let content = r#"
+		<html>
+			<head></head>
+			<body>
+				<object data="report.pdf" type="application/pdf"></object>
+				<embed src="report.pdf" type="application/pdf" />
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("report.pdf").unwrap(),
+            Resource::Object(ImageResource {
+                data: Bytes::from_static(b"%PDF-1.4"),
+                mimetype: "application/pdf".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        // base64("%PDF-1.4") == "JVBERi0xLjQ="
+        assert!(output.contains(
+            r#"<object data="data:application/pdf;base64,JVBERi0xLjQ=""#
+        ));
+        assert!(output.contains(
+            r#"<embed src="data:application/pdf;base64,JVBERi0xLjQ=""#
+        ));
+    }
+
+    #[test]
+    fn test_preload_links() {
+        let content = r#"
+		<html>
+			<head>
+				<link rel="preload" as="style" href="style.css" onload="this.rel='stylesheet'" />
+				<link rel="preload" as="script" href="script.js" />
+				<link rel="modulepreload" href="module.js" />
+				<link rel="preload" as="style" href="missing.css" />
+			</head>
+			<body></body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("style.css").unwrap(),
+            Resource::Css(TextResource {
+                data: Bytes::from("p {}"),
+                mimetype: "text/css".to_string(),
+                charset: None,
+            }),
+        );
+        resource_map.insert(
+            url.join("script.js").unwrap(),
+            Resource::Javascript(TextResource {
+                data: Bytes::from(""),
+                mimetype: "text/javascript".to_string(),
+                charset: None,
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        // base64("p {}") == "cCB7fQ=="
+        assert!(output.contains(concat!(
+            r#"<link as="style" href="data:text/css;charset=utf-8;base64,cCB7fQ==" "#,
+            r#"onload="this.rel='stylesheet'" rel="preload">"#
+        )));
+        assert!(!output.contains("script.js"));
+        assert!(!output.contains("module.js"));
+        assert!(!output.contains("missing.css"));
+    }
+
+    #[test]
+    fn test_stylesheet_rel_values() {
+        let content = r#"
+		<html>
+			<head>
+				<link rel="Stylesheet" href="upper.css" />
+				<link rel="stylesheet preload" as="style" href="preloaded.css" />
+				<link rel="stylesheet preload" as="style" href="missing.css" />
+			</head>
+			<body></body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        for (name, css) in [("upper.css", "a {}"), ("preloaded.css", "b {}")] {
+            resource_map.insert(
+                url.join(name).unwrap(),
+                Resource::Css(TextResource {
+                    data: Bytes::from(css),
+                    mimetype: "text/css".to_string(),
+                    charset: None,
+                }),
+            );
+        }
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains("<style>a {}</style>"));
+        assert!(output.contains("<style>b {}</style>"));
+        assert!(!output.contains("upper.css"));
+        assert!(!output.contains("preloaded.css"));
+        // A stylesheet which wasn't archived is left as it is, rather
+        // than being stripped like a preload hint
+        assert!(output.contains(r#"href="missing.css""#));
+    }
+
+    #[test]
+    fn test_module_imports() {
+        let content = r#"
+		<html>
+			<head>
+				<script type="module" src="js/main.js"></script>
+				<script type="module">import "./js/cycle-a.js";</script>
+			</head>
+			<body></body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        for (path, script) in [
+            ("js/main.js", "import { a } from './a.js'; a();"),
+            ("js/a.js", "export function a() {}"),
+            ("js/cycle-a.js", "import './cycle-b.js';"),
+            ("js/cycle-b.js", "import './cycle-a.js';"),
+        ]
+        .iter()
+        {
+            resource_map.insert(
+                url.join(path).unwrap(),
+                Resource::Javascript(TextResource {
+                    data: Bytes::from(*script),
+                    mimetype: "text/javascript".to_string(),
+                    charset: None,
+                }),
+            );
+        }
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        let a = base64::encode("export function a() {}");
+        assert!(output.contains(&format!(
+            "import {{ a }} from \"data:text/javascript;base64,{}\"; a();",
+            a
+        )));
+
+        // The cycle is broken by pointing back at the network
+        let cycle_b = "import \"http://example.com/js/cycle-a.js\";";
+        let cycle_a = format!(
+            "import \"data:text/javascript;base64,{}\";",
+            base64::encode(cycle_b)
+        );
+        assert!(output.contains(&format!(
+            "<script type=\"module\">\
+             import \"data:text/javascript;base64,{}\";</script>",
+            base64::encode(cycle_a)
+        )));
+    }
+
+    #[test]
+    fn test_remove_subresource_attributes() {
+        let content = r#"
+		<html>
+			<head>
+				<script src="script.js" integrity="sha384-abc" crossorigin="anonymous"></script>
+				<script src="missing.js" integrity="sha384-def"></script>
+			</head>
+			<body>
+				<img src="logo.png" crossorigin />
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("script.js").unwrap(),
+            Resource::Javascript(TextResource {
+                data: Bytes::from("go();"),
+                mimetype: "text/javascript".to_string(),
+                charset: None,
+            }),
+        );
+        resource_map.insert(
+            url.join("logo.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains("<script>go();</script>"));
+        assert!(output.contains(r#"<script integrity="sha384-def"></script>"#));
+        assert!(output
+            .contains(r#"<img src="data:image/png;base64,iVBORw0KGgo=">"#));
+    }
+
+    #[test]
+    fn test_template_contents() {
+        let content = r#"
+		<html>
+			<head></head>
+			<body>
+				<my-element>
+					<template shadowrootmode="open"><img src="logo.png"></template>
+				</my-element>
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("logo.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(concat!(
+            r#"<template shadowrootmode="open">"#,
+            r#"<img src="data:image/png;base64,iVBORw0KGgo="></template>"#
+        )));
+    }
+
+    #[test]
+    fn test_noscript() {
+        let content = r#"
+		<html>
+			<head></head>
+			<body>
+				<img class="lazyload" data-src="logo.png" />
+				<noscript><img src="logo.png" alt="1 < 2"></noscript>
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("logo.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(concat!(
+            r#"<noscript><img alt="1 < 2" "#,
+            r#"src="data:image/png;base64,iVBORw0KGgo="></noscript>"#
+        )));
+    }
+
+    #[test]
+    fn test_strip_resource_hints() {
+        let content = r#"
+		<html>
+			<head>
+				<link rel="preconnect" href="https://fonts.example.com" />
+				<link rel="dns-prefetch" href="https://cdn.example.com" />
+				<link rel="prefetch" href="next-page.html" />
+				<link rel="icon" href="favicon.ico" />
+			</head>
+			<body></body>
+		</html>
+		"#
+        .to_string();
+        let archive = PageArchive {
+            url: Url::parse("http://example.com").unwrap(),
+            content,
+            resource_map: ResourceMap::new(),
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        // Hints are kept by default
+        let output = archive.embed_resources();
+        assert!(output.contains("preconnect"));
+        assert!(output.contains("dns-prefetch"));
+        assert!(output.contains("prefetch"));
+
+        let output = archive.embed_resources_with(&EmbedOptions {
+            strip_resource_hints: true,
+            ..Default::default()
+        });
+        assert!(!output.contains("preconnect"));
+        assert!(!output.contains("dns-prefetch"));
+        assert!(!output.contains("prefetch"));
+        assert!(output.contains(r#"<link href="favicon.ico" rel="icon">"#));
+    }
+
+    #[test]
+    fn test_filter_list() {
+        let content = r#"
+		<html>
+			<head>
+				<script src="https://ads.example.net/ad.js"></script>
+			</head>
+			<body>
+				<div class="ad-banner"><p>Buy now</p></div>
+				<div class="sponsored">Sponsored</div>
+				<img src="https://ads.example.net/pixel.gif">
+				<p>Article</p>
+				<template><div class="ad-banner"></div></template>
+			</body>
+		</html>
+		"#
+        .to_string();
+        let archive = PageArchive {
+            url: Url::parse("http://example.com").unwrap(),
+            content,
+            resource_map: ResourceMap::new(),
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+        let list = FilterList::parse(
+            "||ads.example.net^
+##.ad-banner
+example.org##.sponsored
+example.com##div:-abp-has(.ad)",
+        );
+
+        let output = archive.embed_resources_with(&EmbedOptions {
+            filter_list: Some(&list),
+            ..Default::default()
+        });
+        assert!(!output.contains("ads.example.net"));
+        assert!(!output.contains("ad-banner"));
+        assert!(output.contains("Sponsored"));
+        assert!(output.contains("Article"));
+    }
+
+    #[test]
+    fn test_embed_options() {
+        let content = r#"
+		<html>
+			<head>
+				<script src="app.js"></script>
+				<script src="missing.js"></script>
+			</head>
+			<body style="background: url(missing.png)">
+				<img src="logo.png" srcset="logo.png 1x, logo@2x.png 2x">
+				<a href="about.html">About</a>
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com/blog/").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("app.js").unwrap(),
+            Resource::Javascript(TextResource {
+                data: Bytes::from_static(b"console.log('app');"),
+                mimetype: "text/javascript".to_string(),
+                charset: None,
+            }),
+        );
+        resource_map.insert(
+            url.join("logo.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources_with(&EmbedOptions {
+            skip_scripts: true,
+            skip_images: true,
+            ..Default::default()
+        });
+        assert!(!output.contains("console.log"));
+        assert!(!output.contains(".js"));
+        assert!(output.contains(r#"<img src="logo.png""#));
+
+        let output = archive.embed_resources_with(&EmbedOptions {
+            max_data_uri_size: Some(8),
+            keep_original_urls: true,
+            keep_unarchived_scripts: true,
+            ..Default::default()
+        });
+        // Neither resource is small enough to embed
+        assert!(output.contains(
+            r#"srcset="http://example.com/blog/logo.png 1x, http://example.com/blog/logo@2x.png 2x""#
+        ));
+        assert!(output.contains(
+            r#"<script src="http://example.com/blog/app.js"></script>"#
+        ));
+        assert!(output.contains(
+            r#"<script src="http://example.com/blog/missing.js"></script>"#
+        ));
+        assert!(output
+            .contains("url(&quot;http://example.com/blog/missing.png&quot;)"));
+        assert!(output.contains(r#"href="http://example.com/blog/about.html""#));
+    }
+
+    #[test]
+    fn test_single_js() {
+        let content = r#"
+		<html>
+			<head>
+				<script src="script.js"></script>
+			</head>
+			<body></body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("script.js").unwrap(),
+            Resource::Javascript(TextResource {
+                data: Bytes::from(
+                    r#"
+					function do_stuff() {
+						console.log("Hello!");
+					}
+				"#,
+                ),
+                mimetype: "text/javascript".to_string(),
+                charset: None,
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert_eq!(
+            output.replace("\t", "").replace("\n", ""),
+            r#"
+		<html><head>
+				<script>
+					function do_stuff() {
+						console.log("Hello!");
+					}
+				</script>
+			</head>
+			<body></body>
+		</html>
+		"#
+            .to_string()
+            .replace("\t", "")
+            .replace("\n", "")
+        );
+    }
+
+    #[test]
+    fn test_meta_charset() {
+        let content = r#"
+		<html>
+			<head>
+				<meta charset="Shift_JIS" />
+				<meta http-equiv="Content-Type" content="text/html; charset=windows-1251" />
+				<meta name="viewport" content="width=device-width" />
+			</head>
+			<body>こんにちは</body>
+		</html>
+		"#
+        .to_string();
+        let archive = PageArchive {
+            url: Url::parse("http://example.com").unwrap(),
+            content,
+            resource_map: ResourceMap::new(),
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(r#"<meta charset="utf-8">"#));
+        assert!(output.contains(r#"content="text/html; charset=utf-8""#));
+        assert!(output.contains(r#"content="width=device-width""#));
+        assert!(output.contains("こんにちは"));
+    }
+
+    #[test]
+    fn test_css_charset() {
+        let content = r#"
+		<html>
+			<head>
+				<link rel="stylesheet" href="style.css" />
+			</head>
+			<body></body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("style.css").unwrap(),
+            Resource::Css(TextResource {
+                // `p::before { content: "Привет"; }` in windows-1251
+                data: Bytes::from_static(
+                    b"p::before { content: \"\xCF\xF0\xE8\xE2\xE5\xF2\"; }",
+                ),
+                mimetype: "text/css".to_string(),
+                charset: Some("windows-1251".to_string()),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output
+            .contains(r#"<style>p::before { content: "Привет"; }</style>"#));
+    }
+
+    #[test]
+    fn test_doctype_and_comments() {
+        let content = r#"<!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN" "http://www.w3.org/TR/html4/loose.dtd">
+<!-- Saved from example.com -->
+<html>
+	<head>
+		<!--[if lt IE 9]><script src="html5shiv.js"></script><![endif]-->
+	</head>
+	<body>
+		<noscript><!-- fallback --><img src="image.png"></noscript>
+	</body>
+</html>
+<!-- Generated in 0.1s -->"#
+            .to_string();
+        let archive = PageArchive {
+            url: Url::parse("http://example.com").unwrap(),
+            content,
+            resource_map: ResourceMap::new(),
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.starts_with(
+            r#"<!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN" "http://www.w3.org/TR/html4/loose.dtd"><!-- Saved from example.com --><html>"#
+        ));
+        assert!(output.contains(
+            r#"<!--[if lt IE 9]><script src="html5shiv.js"></script><![endif]-->"#
+        ));
+        assert!(output.contains(
+            r#"<noscript><!-- fallback --><img src="image.png"></noscript>"#
+        ));
+        assert!(output.ends_with("</html><!-- Generated in 0.1s -->"));
+
+        let archive = PageArchive {
+            content: r#"<!DOCTYPE html SYSTEM "about:legacy-compat"><p>"#
+                .to_string(),
+            ..archive
+        };
+        assert!(archive
+            .embed_resources()
+            .starts_with(r#"<!DOCTYPE html SYSTEM "about:legacy-compat">"#));
+    }
+
+    #[test]
+    fn test_document() {
+        let archive = PageArchive {
+            url: Url::parse("http://example.com").unwrap(),
+            content: "<title>Example</title><p>Hello</p>".to_string(),
+            resource_map: ResourceMap::new(),
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let document = archive.document();
+        let title = document.select_first("title").unwrap();
+        assert_eq!(title.text_contents(), "Example");
+
+        // Changes to the DOM don't affect the archive
+        title.as_node().detach();
+        assert!(archive.embed_resources().contains("<title>Example</title>"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let archive = PageArchive {
+            url: Url::parse("http://example.com/list?page=2").unwrap(),
+            content: "<title>Example</title><link rel=next href=?page=3>\
+                <a href=/about>About</a>"
+                .to_string(),
+            resource_map: ResourceMap::new(),
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let page = archive.parse();
+        assert_eq!(page.title(), archive.title());
+        assert_eq!(page.title().as_deref(), Some("Example"));
+        assert_eq!(
+            page.next_page_url().as_ref().map(Url::as_str),
+            Some("http://example.com/list?page=3")
+        );
+        assert_eq!(page.links(), archive.links());
+        assert_eq!(page.links()[0].url.as_str(), "http://example.com/about");
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let depth = 20_000;
+        let content = format!(
+            "<body>{}<noscript><img src=\"a.png\"></noscript>{}",
+            "<span>".repeat(depth),
+            "</span>".repeat(depth)
+        );
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("a.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"a"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(
+            "<noscript><img src=\"data:image/png;base64,YQ==\"></noscript>"
+        ));
+        assert_eq!(output.matches("</span>").count(), depth);
+    }
+
+    #[test]
+    fn test_data_uris() {
+        let content = r#"
+		<html>
+			<head>
+				<link rel="preload" as="image" href="data:image/png;base64,AA">
+				<script src="data:text/javascript,go()"></script>
+			</head>
+			<body>
+				<img src="data:image/png;base64,AA">
+			</body>
+		</html>
+		"#
+        .to_string();
+        let archive = PageArchive {
+            url: Url::parse("http://example.com").unwrap(),
+            content,
+            resource_map: ResourceMap::new(),
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(r#"href="data:image/png;base64,AA""#));
+        assert!(output.contains(r#"<script src="data:text/javascript,go()">"#));
+        assert!(output.contains(r#"<img src="data:image/png;base64,AA">"#));
+    }
+
+    #[test]
+    fn test_url_attributes() {
+        let content = r#"
+		<html>
+			<head></head>
+			<body>
+				<div data-bg="hero.png"></div>
+				<SECTION DATA-BG="hero.png"></SECTION>
+				<p data-bg="hero.png"></p>
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("hero.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"a"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: vec![
+                ("div".to_string(), "data-bg".to_string()),
+                ("section".to_string(), "data-bg".to_string()),
+            ],
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output
+            .contains(r#"<div data-bg="data:image/png;base64,YQ=="></div>"#));
+        assert!(output.contains(
+            r#"<section data-bg="data:image/png;base64,YQ=="></section>"#
+        ));
+        assert!(output.contains(r#"<p data-bg="hero.png"></p>"#));
+    }
+
+    #[test]
+    fn test_frames() {
+        let content = r#"
+		<html>
+			<frameset cols="50%,50%">
+				<frame src="frames/left.html">
+				<frame src="missing.html">
+			</frameset>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        let frames: &[(&str, &'static [u8])] = &[
+            (
+                "frames/left.html",
+                b"<frameset><frame src=\"left.html\">\
+                  <frame src=\"inner.html\"></frameset>",
+            ),
+            ("frames/inner.html", b"<img src=\"logo.png\">"),
+        ];
+        for (u, html) in frames {
+            resource_map.insert(
+                url.join(u).unwrap(),
+                Resource::Document(TextResource {
+                    data: Bytes::from_static(html),
+                    mimetype: "text/html".to_string(),
+                    charset: None,
+                }),
+            );
+        }
+        resource_map.insert(
+            url.join("frames/logo.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"a"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        fn embedded_frame(html: &str) -> String {
+            let prefix = "data:text/html;charset=utf-8;base64,";
+            let start = html.find(prefix).unwrap() + prefix.len();
+            let end = start + html[start..].find('"').unwrap();
+            String::from_utf8(base64::decode(&html[start..end]).unwrap())
+                .unwrap()
+        }
+
+        let output = archive.embed_resources();
+        assert!(output.contains(r#"<frame src="missing.html">"#));
+
+        // A frame which contains itself is left alone
+        let left = embedded_frame(&output);
+        assert!(left.contains(r#"<frame src="left.html">"#));
+
+        // Frames have their own resources embedded, relative to the frame
+        let inner = embedded_frame(&left);
+        assert!(inner.contains(r#"<img src="data:image/png;base64,YQ==">"#));
+
+        // Only the page itself records where it came from
+        let output = archive.embed_resources_with(&EmbedOptions {
+            provenance: Some(Provenance::banner()),
+            ..Default::default()
+        });
+        assert!(output.contains(r#"name="web-archive:url""#));
+        let left = embedded_frame(&output);
+        assert!(!left.contains("web-archive"));
+    }
+
+    #[test]
+    fn test_attachments() {
+        let content = r#"
+		<html>
+			<head></head>
+			<body>
+				<a href="files/report.pdf">Report</a>
+				<a href="files/report.pdf" download="q1.pdf">Report</a>
+				<a href="missing.pdf">Missing</a>
+			</body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("files/report.pdf").unwrap(),
+            Resource::Attachment(ImageResource {
+                data: Bytes::from_static(b"a"),
+                mimetype: "application/pdf".to_string(),
+            }),
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        assert!(output.contains(
+            r#"<a download="report.pdf" href="data:application/pdf;base64,YQ==">"#
+        ));
+        assert!(output.contains(
+            r#"<a download="q1.pdf" href="data:application/pdf;base64,YQ==">"#
+        ));
+        assert!(output.contains(r#"<a href="missing.pdf">"#));
+    }
+
+    #[test]
+    fn test_source_maps() {
+        let content = r#"
+		<html>
+			<head>
+				<link rel="stylesheet" href="style.css">
+				<script src="js/app.js"></script>
+			</head>
+			<body></body>
+		</html>
+		"#
+        .to_string();
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        resource_map.insert(
+            url.join("style.css").unwrap(),
+            Resource::Css(TextResource {
+                data: Bytes::from_static(
+                    b"p {}\n/*# sourceMappingURL=style.css.map */",
+                ),
+                mimetype: "text/css".to_string(),
+                charset: None,
+            }),
+        );
+        resource_map.insert(
+            url.join("js/app.js").unwrap(),
+            Resource::Javascript(TextResource {
+                data: Bytes::from_static(
+                    b"go();\n//# sourceMappingURL=app.js.map",
+                ),
+                mimetype: "text/javascript".to_string(),
+                charset: None,
+            }),
+        );
+        resource_map.insert(
+            url.join("js/app.js.map").unwrap(),
+            Resource::Other {
+                data: Bytes::from_static(b"{}"),
+                mimetype: "application/json".to_string(),
+            },
+        );
+        let archive = PageArchive {
+            url,
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        };
+
+        let output = archive.embed_resources();
+        // The archived map is embedded, and the missing one is removed
+        assert!(output.contains(
+            "go();\n//# sourceMappingURL=data:application/json;base64,e30="
+        ));
+        assert!(output.contains("<style>p {}\n</style>"));
+    }
+
+    fn css(data: &'static str) -> Resource {
+        Resource::Css(TextResource {
+            data: Bytes::from(data),
+            mimetype: "text/css".to_string(),
+            charset: Some("utf-8".to_string()),
+        })
+    }
+
+    fn meta(url: &Url) -> ResourceMeta {
+        ResourceMeta {
+            status: 200,
+            final_url: url.clone(),
+            fetched_at: std::time::SystemTime::UNIX_EPOCH,
+            content_type: Some("text/css".to_string()),
+            cache_control: None,
+            validators: crate::Validators {
+                etag: Some("\"abc\"".to_string()),
+                last_modified: None,
+            },
+            snapshot: None,
+            sha256: None,
+        }
+    }
+
+    fn refresh_archive(resources: &[(&Url, Resource)]) -> PageArchive {
+        let url = Url::parse("http://example.com/").unwrap();
+        PageArchive {
+            meta: resources
+                .iter()
+                .map(|(u, _)| *u)
+                .chain(Some(&url))
+                .map(|u| (u.clone(), meta(u)))
+                .collect(),
+            content: "<html></html>".to_string(),
+            resource_map: resources
+                .iter()
+                .map(|(u, r)| ((*u).clone(), r.clone()))
+                .collect(),
+            url,
+            lazy_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        }
+    }
+
+    #[test]
+    fn test_responses() {
+        let style = Url::parse("http://example.com/style.css").unwrap();
+        let mut archive = refresh_archive(&[(&style, css("p {}"))]);
+        let unchecked = Url::parse("http://example.com/unchecked.css").unwrap();
+        archive.resource_map.insert(unchecked.clone(), css("a {}"));
+        archive.meta.insert(
+            unchecked.clone(),
+            ResourceMeta {
+                validators: Default::default(),
+                ..meta(&unchecked)
+            },
+        );
+        let gone = Url::parse("http://example.com/gone.css").unwrap();
+        archive.meta.insert(gone.clone(), meta(&gone));
+
+        let responses = archive.responses();
+        assert_eq!(responses.len(), 2);
+        let page = responses.get(&archive.url).unwrap();
+        assert_eq!(&page.data[..], b"<html></html>");
+        assert_eq!(
+            page.meta.content_type.as_deref(),
+            Some("text/html;charset=utf-8")
+        );
+        assert!(!page.is_fresh());
+        let cached = responses.get(&style).unwrap();
+        assert_eq!(cached.meta, meta(&style));
+        assert_eq!(&cached.data[..], b"p {}");
+        assert!(responses.get(&unchecked).is_none());
+        assert!(responses.get(&gone).is_none());
+    }
+
+    #[test]
+    fn test_take_refreshed() {
+        let base = Url::parse("http://example.com/").unwrap();
+        let [same, changed, new, gone, failed, moved] =
+            ["same", "changed", "new", "gone", "failed", "moved"]
+                .map(|name| base.join(&format!("{}.css", name)).unwrap());
+        let mut archive = refresh_archive(&[
+            (&same, css("a {}")),
+            (&changed, css("b {}")),
+            (&gone, css("c {}")),
+            (&failed, css("d {}")),
+            (&moved, css("d {}")),
+        ]);
+        archive
+            .redirects
+            .insert(failed.clone(), vec![moved.clone()]);
+        let mut refreshed = refresh_archive(&[
+            (&same, css("a {}")),
+            (&changed, css("b { color: red }")),
+            (&new, css("e {}")),
+        ]);
+        refreshed.failures.push(ResourceFailure {
+            url: failed.clone(),
+            reason: crate::FailureReason::Status(503),
+            retries: 0,
+        });
+
+        let refresh = archive.take_refreshed(refreshed);
+        assert_eq!(
+            refresh,
+            Refresh {
+                page_changed: false,
+                updated: vec![changed.clone(), new],
+                removed: vec![gone],
+            }
+        );
+        assert_eq!(archive.resource_map.len(), 5);
+        assert_eq!(archive.resource_map[&changed], css("b { color: red }"));
+        assert_eq!(archive.resource_map[&failed], css("d {}"));
+        assert_eq!(archive.resource_map[&moved], css("d {}"));
+        assert_eq!(archive.meta[&moved], meta(&moved));
+        assert_eq!(archive.redirects[&failed], vec![moved]);
+        assert_eq!(archive.failures.len(), 1);
+
+        let mut refreshed = refresh_archive(&[]);
+        refreshed.content = "<html><p></p></html>".to_string();
+        assert!(archive.take_refreshed(refreshed).page_changed);
     }
 }
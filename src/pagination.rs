@@ -0,0 +1,447 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for archiving every page of an article or listing split across
+//! several, see [`archive_paginated`]
+
+use crate::archiver::Archiver;
+use crate::cancel::CancellationToken;
+use crate::error::Error;
+use crate::failure::FailureReason;
+use crate::page_archive::PageArchive;
+use crate::site::SiteArchive;
+use crate::ArchiveOptions;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fmt::Display;
+use url::Url;
+
+/// Configuration options for [`archive_paginated`], controlling how far
+/// the pages' `rel="next"` and `rel="prev"` links are followed
+pub struct PaginationOptions<'a> {
+    /// The options used to archive each page
+    ///
+    /// Default: [`ArchiveOptions::default`]
+    pub archive: ArchiveOptions<'a>,
+    /// The most pages to follow in each direction from the first one,
+    /// which stops a runaway chain, such as an endless calendar, from
+    /// being followed forever
+    ///
+    /// Default: `20`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::PaginationOptions;
+    /// let options = PaginationOptions {
+    ///     max_hops: 5,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_hops: usize,
+    /// Whether to follow `rel="prev"` links back from the first page as
+    /// well as `rel="next"` links on from it, for when the URL given is
+    /// part way through
+    ///
+    /// Default: `true`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::PaginationOptions;
+    /// let options = PaginationOptions {
+    ///     follow_prev: false,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub follow_prev: bool,
+}
+
+impl<'a> PaginationOptions<'a> {
+    /// Archive each page with the options, see
+    /// [`PaginationOptions::archive`](#structfield.archive)
+    pub fn archive(mut self, options: ArchiveOptions<'a>) -> Self {
+        self.archive = options;
+        self
+    }
+
+    /// Set the most pages to follow in each direction, see
+    /// [`PaginationOptions::max_hops`](#structfield.max_hops)
+    pub fn max_hops(mut self, n: usize) -> Self {
+        self.max_hops = n;
+        self
+    }
+
+    /// Set whether to follow `rel="prev"` links, see
+    /// [`PaginationOptions::follow_prev`](#structfield.follow_prev)
+    pub fn follow_prev(mut self, follow: bool) -> Self {
+        self.follow_prev = follow;
+        self
+    }
+}
+
+impl<'a> Default for PaginationOptions<'a> {
+    fn default() -> Self {
+        Self {
+            archive: ArchiveOptions::default(),
+            max_hops: 20,
+            follow_prev: true,
+        }
+    }
+}
+
+/// Archive the page at `url` along with the rest of the pages it's one
+/// of, such as an article split across several, into a [`SiteArchive`]
+/// with the pages in order from first to last.
+///
+/// The pages are found by following the `<link rel="next">` or
+/// `<a rel="next">` of each page in turn, see
+/// [`PageArchive::next_page_url`], and then the `rel="prev"` links back
+/// from the page at `url`. Only pages on the same host as it are
+/// followed, and a chain which loops back on itself is stopped.
+///
+/// An error is returned if the page at `url` can't be archived. Pages
+/// after it which can't be archived, including those with an
+/// unsuccessful status code, end their chain and are recorded in
+/// [`SiteArchive::failures`]. If the options'
+/// [`cancellation`](ArchiveOptions::cancellation) token is cancelled,
+/// the pages archived so far are returned.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{archive_paginated, PaginationOptions};
+///
+/// # async fn archive_paginated_async() {
+/// let options = PaginationOptions::default().max_hops(10);
+/// let site = archive_paginated("http://example.com/article?page=2", options)
+///     .await
+///     .unwrap();
+/// for url in site.urls() {
+///     println!("{}", url);
+/// }
+/// # }
+/// ```
+pub async fn archive_paginated<U>(
+    url: U,
+    options: PaginationOptions<'_>,
+) -> Result<SiteArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let mut pagination = Pagination::new(url, &options);
+    let archiver = Archiver::new(options.archive)?;
+    while let Some(url) = pagination.next_page() {
+        let archive = archiver.archive(url.clone()).await;
+        pagination.archived(url, archive)?;
+    }
+    Ok(pagination.finish())
+}
+
+/// Which way a page was reached from the first one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Start,
+    Next,
+    Prev,
+}
+
+/// The state of following a chain of pages, shared between the async
+/// and blocking APIs, which hand out the pages to archive one at a time
+pub(crate) struct Pagination {
+    max_hops: usize,
+    follow_prev: bool,
+    cancellation: Option<CancellationToken>,
+    /// The host of the first page once it's been archived, which the
+    /// chain is kept to
+    host: Option<String>,
+    /// The page to archive next, and which way it was reached
+    pending: Option<(Url, Direction)>,
+    /// Which way the page being archived was reached
+    direction: Direction,
+    /// The page before the first one, which is followed once the pages
+    /// after it run out
+    prev_of_start: Option<Url>,
+    /// How many pages have been followed in the current direction
+    hops: usize,
+    /// Pages which have been requested, without their fragments
+    seen: HashSet<Url>,
+    /// The pages before the first one, from the nearest back
+    before: Vec<PageArchive>,
+    /// The first page and those after it
+    after: Vec<PageArchive>,
+    cancelled: bool,
+    site: SiteArchive,
+}
+
+impl Pagination {
+    pub fn new(mut url: Url, options: &PaginationOptions) -> Self {
+        url.set_fragment(None);
+        Self {
+            max_hops: options.max_hops,
+            follow_prev: options.follow_prev,
+            cancellation: options.archive.cancellation.clone(),
+            host: None,
+            pending: Some((url.clone(), Direction::Start)),
+            direction: Direction::Start,
+            prev_of_start: None,
+            hops: 0,
+            seen: vec![url].into_iter().collect(),
+            before: Vec::new(),
+            after: Vec::new(),
+            cancelled: false,
+            site: SiteArchive::new(),
+        }
+    }
+
+    /// The next page to archive, or `None` once the chain has ended
+    pub fn next_page(&mut self) -> Option<Url> {
+        let cancelled = self
+            .cancellation
+            .as_ref()
+            .map_or(false, CancellationToken::is_cancelled);
+        if self.cancelled || cancelled {
+            return None;
+        }
+        let (url, direction) = self.pending.take()?;
+        self.direction = direction;
+        Some(url)
+    }
+
+    /// Record how archiving the page at `url` went, following its link
+    /// to the page after or before it. Only an error archiving the first
+    /// page is returned.
+    pub fn archived(
+        &mut self,
+        url: Url,
+        archive: Result<PageArchive, Error>,
+    ) -> Result<(), Error> {
+        let direction = self.direction;
+        let start = direction == Direction::Start;
+        let archive = match archive {
+            Ok(archive) => archive,
+            Err(e) if start => return Err(e),
+            Err(Error::Cancelled) => {
+                self.cancelled = true;
+                return Ok(());
+            }
+            Err(e) => {
+                self.site.failed(url, FailureReason::from_error(e));
+                self.follow(None, direction);
+                return Ok(());
+            }
+        };
+        if start {
+            let host = archive.url.host_str().unwrap_or("");
+            self.host = Some(host.to_ascii_lowercase());
+        }
+        self.cancelled |= archive.cancelled;
+        for url in archive.redirects.keys().chain(Some(&archive.url)) {
+            self.seen.insert(url.clone());
+        }
+
+        let meta = archive.meta.get(&archive.url);
+        let status = meta.map_or(200, |meta| meta.status);
+        if status >= 400 {
+            self.site.failed(url, FailureReason::Status(status));
+            self.follow(None, direction);
+            return Ok(());
+        }
+
+        let page = archive.parse();
+        match direction {
+            Direction::Start => {
+                if self.follow_prev {
+                    self.prev_of_start = page.prev_page_url();
+                }
+                self.follow(page.next_page_url(), Direction::Next);
+                self.after.push(archive);
+            }
+            Direction::Next => {
+                self.follow(page.next_page_url(), Direction::Next);
+                self.after.push(archive);
+            }
+            Direction::Prev => {
+                self.follow(page.prev_page_url(), Direction::Prev);
+                self.before.push(archive);
+            }
+        }
+        Ok(())
+    }
+
+    /// The pages archived so far, in order from first to last
+    pub fn finish(mut self) -> SiteArchive {
+        let pages = self.before.into_iter().rev().chain(self.after);
+        self.site.extend(pages);
+        self.site
+    }
+
+    /// Queue the page at `url` to be archived next, if it's on the same
+    /// host, hasn't been seen already and the hop limit hasn't been
+    /// reached. Once the pages after the first run out, the pages before
+    /// it are followed.
+    fn follow(&mut self, url: Option<Url>, direction: Direction) {
+        let url = url
+            .map(|mut url| {
+                url.set_fragment(None);
+                url
+            })
+            .filter(|url| {
+                let host = url.host_str().unwrap_or("").to_ascii_lowercase();
+                self.hops < self.max_hops
+                    && self.host.as_deref() == Some(host.as_str())
+                    && !self.seen.contains(url)
+            });
+        match url {
+            Some(url) => {
+                self.hops += 1;
+                self.seen.insert(url.clone());
+                self.pending = Some((url, direction));
+            }
+            None if direction == Direction::Next => {
+                self.hops = 0;
+                let prev = self.prev_of_start.take();
+                self.follow(prev, Direction::Prev);
+            }
+            None => self.pending = None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::meta::ResourceMeta;
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    fn page(url: &str, status: u16, content: &str) -> PageArchive {
+        let url = Url::parse(url).unwrap();
+        let meta = ResourceMeta {
+            status,
+            final_url: url.clone(),
+            fetched_at: SystemTime::now(),
+            content_type: Some("text/html".to_string()),
+            cache_control: None,
+            validators: Default::default(),
+            snapshot: None,
+            sha256: None,
+        };
+        PageArchive {
+            url: url.clone(),
+            content: content.to_string(),
+            resource_map: HashMap::new(),
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: vec![(url, meta)].into_iter().collect(),
+            stats: Default::default(),
+            screenshot: None,
+        }
+    }
+
+    /// Follow the chain of test pages from page 3, returning the URLs
+    /// requested and the site
+    fn paginate(options: PaginationOptions) -> (Vec<String>, SiteArchive) {
+        let pages = [
+            (
+                "http://example.com/a?page=1",
+                "<a rel=\"prev\" href=\"http://example.org/\">Elsewhere</a>",
+            ),
+            (
+                "http://example.com/a?page=2",
+                "<link rel=\"previous\" href=\"?page=1\">",
+            ),
+            (
+                "http://example.com/a?page=3",
+                "<link rel=\"prev\" href=\"?page=2\">\
+                <a rel=\"next\" href=\"?page=4#top\">Next</a>",
+            ),
+            (
+                "http://example.com/a?page=4",
+                "<link rel=\"next\" href=\"?page=5\">\
+                <a rel=\"prev\" href=\"?page=3\">Back</a>",
+            ),
+            (
+                "http://example.com/a?page=5",
+                "<link rel=\"next\" href=\"?page=3\">\
+                <link rel=\"next\" href=\"?page=6\">",
+            ),
+        ];
+        let url = Url::parse("http://example.com/a?page=3").unwrap();
+        let mut pagination = Pagination::new(url, &options);
+        let mut requested = Vec::new();
+        while let Some(url) = pagination.next_page() {
+            requested.push(url.to_string());
+            let archive = pages
+                .iter()
+                .find(|(page, _)| *page == url.as_str())
+                .map(|(url, content)| page(url, 200, content))
+                .ok_or_else(|| Error::ReqwestError("not found".to_string()));
+            pagination.archived(url, archive).unwrap();
+        }
+        (requested, pagination.finish())
+    }
+
+    #[test]
+    fn test_pagination() {
+        let (requested, site) = paginate(Default::default());
+        assert_eq!(
+            requested,
+            vec![
+                "http://example.com/a?page=3",
+                "http://example.com/a?page=4",
+                "http://example.com/a?page=5",
+                "http://example.com/a?page=2",
+                "http://example.com/a?page=1",
+            ]
+        );
+        let urls: Vec<_> =
+            site.urls().map(|url| url.query().unwrap()).collect();
+        assert_eq!(
+            urls,
+            vec!["page=1", "page=2", "page=3", "page=4", "page=5"]
+        );
+        assert!(site.failures().is_empty());
+
+        let options =
+            PaginationOptions::default().max_hops(1).follow_prev(false);
+        let (requested, _) = paginate(options);
+        assert_eq!(
+            requested,
+            vec!["http://example.com/a?page=3", "http://example.com/a?page=4"]
+        );
+    }
+
+    #[test]
+    fn test_first_page_fails() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let mut pagination = Pagination::new(url, &Default::default());
+        let url = pagination.next_page().unwrap();
+        let error = Error::ReqwestError("connection refused".to_string());
+        assert!(pagination.archived(url, Err(error)).is_err());
+
+        let url = Url::parse("http://example.com/a?page=1").unwrap();
+        let mut pagination = Pagination::new(url, &Default::default());
+        let url = pagination.next_page().unwrap();
+        let archive =
+            page(url.as_str(), 200, "<link rel=\"next\" href=\"?page=2\">");
+        pagination.archived(url, Ok(archive)).unwrap();
+        let url = pagination.next_page().unwrap();
+        let error = Error::ReqwestError("connection refused".to_string());
+        pagination.archived(url, Err(error)).unwrap();
+        assert!(pagination.next_page().is_none());
+        let site = pagination.finish();
+        assert_eq!(site.len(), 1);
+        assert_eq!(site.failures().len(), 1);
+    }
+}
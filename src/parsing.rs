@@ -7,94 +7,432 @@
 
 //! Module for the core parsing functionality
 
+use crate::css::{self, CssUrlKind};
+use crate::dom::walk_elements;
+use crate::javascript;
+use crate::ArchiveOptions;
 use bytes::Bytes;
+use encoding_rs::{Encoding, UTF_8};
+use html5ever::{local_name, namespace_url, ns};
 use kuchiki::traits::TendrilSink;
-use kuchiki::{parse_html, NodeData};
+use kuchiki::{
+    parse_html, Attributes, ElementData, ExpandedName, NodeData, NodeDataRef,
+    NodeRef,
+};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use url::Url;
 
-// https://github.com/Y2Z/monolith/blob/fa71f6a42c94df4c48d01819922afe1248eabad5/src/utils.rs#L13
-const MAGIC: [(&[u8], &str); 18] = [
-    // Image
-    (b"GIF87a", "image/gif"),
-    (b"GIF89a", "image/gif"),
-    (b"\xFF\xD8\xFF", "image/jpeg"),
-    (b"\x89PNG\x0D\x0A\x1A\x0A", "image/png"),
-    (b"<svg ", "image/svg+xml"),
-    (b"RIFF....WEBPVP8 ", "image/webp"),
-    (b"\x00\x00\x01\x00", "image/x-icon"),
-    // Audio
-    (b"ID3", "audio/mpeg"),
-    (b"\xFF\x0E", "audio/mpeg"),
-    (b"\xFF\x0F", "audio/mpeg"),
-    (b"OggS", "audio/ogg"),
-    (b"RIFF....WAVEfmt ", "audio/wav"),
-    (b"fLaC", "audio/x-flac"),
-    // Video
-    (b"RIFF....AVI LIST", "video/avi"),
-    (b"....ftyp", "video/mp4"),
-    (b"\x00\x00\x01\x0B", "video/mpeg"),
-    (b"....moov", "video/quicktime"),
-    (b"\x1A\x45\xDF\xA3", "video/webm"),
-];
-
-/// Search image, style, and script resources and store their URIs
+/// Search image, style, script, and embedded object resources and store
+/// their URIs
 pub(crate) fn parse_resource_urls(
     url_base: &Url,
     page: &str,
+    options: &ArchiveOptions,
 ) -> Vec<ResourceUrl> {
     let document = parse_html().one(page);
+    let url_base = &base_url(url_base, &document);
 
-    // Collect resource URLs for each element type
     let mut resource_urls = Vec::new();
+    walk_elements(&document, options.max_depth, |element| {
+        collect_resource_urls(url_base, element, options, &mut resource_urls)
+    });
 
-    for element in document.select("img").unwrap() {
-        let node = element.as_node();
-        if let NodeData::Element(data) = node.data() {
-            let attr = data.attributes.borrow();
-            if let Some(u) = attr.get("src") {
-                if let Ok(u) = url_base.join(u) {
-                    resource_urls.push(ResourceUrl::Image(u));
-                }
+    // Dedup the URLs to avoid fetching the same one twice
+    resource_urls.sort();
+    resource_urls.dedup();
+
+    resource_urls
+}
+
+/// Collect the resources referenced by a single element. `<template>`
+/// contents are walked separately, since they live in their own document
+/// fragment rather than as children of the element.
+///
+/// `<noscript>` contents are parsed as text, so they are returned as their
+/// own document to be searched for the fallback resources.
+fn collect_resource_urls(
+    url_base: &Url,
+    element: &NodeDataRef<ElementData>,
+    options: &ArchiveOptions,
+    resource_urls: &mut Vec<ResourceUrl>,
+) -> Option<NodeRef> {
+    let attr = element.attributes.borrow();
+
+    // Images referenced from inline `style` attributes
+    if let Some(style) = attr.get("style") {
+        resource_urls.extend(css_resource_urls(url_base, style));
+    }
+
+    let mut push = |kind: fn(Url) -> ResourceUrl, u: &str| {
+        if let Some(u) = resolve_url(url_base, u) {
+            resource_urls.push(kind(u));
+        }
+    };
+
+    // Attributes added with `ArchiveOptions::extra_url_attributes`
+    for extra in &options.url_attributes {
+        if matches_tag(extra.tag, &element.name.local) {
+            if let Some(u) = attr.get(extra.attribute) {
+                push(extra.kind, u);
             }
         }
     }
 
-    for element in document.select("link").unwrap() {
-        let node = element.as_node();
-        if let NodeData::Element(data) = node.data() {
-            let attr = data.attributes.borrow();
-            if Some("stylesheet") == attr.get("rel") {
-                if let Some(u) = attr.get("href") {
-                    if let Ok(u) = url_base.join(u) {
-                        resource_urls.push(ResourceUrl::Css(u));
+    if element.name.ns == ns!(svg) {
+        // Inline SVG can pull in external files via `<image>` and `<use>`
+        if !matches!(&*element.name.local, "image" | "use") {
+            return None;
+        }
+        if let Some(u) = svg_href_name(&attr).and_then(|n| attr.map.get(&n)) {
+            if let Some(mut u) = resolve_url(url_base, &u.value) {
+                // `<use>` points at an element within the file, but it is
+                // the whole file that needs to be downloaded
+                u.set_fragment(None);
+                resource_urls.push(ResourceUrl::Image(u));
+            }
+        }
+        return None;
+    }
+    if element.name.ns != ns!(html) {
+        return None;
+    }
+
+    match &*element.name.local {
+        "img" => {
+            if let Some(u) = attr.get("src") {
+                push(ResourceUrl::Image, u);
+            }
+
+            // Lazy-loaded images keep their real URLs in other attributes
+            for name in options.lazy_attributes {
+                let value = match attr.get(*name) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                if is_srcset_attribute(name) {
+                    for candidate in parse_srcset(value) {
+                        push(ResourceUrl::Image, candidate.url);
                     }
+                } else {
+                    push(ResourceUrl::Image, value);
+                }
+            }
+        }
+        "input" if is_image_input(attr.get("type")) => {
+            if let Some(u) = attr.get("src") {
+                push(ResourceUrl::Image, u);
+            }
+        }
+        "style" => {
+            // Images, fonts, and stylesheets referenced from inline
+            // `<style>` tags
+            resource_urls
+                .extend(css_resource_urls(url_base, &element.text_contents()));
+        }
+        "link" => {
+            // Stylesheets, and preloaded resources tagged by their `as`
+            // attribute
+            let kind = if has_rel(attr.get("rel"), "stylesheet") {
+                Some(ResourceUrl::Css as fn(Url) -> ResourceUrl)
+            } else {
+                preload_kind(attr.get("rel"), attr.get("as"))
+            };
+            if let (Some(kind), Some(u)) = (kind, attr.get("href")) {
+                push(kind, u);
+            }
+        }
+        "script" => {
+            if let Some(u) = attr.get("src") {
+                push(ResourceUrl::Javascript, u);
+            } else if is_module_script(attr.get("type")) {
+                // Modules imported by an inline module script are
+                // referenced directly by the page, like external ones
+                resource_urls.extend(
+                    javascript::module_imports(
+                        url_base,
+                        &element.text_contents(),
+                    )
+                    .into_iter()
+                    .map(ResourceUrl::Javascript),
+                );
+            }
+        }
+        "video" | "audio" => {
+            if let Some(u) = attr.get("src") {
+                push(ResourceUrl::Media, u);
+            }
+            if let Some(u) = attr.get("poster") {
+                push(ResourceUrl::Image, u);
+            }
+        }
+        "source" => {
+            // Sources within `<video>` and `<audio>` offer media, and
+            // those within `<picture>` images
+            if let Some(u) = attr.get("src") {
+                push(ResourceUrl::Media, u);
+            }
+            if let Some(srcset) = attr.get("srcset") {
+                for candidate in parse_srcset(srcset) {
+                    push(ResourceUrl::Image, candidate.url);
                 }
             }
         }
+        "track" => {
+            if let Some(u) = attr.get("src") {
+                push(ResourceUrl::Media, u);
+            }
+        }
+        "object" => {
+            if let Some(u) = attr.get("data") {
+                push(ResourceUrl::Object, u);
+            }
+        }
+        "embed" => {
+            if let Some(u) = attr.get("src") {
+                push(ResourceUrl::Object, u);
+            }
+        }
+        "a" | "area" => {
+            // Downloadable files, if they have one of the extensions asked
+            // for
+            let attachment = attr
+                .get("href")
+                .and_then(|u| resolve_url(url_base, u))
+                .filter(|u| has_extension(u, options.attachment_extensions));
+            if let Some(u) = attachment {
+                resource_urls.push(ResourceUrl::Attachment(u));
+            }
+        }
+        "frame" if options.frame_depth > 0 => {
+            if let Some(u) = attr.get("src") {
+                push(ResourceUrl::Document, u);
+            }
+        }
+        "noscript" => {
+            return Some(parse_html().one(element.text_contents()));
+        }
+        _ => {}
     }
 
-    for element in document.select("script").unwrap() {
+    None
+}
+
+/// Find the resources referenced by some CSS, resolved against `base`.
+/// Each is tagged by where it appears: fonts from the `src` of
+/// `@font-face` rules, stylesheets from `@import` rules, and images from
+/// everywhere else.
+pub(crate) fn css_resource_urls(base: &Url, css: &str) -> Vec<ResourceUrl> {
+    css::find_urls(css)
+        .into_iter()
+        .filter_map(|css_url| {
+            let kind = match css_url.kind {
+                CssUrlKind::Image => ResourceUrl::Image,
+                CssUrlKind::Font => ResourceUrl::Font,
+                CssUrlKind::Import => ResourceUrl::Css,
+            };
+            resolve_url(base, &css_url.url).map(kind)
+        })
+        .collect()
+}
+
+/// Determine the URL which relative URLs in the document are resolved
+/// against. This is the page URL unless the document has a `<base>`
+/// element with an `href`, in which case the first one wins.
+pub(crate) fn base_url(page_url: &Url, document: &NodeRef) -> Url {
+    for element in document.select("base").unwrap() {
         let node = element.as_node();
         if let NodeData::Element(data) = node.data() {
             let attr = data.attributes.borrow();
-            if let Some(u) = attr.get("src") {
-                if let Ok(u) = url_base.join(u) {
-                    resource_urls.push(ResourceUrl::Javascript(u));
+            if let Some(href) = attr.get("href") {
+                if let Ok(u) = page_url.join(href) {
+                    return u;
                 }
+                break;
             }
         }
     }
+    page_url.clone()
+}
 
-    // Dedup the URLs to avoid fetching the same one twice
-    resource_urls.sort();
-    resource_urls.dedup();
+/// Resolve a URL from the page against `base`, as long as it points at
+/// something which can be downloaded. `data:`, `javascript:`, and
+/// `mailto:` URIs, along with empty and fragment-only URLs which refer
+/// back to the page itself, give `None`.
+pub(crate) fn resolve_url(base: &Url, u: &str) -> Option<Url> {
+    let u = u.trim();
+    if u.is_empty() || u.starts_with('#') {
+        return None;
+    }
+    let url = base.join(u).ok()?;
+    match url.scheme() {
+        "http" | "https" => Some(url),
+        _ => None,
+    }
+}
 
-    resource_urls
+/// Whether an element name matches the `tag` of an extra URL attribute,
+/// where `*` matches every element
+pub(crate) fn matches_tag(tag: &str, name: &str) -> bool {
+    tag == "*" || tag.eq_ignore_ascii_case(name)
 }
 
-/// Tag the resource URLs with the type of resource they correspond to
+/// Whether the file name at the end of a URL's path has one of the given
+/// extensions, ignoring case
+pub(crate) fn has_extension(url: &Url, extensions: &[&str]) -> bool {
+    match file_name(url).rsplit_once('.') {
+        Some((_, extension)) => extensions
+            .iter()
+            .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension)),
+        None => false,
+    }
+}
+
+/// The last segment of a URL's path, e.g. `report.pdf` for
+/// `http://example.com/files/report.pdf?v=2`
+pub(crate) fn file_name(url: &Url) -> &str {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or("")
+}
+
+/// Whether a space-separated `rel` attribute contains the given keyword
+pub(crate) fn has_rel(rel: Option<&str>, keyword: &str) -> bool {
+    rel.map(|rel| {
+        rel.split_ascii_whitespace()
+            .any(|r| r.eq_ignore_ascii_case(keyword))
+    })
+    .unwrap_or(false)
+}
+
+/// For `<link rel="preload">` and `<link rel="modulepreload">` hints,
+/// return the kind of resource being preloaded.
+///
+/// Takes the values of the `rel` and `as` attributes.
+pub(crate) fn preload_kind(
+    rel: Option<&str>,
+    as_type: Option<&str>,
+) -> Option<fn(Url) -> ResourceUrl> {
+    if has_rel(rel, "modulepreload") {
+        return Some(ResourceUrl::Javascript);
+    }
+    if !has_rel(rel, "preload") {
+        return None;
+    }
+    match as_type?.to_ascii_lowercase().as_str() {
+        "style" => Some(ResourceUrl::Css),
+        "script" => Some(ResourceUrl::Javascript),
+        "image" => Some(ResourceUrl::Image),
+        "font" => Some(ResourceUrl::Font),
+        "audio" | "video" | "track" => Some(ResourceUrl::Media),
+        _ => None,
+    }
+}
+
+/// Whether the `type` attribute of a `<script>` marks it as an ES module,
+/// i.e. `<script type="module">`
+pub(crate) fn is_module_script(script_type: Option<&str>) -> bool {
+    script_type
+        .map(|t| t.trim().eq_ignore_ascii_case("module"))
+        .unwrap_or(false)
+}
+
+/// Whether the `type` attribute of an `<input>` marks it as an image
+/// button, i.e. `<input type="image" src="...">`
+pub(crate) fn is_image_input(input_type: Option<&str>) -> bool {
+    input_type
+        .map(|t| t.trim().eq_ignore_ascii_case("image"))
+        .unwrap_or(false)
+}
+
+/// A single image candidate from a `srcset` attribute
 #[derive(Debug, PartialEq, Eq)]
+pub(crate) struct SrcsetCandidate<'a> {
+    /// URL of the image
+    pub url: &'a str,
+    /// Width or pixel density descriptor, e.g. `2x` or `480w`. Empty if
+    /// the candidate has no descriptor.
+    pub descriptor: &'a str,
+}
+
+/// Split a `srcset` attribute into its image candidates, following the
+/// parsing rules from the HTML specification. URLs may themselves
+/// contain commas, so the list can't just be split on them.
+pub(crate) fn parse_srcset(srcset: &str) -> Vec<SrcsetCandidate<'_>> {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+
+    loop {
+        rest = rest
+            .trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+
+        let url_end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let url = &rest[..url_end];
+        rest = &rest[url_end..];
+
+        if url.ends_with(',') {
+            // A trailing comma ends the candidate without a descriptor
+            candidates.push(SrcsetCandidate {
+                url: url.trim_end_matches(','),
+                descriptor: "",
+            });
+            continue;
+        }
+
+        // The descriptor runs up to the next comma outside of brackets
+        let mut depth = 0;
+        let descriptor_end = rest
+            .char_indices()
+            .find(|&(_, c)| match c {
+                '(' => {
+                    depth += 1;
+                    false
+                }
+                ')' => {
+                    depth -= 1;
+                    false
+                }
+                ',' => depth <= 0,
+                _ => false,
+            })
+            .map(|(i, _)| i)
+            .unwrap_or(rest.len());
+        candidates.push(SrcsetCandidate {
+            url,
+            descriptor: rest[..descriptor_end].trim(),
+        });
+        rest = &rest[descriptor_end..];
+    }
+
+    candidates
+}
+
+/// Whether a lazy-loading attribute holds a `srcset` rather than a
+/// single URL
+pub(crate) fn is_srcset_attribute(name: &str) -> bool {
+    name.ends_with("srcset")
+}
+
+/// Name of the attribute linking an SVG element to another resource:
+/// either `href` or the legacy `xlink:href` from SVG 1.1
+pub(crate) fn svg_href_name(attr: &Attributes) -> Option<ExpandedName> {
+    [
+        ExpandedName::new(ns!(), local_name!("href")),
+        ExpandedName::new(ns!(xlink), local_name!("href")),
+    ]
+    .iter()
+    .find(|name| attr.map.contains_key(name))
+    .cloned()
+}
+
+/// Tag the resource URLs with the type of resource they correspond to
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum ResourceUrl {
     /// Javascript files
     Javascript(Url),
@@ -102,6 +440,18 @@ pub enum ResourceUrl {
     Css(Url),
     /// Image files
     Image(Url),
+    /// Files embedded via `<object>` or `<embed>`, e.g. PDFs
+    Object(Url),
+    /// Web fonts
+    Font(Url),
+    /// Audio, video, and text tracks
+    Media(Url),
+    /// HTML documents
+    Document(Url),
+    /// Files linked for download, e.g. PDFs linked from `<a href>`
+    Attachment(Url),
+    /// Anything else
+    Other(Url),
 }
 
 impl ResourceUrl {
@@ -109,16 +459,22 @@ impl ResourceUrl {
     pub fn url(&self) -> &Url {
         use ResourceUrl::*;
         match self {
-            Javascript(u) => &u,
-            Css(u) => &u,
-            Image(u) => &u,
+            Javascript(u) => u,
+            Css(u) => u,
+            Image(u) => u,
+            Object(u) => u,
+            Font(u) => u,
+            Media(u) => u,
+            Document(u) => u,
+            Attachment(u) => u,
+            Other(u) => u,
         }
     }
 }
 
 impl PartialOrd for ResourceUrl {
     fn partial_cmp(&self, rhs: &ResourceUrl) -> Option<std::cmp::Ordering> {
-        Some(self.url().cmp(rhs.url()))
+        Some(self.cmp(rhs))
     }
 }
 
@@ -133,21 +489,146 @@ impl Ord for ResourceUrl {
 pub type ResourceMap = HashMap<Url, Resource>;
 
 /// Generic resource type
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Resource {
-    /// Javascript is stored as a String
-    Javascript(String),
-    /// Stylesheets are stored as a String
-    Css(String),
+    /// Javascript is stored as a [`TextResource`]
+    Javascript(TextResource),
+    /// Stylesheets are stored as a [`TextResource`]
+    Css(TextResource),
     /// Images are stored as an [`ImageResource`] to allow the mimetype
     /// metadata to be useful
     Image(ImageResource),
+    /// Embedded objects are binary data with a mimetype, just like
+    /// images, so they are also stored as an [`ImageResource`]
+    Object(ImageResource),
+    /// Fonts are stored as an [`ImageResource`]
+    Font(ImageResource),
+    /// Audio, video, and text tracks are stored as an [`ImageResource`]
+    Media(ImageResource),
+    /// HTML documents are stored as a [`TextResource`]
+    Document(TextResource),
+    /// Attachments are stored as an [`ImageResource`]
+    Attachment(ImageResource),
+    /// Any other kind of resource
+    Other {
+        /// Raw data of the resource, as downloaded
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_data"))]
+        data: Bytes,
+        /// Mime type of the resource, e.g. `application/json`
+        mimetype: String,
+    },
+}
+
+impl Resource {
+    /// Raw data of the resource, as downloaded
+    pub fn data(&self) -> &Bytes {
+        use Resource::*;
+        match self {
+            Javascript(r) | Css(r) | Document(r) => &r.data,
+            Image(r) | Object(r) | Font(r) | Media(r) | Attachment(r) => {
+                &r.data
+            }
+            Other { data, .. } => data,
+        }
+    }
+
+    /// Raw data of the resource, for replacing it with the same data held
+    /// elsewhere
+    pub(crate) fn data_mut(&mut self) -> &mut Bytes {
+        use Resource::*;
+        match self {
+            Javascript(r) | Css(r) | Document(r) => &mut r.data,
+            Image(r) | Object(r) | Font(r) | Media(r) | Attachment(r) => {
+                &mut r.data
+            }
+            Other { data, .. } => data,
+        }
+    }
+
+    /// Mime type of the resource, e.g. `image/png`
+    pub fn mimetype(&self) -> &str {
+        use Resource::*;
+        match self {
+            Javascript(r) | Css(r) | Document(r) => &r.mimetype,
+            Image(r) | Object(r) | Font(r) | Media(r) | Attachment(r) => {
+                &r.mimetype
+            }
+            Other { mimetype, .. } => mimetype,
+        }
+    }
+
+    /// Size of the resource's data in bytes
+    pub fn len(&self) -> usize {
+        self.data().len()
+    }
+
+    /// Whether the resource's data is empty
+    pub fn is_empty(&self) -> bool {
+        self.data().is_empty()
+    }
+
+    /// Encode the data as base 64 and embed it into a `data:` URI. Text
+    /// resources keep their charset, e.g.
+    /// `data:text/css;charset=Shift_JIS;base64,...`.
+    pub fn to_data_uri(&self) -> String {
+        let content_type = self.content_type();
+        format!(
+            "data:{};base64,{}",
+            content_type,
+            base64::encode(self.data())
+        )
+    }
+
+    /// The mime type along with the charset of text resources, e.g.
+    /// `text/css;charset=Shift_JIS`
+    pub(crate) fn content_type(&self) -> String {
+        use Resource::*;
+        match self {
+            Javascript(r) | Css(r) | Document(r) => match &r.charset {
+                Some(charset) => format!("{};charset={}", r.mimetype, charset),
+                None => r.mimetype.clone(),
+            },
+            _ => self.mimetype().to_string(),
+        }
+    }
+}
+
+/// Data type representing a text resource, i.e. a script or stylesheet
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextResource {
+    /// Raw data of the resource, as downloaded
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_data"))]
+    pub data: Bytes,
+    /// Mime type of the resource, e.g. `text/css`
+    pub mimetype: String,
+    /// Name of the character encoding of the data, e.g. `Shift_JIS`, if
+    /// one was declared
+    pub charset: Option<String>,
+}
+
+impl TextResource {
+    /// Decode the data into a string using its charset, falling back to
+    /// UTF-8 if it doesn't have one. A byte order mark at the start of
+    /// the data overrides the charset.
+    pub fn text(&self) -> Cow<'_, str> {
+        let encoding = self
+            .charset
+            .as_deref()
+            .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+            .unwrap_or(UTF_8);
+        encoding.decode(&self.data).0
+    }
 }
 
 /// Data type representing an image
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ImageResource {
     /// Raw image data
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_data"))]
     pub data: Bytes,
     /// Mime type of the image, e.g. `image/png`
     pub mimetype: String,
@@ -162,22 +643,8 @@ impl ImageResource {
     }
 }
 
-// https://github.com/Y2Z/monolith/blob/fa71f6a42c94df4c48d01819922afe1248eabad5/src/utils.rs#L44
-pub(crate) fn mimetype_from_response(data: &[u8], url: &Url) -> String {
-    for item in MAGIC.iter() {
-        if data.starts_with(item.0) {
-            return item.1.to_string();
-        }
-    }
-
-    if url.path().to_lowercase().ends_with(".svg") {
-        return "image/svg+xml".to_string();
-    }
-
-    "".to_string()
-}
-
 #[cfg(test)]
+#[allow(clippy::needless_borrow)]
 mod test {
     use super::*;
 
@@ -205,6 +672,24 @@ mod test {
         assert!(data_uri.ends_with("Q/hkoEnAH1wAAAABJRU5ErkJggg=="));
     }
 
+    #[test]
+    fn test_resource_accessors() {
+        let css = Resource::Css(TextResource {
+            data: Bytes::from_static(b"p {}"),
+            mimetype: "text/css".to_string(),
+            charset: None,
+        });
+        assert_eq!(css.mimetype(), "text/css");
+        assert_eq!(css.len(), 4);
+
+        let other = Resource::Other {
+            data: Bytes::new(),
+            mimetype: "application/json".to_string(),
+        };
+        assert_eq!(other.mimetype(), "application/json");
+        assert!(other.is_empty());
+    }
+
     #[test]
     fn test_image_tags() {
         let html = r#"
@@ -219,7 +704,8 @@ mod test {
         </html>
         "#;
 
-        let resource_urls = parse_resource_urls(&u(), &html);
+        let resource_urls =
+            parse_resource_urls(&u(), &html, &Default::default());
 
         assert_eq!(resource_urls.len(), 1);
         assert_eq!(
@@ -231,135 +717,169 @@ mod test {
     }
 
     #[test]
-    fn test_css_tags() {
+    fn test_lazy_loaded_images() {
         let html = r#"
         <!DOCTYPE html>
         <html>
-            <head>
-                <link rel="stylesheet" type="text/css" href="/style.css" />
-                <link rel="something_else" href="NOT_ALLOWED" />
-            </head>
+            <head></head>
             <body>
-                <div id="content">
-                </div>
+                <img src="placeholder.gif" data-src="/images/real.jpg" />
+                <img data-srcset="small.jpg 480w, large.jpg 1080w" />
+                <img data-original="/images/custom.jpg" />
             </body>
         </html>
         "#;
 
-        let resource_urls = parse_resource_urls(&u(), &html);
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        let mut test_urls = vec![
+            ResourceUrl::Image(
+                Url::parse("http://example.com/placeholder.gif").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/real.jpg").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/small.jpg").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/large.jpg").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+        assert_eq!(resource_urls, test_urls);
+
+        // Custom attributes can be added to the list
+        let options = ArchiveOptions {
+            lazy_attributes: &["data-original"],
+            ..Default::default()
+        };
+        let resource_urls = parse_resource_urls(&u(), html, &options);
+        assert!(resource_urls.contains(&ResourceUrl::Image(
+            Url::parse("http://example.com/images/custom.jpg").unwrap()
+        )));
+        assert!(!resource_urls.contains(&ResourceUrl::Image(
+            Url::parse("http://example.com/images/real.jpg").unwrap()
+        )));
+    }
 
-        assert_eq!(resource_urls.len(), 1);
+    #[test]
+    fn test_parse_srcset() {
+        let candidates = parse_srcset(
+            "a.jpg, b.jpg 2x,c,d.jpg 480w,  e.jpg (max-width: 1px, 2px) 3x,",
+        );
         assert_eq!(
-            resource_urls[0],
-            ResourceUrl::Css(
-                Url::parse("http://example.com/style.css").unwrap()
-            )
+            candidates,
+            vec![
+                SrcsetCandidate {
+                    url: "a.jpg",
+                    descriptor: "",
+                },
+                SrcsetCandidate {
+                    url: "b.jpg",
+                    descriptor: "2x",
+                },
+                SrcsetCandidate {
+                    url: "c,d.jpg",
+                    descriptor: "480w",
+                },
+                SrcsetCandidate {
+                    url: "e.jpg",
+                    descriptor: "(max-width: 1px, 2px) 3x",
+                },
+            ]
         );
     }
 
     #[test]
-    fn test_script_tags() {
+    fn test_image_input_tags() {
         let html = r#"
         <!DOCTYPE html>
         <html>
-            <head>
-                <script language="javascript" src="/js.js"></script>
-            </head>
+            <head></head>
             <body>
-                <div id="content">
-                </div>
+                <form>
+                    <input type="IMAGE" src="/images/submit.png" />
+                    <input type="text" src="NOT_ALLOWED" />
+                    <input src="ALSO_NOT_ALLOWED" />
+                </form>
             </body>
         </html>
         "#;
 
-        let resource_urls = parse_resource_urls(&u(), &html);
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
 
         assert_eq!(resource_urls.len(), 1);
         assert_eq!(
             resource_urls[0],
-            ResourceUrl::Javascript(
-                Url::parse("http://example.com/js.js").unwrap()
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/submit.png").unwrap()
             )
         );
     }
 
     #[test]
-    fn test_deep_nesting() {
-        let html = r#"
+    fn test_svg_subresources() {
+        let html = r##"
         <!DOCTYPE html>
         <html>
-            <head>
-                <script language="javascript" src="/js.js"></script>
-                <link rel="stylesheet" href="1.css" type="text/css" />
-            </head>
+            <head></head>
             <body>
-                <div id="content">
-                    <div><div><div>
-                            <img src="1.png" />
-                        </div></div>
-                        <script src="2.js"></script>
-                    </div>
-                    <div><div>
-                        <img src="2.tiff" />
-                    </div></div>
-                </div>
+                <svg xmlns:xlink="http://www.w3.org/1999/xlink">
+                    <image href="/images/photo.jpg" />
+                    <image xlink:href="/images/legacy.png" />
+                    <use href="/icons/sprite.svg#home" />
+                    <use xlink:href="/icons/sprite.svg#search" />
+                    <use href="#local-symbol" />
+                </svg>
             </body>
         </html>
-        "#;
-
-        let resource_urls = parse_resource_urls(&u(), &html);
+        "##;
 
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
         let mut test_urls = vec![
-            ResourceUrl::Javascript(
-                Url::parse("http://example.com/js.js").unwrap(),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/photo.jpg").unwrap(),
             ),
-            ResourceUrl::Css(Url::parse("http://example.com/1.css").unwrap()),
-            ResourceUrl::Image(Url::parse("http://example.com/1.png").unwrap()),
-            ResourceUrl::Javascript(
-                Url::parse("http://example.com/2.js").unwrap(),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/legacy.png").unwrap(),
             ),
             ResourceUrl::Image(
-                Url::parse("http://example.com/2.tiff").unwrap(),
+                Url::parse("http://example.com/icons/sprite.svg").unwrap(),
             ),
         ];
         test_urls.sort();
 
-        assert_eq!(resource_urls.len(), 5);
-        assert_eq!(resource_urls, test_urls,);
+        assert_eq!(resource_urls.len(), 3);
+        assert_eq!(resource_urls, test_urls);
     }
 
     #[test]
-    fn test_relative_paths() {
+    fn test_style_attributes() {
         let html = r#"
         <!DOCTYPE html>
         <html>
             <head></head>
             <body>
-                <div id="content">
-                    <img src="../../images/fun.png" />
-                    <img src="/absolute_path.jpg" />
-        <img src="https://www.rust-lang.org/static/images/rust-logo-blk.svg" />
+                <div style="background-image: url('/images/hero.jpg')">
+                    <span style="color: red">No images here</span>
+                    <p style="background: url(a.png), url(&quot;b.png&quot;)">
+                    </p>
                 </div>
             </body>
         </html>
         "#;
 
-        let u = Url::parse("http://example.com/one/two/three/four/").unwrap();
-        let resource_urls = parse_resource_urls(&u, &html);
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
         let mut test_urls = vec![
             ResourceUrl::Image(
-                Url::parse("http://example.com/one/two/images/fun.png")
-                    .unwrap(),
-            ),
-            ResourceUrl::Image(
-                Url::parse("http://example.com/absolute_path.jpg").unwrap(),
-            ),
-            ResourceUrl::Image(
-                Url::parse(
-                    "https://www.rust-lang.org/static/images/rust-logo-blk.svg",
-                )
-                .unwrap(),
+                Url::parse("http://example.com/images/hero.jpg").unwrap(),
             ),
+            ResourceUrl::Image(Url::parse("http://example.com/a.png").unwrap()),
+            ResourceUrl::Image(Url::parse("http://example.com/b.png").unwrap()),
         ];
         test_urls.sort();
 
@@ -368,20 +888,650 @@ mod test {
     }
 
     #[test]
-    fn test_upper_case_tags() {
+    fn test_style_tags() {
         let html = r#"
-        <HTML>
-            <HEAD>
-                <SCRIPT LANGUAGE="javascript" SRC="/js.js"></SCRIPT>
-            </HEAD>
-            <BODY>
-                <DIV ID="content">
-                </DIV>
+        <!DOCTYPE html>
+        <html>
+            <head>
+                <style>
+                    @import url(base.css);
+                    @import "theme.css" screen;
+                    @font-face {
+                        font-family: "Fancy";
+                        src: url(/fonts/fancy.woff2) format("woff2");
+                    }
+                    body { background: url("images/bg.png"); }
+                </style>
+            </head>
+            <body></body>
+        </html>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        let mut test_urls = vec![
+            ResourceUrl::Css(
+                Url::parse("http://example.com/base.css").unwrap(),
+            ),
+            ResourceUrl::Css(
+                Url::parse("http://example.com/theme.css").unwrap(),
+            ),
+            ResourceUrl::Font(
+                Url::parse("http://example.com/fonts/fancy.woff2").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/bg.png").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls.len(), 4);
+        assert_eq!(resource_urls, test_urls);
+    }
+
+    #[test]
+    fn test_css_tags() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head>
+                <link rel="stylesheet" type="text/css" href="/style.css" />
+                <link rel="something_else" href="NOT_ALLOWED" />
+            </head>
+            <body>
+                <div id="content">
+                </div>
+            </body>
+        </html>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), &html, &Default::default());
+
+        assert_eq!(resource_urls.len(), 1);
+        assert_eq!(
+            resource_urls[0],
+            ResourceUrl::Css(
+                Url::parse("http://example.com/style.css").unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_stylesheet_rel_values() {
+        let html = r#"
+        <link rel="Stylesheet" href="/upper.css">
+        <link rel="stylesheet preload" as="style" href="/preloaded.css">
+        <link rel="alternate stylesheet" href="/alternate.css">
+        <link rel="stylesheets" href="/not-a-stylesheet.css">
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        let mut test_urls = vec![
+            ResourceUrl::Css(
+                Url::parse("http://example.com/upper.css").unwrap(),
+            ),
+            ResourceUrl::Css(
+                Url::parse("http://example.com/preloaded.css").unwrap(),
+            ),
+            ResourceUrl::Css(
+                Url::parse("http://example.com/alternate.css").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls, test_urls);
+    }
+
+    #[test]
+    fn test_preload_links() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head>
+                <link rel="preload" as="style" href="/preload.css" />
+                <link rel="preload" as="script" href="/preload.js" />
+                <link rel="PRELOAD" as="font" href="/font.woff2" crossorigin />
+                <link rel="preload" as="image" href="/hero.jpg" />
+                <link rel="preload" as="video" href="/intro.mp4" />
+                <link rel="preload" as="fetch" href="/data.json" />
+                <link rel="modulepreload" href="/module.js" />
+            </head>
+            <body></body>
+        </html>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        let mut test_urls = vec![
+            ResourceUrl::Css(
+                Url::parse("http://example.com/preload.css").unwrap(),
+            ),
+            ResourceUrl::Javascript(
+                Url::parse("http://example.com/preload.js").unwrap(),
+            ),
+            ResourceUrl::Font(
+                Url::parse("http://example.com/font.woff2").unwrap(),
+            ),
+            ResourceUrl::Media(
+                Url::parse("http://example.com/intro.mp4").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/hero.jpg").unwrap(),
+            ),
+            ResourceUrl::Javascript(
+                Url::parse("http://example.com/module.js").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls, test_urls);
+    }
+
+    #[test]
+    fn test_script_tags() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head>
+                <script language="javascript" src="/js.js"></script>
+            </head>
+            <body>
+                <div id="content">
+                </div>
+            </body>
+        </html>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), &html, &Default::default());
+
+        assert_eq!(resource_urls.len(), 1);
+        assert_eq!(
+            resource_urls[0],
+            ResourceUrl::Javascript(
+                Url::parse("http://example.com/js.js").unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_media_tags() {
+        let html = r#"
+        <video src="/clip.mp4" poster="/poster.jpg">
+            <source src="/clip.webm" type="video/webm">
+            <track src="/captions.vtt" kind="captions">
+        </video>
+        <audio><source src="/song.ogg"></audio>
+        <picture>
+            <source srcset="/wide.avif 2x, /narrow.avif" type="image/avif">
+            <img src="/fallback.png">
+        </picture>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        let u = |path| Url::parse("http://example.com").unwrap().join(path);
+        let mut test_urls = vec![
+            ResourceUrl::Media(u("clip.mp4").unwrap()),
+            ResourceUrl::Image(u("poster.jpg").unwrap()),
+            ResourceUrl::Media(u("clip.webm").unwrap()),
+            ResourceUrl::Media(u("captions.vtt").unwrap()),
+            ResourceUrl::Media(u("song.ogg").unwrap()),
+            ResourceUrl::Image(u("wide.avif").unwrap()),
+            ResourceUrl::Image(u("narrow.avif").unwrap()),
+            ResourceUrl::Image(u("fallback.png").unwrap()),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls, test_urls);
+    }
+
+    #[test]
+    fn test_object_and_embed_tags() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head></head>
+            <body>
+                <object data="/report.pdf" type="application/pdf"></object>
+                <embed src="diagram.svg" type="image/svg+xml" />
+                <object>No data attribute</object>
+            </body>
+        </html>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        let mut test_urls = vec![
+            ResourceUrl::Object(
+                Url::parse("http://example.com/report.pdf").unwrap(),
+            ),
+            ResourceUrl::Object(
+                Url::parse("http://example.com/diagram.svg").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls.len(), 2);
+        assert_eq!(resource_urls, test_urls);
+    }
+
+    #[test]
+    fn test_base_element() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head>
+                <base href="https://cdn.example.com/app/" />
+                <base href="https://ignored.example.com/" />
+                <link rel="stylesheet" href="style.css" />
+            </head>
+            <body>
+                <img src="/logo.png" />
+            </body>
+        </html>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        let mut test_urls = vec![
+            ResourceUrl::Css(
+                Url::parse("https://cdn.example.com/app/style.css").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("https://cdn.example.com/logo.png").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls, test_urls);
+
+        // A relative base is resolved against the page URL
+        let html = r#"<base href="static/"><img src="a.png">"#;
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        assert_eq!(
+            resource_urls,
+            vec![ResourceUrl::Image(
+                Url::parse("http://example.com/static/a.png").unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_noscript() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head>
+                <noscript><link rel="stylesheet" href="/noscript.css"></noscript>
+            </head>
+            <body>
+                <img class="lazyload" data-src="/images/lazy.jpg" />
+                <noscript><img src="/images/fallback.jpg" /></noscript>
+            </body>
+        </html>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        let mut test_urls = vec![
+            ResourceUrl::Css(
+                Url::parse("http://example.com/noscript.css").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/lazy.jpg").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/fallback.jpg").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls, test_urls);
+    }
+
+    #[test]
+    fn test_template_contents() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head></head>
+            <body>
+                <template id="card">
+                    <img src="/images/card.png" />
+                    <template><img src="/images/nested.png" /></template>
+                </template>
+                <my-element>
+                    <template shadowrootmode="open">
+                        <link rel="stylesheet" href="/shadow.css" />
+                    </template>
+                </my-element>
+            </body>
+        </html>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        let mut test_urls = vec![
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/card.png").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/nested.png").unwrap(),
+            ),
+            ResourceUrl::Css(
+                Url::parse("http://example.com/shadow.css").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls, test_urls);
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head>
+                <script language="javascript" src="/js.js"></script>
+                <link rel="stylesheet" href="1.css" type="text/css" />
+            </head>
+            <body>
+                <div id="content">
+                    <div><div><div>
+                            <img src="1.png" />
+                        </div></div>
+                        <script src="2.js"></script>
+                    </div>
+                    <div><div>
+                        <img src="2.tiff" />
+                    </div></div>
+                </div>
+            </body>
+        </html>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), &html, &Default::default());
+
+        let mut test_urls = vec![
+            ResourceUrl::Javascript(
+                Url::parse("http://example.com/js.js").unwrap(),
+            ),
+            ResourceUrl::Css(Url::parse("http://example.com/1.css").unwrap()),
+            ResourceUrl::Image(Url::parse("http://example.com/1.png").unwrap()),
+            ResourceUrl::Javascript(
+                Url::parse("http://example.com/2.js").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/2.tiff").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls.len(), 5);
+        assert_eq!(resource_urls, test_urls,);
+    }
+
+    #[test]
+    fn test_attachments() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head></head>
+            <body>
+                <a href="/reports/q1.PDF">Report</a>
+                <a href="data.zip?v=2">Data</a>
+                <a href="/about.html">About</a>
+                <map><area href="slides.pptx" /></map>
+            </body>
+        </html>
+        "#;
+
+        // Attachments are opt-in
+        assert!(parse_resource_urls(&u(), html, &Default::default()).is_empty());
+
+        let options = ArchiveOptions {
+            attachment_extensions: &["pdf", ".zip", "pptx"],
+            ..Default::default()
+        };
+        let resource_urls = parse_resource_urls(&u(), html, &options);
+        let mut test_urls = vec![
+            ResourceUrl::Attachment(
+                Url::parse("http://example.com/reports/q1.PDF").unwrap(),
+            ),
+            ResourceUrl::Attachment(
+                Url::parse("http://example.com/data.zip?v=2").unwrap(),
+            ),
+            ResourceUrl::Attachment(
+                Url::parse("http://example.com/slides.pptx").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+        assert_eq!(resource_urls, test_urls);
+    }
+
+    #[test]
+    fn test_frames() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <frameset cols="25%,75%">
+                <frame src="menu.html" />
+                <frame src="/content.html" />
+            </frameset>
+        </html>
+        "#;
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        let mut test_urls = vec![
+            ResourceUrl::Document(
+                Url::parse("http://example.com/menu.html").unwrap(),
+            ),
+            ResourceUrl::Document(
+                Url::parse("http://example.com/content.html").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+        assert_eq!(resource_urls, test_urls);
+
+        let options = ArchiveOptions {
+            frame_depth: 0,
+            ..Default::default()
+        };
+        assert!(parse_resource_urls(&u(), html, &options).is_empty());
+    }
+
+    #[test]
+    fn test_extra_url_attributes() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head></head>
+            <body>
+                <div data-bg="/images/hero.jpg"></div>
+                <VIDEO DATA-POSTER="/images/poster.jpg"></VIDEO>
+                <p data-bg="/images/ignored.jpg"></p>
+                <span data-font="/fonts/icons.woff2"></span>
+            </body>
+        </html>
+        "#;
+
+        let options = ArchiveOptions::default()
+            .extra_url_attributes("div", "data-bg", ResourceUrl::Image)
+            .extra_url_attributes("video", "data-poster", ResourceUrl::Image)
+            .extra_url_attributes("*", "data-font", ResourceUrl::Font);
+        let resource_urls = parse_resource_urls(&u(), html, &options);
+        let mut test_urls = vec![
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/hero.jpg").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/images/poster.jpg").unwrap(),
+            ),
+            ResourceUrl::Font(
+                Url::parse("http://example.com/fonts/icons.woff2").unwrap(),
+            ),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls, test_urls);
+    }
+
+    #[test]
+    fn test_unfetchable_urls() {
+        let html = r##"
+        <!DOCTYPE html>
+        <html>
+            <head>
+                <link rel="stylesheet" href="data:text/css,p{}" />
+                <script src="javascript:void(0)"></script>
+            </head>
+            <body style="background: url(data:image/png;base64,AAAA)">
+                <img src="data:image/png;base64,AAAA" />
+                <img src="" />
+                <img src="#top" />
+                <embed src="mailto:someone@example.com" />
+                <img src="/images/real.png" />
+            </body>
+        </html>
+        "##;
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        assert_eq!(
+            resource_urls,
+            vec![ResourceUrl::Image(
+                Url::parse("http://example.com/images/real.png").unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <body>
+                <img src="shallow.png" />
+                <div><div><div><img src="deep.png" /></div></div></div>
+            </body>
+        </html>
+        "#;
+
+        // The document, `<html>`, and `<body>` take up the first levels
+        let options = ArchiveOptions {
+            max_depth: 5,
+            ..Default::default()
+        };
+        let resource_urls = parse_resource_urls(&u(), html, &options);
+        assert_eq!(
+            resource_urls,
+            vec![ResourceUrl::Image(
+                Url::parse("http://example.com/shallow.png").unwrap()
+            )]
+        );
+
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        assert_eq!(resource_urls.len(), 2);
+    }
+
+    #[test]
+    fn test_relative_paths() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head></head>
+            <body>
+                <div id="content">
+                    <img src="../../images/fun.png" />
+                    <img src="/absolute_path.jpg" />
+        <img src="https://www.rust-lang.org/static/images/rust-logo-blk.svg" />
+                </div>
+            </body>
+        </html>
+        "#;
+
+        let u = Url::parse("http://example.com/one/two/three/four/").unwrap();
+        let resource_urls = parse_resource_urls(&u, &html, &Default::default());
+        let mut test_urls = vec![
+            ResourceUrl::Image(
+                Url::parse("http://example.com/one/two/images/fun.png")
+                    .unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse("http://example.com/absolute_path.jpg").unwrap(),
+            ),
+            ResourceUrl::Image(
+                Url::parse(
+                    "https://www.rust-lang.org/static/images/rust-logo-blk.svg",
+                )
+                .unwrap(),
+            ),
+        ];
+        test_urls.sort();
+
+        assert_eq!(resource_urls.len(), 3);
+        assert_eq!(resource_urls, test_urls);
+    }
+
+    #[test]
+    fn test_inline_module_imports() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head>
+                <script type="module">
+                    import { start } from "./js/app.js";
+                    import "lodash";
+                    start();
+                </script>
+                <script>
+                    var notAModule = 'import "./classic.js"';
+                </script>
+            </head>
+            <body></body>
+        </html>
+        "#;
+
+        let app = vec![ResourceUrl::Javascript(
+            Url::parse("http://example.com/js/app.js").unwrap(),
+        )];
+        let resource_urls =
+            parse_resource_urls(&u(), html, &Default::default());
+        assert_eq!(resource_urls, app);
+
+        // They're at depth zero, so they're downloaded even when no
+        // imports are followed
+        let options = ArchiveOptions {
+            module_depth: 0,
+            ..Default::default()
+        };
+        assert_eq!(parse_resource_urls(&u(), html, &options), app);
+    }
+
+    #[test]
+    fn test_upper_case_tags() {
+        let html = r#"
+        <HTML>
+            <HEAD>
+                <SCRIPT LANGUAGE="javascript" SRC="/js.js"></SCRIPT>
+            </HEAD>
+            <BODY>
+                <DIV ID="content">
+                </DIV>
             </BODY>
         </HTML>
         "#;
 
-        let resource_urls = parse_resource_urls(&u(), &html);
+        let resource_urls =
+            parse_resource_urls(&u(), &html, &Default::default());
 
         assert_eq!(resource_urls.len(), 1);
         assert_eq!(
@@ -409,7 +1559,8 @@ mod test {
         </html>
         "#;
 
-        let resource_urls = parse_resource_urls(&u(), &html);
+        let resource_urls =
+            parse_resource_urls(&u(), &html, &Default::default());
         let mut test_urls = vec![
             ResourceUrl::Javascript(
                 Url::parse("http://example.com/js.js").unwrap(),
@@ -421,20 +1572,4 @@ mod test {
         assert_eq!(resource_urls.len(), 2);
         assert_eq!(resource_urls, test_urls);
     }
-
-    #[test]
-    fn test_mimetype_detection() {
-        let data: &[u8] = include_bytes!(
-            "../dynamic_tests/resources/rustacean-flat-happy.png"
-        );
-        let url = Url::parse("http://example.com/ferris.png").unwrap();
-        let mimetype = mimetype_from_response(&data, &url);
-        assert_eq!(mimetype, "image/png");
-
-        let data: &[u8] =
-            include_bytes!("../dynamic_tests/resources/rust-logo-blk.svg");
-        let url = Url::parse("http://example.com/rust.svg").unwrap();
-        let mimetype = mimetype_from_response(&data, &url);
-        assert_eq!(mimetype, "image/svg+xml");
-    }
 }
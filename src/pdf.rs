@@ -0,0 +1,260 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for rendering archived pages to PDF, see
+//! [`PageArchive::to_pdf`]
+
+use crate::error::Error;
+use crate::headless::{browser_error, close, launch, open, LOADED};
+use crate::page_archive::{EmbedOptions, PageArchive};
+use crate::ArchiveOptions;
+use chromiumoxide::cdp::browser_protocol::page::PrintToPdfParams;
+use std::path::Path;
+
+/// Size of the paper to print a page on, in inches
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaperSize {
+    /// Width of the paper, or its height when printing in
+    /// [`landscape`](PdfOptions::landscape)
+    pub width: f64,
+    /// Height of the paper
+    pub height: f64,
+}
+
+impl PaperSize {
+    /// US Letter, 8.5 by 11 inches
+    pub const LETTER: PaperSize = PaperSize {
+        width: 8.5,
+        height: 11.0,
+    };
+    /// US Legal, 8.5 by 14 inches
+    pub const LEGAL: PaperSize = PaperSize {
+        width: 8.5,
+        height: 14.0,
+    };
+    /// A4, 210 by 297 millimetres
+    pub const A4: PaperSize = PaperSize {
+        width: 8.27,
+        height: 11.69,
+    };
+    /// A3, 297 by 420 millimetres
+    pub const A3: PaperSize = PaperSize {
+        width: 11.69,
+        height: 16.54,
+    };
+}
+
+/// Margins around each printed page, in inches
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Margins {
+    /// Margin at the top of each page
+    pub top: f64,
+    /// Margin on the right of each page
+    pub right: f64,
+    /// Margin at the bottom of each page
+    pub bottom: f64,
+    /// Margin on the left of each page
+    pub left: f64,
+}
+
+impl Margins {
+    /// The same margin on every side
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::Margins;
+    /// let margins = Margins::uniform(0.5);
+    /// assert_eq!(margins.left, 0.5);
+    /// ```
+    pub fn uniform(inches: f64) -> Self {
+        Self {
+            top: inches,
+            right: inches,
+            bottom: inches,
+            left: inches,
+        }
+    }
+}
+
+/// Configuration options for [`PageArchive::to_pdf`], controlling how the
+/// page is laid out on paper
+pub struct PdfOptions<'a> {
+    /// How the resources are embedded into the page before it's rendered
+    ///
+    /// Default: [`EmbedOptions::default`]
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{EmbedOptions, PdfOptions};
+    /// let options = PdfOptions {
+    ///     embed: EmbedOptions {
+    ///         skip_scripts: true,
+    ///         ..Default::default()
+    ///     },
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub embed: EmbedOptions<'a>,
+    /// Size of the paper. A page which sets its own with an `@page` rule
+    /// is still printed on this.
+    ///
+    /// Default: [`PaperSize::LETTER`]
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{PaperSize, PdfOptions};
+    /// let options = PdfOptions {
+    ///     paper: PaperSize::A4,
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub paper: PaperSize,
+    /// Margins around each page
+    ///
+    /// Default: 0.4 inches on every side
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{Margins, PdfOptions};
+    /// let options = PdfOptions {
+    ///     margins: Margins::uniform(1.0),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub margins: Margins,
+    /// Print the page sideways, with the paper's width and height swapped
+    ///
+    /// Default: `false`
+    pub landscape: bool,
+    /// Print the page's background colours and images, which browsers
+    /// usually leave out to save ink
+    ///
+    /// Default: `true`
+    pub background: bool,
+    /// Path of the Chrome or Chromium executable to run, or `None` to look
+    /// for one in the usual places
+    ///
+    /// Default: `None`
+    pub browser: Option<&'a Path>,
+}
+
+impl<'a> PdfOptions<'a> {
+    /// Embed the resources with the options, see
+    /// [`PdfOptions::embed`](#structfield.embed)
+    pub fn embed(mut self, options: EmbedOptions<'a>) -> Self {
+        self.embed = options;
+        self
+    }
+
+    /// Print on paper of the size, see
+    /// [`PdfOptions::paper`](#structfield.paper)
+    pub fn paper(mut self, paper: PaperSize) -> Self {
+        self.paper = paper;
+        self
+    }
+
+    /// Set the margins around each page, see
+    /// [`PdfOptions::margins`](#structfield.margins)
+    pub fn margins(mut self, margins: Margins) -> Self {
+        self.margins = margins;
+        self
+    }
+
+    /// Print the page sideways, see
+    /// [`PdfOptions::landscape`](#structfield.landscape)
+    pub fn landscape(mut self, landscape: bool) -> Self {
+        self.landscape = landscape;
+        self
+    }
+
+    /// Print the page's backgrounds, see
+    /// [`PdfOptions::background`](#structfield.background)
+    pub fn background(mut self, background: bool) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Run the browser at `path`, see
+    /// [`PdfOptions::browser`](#structfield.browser)
+    pub fn browser(mut self, path: &'a Path) -> Self {
+        self.browser = Some(path);
+        self
+    }
+}
+
+impl<'a> Default for PdfOptions<'a> {
+    fn default() -> Self {
+        Self {
+            embed: EmbedOptions::default(),
+            paper: PaperSize::LETTER,
+            margins: Margins::uniform(0.4),
+            landscape: false,
+            background: true,
+            browser: None,
+        }
+    }
+}
+
+/// Render the `archive`'s page with its resources embedded to a PDF, see
+/// [`PageArchive::to_pdf`]
+pub(crate) async fn to_pdf(
+    archive: &PageArchive,
+    options: &PdfOptions<'_>,
+) -> Result<Vec<u8>, Error> {
+    let content = archive.embed_resources_with(&options.embed);
+    let archive_options = ArchiveOptions::default();
+    let (browser, handling) = launch(&archive_options, options.browser).await?;
+    let printed = async {
+        let page = open(&browser, &archive_options).await?;
+        page.set_content(content).await.map_err(browser_error)?;
+        page.evaluate_expression(LOADED)
+            .await
+            .map_err(browser_error)?;
+        page.pdf(params(options)).await.map_err(browser_error)
+    }
+    .await;
+    close(browser, handling).await;
+    printed
+}
+
+/// How the browser is asked to print the page
+fn params(options: &PdfOptions<'_>) -> PrintToPdfParams {
+    let PdfOptions { paper, margins, .. } = options;
+    PrintToPdfParams::builder()
+        .paper_width(paper.width)
+        .paper_height(paper.height)
+        .margin_top(margins.top)
+        .margin_right(margins.right)
+        .margin_bottom(margins.bottom)
+        .margin_left(margins.left)
+        .landscape(options.landscape)
+        .print_background(options.background)
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_params() {
+        let options = PdfOptions::default()
+            .paper(PaperSize::A4)
+            .margins(Margins {
+                top: 1.0,
+                ..Margins::uniform(0.5)
+            })
+            .landscape(true);
+        let params = params(&options);
+        assert_eq!(params.paper_width, Some(8.27));
+        assert_eq!(params.paper_height, Some(11.69));
+        assert_eq!(params.margin_top, Some(1.0));
+        assert_eq!(params.margin_left, Some(0.5));
+        assert_eq!(params.landscape, Some(true));
+        assert_eq!(params.print_background, Some(true));
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for recording where and when an embedded page was archived
+//! from, see [`EmbedOptions::provenance`](crate::EmbedOptions::provenance)
+
+use crate::page_archive::PageArchive;
+use html5ever::{interface::QualName, local_name, namespace_url, ns};
+use kuchiki::{Attribute, ExpandedName, NodeRef};
+
+/// Class of the banner, for stylesheets to select it by
+pub(crate) const BANNER_CLASS: &str = "web-archive-banner";
+
+/// How an embedded page records where and when it was archived from, so
+/// that anyone viewing the file knows it's an archive, see
+/// [`EmbedOptions::provenance`](crate::EmbedOptions::provenance)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provenance<'a> {
+    /// An HTML comment and `<meta>` tags at the start of the `<head>`,
+    /// giving the page's URL as `web-archive:url`, when it was downloaded
+    /// as `web-archive:date`, and the version of this crate as
+    /// `web-archive:version`. These don't change how the page looks.
+    Metadata,
+    /// The metadata, along with a banner at the top of the page saying the
+    /// same thing, styled by the CSS. The banner is a `<div>` with the
+    /// `web-archive-banner` class, for the CSS to select it by.
+    Banner(&'a str),
+}
+
+impl Provenance<'static> {
+    /// A plain style for the banner: a thin strip of small text above the
+    /// page
+    pub const BANNER_CSS: &'static str = ".web-archive-banner { \
+        position: relative; z-index: 2147483647; margin: 0; \
+        padding: 4px 8px; border-bottom: 1px solid #d4c98f; \
+        background: #fff8d6; color: #333; \
+        font: 13px/1.4 sans-serif; text-align: left; } \
+        .web-archive-banner a { color: inherit; }";
+
+    /// A banner with the plain [`BANNER_CSS`](Provenance::BANNER_CSS) style
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{EmbedOptions, Provenance};
+    /// let options = EmbedOptions {
+    ///     provenance: Some(Provenance::banner()),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub fn banner() -> Self {
+        Provenance::Banner(Self::BANNER_CSS)
+    }
+}
+
+/// The nodes recording where the page came from: those to go at the start
+/// of its `<head>`, and the banner to go at the start of its `<body>` if
+/// there is one
+pub(crate) fn nodes(
+    archive: &PageArchive,
+    provenance: Provenance,
+) -> (Vec<NodeRef>, Option<NodeRef>) {
+    let date = archive
+        .meta
+        .get(&archive.url)
+        .map(|meta| httpdate::fmt_http_date(meta.fetched_at));
+    let version = env!("CARGO_PKG_VERSION");
+    let mut description = format!("Archived from {}", archive.url);
+    if let Some(date) = &date {
+        description.push_str(&format!(" on {}", date));
+    }
+    description.push_str(&format!(" by web-archive {}", version));
+
+    let mut head = vec![
+        NodeRef::new_comment(format!(" {} ", description)),
+        meta("web-archive:url", archive.url.as_str()),
+    ];
+    if let Some(date) = &date {
+        head.push(meta("web-archive:date", date));
+    }
+    head.push(meta("web-archive:version", version));
+
+    let css = match provenance {
+        Provenance::Metadata => return (head, None),
+        Provenance::Banner(css) => css,
+    };
+    let style = element(local_name!("style"), &[]);
+    style.append(NodeRef::new_text(css));
+    head.push(style);
+
+    let link = element(local_name!("a"), &[("href", archive.url.as_str())]);
+    link.append(NodeRef::new_text(archive.url.as_str()));
+    let banner = element(local_name!("div"), &[("class", BANNER_CLASS)]);
+    banner.append(NodeRef::new_text("Archived from "));
+    banner.append(link);
+    if let Some(date) = &date {
+        banner.append(NodeRef::new_text(format!(" on {}", date)));
+    }
+    (head, Some(banner))
+}
+
+/// Insert the nodes recording where the page came from into the parsed
+/// `document`
+pub(crate) fn insert(
+    document: &NodeRef,
+    archive: &PageArchive,
+    provenance: Provenance,
+) {
+    let (head_nodes, banner) = nodes(archive, provenance);
+    // The parser always adds a `<head>` if the page doesn't have one
+    if let Ok(head) = document.select_first("head") {
+        for node in head_nodes.into_iter().rev() {
+            head.as_node().prepend(node);
+        }
+    }
+    // A page of frames has no `<body>` to put the banner in
+    if let (Some(banner), Ok(body)) = (banner, document.select_first("body")) {
+        body.as_node().prepend(banner);
+    }
+}
+
+fn meta(name: &str, content: &str) -> NodeRef {
+    element(local_name!("meta"), &[("name", name), ("content", content)])
+}
+
+fn element(name: html5ever::LocalName, attributes: &[(&str, &str)]) -> NodeRef {
+    let attributes = attributes.iter().map(|(name, value)| {
+        (
+            ExpandedName::new(ns!(), *name),
+            Attribute {
+                prefix: None,
+                value: value.to_string(),
+            },
+        )
+    });
+    NodeRef::new_element(QualName::new(None, ns!(html), name), attributes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dom;
+    use crate::meta::ResourceMeta;
+    use kuchiki::traits::TendrilSink;
+    use reqwest::header::HeaderMap;
+    use reqwest::StatusCode;
+    use std::time::{Duration, UNIX_EPOCH};
+    use url::Url;
+
+    fn archive() -> PageArchive {
+        let url = Url::parse("http://example.com/a?b=<c>").unwrap();
+        let mut meta =
+            ResourceMeta::new(StatusCode::OK, &url, &HeaderMap::new());
+        meta.fetched_at = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let mut archive = PageArchive {
+            url: url.clone(),
+            content: "<p>Hi</p>".to_string(),
+            resource_map: Default::default(),
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: Default::default(),
+            meta: Default::default(),
+            stats: Default::default(),
+            screenshot: None,
+        };
+        archive.meta.insert(url, meta);
+        archive
+    }
+
+    #[test]
+    fn test_metadata() {
+        let archive = archive();
+        let document = kuchiki::parse_html().one("<title>A</title><p>Hi</p>");
+        insert(&document, &archive, Provenance::Metadata);
+        let html = dom::serialize(&document);
+        let version = env!("CARGO_PKG_VERSION");
+        assert!(html.starts_with(&format!(
+            "<html><head><!-- Archived from http://example.com/a?b=%3Cc%3E on \
+             Sun, 13 Sep 2020 12:26:40 GMT by web-archive {} -->\
+             <meta content=\"http://example.com/a?b=%3Cc%3E\" \
+             name=\"web-archive:url\">\
+             <meta content=\"Sun, 13 Sep 2020 12:26:40 GMT\" \
+             name=\"web-archive:date\">\
+             <meta content=\"{}\" name=\"web-archive:version\">\
+             <title>A</title></head><body><p>Hi</p>",
+            version, version
+        )));
+    }
+
+    #[test]
+    fn test_banner() {
+        let mut archive = archive();
+        archive.meta.clear();
+        let document = kuchiki::parse_html().one("<p>Hi</p>");
+        insert(
+            &document,
+            &archive,
+            Provenance::Banner("div { color: red }"),
+        );
+        let html = dom::serialize(&document);
+        assert!(!html.contains("web-archive:date"));
+        assert!(html.contains("<style>div { color: red }</style></head>"));
+        assert!(html.contains(
+            "<body><div class=\"web-archive-banner\">Archived from \
+             <a href=\"http://example.com/a?b=%3Cc%3E\">\
+             http://example.com/a?b=%3Cc%3E</a></div><p>Hi</p>"
+        ));
+
+        // No body to put the banner in
+        let document = kuchiki::parse_html()
+            .one("<frameset><frame src=\"a.html\"></frameset>");
+        insert(&document, &archive, Provenance::banner());
+        let html = dom::serialize(&document);
+        assert!(html.contains("name=\"web-archive:url\""));
+        assert!(!html.contains("<div"));
+    }
+}
@@ -0,0 +1,721 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for recording what a browser fetches through a proxy, see
+//! [`RecordingProxy`]
+
+use crate::error::Error;
+use crate::failure::FailureReason;
+use crate::meta::ResourceMeta;
+use crate::replay::{replay, Record, Records};
+use crate::site::SiteArchive;
+use crate::ArchiveOptions;
+use bytes::Bytes;
+use hyper::header::{HeaderMap, CONTENT_TYPE, LOCATION};
+use hyper::server::conn::Http;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode, Uri};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType,
+};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use time::{Duration, OffsetDateTime};
+use tokio_rustls::rustls::{self, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use url::Url;
+
+/// Common name of the certificate authorities that [`RecordingProxy::new`]
+/// generates
+const CA_NAME: &str = "web-archive recording proxy";
+
+/// Headers which only apply to one connection, so aren't passed on
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Request headers which aren't passed on, so that servers send whole,
+/// uncompressed responses which can be recorded, rather than a part of
+/// one or word that it hasn't changed. `Host` and `Content-Length` are set
+/// again for the request that's sent on.
+const NOT_FORWARDED: &[&str] = &[
+    "accept-encoding",
+    "content-length",
+    "host",
+    "if-modified-since",
+    "if-none-match",
+    "if-range",
+    "range",
+];
+
+/// An HTTP proxy which records the responses to the requests passing
+/// through it, so that a site can be archived by browsing it in a real
+/// browser, e.g. to get past a login form or a challenge that
+/// [`archive`](crate::archive) can't.
+///
+/// Point the browser's HTTP and HTTPS proxy settings at the address given
+/// to [`run`](RecordingProxy::run), and browse the pages to archive. HTTPS
+/// is intercepted by presenting the browser with a certificate for each
+/// host, signed by the proxy's own certificate authority, which the
+/// browser has to be told to trust: see
+/// [`ca_certificate`](RecordingProxy::ca_certificate). Trusting it lets
+/// the proxy read everything sent over HTTPS, so it's best done in a
+/// separate browser profile which is only used for recording.
+///
+/// The responses to `GET` requests are recorded, with a later response
+/// for a URL replacing an earlier one unless it was unsuccessful and the
+/// earlier one wasn't, so that e.g. a page which redirected to a login
+/// form is recorded as it was after logging in. Each HTML document that
+/// the browser navigated to, rather than fetched from a script, becomes a
+/// page of the [`archive`](RecordingProxy::archive). Servers are asked for
+/// whole, uncompressed responses, but anything that the browser had
+/// cached before recording started won't be requested, so a fresh profile
+/// or disabling its cache is best.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use web_archive::RecordingProxy;
+///
+/// # async fn record() {
+/// let proxy = RecordingProxy::new().unwrap();
+/// std::fs::write("proxy-ca.pem", proxy.ca_certificate()).unwrap();
+///
+/// // Browse through 127.0.0.1:8080 for ten minutes
+/// let addr = "127.0.0.1:8080".parse().unwrap();
+/// let stop = tokio::time::sleep(Duration::from_secs(600));
+/// proxy.run(addr, stop).await.unwrap();
+///
+/// let site = proxy.archive(Default::default());
+/// site.write_to_disk(&"site").unwrap();
+/// # }
+/// ```
+pub struct RecordingProxy {
+    inner: Arc<Inner>,
+}
+
+/// What the proxy's connections share
+struct Inner {
+    /// The certificate authority which signs the certificates for hosts
+    ca: Certificate,
+    /// `ca` in PEM format, as it was given or generated
+    ca_pem: String,
+    /// Client for sending requests on, which leaves redirects and cookies
+    /// to the browser
+    client: reqwest::Client,
+    /// The TLS configuration for each host, with a certificate for it
+    configs: Mutex<HashMap<String, Arc<ServerConfig>>>,
+    recording: Mutex<Recording>,
+}
+
+/// The responses recorded so far
+struct Recording {
+    records: Records,
+    /// URLs of the pages that the browser navigated to, in order
+    pages: Vec<Url>,
+    /// URLs of all of the responses, in the order they were first recorded
+    urls: Vec<Url>,
+}
+
+impl RecordingProxy {
+    /// A proxy with a certificate authority generated for it, which the
+    /// browser has to trust for HTTPS to be recorded
+    pub fn new() -> Result<Self, Error> {
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, CA_NAME);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        let ca = Certificate::from_params(params).map_err(certificate_error)?;
+        let ca_pem = ca.serialize_pem().map_err(certificate_error)?;
+        Self::with(ca, ca_pem)
+    }
+
+    /// A proxy using the certificate authority with the PEM `certificate`
+    /// and `key` given, e.g. one which a previous proxy generated and the
+    /// browser has been told to trust already, see
+    /// [`ca_key`](RecordingProxy::ca_key)
+    ///
+    /// Fails with [`Error::ParseError`] if they can't be read.
+    pub fn with_ca(certificate: &str, key: &str) -> Result<Self, Error> {
+        let invalid = |e: rcgen::Error| Error::ParseError(e.to_string());
+        let key = KeyPair::from_pem(key).map_err(invalid)?;
+        let params = CertificateParams::from_ca_cert_pem(certificate, key)
+            .map_err(invalid)?;
+        let ca = Certificate::from_params(params).map_err(invalid)?;
+        Self::with(ca, certificate.to_string())
+    }
+
+    fn with(ca: Certificate, ca_pem: String) -> Result<Self, Error> {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let inner = Inner {
+            ca,
+            ca_pem,
+            client,
+            configs: Mutex::new(HashMap::new()),
+            recording: Mutex::new(Recording {
+                records: Records::new(FailureReason::NotRecorded),
+                pages: Vec::new(),
+                urls: Vec::new(),
+            }),
+        };
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// The certificate of the proxy's certificate authority in PEM format,
+    /// for adding to the certificates that the browser trusts
+    pub fn ca_certificate(&self) -> &str {
+        &self.inner.ca_pem
+    }
+
+    /// The private key of the proxy's certificate authority in PEM
+    /// format, for keeping alongside the
+    /// [certificate](RecordingProxy::ca_certificate) to use it again with
+    /// [`RecordingProxy::with_ca`]
+    pub fn ca_key(&self) -> String {
+        self.inner.ca.serialize_private_key_pem()
+    }
+
+    /// Listen for requests at `addr`, recording the responses to them
+    /// until `until` completes. It can be run again afterwards to record
+    /// more.
+    ///
+    /// Fails with [`Error::Serve`] if `addr` can't be listened on.
+    pub async fn run<F>(&self, addr: SocketAddr, until: F) -> Result<(), Error>
+    where
+        F: Future<Output = ()>,
+    {
+        let inner = self.inner.clone();
+        let make_service = make_service_fn(move |_| {
+            let inner = inner.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(
+                    move |request: Request<Body>| {
+                        let inner = inner.clone();
+                        async move {
+                            Ok::<_, Infallible>(inner.handle(request).await)
+                        }
+                    },
+                ))
+            }
+        });
+        Server::try_bind(&addr)
+            .map_err(|e| Error::Serve(e.to_string()))?
+            .serve(make_service)
+            .with_graceful_shutdown(until)
+            .await
+            .map_err(|e| Error::Serve(e.to_string()))
+    }
+
+    /// The URLs of the pages that the browser has navigated to so far, in
+    /// the order that it first did
+    pub fn pages(&self) -> Vec<Url> {
+        self.inner.recording.lock().unwrap().pages.clone()
+    }
+
+    /// Archive the pages that the browser has navigated to so far from the
+    /// recorded responses, finding their resources in the same way as
+    /// [`archive`](crate::archive) following the `options`. Nothing is
+    /// requested from the network: resources which weren't recorded are
+    /// reported in each page's [`failures`](crate::PageArchive::failures)
+    /// as [`FailureReason::NotRecorded`], and left pointing at their
+    /// original URLs.
+    pub fn archive(&self, options: ArchiveOptions<'_>) -> SiteArchive {
+        let recording = self.inner.recording.lock().unwrap();
+        let mut site = SiteArchive::new();
+        for url in &recording.pages {
+            let archived = replay(
+                &recording.records,
+                url.clone(),
+                &options,
+                Error::NotRecorded,
+            );
+            match archived {
+                Ok(archive) => site.add_archived(url.clone(), archive),
+                Err(e) => {
+                    site.failed(url.clone(), FailureReason::from_error(e))
+                }
+            }
+        }
+        site
+    }
+
+    /// Write all of the responses recorded so far out as a WARC file, in
+    /// the order they were recorded, which can be read back with
+    /// [`read_warc`](crate::read_warc)
+    #[cfg(feature = "warc")]
+    pub fn write_warc<W: std::io::Write>(
+        &self,
+        mut writer: W,
+    ) -> Result<(), std::io::Error> {
+        let recording = self.inner.recording.lock().unwrap();
+        let warc =
+            crate::warc::write_recorded(&recording.records, &recording.urls);
+        writer.write_all(&warc)
+    }
+}
+
+impl Inner {
+    /// The response to a request made to the proxy
+    async fn handle(self: Arc<Self>, request: Request<Body>) -> Response<Body> {
+        if request.method() == Method::CONNECT {
+            self.connect(request)
+        } else {
+            self.respond(request, None).await
+        }
+    }
+
+    /// Accept a request to open a tunnel to an HTTPS server, and read the
+    /// requests sent through it by posing as the server
+    fn connect(self: Arc<Self>, request: Request<Body>) -> Response<Body> {
+        let origin = request
+            .uri()
+            .authority()
+            .and_then(|authority| {
+                Url::parse(&format!("https://{}", authority)).ok()
+            })
+            .filter(|origin| origin.host_str().is_some());
+        let origin = match origin {
+            Some(origin) => origin,
+            None => {
+                return error(StatusCode::BAD_REQUEST, "no host to connect to")
+            }
+        };
+        let config = match self.config(origin.host_str().unwrap_or("")) {
+            Ok(config) => config,
+            Err(e) => {
+                return error(StatusCode::BAD_GATEWAY, &format!("{:?}", e))
+            }
+        };
+        tokio::spawn(async move {
+            let upgraded = match hyper::upgrade::on(request).await {
+                Ok(upgraded) => upgraded,
+                Err(_) => return,
+            };
+            let stream = match TlsAcceptor::from(config).accept(upgraded).await
+            {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            let service = service_fn(move |request| {
+                let inner = self.clone();
+                let origin = origin.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        inner.respond(request, Some(origin)).await,
+                    )
+                }
+            });
+            // The browser gave up on the connection, or the proxy is
+            // stopping, so there's no one to tell
+            let _ = Http::new()
+                .http1_only(true)
+                .serve_connection(stream, service)
+                .await;
+        });
+        Response::new(Body::empty())
+    }
+
+    /// The response to a request for a URL at `origin`, if it came through
+    /// a tunnel to it, or for the URL it gives otherwise
+    async fn respond(
+        &self,
+        request: Request<Body>,
+        origin: Option<Url>,
+    ) -> Response<Body> {
+        let url = match target(request.uri(), origin.as_ref()) {
+            Some(url) => url,
+            None => {
+                return error(StatusCode::BAD_REQUEST, "not a proxy request")
+            }
+        };
+        match self.forward(request, url).await {
+            Ok(response) => response,
+            Err(e) => error(StatusCode::BAD_GATEWAY, &format!("{:?}", e)),
+        }
+    }
+
+    /// Send the request on to `url`, recording the response before
+    /// passing it back
+    async fn forward(
+        &self,
+        request: Request<Body>,
+        url: Url,
+    ) -> Result<Response<Body>, Error> {
+        let (parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body)
+            .await
+            .map_err(|e| Error::Serve(e.to_string()))?;
+        let mut headers = parts.headers.clone();
+        remove(&mut headers, HOP_BY_HOP);
+        remove(&mut headers, NOT_FORWARDED);
+        let response = self
+            .client
+            .request(parts.method.clone(), url.clone())
+            .headers(headers)
+            .body(body)
+            .send()
+            .await?;
+        let status = response.status();
+        let mut headers = response.headers().clone();
+        let data = response.bytes().await?;
+        self.record(
+            &parts.method,
+            &parts.headers,
+            url,
+            status,
+            &headers,
+            &data,
+        );
+
+        remove(&mut headers, HOP_BY_HOP);
+        let mut response = Response::new(Body::from(data));
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+        Ok(response)
+    }
+
+    /// Record the response to a request for `url`, if it was a `GET`
+    /// request with a whole response
+    fn record(
+        &self,
+        method: &Method,
+        request_headers: &HeaderMap,
+        url: Url,
+        status: StatusCode,
+        headers: &HeaderMap,
+        data: &Bytes,
+    ) {
+        let partial = status == StatusCode::PARTIAL_CONTENT
+            || status == StatusCode::NOT_MODIFIED;
+        if method != Method::GET || partial {
+            return;
+        }
+        let header = |headers: &HeaderMap, name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let is_html = header(headers, CONTENT_TYPE.as_str())
+            .map_or(false, |t| t.trim_start().starts_with("text/html"));
+        // Browsers say where a response will be used, and pages are the
+        // ones navigated to. Those which don't say are taken at their word
+        // that HTML is a page.
+        let navigated = header(request_headers, "sec-fetch-dest")
+            .map_or(true, |dest| dest == "document");
+        let record = Record {
+            status,
+            location: header(headers, LOCATION.as_str()),
+            meta: ResourceMeta::new(status, &url, headers),
+            data: data.clone(),
+        };
+        let is_page = status.is_success() && is_html && navigated;
+        self.recording.lock().unwrap().add(url, record, is_page);
+    }
+
+    /// The TLS configuration for posing as `host`, with a certificate for
+    /// it signed by the certificate authority
+    fn config(&self, host: &str) -> Result<Arc<ServerConfig>, Error> {
+        let mut configs = self.configs.lock().unwrap();
+        if let Some(config) = configs.get(host) {
+            return Ok(config.clone());
+        }
+        let name = host.trim_start_matches('[').trim_end_matches(']');
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.subject_alt_names = vec![match name.parse::<IpAddr>() {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(name.to_string()),
+        }];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        // Browsers refuse certificates which are valid for too long
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - Duration::days(1);
+        params.not_after = now + Duration::days(365);
+        let certificate =
+            Certificate::from_params(params).map_err(certificate_error)?;
+        let der = certificate
+            .serialize_der_with_signer(&self.ca)
+            .map_err(certificate_error)?;
+        let key = certificate.serialize_private_key_der();
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(der)],
+                rustls::PrivateKey(key),
+            )
+            .map_err(|e| Error::Serve(e.to_string()))?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let config = Arc::new(config);
+        configs.insert(host.to_string(), config.clone());
+        Ok(config)
+    }
+}
+
+impl Recording {
+    /// Add the response for `url`, replacing the one recorded before
+    /// unless that was successful and this isn't
+    fn add(&mut self, url: Url, record: Record, is_page: bool) {
+        match self.records.responses.get(&url) {
+            Some(earlier)
+                if earlier.status.is_success()
+                    && !record.status.is_success() =>
+            {
+                return
+            }
+            Some(_) => {
+                self.records.responses.remove(&url);
+            }
+            None => self.urls.push(url.clone()),
+        }
+        if is_page && !self.pages.contains(&url) {
+            self.pages.push(url.clone());
+        }
+        self.records.insert(url, record);
+    }
+}
+
+/// The URL that a request is for: a path at `origin` for those made
+/// through a tunnel to it, or the whole URL for those made to the proxy
+fn target(uri: &Uri, origin: Option<&Url>) -> Option<Url> {
+    match origin {
+        Some(origin) => {
+            let mut url = origin.clone();
+            url.set_path(uri.path());
+            url.set_query(uri.query());
+            Some(url)
+        }
+        None => match uri.scheme_str() {
+            Some("http") | Some("https") => Url::parse(&uri.to_string()).ok(),
+            _ => None,
+        },
+    }
+}
+
+fn remove(headers: &mut HeaderMap, names: &[&str]) {
+    for name in names {
+        headers.remove(*name);
+    }
+}
+
+fn certificate_error(e: rcgen::Error) -> Error {
+    Error::Serve(format!("couldn't make a certificate: {}", e))
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(message.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "text/plain; charset=utf-8".parse().unwrap());
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::Resource;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    /// Record a response to a `GET` request for `url`
+    fn record(
+        proxy: &RecordingProxy,
+        dest: &str,
+        url: &str,
+        status: u16,
+        response_headers: &[(&'static str, &str)],
+        data: &'static str,
+    ) {
+        proxy.inner.record(
+            &Method::GET,
+            &headers(&[("sec-fetch-dest", dest)]),
+            Url::parse(url).unwrap(),
+            StatusCode::from_u16(status).unwrap(),
+            &headers(response_headers),
+            &Bytes::from(data),
+        );
+    }
+
+    fn recorded() -> RecordingProxy {
+        let proxy = RecordingProxy::new().unwrap();
+        let html = [("content-type", "text/html")];
+        let login = [("location", "/login")];
+        record(&proxy, "document", "https://example.com/", 302, &login, "");
+        record(
+            &proxy,
+            "document",
+            "https://example.com/login",
+            200,
+            &html,
+            "",
+        );
+        record(
+            &proxy,
+            "document",
+            "https://example.com/",
+            200,
+            &html,
+            "<link rel=\"stylesheet\" href=\"/style.css\">\
+                <img src=\"/missing.png\">",
+        );
+        let css = [("content-type", "text/css")];
+        let style = "body { color: red }";
+        record(
+            &proxy,
+            "style",
+            "https://example.com/style.css",
+            200,
+            &css,
+            style,
+        );
+        record(
+            &proxy,
+            "style",
+            "https://example.com/style.css",
+            500,
+            &[],
+            "",
+        );
+        record(&proxy, "empty", "https://example.com/data", 200, &html, "");
+        proxy
+    }
+
+    #[test]
+    fn test_record() {
+        let proxy = recorded();
+        let page = Url::parse("https://example.com/").unwrap();
+        let login = Url::parse("https://example.com/login").unwrap();
+        assert_eq!(proxy.pages(), vec![login, page.clone()]);
+
+        let site = proxy.archive(Default::default());
+        assert_eq!(site.len(), 2);
+        let archive = site.page(&page).unwrap();
+        let style = Url::parse("https://example.com/style.css").unwrap();
+        match &archive.resource_map[&style] {
+            Resource::Css(css) => assert_eq!(css.text(), "body { color: red }"),
+            _ => panic!("style.css isn't CSS"),
+        }
+        assert_eq!(archive.failures.len(), 1);
+        assert_eq!(archive.failures[0].reason, FailureReason::NotRecorded);
+    }
+
+    #[test]
+    fn test_not_recorded() {
+        let proxy = RecordingProxy::new().unwrap();
+        let url = Url::parse("http://example.com/").unwrap();
+        let status = StatusCode::OK;
+        let html = headers(&[("content-type", "text/html")]);
+        let data = Bytes::from("");
+        let no_headers = HeaderMap::new();
+        proxy.inner.record(
+            &Method::POST,
+            &no_headers,
+            url.clone(),
+            status,
+            &html,
+            &data,
+        );
+        let partial = StatusCode::PARTIAL_CONTENT;
+        proxy.inner.record(
+            &Method::GET,
+            &no_headers,
+            url.clone(),
+            partial,
+            &html,
+            &data,
+        );
+        assert!(proxy.pages().is_empty());
+        proxy.inner.record(
+            &Method::GET,
+            &no_headers,
+            url.clone(),
+            status,
+            &html,
+            &data,
+        );
+        assert_eq!(proxy.pages(), vec![url]);
+    }
+
+    #[test]
+    fn test_target() {
+        let target = |uri: &str, origin: Option<&str>| {
+            let origin = origin.map(|origin| Url::parse(origin).unwrap());
+            let uri = uri.parse::<Uri>().unwrap();
+            target(&uri, origin.as_ref()).map(|url| url.to_string())
+        };
+        assert_eq!(
+            target("http://example.com/a?b=c", None).as_deref(),
+            Some("http://example.com/a?b=c")
+        );
+        assert_eq!(
+            target("/a?b=c", Some("https://example.com:8443")).as_deref(),
+            Some("https://example.com:8443/a?b=c")
+        );
+        assert_eq!(target("/a", None), None);
+    }
+
+    #[test]
+    fn test_certificates() {
+        let proxy = RecordingProxy::new().unwrap();
+        let config = proxy.inner.config("example.com").unwrap();
+        let again = proxy.inner.config("example.com").unwrap();
+        assert!(Arc::ptr_eq(&config, &again));
+        assert!(proxy.inner.config("[::1]").is_ok());
+
+        let key = proxy.ca_key();
+        let reused =
+            RecordingProxy::with_ca(proxy.ca_certificate(), &key).unwrap();
+        assert_eq!(reused.ca_certificate(), proxy.ca_certificate());
+        assert!(reused.inner.config("example.com").is_ok());
+        assert!(RecordingProxy::with_ca("nonsense", "nonsense").is_err());
+    }
+
+    #[cfg(feature = "warc")]
+    #[test]
+    fn test_write_warc() {
+        let proxy = recorded();
+        let mut warc = Vec::new();
+        proxy.write_warc(&mut warc).unwrap();
+        let archive = crate::read_warc(
+            &warc[..],
+            Some("https://example.com/"),
+            Default::default(),
+        )
+        .unwrap();
+        let style = Url::parse("https://example.com/style.css").unwrap();
+        assert!(archive.resource_map.contains_key(&style));
+    }
+}
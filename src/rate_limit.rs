@@ -0,0 +1,121 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for limiting how often requests are made
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A token bucket limiting how many requests are made, see
+/// [`ArchiveOptions::rate_limit`](crate::ArchiveOptions::rate_limit) and
+/// [`ArchiveOptions::host_rate_limit`](crate::ArchiveOptions::host_rate_limit).
+///
+/// The bucket holds up to `requests` tokens, which are used up by each
+/// request and refilled evenly over `per`, so bursts of up to `requests`
+/// are allowed but no more than that are made in any period of `per`.
+///
+/// Clones share the same state, so archives and [`Archiver`]s using
+/// clones of a limit share its allowance.
+///
+/// [`Archiver`]: crate::Archiver
+///
+/// ## Example
+///
+/// ```
+/// use std::time::Duration;
+/// use web_archive::{ArchiveOptions, RateLimit};
+///
+/// let options = ArchiveOptions::default()
+///     .rate_limit(RateLimit::per_second(10))
+///     .host_rate_limit(RateLimit::new(30, Duration::from_secs(60)));
+/// ```
+#[derive(Clone, Debug)]
+pub struct RateLimit(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    /// How long it takes for a token to be refilled
+    interval: Duration,
+    /// How far ahead of time requests may be made, from the size of the
+    /// bucket
+    tolerance: Duration,
+    /// When the bucket for each key will next be full, in the future if
+    /// any tokens have been used up
+    full_at: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimit {
+    /// Allow up to `requests` requests in any period of `per`. A value of
+    /// `0` is treated as `1`.
+    pub fn new(requests: u32, per: Duration) -> Self {
+        let requests = requests.max(1);
+        let interval = per / requests;
+        Self(Arc::new(Inner {
+            interval,
+            tolerance: interval * (requests - 1),
+            full_at: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Allow up to `requests` requests a second
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(1))
+    }
+
+    /// Take a token from the bucket for `key`, returning how long to wait
+    /// before making the request
+    pub(crate) fn reserve(&self, key: &str) -> Duration {
+        self.reserve_at(key, Instant::now())
+    }
+
+    fn reserve_at(&self, key: &str, now: Instant) -> Duration {
+        let mut full_at = self.0.full_at.lock().unwrap();
+        let full_at = full_at.entry(key.to_string()).or_insert(now);
+        let start = (*full_at).max(now);
+        *full_at = start + self.0.interval;
+        start
+            .saturating_duration_since(now)
+            .saturating_sub(self.0.tolerance)
+    }
+}
+
+/// Wait for the delay from a rate limit. The timer is only needed if
+/// there's a rate limit, so that archiving doesn't require it otherwise.
+pub(crate) async fn wait(delay: Duration) {
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        let limit = RateLimit::new(2, Duration::from_secs(1));
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        // The first two are a burst, then one every half a second
+        assert_eq!(limit.reserve_at("a", now), ms(0));
+        assert_eq!(limit.reserve_at("a", now), ms(0));
+        assert_eq!(limit.reserve_at("a", now), ms(500));
+        assert_eq!(limit.reserve_at("a", now), ms(1000));
+        // Other keys have buckets of their own
+        assert_eq!(limit.reserve_at("b", now), ms(0));
+        // The bucket refills over time
+        let later = now + ms(3000);
+        assert_eq!(limit.reserve_at("a", later), ms(0));
+        assert_eq!(limit.reserve_at("a", later), ms(0));
+        assert_eq!(limit.reserve_at("a", later), ms(500));
+
+        // Clones share the buckets
+        let clone = limit.clone();
+        assert_eq!(clone.reserve_at("a", later), ms(1000));
+    }
+}
@@ -0,0 +1,625 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for extracting the main article from a page, in the manner of
+//! Readability, see [`PageArchive::reader_mode`]
+
+use crate::dom;
+use crate::metadata::{self, collapse, meta_content};
+use crate::page_archive::PageArchive;
+use crate::parsing::{base_url, is_srcset_attribute, parse_srcset};
+use html5ever::{interface::QualName, local_name, namespace_url, ns};
+use kuchiki::iter::NodeIterator;
+use kuchiki::traits::TendrilSink;
+use kuchiki::{parse_html, Attribute, ExpandedName, NodeRef};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use url::Url;
+
+/// The main article of a page, extracted by [`PageArchive::reader_mode`]
+#[derive(Debug)]
+pub struct Article {
+    /// The article's title, from its `og:title`, the page's `<title>`
+    /// without the name of the site, or its first heading
+    pub title: Option<String>,
+    /// Who wrote the article, from its `author` metadata or byline
+    pub byline: Option<String>,
+    /// A page holding just the title, byline, and content of the article,
+    /// with only the resources that the content uses. Embed them or write
+    /// it to disk in the same way as any other archive.
+    pub archive: PageArchive,
+}
+
+/// Elements which are never part of an article
+const REMOVED: &[&str] = &[
+    "aside", "button", "canvas", "dialog", "embed", "footer", "form", "header",
+    "iframe", "input", "link", "meta", "nav", "noscript", "object", "script",
+    "select", "style", "template", "textarea",
+];
+
+/// Words in the classes and ids of elements which aren't likely to be
+/// part of an article, such as comments and sidebars
+const UNLIKELY: &[&str] = &[
+    "ad-break",
+    "advert",
+    "agegate",
+    "banner",
+    "breadcrumb",
+    "comment",
+    "cookie",
+    "disqus",
+    "footer",
+    "masthead",
+    "menu",
+    "modal",
+    "newsletter",
+    "pagination",
+    "popup",
+    "promo",
+    "related",
+    "share",
+    "sidebar",
+    "social",
+    "sponsor",
+    "subscribe",
+    "widget",
+];
+
+/// Words in the classes and ids of elements which are likely to hold an
+/// article, outweighing any [`UNLIKELY`] words
+const LIKELY: &[&str] = &[
+    "article", "blog", "body", "column", "content", "entry", "main", "post",
+    "story", "text",
+];
+
+/// Elements which make a `<div>` more than a paragraph of its own
+const BLOCKS: &[&str] = &[
+    "article",
+    "blockquote",
+    "div",
+    "dl",
+    "figure",
+    "img",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+/// Extract the main article from the archive's page
+pub(crate) fn extract(archive: &PageArchive) -> Article {
+    let document = parse_html().one(archive.content.as_str());
+    let base = base_url(&archive.url, &document);
+    let title = title(&document);
+    let byline = byline(&document);
+    remove_unlikely(&document);
+    let body = match document.select_first("body") {
+        Ok(body) => body.as_node().clone(),
+        Err(()) => document.clone(),
+    };
+    let contents = article_contents(&body);
+
+    let page = parse_html().one(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title></title>\
+        </head><body><article></article></body></html>",
+    );
+    let article = page.select_first("article").unwrap().as_node().clone();
+    if let Some(title) = &title {
+        let head = page.select_first("title").unwrap();
+        head.as_node().append(NodeRef::new_text(title.as_str()));
+        article.append(element(local_name!("h1"), None, title));
+    }
+    if let Some(byline) = &byline {
+        article.append(element(local_name!("p"), Some("byline"), byline));
+    }
+    for node in contents {
+        article.append(node);
+    }
+    // The article has a heading of its own with the title, so drop the
+    // page's
+    let headings: Vec<_> = article
+        .descendants()
+        .elements()
+        .filter(|e| matches!(&*e.name.local, "h1" | "h2"))
+        .skip(usize::from(title.is_some()))
+        .filter(|e| Some(collapse(&e.text_contents())) == title)
+        .collect();
+    for heading in headings {
+        heading.as_node().detach();
+    }
+    clean(&article, &base, &archive.lazy_attributes);
+
+    let used = used_urls(&article, &base);
+    Article {
+        title,
+        byline,
+        archive: PageArchive {
+            url: archive.url.clone(),
+            content: dom::serialize(&page),
+            resource_map: archive
+                .resource_map
+                .iter()
+                .filter(|(url, _)| used.contains(*url))
+                .map(|(url, resource)| (url.clone(), resource.clone()))
+                .collect(),
+            lazy_attributes: archive.lazy_attributes.clone(),
+            url_attributes: archive.url_attributes.clone(),
+            timed_out: archive
+                .timed_out
+                .iter()
+                .filter(|url| used.contains(*url))
+                .cloned()
+                .collect(),
+            failures: archive
+                .failures
+                .iter()
+                .filter(|failure| used.contains(&failure.url))
+                .cloned()
+                .collect(),
+            cancelled: archive.cancelled,
+            redirects: archive
+                .redirects
+                .iter()
+                .filter(|(url, _)| **url == archive.url || used.contains(*url))
+                .map(|(url, redirects)| (url.clone(), redirects.clone()))
+                .collect(),
+            meta: archive
+                .meta
+                .iter()
+                .filter(|(url, _)| **url == archive.url || used.contains(*url))
+                .map(|(url, meta)| (url.clone(), meta.clone()))
+                .collect(),
+            stats: archive.stats.clone(),
+            screenshot: None,
+        },
+    }
+}
+
+/// A new HTML element holding `text`
+fn element(
+    name: html5ever::LocalName,
+    class: Option<&str>,
+    text: &str,
+) -> NodeRef {
+    let class = class.map(|class| {
+        (
+            ExpandedName::new(ns!(), local_name!("class")),
+            Attribute {
+                prefix: None,
+                value: class.to_string(),
+            },
+        )
+    });
+    let element =
+        NodeRef::new_element(QualName::new(None, ns!(html), name), class);
+    element.append(NodeRef::new_text(text));
+    element
+}
+
+/// The title of the article
+fn title(document: &NodeRef) -> Option<String> {
+    if let Some(title) = meta_content(document, "meta[property=\"og:title\"]") {
+        return Some(title);
+    }
+    if let Some(title) = metadata::title(document) {
+        // Pages tend to put the name of the site after the title, e.g.
+        // "Title | Site", so leave it off unless that leaves too little
+        let split = [" | ", " - ", " \u{2013} ", " \u{2014} ", " \u{b7} "]
+            .iter()
+            .filter_map(|separator| title.rfind(separator))
+            .max();
+        return match split {
+            Some(split) if title[..split].split_whitespace().count() >= 3 => {
+                Some(title[..split].to_string())
+            }
+            _ => Some(title),
+        };
+    }
+    document
+        .select_first("h1")
+        .map(|h1| collapse(&h1.text_contents()))
+        .ok()
+        .filter(|title| !title.is_empty())
+}
+
+/// The byline of the article. An element in the page holding it is
+/// removed, since the article is given a byline of its own.
+fn byline(document: &NodeRef) -> Option<String> {
+    if let Some(author) = meta_content(document, "meta[name=\"author\"]") {
+        return Some(author);
+    }
+    let selector =
+        "[rel~=\"author\"], [itemprop~=\"author\"], .byline, .author";
+    for element in document.select(selector).unwrap() {
+        let text = collapse(&element.text_contents());
+        if !text.is_empty() && text.chars().count() <= 100 {
+            element.as_node().detach();
+            return Some(text);
+        }
+    }
+    None
+}
+
+/// The classes and id of an element, in lower case
+fn class_and_id(node: &NodeRef) -> String {
+    let element = match node.as_element() {
+        Some(element) => element,
+        None => return String::new(),
+    };
+    let attributes = element.attributes.borrow();
+    let class = attributes.get("class").unwrap_or("");
+    let id = attributes.get("id").unwrap_or("");
+    format!("{} {}", class, id).to_lowercase()
+}
+
+/// Remove the elements which can't be part of the article: scripts,
+/// forms, navigation, hidden elements, and those whose classes or ids
+/// mark them as comments, sidebars, and the like
+fn remove_unlikely(document: &NodeRef) {
+    let unlikely: Vec<_> = document
+        .descendants()
+        .elements()
+        .filter(|element| {
+            let name = &*element.name.local;
+            if REMOVED.contains(&name) {
+                return true;
+            }
+            let attributes = element.attributes.borrow();
+            let style = attributes.get("style").unwrap_or("");
+            let style: String =
+                style.chars().filter(|c| !c.is_whitespace()).collect();
+            if attributes.contains("hidden")
+                || attributes.get("aria-hidden") == Some("true")
+                || style.contains("display:none")
+            {
+                return true;
+            }
+            if matches!(name, "html" | "body" | "article" | "main") {
+                return false;
+            }
+            let class_and_id = class_and_id(element.as_node());
+            UNLIKELY.iter().any(|word| class_and_id.contains(word))
+                && !LIKELY.iter().any(|word| class_and_id.contains(word))
+        })
+        .collect();
+    for element in unlikely {
+        element.as_node().detach();
+    }
+}
+
+/// The length of the text within a node, with its whitespace collapsed
+fn text_length(node: &NodeRef) -> usize {
+    node.text_contents()
+        .split_whitespace()
+        .map(|word| word.chars().count() + 1)
+        .sum()
+}
+
+/// How much of the text within a node is the text of links
+fn link_density(node: &NodeRef) -> f64 {
+    let length = text_length(node);
+    if length == 0 {
+        return 0.0;
+    }
+    let links: usize = node
+        .descendants()
+        .elements()
+        .filter(|element| &*element.name.local == "a")
+        .map(|link| text_length(link.as_node()))
+        .sum();
+    links as f64 / length as f64
+}
+
+/// The score that an element starts with, before the paragraphs in it
+/// are counted, from its name and its classes and id
+fn initial_score(node: &NodeRef) -> f64 {
+    let name = match node.as_element() {
+        Some(element) => element.name.local.to_string(),
+        None => return 0.0,
+    };
+    let mut score = match name.as_str() {
+        "article" => 10.0,
+        "div" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+    let class_and_id = class_and_id(node);
+    if UNLIKELY.iter().any(|word| class_and_id.contains(word)) {
+        score -= 25.0;
+    }
+    if LIKELY.iter().any(|word| class_and_id.contains(word)) {
+        score += 25.0;
+    }
+    score
+}
+
+/// Whether an element is a paragraph of text, including `<div>`s which
+/// only hold text and inline elements
+fn is_paragraph(node: &NodeRef) -> bool {
+    match node.as_element().map(|e| &*e.name.local) {
+        Some("p") | Some("pre") | Some("td") => true,
+        Some("div") => !node
+            .children()
+            .elements()
+            .any(|child| BLOCKS.contains(&&*child.name.local)),
+        _ => false,
+    }
+}
+
+/// The nodes making up the article within the `body`. Each paragraph
+/// scores points for its length and commas, which go to the elements
+/// around it, and the element with the highest score after discounting
+/// its links is taken to be the article, along with any siblings which
+/// look like part of it.
+fn article_contents(body: &NodeRef) -> Vec<NodeRef> {
+    // Keyed by the address of each node, since nodes can't be hashed
+    let mut scores: HashMap<*const kuchiki::Node, (NodeRef, f64)> =
+        HashMap::new();
+    let paragraphs: Vec<_> = body.descendants().filter(is_paragraph).collect();
+    for paragraph in paragraphs {
+        let length = text_length(&paragraph);
+        if length < 25 {
+            continue;
+        }
+        let commas = paragraph.text_contents().matches(',').count();
+        let score = 1.0 + commas as f64 + (length / 100).min(3) as f64;
+        for (level, ancestor) in paragraph.ancestors().take(5).enumerate() {
+            if ancestor.as_element().is_none() {
+                break;
+            }
+            let divider = match level {
+                0 => 1.0,
+                1 => 2.0,
+                _ => level as f64 * 3.0,
+            };
+            let entry =
+                scores.entry(Rc::as_ptr(&ancestor.0)).or_insert_with(|| {
+                    (ancestor.clone(), initial_score(&ancestor))
+                });
+            entry.1 += score / divider;
+        }
+    }
+    for (node, score) in scores.values_mut() {
+        *score *= 1.0 - link_density(node);
+    }
+
+    let top = scores
+        .values()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(node, score)| (node.clone(), *score));
+    let (top, top_score) = match top {
+        Some(top) => top,
+        None => return body.children().collect(),
+    };
+    let parent = match top.parent() {
+        Some(parent) if top != *body => parent,
+        _ => return top.children().collect(),
+    };
+    let threshold = (top_score * 0.2).max(10.0);
+    parent
+        .children()
+        .filter(|sibling| {
+            if *sibling == top {
+                return true;
+            }
+            let score = scores.get(&Rc::as_ptr(&sibling.0));
+            if score.map(|(_, score)| *score >= threshold) == Some(true) {
+                return true;
+            }
+            let is_p = sibling.as_element().map(|e| &*e.name.local == "p");
+            if is_p != Some(true) {
+                return false;
+            }
+            let length = text_length(sibling);
+            let density = link_density(sibling);
+            let text = collapse(&sibling.text_contents());
+            (length > 80 && density < 0.25)
+                || (density == 0.0
+                    && (text.contains(". ") || text.ends_with('.')))
+        })
+        .collect()
+}
+
+/// Strip the styles and event handlers from the article, and point its
+/// URLs at their absolute URLs, since the page it's in has no `<base>`
+fn clean(article: &NodeRef, base: &Url, lazy_attributes: &[String]) {
+    for element in article.descendants().elements() {
+        let mut attributes = element.attributes.borrow_mut();
+        let removed: Vec<_> = attributes
+            .map
+            .keys()
+            .filter(|name| {
+                let name = &*name.local;
+                name == "style" || name.starts_with("on")
+            })
+            .cloned()
+            .collect();
+        for name in removed {
+            attributes.map.remove(&name);
+        }
+        for (name, attribute) in attributes.map.iter_mut() {
+            let name = &*name.local;
+            let is_url = ["href", "src", "srcset", "poster"].contains(&name)
+                || lazy_attributes.iter().any(|lazy| lazy == name);
+            let value = attribute.value.trim();
+            if !is_url || value.starts_with('#') {
+                continue;
+            }
+            let absolute = if is_srcset_attribute(name) {
+                let candidates: Vec<_> = parse_srcset(value)
+                    .iter()
+                    .map(|c| {
+                        let url = base
+                            .join(c.url)
+                            .map(String::from)
+                            .unwrap_or_else(|_| c.url.to_string());
+                        format!("{} {}", url, c.descriptor)
+                            .trim_end()
+                            .to_string()
+                    })
+                    .collect();
+                Some(candidates.join(", "))
+            } else {
+                base.join(value).ok().map(String::from)
+            };
+            if let Some(absolute) = absolute {
+                attribute.value = absolute;
+            }
+        }
+    }
+}
+
+/// The absolute URLs in the attributes of the article, which are those
+/// of every resource that it uses
+fn used_urls(article: &NodeRef, base: &Url) -> HashSet<Url> {
+    let mut used = HashSet::new();
+    for element in article.descendants().elements() {
+        for (name, attribute) in element.attributes.borrow().map.iter() {
+            if is_srcset_attribute(&name.local) {
+                for candidate in parse_srcset(&attribute.value) {
+                    used.extend(base.join(candidate.url).ok());
+                }
+            } else {
+                used.extend(base.join(attribute.value.trim()).ok());
+            }
+        }
+    }
+    used
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::failure::{FailureReason, ResourceFailure};
+    use crate::parsing::{ImageResource, Resource, TextResource};
+    use bytes::Bytes;
+
+    const PARAGRAPH: &str = "Lorem ipsum dolor sit amet, consectetur \
+        adipiscing elit, sed do eiusmod tempor incididunt ut labore et \
+        dolore magna aliqua.";
+
+    fn archive() -> PageArchive {
+        let url = Url::parse("http://example.com/posts/1").unwrap();
+        let content = format!(
+            "<html><head><title>A Post About Things | Example Blog</title>\
+            <link rel=\"stylesheet\" href=\"/style.css\"></head><body>\
+            <header><nav><a href=\"/\">Home</a> <a href=\"/about\">About</a>\
+            </nav></header>\
+            <div class=\"layout\">\
+            <div class=\"post-body\" style=\"color: red\">\
+            <h1>A Post About Things</h1>\
+            <p class=\"byline\">By Jane Doe</p>\
+            <p onclick=\"track()\">{p}</p>\
+            <img src=\"cat.png\" srcset=\"cat-2x.png 2x\">\
+            <p>{p} <a href=\"/more\">More</a></p>\
+            <div>{p}</div>\
+            </div>\
+            <div class=\"sidebar\"><img src=\"/ad.png\">{p}</div>\
+            <div id=\"comments\"><p>{p}</p></div>\
+            </div>\
+            <script src=\"/app.js\"></script>\
+            </body></html>",
+            p = PARAGRAPH
+        );
+        let mut resource_map = HashMap::new();
+        for path in &["/posts/cat.png", "/posts/cat-2x.png", "/ad.png"] {
+            resource_map.insert(
+                url.join(path).unwrap(),
+                Resource::Image(ImageResource {
+                    data: Bytes::from_static(b"png"),
+                    mimetype: "image/png".to_string(),
+                }),
+            );
+        }
+        resource_map.insert(
+            url.join("/style.css").unwrap(),
+            Resource::Css(TextResource {
+                data: Bytes::from_static(b"p { color: red }"),
+                mimetype: "text/css".to_string(),
+                charset: None,
+            }),
+        );
+        PageArchive {
+            url: url.clone(),
+            content,
+            resource_map,
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: vec![ResourceFailure {
+                url: url.join("/app.js").unwrap(),
+                reason: FailureReason::Status(404),
+                retries: 0,
+            }],
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            screenshot: None,
+        }
+    }
+
+    #[test]
+    fn test_reader_mode() {
+        let article = archive().reader_mode();
+        assert_eq!(article.title.as_deref(), Some("A Post About Things"));
+        assert_eq!(article.byline.as_deref(), Some("By Jane Doe"));
+
+        let content = &article.archive.content;
+        assert_eq!(content.matches(PARAGRAPH).count(), 3);
+        assert_eq!(content.matches("A Post About Things").count(), 2);
+        assert!(content.contains("<title>A Post About Things</title>"));
+        assert!(content.contains("<p class=\"byline\">By Jane Doe</p>"));
+        assert!(content.contains(
+            "<img src=\"http://example.com/posts/cat.png\" \
+            srcset=\"http://example.com/posts/cat-2x.png 2x\">"
+        ));
+        assert!(content.contains("href=\"http://example.com/more\""));
+        for removed in &["Home", "ad.png", "style", "onclick", "app.js"] {
+            assert!(!content.contains(removed), "{} kept", removed);
+        }
+
+        let mut kept: Vec<_> = article
+            .archive
+            .resource_map
+            .keys()
+            .map(Url::as_str)
+            .collect();
+        kept.sort_unstable();
+        assert_eq!(
+            kept,
+            vec![
+                "http://example.com/posts/cat-2x.png",
+                "http://example.com/posts/cat.png"
+            ]
+        );
+        assert!(article.archive.failures.is_empty());
+        assert!(article
+            .archive
+            .embed_resources()
+            .contains("data:image/png;base64,cG5n"));
+    }
+
+    #[test]
+    fn test_reader_mode_without_paragraphs() {
+        let mut archive = archive();
+        archive.content = "<head><meta name=\"author\" content=\"Jo\">\
+            </head><h1>Short</h1><span>Hello</span>"
+            .to_string();
+        let article = archive.reader_mode();
+        assert_eq!(article.title.as_deref(), Some("Short"));
+        assert_eq!(article.byline.as_deref(), Some("Jo"));
+        assert!(article.archive.content.contains(
+            "<h1>Short</h1><p class=\"byline\">Jo</p><span>Hello</span>"
+        ));
+        assert!(article.archive.resource_map.is_empty());
+    }
+}
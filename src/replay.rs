@@ -0,0 +1,328 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for archiving pages from responses recorded earlier, e.g. in a
+//! WARC or HAR file, instead of the network
+
+use crate::download::{Download, Downloader, Fetched, Response};
+use crate::encoding::decode_page;
+use crate::event::ArchiveEvent;
+use crate::failure::{FailureReason, ResourceFailure};
+use crate::meta::ResourceMeta;
+use crate::page_archive::PageArchive;
+use crate::parsing::{parse_resource_urls, ResourceUrl};
+use crate::{ArchiveOptions, Error};
+use bytes::Bytes;
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// How many redirects to follow, the same as the HTTP client
+const MAX_REDIRECTS: usize = 10;
+
+/// A recorded response
+#[derive(Debug)]
+pub(crate) struct Record {
+    pub status: StatusCode,
+    /// `Location` header of a redirect
+    pub location: Option<String>,
+    pub meta: ResourceMeta,
+    pub data: Bytes,
+}
+
+/// The recorded responses, keyed by their URLs
+#[derive(Debug)]
+pub(crate) struct Records {
+    pub responses: HashMap<Url, Record>,
+    /// URL of the first HTML response, which is usually the page that the
+    /// responses were recorded for
+    pub first_page: Option<Url>,
+    /// Why the recorded responses which can't be used failed, e.g. because
+    /// they were too large to read
+    pub failed: HashMap<Url, FailureReason>,
+    /// Why a resource which wasn't recorded failed
+    missing: FailureReason,
+}
+
+impl Records {
+    /// No responses yet, with resources which aren't recorded failing
+    /// with the `missing` reason
+    pub fn new(missing: FailureReason) -> Self {
+        Self {
+            responses: HashMap::new(),
+            first_page: None,
+            failed: HashMap::new(),
+            missing,
+        }
+    }
+
+    /// Add the response for `url`, unless there is one for it already
+    pub fn insert(&mut self, mut url: Url, record: Record) {
+        url.set_fragment(None);
+        if self.contains(&url) {
+            return;
+        }
+        let is_html = record
+            .meta
+            .content_type
+            .as_deref()
+            .map(|t| t.trim_start().starts_with("text/html"))
+            == Some(true);
+        if self.first_page.is_none() && record.status.is_success() && is_html {
+            self.first_page = Some(url.clone());
+        }
+        self.responses.insert(url, record);
+    }
+
+    /// Record that the response for `url` can't be used, unless there is
+    /// one for it already
+    #[cfg_attr(not(feature = "warc"), allow(dead_code))]
+    pub fn insert_failed(&mut self, mut url: Url, reason: FailureReason) {
+        url.set_fragment(None);
+        if !self.contains(&url) {
+            self.failed.insert(url, reason);
+        }
+    }
+
+    /// Whether there's a response for `url`, even one which can't be used
+    pub fn contains(&self, url: &Url) -> bool {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        self.responses.contains_key(&url) || self.failed.contains_key(&url)
+    }
+
+    /// The resource at `resource_url` as the response for it, following
+    /// any redirects, or why it failed if it can't be
+    pub fn fetched(&self, resource_url: ResourceUrl, depth: usize) -> Fetched {
+        match self.response(resource_url.url()) {
+            Ok(response) => {
+                let download = Download::new(resource_url, depth, response);
+                Fetched::Downloaded(Box::new(download))
+            }
+            Err(reason) => Fetched::Failed(ResourceFailure {
+                url: resource_url.url().clone(),
+                reason,
+                retries: 0,
+            }),
+        }
+    }
+
+    /// The response for `url`, following any redirects
+    pub fn response(&self, url: &Url) -> Result<Response, FailureReason> {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        let mut redirects = Vec::new();
+        loop {
+            if let Some(reason) = self.failed.get(&url) {
+                return Err(reason.clone());
+            }
+            let record = self
+                .responses
+                .get(&url)
+                .ok_or_else(|| self.missing.clone())?;
+            let status = record.status;
+            let next = record
+                .location
+                .as_deref()
+                .filter(|_| status.is_redirection())
+                .and_then(|location| url.join(location).ok());
+            if let Some(mut next) = next {
+                if redirects.len() >= MAX_REDIRECTS {
+                    return Err(FailureReason::Status(status.as_u16()));
+                }
+                next.set_fragment(None);
+                redirects.push(next.clone());
+                url = next;
+                continue;
+            }
+            if !status.is_success() {
+                return Err(FailureReason::Status(status.as_u16()));
+            }
+            return Ok(Response {
+                redirects,
+                meta: record.meta.clone(),
+                data: record.data.clone(),
+                timing: None,
+                transformed: false,
+            });
+        }
+    }
+}
+
+/// Archive the page at `url` from the `records`, failing with the error
+/// made by `not_found` if the page isn't in them
+pub(crate) fn replay(
+    records: &Records,
+    url: Url,
+    options: &ArchiveOptions<'_>,
+    not_found: fn(String) -> Error,
+) -> Result<PageArchive, Error> {
+    let started = Instant::now();
+    let page = records
+        .response(&url)
+        .map_err(|reason| not_found(format!("{}: {}", url, reason)))?;
+    let requested = url;
+    let url = page.redirects.last().unwrap_or(&requested).clone();
+    options.emit(ArchiveEvent::PageFetched {
+        url: url.clone(),
+        bytes: page.data.len(),
+    });
+    let content = decode_page(page.meta.content_type.as_deref(), &page.data);
+
+    let resource_urls = parse_resource_urls(&url, &content, options);
+    let mut downloader =
+        Downloader::new(url.clone(), resource_urls, options, None);
+    downloader.redirected(requested, page.redirects);
+    downloader.fetched(url, page.meta);
+    loop {
+        let batch = downloader.take_queue();
+        if batch.is_empty() {
+            break;
+        }
+        for (resource_url, depth) in batch {
+            downloader.record(records.fetched(resource_url, depth));
+        }
+    }
+
+    Ok(downloader.into_archive(content, started.elapsed()))
+}
+
+/// Parse an ISO 8601 timestamp, e.g. `2021-02-03T04:05:06Z`, possibly
+/// with fractions of a second, and with either `Z` or an offset from UTC
+/// such as `+01:00` at the end
+pub(crate) fn parse_date(date: &str) -> Option<SystemTime> {
+    let date = date.trim();
+    let (date, offset) = match date.strip_suffix('Z') {
+        Some(date) => (date, 0),
+        None => {
+            let split = date.len().checked_sub(6)?;
+            if !date.is_char_boundary(split) {
+                return None;
+            }
+            let (date, offset) = date.split_at(split);
+            let sign = match offset.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let minutes = hours.parse::<i64>().ok()? * 60
+                + minutes.parse::<i64>().ok()?;
+            (date, sign * minutes * 60)
+        }
+    };
+    let (day, time) = date.split_once('T')?;
+    let mut day = day.splitn(3, '-').map(|n| n.parse::<i64>().ok());
+    let (year, month, day) = (day.next()??, day.next()??, day.next()??);
+    let (time, fraction) = match time.split_once('.') {
+        Some((time, fraction)) => (time, Some(fraction)),
+        None => (time, None),
+    };
+    let mut time = time.splitn(3, ':').map(|n| n.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(0..=24).contains(&hour)
+        || !(0..=59).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return None;
+    }
+    let days = crate::date::days_from_civil(
+        year,
+        month.try_into().ok()?,
+        day.try_into().ok()?,
+    )?;
+
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    let nanos = match fraction {
+        Some(fraction) => {
+            let digits: String = fraction.chars().take(9).collect();
+            let nanos: u32 = digits.parse().ok()?;
+            nanos * 10u32.pow(9 - digits.len() as u32)
+        }
+        None => 0,
+    };
+    Some(UNIX_EPOCH + Duration::new(seconds.try_into().ok()?, nanos))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::HeaderMap;
+
+    fn record(
+        status: u16,
+        location: Option<&str>,
+        data: &'static str,
+    ) -> Record {
+        let url = Url::parse("http://example.com/").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "text/html".parse().unwrap());
+        let status = StatusCode::from_u16(status).unwrap();
+        Record {
+            status,
+            location: location.map(str::to_string),
+            meta: ResourceMeta::new(status, &url, &headers),
+            data: Bytes::from_static(data.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_records() {
+        let url = |u| Url::parse(u).unwrap();
+        let mut records = Records::new(FailureReason::NotInWarc);
+        records.insert(url("http://example.com/"), record(301, Some("/a"), ""));
+        records.insert(url("http://example.com/a#top"), record(200, None, "a"));
+        records.insert(url("http://example.com/a"), record(200, None, "b"));
+        records
+            .insert(url("http://example.com/loop"), record(302, Some(""), ""));
+        records.insert(url("http://example.com/gone"), record(404, None, ""));
+        assert_eq!(
+            records.first_page.as_ref().map(Url::as_str),
+            Some("http://example.com/a")
+        );
+
+        let page = records.response(&url("http://example.com/")).unwrap();
+        assert_eq!(page.redirects, vec![url("http://example.com/a")]);
+        assert_eq!(page.data, "a".as_bytes());
+        assert_eq!(
+            records.response(&url("http://example.com/loop")),
+            Err(FailureReason::Status(302))
+        );
+        assert_eq!(
+            records.response(&url("http://example.com/gone")),
+            Err(FailureReason::Status(404))
+        );
+        assert_eq!(
+            records.response(&url("http://example.com/missing")),
+            Err(FailureReason::NotInWarc)
+        );
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse_date("2000-03-01T12:30:15.5Z"),
+            Some(UNIX_EPOCH + Duration::new(951_913_815, 500_000_000))
+        );
+        assert_eq!(
+            parse_date("2000-03-01T13:30:15.5+01:00"),
+            parse_date("2000-03-01T12:30:15.5Z")
+        );
+        assert_eq!(
+            parse_date("2000-03-01T07:00:15-05:30"),
+            parse_date("2000-03-01T12:30:15Z")
+        );
+        assert_eq!(parse_date("2021-02-03"), None);
+        assert_eq!(parse_date("2021-13-03T04:05:06Z"), None);
+        assert_eq!(parse_date("2021-02-03T04:05:06"), None);
+        assert_eq!(parse_date("99999999999999-02-03T04:05:06Z"), None);
+        assert_eq!(parse_date("2021-02-03T99999999999999:05:06Z"), None);
+    }
+}
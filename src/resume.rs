@@ -0,0 +1,185 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for carrying on with downloads which were interrupted, using
+//! `Range` requests
+
+use crate::failure::FailureReason;
+use bytes::BytesMut;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_RANGE, ETAG, IF_RANGE,
+    LAST_MODIFIED, RANGE,
+};
+use reqwest::StatusCode;
+
+/// The part of a resource which was downloaded before its request failed,
+/// for a retry to carry on from
+#[derive(Debug)]
+pub(crate) struct Partial {
+    pub data: BytesMut,
+    /// `ETag` or `Last-Modified` header of the response, so that the rest
+    /// is only sent if the resource hasn't changed since
+    pub validator: HeaderValue,
+}
+
+/// How a response could be resumed if it's interrupted: the validator to
+/// send, if the server accepts ranges and gave one
+pub(crate) fn validator(headers: &HeaderMap) -> Option<HeaderValue> {
+    let ranges = headers.get(ACCEPT_RANGES)?.to_str().ok()?;
+    if !ranges.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    // Weak ETags can't be used with `If-Range`
+    match headers.get(ETAG) {
+        Some(etag) if !etag.as_bytes().starts_with(b"W/") => Some(etag.clone()),
+        _ => headers.get(LAST_MODIFIED).cloned(),
+    }
+}
+
+impl Partial {
+    /// Keep the `data` downloaded so far, if the response could be
+    /// resumed and there's anything to resume
+    pub fn new(validator: Option<HeaderValue>, data: BytesMut) -> Option<Self> {
+        match validator {
+            Some(validator) if !data.is_empty() => {
+                Some(Self { data, validator })
+            }
+            _ => None,
+        }
+    }
+
+    /// Headers asking for the rest of the resource, as long as it hasn't
+    /// changed
+    pub fn request_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let range = format!("bytes={}-", self.data.len());
+        headers.insert(RANGE, HeaderValue::from_str(&range).unwrap());
+        headers.insert(IF_RANGE, self.validator.clone());
+        headers
+    }
+
+    /// Whether a response carries on from the end of the data. Otherwise
+    /// it's the whole resource, or something unexpected.
+    pub fn resumed_by(&self, status: StatusCode, headers: &HeaderMap) -> bool {
+        if status != StatusCode::PARTIAL_CONTENT {
+            return false;
+        }
+        // e.g. `bytes 100-999/1000`
+        let start = headers
+            .get(CONTENT_RANGE)
+            .and_then(|range| range.to_str().ok())
+            .and_then(|range| range.strip_prefix("bytes "))
+            .and_then(|range| range.split('-').next())
+            .and_then(|start| start.trim().parse::<usize>().ok());
+        start == Some(self.data.len())
+    }
+}
+
+/// Where to carry on from after the response to a request which may have
+/// asked for the rest of `partial`: the data downloaded before, which is
+/// empty unless the response resumes it, and the validator for resuming
+/// this response in turn if it's interrupted
+pub(crate) fn carry_on(
+    partial: Option<Partial>,
+    status: StatusCode,
+    headers: &HeaderMap,
+) -> Result<(BytesMut, Option<HeaderValue>), FailureReason> {
+    match partial {
+        Some(partial) if partial.resumed_by(status, headers) => {
+            Ok((partial.data, Some(partial.validator)))
+        }
+        // A different range, so it's safest to start again
+        Some(_) if status == StatusCode::PARTIAL_CONTENT => Err(
+            FailureReason::Network("unexpected partial content".to_string()),
+        ),
+        _ => Ok((BytesMut::new(), validator(headers))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (name.parse().unwrap(), HeaderValue::from_static(value))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_validator() {
+        let etag = headers(&[("accept-ranges", "bytes"), ("etag", "\"abc\"")]);
+        assert_eq!(validator(&etag).unwrap(), "\"abc\"");
+        let weak = headers(&[
+            ("accept-ranges", "bytes"),
+            ("etag", "W/\"abc\""),
+            ("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT"),
+        ]);
+        assert_eq!(validator(&weak).unwrap(), "Wed, 21 Oct 2015 07:28:00 GMT");
+        assert!(validator(&headers(&[("accept-ranges", "bytes")])).is_none());
+        let none = headers(&[("accept-ranges", "none"), ("etag", "\"abc\"")]);
+        assert!(validator(&none).is_none());
+        assert!(validator(&headers(&[("etag", "\"abc\"")])).is_none());
+    }
+
+    #[test]
+    fn test_partial() {
+        let validator = HeaderValue::from_static("\"abc\"");
+        assert!(
+            Partial::new(Some(validator.clone()), BytesMut::new()).is_none()
+        );
+        assert!(Partial::new(None, BytesMut::from("abc")).is_none());
+
+        let partial =
+            Partial::new(Some(validator), BytesMut::from("0123456789"))
+                .unwrap();
+        let request = partial.request_headers();
+        assert_eq!(request[RANGE], "bytes=10-");
+        assert_eq!(request[IF_RANGE], "\"abc\"");
+
+        let range = headers(&[("content-range", "bytes 10-19/20")]);
+        assert!(partial.resumed_by(StatusCode::PARTIAL_CONTENT, &range));
+        assert!(!partial.resumed_by(StatusCode::OK, &range));
+        let other = headers(&[("content-range", "bytes 0-19/20")]);
+        assert!(!partial.resumed_by(StatusCode::PARTIAL_CONTENT, &other));
+        assert!(!partial.resumed_by(StatusCode::PARTIAL_CONTENT, &headers(&[])));
+    }
+
+    #[test]
+    fn test_carry_on() {
+        let partial = || {
+            let validator = HeaderValue::from_static("\"abc\"");
+            Partial::new(Some(validator), BytesMut::from("0123456789"))
+        };
+        let range = headers(&[("content-range", "bytes 10-19/20")]);
+        let (data, validator) =
+            carry_on(partial(), StatusCode::PARTIAL_CONTENT, &range).unwrap();
+        assert_eq!(&data[..], b"0123456789");
+        assert_eq!(validator.unwrap(), "\"abc\"");
+
+        // The resource changed, so it's sent in full
+        let changed =
+            headers(&[("accept-ranges", "bytes"), ("etag", "\"def\"")]);
+        let (data, validator) =
+            carry_on(partial(), StatusCode::OK, &changed).unwrap();
+        assert!(data.is_empty());
+        assert_eq!(validator.unwrap(), "\"def\"");
+
+        let other = headers(&[("content-range", "bytes 0-9/20")]);
+        assert!(
+            carry_on(partial(), StatusCode::PARTIAL_CONTENT, &other).is_err()
+        );
+
+        let (data, validator) =
+            carry_on(None, StatusCode::OK, &changed).unwrap();
+        assert!(data.is_empty());
+        assert!(validator.is_some());
+    }
+}
@@ -0,0 +1,212 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for reading `robots.txt` files, which say which pages of a
+//! site crawlers may visit, as described by RFC 9309
+
+use std::time::Duration;
+use url::Url;
+
+/// The rules of a site's `robots.txt` which apply to one crawler
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Robots {
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+impl Robots {
+    /// Rules which allow every page, for sites without a `robots.txt`
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// Rules which disallow every page, for sites whose `robots.txt`
+    /// couldn't be fetched because of a server or network error
+    pub fn disallow_all() -> Self {
+        Self {
+            rules: vec![Rule {
+                allow: false,
+                pattern: "/".to_string(),
+            }],
+            crawl_delay: None,
+        }
+    }
+
+    /// Read the rules in `robots.txt` which apply to the crawler named
+    /// `agent`: those of the groups naming it if there are any, or else
+    /// those of the `*` groups
+    pub fn parse(text: &str, agent: &str) -> Self {
+        let agent = agent.to_ascii_lowercase();
+        let mut named = Robots::default();
+        let mut wildcard = Robots::default();
+        let mut has_named = false;
+        // Which of the two the current group applies to
+        let (mut to_named, mut to_wildcard) = (false, false);
+        let mut in_agents = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            let key = key.to_ascii_lowercase();
+            if key == "user-agent" {
+                if !in_agents {
+                    // A new group starts
+                    to_named = false;
+                    to_wildcard = false;
+                    in_agents = true;
+                }
+                let value = value.to_ascii_lowercase();
+                if value == "*" {
+                    to_wildcard = true;
+                } else if !value.is_empty() && agent.starts_with(&value) {
+                    to_named = true;
+                    has_named = true;
+                }
+                continue;
+            }
+            in_agents = false;
+            let rule = match key.as_str() {
+                "allow" | "disallow" if !value.is_empty() => Rule {
+                    allow: key == "allow",
+                    pattern: value.to_string(),
+                },
+                "crawl-delay" => {
+                    let delay = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|delay| delay.is_finite() && *delay >= 0.0)
+                        .map(Duration::from_secs_f64);
+                    if to_named {
+                        named.crawl_delay = named.crawl_delay.or(delay);
+                    }
+                    if to_wildcard {
+                        wildcard.crawl_delay = wildcard.crawl_delay.or(delay);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            if to_named {
+                named.rules.push(rule.clone());
+            }
+            if to_wildcard {
+                wildcard.rules.push(rule);
+            }
+        }
+        if has_named {
+            named
+        } else {
+            wildcard
+        }
+    }
+
+    /// Whether the crawler may visit `url`. The most specific rule
+    /// matching its path wins, and `Allow` wins a tie.
+    pub fn allows(&self, url: &Url) -> bool {
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        self.rules
+            .iter()
+            .filter(|rule| matches(&rule.pattern, &path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .map_or(true, |rule| rule.allow)
+    }
+
+    /// How long to leave between requests to the site
+    pub fn crawl_delay(&self) -> Option<Duration> {
+        self.crawl_delay
+    }
+}
+
+/// Whether a rule's `pattern` matches the start of `path`. A `*` in the
+/// pattern matches any run of characters, and a `$` at its end matches
+/// the end of the path.
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match path.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<_> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        let is_last = i == parts.len() - 1;
+        if is_last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_robots() {
+        let text = "# Example\n\
+            User-agent: *\n\
+            Disallow: /private/\n\
+            Allow: /private/public$\n\
+            Crawl-delay: 2\n\
+            \n\
+            User-agent: other-bot\n\
+            User-Agent: Web-Archive\n\
+            Disallow: /*.php\n\
+            Disallow: /search?\n\
+            Allow: /search?q=\n\
+            Crawl-delay: 0.5 # seconds\n\
+            \n\
+            User-agent: someone-else\n\
+            Disallow: /\n";
+        let allows = |robots: &Robots, path: &str| {
+            let url = Url::parse("http://example.com").unwrap();
+            robots.allows(&url.join(path).unwrap())
+        };
+
+        let robots = Robots::parse(text, "web-archive/0.3");
+        assert!(allows(&robots, "/private/"));
+        assert!(!allows(&robots, "/index.php"));
+        assert!(!allows(&robots, "/a/b.php?c=d"));
+        assert!(!allows(&robots, "/search?page=2"));
+        assert!(allows(&robots, "/search?q=cats"));
+        assert!(allows(&robots, "/search"));
+        assert_eq!(robots.crawl_delay(), Some(Duration::from_millis(500)));
+
+        let robots = Robots::parse(text, "mozilla/5.0");
+        assert!(allows(&robots, "/"));
+        assert!(!allows(&robots, "/private/secret"));
+        assert!(allows(&robots, "/private/public"));
+        assert!(!allows(&robots, "/private/public/more"));
+        assert!(allows(&robots, "/index.php"));
+        assert_eq!(robots.crawl_delay(), Some(Duration::from_secs(2)));
+
+        assert!(allows(&Robots::allow_all(), "/private/"));
+        assert!(!allows(&Robots::disallow_all(), "/"));
+        assert!(allows(&Robots::parse("Disallow:\n", "bot"), "/"));
+    }
+}
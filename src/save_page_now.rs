@@ -0,0 +1,477 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for submitting archived pages to the Internet Archive's Save
+//! Page Now, see [`PageArchive::save_page_now`]
+
+use crate::error::Error;
+use crate::page_archive::PageArchive;
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Configuration options for [`PageArchive::save_page_now`], controlling
+/// how the Wayback Machine is asked to capture the page
+pub struct SavePageNowOptions<'a> {
+    /// The access key and secret key of an Internet Archive account, from
+    /// <https://archive.org/account/s3.php>, or `None` to submit
+    /// anonymously. Anonymous submissions are more strictly rate limited.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::SavePageNowOptions;
+    /// let options = SavePageNowOptions {
+    ///     keys: Some(("access", "secret")),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub keys: Option<(&'a str, &'a str)>,
+    /// Also submit each of the archived resources on their own, so that
+    /// the Wayback Machine has its own copy of those it doesn't capture
+    /// along with the page. Resources which fail to be captured are left
+    /// without a snapshot.
+    ///
+    /// Default: `false`
+    pub resources: bool,
+    /// Where the Wayback Machine is, for the `save` endpoint and the
+    /// snapshots it returns
+    ///
+    /// Default: `"https://web.archive.org/"`
+    pub endpoint: &'a str,
+    /// How long to wait between asking whether a capture has finished
+    ///
+    /// Default: 5 seconds
+    pub poll_interval: Duration,
+    /// How long to wait for each capture to finish before giving up
+    ///
+    /// Default: 5 minutes
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use web_archive::SavePageNowOptions;
+    /// let options = SavePageNowOptions {
+    ///     timeout: Duration::from_secs(60),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub timeout: Duration,
+}
+
+impl<'a> SavePageNowOptions<'a> {
+    /// Submit with an Internet Archive account's keys, see
+    /// [`SavePageNowOptions::keys`](#structfield.keys)
+    pub fn keys(mut self, access: &'a str, secret: &'a str) -> Self {
+        self.keys = Some((access, secret));
+        self
+    }
+
+    /// Submit the resources as well as the page, see
+    /// [`SavePageNowOptions::resources`](#structfield.resources)
+    pub fn resources(mut self, resources: bool) -> Self {
+        self.resources = resources;
+        self
+    }
+
+    /// Submit to the Wayback Machine at `endpoint`, see
+    /// [`SavePageNowOptions::endpoint`](#structfield.endpoint)
+    pub fn endpoint(mut self, endpoint: &'a str) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
+    /// Set how long to wait between polls, see
+    /// [`SavePageNowOptions::poll_interval`](#structfield.poll_interval)
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Set how long to wait for each capture, see
+    /// [`SavePageNowOptions::timeout`](#structfield.timeout)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The Wayback Machine's URL, which the endpoints are relative to
+    pub(crate) fn endpoint_url(&self) -> Result<Url, Error> {
+        let endpoint = Url::parse(self.endpoint)
+            .map_err(|e| Error::ParseError(format!("{}", e)))?;
+        if endpoint.path().ends_with('/') {
+            Ok(endpoint)
+        } else {
+            endpoint
+                .join(&format!("{}/", endpoint.path()))
+                .map_err(|e| Error::ParseError(format!("{}", e)))
+        }
+    }
+
+    /// Headers sent with every request, asking for JSON responses and
+    /// authorising them with the keys if there are any
+    pub(crate) fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        if let Some((access, secret)) = self.keys {
+            let authorization = format!("LOW {}:{}", access, secret);
+            if let Ok(value) = HeaderValue::from_str(&authorization) {
+                headers.insert(AUTHORIZATION, value);
+            }
+        }
+        headers
+    }
+}
+
+impl<'a> Default for SavePageNowOptions<'a> {
+    fn default() -> Self {
+        Self {
+            keys: None,
+            resources: false,
+            endpoint: "https://web.archive.org/",
+            poll_interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// Where a capture has got to, according to the Wayback Machine
+#[derive(Debug, PartialEq)]
+pub(crate) enum Job {
+    /// Still being captured
+    Pending,
+    /// Captured, and kept at the snapshot URL
+    Captured(Url),
+}
+
+/// Submit the `archive`'s page, and its resources if the options say so,
+/// recording their snapshots in its [`meta`](PageArchive::meta), see
+/// [`PageArchive::save_page_now`]
+pub(crate) async fn save(
+    archive: &mut PageArchive,
+    client: &reqwest::Client,
+    options: &SavePageNowOptions<'_>,
+) -> Result<Url, Error> {
+    let page = archive.url.clone();
+    let snapshot = capture(client, &page, options).await?;
+    set_snapshot(archive, &page, snapshot.clone());
+    if options.resources {
+        for url in resources(archive) {
+            if let Ok(snapshot) = capture(client, &url, options).await {
+                set_snapshot(archive, &url, snapshot);
+            }
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Ask the Wayback Machine to capture `url`, waiting until it's done
+async fn capture(
+    client: &reqwest::Client,
+    url: &Url,
+    options: &SavePageNowOptions<'_>,
+) -> Result<Url, Error> {
+    let endpoint = options.endpoint_url()?;
+    let response = client
+        .post(save_url(&endpoint))
+        .headers(options.headers())
+        .form(&[("url", url.as_str())])
+        .send()
+        .await?;
+    let status = response.status().as_u16();
+    let job = job_id(status, &response.bytes().await?)?;
+    let started = Instant::now();
+    loop {
+        tokio::time::sleep(options.poll_interval).await;
+        let response = client
+            .get(status_url(&endpoint, &job)?)
+            .headers(options.headers())
+            .send()
+            .await?;
+        let status = response.status().as_u16();
+        let body = response.bytes().await?;
+        if let Job::Captured(snapshot) = job_status(&endpoint, status, &body)? {
+            return Ok(snapshot);
+        }
+        if started.elapsed() >= options.timeout {
+            return Err(timed_out(url));
+        }
+    }
+}
+
+/// Submit the `archive`'s page, and its resources if the options say so,
+/// blocking the thread, see [`PageArchive::save_page_now_blocking`]
+#[cfg(feature = "blocking")]
+pub(crate) fn save_blocking(
+    archive: &mut PageArchive,
+    client: &reqwest::blocking::Client,
+    options: &SavePageNowOptions<'_>,
+) -> Result<Url, Error> {
+    let page = archive.url.clone();
+    let snapshot = capture_blocking(client, &page, options)?;
+    set_snapshot(archive, &page, snapshot.clone());
+    if options.resources {
+        for url in resources(archive) {
+            if let Ok(snapshot) = capture_blocking(client, &url, options) {
+                set_snapshot(archive, &url, snapshot);
+            }
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Ask the Wayback Machine to capture `url`, blocking the thread until
+/// it's done
+#[cfg(feature = "blocking")]
+fn capture_blocking(
+    client: &reqwest::blocking::Client,
+    url: &Url,
+    options: &SavePageNowOptions<'_>,
+) -> Result<Url, Error> {
+    let endpoint = options.endpoint_url()?;
+    let response = client
+        .post(save_url(&endpoint))
+        .headers(options.headers())
+        .form(&[("url", url.as_str())])
+        .send()?;
+    let status = response.status().as_u16();
+    let job = job_id(status, &response.bytes()?)?;
+    let started = Instant::now();
+    loop {
+        std::thread::sleep(options.poll_interval);
+        let response = client
+            .get(status_url(&endpoint, &job)?)
+            .headers(options.headers())
+            .send()?;
+        let status = response.status().as_u16();
+        let body = response.bytes()?;
+        if let Job::Captured(snapshot) = job_status(&endpoint, status, &body)? {
+            return Ok(snapshot);
+        }
+        if started.elapsed() >= options.timeout {
+            return Err(timed_out(url));
+        }
+    }
+}
+
+/// The archived resources which can be submitted, in order of their URLs
+fn resources(archive: &PageArchive) -> Vec<Url> {
+    let mut urls: Vec<_> = archive
+        .resource_map
+        .keys()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .cloned()
+        .collect();
+    urls.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    urls
+}
+
+/// Record the snapshot of the page or a resource in its meta, if it has
+/// any
+fn set_snapshot(archive: &mut PageArchive, url: &Url, snapshot: Url) {
+    if let Some(meta) = archive.meta.get_mut(url) {
+        meta.snapshot = Some(snapshot);
+    }
+}
+
+fn timed_out(url: &Url) -> Error {
+    Error::SavePageNow(format!("capture of {} timed out", url))
+}
+
+/// Where captures are submitted
+fn save_url(endpoint: &Url) -> Url {
+    endpoint.join("save").unwrap()
+}
+
+/// Where the progress of the capture `job` is asked about
+fn status_url(endpoint: &Url, job: &str) -> Result<Url, Error> {
+    endpoint
+        .join(&format!("save/status/{}", job))
+        .map_err(|e| Error::ParseError(format!("{}", e)))
+}
+
+/// The message explaining why the Wayback Machine refused or failed a
+/// capture
+fn message(status: u16, response: &Value) -> String {
+    let message = response["message"]
+        .as_str()
+        .or_else(|| response["status_ext"].as_str());
+    match message {
+        Some(message) => message.to_string(),
+        None => format!("status {}", status),
+    }
+}
+
+/// Read the ID of the capture job from the response to submitting it
+pub(crate) fn job_id(status: u16, body: &[u8]) -> Result<String, Error> {
+    let response: Value = serde_json::from_slice(body).unwrap_or_default();
+    match response["job_id"].as_str() {
+        Some(job) if (200..300).contains(&status) => Ok(job.to_string()),
+        _ => Err(Error::SavePageNow(message(status, &response))),
+    }
+}
+
+/// Read how a capture job is getting on, from the response to asking
+/// about it
+pub(crate) fn job_status(
+    endpoint: &Url,
+    status: u16,
+    body: &[u8],
+) -> Result<Job, Error> {
+    let response: Value = serde_json::from_slice(body).unwrap_or_default();
+    if !(200..300).contains(&status) {
+        return Err(Error::SavePageNow(message(status, &response)));
+    }
+    match response["status"].as_str() {
+        Some("pending") => Ok(Job::Pending),
+        Some("success") => {
+            let timestamp = response["timestamp"].as_str();
+            let original = response["original_url"].as_str();
+            match (timestamp, original) {
+                (Some(timestamp), Some(original)) => endpoint
+                    .join(&format!("web/{}/{}", timestamp, original))
+                    .map(Job::Captured)
+                    .map_err(|e| Error::ParseError(format!("{}", e))),
+                _ => Err(Error::SavePageNow(
+                    "capture finished without a snapshot".to_string(),
+                )),
+            }
+        }
+        _ => Err(Error::SavePageNow(message(status, &response))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::meta::ResourceMeta;
+    use crate::parsing::{ImageResource, Resource};
+    use bytes::Bytes;
+    use reqwest::StatusCode;
+
+    fn endpoint() -> Url {
+        SavePageNowOptions::default().endpoint_url().unwrap()
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let options = SavePageNowOptions::default()
+            .endpoint("http://localhost:8080/wayback");
+        let endpoint = options.endpoint_url().unwrap();
+        assert_eq!(
+            save_url(&endpoint).as_str(),
+            "http://localhost:8080/wayback/save"
+        );
+        assert_eq!(
+            status_url(&endpoint, "spn2-abc").unwrap().as_str(),
+            "http://localhost:8080/wayback/save/status/spn2-abc"
+        );
+    }
+
+    #[test]
+    fn test_headers() {
+        let headers = SavePageNowOptions::default().headers();
+        assert_eq!(headers[ACCEPT], "application/json");
+        assert!(headers.get(AUTHORIZATION).is_none());
+
+        let headers = SavePageNowOptions::default()
+            .keys("access", "secret")
+            .headers();
+        assert_eq!(headers[AUTHORIZATION], "LOW access:secret");
+    }
+
+    #[test]
+    fn test_job_id() {
+        let body = br#"{"url": "http://example.com/", "job_id": "spn2-abc"}"#;
+        assert_eq!(job_id(200, body).unwrap(), "spn2-abc");
+
+        let body = br#"{"status": "error", "message": "Too many captures"}"#;
+        assert!(matches!(
+            job_id(429, body),
+            Err(Error::SavePageNow(message)) if message == "Too many captures"
+        ));
+        assert!(matches!(
+            job_id(502, b"<html>Bad gateway</html>"),
+            Err(Error::SavePageNow(message)) if message == "status 502"
+        ));
+    }
+
+    #[test]
+    fn test_job_status() {
+        let endpoint = endpoint();
+        let pending = br#"{"status": "pending", "job_id": "spn2-abc"}"#;
+        assert_eq!(job_status(&endpoint, 200, pending).unwrap(), Job::Pending);
+
+        let success = br#"{
+            "status": "success",
+            "job_id": "spn2-abc",
+            "timestamp": "20210102030405",
+            "original_url": "http://example.com/"
+        }"#;
+        let snapshot = Url::parse(
+            "https://web.archive.org/web/20210102030405/http://example.com/",
+        )
+        .unwrap();
+        assert_eq!(
+            job_status(&endpoint, 200, success).unwrap(),
+            Job::Captured(snapshot)
+        );
+
+        let error = br#"{
+            "status": "error",
+            "status_ext": "error:not-found",
+            "message": "Couldn't find the page"
+        }"#;
+        assert!(matches!(
+            job_status(&endpoint, 200, error),
+            Err(Error::SavePageNow(message))
+                if message == "Couldn't find the page"
+        ));
+    }
+
+    #[test]
+    fn test_resources() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let mut archive = PageArchive {
+            url: url.clone(),
+            content: String::new(),
+            resource_map: Default::default(),
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: Default::default(),
+            meta: Default::default(),
+            stats: Default::default(),
+            screenshot: None,
+        };
+        let image = || {
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"PNG"),
+                mimetype: "image/png".to_string(),
+            })
+        };
+        let logo = url.join("logo.png").unwrap();
+        let icon = url.join("icon.png").unwrap();
+        let data = Url::parse("data:image/png;base64,UE5H").unwrap();
+        for url in [&logo, &icon, &data] {
+            archive.resource_map.insert(url.clone(), image());
+        }
+        assert_eq!(resources(&archive), vec![icon, logo.clone()]);
+
+        let meta = ResourceMeta::new(StatusCode::OK, &logo, &HeaderMap::new());
+        archive.meta.insert(logo.clone(), meta);
+        let snapshot = endpoint().join("web/2021/http://example.com/").unwrap();
+        set_snapshot(&mut archive, &logo, snapshot.clone());
+        set_snapshot(&mut archive, &url, snapshot.clone());
+        assert_eq!(archive.meta[&logo].snapshot, Some(snapshot));
+        assert!(!archive.meta.contains_key(&url));
+    }
+}
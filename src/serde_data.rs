@@ -0,0 +1,192 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for serialising the data of resources with serde, for use with
+//! `#[serde(with = "crate::serde_data")]`
+//!
+//! Human-readable formats such as JSON get the data as a base 64 string,
+//! instead of an array with a number for every byte, and binary formats
+//! get the bytes as they are.
+
+use bytes::Bytes;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserializer, Serializer};
+use std::fmt;
+
+pub(crate) fn serialize<S: Serializer>(
+    data: &Bytes,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&base64::encode(data))
+    } else {
+        serializer.serialize_bytes(data)
+    }
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Bytes, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(DataVisitor)
+    } else {
+        deserializer.deserialize_byte_buf(DataVisitor)
+    }
+}
+
+struct DataVisitor;
+
+impl<'de> Visitor<'de> for DataVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "base 64 encoded data, or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Bytes, E> {
+        base64::decode(v).map(Bytes::from).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Bytes, E> {
+        Ok(Bytes::copy_from_slice(v))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Bytes, E> {
+        Ok(Bytes::from(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> Result<Bytes, A::Error> {
+        let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            data.push(byte);
+        }
+        Ok(Bytes::from(data))
+    }
+}
+
+/// The same for optional data, for use with
+/// `#[serde(default, with = "crate::serde_data::option")]`
+pub(crate) mod option {
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Data(#[serde(with = "super")] Bytes);
+
+    pub(crate) fn serialize<S: Serializer>(
+        data: &Option<Bytes>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        data.clone().map(Data).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Bytes>, D::Error> {
+        Ok(Option::<Data>::deserialize(deserializer)?.map(|data| data.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::failure::{FailureReason, ResourceFailure};
+    use crate::meta::ResourceMeta;
+    use crate::page_archive::PageArchive;
+    use crate::parsing::{ImageResource, Resource, TextResource};
+    use crate::stats::{ArchiveStats, FetchTiming};
+    use bytes::Bytes;
+    use reqwest::header::HeaderMap;
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use url::Url;
+
+    #[test]
+    fn test_serialize() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let style = url.join("style.css").unwrap();
+        let image = url.join("image.png").unwrap();
+        let mut resource_map = HashMap::new();
+        resource_map.insert(
+            style.clone(),
+            Resource::Css(TextResource {
+                data: Bytes::from_static(b"a {}"),
+                mimetype: "text/css".to_string(),
+                charset: Some("utf-8".to_string()),
+            }),
+        );
+        resource_map.insert(
+            image.clone(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(&[0x89, b'P', b'N', b'G']),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let mut meta = HashMap::new();
+        meta.insert(
+            url.clone(),
+            ResourceMeta::new(StatusCode::OK, &url, &HeaderMap::new()),
+        );
+        let archive = PageArchive {
+            url: url.clone(),
+            content: "<p>Hello</p>".to_string(),
+            resource_map,
+            lazy_attributes: vec!["data-src".to_string()],
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: vec![ResourceFailure {
+                url: url.join("missing.png").unwrap(),
+                reason: FailureReason::Status(404),
+                retries: 0,
+            }],
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta,
+            stats: ArchiveStats {
+                wall_time: Duration::from_millis(1500),
+                fetches: vec![FetchTiming {
+                    url: style.clone(),
+                    time_to_first_byte: Duration::from_millis(10),
+                    total: Duration::from_millis(20),
+                    bytes: 4,
+                }],
+            },
+            screenshot: Some(Bytes::from_static(&[0x89, b'P', b'N', b'G'])),
+        };
+
+        let value = serde_json::to_value(&archive).unwrap();
+        assert_eq!(value["url"], "http://example.com/");
+        assert_eq!(
+            value["resource_map"]["http://example.com/image.png"],
+            json!({ "Image": { "data": "iVBORw==", "mimetype": "image/png" } })
+        );
+        assert_eq!(value["failures"][0]["reason"], json!({ "Status": 404 }));
+        assert_eq!(value["screenshot"], "iVBORw==");
+
+        let read_back: PageArchive = serde_json::from_value(value).unwrap();
+        assert_eq!(read_back.url, archive.url);
+        assert_eq!(read_back.content, archive.content);
+        assert_eq!(read_back.resource_map, archive.resource_map);
+        assert_eq!(read_back.lazy_attributes, archive.lazy_attributes);
+        assert_eq!(read_back.failures, archive.failures);
+        assert_eq!(read_back.meta, archive.meta);
+        assert_eq!(read_back.stats, archive.stats);
+        assert_eq!(read_back.screenshot, archive.screenshot);
+
+        let mut value = serde_json::to_value(&archive).unwrap();
+        value.as_object_mut().unwrap().remove("screenshot");
+        let read_back: PageArchive = serde_json::from_value(value).unwrap();
+        assert_eq!(read_back.screenshot, None);
+
+        let invalid = json!({ "data": "not base 64!", "mimetype": "" });
+        assert!(serde_json::from_value::<ImageResource>(invalid).is_err());
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for browsing archives through a local web server, see [`serve`]
+
+use crate::disk::{self, without_fragment, Links, Rewriter};
+use crate::error::Error;
+use crate::javascript::resolve_specifier;
+use crate::page_archive::PageArchive;
+use crate::parsing::{resolve_url, Resource};
+use crate::site::SiteArchive;
+use bytes::Bytes;
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use url::Url;
+
+/// The start of the paths which resources from origins other than the
+/// first page's are served at, followed by their scheme, host and path,
+/// e.g. `/_web-archive/https/cdn.example.com/lib.js`
+const OTHER_ORIGIN: &str = "/_web-archive/";
+
+/// Serve an archived page or site at `addr`, so that it can be browsed
+/// locally without writing it out to disk first.
+///
+/// The pages and resources are served at their original paths, relative
+/// to the origin of the first page, e.g. `http://example.com/about` at
+/// `http://127.0.0.1:8080/about`, with resources from other origins
+/// under `/_web-archive/`. The URLs in the pages, stylesheets and modules
+/// are pointed at the server if they were archived, or left pointing at
+/// the live site otherwise. The root redirects to the first page if it
+/// isn't at the root already.
+///
+/// This runs until the server fails, or the future is dropped. An error
+/// is returned if `addr` can't be listened on.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{blocking, serve};
+///
+/// # async fn serve_async() {
+/// let archive =
+///     blocking::archive("http://example.com", Default::default()).unwrap();
+/// let addr = "127.0.0.1:8080".parse().unwrap();
+/// serve(archive, addr).await.unwrap();
+/// # }
+/// ```
+pub async fn serve<A>(archive: A, addr: SocketAddr) -> Result<(), Error>
+where
+    A: Into<SiteArchive>,
+{
+    let replay = Arc::new(Replay::new(&archive.into()));
+    let make_service = make_service_fn(move |_| {
+        let replay = replay.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let replay = replay.clone();
+                async move { Ok::<_, Infallible>(replay.respond(&request)) }
+            }))
+        }
+    });
+    Server::try_bind(&addr)
+        .map_err(|e| Error::Serve(e.to_string()))?
+        .serve(make_service)
+        .await
+        .map_err(|e| Error::Serve(e.to_string()))
+}
+
+/// The responses that the server gives, for the pages and resources of a
+/// site
+struct Replay {
+    /// The pages alongside the resources, see [`disk::combined`]
+    archive: PageArchive,
+    /// The key of each page and resource in the archive's resource map,
+    /// keyed by their URLs without fragments, including the URLs which
+    /// redirected to the pages
+    urls: HashMap<Url, Url>,
+}
+
+impl Replay {
+    fn new(site: &SiteArchive) -> Self {
+        let archive = disk::combined(site);
+        let mut urls: HashMap<Url, Url> = archive
+            .resource_map
+            .keys()
+            .map(|url| (without_fragment(url), url.clone()))
+            .collect();
+        for (requested, chain) in &archive.redirects {
+            if let Some(page) = chain.last() {
+                let through = &chain[..chain.len() - 1];
+                for url in Some(requested).into_iter().chain(through) {
+                    let url = without_fragment(url);
+                    urls.entry(url).or_insert_with(|| page.clone());
+                }
+            }
+        }
+        Self { archive, urls }
+    }
+
+    /// The response to `request`
+    fn respond(&self, request: &Request<Body>) -> Response<Body> {
+        let path = request
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str());
+        let url = match self.url(path) {
+            Some(url) => url,
+            None => return not_found(path),
+        };
+        let key = match self.urls.get(&url) {
+            Some(key) => key,
+            None if path == "/" && !self.urls.is_empty() => {
+                let first = local_path(&self.archive.url, &self.archive.url);
+                return Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(LOCATION, first)
+                    .body(Body::empty())
+                    .unwrap();
+            }
+            None => return not_found(path),
+        };
+        let resource = &self.archive.resource_map[key];
+        let rewriter = Rewriter::new(self, &self.archive);
+        let text = match resource {
+            Resource::Document(page) => rewriter.html(key, &page.text()),
+            Resource::Css(css) => rewriter.css(key, &css.text()),
+            Resource::Javascript(js) => rewriter.js(key, &js.text()),
+            _ => return respond(resource.mimetype(), resource.data().clone()),
+        };
+        let content_type = format!("{}; charset=utf-8", resource.mimetype());
+        respond(&content_type, text.into())
+    }
+
+    /// The original URL of the page or resource served at `path`
+    fn url(&self, path: &str) -> Option<Url> {
+        let url = match path.strip_prefix(OTHER_ORIGIN) {
+            Some(rest) => {
+                let (scheme, rest) = rest.split_once('/')?;
+                Url::parse(&format!("{}://{}", scheme, rest)).ok()?
+            }
+            None => self.archive.url.join(path).ok()?,
+        };
+        Some(without_fragment(&url))
+    }
+}
+
+/// Points URLs at the server if they were archived
+impl Links for &Replay {
+    fn url(&self, base: &Url, u: &str) -> Option<(String, bool)> {
+        let url = resolve_url(base, u)?;
+        if self.urls.contains_key(&without_fragment(&url)) {
+            Some((local_path(&self.archive.url, &url), true))
+        } else {
+            Some((url.to_string(), false))
+        }
+    }
+
+    fn import(&self, base: &Url, specifier: &str) -> Option<String> {
+        let url = resolve_specifier(base, specifier)?;
+        if self.urls.contains_key(&without_fragment(&url)) {
+            Some(local_path(&self.archive.url, &url))
+        } else {
+            Some(url.to_string())
+        }
+    }
+}
+
+/// The path that `url` is served at, when the first page is at `first`
+fn local_path(first: &Url, url: &Url) -> String {
+    let mut path = if url.origin() == first.origin() {
+        url.path().to_string()
+    } else {
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (host, _) => host.unwrap_or("").to_string(),
+        };
+        format!("{}{}/{}{}", OTHER_ORIGIN, url.scheme(), host, url.path())
+    };
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+    if let Some(fragment) = url.fragment() {
+        path.push('#');
+        path.push_str(fragment);
+    }
+    path
+}
+
+fn respond(content_type: &str, body: Bytes) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn not_found(path: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(format!("{} wasn't archived", path)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::{ImageResource, TextResource};
+    use tokio_test::block_on;
+
+    fn site() -> SiteArchive {
+        let url = Url::parse("http://example.com/blog/").unwrap();
+        let mut resource_map = HashMap::new();
+        resource_map.insert(
+            Url::parse("http://example.com/style.css").unwrap(),
+            Resource::Css(TextResource {
+                data: "body { background: url(http://cdn.example.org/bg.png) }"
+                    .into(),
+                mimetype: "text/css".to_string(),
+                charset: None,
+            }),
+        );
+        resource_map.insert(
+            Url::parse("http://cdn.example.org/bg.png").unwrap(),
+            Resource::Image(ImageResource {
+                data: "png".into(),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let mut redirects = HashMap::new();
+        redirects.insert(
+            Url::parse("http://example.com/blog").unwrap(),
+            vec![url.clone()],
+        );
+        let page = PageArchive {
+            url,
+            content: "<link rel=\"stylesheet\" href=\"/style.css\">\
+                <a href=\"/blog#top\">Blog</a>\
+                <a href=\"http://example.com/about\">About</a>"
+                .to_string(),
+            resource_map,
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects,
+            meta: HashMap::new(),
+            stats: Default::default(),
+            screenshot: None,
+        };
+        page.into()
+    }
+
+    fn get(replay: &Replay, path: &str) -> (u16, String, String) {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        let response = replay.respond(&request);
+        let status = response.status().as_u16();
+        let header = |name| {
+            let value = response.headers().get(name);
+            value
+                .map_or("", |value| value.to_str().unwrap())
+                .to_string()
+        };
+        let content_type = match status {
+            302 => header(LOCATION),
+            _ => header(CONTENT_TYPE),
+        };
+        let body = block_on(hyper::body::to_bytes(response.into_body()));
+        let body = String::from_utf8(body.unwrap().to_vec()).unwrap();
+        (status, content_type, body)
+    }
+
+    #[test]
+    fn test_replay() {
+        let replay = Replay::new(&site());
+
+        let (status, content_type, page) = get(&replay, "/blog/");
+        assert_eq!(status, 200);
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert!(page.contains("href=\"/style.css\""));
+        assert!(page.contains("href=\"/blog#top\""));
+        assert!(page.contains("href=\"http://example.com/about\""));
+        assert_eq!(get(&replay, "/blog").0, 200);
+
+        let (_, content_type, css) = get(&replay, "/style.css");
+        assert_eq!(content_type, "text/css; charset=utf-8");
+        assert!(
+            css.contains("url(\"/_web-archive/http/cdn.example.org/bg.png\")")
+        );
+        let (status, content_type, png) =
+            get(&replay, "/_web-archive/http/cdn.example.org/bg.png");
+        assert_eq!((status, content_type.as_str()), (200, "image/png"));
+        assert_eq!(png, "png");
+
+        assert_eq!(get(&replay, "/").1, "/blog/");
+        assert_eq!(get(&replay, "/about").0, 404);
+        assert_eq!(get(&replay, "/_web-archive/nonsense").0, 404);
+    }
+}
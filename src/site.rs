@@ -0,0 +1,439 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for archives of many pages sharing their resources, see
+//! [`SiteArchive`]
+
+use crate::failure::{FailureReason, ResourceFailure};
+use crate::feed::FeedEntry;
+use crate::page_archive::{EmbedOptions, PageArchive, WriteOptions};
+use crate::parsing::ResourceMap;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::iter::FromIterator;
+use std::path::Path;
+use url::Url;
+
+/// Many pages of a site archived together, with a single store of the
+/// resources that they use. A stylesheet or script which every page
+/// uses is only held once, however many pages there are, and so is data
+/// which is the same at different URLs, e.g. with a cache-busting query
+/// string.
+///
+/// Pages can be taken back out as [`PageArchive`]s of their own, embedded
+/// one at a time, or the whole site written out to disk with links
+/// between its pages pointing at each other.
+///
+/// ## Example
+/// ```no_run
+/// use web_archive::{blocking, SiteArchive};
+///
+/// let mut site = SiteArchive::new();
+/// for &url in &["http://example.com/", "http://example.com/about"] {
+///     site.add_page(blocking::archive(url, Default::default()).unwrap());
+/// }
+/// site.write_to_disk(&"example").unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct SiteArchive {
+    /// The pages in the order they were added, each without its resources
+    pages: Vec<SitePage>,
+    /// The resources of every page, keyed by their URLs
+    resources: ResourceMap,
+    /// The URL of a resource holding each piece of data, keyed by its
+    /// SHA-256 hash, for sharing the data between resources at other
+    /// URLs
+    hashes: HashMap<[u8; 32], Url>,
+    /// Pages which couldn't be archived while crawling the site
+    pub(crate) failures: Vec<ResourceFailure>,
+    /// Pages which weren't crawled because `robots.txt` disallowed them
+    pub(crate) disallowed: Vec<Url>,
+    /// The feed entries whose pages were archived by `archive_feed`
+    pub(crate) entries: Vec<FeedEntry>,
+}
+
+#[derive(Debug)]
+struct SitePage {
+    /// The page, with its resources moved into the site's store
+    archive: PageArchive,
+    /// The URLs of the resources that it uses
+    resources: Vec<Url>,
+}
+
+impl SiteArchive {
+    /// An archive with no pages in it yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a page to the site, moving its resources into the shared
+    /// store. A page which was archived from the same URL as one in the
+    /// site already replaces it.
+    ///
+    /// Where a page has a different version of a resource than the one in
+    /// the store, e.g. because it changed between the pages being
+    /// archived, the page added last wins.
+    pub fn add_page(&mut self, mut archive: PageArchive) {
+        let resource_map = std::mem::take(&mut archive.resource_map);
+        let mut resources = Vec::with_capacity(resource_map.len());
+        for (url, mut resource) in resource_map {
+            let hash: [u8; 32] = Sha256::digest(resource.data()).into();
+            let same = self
+                .hashes
+                .get(&hash)
+                .and_then(|url| self.resources.get(url))
+                .filter(|other| other.data() == resource.data());
+            match same {
+                Some(other) => *resource.data_mut() = other.data().clone(),
+                None => {
+                    self.hashes.insert(hash, url.clone());
+                }
+            }
+            self.resources.insert(url.clone(), resource);
+            resources.push(url);
+        }
+        let page = SitePage { archive, resources };
+        match self.position(&page.archive.url) {
+            Some(i) => self.pages[i] = page,
+            None => self.pages.push(page),
+        }
+    }
+
+    /// The number of pages in the site
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Whether the site has no pages
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// The URLs of the pages, in the order they were added
+    pub fn urls(&self) -> impl Iterator<Item = &Url> {
+        self.pages.iter().map(|page| &page.archive.url)
+    }
+
+    /// The resources of every page in the site, each held once
+    pub fn resources(&self) -> &ResourceMap {
+        &self.resources
+    }
+
+    /// The pages which [`crawl`](crate::crawl) couldn't archive, such as
+    /// those which weren't found. Links to them are left pointing at
+    /// their original URLs.
+    pub fn failures(&self) -> &[ResourceFailure] {
+        &self.failures
+    }
+
+    /// The pages which [`crawl`](crate::crawl) didn't archive because
+    /// their sites' `robots.txt` files disallowed them, see
+    /// [`CrawlOptions::ignore_robots`](crate::CrawlOptions::ignore_robots)
+    pub fn disallowed(&self) -> &[Url] {
+        &self.disallowed
+    }
+
+    /// The entries of the feed whose pages
+    /// [`archive_feed`](crate::archive_feed) archived, in the order the
+    /// feed lists them
+    pub fn entries(&self) -> &[FeedEntry] {
+        &self.entries
+    }
+
+    /// A copy of the page of the feed entry with the `id` given, like
+    /// [`SiteArchive::page`], or `None` if there's no such entry
+    pub fn entry_page(&self, id: &str) -> Option<PageArchive> {
+        let entry = self.entries.iter().find(|entry| entry.id == id)?;
+        self.page(&entry.url)
+    }
+
+    /// Whether the site has the page archived from `url`, or redirected
+    /// from it
+    pub fn contains(&self, url: &Url) -> bool {
+        self.position(url).is_some()
+    }
+
+    /// A copy of the page archived from `url`, or redirected from it,
+    /// along with the resources it uses, or `None` if it isn't in the
+    /// site. The copy shares the resources' data with the site rather
+    /// than duplicating it.
+    pub fn page(&self, url: &Url) -> Option<PageArchive> {
+        let page = &self.pages[self.position(url)?];
+        let archive = &page.archive;
+        Some(PageArchive {
+            url: archive.url.clone(),
+            content: archive.content.clone(),
+            resource_map: page
+                .resources
+                .iter()
+                .filter_map(|url| {
+                    let resource = self.resources.get(url)?;
+                    Some((url.clone(), resource.clone()))
+                })
+                .collect(),
+            lazy_attributes: archive.lazy_attributes.clone(),
+            url_attributes: archive.url_attributes.clone(),
+            timed_out: archive.timed_out.clone(),
+            failures: archive.failures.clone(),
+            cancelled: archive.cancelled,
+            redirects: archive.redirects.clone(),
+            meta: archive.meta.clone(),
+            stats: archive.stats.clone(),
+            screenshot: archive.screenshot.clone(),
+        })
+    }
+
+    /// Embed the resources into the page archived from `url`, as
+    /// [`PageArchive::embed_resources_with`] does, or `None` if it isn't
+    /// in the site
+    pub fn embed_page(
+        &self,
+        url: &Url,
+        options: &EmbedOptions,
+    ) -> Option<String> {
+        Some(self.page(url)?.embed_resources_with(options))
+    }
+
+    /// Write the whole site out to the directory specified, creating it
+    /// if need be, so that it can be browsed from disk.
+    ///
+    /// The pages and resources are written under `resources/` in the same
+    /// way as [`PageArchive::write_to_disk`] writes a page's resources,
+    /// with each resource written once however many pages use it. Links
+    /// between pages in the site, including links to URLs which were
+    /// redirected to one of them, are pointed at each other's files so
+    /// that the site can be browsed offline, while links to pages outside
+    /// it are made absolute. An `index.html` listing the pages is written
+    /// alongside, along with a `SHA256SUMS` giving the digests of the
+    /// files like [`PageArchive::write_to_disk`].
+    pub fn write_to_disk<P: AsRef<Path>>(
+        &self,
+        output_dir: &P,
+    ) -> Result<(), io::Error> {
+        self.write_to_disk_with(output_dir, &WriteOptions::default())
+    }
+
+    /// Write the whole site out to the directory specified, like
+    /// [`SiteArchive::write_to_disk`], with [`WriteOptions`]
+    pub fn write_to_disk_with<P: AsRef<Path>>(
+        &self,
+        output_dir: &P,
+        options: &WriteOptions,
+    ) -> Result<(), io::Error> {
+        crate::disk::write_site(self, output_dir.as_ref(), options.sync)
+    }
+
+    /// Add a page archived from `url`, unless it has an unsuccessful
+    /// status, in which case it's recorded as a failure instead
+    pub(crate) fn add_archived(&mut self, url: Url, archive: PageArchive) {
+        let status = archive.meta.get(&archive.url).map(|meta| meta.status);
+        match status {
+            Some(status) if status >= 400 => {
+                self.failed(url, FailureReason::Status(status))
+            }
+            _ => self.add_page(archive),
+        }
+    }
+
+    /// Record that the page at `url` couldn't be archived
+    pub(crate) fn failed(&mut self, url: Url, reason: FailureReason) {
+        self.failures.push(ResourceFailure {
+            url,
+            reason,
+            retries: 0,
+        });
+    }
+
+    /// The pages in the order they were added, without their resources
+    pub(crate) fn pages(&self) -> impl Iterator<Item = &PageArchive> {
+        self.pages.iter().map(|page| &page.archive)
+    }
+
+    /// Where the page archived from `url`, or redirected from it, is in
+    /// the list of pages
+    fn position(&self, url: &Url) -> Option<usize> {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        self.pages.iter().position(|page| {
+            page.archive.url == url || page.archive.redirects.contains_key(&url)
+        })
+    }
+}
+
+impl From<PageArchive> for SiteArchive {
+    fn from(archive: PageArchive) -> Self {
+        let mut site = SiteArchive::new();
+        site.add_page(archive);
+        site
+    }
+}
+
+impl FromIterator<PageArchive> for SiteArchive {
+    fn from_iter<I: IntoIterator<Item = PageArchive>>(iter: I) -> Self {
+        let mut site = SiteArchive::new();
+        site.extend(iter);
+        site
+    }
+}
+
+impl Extend<PageArchive> for SiteArchive {
+    fn extend<I: IntoIterator<Item = PageArchive>>(&mut self, iter: I) {
+        for archive in iter {
+            self.add_page(archive);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::{ImageResource, Resource, TextResource};
+    use bytes::Bytes;
+    use std::fs;
+
+    fn page(
+        url: &str,
+        content: &str,
+        resources: &[(&str, &[u8])],
+    ) -> PageArchive {
+        let url = Url::parse(url).unwrap();
+        let mut resource_map = ResourceMap::new();
+        for (resource, data) in resources {
+            let resource_url = url.join(resource).unwrap();
+            let data = Bytes::copy_from_slice(data);
+            let resource = if resource.ends_with(".css") {
+                Resource::Css(TextResource {
+                    data,
+                    mimetype: "text/css".to_string(),
+                    charset: None,
+                })
+            } else {
+                Resource::Image(ImageResource {
+                    data,
+                    mimetype: "image/png".to_string(),
+                })
+            };
+            resource_map.insert(resource_url, resource);
+        }
+        PageArchive {
+            url,
+            content: content.to_string(),
+            resource_map,
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            screenshot: None,
+        }
+    }
+
+    fn site() -> SiteArchive {
+        let head =
+            "<title>Home</title><link rel=\"stylesheet\" href=\"/style.css\">";
+        let mut about = page(
+            "http://example.com/about",
+            "<link rel=\"stylesheet\" href=\"/style.css\">\
+            <a href=\"/\">Home</a><img src=\"logo.png?v=2\">",
+            &[
+                ("/style.css", b"a { color: red }"),
+                ("logo.png?v=2", b"png"),
+            ],
+        );
+        about.redirects.insert(
+            Url::parse("http://example.com/team").unwrap(),
+            vec![about.url.clone()],
+        );
+        vec![
+            page(
+                "http://example.com/",
+                &format!(
+                    "{}<a href=\"/about\">About</a><img src=\"logo.png?v=1\">\
+                    <a href=\"/team#jane\">Jane</a>\
+                    <a href=\"/contact\">Contact</a>",
+                    head
+                ),
+                &[
+                    ("/style.css", b"a { color: red }"),
+                    ("logo.png?v=1", b"png"),
+                ],
+            ),
+            about,
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_site_archive() {
+        let mut site = site();
+        assert_eq!(site.len(), 2);
+        assert_eq!(site.resources().len(), 3);
+        let url = |u| Url::parse(u).unwrap();
+        let logos: Vec<_> = [
+            "http://example.com/logo.png?v=1",
+            "http://example.com/logo.png?v=2",
+        ]
+        .iter()
+        .map(|logo| site.resources()[&url(logo)].data().as_ptr())
+        .collect();
+        assert_eq!(logos[0], logos[1], "identical data is shared");
+
+        let about = site.page(&url("http://example.com/about#team")).unwrap();
+        assert_eq!(about.url.as_str(), "http://example.com/about");
+        assert_eq!(about.resource_map.len(), 2);
+        let embedded = site
+            .embed_page(&url("http://example.com/about"), &Default::default())
+            .unwrap();
+        assert!(embedded.contains("a { color: red }"));
+        assert!(site.page(&url("http://example.com/missing")).is_none());
+
+        site.add_page(page("http://example.com/about", "Replaced", &[]));
+        assert_eq!(site.len(), 2);
+        assert_eq!(
+            site.page(&url("http://example.com/about")).unwrap().content,
+            "Replaced"
+        );
+        let urls: Vec<_> = site.urls().map(Url::as_str).collect();
+        assert_eq!(
+            urls,
+            vec!["http://example.com/", "http://example.com/about"]
+        );
+    }
+
+    #[test]
+    fn test_write_site() {
+        let dir = std::env::temp_dir()
+            .join(format!("web-archive-site-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        site().write_to_disk(&dir).unwrap();
+
+        let read = |path: &str| fs::read_to_string(dir.join(path)).unwrap();
+        let index = read("index.html");
+        assert!(index
+            .contains("<a href=\"resources/example.com/index.html\">Home</a>"));
+        assert!(index.contains(
+            "<a href=\"resources/example.com/about.html\">http://example.com/about</a>"
+        ));
+        let home = read("resources/example.com/index.html");
+        assert!(home.contains("href=\"style.css\""));
+        assert!(home.contains("href=\"about.html\""));
+        assert!(home.contains("href=\"about.html#jane\""));
+        assert!(home.contains("href=\"http://example.com/contact\""));
+        let about = read("resources/example.com/about.html");
+        assert!(about.contains("href=\"index.html\""));
+        assert_eq!(read("resources/example.com/style.css"), "a { color: red }");
+        assert!(read("SHA256SUMS")
+            .lines()
+            .any(|line| line.ends_with("  resources/example.com/style.css")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,626 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for archiving the pages listed by a site's sitemap, see
+//! [`archive_sitemap`]
+
+use crate::archiver::Archiver;
+use crate::cancel::CancellationToken;
+use crate::date;
+use crate::error::Error;
+use crate::failure::FailureReason;
+use crate::page_archive::PageArchive;
+use crate::site::SiteArchive;
+use crate::xml;
+use crate::ArchiveOptions;
+use flate2::read::GzDecoder;
+use regex::Regex;
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryInto;
+use std::fmt::Display;
+use std::io::Read;
+use std::time::SystemTime;
+use url::Url;
+
+/// How many levels of sitemap indexes to follow. The protocol doesn't
+/// allow indexes to list other indexes, but some sites do anyway.
+const MAX_NESTING: usize = 3;
+/// Largest sitemap to read once decompressed, in bytes, which is the
+/// protocol's limit. Anything larger is more likely to be a gzip bomb.
+const MAX_SIZE: u64 = 50 * 1024 * 1024;
+
+/// Configuration options for [`archive_sitemap`], controlling which of
+/// the listed pages are archived
+#[derive(Default)]
+pub struct SitemapOptions<'a> {
+    /// The options used to archive each page
+    ///
+    /// Default: [`ArchiveOptions::default`]
+    pub archive: ArchiveOptions<'a>,
+    /// Only archive pages whose URLs match at least one of these
+    /// patterns, unless there are none
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{Regex, SitemapOptions};
+    /// let options = SitemapOptions {
+    ///     include: vec![Regex::new("/blog/").unwrap()],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub include: Vec<Regex>,
+    /// Don't archive pages whose URLs match any of these patterns
+    ///
+    /// Default: `[]`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::{Regex, SitemapOptions};
+    /// let options = SitemapOptions {
+    ///     exclude: vec![Regex::new(r"/tag/").unwrap()],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub exclude: Vec<Regex>,
+    /// Only archive pages which the sitemap says were modified at or
+    /// after this time, in their `<lastmod>`. Pages without one are
+    /// archived regardless, while sitemaps listed by an index which
+    /// weren't modified since aren't fetched at all.
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    /// use web_archive::SitemapOptions;
+    /// let week = Duration::from_secs(7 * 24 * 60 * 60);
+    /// let options = SitemapOptions {
+    ///     modified_since: Some(SystemTime::now() - week),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub modified_since: Option<SystemTime>,
+    /// The most pages to archive, in the order the sitemaps list them,
+    /// or `None` for no limit
+    ///
+    /// Default: `None`
+    ///
+    /// ## Example
+    /// ```
+    /// use web_archive::SitemapOptions;
+    /// let options = SitemapOptions {
+    ///     max_pages: Some(500),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub max_pages: Option<usize>,
+}
+
+impl<'a> SitemapOptions<'a> {
+    /// Archive each page with the options, see
+    /// [`SitemapOptions::archive`](#structfield.archive)
+    pub fn archive(mut self, options: ArchiveOptions<'a>) -> Self {
+        self.archive = options;
+        self
+    }
+
+    /// Only archive pages matching `pattern`, or any of the other
+    /// patterns included, see
+    /// [`SitemapOptions::include`](#structfield.include)
+    pub fn include(mut self, pattern: Regex) -> Self {
+        self.include.push(pattern);
+        self
+    }
+
+    /// Don't archive pages matching `pattern`, see
+    /// [`SitemapOptions::exclude`](#structfield.exclude)
+    pub fn exclude(mut self, pattern: Regex) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
+
+    /// Only archive pages modified since `time`, see
+    /// [`SitemapOptions::modified_since`](#structfield.modified_since)
+    pub fn modified_since(mut self, time: SystemTime) -> Self {
+        self.modified_since = Some(time);
+        self
+    }
+
+    /// Set the most pages to archive, see
+    /// [`SitemapOptions::max_pages`](#structfield.max_pages)
+    pub fn max_pages(mut self, n: usize) -> Self {
+        self.max_pages = Some(n);
+        self
+    }
+}
+
+/// Archive the pages listed by the sitemap at `url`, such as
+/// `http://example.com/sitemap.xml`, into a [`SiteArchive`].
+///
+/// Sitemap indexes are followed to the sitemaps that they list, and
+/// sitemaps may be gzipped, or plain text files listing one URL per
+/// line. Pages are archived one after another in the order they're
+/// listed, with an [`Archiver`] so that resources shared between them
+/// are only downloaded once, and filtered as the options say.
+///
+/// An error is returned if the sitemap at `url` can't be fetched or
+/// read. Pages which can't be archived, including those with an
+/// unsuccessful status code, and sitemaps listed by an index which can't
+/// be fetched or read, are recorded in [`SiteArchive::failures`]. If the
+/// options' [`cancellation`](ArchiveOptions::cancellation) token is
+/// cancelled, the pages archived so far are returned.
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::{archive_sitemap, Regex, SitemapOptions};
+///
+/// # async fn archive_sitemap_async() {
+/// let options =
+///     SitemapOptions::default().include(Regex::new("/blog/").unwrap());
+/// let site = archive_sitemap("http://example.com/sitemap.xml", options)
+///     .await
+///     .unwrap();
+/// site.write_to_disk(&"example").unwrap();
+/// # }
+/// ```
+pub async fn archive_sitemap<U>(
+    url: U,
+    options: SitemapOptions<'_>,
+) -> Result<SiteArchive, Error>
+where
+    U: TryInto<Url>,
+    <U as TryInto<Url>>::Error: Display,
+{
+    let url: Url = url
+        .try_into()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let mut sitemaps = Sitemaps::new(url, &options);
+    let archiver = Archiver::new(options.archive)?;
+    while let Some((url, depth)) = sitemaps.next_sitemap() {
+        let response = fetch_sitemap(archiver.client(), &url).await;
+        sitemaps.fetched(url, depth, response)?;
+    }
+    while let Some(url) = sitemaps.next_page() {
+        let archive = archiver.archive(url.clone()).await;
+        sitemaps.archived(url, archive);
+    }
+    Ok(sitemaps.finish())
+}
+
+/// Fetch a sitemap, returning its status and contents
+async fn fetch_sitemap(
+    client: &reqwest::Client,
+    url: &Url,
+) -> Result<(u16, Vec<u8>), Error> {
+    let response = client.get(url.clone()).send().await?;
+    let status = response.status().as_u16();
+    Ok((status, response.bytes().await?.to_vec()))
+}
+
+/// The state of archiving a sitemap, shared between the async and
+/// blocking APIs, which hand out the sitemaps to fetch and then the
+/// pages to archive
+pub(crate) struct Sitemaps {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    modified_since: Option<SystemTime>,
+    max_pages: Option<usize>,
+    cancellation: Option<CancellationToken>,
+    /// Sitemaps waiting to be fetched, with how many indexes deep they are
+    queue: VecDeque<(Url, usize)>,
+    /// Sitemaps which have been queued
+    seen_sitemaps: HashSet<Url>,
+    /// Pages waiting to be archived, in the order they were listed
+    pages: VecDeque<Url>,
+    /// Pages which have been listed, without their fragments
+    seen_pages: HashSet<Url>,
+    /// Whether the first sitemap has been fetched
+    started: bool,
+    cancelled: bool,
+    site: SiteArchive,
+}
+
+impl Sitemaps {
+    pub fn new(url: Url, options: &SitemapOptions) -> Self {
+        Self {
+            include: options.include.clone(),
+            exclude: options.exclude.clone(),
+            modified_since: options.modified_since,
+            max_pages: options.max_pages,
+            cancellation: options.archive.cancellation.clone(),
+            queue: vec![(url.clone(), 0)].into(),
+            seen_sitemaps: vec![url].into_iter().collect(),
+            pages: VecDeque::new(),
+            seen_pages: HashSet::new(),
+            started: false,
+            cancelled: false,
+            site: SiteArchive::new(),
+        }
+    }
+
+    /// The next sitemap to fetch and how many indexes deep it is, or
+    /// `None` once every page to archive has been found
+    pub fn next_sitemap(&mut self) -> Option<(Url, usize)> {
+        let full = self
+            .max_pages
+            .map_or(false, |max| self.seen_pages.len() >= max);
+        if full || self.is_cancelled() {
+            return None;
+        }
+        self.queue.pop_front()
+    }
+
+    /// Read the sitemap fetched from `url`, queueing the sitemaps or
+    /// pages it lists. Only an error fetching or reading the first
+    /// sitemap is returned.
+    pub fn fetched(
+        &mut self,
+        url: Url,
+        depth: usize,
+        response: Result<(u16, Vec<u8>), Error>,
+    ) -> Result<(), Error> {
+        let first = !std::mem::replace(&mut self.started, true);
+        let read = response
+            .map_err(FailureReason::from_error)
+            .and_then(|(status, data)| read_sitemap(status, &data));
+        let sitemap = match read {
+            Ok(sitemap) => sitemap,
+            Err(reason) if first => {
+                let message = format!("{}: {}", url, reason);
+                return Err(match reason {
+                    FailureReason::Unreadable(_) => Error::ParseError(message),
+                    _ => Error::ReqwestError(message),
+                });
+            }
+            Err(reason) => {
+                self.site.failed(url, reason);
+                return Ok(());
+            }
+        };
+
+        match sitemap {
+            Sitemap::Index(sitemaps) if depth < MAX_NESTING => {
+                for entry in sitemaps {
+                    let listed = match url.join(&entry.loc) {
+                        Ok(listed) => listed,
+                        Err(_) => continue,
+                    };
+                    if self.is_modified(&entry)
+                        && self.seen_sitemaps.insert(listed.clone())
+                    {
+                        self.queue.push_back((listed, depth + 1));
+                    }
+                }
+            }
+            Sitemap::Index(_) => {}
+            Sitemap::Pages(pages) => {
+                for entry in pages {
+                    let mut listed = match url.join(&entry.loc) {
+                        Ok(listed) => listed,
+                        Err(_) => continue,
+                    };
+                    listed.set_fragment(None);
+                    let full = self
+                        .max_pages
+                        .map_or(false, |max| self.seen_pages.len() >= max);
+                    if full {
+                        break;
+                    }
+                    if self.is_wanted(&listed, &entry)
+                        && self.seen_pages.insert(listed.clone())
+                    {
+                        self.pages.push_back(listed);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The next page to archive, or `None` once they've all been archived
+    pub fn next_page(&mut self) -> Option<Url> {
+        if self.is_cancelled() {
+            return None;
+        }
+        self.pages.pop_front()
+    }
+
+    /// Record how archiving the page at `url` went
+    pub fn archived(&mut self, url: Url, archive: Result<PageArchive, Error>) {
+        match archive {
+            Ok(archive) => {
+                self.cancelled |= archive.cancelled;
+                self.site.add_archived(url, archive);
+            }
+            Err(Error::Cancelled) => self.cancelled = true,
+            Err(e) => self.site.failed(url, FailureReason::from_error(e)),
+        }
+    }
+
+    /// The pages archived
+    pub fn finish(self) -> SiteArchive {
+        self.site
+    }
+
+    fn is_cancelled(&self) -> bool {
+        let cancelled = self
+            .cancellation
+            .as_ref()
+            .map_or(false, CancellationToken::is_cancelled);
+        self.cancelled || cancelled
+    }
+
+    /// Whether a sitemap or page has been modified since the options'
+    /// `modified_since`, going by its `lastmod`
+    fn is_modified(&self, entry: &Entry) -> bool {
+        let since = match self.modified_since {
+            Some(since) => since,
+            None => return true,
+        };
+        entry
+            .lastmod
+            .as_deref()
+            .and_then(date::parse_w3c)
+            .map_or(true, |lastmod| lastmod >= since)
+    }
+
+    /// Whether a listed page should be archived
+    fn is_wanted(&self, url: &Url, entry: &Entry) -> bool {
+        let url_str = url.as_str();
+        let included = self.include.is_empty()
+            || self.include.iter().any(|pattern| pattern.is_match(url_str));
+        let excluded =
+            self.exclude.iter().any(|pattern| pattern.is_match(url_str));
+        matches!(url.scheme(), "http" | "https")
+            && included
+            && !excluded
+            && self.is_modified(entry)
+    }
+}
+
+/// What a sitemap lists
+#[derive(Debug, PartialEq)]
+enum Sitemap {
+    /// Other sitemaps, from a sitemap index
+    Index(Vec<Entry>),
+    Pages(Vec<Entry>),
+}
+
+/// A sitemap or page listed by a sitemap
+#[derive(Debug, PartialEq)]
+struct Entry {
+    loc: String,
+    lastmod: Option<String>,
+}
+
+/// Read a sitemap from its response, which may be gzipped, and either
+/// XML or a plain text list of URLs
+fn read_sitemap(status: u16, data: &[u8]) -> Result<Sitemap, FailureReason> {
+    if status >= 400 {
+        return Err(FailureReason::Status(status));
+    }
+    let mut decompressed = Vec::new();
+    let data = if data.starts_with(&[0x1f, 0x8b]) {
+        // Read one byte past the limit to tell whether it was exceeded
+        GzDecoder::new(data)
+            .take(MAX_SIZE + 1)
+            .read_to_end(&mut decompressed)
+            .map_err(|e| FailureReason::Unreadable(e.to_string()))?;
+        if decompressed.len() as u64 > MAX_SIZE {
+            return Err(FailureReason::Unreadable(
+                "sitemap larger than 50 MiB".to_string(),
+            ));
+        }
+        &decompressed[..]
+    } else {
+        data
+    };
+    let text = String::from_utf8_lossy(data);
+    let text = text.trim_start_matches('\u{feff}').trim();
+
+    if !text.starts_with('<') {
+        let pages = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| Entry {
+                loc: line.to_string(),
+                lastmod: None,
+            })
+            .collect();
+        return Ok(Sitemap::Pages(pages));
+    }
+
+    let unreadable = || FailureReason::Unreadable("not a sitemap".to_string());
+    let root = xml::parse(text).ok_or_else(unreadable)?;
+    let (entry_name, index) = match root.local_name() {
+        "sitemapindex" => ("sitemap", true),
+        "urlset" => ("url", false),
+        _ => return Err(unreadable()),
+    };
+    let entries = root
+        .elements_named(entry_name)
+        .filter_map(|element| {
+            Some(Entry {
+                loc: element.child_text("loc")?,
+                lastmod: element.child_text("lastmod"),
+            })
+        })
+        .collect();
+    Ok(if index {
+        Sitemap::Index(entries)
+    } else {
+        Sitemap::Pages(entries)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::meta::ResourceMeta;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::collections::HashMap;
+    use std::io::Write;
+
+    const INDEX: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\
+        <sitemap><loc>http://example.com/posts.xml</loc>\
+        <lastmod>2021-03-01</lastmod></sitemap>\
+        <sitemap><loc>/old.xml</loc><lastmod>2019-01-01</lastmod></sitemap>\
+        <sitemap><loc>http://example.com/pages.txt.gz</loc></sitemap>\
+        <sitemap><loc>http://example.com/missing.xml</loc></sitemap>\
+        </sitemapindex>";
+
+    const POSTS: &str = "<urlset \
+        xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n\
+        <url><loc>http://example.com/blog/new</loc>\
+        <lastmod>2021-02-03T04:05:06+00:00</lastmod></url>\n\
+        <url><loc> http://example.com/blog/old </loc>\
+        <lastmod>2020-01-01</lastmod></url>\n\
+        <url><loc>http://example.com/blog/tag/news</loc></url>\n\
+        <url><loc>http://example.com/blog/new#comments</loc></url>\n\
+        <url><loc>ftp://example.com/blog/file</loc></url>\n\
+        <url><lastmod>2021-01-01</lastmod></url>\n\
+        </urlset>";
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn response(url: &Url) -> Result<(u16, Vec<u8>), Error> {
+        Ok(match url.path() {
+            "/sitemap.xml" => (200, INDEX.as_bytes().to_vec()),
+            "/posts.xml" => (200, POSTS.as_bytes().to_vec()),
+            "/old.xml" => (200, b"http://example.com/old".to_vec()),
+            "/pages.txt.gz" => (
+                200,
+                gzip(b"http://example.com/\r\n\nhttp://example.com/about\n"),
+            ),
+            _ => (404, Vec::new()),
+        })
+    }
+
+    fn page(url: &Url, status: u16) -> PageArchive {
+        let meta = ResourceMeta {
+            status,
+            final_url: url.clone(),
+            fetched_at: SystemTime::now(),
+            content_type: Some("text/html".to_string()),
+            cache_control: None,
+            validators: Default::default(),
+            snapshot: None,
+            sha256: None,
+        };
+        PageArchive {
+            url: url.clone(),
+            content: String::new(),
+            resource_map: HashMap::new(),
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: vec![(url.clone(), meta)].into_iter().collect(),
+            stats: Default::default(),
+            screenshot: None,
+        }
+    }
+
+    /// Archive the test sitemap, returning the pages requested and the
+    /// site
+    fn archive(options: SitemapOptions) -> (Vec<String>, SiteArchive) {
+        let url = Url::parse("http://example.com/sitemap.xml").unwrap();
+        let mut sitemaps = Sitemaps::new(url, &options);
+        while let Some((url, depth)) = sitemaps.next_sitemap() {
+            let response = response(&url);
+            sitemaps.fetched(url, depth, response).unwrap();
+        }
+        let mut requested = Vec::new();
+        while let Some(url) = sitemaps.next_page() {
+            requested.push(url.to_string());
+            let status = if url.path() == "/about" { 500 } else { 200 };
+            let archive = page(&url, status);
+            sitemaps.archived(url, Ok(archive));
+        }
+        (requested, sitemaps.finish())
+    }
+
+    #[test]
+    fn test_archive_sitemap() {
+        let (requested, site) = archive(Default::default());
+        assert_eq!(
+            requested,
+            vec![
+                "http://example.com/blog/new",
+                "http://example.com/blog/old",
+                "http://example.com/blog/tag/news",
+                "http://example.com/old",
+                "http://example.com/",
+                "http://example.com/about",
+            ]
+        );
+        assert_eq!(site.len(), 5);
+        let failures: Vec<_> = site
+            .failures()
+            .iter()
+            .map(|failure| (failure.url.as_str(), &failure.reason))
+            .collect();
+        assert_eq!(
+            failures,
+            vec![
+                (
+                    "http://example.com/missing.xml",
+                    &FailureReason::Status(404)
+                ),
+                ("http://example.com/about", &FailureReason::Status(500)),
+            ]
+        );
+
+        let since = date::parse_w3c("2021-01-01").unwrap();
+        let options = SitemapOptions::default()
+            .include(Regex::new("/blog/").unwrap())
+            .exclude(Regex::new("/tag/").unwrap())
+            .modified_since(since);
+        let (requested, _) = archive(options);
+        assert_eq!(requested, vec!["http://example.com/blog/new"]);
+
+        let (requested, _) = archive(SitemapOptions::default().max_pages(2));
+        assert_eq!(requested.len(), 2);
+    }
+
+    #[test]
+    fn test_first_sitemap_fails() {
+        let url = Url::parse("http://example.com/missing.xml").unwrap();
+        let options = SitemapOptions::default();
+        let mut sitemaps = Sitemaps::new(url, &options);
+        let (url, depth) = sitemaps.next_sitemap().unwrap();
+        let res = sitemaps.fetched(url, depth, Ok((404, Vec::new())));
+        assert!(matches!(res, Err(Error::ReqwestError(_))));
+
+        let url = Url::parse("http://example.com/feed.xml").unwrap();
+        let mut sitemaps = Sitemaps::new(url, &options);
+        let (url, depth) = sitemaps.next_sitemap().unwrap();
+        let res = sitemaps.fetched(url, depth, Ok((200, b"<rss/>".to_vec())));
+        assert!(matches!(res, Err(Error::ParseError(_))));
+    }
+
+    #[test]
+    fn test_gzip_bomb() {
+        let bomb = gzip(&vec![0; MAX_SIZE as usize + 1]);
+        assert!(matches!(
+            read_sitemap(200, &bomb),
+            Err(FailureReason::Unreadable(_))
+        ));
+    }
+}
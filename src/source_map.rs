@@ -0,0 +1,148 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for locating and rewriting the `sourceMappingURL` comments
+//! which link scripts and stylesheets to their source maps
+
+use crate::parsing::resolve_url;
+use std::ops::Range;
+use url::Url;
+
+const DIRECTIVE: &str = "sourceMappingURL=";
+
+/// A `sourceMappingURL` comment found within a script or stylesheet
+struct Comment {
+    /// Byte range of the whole comment
+    range: Range<usize>,
+    /// Byte range of the URL within the comment
+    url: Range<usize>,
+}
+
+/// Find the URL of the source map linked from a script or stylesheet,
+/// e.g. `app.js.map` from `//# sourceMappingURL=app.js.map`. Only the
+/// last such comment counts, as in browsers.
+pub(crate) fn find_url(text: &str) -> Option<&str> {
+    find_comment(text).map(|comment| &text[comment.url])
+}
+
+/// The absolute URL of the source map linked from a script or stylesheet
+/// downloaded from `base`, as long as it can be downloaded itself
+pub(crate) fn map_url(base: &Url, text: &str) -> Option<Url> {
+    resolve_url(base, find_url(text)?)
+}
+
+/// Replace the source map URL with the result of calling `replace` on
+/// it. If `replace` returns `None` then the whole comment is removed.
+pub(crate) fn rewrite<F>(text: &str, replace: F) -> String
+where
+    F: FnOnce(&str) -> Option<String>,
+{
+    let comment = match find_comment(text) {
+        Some(comment) => comment,
+        None => return text.to_string(),
+    };
+    match replace(&text[comment.url.clone()]) {
+        Some(url) => format!(
+            "{}{}{}",
+            &text[..comment.url.start],
+            url,
+            &text[comment.url.end..]
+        ),
+        None => format!(
+            "{}{}",
+            &text[..comment.range.start],
+            &text[comment.range.end..]
+        ),
+    }
+}
+
+fn find_comment(text: &str) -> Option<Comment> {
+    let mut end = text.len();
+    while let Some(directive) = text[..end].rfind(DIRECTIVE) {
+        end = directive;
+
+        // Both `//# ` and the deprecated `//@ ` are allowed, along with
+        // the `/* */` comments used by CSS
+        let start = match directive.checked_sub(4) {
+            Some(start) => start,
+            None => continue,
+        };
+        let prefix = match text.get(start..directive) {
+            Some(prefix) => prefix,
+            None => continue,
+        };
+        let block = match prefix {
+            "//# " | "//@ " => false,
+            "/*# " | "/*@ " => true,
+            _ => continue,
+        };
+
+        let url_start = directive + DIRECTIVE.len();
+        let rest = &text[url_start..];
+        let url_end = url_start
+            + rest
+                .find(|c: char| c.is_whitespace() || (block && c == '*'))
+                .unwrap_or(rest.len());
+        let comment_end = if block {
+            text[url_end..]
+                .find("*/")
+                .map(|i| url_end + i + 2)
+                .unwrap_or(text.len())
+        } else {
+            text[url_end..]
+                .find('\n')
+                .map(|i| url_end + i)
+                .unwrap_or(text.len())
+        };
+
+        return Some(Comment {
+            range: start..comment_end,
+            url: url_start..url_end,
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_url() {
+        assert_eq!(
+            find_url("go();\n//# sourceMappingURL=app.js.map\n"),
+            Some("app.js.map")
+        );
+        assert_eq!(
+            find_url("p {}\n/*# sourceMappingURL=style.css.map */"),
+            Some("style.css.map")
+        );
+        assert_eq!(find_url("//@ sourceMappingURL=old.map"), Some("old.map"));
+        assert_eq!(
+            find_url(
+                "//# sourceMappingURL=first.map\n//# sourceMappingURL=last.map"
+            ),
+            Some("last.map")
+        );
+        assert_eq!(find_url("var s = 'sourceMappingURL=nope';"), None);
+        assert_eq!(find_url("go();"), None);
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let js = "go();\n//# sourceMappingURL=app.js.map\n";
+        assert_eq!(
+            rewrite(js, |u| Some(format!("http://example.com/{}", u))),
+            "go();\n//# sourceMappingURL=http://example.com/app.js.map\n"
+        );
+        assert_eq!(rewrite(js, |_| None), "go();\n\n");
+
+        let css = "p {}\n/*# sourceMappingURL=style.css.map */\n";
+        assert_eq!(rewrite(css, |_| None), "p {}\n\n");
+        assert_eq!(rewrite("go();", |_| None), "go();");
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for timing the requests made while archiving
+
+use std::cmp::Reverse;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// How long a request for the page or a resource took, and how much it
+/// downloaded
+///
+/// The HTTP client doesn't report how long looking up the host and
+/// connecting to it took, so they're counted in the
+/// [`time_to_first_byte`](#structfield.time_to_first_byte), along with
+/// waiting for the server to respond. Connections are reused where
+/// possible, so later requests to a host usually have neither.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FetchTiming {
+    /// URL that was requested, before following any redirects
+    pub url: Url,
+    /// Time from sending the request until the response headers arrived,
+    /// including any redirects
+    pub time_to_first_byte: Duration,
+    /// Time from sending the request until the whole response arrived
+    pub total: Duration,
+    /// Size of the response body. A resumed download only counts the
+    /// rest of the resource, and a response confirming that a cached one
+    /// is unchanged counts nothing.
+    pub bytes: u64,
+}
+
+/// A summary of the requests made for a [`PageArchive`](crate::PageArchive),
+/// kept in [`PageArchive::stats`](crate::PageArchive::stats)
+///
+/// ## Example
+///
+/// ```no_run
+/// use web_archive::archive;
+///
+/// # async fn run() -> Result<(), web_archive::Error> {
+/// let archive = archive("http://example.com", Default::default()).await?;
+/// let stats = &archive.stats;
+/// println!("{} bytes in {:?}", stats.total_bytes(), stats.wall_time);
+/// for fetch in stats.slowest(5) {
+///     println!("{} took {:?}", fetch.url, fetch.total);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchiveStats {
+    /// Time from starting the archive until the last resource was stored
+    pub wall_time: Duration,
+    /// The requests which were answered, in the order that they finished.
+    /// Failed requests aren't included, and neither are responses reused
+    /// from a cache without asking the server.
+    pub fetches: Vec<FetchTiming>,
+}
+
+impl ArchiveStats {
+    /// Total size of the response bodies which were downloaded
+    pub fn total_bytes(&self) -> u64 {
+        self.fetches.iter().map(|fetch| fetch.bytes).sum()
+    }
+
+    /// The `count` requests which took the longest, slowest first
+    pub fn slowest(&self, count: usize) -> Vec<&FetchTiming> {
+        let mut fetches: Vec<_> = self.fetches.iter().collect();
+        fetches.sort_by_key(|fetch| Reverse(fetch.total));
+        fetches.truncate(count);
+        fetches
+    }
+}
+
+/// Times a single request, from just before it's sent
+pub(crate) struct Stopwatch {
+    url: Url,
+    started: Instant,
+    first_byte: Option<Duration>,
+}
+
+impl Stopwatch {
+    pub fn start(url: &Url) -> Self {
+        Self {
+            url: url.clone(),
+            started: Instant::now(),
+            first_byte: None,
+        }
+    }
+
+    /// Note that the response headers have arrived
+    pub fn first_byte(&mut self) {
+        self.first_byte = Some(self.started.elapsed());
+    }
+
+    /// Finish timing once `bytes` of the body have arrived
+    pub fn stop(self, bytes: u64) -> FetchTiming {
+        let total = self.started.elapsed();
+        FetchTiming {
+            url: self.url,
+            time_to_first_byte: self.first_byte.unwrap_or(total),
+            total,
+            bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn timing(path: &str, millis: u64, bytes: u64) -> FetchTiming {
+        FetchTiming {
+            url: Url::parse("http://example.com")
+                .unwrap()
+                .join(path)
+                .unwrap(),
+            time_to_first_byte: Duration::from_millis(millis / 2),
+            total: Duration::from_millis(millis),
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let stats = ArchiveStats {
+            wall_time: Duration::from_millis(500),
+            fetches: vec![
+                timing("/", 100, 1000),
+                timing("/style.css", 300, 200),
+                timing("/image.png", 200, 5000),
+            ],
+        };
+        assert_eq!(stats.total_bytes(), 6200);
+        let slowest: Vec<_> = stats
+            .slowest(2)
+            .iter()
+            .map(|fetch| fetch.url.path())
+            .collect();
+        assert_eq!(slowest, vec!["/style.css", "/image.png"]);
+        assert_eq!(stats.slowest(10).len(), 3);
+        assert_eq!(ArchiveStats::default().total_bytes(), 0);
+    }
+
+    #[test]
+    fn test_stopwatch() {
+        let url = Url::parse("http://example.com").unwrap();
+        let mut stopwatch = Stopwatch::start(&url);
+        stopwatch.first_byte();
+        let timing = stopwatch.stop(42);
+        assert_eq!(timing.url, url);
+        assert!(timing.time_to_first_byte <= timing.total);
+        assert_eq!(timing.bytes, 42);
+    }
+}
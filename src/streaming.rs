@@ -0,0 +1,987 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for embedding resources by streaming the page through
+//! `lol_html`, which only rewrites the markup that has to change
+
+use crate::dom;
+use crate::page_archive::{
+    EmbedOptions, PageArchive, MEDIA_ATTRIBUTES, ORIGINAL_URL_ATTRIBUTES,
+};
+use crate::parsing::{
+    file_name, has_rel, is_image_input, is_module_script, matches_tag,
+    preload_kind, resolve_url, Resource,
+};
+use crate::provenance::{self, Provenance};
+use kuchiki::iter::NodeIterator;
+use kuchiki::traits::TendrilSink;
+use kuchiki::{parse_html, NodeRef};
+use lol_html::errors::RewritingError;
+use lol_html::html_content::{ContentType, Element, TextChunk};
+use lol_html::{
+    element, end, rewrite_str, text, ElementContentHandlers, HandlerResult,
+    RewriteStrSettings, Selector,
+};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use url::Url;
+
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+
+/// Embed the archived resources into the page, see
+/// [`PageArchive::embed_resources_streaming`]
+pub(crate) fn embed(
+    archive: &PageArchive,
+    options: &EmbedOptions,
+) -> Result<String, RewritingError> {
+    let rewriter = Rewriter {
+        archive,
+        options,
+        base: RefCell::new(archive.url.clone()),
+        found_base: Cell::new(false),
+        modules: RefCell::new(HashMap::new()),
+        sprites: RefCell::new(HashMap::new()),
+        inlined: Rc::new(RefCell::new(Vec::new())),
+        inlined_ids: RefCell::new(HashSet::new()),
+        frames: RefCell::new(vec![archive.url.clone()]),
+    };
+    rewriter.rewrite(&archive.content, true)
+}
+
+/// State shared between the handlers while rewriting a page
+struct Rewriter<'a> {
+    archive: &'a PageArchive,
+    options: &'a EmbedOptions<'a>,
+    /// URL that relative URLs are resolved against. This is updated when
+    /// the `<base>` element is reached.
+    base: RefCell<Url>,
+    found_base: Cell<bool>,
+    /// `data:` URIs of the ES modules which have been rewritten so far
+    modules: RefCell<HashMap<Url, String>>,
+    /// Parsed SVG files referenced by `<use>` elements
+    sprites: RefCell<HashMap<Url, NodeRef>>,
+    /// Serialised elements referenced by `<use>`, waiting to be inserted
+    /// at the end of the body
+    inlined: Rc<RefCell<Vec<String>>>,
+    inlined_ids: RefCell<HashSet<String>>,
+    /// URLs of the page and any frames currently being embedded
+    frames: RefCell<Vec<Url>>,
+}
+
+impl<'a> Rewriter<'a> {
+    /// Rewrite a page, or the contents of a `<noscript>` within it
+    fn rewrite(
+        &self,
+        html: &str,
+        top_level: bool,
+    ) -> Result<String, RewritingError> {
+        // Raw text is delivered in chunks, so it has to be collected up
+        // before it can be rewritten
+        let style_text = RefCell::new(String::new());
+        let script_text = RefCell::new(String::new());
+        let noscript_text = RefCell::new(String::new());
+        let inline_module = Cell::new(false);
+        // Where the page came from, recorded at the start of its `<head>`,
+        // or of its `<body>` if it doesn't have one
+        let provenance = match (top_level, self.options.provenance) {
+            (true, Some(provenance)) => Some(self.provenance(provenance)),
+            _ => None,
+        };
+        let marked = Cell::new(false);
+
+        let mut element_content_handlers = vec![
+            element!("base", |el| {
+                self.set_base(el);
+                el.remove();
+                Ok(())
+            }),
+            element!("img, input", |el| self.embed_image(el)),
+            element!("[style]", |el| self.embed_style_attribute(el)),
+            element!("object[data], embed[src]", |el| self.embed_object(el)),
+            element!("video, audio, source, track", |el| self.embed_media(el)),
+            element!("frame[src]", |el| self.embed_frame(el)),
+            element!("a[href], area[href]", |el| self.embed_attachment(el)),
+            element!("image, use", |el| self.embed_svg(el)),
+            element!("link", |el| self.embed_link(el)),
+            element!("script", |el| {
+                inline_module.set(false);
+                self.embed_script(el, &inline_module)
+            }),
+            element!("meta", set_utf8_charset),
+            text!("style", |chunk| {
+                if let Some(css) = collect_text(&style_text, chunk) {
+                    let base = self.base.borrow();
+                    let rewritten = self.archive.embed_css_urls(
+                        &base,
+                        &css,
+                        &mut Vec::new(),
+                        |u| self.options.original_url(&base, u),
+                    );
+                    chunk.replace(&rewritten, ContentType::Html);
+                }
+                Ok(())
+            }),
+            text!("script", |chunk| {
+                if !inline_module.get() {
+                    return Ok(());
+                }
+                if let Some(script) = collect_text(&script_text, chunk) {
+                    // Inline module, whose imports are relative to the page
+                    let rewritten = self.archive.embed_module_imports(
+                        &self.base.borrow(),
+                        &script,
+                        &mut Vec::new(),
+                        &mut self.modules.borrow_mut(),
+                    );
+                    chunk.replace(&rewritten, ContentType::Html);
+                }
+                Ok(())
+            }),
+            text!("noscript", |chunk| {
+                // `<noscript>` contents are raw text, so rewrite them as a
+                // page of their own
+                if let Some(html) = collect_text(&noscript_text, chunk) {
+                    let rewritten = self.rewrite(&html, false)?;
+                    chunk.replace(&rewritten, ContentType::Html);
+                }
+                Ok(())
+            }),
+        ];
+
+        // Filtered elements are removed before any other handlers see them
+        if let Some(list) = self.options.filter_list {
+            let mut filtered = vec![element!("*", move |el| {
+                let tag = el.tag_name();
+                let attribute = |name: &str| el.get_attribute(name);
+                if list.blocks_element(&tag, attribute, &self.base.borrow()) {
+                    el.remove();
+                }
+                Ok(())
+            })];
+            // Selectors which can't be parsed are skipped
+            let base = self.base.borrow().clone();
+            for selector in list.hidden_selectors(&base) {
+                if let Ok(selector) = selector.parse::<Selector>() {
+                    filtered.push((
+                        Cow::Owned(selector),
+                        ElementContentHandlers::default().element(|el| {
+                            el.remove();
+                            Ok(())
+                        }),
+                    ));
+                }
+            }
+            element_content_handlers.splice(0..0, filtered);
+        }
+
+        if !self.archive.url_attributes.is_empty() {
+            element_content_handlers
+                .push(element!("*", |el| self.embed_url_attributes(el)));
+        }
+
+        // Runs after the other handlers, so anything left pointing at a
+        // relative URL wasn't embedded
+        if self.options.keep_original_urls {
+            element_content_handlers
+                .push(element!("*", |el| self.keep_original_urls(el)));
+        }
+
+        if let Some((head, banner)) = &provenance {
+            let marked = &marked;
+            element_content_handlers.push(element!("head", move |el| {
+                if !marked.replace(true) {
+                    el.prepend(head, ContentType::Html);
+                }
+                Ok(())
+            }));
+            element_content_handlers.push(element!("body", move |el| {
+                if let Some(banner) = banner {
+                    el.prepend(banner, ContentType::Html);
+                }
+                if !marked.replace(true) {
+                    el.prepend(head, ContentType::Html);
+                }
+                Ok(())
+            }));
+        }
+
+        let mut document_content_handlers = Vec::new();
+        if top_level {
+            element_content_handlers.push(element!("body", |el| {
+                let inlined = Rc::clone(&self.inlined);
+                if let Some(handlers) = el.end_tag_handlers() {
+                    handlers.push(Box::new(move |end_tag| {
+                        if let Some(svg) = take_inlined(&inlined) {
+                            end_tag.before(&svg, ContentType::Html);
+                        }
+                        Ok(())
+                    }));
+                }
+                Ok(())
+            }));
+            // In case the page doesn't close its `<body>`
+            let inlined = Rc::clone(&self.inlined);
+            document_content_handlers.push(end!(move |end| {
+                if let Some(svg) = take_inlined(&inlined) {
+                    end.append(&svg, ContentType::Html);
+                }
+                Ok(())
+            }));
+        }
+
+        rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers,
+                document_content_handlers,
+                ..RewriteStrSettings::default()
+            },
+        )
+    }
+
+    /// The markup recording where the page came from: that to go at the
+    /// start of its `<head>`, and the banner to go at the start of its
+    /// `<body>` if there is one
+    fn provenance(&self, provenance: Provenance) -> (String, Option<String>) {
+        let (head, banner) = provenance::nodes(self.archive, provenance);
+        let head = head.iter().map(dom::serialize).collect();
+        (head, banner.map(|banner| dom::serialize(&banner)))
+    }
+
+    /// Resolve a URL from the page and look up the archived resource
+    fn resource(&self, u: &str) -> Option<(Url, &'a Resource)> {
+        let url = self.base.borrow().join(u).ok()?;
+        let resource = self.archive.resource_map.get(&url)?;
+        Some((url, resource))
+    }
+
+    /// Only the first `<base>` with an `href` counts
+    fn set_base(&self, el: &Element) {
+        if self.found_base.get() {
+            return;
+        }
+        if let Some(href) = el.get_attribute("href") {
+            self.found_base.set(true);
+            if let Ok(u) = self.archive.url.join(&href) {
+                *self.base.borrow_mut() = u;
+            }
+        }
+    }
+
+    /// Replace images, including `<input type="image">` buttons, and
+    /// promote lazy-loaded images
+    fn embed_image(&self, el: &mut Element) -> HandlerResult {
+        let is_img = el.tag_name() == "img";
+        if !is_img && !is_image_input(el.get_attribute("type").as_deref()) {
+            // Only image buttons have an image source
+            return Ok(());
+        }
+
+        let base = self.base.borrow();
+        let mut embedded = false;
+        let data_uri = el
+            .get_attribute("src")
+            .and_then(|u| self.archive.image_data_uri(&base, &u));
+        if let Some(data_uri) = data_uri {
+            el.set_attribute("src", &data_uri)?;
+            embedded = true;
+        }
+        if is_img {
+            for name in &self.archive.lazy_attributes {
+                let promoted = el.get_attribute(name).and_then(|value| {
+                    self.archive.promote_lazy_attribute(&base, name, &value)
+                });
+                if let Some((target, value)) = promoted {
+                    el.set_attribute(target, &value)?;
+                    el.remove_attribute(name);
+                    embedded = true;
+                }
+            }
+        }
+        if embedded {
+            remove_subresource_attributes(el);
+        }
+        Ok(())
+    }
+
+    /// Replace images referenced from inline `style` attributes
+    fn embed_style_attribute(&self, el: &mut Element) -> HandlerResult {
+        if let Some(style) = el.get_attribute("style") {
+            let base = self.base.borrow();
+            let rewritten = self.archive.embed_css_urls(
+                &base,
+                &style,
+                &mut Vec::new(),
+                |u| self.options.original_url(&base, u),
+            );
+            if rewritten != style {
+                el.set_attribute("style", &rewritten)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace `<object>` and `<embed>` payloads
+    fn embed_object(&self, el: &mut Element) -> HandlerResult {
+        let name = if el.tag_name() == "object" {
+            "data"
+        } else {
+            "src"
+        };
+        let resource = el.get_attribute(name).and_then(|u| self.resource(&u));
+        if let Some((_, Resource::Object(object_data))) = resource {
+            el.set_attribute(name, &object_data.to_data_uri())?;
+        }
+        Ok(())
+    }
+
+    /// Replace audio, video, and the sources and text tracks within them,
+    /// along with the images offered by `<picture>` sources
+    fn embed_media(&self, el: &mut Element) -> HandlerResult {
+        let base = self.base.borrow();
+        let mut embedded = false;
+        for name in MEDIA_ATTRIBUTES {
+            let data_uri = el.get_attribute(name).and_then(|value| {
+                self.archive.media_attribute(&base, name, &value)
+            });
+            if let Some(data_uri) = data_uri {
+                el.set_attribute(name, &data_uri)?;
+                embedded = true;
+            }
+        }
+        if embedded {
+            remove_subresource_attributes(el);
+        }
+        Ok(())
+    }
+
+    /// Replace SVG subresources. `<image>` can take a `data:` URI, but the
+    /// element referenced by a `<use>` is copied into the page instead.
+    fn embed_svg(&self, el: &mut Element) -> HandlerResult {
+        if el.namespace_uri() != SVG_NAMESPACE {
+            return Ok(());
+        }
+        let name = match ["href", "xlink:href"]
+            .iter()
+            .find(|name| el.has_attribute(name))
+        {
+            Some(name) => *name,
+            None => return Ok(()),
+        };
+        let href = el.get_attribute(name).unwrap_or_default();
+        if href.starts_with('#') {
+            // Already refers to something within the page
+            return Ok(());
+        }
+        let mut url = match self.base.borrow().join(&href) {
+            Ok(url) => url,
+            Err(_) => return Ok(()),
+        };
+        let fragment = url.fragment().map(str::to_string);
+        url.set_fragment(None);
+        let image_data = match self.archive.resource_map.get(&url) {
+            Some(Resource::Image(image_data)) => image_data,
+            _ => return Ok(()),
+        };
+
+        if el.tag_name() == "image" {
+            el.set_attribute(name, &image_data.to_data_uri())?;
+            remove_subresource_attributes(el);
+        } else if let Some(id) = fragment {
+            if !self.inlined_ids.borrow().contains(&id) {
+                let mut sprites = self.sprites.borrow_mut();
+                let sprite = sprites.entry(url).or_insert_with(|| {
+                    parse_html()
+                        .one(String::from_utf8_lossy(&image_data.data).as_ref())
+                });
+                let target = sprite.descendants().elements().find(|e| {
+                    e.attributes.borrow().get("id") == Some(id.as_str())
+                });
+                match target {
+                    Some(target) => {
+                        self.inlined
+                            .borrow_mut()
+                            .push(dom::serialize(target.as_node()));
+                        self.inlined_ids.borrow_mut().insert(id.clone());
+                    }
+                    // Nothing to point at, so leave it alone
+                    None => return Ok(()),
+                }
+            }
+            el.set_attribute(name, &format!("#{}", id))?;
+        }
+        Ok(())
+    }
+
+    /// Replace stylesheets with `<style>` tags and deal with preload and
+    /// resource hints
+    fn embed_link(&self, el: &mut Element) -> HandlerResult {
+        let rel = el.get_attribute("rel");
+        let rel = rel.as_deref();
+        let css =
+            el.get_attribute("href")
+                .and_then(|u| match self.resource(&u) {
+                    Some((url, Resource::Css(css))) => {
+                        Some(self.archive.embed_stylesheet(
+                            &url,
+                            &css.text(),
+                            &mut Vec::new(),
+                        ))
+                    }
+                    _ => None,
+                });
+
+        if has_rel(rel, "stylesheet") {
+            if let Some(css) = css {
+                el.replace(
+                    &format!("<style>{}</style>", css),
+                    ContentType::Html,
+                );
+            }
+        } else if preload_kind(rel, el.get_attribute("as").as_deref()).is_some()
+            && el
+                .get_attribute("href")
+                .and_then(|u| resolve_url(&self.base.borrow(), &u))
+                .is_some()
+        {
+            match css {
+                Some(css) => {
+                    el.set_attribute(
+                        "href",
+                        &format!(
+                            "data:text/css;charset=utf-8;base64,{}",
+                            base64::encode(css.as_bytes())
+                        ),
+                    )?;
+                    remove_subresource_attributes(el);
+                }
+                None => el.remove(),
+            }
+        } else if self.options.strip_resource_hints
+            && ["preconnect", "dns-prefetch", "prefetch", "prerender"]
+                .iter()
+                .any(|hint| has_rel(rel, hint))
+        {
+            el.remove();
+        }
+        Ok(())
+    }
+
+    /// Replace links to attachments, adding a `download` attribute so that
+    /// browsers don't try to navigate to the `data:` URI
+    fn embed_attachment(&self, el: &mut Element) -> HandlerResult {
+        let resource = el.get_attribute("href").and_then(|u| self.resource(&u));
+        if let Some((url, Resource::Attachment(attachment))) = resource {
+            el.set_attribute("href", &attachment.to_data_uri())?;
+            if !el.has_attribute("download") {
+                el.set_attribute("download", file_name(&url))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace frames with their archived documents. These are embedded
+    /// via the DOM, like [`PageArchive::embed_resources_with`].
+    fn embed_frame(&self, el: &mut Element) -> HandlerResult {
+        let data_uri = el.get_attribute("src").and_then(|u| {
+            self.archive.frame_data_uri(
+                &self.base.borrow(),
+                &u,
+                self.options,
+                &mut self.frames.borrow_mut(),
+            )
+        });
+        if let Some(data_uri) = data_uri {
+            el.set_attribute("src", &data_uri)?;
+        }
+        Ok(())
+    }
+
+    /// Replace URLs in the extra attributes from the archive options
+    fn embed_url_attributes(&self, el: &mut Element) -> HandlerResult {
+        let tag_name = el.tag_name();
+        for (tag, name) in &self.archive.url_attributes {
+            if !matches_tag(tag, &tag_name) {
+                continue;
+            }
+            let data_uri = el
+                .get_attribute(name)
+                .and_then(|u| self.archive.data_uri(&self.base.borrow(), &u));
+            if let Some(data_uri) = data_uri {
+                el.set_attribute(name, &data_uri)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Point URLs which weren't embedded at their original absolute URLs
+    fn keep_original_urls(&self, el: &mut Element) -> HandlerResult {
+        let base = self.base.borrow();
+        for name in ORIGINAL_URL_ATTRIBUTES {
+            let original = el.get_attribute(name).and_then(|value| {
+                self.options.original_attribute(&base, name, &value)
+            });
+            if let Some(original) = original {
+                el.set_attribute(name, &original)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace scripts, rewriting the imports of ES modules. Inline
+    /// modules are flagged in `inline_module` so that their text can be
+    /// rewritten.
+    fn embed_script(
+        &self,
+        el: &mut Element,
+        inline_module: &Cell<bool>,
+    ) -> HandlerResult {
+        let is_module = is_module_script(el.get_attribute("type").as_deref());
+        let u = match el.get_attribute("src") {
+            Some(u) => u,
+            None => {
+                inline_module.set(is_module);
+                return Ok(());
+            }
+        };
+
+        let mut embedded = false;
+        if let Some((url, Resource::Javascript(script))) = self.resource(&u) {
+            let script_text =
+                self.archive.embed_source_map(&url, &script.text());
+            let script_text = if is_module {
+                self.archive.embed_module_imports(
+                    &url,
+                    &script_text,
+                    &mut Vec::new(),
+                    &mut self.modules.borrow_mut(),
+                )
+            } else {
+                script_text
+            };
+            el.set_inner_content(&script_text, ContentType::Html);
+            remove_subresource_attributes(el);
+            embedded = true;
+        }
+        // External resources won't be reachable from the archived page,
+        // so by default the `src` goes whether or not it was archived.
+        // `data:` URIs are self-contained, so they stay.
+        if self.options.strips_script_src(embedded)
+            && resolve_url(&self.base.borrow(), &u).is_some()
+        {
+            el.remove_attribute("src");
+        }
+        Ok(())
+    }
+}
+
+/// Collect the chunks of a text node into `buffer`, returning the whole
+/// text once the last chunk arrives. The earlier chunks are removed so
+/// that the last one can be replaced with the rewritten text.
+fn collect_text(
+    buffer: &RefCell<String>,
+    chunk: &mut TextChunk,
+) -> Option<String> {
+    let mut buffer = buffer.borrow_mut();
+    buffer.push_str(chunk.as_str());
+    if chunk.last_in_text_node() {
+        Some(std::mem::take(&mut *buffer))
+    } else {
+        chunk.remove();
+        None
+    }
+}
+
+/// Wrap the elements referenced by `<use>` in a hidden `<svg>` so that
+/// they don't affect the page layout
+fn take_inlined(inlined: &RefCell<Vec<String>>) -> Option<String> {
+    let mut inlined = inlined.borrow_mut();
+    if inlined.is_empty() {
+        return None;
+    }
+    let svg =
+        format!(r#"<svg style="display: none">{}</svg>"#, inlined.join(""));
+    inlined.clear();
+    Some(svg)
+}
+
+/// Point charset declarations at UTF-8, see
+/// [`PageArchive::embed_resources_with`]
+fn set_utf8_charset(el: &mut Element) -> HandlerResult {
+    if el.has_attribute("charset") {
+        el.set_attribute("charset", "utf-8")?;
+    }
+    let is_content_type = el
+        .get_attribute("http-equiv")
+        .map(|h| h.trim().eq_ignore_ascii_case("content-type"))
+        .unwrap_or(false);
+    if is_content_type {
+        el.set_attribute("content", "text/html; charset=utf-8")?;
+    }
+    Ok(())
+}
+
+/// Remove the attributes which only apply to fetching a subresource from
+/// the network
+fn remove_subresource_attributes(el: &mut Element) {
+    el.remove_attribute("integrity");
+    el.remove_attribute("crossorigin");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use bytes::Bytes;
+
+    fn png() -> Resource {
+        Resource::Image(ImageResource {
+            data: Bytes::from_static(b"\x89PNG\x0D\x0A\x1A\x0A"),
+            mimetype: "image/png".to_string(),
+        })
+    }
+
+    fn text(mimetype: &str, text: &'static str) -> TextResource {
+        TextResource {
+            data: Bytes::from_static(text.as_bytes()),
+            mimetype: mimetype.to_string(),
+            charset: None,
+        }
+    }
+
+    fn archive(content: &str, resources: Vec<(&str, Resource)>) -> PageArchive {
+        let url = Url::parse("http://example.com").unwrap();
+        let mut resource_map = ResourceMap::new();
+        for (u, resource) in resources {
+            resource_map.insert(url.join(u).unwrap(), resource);
+        }
+        PageArchive {
+            url,
+            content: content.to_string(),
+            resource_map,
+            lazy_attributes: vec!["data-src".to_string()],
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            url_attributes: Vec::new(),
+            screenshot: None,
+        }
+    }
+
+    fn png_uri() -> String {
+        format!(
+            "data:image/png;base64,{}",
+            base64::encode(b"\x89PNG\x0D\x0A\x1A\x0A")
+        )
+    }
+
+    #[test]
+    fn test_untouched_markup() {
+        // Unusual formatting and unknown elements come out exactly as they
+        // went in
+        let content = "<!DOCTYPE html>\n<HTML><head>\n<TITLE>Hi</TITLE>\
+                       </head>\n<body class=a   id='b'><p>One<p>Two\
+                       <custom-el  x=y></custom-el></body></HTML>\n";
+        let output = archive(content, Vec::new())
+            .embed_resources_streaming(&Default::default())
+            .unwrap();
+
+        assert_eq!(output, content);
+    }
+
+    #[test]
+    fn test_images() {
+        let content = r#"<body>
+<img src="a.png" integrity="sha384-abc" alt=x>
+<img src="placeholder.gif" data-src="lazy.png">
+<input type="image" src="a.png">
+<input type="text" src="a.png">
+<div style="background: url(a.png)"></div>
+<img src="missing.png">
+</body>"#;
+        let output =
+            archive(content, vec![("a.png", png()), ("lazy.png", png())])
+                .embed_resources_streaming(&Default::default())
+                .unwrap();
+
+        let uri = png_uri();
+        assert_eq!(
+            output,
+            format!(
+                r#"<body>
+<img src="{0}" alt=x>
+<img src="{0}">
+<input type="image" src="{0}">
+<input type="text" src="a.png">
+<div style="background: url(&quot;{0}&quot;)"></div>
+<img src="missing.png">
+</body>"#,
+                uri
+            )
+        );
+    }
+
+    #[test]
+    fn test_media() {
+        let content = r#"<video src="clip.mp4" poster="a.png" crossorigin>
+<source srcset="a.png 2x"><track src="missing.vtt"></video>"#;
+        let media = Resource::Media(ImageResource {
+            data: Bytes::from_static(b"mp4"),
+            mimetype: "video/mp4".to_string(),
+        });
+        let output =
+            archive(content, vec![("a.png", png()), ("clip.mp4", media)])
+                .embed_resources_streaming(&Default::default())
+                .unwrap();
+
+        assert_eq!(
+            output,
+            format!(
+                r#"<video src="data:video/mp4;base64,bXA0" poster="{0}">
+<source srcset="{0} 2x"><track src="missing.vtt"></video>"#,
+                png_uri()
+            )
+        );
+    }
+
+    #[test]
+    fn test_stylesheets_and_scripts() {
+        let content = r#"<html><head>
+<base href="http://example.com/assets/">
+<link rel="stylesheet" href="style.css">
+<link rel="preload" as="script" href="script.js">
+<link rel="preconnect" href="https://cdn.example.com">
+<style>p { background: url(a.png) }</style>
+</head><body>
+<script src="script.js" crossorigin="anonymous"></script>
+<script src="missing.js"></script>
+<script type="module">import "./module.js";</script>
+</body></html>"#;
+        let output = archive(
+            content,
+            vec![
+                ("assets/style.css", Resource::Css(text("text/css", "p {}"))),
+                (
+                    "assets/script.js",
+                    Resource::Javascript(text("text/javascript", "go();")),
+                ),
+                (
+                    "assets/module.js",
+                    Resource::Javascript(text("text/javascript", "")),
+                ),
+                ("assets/a.png", png()),
+            ],
+        )
+        .embed_resources_streaming(&EmbedOptions {
+            strip_resource_hints: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            output,
+            format!(
+                r#"<html><head>
+
+<style>p {{}}</style>
+
+
+<style>p {{ background: url("{}") }}</style>
+</head><body>
+<script>go();</script>
+<script></script>
+<script type="module">import "data:text/javascript;base64,";</script>
+</body></html>"#,
+                png_uri()
+            )
+        );
+    }
+
+    #[test]
+    fn test_noscript_and_template() {
+        let content = r#"<body>
+<noscript><img src="a.png"></noscript>
+<template><img src="a.png"></template>
+</body>"#;
+        let output = archive(content, vec![("a.png", png())])
+            .embed_resources_streaming(&Default::default())
+            .unwrap();
+
+        assert_eq!(
+            output,
+            format!(
+                r#"<body>
+<noscript><img src="{0}"></noscript>
+<template><img src="{0}"></template>
+</body>"#,
+                png_uri()
+            )
+        );
+    }
+
+    #[test]
+    fn test_svg() {
+        let content = r##"<html><head><meta charset="windows-1251"></head><body>
+<svg><use href="sprite.svg#icon"></use><image xlink:href="a.png"></image></svg>
+<svg><use href="sprite.svg#icon"></use></svg>
+</body></html>"##;
+        let sprite = Resource::Image(ImageResource {
+            data: Bytes::from_static(
+                br#"<svg><symbol id="icon"><path d="M0 0"></path></symbol></svg>"#,
+            ),
+            mimetype: "image/svg+xml".to_string(),
+        });
+        let output =
+            archive(content, vec![("sprite.svg", sprite), ("a.png", png())])
+                .embed_resources_streaming(&Default::default())
+                .unwrap();
+
+        assert_eq!(
+            output,
+            format!(
+                r##"<html><head><meta charset="utf-8"></head><body>
+<svg><use href="#icon"></use><image xlink:href="{}"></image></svg>
+<svg><use href="#icon"></use></svg>
+<svg style="display: none"><symbol id="icon"><path d="M0 0"></path></symbol></svg></body></html>"##,
+                png_uri()
+            )
+        );
+    }
+
+    #[test]
+    fn test_url_attributes() {
+        let mut archive = archive(
+            r#"<div data-bg="a.png"></div><p data-bg="a.png"></p>"#,
+            vec![("a.png", png())],
+        );
+        archive.url_attributes =
+            vec![("div".to_string(), "data-bg".to_string())];
+        let output = archive
+            .embed_resources_streaming(&Default::default())
+            .unwrap();
+        assert_eq!(
+            output,
+            format!(
+                r#"<div data-bg="{}"></div><p data-bg="a.png"></p>"#,
+                png_uri()
+            )
+        );
+    }
+
+    #[test]
+    fn test_frames() {
+        let frame = Resource::Document(text("text/html", "<p>Frame</p>"));
+        let output = archive(
+            r#"<frameset><frame src="a.html"></frameset>"#,
+            vec![("a.html", frame)],
+        )
+        .embed_resources_streaming(&Default::default())
+        .unwrap();
+        assert_eq!(
+            output,
+            format!(
+                r#"<frameset><frame src="data:text/html;charset=utf-8;base64,{}"></frameset>"#,
+                base64::encode(
+                    "<html><head></head><body><p>Frame</p></body></html>"
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn test_attachments() {
+        let pdf = Resource::Attachment(ImageResource {
+            data: Bytes::from_static(b"a"),
+            mimetype: "application/pdf".to_string(),
+        });
+        let output = archive(
+            r#"<a href="files/a.pdf">A</a><a href="b.pdf">B</a>"#,
+            vec![("files/a.pdf", pdf)],
+        )
+        .embed_resources_streaming(&Default::default())
+        .unwrap();
+        assert_eq!(
+            output,
+            r#"<a href="data:application/pdf;base64,YQ==" download="a.pdf">A</a><a href="b.pdf">B</a>"#
+        );
+    }
+
+    #[test]
+    fn test_filter_list() {
+        let list = FilterList::parse("||ads.example.net^\n##.ad-banner");
+        let content = r#"<body><div class="ad-banner"><p>Buy</p></div><script src="https://ads.example.net/ad.js"></script><p>Article</p></body>"#;
+        let output = archive(content, Vec::new())
+            .embed_resources_streaming(&EmbedOptions {
+                filter_list: Some(&list),
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(output, "<body><p>Article</p></body>");
+    }
+
+    #[test]
+    fn test_embed_options() {
+        let content = r#"<script src="app.js"></script><script src="missing.js"></script><img src="a.png" style="background: url(b.png)">"#;
+        let archive = archive(
+            content,
+            vec![
+                ("app.js", Resource::Javascript(text("text/javascript", "1"))),
+                ("a.png", png()),
+            ],
+        );
+        let output = archive
+            .embed_resources_streaming(&EmbedOptions {
+                skip_images: true,
+                keep_original_urls: true,
+                keep_unarchived_scripts: true,
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            output,
+            r#"<script>1</script><script src="http://example.com/missing.js"></script><img src="http://example.com/a.png" style="background: url(&quot;http://example.com/b.png&quot;)">"#
+        );
+    }
+
+    #[test]
+    fn test_provenance() {
+        let version = env!("CARGO_PKG_VERSION");
+        let page =
+            archive("<head><title>A</title></head><body>B</body>", Vec::new());
+        let output = page
+            .embed_resources_streaming(&EmbedOptions {
+                provenance: Some(Provenance::Banner("div {}")),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            output,
+            format!(
+                r#"<head><!-- Archived from http://example.com/ by web-archive {0} --><meta content="http://example.com/" name="web-archive:url"><meta content="{0}" name="web-archive:version"><style>div {{}}</style><title>A</title></head><body><div class="web-archive-banner">Archived from <a href="http://example.com/">http://example.com/</a></div>B</body>"#,
+                version
+            )
+        );
+
+        // Without a `<head>`, the metadata goes at the start of the body
+        let page = archive("<body>B</body>", Vec::new());
+        let output = page
+            .embed_resources_streaming(&EmbedOptions {
+                provenance: Some(Provenance::Metadata),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(output.starts_with("<body><!-- Archived from"));
+        assert!(output.ends_with(r#"name="web-archive:version">B</body>"#));
+    }
+}
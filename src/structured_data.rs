@@ -0,0 +1,437 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for extracting the structured data embedded in a page, see
+//! [`PageArchive::structured_data`]
+
+use crate::metadata::collapse;
+use crate::parsing::base_url;
+use kuchiki::iter::NodeIterator;
+use kuchiki::{ElementData, NodeDataRef, NodeRef};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use url::Url;
+
+/// The structured data embedded in a page, found by
+/// [`PageArchive::structured_data`](crate::PageArchive::structured_data),
+/// such as the schema.org descriptions of products, articles, and events
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StructuredData {
+    /// The contents of each `<script type="application/ld+json">`. Scripts
+    /// holding an array have each of its values listed separately, and
+    /// those which aren't valid JSON are left out.
+    pub json_ld: Vec<Value>,
+    /// The top-level microdata items, those with an `itemscope` which
+    /// aren't properties of another item, converted to JSON as the HTML
+    /// standard describes: an object with the item's `type` and `id` if
+    /// it has them, and its `properties`, each holding a list of values.
+    /// Values are strings, or objects for nested items.
+    pub microdata: Vec<Value>,
+    /// The top-level RDFa items, those with a `typeof` which aren't the
+    /// value of another item's `property`, converted to JSON in the same
+    /// way as the microdata. Each also has the `vocab` that its types and
+    /// properties are from, if one is given.
+    pub rdfa: Vec<Value>,
+}
+
+impl StructuredData {
+    /// Whether the page has no structured data at all
+    pub fn is_empty(&self) -> bool {
+        self.json_ld.is_empty()
+            && self.microdata.is_empty()
+            && self.rdfa.is_empty()
+    }
+}
+
+/// How deeply items can be nested in each other before they're given up
+/// on, so that pages can't overflow the stack
+const MAX_DEPTH: usize = 32;
+
+/// Find the structured data in a page
+pub(crate) fn extract(page_url: &Url, document: &NodeRef) -> StructuredData {
+    let base = base_url(page_url, document);
+    let mut json_ld = Vec::new();
+    for script in document.select("script[type]").unwrap() {
+        let script_type =
+            script.attributes.borrow().get("type").map(str::to_string);
+        let is_json_ld = script_type
+            .as_deref()
+            .and_then(|t| t.split(';').next())
+            .map(|t| t.trim().eq_ignore_ascii_case("application/ld+json"))
+            == Some(true);
+        if !is_json_ld {
+            continue;
+        }
+        match serde_json::from_str(&script.text_contents()) {
+            Ok(Value::Array(values)) => json_ld.extend(values),
+            Ok(value) => json_ld.push(value),
+            Err(_) => {}
+        }
+    }
+
+    let microdata = Microdata::new(&base, document);
+    let microdata = document
+        .select("[itemscope]:not([itemprop])")
+        .unwrap()
+        .map(|item| microdata.item(&item, &mut Vec::new()))
+        .collect();
+
+    let rdfa = document
+        .select("[typeof]:not([property])")
+        .unwrap()
+        .map(|item| rdfa_item(&base, &item, 0))
+        .collect();
+
+    StructuredData {
+        json_ld,
+        microdata,
+        rdfa,
+    }
+}
+
+/// The attribute's words, split on whitespace
+fn words(element: &NodeDataRef<ElementData>, name: &str) -> Vec<String> {
+    let attributes = element.attributes.borrow();
+    let value = attributes.get(name).unwrap_or("");
+    value.split_ascii_whitespace().map(str::to_string).collect()
+}
+
+/// The value of a property which isn't an item, from the attribute
+/// which holds it for the kind of element, or its text otherwise
+fn property_value(base: &Url, element: &NodeDataRef<ElementData>) -> String {
+    let attributes = element.attributes.borrow();
+    let url = |name| {
+        let value = attributes.get(name).unwrap_or("").trim();
+        if value.is_empty() {
+            return String::new();
+        }
+        base.join(value).map(String::from).unwrap_or_default()
+    };
+    match &*element.name.local {
+        "meta" => attributes.get("content").unwrap_or("").to_string(),
+        "audio" | "embed" | "iframe" | "img" | "source" | "track" | "video" => {
+            url("src")
+        }
+        "a" | "area" | "link" => url("href"),
+        "object" => url("data"),
+        "data" | "meter" => attributes.get("value").unwrap_or("").to_string(),
+        "time" if attributes.contains("datetime") => {
+            attributes.get("datetime").unwrap_or("").to_string()
+        }
+        _ => element.text_contents(),
+    }
+}
+
+/// A JSON object for an item with the given types, and the given
+/// properties in the order they were found
+fn item_json(
+    types: Vec<String>,
+    id: Option<String>,
+    properties: Vec<(String, Value)>,
+) -> Map<String, Value> {
+    let mut item = Map::new();
+    if !types.is_empty() {
+        let types = types.into_iter().map(Value::String).collect();
+        item.insert("type".to_string(), Value::Array(types));
+    }
+    if let Some(id) = id {
+        item.insert("id".to_string(), Value::String(id));
+    }
+    let mut values = Map::new();
+    for (name, value) in properties {
+        let list = values
+            .entry(name)
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(list) = list {
+            list.push(value);
+        }
+    }
+    item.insert("properties".to_string(), Value::Object(values));
+    item
+}
+
+/// Converts microdata items to JSON
+struct Microdata<'a> {
+    base: &'a Url,
+    /// Elements with ids, which `itemref` attributes point at
+    ids: HashMap<String, NodeDataRef<ElementData>>,
+}
+
+impl<'a> Microdata<'a> {
+    fn new(base: &'a Url, document: &NodeRef) -> Self {
+        let mut ids = HashMap::new();
+        for element in document.select("[id]").unwrap() {
+            let id = element.attributes.borrow().get("id").map(str::to_string);
+            if let Some(id) = id {
+                ids.entry(id).or_insert(element);
+            }
+        }
+        Self { base, ids }
+    }
+
+    /// The JSON for an item. `path` holds the items which it's nested
+    /// within, so that an item which refers back to one of them doesn't
+    /// go on forever.
+    fn item(
+        &self,
+        item: &NodeDataRef<ElementData>,
+        path: &mut Vec<NodeRef>,
+    ) -> Value {
+        path.push(item.as_node().clone());
+        let mut properties = Vec::new();
+        for element in self.properties(item) {
+            let value = if !element.attributes.borrow().contains("itemscope") {
+                Value::String(property_value(self.base, &element))
+            } else if path.contains(element.as_node()) || path.len() > MAX_DEPTH
+            {
+                Value::String("ERROR".to_string())
+            } else {
+                self.item(&element, path)
+            };
+            for name in words(&element, "itemprop") {
+                properties.push((name, value.clone()));
+            }
+        }
+        path.pop();
+
+        let id =
+            item.attributes.borrow().get("itemid").and_then(|id| {
+                self.base.join(id.trim()).ok().map(String::from)
+            });
+        Value::Object(item_json(words(item, "itemtype"), id, properties))
+    }
+
+    /// The elements holding the properties of an item, in document order:
+    /// those with an `itemprop` within it and the elements it refers to
+    /// with `itemref`, apart from those within nested items
+    fn properties(
+        &self,
+        item: &NodeDataRef<ElementData>,
+    ) -> Vec<NodeDataRef<ElementData>> {
+        let mut pending: Vec<NodeRef> = item.as_node().children().collect();
+        for id in words(item, "itemref") {
+            if let Some(element) = self.ids.get(&id) {
+                pending.push(element.as_node().clone());
+            }
+        }
+        let mut properties = Vec::new();
+        let mut seen = Vec::new();
+        // Walked with a stack rather than recursively, so that deeply
+        // nested pages can't overflow the stack
+        pending.reverse();
+        while let Some(node) = pending.pop() {
+            let element = match node.clone().into_element_ref() {
+                Some(element) => element,
+                None => continue,
+            };
+            if seen.contains(&node) || node == *item.as_node() {
+                continue;
+            }
+            seen.push(node.clone());
+            let attributes = element.attributes.borrow();
+            if attributes.contains("itemprop") {
+                properties.push(element.clone());
+            }
+            if !attributes.contains("itemscope") {
+                pending.extend(node.children().rev());
+            }
+        }
+        properties.sort_by_key(|element| document_position(element.as_node()));
+        properties
+    }
+}
+
+/// Where a node is in its document, for sorting nodes into document
+/// order
+fn document_position(node: &NodeRef) -> Vec<usize> {
+    let mut position: Vec<usize> = node
+        .inclusive_ancestors()
+        .map(|ancestor| ancestor.preceding_siblings().count())
+        .collect();
+    position.reverse();
+    position
+}
+
+/// The JSON for an RDFa item
+fn rdfa_item(
+    base: &Url,
+    item: &NodeDataRef<ElementData>,
+    depth: usize,
+) -> Value {
+    let mut properties = Vec::new();
+    let mut pending: Vec<NodeRef> = item.as_node().children().rev().collect();
+    while let Some(node) = pending.pop() {
+        let element = match node.clone().into_element_ref() {
+            Some(element) => element,
+            None => continue,
+        };
+        let attributes = element.attributes.borrow();
+        let is_item = attributes.contains("typeof");
+        if attributes.contains("property") {
+            let value = if is_item && depth < MAX_DEPTH {
+                rdfa_item(base, &element, depth + 1)
+            } else if let Some(content) = attributes.get("content") {
+                Value::String(content.to_string())
+            } else {
+                let url = ["href", "src", "resource"]
+                    .iter()
+                    .find_map(|name| attributes.get(*name))
+                    .and_then(|url| base.join(url.trim()).ok());
+                let value = match url {
+                    Some(url) => url.to_string(),
+                    None => match attributes.get("datetime") {
+                        Some(datetime) => datetime.to_string(),
+                        None => collapse(&element.text_contents()),
+                    },
+                };
+                Value::String(value)
+            };
+            for name in words(&element, "property") {
+                properties.push((name, value.clone()));
+            }
+        }
+        if !is_item {
+            pending.extend(node.children().rev());
+        }
+    }
+
+    let attributes = item.attributes.borrow();
+    let id = ["resource", "about"]
+        .iter()
+        .find_map(|name| attributes.get(*name))
+        .and_then(|id| base.join(id.trim()).ok())
+        .map(String::from);
+    let mut json = item_json(words(item, "typeof"), id, properties);
+    let vocab = item
+        .as_node()
+        .inclusive_ancestors()
+        .elements()
+        .find_map(|e| e.attributes.borrow().get("vocab").map(str::to_string));
+    if let Some(vocab) = vocab {
+        json.insert("vocab".to_string(), Value::String(vocab));
+    }
+    Value::Object(json)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kuchiki::parse_html;
+    use kuchiki::traits::TendrilSink;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_ld() {
+        let document = parse_html().one(
+            "<script type=\"application/ld+json\">\
+            {\"@type\": \"Article\", \"headline\": \"A Post\"}</script>\
+            <script type=\"Application/LD+JSON; charset=utf-8\">\
+            [{\"@type\": \"Person\"}, {\"@type\": \"Event\"}]</script>\
+            <script type=\"application/ld+json\">{ not json</script>\
+            <script>{\"@type\": \"Ignored\"}</script>",
+        );
+        let url = Url::parse("http://example.com/").unwrap();
+        let data = extract(&url, &document);
+        assert_eq!(
+            data.json_ld,
+            vec![
+                json!({ "@type": "Article", "headline": "A Post" }),
+                json!({ "@type": "Person" }),
+                json!({ "@type": "Event" }),
+            ]
+        );
+        assert!(data.microdata.is_empty() && data.rdfa.is_empty());
+    }
+
+    #[test]
+    fn test_microdata() {
+        let document = parse_html().one(
+            "<div itemscope itemtype=\"http://schema.org/Product\" \
+                itemid=\"/products/1\" itemref=\"reviews\">\
+              <span itemprop=\"name\">Widget</span>\
+              <img itemprop=\"image\" src=\"widget.png\">\
+              <div itemprop=\"offers\" itemscope \
+                  itemtype=\"http://schema.org/Offer\">\
+                <meta itemprop=\"price\" content=\"9.99\">\
+                <span itemprop=\"name\">Not the product's name</span>\
+              </div>\
+              <div itemscope itemprop=\"loop\" itemref=\"product\"></div>\
+            </div>\
+            <ul id=\"reviews\"><li itemprop=\"review\">Great</li></ul>\
+            <p id=\"product\" itemprop=\"related\">Loops</p>\
+            <time itemscope><span itemprop=\"date\">\
+            <time itemprop=\"start\" datetime=\"2021-02-03\">Feb 3</time>\
+            </span></time>",
+        );
+        let url = Url::parse("http://example.com/shop/").unwrap();
+        let data = extract(&url, &document);
+        assert_eq!(
+            data.microdata,
+            vec![
+                json!({
+                    "type": ["http://schema.org/Product"],
+                    "id": "http://example.com/products/1",
+                    "properties": {
+                        "name": ["Widget"],
+                        "image": ["http://example.com/shop/widget.png"],
+                        "offers": [{
+                            "type": ["http://schema.org/Offer"],
+                            "properties": {
+                                "price": ["9.99"],
+                                "name": ["Not the product's name"],
+                            },
+                        }],
+                        "loop": [{ "properties": { "related": ["Loops"] } }],
+                        "review": ["Great"],
+                    },
+                }),
+                json!({
+                    "properties": {
+                        "date": ["Feb 3"],
+                        "start": ["2021-02-03"],
+                    },
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rdfa() {
+        let document = parse_html().one(
+            "<div vocab=\"https://schema.org/\" typeof=\"Event\" \
+                resource=\"#party\">\
+              <h1 property=\"name\">Launch   party</h1>\
+              <a property=\"url\" href=\"/events/1\">Details</a>\
+              <meta property=\"startDate\" content=\"2021-02-03\">\
+              <div property=\"location\" typeof=\"Place\">\
+                <span property=\"name\">The Hall</span>\
+              </div>\
+            </div>",
+        );
+        let url = Url::parse("http://example.com/").unwrap();
+        let data = extract(&url, &document);
+        assert_eq!(
+            data.rdfa,
+            vec![json!({
+                "type": ["Event"],
+                "id": "http://example.com/#party",
+                "vocab": "https://schema.org/",
+                "properties": {
+                    "name": ["Launch party"],
+                    "url": ["http://example.com/events/1"],
+                    "startDate": ["2021-02-03"],
+                    "location": [{
+                        "type": ["Place"],
+                        "vocab": "https://schema.org/",
+                        "properties": { "name": ["The Hall"] },
+                    }],
+                },
+            })]
+        );
+        assert!(!data.is_empty());
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for extracting the visible text of a page, see
+//! [`PageArchive::text`]
+
+use crate::page_archive::PageArchive;
+use kuchiki::traits::TendrilSink;
+use kuchiki::{parse_html, NodeData, NodeRef};
+
+/// Elements whose contents are never shown
+const INVISIBLE: &[&str] = &[
+    "head", "iframe", "noscript", "script", "style", "template", "title",
+];
+
+/// Elements which start a new line of their own
+const LINES: &[&str] = &["caption", "dd", "dt", "li", "option", "tr"];
+
+/// Elements which start a new paragraph
+const PARAGRAPHS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "center",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "html",
+    "legend",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "ul",
+];
+
+/// What separates one piece of text from the next, from the least to the
+/// most separation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Break {
+    Space,
+    /// Between the cells of a table
+    Tab,
+    Line,
+    Paragraph,
+}
+
+/// Text being built up, with the breaks between its pieces merged so
+/// that e.g. nested blocks don't add blank lines of their own
+#[derive(Default)]
+struct Text {
+    text: String,
+    pending: Option<Break>,
+}
+
+impl Text {
+    fn push_break(&mut self, separation: Break) {
+        self.pending = self.pending.max(Some(separation));
+    }
+
+    fn push_str(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if !self.text.is_empty() {
+            self.text.push_str(match self.pending {
+                None => "",
+                Some(Break::Space) => " ",
+                Some(Break::Tab) => "\t",
+                Some(Break::Line) => "\n",
+                Some(Break::Paragraph) => "\n\n",
+            });
+        }
+        self.pending = None;
+        self.text.push_str(text);
+    }
+
+    /// Add text with its whitespace collapsed, as a browser shows it
+    fn push_collapsed(&mut self, text: &str) {
+        if text.starts_with(char::is_whitespace) {
+            self.push_break(Break::Space);
+        }
+        for (i, word) in text.split_whitespace().enumerate() {
+            if i > 0 {
+                self.push_break(Break::Space);
+            }
+            self.push_str(word);
+        }
+        if text.ends_with(char::is_whitespace) {
+            self.push_break(Break::Space);
+        }
+    }
+}
+
+enum Step {
+    Open(NodeRef),
+    Close(Option<Break>),
+}
+
+/// The visible text of the archive's page
+pub(crate) fn text(archive: &PageArchive) -> String {
+    let document = parse_html().one(archive.content.as_str());
+    let body = match document.select_first("body") {
+        Ok(body) => body.as_node().clone(),
+        Err(()) => document,
+    };
+
+    let mut text = Text::default();
+    // Walked with a stack rather than recursively, so that deeply nested
+    // pages can't overflow the stack
+    let mut stack = vec![Step::Open(body)];
+    while let Some(step) = stack.pop() {
+        let node = match step {
+            Step::Open(node) => node,
+            Step::Close(separation) => {
+                if let Some(separation) = separation {
+                    text.push_break(separation);
+                }
+                continue;
+            }
+        };
+        let element = match node.data() {
+            NodeData::Text(contents) => {
+                text.push_collapsed(&contents.borrow());
+                continue;
+            }
+            NodeData::Element(element) => element,
+            _ => continue,
+        };
+        let name = &*element.name.local;
+        if INVISIBLE.contains(&name) || is_hidden(&node) {
+            continue;
+        }
+        match name {
+            "br" => {
+                text.push_break(Break::Line);
+                continue;
+            }
+            "pre" => {
+                text.push_break(Break::Paragraph);
+                let contents = node.text_contents();
+                text.push_str(contents.strip_suffix('\n').unwrap_or(&contents));
+                text.push_break(Break::Paragraph);
+                continue;
+            }
+            _ => {}
+        }
+        let separation = if PARAGRAPHS.contains(&name) {
+            Some(Break::Paragraph)
+        } else if LINES.contains(&name) {
+            Some(Break::Line)
+        } else if matches!(name, "td" | "th") {
+            Some(Break::Tab)
+        } else {
+            None
+        };
+        if let Some(separation) = separation {
+            text.push_break(separation);
+        }
+        stack.push(Step::Close(separation));
+        stack.extend(node.children().rev().map(Step::Open));
+    }
+    text.text
+}
+
+/// Whether an element is hidden with the `hidden` attribute or an inline
+/// `display: none`
+fn is_hidden(node: &NodeRef) -> bool {
+    let element = match node.as_element() {
+        Some(element) => element,
+        None => return false,
+    };
+    let attributes = element.attributes.borrow();
+    let style = attributes.get("style").unwrap_or("");
+    let style: String = style
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    attributes.contains("hidden") || style.contains("display:none")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use url::Url;
+
+    fn text_of(content: &str) -> String {
+        let archive = PageArchive {
+            url: Url::parse("http://example.com/").unwrap(),
+            content: content.to_string(),
+            resource_map: HashMap::new(),
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta: HashMap::new(),
+            stats: Default::default(),
+            screenshot: None,
+        };
+        archive.text()
+    }
+
+    #[test]
+    fn test_text() {
+        let content = "<html><head><title>Title</title>\
+            <style>p { color: red }</style></head><body>\
+            <h1>A   <em>heading</em></h1>\
+            <div><div><p>First\n   paragraph,<br>next line.</p></div></div>\
+            <script>alert(1)</script><noscript>Enable scripts</noscript>\
+            <p hidden>Hidden</p><p style=\"DISPLAY: none\">Hidden</p>\
+            <ul><li>One</li><li>Two</li></ul>\
+            <pre>  indented\n    code\n</pre>\
+            <table><tr><th>Name</th><th>Value</th></tr>\
+            <tr><td>a</td><td>1</td></tr></table>\
+            Some <a href=\"/\">linked</a> text\
+            </body></html>";
+        assert_eq!(
+            text_of(content),
+            "A heading\n\nFirst paragraph,\nnext line.\n\nOne\nTwo\n\n  \
+            indented\n    code\n\nName\tValue\na\t1\n\nSome linked text"
+        );
+        assert_eq!(text_of(""), "");
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! `User-Agent` strings of common browsers, for sites which serve
+//! different content to clients they don't recognise. Use them with
+//! [`ArchiveOptions::user_agent`](crate::ArchiveOptions::user_agent), or
+//! rotate between several with
+//! [`ArchiveOptions::user_agents`](crate::ArchiveOptions::user_agents).
+//!
+//! ## Example
+//!
+//! ```
+//! use web_archive::{user_agent, ArchiveOptions};
+//!
+//! let options = ArchiveOptions::default().user_agent(user_agent::FIREFOX_LINUX);
+//! let rotating = ArchiveOptions::default().user_agents(user_agent::DESKTOP);
+//! ```
+
+/// Chrome on Windows
+pub const CHROME_WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
+    AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36";
+
+/// Chrome on macOS
+pub const CHROME_MAC: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) \
+    AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36";
+
+/// Chrome on Android
+pub const CHROME_ANDROID: &str = "Mozilla/5.0 (Linux; Android 10; K) \
+    AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Mobile \
+    Safari/537.36";
+
+/// Edge on Windows
+pub const EDGE_WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
+    AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36 \
+    Edg/130.0.0.0";
+
+/// Firefox on Windows
+pub const FIREFOX_WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64; \
+    rv:132.0) Gecko/20100101 Firefox/132.0";
+
+/// Firefox on Linux
+pub const FIREFOX_LINUX: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:132.0) Gecko/20100101 Firefox/132.0";
+
+/// Safari on macOS
+pub const SAFARI_MAC: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) \
+    AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.0 Safari/605.1.15";
+
+/// Safari on iPhone
+pub const SAFARI_IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 18_0 like \
+    Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.0 \
+    Mobile/15E148 Safari/604.1";
+
+/// The desktop browsers
+pub const DESKTOP: &[&str] = &[
+    CHROME_WINDOWS,
+    CHROME_MAC,
+    EDGE_WINDOWS,
+    FIREFOX_WINDOWS,
+    FIREFOX_LINUX,
+    SAFARI_MAC,
+];
+
+/// The mobile browsers
+pub const MOBILE: &[&str] = &[CHROME_ANDROID, SAFARI_IPHONE];
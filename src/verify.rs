@@ -0,0 +1,419 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for checking that an archive holds everything its page needs,
+//! see [`PageArchive::verify`]
+
+use crate::digest;
+use crate::javascript::module_imports;
+use crate::page_archive::PageArchive;
+use crate::parsing::{
+    base_url, parse_resource_urls, resolve_url, Resource, ResourceUrl,
+};
+use crate::ArchiveOptions;
+use kuchiki::traits::TendrilSink;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::collections::{HashSet, VecDeque};
+use url::Url;
+
+/// What was found when checking an archive, see [`PageArchive::verify`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Verification {
+    /// Resources which the page needs but aren't in the archive, and
+    /// aren't recorded as [`failures`](PageArchive::failures) either, in
+    /// order of their URLs
+    pub missing: Vec<Url>,
+    /// Resources which the page needs but couldn't be downloaded, as
+    /// recorded in the archive's [`failures`](PageArchive::failures), in
+    /// order of their URLs
+    pub failed: Vec<Url>,
+    /// The page and archived resources whose contents don't match their
+    /// recorded [`sha256`](crate::ResourceMeta::sha256) digest, or a hash
+    /// from the `integrity` attribute of the element loading them, in
+    /// order of their URLs
+    pub mismatched: Vec<Url>,
+}
+
+impl Verification {
+    /// Whether the archive holds everything that it should, i.e. nothing
+    /// is missing without explanation and every hash matched. Resources
+    /// which are known to have failed don't count.
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+/// Check the `archive`, which was archived with the `options`, see
+/// [`PageArchive::verify`]
+pub(crate) fn verify(
+    archive: &PageArchive,
+    options: &ArchiveOptions,
+) -> Verification {
+    let failed: HashSet<_> = archive
+        .failures
+        .iter()
+        .map(|failure| &failure.url)
+        .chain(&archive.timed_out)
+        .collect();
+
+    let mut verification = Verification::default();
+    let mut hashes = integrity_hashes(&archive.url, &archive.content);
+    let mut queue: VecDeque<_> =
+        parse_resource_urls(&archive.url, &archive.content, options)
+            .into_iter()
+            .map(|resource_url| (resource_url, 0))
+            .collect();
+    let mut seen = HashSet::new();
+    // The same resources that archiving would have downloaded, down to the
+    // same depths
+    while let Some((resource_url, depth)) = queue.pop_front() {
+        let url = resource_url.url().clone();
+        if options.skips(&archive.url, &resource_url)
+            || !seen.insert(url.clone())
+        {
+            continue;
+        }
+        let resource = match archive.resource_map.get(&url) {
+            Some(resource) => resource,
+            None if failed.contains(&url) => {
+                verification.failed.push(url);
+                continue;
+            }
+            None => {
+                verification.missing.push(url);
+                continue;
+            }
+        };
+        match resource {
+            Resource::Javascript(script) if depth < options.module_depth => {
+                for import in module_imports(&url, &script.text()) {
+                    queue.push_back((
+                        ResourceUrl::Javascript(import),
+                        depth + 1,
+                    ));
+                }
+            }
+            Resource::Document(frame) => {
+                let html = frame.text();
+                hashes.extend(integrity_hashes(&url, &html));
+                for resource_url in parse_resource_urls(&url, &html, options) {
+                    match resource_url {
+                        ResourceUrl::Document(_)
+                            if depth + 1 >= options.frame_depth => {}
+                        ResourceUrl::Document(_) => {
+                            queue.push_back((resource_url, depth + 1))
+                        }
+                        _ => queue.push_back((resource_url, 0)),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (url, integrity) in hashes {
+        let matches = archive.resource_map.get(&url).and_then(|resource| {
+            matches_integrity(resource.data(), &integrity)
+        });
+        if matches == Some(false) {
+            verification.mismatched.push(url);
+        }
+    }
+
+    // Anything changed since the digests were recorded
+    for (url, meta) in &archive.meta {
+        let data = if url == &archive.url {
+            archive.content.as_bytes()
+        } else {
+            match archive.resource_map.get(url) {
+                Some(resource) => resource.data(),
+                None => continue,
+            }
+        };
+        if let Some(recorded) = &meta.sha256 {
+            if *recorded != digest::sha256(data) {
+                verification.mismatched.push(url.clone());
+            }
+        }
+    }
+
+    for urls in [
+        &mut verification.missing,
+        &mut verification.failed,
+        &mut verification.mismatched,
+    ] {
+        urls.sort();
+        urls.dedup();
+    }
+    verification
+}
+
+/// The `integrity` attributes of the scripts and stylesheets in a page,
+/// with the URLs they apply to
+fn integrity_hashes(url: &Url, html: &str) -> Vec<(Url, String)> {
+    let document = kuchiki::parse_html().one(html);
+    let base = base_url(url, &document);
+    let mut hashes = Vec::new();
+    for element in document.select("script, link").unwrap() {
+        let attributes = element.attributes.borrow();
+        let name = if &*element.name.local == "script" {
+            "src"
+        } else {
+            "href"
+        };
+        let u = attributes.get(name).and_then(|u| resolve_url(&base, u));
+        if let (Some(u), Some(integrity)) = (u, attributes.get("integrity")) {
+            hashes.push((u, integrity.to_string()));
+        }
+    }
+    hashes
+}
+
+/// Whether `data` matches an `integrity` attribute, following the rules
+/// of Subresource Integrity: only the hashes using the strongest of the
+/// algorithms given count, and any of them may match. Gives `None` if
+/// none of the algorithms are supported.
+pub(crate) fn matches_integrity(data: &[u8], integrity: &str) -> Option<bool> {
+    let hashes: Vec<_> = integrity
+        .split_whitespace()
+        .filter_map(|hash| {
+            let (algorithm, digest) = hash.split_once('-')?;
+            // Options after a `?` are reserved, and ignored
+            let digest = digest.split('?').next()?;
+            let strength = match algorithm {
+                "sha256" => 1,
+                "sha384" => 2,
+                "sha512" => 3,
+                _ => return None,
+            };
+            Some((strength, digest))
+        })
+        .collect();
+    let strongest = hashes.iter().map(|(strength, _)| *strength).max()?;
+    let actual = base64::encode(match strongest {
+        1 => Sha256::digest(data).to_vec(),
+        2 => Sha384::digest(data).to_vec(),
+        _ => Sha512::digest(data).to_vec(),
+    });
+    Some(
+        hashes
+            .iter()
+            .filter(|(strength, _)| *strength == strongest)
+            .any(|(_, digest)| *digest == actual),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::failure::{FailureReason, ResourceFailure};
+    use crate::filter_list::FilterList;
+    use crate::meta::ResourceMeta;
+    use crate::parsing::{ImageResource, TextResource};
+    use bytes::Bytes;
+    use regex::Regex;
+    use reqwest::header::HeaderMap;
+    use reqwest::StatusCode;
+
+    fn text(mimetype: &str, text: &'static str) -> TextResource {
+        TextResource {
+            data: Bytes::from_static(text.as_bytes()),
+            mimetype: mimetype.to_string(),
+            charset: None,
+        }
+    }
+
+    fn archive(content: &str, resources: Vec<(&str, Resource)>) -> PageArchive {
+        let url = Url::parse("http://example.com/").unwrap();
+        let resource_map = resources
+            .into_iter()
+            .map(|(u, resource)| (url.join(u).unwrap(), resource))
+            .collect();
+        PageArchive {
+            url,
+            content: content.to_string(),
+            resource_map,
+            lazy_attributes: vec!["data-src".to_string()],
+            url_attributes: vec![("div".to_string(), "data-bg".to_string())],
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: Default::default(),
+            meta: Default::default(),
+            stats: Default::default(),
+            screenshot: None,
+        }
+    }
+
+    fn urls(urls: &[&str]) -> Vec<Url> {
+        let base = Url::parse("http://example.com/").unwrap();
+        urls.iter().map(|u| base.join(u).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_matches_integrity() {
+        // `echo -n "alert('Hello, world.');" | openssl dgst -sha384 -binary
+        // | base64`, from the Subresource Integrity spec
+        let data = b"alert('Hello, world.');";
+        let sha384 =
+            "sha384-H8BRh8j48O9oYatfu5AZzq6A9RINhZO5H16dQZngK7T62em8MUt1FLm52t+eX6xO";
+        assert_eq!(matches_integrity(data, sha384), Some(true));
+        assert_eq!(
+            matches_integrity(data, &format!("sha256-abc {}", sha384)),
+            Some(true)
+        );
+        // Only the strongest algorithm counts
+        assert_eq!(
+            matches_integrity(data, &format!("{} sha512-abc", sha384)),
+            Some(false)
+        );
+        assert_eq!(matches_integrity(data, "md5-abc"), None);
+        assert_eq!(matches_integrity(data, "nonsense"), None);
+    }
+
+    #[test]
+    fn test_verify() {
+        let image = || {
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"PNG"),
+                mimetype: "image/png".to_string(),
+            })
+        };
+        let content = r#"
+            <link rel="stylesheet" href="style.css" integrity="sha256-abc">
+            <script src="app.js"></script>
+            <frameset><frame src="frame.html"></frameset>
+        "#;
+        let mut archive = archive(
+            content,
+            vec![
+                ("style.css", Resource::Css(text("text/css", "p {}"))),
+                (
+                    "app.js",
+                    Resource::Javascript(text(
+                        "text/javascript",
+                        "import './lib.js';",
+                    )),
+                ),
+                ("a.png", image()),
+                ("lazy.png", image()),
+                (
+                    "frame.html",
+                    Resource::Document(text(
+                        "text/html",
+                        r#"
+                            <img src="a.png"><img data-src="lazy.png">
+                            <div data-bg="bg.png"></div>
+                            <img src="gone.png"><img src="lost.png">
+                            <img src="frames/b.png">
+                        "#,
+                    )),
+                ),
+            ],
+        );
+        archive.failures.push(ResourceFailure {
+            url: urls(&["gone.png"]).remove(0),
+            reason: FailureReason::Status(404),
+            retries: 0,
+        });
+
+        let options = ArchiveOptions::default().extra_url_attributes(
+            "div",
+            "data-bg",
+            ResourceUrl::Image,
+        );
+        let verification = archive.verify(&options);
+        assert_eq!(
+            verification,
+            Verification {
+                missing: urls(&[
+                    "bg.png",
+                    "frames/b.png",
+                    "lib.js",
+                    "lost.png"
+                ]),
+                failed: urls(&["gone.png"]),
+                mismatched: urls(&["style.css"]),
+            }
+        );
+        assert!(!verification.is_ok());
+
+        // Resources which the options leave out aren't missing
+        let mut options = options.images(false);
+        options.module_depth = 0;
+        let verification = archive.verify(&options);
+        assert!(verification.missing.is_empty());
+        assert!(verification.failed.is_empty());
+    }
+
+    #[test]
+    fn test_verify_skipped() {
+        let archive = archive(
+            r#"
+                <script src="https://cdn.example.net/app.js"></script>
+                <img src="https://tracker.example.net/pixel.gif">
+                <img src="logo.png">
+            "#,
+            Vec::new(),
+        );
+        let verification = archive.verify(&Default::default());
+        assert_eq!(verification.missing.len(), 3);
+
+        let list = FilterList::parse("||tracker.example.net^");
+        let options = ArchiveOptions {
+            filter_list: Some(&list),
+            ..Default::default()
+        }
+        .same_origin(true)
+        .allowed_host("tracker.example.net")
+        .exclude(Regex::new("logo").unwrap());
+        let verification = archive.verify(&options);
+        assert_eq!(verification.missing, Vec::<Url>::new());
+    }
+
+    #[test]
+    fn test_verify_digests() {
+        let mut archive = archive(
+            r#"<img src="logo.png">"#,
+            vec![(
+                "logo.png",
+                Resource::Image(ImageResource {
+                    data: Bytes::from_static(b"png"),
+                    mimetype: "image/png".to_string(),
+                }),
+            )],
+        );
+        for u in urls(&["/", "logo.png"]) {
+            let meta = ResourceMeta::new(StatusCode::OK, &u, &HeaderMap::new());
+            archive.meta.insert(u, meta);
+        }
+        digest::record(&mut archive);
+        assert!(archive.verify(&Default::default()).is_ok());
+
+        archive.content.push_str("<p>Changed</p>");
+        let logo = urls(&["logo.png"]).remove(0);
+        if let Some(Resource::Image(image)) =
+            archive.resource_map.get_mut(&logo)
+        {
+            image.data = Bytes::from_static(b"gif");
+        }
+        let verification = archive.verify(&Default::default());
+        assert_eq!(verification.mismatched, urls(&["/", "logo.png"]));
+    }
+
+    #[test]
+    fn test_verify_complete() {
+        let archive = archive(
+            r#"<script src="app.js" integrity="sha256-5jFwrAK0UV47oFbVg/iCCBbxD8X1w+QvoOUepu4C2YA="></script>"#,
+            vec![(
+                "app.js",
+                Resource::Javascript(text("text/javascript", "alert(1);")),
+            )],
+        );
+        assert!(archive.verify(&Default::default()).is_ok());
+    }
+}
@@ -0,0 +1,289 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for packaging archives as WACZ files, see
+//! [`PageArchive::write_wacz`]
+
+use crate::digest;
+use crate::page_archive::PageArchive;
+use crate::warc::{self, format_date, Written};
+use kuchiki::traits::TendrilSink;
+use serde_json::json;
+use std::io::{self, Seek, Write};
+use std::time::SystemTime;
+use url::Url;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Version of the WACZ specification that the packages follow
+const WACZ_VERSION: &str = "1.1.1";
+/// Where the WARC file, index, and list of pages go in the package
+const WARC: &str = "archive/data.warc";
+const INDEX: &str = "indexes/index.cdxj";
+const PAGES: &str = "pages/pages.jsonl";
+
+/// Write the archive out as a WACZ package
+pub(crate) fn write<W: Write + Seek>(
+    archive: &PageArchive,
+    writer: W,
+) -> io::Result<()> {
+    let (warc, records) = warc::write_records(archive);
+    let index = index(&records);
+    let pages = pages(archive, &records);
+    let files = [(WARC, warc), (INDEX, index), (PAGES, pages)];
+
+    let resources: Vec<_> = files
+        .iter()
+        .map(|(path, data)| {
+            json!({
+                "name": path.rsplit('/').next(),
+                "path": path,
+                "hash": sha256(data),
+                "bytes": data.len(),
+            })
+        })
+        .collect();
+    let datapackage = json!({
+        "profile": "data-package",
+        "wacz_version": WACZ_VERSION,
+        "title": archive.url.as_str(),
+        "created": format_date(SystemTime::now()),
+        "software": format!("web-archive {}", env!("CARGO_PKG_VERSION")),
+        "mainPageUrl": archive.url.as_str(),
+        "mainPageDate": format_date(page_date(archive, &records)),
+        "resources": resources,
+    });
+    let datapackage = serde_json::to_vec_pretty(&datapackage).unwrap();
+    let digest = json!({
+        "path": "datapackage.json",
+        "hash": sha256(&datapackage),
+    });
+    let digest = serde_json::to_vec_pretty(&digest).unwrap();
+
+    // Players read the WARC records straight out of the package by their
+    // offsets, so nothing is compressed
+    let options =
+        FileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut zip = ZipWriter::new(writer);
+    let metadata = [
+        ("datapackage.json", datapackage),
+        ("datapackage-digest.json", digest),
+    ];
+    for (path, data) in files.iter().chain(&metadata) {
+        zip.start_file(*path, options)?;
+        zip.write_all(data)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// A CDXJ index of the records, which players use to find each response
+/// in the WARC file
+fn index(records: &[Written]) -> Vec<u8> {
+    let mut lines: Vec<_> = records
+        .iter()
+        .map(|record| {
+            let fields = json!({
+                "url": record.url.as_str(),
+                "mime": record.mimetype,
+                "status": record.status.to_string(),
+                "digest": sha256(&record.payload),
+                "length": record.length.to_string(),
+                "offset": record.offset.to_string(),
+                "filename": "data.warc",
+            });
+            format!(
+                "{} {} {}\n",
+                surt(&record.url),
+                timestamp(record.date),
+                fields
+            )
+        })
+        .collect();
+    lines.sort();
+    lines.concat().into_bytes()
+}
+
+/// The list of pages in the package, which is just the one
+fn pages(archive: &PageArchive, records: &[Written]) -> Vec<u8> {
+    let header = json!({
+        "format": "json-pages-1.0",
+        "id": "pages",
+        "title": "All Pages",
+    });
+    let document = kuchiki::parse_html().one(archive.content.as_str());
+    let title = document
+        .select_first("title")
+        .map(|title| title.text_contents().trim().to_string())
+        .unwrap_or_default();
+    let page = json!({
+        "id": &sha256(archive.url.as_str().as_bytes())["sha256:".len()..][..32],
+        "url": archive.url.as_str(),
+        "ts": format_date(page_date(archive, records)),
+        "title": title,
+    });
+    format!("{}\n{}\n", header, page).into_bytes()
+}
+
+/// When the page was captured
+fn page_date(archive: &PageArchive, records: &[Written]) -> SystemTime {
+    records
+        .iter()
+        .find(|record| record.url == archive.url)
+        .map(|record| record.date)
+        .unwrap_or_else(SystemTime::now)
+}
+
+/// The Sort-friendly URI Reordering Transform of a URL, which the index is
+/// sorted by, e.g. `com,example)/path?query` for
+/// `http://www.example.com/path?query`. URIs without a host, such as
+/// the screenshot's `urn:screenshot:` URI, are left as they are.
+fn surt(url: &Url) -> String {
+    if url.cannot_be_a_base() {
+        return url.to_string();
+    }
+    let host = url.host_str().unwrap_or("").to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let mut surt: String = host.rsplit('.').collect::<Vec<_>>().join(",");
+    if let Some(port) = url.port() {
+        surt.push_str(&format!(":{}", port));
+    }
+    surt.push(')');
+    surt.push_str(&url.path().to_lowercase());
+    if let Some(query) = url.query() {
+        surt.push('?');
+        surt.push_str(&query.to_lowercase());
+    }
+    surt
+}
+
+/// A 14 digit timestamp, e.g. `20210203040506`
+fn timestamp(date: SystemTime) -> String {
+    format_date(date)
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect()
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("sha256:{}", digest::sha256(data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::meta::ResourceMeta;
+    use crate::parsing::{ImageResource, Resource};
+    use bytes::Bytes;
+    use reqwest::header::HeaderMap;
+    use reqwest::StatusCode;
+    use std::collections::HashMap;
+    use std::io::{Cursor, Read};
+    use std::time::{Duration, UNIX_EPOCH};
+    use zip::ZipArchive;
+
+    #[test]
+    fn test_surt() {
+        let surt = |u| super::surt(&Url::parse(u).unwrap());
+        assert_eq!(surt("http://www.Example.com/A?b=C"), "com,example)/a?b=c");
+        assert_eq!(
+            surt("https://sub.example.com:8080/"),
+            "com,example,sub:8080)/"
+        );
+        assert_eq!(
+            surt("urn:screenshot:http://example.com/"),
+            "urn:screenshot:http://example.com/"
+        );
+    }
+
+    #[test]
+    fn test_wacz() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let image = url.join("image.png").unwrap();
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(1_612_325_106);
+        let mut resource_map = HashMap::new();
+        resource_map.insert(
+            image.clone(),
+            Resource::Image(ImageResource {
+                data: Bytes::from_static(b"png"),
+                mimetype: "image/png".to_string(),
+            }),
+        );
+        let mut meta = HashMap::new();
+        for u in [&url, &image] {
+            let mut m = ResourceMeta::new(StatusCode::OK, u, &HeaderMap::new());
+            m.fetched_at = fetched_at;
+            meta.insert(u.clone(), m);
+        }
+        let archive = PageArchive {
+            url: url.clone(),
+            content: "<title> Example </title><img src=\"image.png\">"
+                .to_string(),
+            resource_map,
+            lazy_attributes: Vec::new(),
+            url_attributes: Vec::new(),
+            timed_out: Vec::new(),
+            failures: Vec::new(),
+            cancelled: false,
+            redirects: HashMap::new(),
+            meta,
+            stats: Default::default(),
+            screenshot: Some(Bytes::from_static(b"\x89PNG")),
+        };
+
+        let mut package = Cursor::new(Vec::new());
+        write(&archive, &mut package).unwrap();
+        let mut zip = ZipArchive::new(package).unwrap();
+        let mut read = |path: &str| {
+            let mut data = Vec::new();
+            zip.by_name(path).unwrap().read_to_end(&mut data).unwrap();
+            data
+        };
+
+        let warc = read(WARC);
+        let index = String::from_utf8(read(INDEX)).unwrap();
+        let pages = String::from_utf8(read(PAGES)).unwrap();
+        let datapackage: serde_json::Value =
+            serde_json::from_slice(&read("datapackage.json")).unwrap();
+        let digest: serde_json::Value =
+            serde_json::from_slice(&read("datapackage-digest.json")).unwrap();
+
+        assert_eq!(datapackage["wacz_version"], WACZ_VERSION);
+        assert_eq!(datapackage["mainPageDate"], "2021-02-03T04:05:06Z");
+        assert_eq!(datapackage["resources"][0]["path"], WARC);
+        assert_eq!(datapackage["resources"][0]["hash"], sha256(&warc));
+        assert_eq!(
+            digest["hash"],
+            sha256(&serde_json::to_vec_pretty(&datapackage).unwrap())
+        );
+
+        let lines: Vec<_> = index.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("com,example)/ 20210203040506 {"));
+        assert!(lines[1].starts_with("com,example)/image.png "));
+        assert!(lines[2].starts_with("urn:screenshot:http://example.com/ "));
+        let fields: serde_json::Value =
+            serde_json::from_str(lines[1].splitn(3, ' ').nth(2).unwrap())
+                .unwrap();
+        let offset: usize = fields["offset"].as_str().unwrap().parse().unwrap();
+        assert!(warc[offset..].starts_with(b"WARC/1.1\r\nWARC-Type: response"));
+        assert_eq!(fields["digest"], sha256(b"png"));
+
+        let pages: Vec<serde_json::Value> = pages
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(pages[0]["format"], "json-pages-1.0");
+        assert_eq!(pages[1]["url"], "http://example.com/");
+        assert_eq!(pages[1]["title"], "Example");
+
+        let replayed =
+            crate::read_warc(&warc[..], None, Default::default()).unwrap();
+        assert_eq!(replayed.resource_map, archive.resource_map);
+        assert_eq!(replayed.screenshot, archive.screenshot);
+    }
+}
@@ -0,0 +1,1170 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for replaying pages from WARC files, see [`read_warc`]
+
+use crate::failure::FailureReason;
+use crate::meta::ResourceMeta;
+use crate::page_archive::PageArchive;
+use crate::replay::{parse_date, replay, Record, Records};
+use crate::{ArchiveOptions, Error};
+use bytes::Bytes;
+use flate2::read::{GzDecoder, MultiGzDecoder, ZlibDecoder};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE,
+    LOCATION, TRANSFER_ENCODING,
+};
+use reqwest::StatusCode;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// Read a page and its resources from a WARC file, such as one written by
+/// `wget --warc-file` or kept by a web archive, as though it were being
+/// archived from the network.
+///
+/// The page is the response in the file for the `target` URI, or if that's
+/// `None` the first HTML response in it. Its resources are found in the
+/// same way as when archiving, following the `options`, and looked up in
+/// the file instead of being downloaded. Nothing is requested from the
+/// network: resources which aren't in the file are reported in the
+/// archive's [`failures`](PageArchive::failures) as
+/// [`FailureReason::NotInWarc`], and left pointing at their original URLs.
+///
+/// Files compressed with gzip, as `.warc.gz` files usually are, are
+/// decompressed as they're read, a record at a time. `response` and
+/// `resource` records are used, with redirects followed to their targets,
+/// as are `revisit` records whose earlier capture is in the file. If a URI
+/// was captured more than once the first capture is used. The
+/// [`meta`](PageArchive::meta) of each resource has the date that it was
+/// captured. Resources larger than the
+/// [`max_resource_size`](ArchiveOptions::max_resource_size) once
+/// decompressed are reported as [`FailureReason::TooLarge`] without being
+/// read any further.
+///
+/// Fails with [`Error::NotInWarc`] if the page isn't in the file.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::fs::File;
+/// use web_archive::read_warc;
+///
+/// let file = File::open("example.warc.gz").unwrap();
+/// let archive =
+///     read_warc(file, Some("http://example.com/"), Default::default())
+///         .unwrap();
+/// println!("{}", archive.embed_resources());
+/// ```
+pub fn read_warc<R: Read>(
+    reader: R,
+    target: Option<&str>,
+    options: ArchiveOptions<'_>,
+) -> Result<PageArchive, Error> {
+    let target = target
+        .map(Url::parse)
+        .transpose()
+        .map_err(|e| Error::ParseError(format!("{}", e)))?;
+    let records =
+        read_records(reader, target.as_ref(), options.max_resource_size)?;
+    let target = match target.or_else(|| records.first_page.clone()) {
+        Some(target) => target,
+        None => {
+            let error = "no HTML response in the WARC file".to_string();
+            return Err(Error::NotInWarc(error));
+        }
+    };
+    let mut archive = replay(&records, target, &options, Error::NotInWarc)?;
+    let screenshot = records.responses.get(&screenshot_url(&archive.url));
+    archive.screenshot = screenshot.map(|record| record.data.clone());
+    Ok(archive)
+}
+
+/// Prefix of the URIs of screenshots, see [`screenshot_url`]
+const SCREENSHOT: &str = "urn:screenshot:";
+
+/// Longest HTTP message head read from a record
+const MAX_HEAD: u64 = 64 * 1024;
+
+/// A `revisit` record, which points at an earlier capture of the same
+/// response instead of repeating its payload
+struct Revisit {
+    url: Url,
+    captured: SystemTime,
+    /// Status and headers of the response, if the record has them
+    head: Option<(StatusCode, HeaderMap)>,
+    /// `WARC-Refers-To-Target-URI` of the earlier capture
+    refers_to: Option<Url>,
+    /// `WARC-Payload-Digest` shared with the earlier capture
+    digest: Option<String>,
+}
+
+/// Read every record from a WARC file, which may be compressed with gzip.
+/// Records are read one at a time, and only the responses which are used
+/// are kept. Responses larger than `max_size` bytes once decoded are
+/// recorded as [`FailureReason::TooLarge`] instead, except for the page:
+/// the `target`, or the first HTML response if there isn't one, and
+/// wherever it redirects to.
+fn read_records<R: Read>(
+    reader: R,
+    target: Option<&Url>,
+    max_size: Option<u64>,
+) -> io::Result<Records> {
+    let mut reader = BufReader::new(reader);
+    let mut reader: Box<dyn BufRead> =
+        if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+            // Each record is usually compressed separately, one after
+            // another
+            Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+        } else {
+            Box::new(reader)
+        };
+
+    let mut reading = Reading {
+        records: Records::new(FailureReason::NotInWarc),
+        pages: target.cloned().into_iter().collect(),
+        target_given: target.is_some(),
+        max_size,
+        digests: HashMap::new(),
+        revisits: Vec::new(),
+    };
+    while let Some(headers) = read_warc_headers(&mut reader)? {
+        let length: u64 = headers
+            .get("content-length")
+            .and_then(|length| length.trim().parse().ok())
+            .ok_or_else(|| invalid("WARC record without a Content-Length"))?;
+        let mut block = (&mut reader).take(length);
+        reading.record(&headers, &mut block)?;
+        // Skip whatever of the block wasn't read
+        io::copy(&mut block, &mut io::sink())?;
+        if block.limit() > 0 {
+            return Err(invalid("truncated WARC record"));
+        }
+    }
+    Ok(reading.finish())
+}
+
+/// The records read from a WARC file so far
+struct Reading {
+    records: Records,
+    /// URLs of the page and wherever it redirects to, which aren't limited
+    /// to `max_size`
+    pages: HashSet<Url>,
+    target_given: bool,
+    max_size: Option<u64>,
+    /// URLs of the responses by their payload digests, for revisits
+    digests: HashMap<String, Url>,
+    revisits: Vec<Revisit>,
+}
+
+impl Reading {
+    /// Read the record with the WARC `headers` from its `block`, if it's
+    /// one that's used
+    fn record<R: BufRead>(
+        &mut self,
+        headers: &HashMap<String, String>,
+        block: &mut R,
+    ) -> io::Result<()> {
+        let url = headers
+            .get("warc-target-uri")
+            .map(|uri| uri.trim_start_matches('<').trim_end_matches('>'))
+            .and_then(|uri| Url::parse(uri).ok())
+            .map(|mut url| {
+                url.set_fragment(None);
+                url
+            })
+            .filter(|url| !self.records.contains(url));
+        let url = match url {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+        let captured = headers
+            .get("warc-date")
+            .and_then(|date| parse_date(date))
+            .unwrap_or_else(SystemTime::now);
+        let digest = headers.get("warc-payload-digest").cloned();
+        match headers.get("warc-type").map(String::as_str) {
+            Some("response") => {
+                if let Some(digest) = digest {
+                    self.digests.insert(digest, url.clone());
+                }
+                self.response(url, captured, block)
+            }
+            Some("resource") => {
+                let content_type = headers.get("content-type");
+                self.resource(url, captured, content_type, block)
+            }
+            Some("revisit") => {
+                let refers_to = headers
+                    .get("warc-refers-to-target-uri")
+                    .map(|uri| uri.trim_start_matches('<'))
+                    .map(|uri| uri.trim_end_matches('>'))
+                    .and_then(|uri| Url::parse(uri).ok());
+                self.revisits.push(Revisit {
+                    url,
+                    captured,
+                    head: read_head(block)?,
+                    refers_to,
+                    digest,
+                });
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Read a `response` record for `url`
+    fn response<R: BufRead>(
+        &mut self,
+        url: Url,
+        captured: SystemTime,
+        block: &mut R,
+    ) -> io::Result<()> {
+        let (status, headers) = match read_head(block)? {
+            Some(head) => head,
+            None => return Ok(()),
+        };
+        let is_page = self.pages.contains(&url)
+            || (!self.target_given
+                && self.records.first_page.is_none()
+                && status.is_success()
+                && is_html(&headers));
+        if is_page && status.is_redirection() {
+            let location = header(&headers, LOCATION);
+            let next = location.and_then(|location| url.join(location).ok());
+            self.pages.extend(next);
+        }
+        let limit = self.max_size.filter(|_| !is_page);
+        match parse_response(&url, (status, headers), block, limit)? {
+            Some(mut record) => {
+                record.meta.fetched_at = captured;
+                self.records.insert(url, record);
+            }
+            None => self.records.insert_failed(url, FailureReason::TooLarge),
+        }
+        Ok(())
+    }
+
+    /// Read a `resource` record for `url`, whose block is the resource
+    fn resource<R: BufRead>(
+        &mut self,
+        url: Url,
+        captured: SystemTime,
+        content_type: Option<&String>,
+        block: &mut R,
+    ) -> io::Result<()> {
+        let limit = self
+            .max_size
+            .filter(|_| !url.as_str().starts_with(SCREENSHOT));
+        let data = match read_limited(block, limit)? {
+            Some(data) => data,
+            None => {
+                self.records.insert_failed(url, FailureReason::TooLarge);
+                return Ok(());
+            }
+        };
+        let mut headers = HeaderMap::new();
+        let content_type =
+            content_type.and_then(|value| HeaderValue::from_str(value).ok());
+        if let Some(content_type) = content_type {
+            headers.insert(CONTENT_TYPE, content_type);
+        }
+        let status = StatusCode::OK;
+        let mut meta = ResourceMeta::new(status, &url, &headers);
+        meta.fetched_at = captured;
+        let record = Record {
+            status,
+            location: None,
+            meta,
+            data: Bytes::from(data),
+        };
+        self.records.insert(url, record);
+        Ok(())
+    }
+
+    /// The records, once every revisit has been resolved
+    fn finish(mut self) -> Records {
+        for revisit in std::mem::take(&mut self.revisits) {
+            resolve_revisit(&mut self.records, &self.digests, revisit);
+        }
+        self.records
+    }
+}
+
+/// Add the response captured by a `revisit` record, with the payload of
+/// the capture it refers to, if that's in the file
+fn resolve_revisit(
+    records: &mut Records,
+    digests: &HashMap<String, Url>,
+    revisit: Revisit,
+) {
+    let Revisit {
+        url,
+        captured,
+        head,
+        refers_to,
+        digest,
+    } = revisit;
+    let original = refers_to
+        .filter(|original| records.contains(original))
+        .or_else(|| digest.and_then(|digest| digests.get(&digest).cloned()));
+    let original = match original {
+        Some(original) => original,
+        None => return,
+    };
+    if let Some(reason) = records.failed.get(&original) {
+        let reason = reason.clone();
+        records.insert_failed(url, reason);
+        return;
+    }
+    let original = match records.responses.get(&original) {
+        Some(original) => original,
+        None => return,
+    };
+    let mut record = match head {
+        // A `304 Not Modified` only says that the earlier capture is
+        // still current
+        Some((status, headers)) if status != StatusCode::NOT_MODIFIED => {
+            Record {
+                status,
+                location: header(&headers, LOCATION).map(str::to_string),
+                meta: ResourceMeta::new(status, &url, &headers),
+                data: original.data.clone(),
+            }
+        }
+        _ => Record {
+            status: original.status,
+            location: original.location.clone(),
+            meta: ResourceMeta {
+                final_url: url.clone(),
+                ..original.meta.clone()
+            },
+            data: original.data.clone(),
+        },
+    };
+    record.meta.fetched_at = captured;
+    records.insert(url, record);
+}
+
+/// Read the headers of the next WARC record, with lowercase names, or
+/// `None` once there are no more records
+fn read_warc_headers<R: BufRead>(
+    reader: &mut R,
+) -> io::Result<Option<HashMap<String, String>>> {
+    // Records are separated by blank lines
+    let version = loop {
+        match read_line(reader)? {
+            Some(line) if line.trim().is_empty() => continue,
+            Some(line) => break line,
+            None => return Ok(None),
+        }
+    };
+    if !version.starts_with("WARC/") {
+        return Err(invalid("expected a WARC record"));
+    }
+    let mut headers = HashMap::new();
+    loop {
+        let line = read_line(reader)?
+            .ok_or_else(|| invalid("truncated WARC headers"))?;
+        if line.is_empty() {
+            return Ok(Some(headers));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim().to_ascii_lowercase();
+            headers.insert(name, value.trim().to_string());
+        }
+    }
+}
+
+/// Read the status line and headers of the HTTP response at the start of a
+/// record's block, or `None` if it doesn't start with one
+fn read_head<R: BufRead>(
+    block: &mut R,
+) -> io::Result<Option<(StatusCode, HeaderMap)>> {
+    let mut head = block.take(MAX_HEAD);
+    let status_line = match read_line(&mut head)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| StatusCode::from_bytes(status.as_bytes()).ok());
+    let status = match status {
+        Some(status) => status,
+        None => return Ok(None),
+    };
+    let mut headers = HeaderMap::new();
+    loop {
+        let line = match read_line(&mut head)? {
+            Some(line) if !line.is_empty() => line,
+            Some(_) => return Ok(Some((status, headers))),
+            // Cut off before the end of the head
+            None => return Ok(None),
+        };
+        if let Some((name, value)) = line.split_once(':') {
+            let name = HeaderName::from_bytes(name.trim().as_bytes());
+            let value = HeaderValue::from_str(value.trim());
+            if let (Ok(name), Ok(value)) = (name, value) {
+                headers.append(name, value);
+            }
+        }
+    }
+}
+
+/// Read the body of an HTTP response after its `head`, decoding it, or
+/// `None` if it's larger than `limit` bytes
+fn parse_response<R: Read>(
+    url: &Url,
+    (status, headers): (StatusCode, HeaderMap),
+    body: R,
+    limit: Option<u64>,
+) -> io::Result<Option<Record>> {
+    let mut data = match read_limited(body, limit)? {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+    let chunked = header(&headers, TRANSFER_ENCODING)
+        .map(|encoding| encoding.to_ascii_lowercase().contains("chunked"))
+        == Some(true);
+    if chunked {
+        data = dechunk(&data).unwrap_or(data);
+    }
+    let encoding = header(&headers, CONTENT_ENCODING)
+        .map(|e| e.trim().to_ascii_lowercase());
+    let decoded = match encoding.as_deref() {
+        Some("gzip") | Some("x-gzip") => {
+            read_limited(GzDecoder::new(&data[..]), limit)
+        }
+        Some("deflate") => read_limited(ZlibDecoder::new(&data[..]), limit),
+        _ => Ok(Some(data.clone())),
+    };
+    data = match decoded {
+        Ok(Some(decoded)) => decoded,
+        Ok(None) => return Ok(None),
+        // Keep it as it is
+        Err(_) => data,
+    };
+
+    Ok(Some(Record {
+        status,
+        location: header(&headers, LOCATION).map(str::to_string),
+        meta: ResourceMeta::new(status, url, &headers),
+        data: Bytes::from(data),
+    }))
+}
+
+/// Read the next line, without its line ending, or `None` at the end
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    let line = line.strip_suffix(b"\n").unwrap_or(&line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    Ok(Some(String::from_utf8_lossy(line).into_owned()))
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn is_html(headers: &HeaderMap) -> bool {
+    header(headers, CONTENT_TYPE)
+        .map(|t| t.trim_start().starts_with("text/html"))
+        == Some(true)
+}
+
+/// Undo `Transfer-Encoding: chunked`, or `None` if the body isn't validly
+/// chunked
+fn dechunk(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let end = find(data, b"\r\n")?;
+        let size = std::str::from_utf8(&data[..end]).ok()?;
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        data = &data[end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+/// Read everything from the `reader`, or `None` if it's more than `limit`
+/// bytes, without reading much more than that
+fn read_limited<R: Read>(
+    reader: R,
+    limit: Option<u64>,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    let most = limit.map_or(u64::MAX, |limit| limit.saturating_add(1));
+    reader.take(most).read_to_end(&mut data)?;
+    let fits = limit.map_or(true, |limit| data.len() as u64 <= limit);
+    Ok(Some(data).filter(|_| fits))
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A record written by [`write_records`], for indexing it
+#[cfg_attr(not(feature = "wacz"), allow(dead_code))]
+pub(crate) struct Written {
+    pub url: Url,
+    pub date: SystemTime,
+    pub status: u16,
+    pub mimetype: String,
+    /// The response body
+    pub payload: Bytes,
+    /// Where the record starts in the file, and how long it is
+    pub offset: usize,
+    pub length: usize,
+}
+
+/// Write the page and its resources out as a WARC file, returning it along
+/// with where each response record was written. Redirects are written as
+/// `3xx` responses pointing at where they led, so that reading the file
+/// back with [`read_warc`] follows them again. The screenshot, if there is
+/// one, is written last as a `resource` record, see [`screenshot_url`].
+#[cfg_attr(not(feature = "wacz"), allow(dead_code))]
+pub(crate) fn write_records(archive: &PageArchive) -> (Vec<u8>, Vec<Written>) {
+    let mut warc = Vec::new();
+    write_info(&mut warc, archive.url.as_str());
+
+    let date = |url: &Url| {
+        archive
+            .meta
+            .get(url)
+            .map(|meta| meta.fetched_at)
+            .unwrap_or_else(SystemTime::now)
+    };
+    let mut written: Vec<Written> = Vec::new();
+    let mut redirects: Vec<_> = archive.redirects.iter().collect();
+    redirects.sort();
+    for (requested, chain) in redirects {
+        let hops = std::iter::once(requested).chain(chain).zip(chain);
+        for (from, to) in hops {
+            let head = format!(
+                "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\n",
+                to
+            );
+            written.extend(write_response(
+                &mut warc,
+                &written,
+                from,
+                date(from),
+                302,
+                &head,
+                Bytes::new(),
+            ));
+        }
+    }
+
+    let page = Bytes::from(archive.content.clone());
+    let mut resources = vec![(&archive.url, "text/html; charset=utf-8", page)];
+    let mut urls: Vec<_> = archive.resource_map.keys().collect();
+    urls.sort();
+    let content_types: Vec<_> = urls
+        .iter()
+        .map(|url| archive.resource_map[*url].content_type())
+        .collect();
+    for (url, content_type) in urls.into_iter().zip(&content_types) {
+        let data = archive.resource_map[url].data().clone();
+        resources.push((url, content_type, data));
+    }
+    for (url, content_type, data) in resources {
+        let status = archive
+            .meta
+            .get(url)
+            .map(|meta| meta.status)
+            .filter(|status| (200..300).contains(status))
+            .unwrap_or(200);
+        let reason = StatusCode::from_u16(status)
+            .ok()
+            .and_then(|status| status.canonical_reason())
+            .unwrap_or("OK");
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+            status,
+            reason,
+            content_type,
+            data.len()
+        );
+        written.extend(write_response(
+            &mut warc,
+            &written,
+            url,
+            date(url),
+            status,
+            &head,
+            data,
+        ));
+    }
+    if let Some(screenshot) = &archive.screenshot {
+        let url = screenshot_url(&archive.url);
+        written.push(write_resource(
+            &mut warc,
+            &written,
+            &url,
+            date(&archive.url),
+            "image/png",
+            screenshot.clone(),
+        ));
+    }
+    (warc, written)
+}
+
+/// The URI that the screenshot of the page at `url` is stored under, e.g.
+/// `urn:screenshot:http://example.com/`, as other archiving tools do
+fn screenshot_url(url: &Url) -> Url {
+    Url::parse(&format!("{}{}", SCREENSHOT, url)).unwrap()
+}
+
+/// Write recorded responses out as a WARC file, in the order of their
+/// `urls`
+#[cfg(feature = "proxy")]
+pub(crate) fn write_recorded(records: &Records, urls: &[Url]) -> Vec<u8> {
+    let mut warc = Vec::new();
+    write_info(&mut warc, "proxy");
+    let mut written: Vec<Written> = Vec::new();
+    for url in urls {
+        let record = match records.responses.get(url) {
+            Some(record) => record,
+            None => continue,
+        };
+        let status = record.status;
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or("")
+        );
+        if let Some(content_type) = &record.meta.content_type {
+            head.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        if let Some(location) = &record.location {
+            head.push_str(&format!("Location: {}\r\n", location));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n", record.data.len()));
+        written.extend(write_response(
+            &mut warc,
+            &written,
+            url,
+            record.meta.fetched_at,
+            status.as_u16(),
+            &head,
+            record.data.clone(),
+        ));
+    }
+    warc
+}
+
+/// Write the `warcinfo` record at the start of a file, with an ID made
+/// from `about`, e.g. the page's URL
+fn write_info(warc: &mut Vec<u8>, about: &str) {
+    let info = format!(
+        "software: web-archive/{}\r\nformat: WARC File Format 1.1\r\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    write_record(
+        warc,
+        &[
+            ("WARC-Type", "warcinfo"),
+            ("WARC-Record-ID", &record_id(about, 0)),
+            ("WARC-Date", &format_date(SystemTime::now())),
+            ("Content-Type", "application/warc-fields"),
+        ],
+        info.as_bytes(),
+    );
+}
+
+/// Write a `response` record for `url`, unless one was `written` already
+fn write_response(
+    warc: &mut Vec<u8>,
+    written: &[Written],
+    url: &Url,
+    date: SystemTime,
+    status: u16,
+    head: &str,
+    payload: Bytes,
+) -> Option<Written> {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    if written.iter().any(|record| record.url == url) {
+        return None;
+    }
+    let mut block = format!("{}\r\n", head).into_bytes();
+    block.extend_from_slice(&payload);
+    let offset = warc.len();
+    write_record(
+        warc,
+        &[
+            ("WARC-Type", "response"),
+            (
+                "WARC-Record-ID",
+                &record_id(url.as_str(), written.len() + 1),
+            ),
+            ("WARC-Date", &format_date(date)),
+            ("WARC-Target-URI", url.as_str()),
+            ("Content-Type", "application/http;msgtype=response"),
+        ],
+        &block,
+    );
+    let mimetype = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Type: "))
+        .and_then(|content_type| content_type.split(';').next())
+        .unwrap_or("")
+        .to_string();
+    Some(Written {
+        url,
+        date,
+        status,
+        mimetype,
+        payload,
+        offset,
+        length: warc.len() - offset,
+    })
+}
+
+/// Write a `resource` record for `url`, holding a `payload` which isn't an
+/// HTTP response
+fn write_resource(
+    warc: &mut Vec<u8>,
+    written: &[Written],
+    url: &Url,
+    date: SystemTime,
+    mimetype: &str,
+    payload: Bytes,
+) -> Written {
+    let offset = warc.len();
+    write_record(
+        warc,
+        &[
+            ("WARC-Type", "resource"),
+            (
+                "WARC-Record-ID",
+                &record_id(url.as_str(), written.len() + 1),
+            ),
+            ("WARC-Date", &format_date(date)),
+            ("WARC-Target-URI", url.as_str()),
+            ("Content-Type", mimetype),
+        ],
+        &payload,
+    );
+    Written {
+        url: url.clone(),
+        date,
+        status: 200,
+        mimetype: mimetype.to_string(),
+        payload,
+        offset,
+        length: warc.len() - offset,
+    }
+}
+
+fn write_record(warc: &mut Vec<u8>, headers: &[(&str, &str)], block: &[u8]) {
+    warc.extend_from_slice(b"WARC/1.1\r\n");
+    for (name, value) in headers {
+        warc.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+    let length = format!("Content-Length: {}\r\n\r\n", block.len());
+    warc.extend_from_slice(length.as_bytes());
+    warc.extend_from_slice(block);
+    warc.extend_from_slice(b"\r\n\r\n");
+}
+
+/// A `WARC-Record-ID` for the `n`th record in the file, which is for `url`
+fn record_id(url: &str, n: usize) -> String {
+    let hash = |seed: u64| {
+        let mut hasher = DefaultHasher::new();
+        (seed, url, n).hash(&mut hasher);
+        hasher.finish()
+    };
+    let id = format!("{:016x}{:016x}", hash(0), hash(1));
+    format!(
+        "<urn:uuid:{}-{}-{}-{}-{}>",
+        &id[..8],
+        &id[8..12],
+        &id[12..16],
+        &id[16..20],
+        &id[20..]
+    )
+}
+
+/// Format a time as a `WARC-Date`, e.g. `2021-02-03T04:05:06Z`
+pub(crate) fn format_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+
+    // Howard Hinnant's `civil_from_days`
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096)
+        / 365;
+    let day_of_year =
+        day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::Resource;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::time::Duration;
+
+    fn record(warc_type: &str, uri: &str, block: &[u8]) -> Vec<u8> {
+        let mut record = format!(
+            "WARC/1.0\r\n\
+             WARC-Type: {}\r\n\
+             WARC-Target-URI: {}\r\n\
+             WARC-Date: 2021-02-03T04:05:06Z\r\n\
+             Content-Length: {}\r\n\r\n",
+            warc_type,
+            uri,
+            block.len()
+        )
+        .into_bytes();
+        record.extend_from_slice(block);
+        record.extend_from_slice(b"\r\n\r\n");
+        record
+    }
+
+    /// A `response` record, whose `head` is the HTTP status line and
+    /// headers
+    fn response(uri: &str, head: &str, body: &[u8]) -> Vec<u8> {
+        let mut block = format!("{}\r\n", head).into_bytes();
+        block.extend_from_slice(body);
+        record("response", uri, &block)
+    }
+
+    /// A `revisit` record of `uri`, with extra WARC `headers` saying what
+    /// it revisits
+    fn revisit(uri: &str, headers: &str, head: &str) -> Vec<u8> {
+        let block = match head {
+            "" => String::new(),
+            head => format!("{}\r\n", head),
+        };
+        let record = format!(
+            "WARC/1.0\r\n\
+             WARC-Type: revisit\r\n\
+             WARC-Target-URI: {}\r\n\
+             WARC-Date: 2021-03-04T05:06:07Z\r\n\
+             {}\
+             Content-Length: {}\r\n\r\n{}\r\n\r\n",
+            uri,
+            headers,
+            block.len(),
+            block
+        )
+        .into_bytes();
+        record
+    }
+
+    fn warc() -> Vec<u8> {
+        let mut warc = record("warcinfo", "", b"software: test\r\n");
+        warc.extend(response(
+            "http://example.com/",
+            "HTTP/1.1 301 Moved Permanently\r\n\
+             Location: /index.html\r\n",
+            b"",
+        ));
+        warc.extend(response(
+            "http://example.com/index.html",
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/html; charset=utf-8\r\n",
+            br#"<html><head><link rel="stylesheet" href="style.css"></head>
+            <body><img src="bg.png"><img src="missing.png"></body></html>"#,
+        ));
+        warc.extend(response(
+            "http://example.com/style.css",
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/css\r\n\
+             Transfer-Encoding: chunked\r\n",
+            b"9\r\nbody { ba\r\n17\r\nckground: url(bg.png) }\r\n0\r\n\r\n",
+        ));
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(b"png").unwrap();
+        let gzipped = gzipped.finish().unwrap();
+        warc.extend(response(
+            "http://example.com/bg.png",
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: image/png\r\n\
+             Content-Encoding: gzip\r\n",
+            &gzipped,
+        ));
+        // Later captures of the same URI are ignored
+        warc.extend(response(
+            "http://example.com/bg.png",
+            "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n",
+            b"newer",
+        ));
+        warc
+    }
+
+    #[test]
+    fn test_records() {
+        let records = read_records(&warc()[..], None, None).unwrap();
+        assert_eq!(records.responses.len(), 4);
+        assert_eq!(
+            records.first_page.as_ref().map(Url::as_str),
+            Some("http://example.com/index.html")
+        );
+
+        let url = |u| Url::parse(u).unwrap();
+        let page = records.response(&url("http://example.com/#top")).unwrap();
+        assert_eq!(page.redirects, vec![url("http://example.com/index.html")]);
+        assert_eq!(
+            page.meta.fetched_at,
+            UNIX_EPOCH + Duration::from_secs(1_612_325_106)
+        );
+        let style = records.response(&url("http://example.com/style.css"));
+        assert_eq!(
+            style.unwrap().data,
+            "body { background: url(bg.png) }".as_bytes()
+        );
+        let image = records.response(&url("http://example.com/bg.png"));
+        assert_eq!(image.unwrap().data, "png".as_bytes());
+        assert_eq!(
+            records.response(&url("http://example.com/missing.png")),
+            Err(FailureReason::NotInWarc)
+        );
+
+        assert!(read_records(&b"not a WARC file"[..], None, None).is_err());
+        let truncated = &warc()[..200];
+        assert!(read_records(truncated, None, None).is_err());
+    }
+
+    #[test]
+    fn test_revisits() {
+        let mut warc = record(
+            "response",
+            "http://example.com/a.png",
+            b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n\r\npng",
+        );
+        // Insert the digest header into the first record
+        let digest = "WARC-Payload-Digest: sha1:ABC\r\n";
+        let at = find(&warc, b"Content-Length").unwrap();
+        warc.splice(at..at, digest.bytes());
+        warc.extend(revisit(
+            "http://example.com/b.png",
+            digest,
+            "HTTP/1.1 200 OK\r\nContent-Type: image/x-png\r\n",
+        ));
+        warc.extend(revisit(
+            "http://example.com/a.png?v=2",
+            "WARC-Refers-To-Target-URI: <http://example.com/a.png>\r\n",
+            "HTTP/1.1 304 Not Modified\r\n",
+        ));
+        warc.extend(revisit(
+            "http://example.com/c.png",
+            "WARC-Refers-To-Target-URI: <http://example.com/gone.png>\r\n",
+            "",
+        ));
+        let records = read_records(&warc[..], None, None).unwrap();
+
+        let url = |u| Url::parse(u).unwrap();
+        let same_digest = records.response(&url("http://example.com/b.png"));
+        let same_digest = same_digest.unwrap();
+        assert_eq!(same_digest.data, "png".as_bytes());
+        assert_eq!(
+            same_digest.meta.content_type.as_deref(),
+            Some("image/x-png")
+        );
+        assert_eq!(
+            same_digest.meta.fetched_at,
+            UNIX_EPOCH + Duration::from_secs(1_614_834_367)
+        );
+        let not_modified =
+            records.response(&url("http://example.com/a.png?v=2"));
+        let not_modified = not_modified.unwrap();
+        assert_eq!(not_modified.data, "png".as_bytes());
+        assert_eq!(not_modified.meta.status, 200);
+        assert_eq!(
+            not_modified.meta.content_type.as_deref(),
+            Some("image/png")
+        );
+        assert_eq!(
+            records.response(&url("http://example.com/c.png")),
+            Err(FailureReason::NotInWarc)
+        );
+    }
+
+    #[test]
+    fn test_max_size() {
+        let page = Url::parse("http://example.com/").unwrap();
+        let mut warc = warc();
+        let mut zeros = GzEncoder::new(Vec::new(), Compression::default());
+        zeros.write_all(&[0; 1000]).unwrap();
+        let zeros = zeros.finish().unwrap();
+        assert!(zeros.len() < 100);
+        warc.extend(response(
+            "http://example.com/zeros.bin",
+            "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n",
+            &zeros,
+        ));
+
+        let records = read_records(&warc[..], Some(&page), Some(30)).unwrap();
+        let url = |u| Url::parse(u).unwrap();
+        // The page, which redirects, isn't limited
+        let index = records.response(&page).unwrap();
+        assert_eq!(index.redirects, vec![url("http://example.com/index.html")]);
+        assert_eq!(
+            records.response(&url("http://example.com/style.css")),
+            Err(FailureReason::TooLarge)
+        );
+        let image = records.response(&url("http://example.com/bg.png"));
+        assert_eq!(image.unwrap().data, "png".as_bytes());
+        // Limited once it's decompressed
+        assert_eq!(
+            records.response(&url("http://example.com/zeros.bin")),
+            Err(FailureReason::TooLarge)
+        );
+
+        // Without a target, the first HTML response is the page
+        let records = read_records(&warc[..], None, Some(2)).unwrap();
+        assert!(records.response(&page).is_ok());
+        assert_eq!(
+            records.response(&url("http://example.com/bg.png")),
+            Err(FailureReason::TooLarge)
+        );
+
+        let options = ArchiveOptions::default().max_resource_size(30);
+        let archive = read_warc(&warc[..], None, options).unwrap();
+        let reasons = archive
+            .failures
+            .iter()
+            .map(|failure| failure.reason.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(reasons.len(), 2);
+        assert!(reasons.contains(&FailureReason::TooLarge));
+    }
+
+    #[test]
+    fn test_read_warc() {
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(&warc()).unwrap();
+        let gzipped = gzipped.finish().unwrap();
+
+        for data in [warc(), gzipped] {
+            let archive =
+                read_warc(&data[..], None, Default::default()).unwrap();
+            assert_eq!(archive.url.as_str(), "http://example.com/index.html");
+            let style = Url::parse("http://example.com/style.css").unwrap();
+            assert!(matches!(
+                archive.resource_map.get(&style),
+                Some(Resource::Css(_))
+            ));
+            let image = Url::parse("http://example.com/bg.png").unwrap();
+            assert_eq!(archive.resource_map[&image].data(), "png".as_bytes());
+            assert_eq!(archive.failures.len(), 1);
+            assert_eq!(archive.failures[0].reason, FailureReason::NotInWarc);
+        }
+
+        let archive = read_warc(
+            &warc()[..],
+            Some("http://example.com/"),
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(archive.url.as_str(), "http://example.com/index.html");
+        let missing = read_warc(
+            &warc()[..],
+            Some("http://example.com/other"),
+            Default::default(),
+        );
+        assert!(matches!(missing, Err(Error::NotInWarc(_))));
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        for date in &["2000-02-29T23:59:59Z", "2021-02-03T04:05:06Z"] {
+            assert_eq!(format_date(parse_date(date).unwrap()), *date);
+        }
+    }
+
+    #[test]
+    fn test_write_records() {
+        let page = Some("http://example.com/");
+        let archive = read_warc(&warc()[..], page, Default::default()).unwrap();
+        let (written, records) = write_records(&archive);
+        assert_eq!(records[0].url.as_str(), "http://example.com/");
+        assert_eq!(records[0].status, 302);
+        let page = &records[1];
+        assert_eq!(page.url, archive.url);
+        assert_eq!(page.mimetype, "text/html");
+        assert_eq!(
+            &written[page.offset..page.offset + 8],
+            "WARC/1.1".as_bytes()
+        );
+
+        let read_back = read_warc(
+            &written[..],
+            Some("http://example.com/"),
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(read_back.url, archive.url);
+        assert_eq!(read_back.content, archive.content);
+        assert_eq!(read_back.resource_map, archive.resource_map);
+        assert_eq!(read_back.redirects, archive.redirects);
+        assert_eq!(read_back.screenshot, None);
+
+        let mut archive = archive;
+        archive.screenshot = Some(Bytes::from_static(b"\x89PNG"));
+        let (written, records) = write_records(&archive);
+        let screenshot = records.last().unwrap();
+        assert_eq!(
+            screenshot.url.as_str(),
+            "urn:screenshot:http://example.com/index.html"
+        );
+        assert_eq!(screenshot.mimetype, "image/png");
+        let read_back = read_warc(&written[..], None, Default::default());
+        assert_eq!(read_back.unwrap().screenshot, archive.screenshot);
+    }
+
+    #[test]
+    fn test_dechunk() {
+        assert_eq!(
+            dechunk(b"3;ext=1\r\nabc\r\n1\r\nd\r\n0\r\n\r\n").unwrap(),
+            b"abcd"
+        );
+        assert_eq!(dechunk(b"5\r\nabc"), None);
+    }
+}
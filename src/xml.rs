@@ -0,0 +1,386 @@
+// Copyright 2021 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Module for reading the XML of sitemaps and feeds.
+//!
+//! This is a forgiving parser rather than a validating one, as the files
+//! found in the wild often aren't well formed: mismatched end tags are
+//! skipped over, unknown entities are left as they are, and anything
+//! after the root element is ignored. Namespaces aren't resolved, so
+//! elements are looked up by their names without any prefix, e.g.
+//! `creator` for `dc:creator`.
+
+/// Elements nested more deeply than this are left out, along with their
+/// contents, so that pathological files can't overflow the stack
+const MAX_DEPTH: usize = 256;
+
+/// An element and everything in it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Element {
+    /// The name as written, including any namespace prefix
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    /// The name without any namespace prefix, e.g. `loc` for `sm:loc`
+    pub fn local_name(&self) -> &str {
+        self.name.rsplit(':').next().unwrap_or("")
+    }
+
+    /// The value of the attribute called `name` as written
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The child elements
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// The child elements whose names are `local_name` without any
+    /// namespace prefix
+    pub fn elements_named<'a>(
+        &'a self,
+        local_name: &'a str,
+    ) -> impl Iterator<Item = &'a Element> {
+        self.elements()
+            .filter(move |element| element.local_name() == local_name)
+    }
+
+    /// The first child element whose name is `local_name` without any
+    /// namespace prefix
+    pub fn element(&self, local_name: &str) -> Option<&Element> {
+        self.elements()
+            .find(|element| element.local_name() == local_name)
+    }
+
+    /// The text of the first child element whose name is `local_name`,
+    /// trimmed, if it isn't blank
+    pub fn child_text(&self, local_name: &str) -> Option<String> {
+        let text = self.element(local_name)?.text();
+        let text = text.trim();
+        Some(text.to_string()).filter(|text| !text.is_empty())
+    }
+
+    /// All the text in the element, including that of its descendants
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        let mut stack = vec![self.children.iter()];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(Node::Text(contents)) => text.push_str(contents),
+                Some(Node::Element(element)) => {
+                    stack.push(element.children.iter())
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        text
+    }
+}
+
+/// Parse an XML document, returning its root element, or `None` if it
+/// doesn't have one
+pub(crate) fn parse(xml: &str) -> Option<Element> {
+    // The open elements, starting with a stand-in for the document
+    let mut stack = vec![Element::default()];
+    // How many elements deeper than `MAX_DEPTH` are open
+    let mut skipped = 0;
+    let mut rest = xml.strip_prefix('\u{feff}').unwrap_or(xml);
+
+    while !rest.is_empty() {
+        if has_closed_root(&stack) {
+            break;
+        }
+        let lt = match rest.find('<') {
+            Some(lt) => lt,
+            None => {
+                push_text(&mut stack, skipped, &decode(rest));
+                break;
+            }
+        };
+        if lt > 0 {
+            push_text(&mut stack, skipped, &decode(&rest[..lt]));
+        }
+        rest = &rest[lt..];
+
+        if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after.find("]]>").unwrap_or(after.len());
+            push_text(&mut stack, skipped, &after[..end]);
+            rest = after.get(end + 3..).unwrap_or("");
+        } else if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+        } else if let Some(after) = rest.strip_prefix("<?") {
+            rest = after.find("?>").map_or("", |end| &after[end + 2..]);
+        } else if rest.starts_with("<!") {
+            rest = skip_declaration(rest);
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').unwrap_or(after.len());
+            let name = after[..end].trim();
+            rest = after.get(end + 1..).unwrap_or("");
+            if skipped > 0 {
+                skipped -= 1;
+            } else if let Some(i) = stack.iter().rposition(|e| e.name == name) {
+                // Close any elements left open inside it too
+                while stack.len() > i.max(1) {
+                    close(&mut stack);
+                }
+            }
+        } else {
+            let (tag, after) = split_tag(&rest[1..]);
+            rest = after;
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let (name, attributes) = parse_tag(tag);
+            if name.is_empty() {
+                push_text(&mut stack, skipped, "<");
+                continue;
+            }
+            if skipped > 0 || stack.len() > MAX_DEPTH {
+                if !self_closing {
+                    skipped += 1;
+                }
+                continue;
+            }
+            stack.push(Element {
+                name,
+                attributes,
+                children: Vec::new(),
+            });
+            if self_closing {
+                close(&mut stack);
+            }
+        }
+    }
+
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+    let document = stack.pop()?;
+    document.children.into_iter().find_map(|child| match child {
+        Node::Element(element) => Some(element),
+        Node::Text(_) => None,
+    })
+}
+
+/// Whether the root element has been closed, leaving only the document
+fn has_closed_root(stack: &[Element]) -> bool {
+    stack.len() == 1 && stack[0].elements().next().is_some()
+}
+
+/// Close the innermost open element, adding it to its parent
+fn close(stack: &mut Vec<Element>) {
+    if let Some(element) = stack.pop() {
+        if let Some(parent) = stack.last_mut() {
+            parent.children.push(Node::Element(element));
+        }
+    }
+}
+
+fn push_text(stack: &mut [Element], skipped: usize, text: &str) {
+    if skipped > 0 || text.is_empty() {
+        return;
+    }
+    let parent = match stack.last_mut() {
+        Some(parent) => parent,
+        None => return,
+    };
+    match parent.children.last_mut() {
+        Some(Node::Text(existing)) => existing.push_str(text),
+        _ => parent.children.push(Node::Text(text.to_string())),
+    }
+}
+
+/// Skip a `<!DOCTYPE>` or other declaration, including any internal
+/// subset in square brackets
+fn skip_declaration(rest: &str) -> &str {
+    let mut in_subset = false;
+    for (i, c) in rest.char_indices() {
+        match c {
+            '[' => in_subset = true,
+            ']' => in_subset = false,
+            '>' if !in_subset => return &rest[i + 1..],
+            _ => {}
+        }
+    }
+    ""
+}
+
+/// Split the inside of a start tag from what follows it, allowing for
+/// `>` in quoted attribute values
+fn split_tag(rest: &str) -> (&str, &str) {
+    let mut quote = None;
+    for (i, c) in rest.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('>', None) => return (&rest[..i], &rest[i + 1..]),
+            _ => {}
+        }
+    }
+    (rest, "")
+}
+
+/// The name and attributes of a start tag
+fn parse_tag(tag: &str) -> (String, Vec<(String, String)>) {
+    let tag = tag.trim();
+    let end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let name = tag[..end].to_string();
+    let mut attributes = Vec::new();
+    let mut rest = tag[end..].trim_start();
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let attribute = rest[..end].to_string();
+        rest = rest[end..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, after) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let inner = &after[1..];
+                        let end = inner.find(quote).unwrap_or(inner.len());
+                        (&inner[..end], inner.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => {
+                        let end = after
+                            .find(char::is_whitespace)
+                            .unwrap_or(after.len());
+                        (&after[..end], &after[end..])
+                    }
+                };
+                rest = after.trim_start();
+                decode(value)
+            }
+            None => String::new(),
+        };
+        if !attribute.is_empty() {
+            attributes.push((attribute, value));
+        }
+    }
+    (name, attributes)
+}
+
+/// Replace the predefined entities and character references in text,
+/// leaving any others as they are
+pub(crate) fn decode(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let semicolon = match rest.find(';') {
+            Some(semicolon) if semicolon <= 12 => semicolon,
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let entity = &rest[1..semicolon];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let code = match entity.strip_prefix('#') {
+                    Some(hex) if hex.starts_with(['x', 'X']) => {
+                        u32::from_str_radix(&hex[1..], 16).ok()
+                    }
+                    Some(decimal) => decimal.parse().ok(),
+                    None => None,
+                };
+                code.and_then(char::from_u32)
+            }
+        };
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[semicolon + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let root = parse(
+            "\u{feff}<?xml version=\"1.0\"?>\n\
+            <!DOCTYPE feed [<!ENTITY x \"y\">]>\n\
+            <!-- A comment -->\
+            <rss version='2.0' xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\
+            <channel><title>Fish &amp; Chips &#x2014; &#8212; &nbsp;</title>\
+            <item><link>http://example.com/?a=1&amp;b=2</link>\
+            <description><![CDATA[<p>Some <b>HTML</b></p>]]></description>\
+            <dc:creator>Jane</dc:creator><br/><open>\
+            </item><enclosure url=\"a.mp3\" type=\"audio/mpeg\" />\
+            </wrong></channel></rss><trailing/>",
+        )
+        .unwrap();
+        assert_eq!(root.name, "rss");
+        assert_eq!(root.attribute("version"), Some("2.0"));
+        let channel = root.element("channel").unwrap();
+        assert_eq!(
+            channel.child_text("title").as_deref(),
+            Some("Fish & Chips \u{2014} \u{2014} &nbsp;")
+        );
+        let item = channel.element("item").unwrap();
+        assert_eq!(
+            item.child_text("link").as_deref(),
+            Some("http://example.com/?a=1&b=2")
+        );
+        assert_eq!(
+            item.child_text("description").as_deref(),
+            Some("<p>Some <b>HTML</b></p>")
+        );
+        let creator = item.element("creator").unwrap();
+        assert_eq!(creator.name, "dc:creator");
+        assert_eq!(creator.text(), "Jane");
+        let names: Vec<_> = item.elements().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["link", "description", "dc:creator", "br", "open"]
+        );
+        let enclosure = channel.elements_named("enclosure").next().unwrap();
+        assert_eq!(enclosure.attribute("type"), Some("audio/mpeg"));
+
+        assert_eq!(parse("no elements"), None);
+        let deep = "<a>".repeat(100_000);
+        assert_eq!(parse(&deep).unwrap().name, "a");
+    }
+}